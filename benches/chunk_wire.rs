@@ -0,0 +1,127 @@
+//! Chunk wire sizes and encode/decode speed, RAW vs PALETTE_RLE, and
+//! FLATBUFFERS read in place (`FlatChunk`, every block) vs a full decode.
+//!
+//! `cargo bench --bench chunk_wire`
+
+use std::{hint::black_box, time::Instant};
+use teleboxel::{
+    chunk::{CHUNK_SIZE, Chunk},
+    chunk_wire,
+    terrain::{ChunkGenerator, FlatGenerator, NoiseGenerator},
+};
+
+const ROUNDS: u32 = 20;
+
+fn main() {
+    let noise = NoiseGenerator::new(1);
+    // 8x8 columns from deep underground to open sky around spawn
+    let region = |g: &dyn ChunkGenerator, ys: std::ops::RangeInclusive<i32>| {
+        let mut chunks = Vec::new();
+        for x in -4..4 {
+            for z in -4..4 {
+                for y in ys.clone() {
+                    chunks.push(g.generate((x, y, z)));
+                }
+            }
+        }
+        chunks
+    };
+
+    let sets = [
+        ("flat world", region(&FlatGenerator, -2..=1)),
+        ("noise underground", region(&noise, -3..=0)),
+        ("noise surface", region(&noise, 1..=3)),
+        ("noise sky", region(&noise, 4..=5)),
+        ("noise all", region(&noise, -3..=5)),
+    ];
+
+    println!(
+        "{:<18} {:>6} {:>10} {:>10} {:>7} {:>10} {:>10} {:>10} {:>10}",
+        "terrain", "chunks", "raw B", "rle B", "ratio", "enc us", "dec us", "fb B", "fb rd us"
+    );
+    for (name, chunks) in &sets {
+        report(name, chunks);
+    }
+}
+
+fn report(name: &str, chunks: &[Chunk]) {
+    let mut raw = Vec::new();
+    let mut encoded = Vec::new();
+    for chunk in chunks {
+        chunk_wire::encode_raw(chunk, &mut raw);
+        chunk_wire::encode(chunk, &mut encoded);
+    }
+
+    let payloads: Vec<Vec<u8>> = chunks
+        .iter()
+        .map(|c| {
+            let mut buf = Vec::new();
+            chunk_wire::encode(c, &mut buf);
+            buf
+        })
+        .collect();
+
+    let start = Instant::now();
+    let mut buf = Vec::with_capacity(chunk_wire::RAW_LEN);
+    for _ in 0..ROUNDS {
+        for chunk in chunks {
+            buf.clear();
+            chunk_wire::encode(black_box(chunk), &mut buf);
+            black_box(&buf);
+        }
+    }
+    let encode_us = per_chunk_us(start, chunks.len());
+
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        for payload in &payloads {
+            black_box(chunk_wire::decode(black_box(payload)).unwrap());
+        }
+    }
+    let decode_us = per_chunk_us(start, chunks.len());
+
+    let mut flat = Vec::new();
+    let flat_payloads: Vec<Vec<u8>> = chunks
+        .iter()
+        .map(|c| {
+            let mut buf = Vec::new();
+            chunk_wire::encode_flatbuffers(c, &mut buf);
+            flat.extend_from_slice(&buf);
+            buf
+        })
+        .collect();
+
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        for payload in &flat_payloads {
+            let (view, _) = chunk_wire::FlatChunk::parse(black_box(&payload[1..])).unwrap();
+            let mut sum = 0u32;
+            for y in 0..CHUNK_SIZE {
+                for z in 0..CHUNK_SIZE {
+                    for x in 0..CHUNK_SIZE {
+                        sum += view.get(x, y, z) as u32;
+                    }
+                }
+            }
+            black_box(sum);
+        }
+    }
+    let flat_read_us = per_chunk_us(start, chunks.len());
+
+    println!(
+        "{:<18} {:>6} {:>10} {:>10} {:>6.1}x {:>10.2} {:>10.2} {:>10} {:>10.2}",
+        name,
+        chunks.len(),
+        raw.len(),
+        encoded.len(),
+        raw.len() as f64 / encoded.len() as f64,
+        encode_us,
+        decode_us,
+        flat.len(),
+        flat_read_us
+    );
+}
+
+fn per_chunk_us(start: Instant, chunks: usize) -> f64 {
+    start.elapsed().as_secs_f64() * 1e6 / (ROUNDS as f64 * chunks as f64)
+}
@@ -0,0 +1,134 @@
+//! Heap allocations per tick on the steady tick path: every player gets a
+//! frame of chunk deltas and one of entity updates, queued in its lanes and
+//! written (dropped) before the next tick. Frames from `FramePool` vs a new
+//! `ServerFrame` each time. Counted by a global allocator wrapper.
+//!
+//! `cargo bench --bench frame_pool`
+
+use bytes::Bytes;
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    hint::black_box,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Instant,
+};
+use teleboxel::{
+    chunk_wire::ChunkFormat,
+    entities::Entity,
+    lanes::{self, Lane},
+    protocol::{FramePool, ServerFrame},
+};
+
+struct Counting;
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+const PLAYERS: usize = 32;
+const WARMUP: u32 = 100;
+const TICKS: u32 = 1000;
+
+fn main() {
+    let edits: Vec<_> = (0..64).map(|i| (i * 61 % 4096, 1)).collect();
+    let entities: Vec<_> = (0..16)
+        .map(|id| Entity {
+            id,
+            position: (id as i32, 40, 0),
+            authority: None,
+            attached: None,
+            fixed: false,
+            always: false,
+            layer: 0,
+        })
+        .collect();
+
+    println!(
+        "{:<12} {:>8} {:>14} {:>10}",
+        "frames", "players", "allocs/tick", "us/tick"
+    );
+    let mut pool = FramePool::default();
+    run("pooled", |tick, tx| {
+        let mut frame = pool.frame(tick, ChunkFormat::default());
+        frame.chunk_delta((0, 0, 0), tick, tick + 1, &edits);
+        let data = pool.finish(&mut frame);
+        pool.recycle(frame);
+        send(tx, Lane::Chunks, data);
+
+        let mut frame = pool.frame(tick, ChunkFormat::default());
+        for entity in &entities {
+            frame.entity(entity);
+        }
+        let data = pool.finish(&mut frame);
+        pool.recycle(frame);
+        send(tx, Lane::Entities, data);
+    });
+    run("new", |tick, tx| {
+        let mut frame = ServerFrame::new(tick);
+        frame.chunk_delta((0, 0, 0), tick, tick + 1, &edits);
+        send(tx, Lane::Chunks, frame.finish());
+
+        let mut frame = ServerFrame::new(tick);
+        for entity in &entities {
+            frame.entity(entity);
+        }
+        send(tx, Lane::Entities, frame.finish());
+    });
+}
+
+fn send(tx: &lanes::Sender, lane: Lane, data: Bytes) {
+    tx.try_send(lane, data).unwrap();
+}
+
+// `encode` builds one player's frames for the tick
+fn run(name: &str, mut encode: impl FnMut(u32, &lanes::Sender)) {
+    let mut players: Vec<_> = (0..PLAYERS).map(|_| lanes::channel()).collect();
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+
+    let mut tick = |tick: u32| {
+        for (tx, _) in &players {
+            encode(tick, tx);
+        }
+        // The connections write everything before the next tick
+        runtime.block_on(async {
+            for (_, rx) in &mut players {
+                black_box(rx.recv().await);
+                black_box(rx.recv().await);
+            }
+        });
+    };
+
+    for t in 0..WARMUP {
+        tick(t);
+    }
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    for t in WARMUP..WARMUP + TICKS {
+        tick(t);
+    }
+    let us = start.elapsed().as_secs_f64() * 1e6 / TICKS as f64;
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations;
+    println!(
+        "{name:<12} {PLAYERS:>8} {:>14.2} {us:>10.1}",
+        allocations as f64 / TICKS as f64
+    );
+}
@@ -0,0 +1,164 @@
+//! The spatial indexes (`spatial.rs`), grid vs octree, with 1k and 100k
+//! entities spread two ways:
+//!
+//! - `uniform`: over a 4096x256x4096 block area around spawn
+//! - `clustered`: 32 towns, 128 blocks across, scattered over a billion
+//!   blocks each way
+//!
+//! Groups:
+//!
+//! - `build`: inserting every entity
+//! - `query`: 64 radius 4 interests, half on entities, half anywhere
+//! - `move`: every entity a block over
+//!
+//! Each index's heap footprint is printed once per distribution.
+//!
+//! `cargo bench --bench spatial`
+
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use std::hint::black_box;
+use teleboxel::{
+    claims::BlockPos,
+    interest,
+    spatial::{SpatialIndex, SpatialKind},
+};
+
+const ENTITIES: [usize; 2] = [1_000, 100_000];
+const KINDS: [SpatialKind; 2] = [SpatialKind::Grid, SpatialKind::Octree];
+
+// xorshift, the same entities every run
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn within(&mut self, half: i32) -> i32 {
+        (self.next() % (2 * half as u64 + 1)) as i32 - half
+    }
+}
+
+fn uniform(entities: usize, rng: &mut Rng) -> Vec<BlockPos> {
+    (0..entities)
+        .map(|_| (rng.within(2048), 64 + rng.within(128), rng.within(2048)))
+        .collect()
+}
+
+fn clustered(entities: usize, rng: &mut Rng) -> Vec<BlockPos> {
+    let far = 1 << 29;
+    let towns: Vec<BlockPos> = (0..32)
+        .map(|_| (rng.within(far), 64, rng.within(far)))
+        .collect();
+    (0..entities)
+        .map(|i| {
+            let (x, y, z) = towns[i % towns.len()];
+            (x + rng.within(64), y + rng.within(16), z + rng.within(64))
+        })
+        .collect()
+}
+
+// Interest boxes: on entities, then anywhere in the distribution's range
+fn queries(positions: &[BlockPos], half: i32, rng: &mut Rng) -> Vec<(BlockPos, BlockPos)> {
+    let chunk = |b: i32| b.div_euclid(16);
+    let on: Vec<BlockPos> = (0..32)
+        .map(|_| positions[rng.next() as usize % positions.len()])
+        .collect();
+    let anywhere: Vec<BlockPos> = (0..32)
+        .map(|_| (rng.within(half), 64, rng.within(half)))
+        .collect();
+    on.into_iter()
+        .chain(anywhere)
+        .map(|(x, y, z)| interest::blocks((chunk(x), chunk(y), chunk(z)), 4))
+        .collect()
+}
+
+fn index(kind: SpatialKind, positions: &[BlockPos]) -> Box<dyn SpatialIndex> {
+    let mut index = kind.build();
+    for (id, p) in positions.iter().enumerate() {
+        index.insert(id as u32, *p);
+    }
+    index
+}
+
+type Distribution = (&'static str, fn(usize, &mut Rng) -> Vec<BlockPos>, i32);
+
+const DISTRIBUTIONS: [Distribution; 2] = [
+    ("uniform", uniform, 2048),
+    ("clustered", clustered, 1 << 29),
+];
+
+fn build(c: &mut Criterion) {
+    let mut group = c.benchmark_group("build");
+    for (name, spread, _) in DISTRIBUTIONS {
+        for entities in ENTITIES {
+            let positions = spread(entities, &mut Rng(7));
+            group.throughput(Throughput::Elements(entities as u64));
+            for kind in KINDS {
+                let footprint = index(kind, &positions).footprint();
+                eprintln!("{name} {entities} {kind:?}: {} KiB", footprint / 1024);
+                let id = BenchmarkId::new(format!("{name}/{kind:?}"), entities);
+                group.bench_with_input(id, &positions, |b, p| b.iter(|| index(kind, p)));
+            }
+        }
+    }
+    group.finish();
+}
+
+fn query(c: &mut Criterion) {
+    let mut group = c.benchmark_group("query");
+    for (name, spread, half) in DISTRIBUTIONS {
+        for entities in ENTITIES {
+            let mut rng = Rng(7);
+            let positions = spread(entities, &mut rng);
+            let boxes = queries(&positions, half, &mut rng);
+            group.throughput(Throughput::Elements(boxes.len() as u64));
+            for kind in KINDS {
+                let index = index(kind, &positions);
+                let mut found = Vec::new();
+                let id = BenchmarkId::new(format!("{name}/{kind:?}"), entities);
+                group.bench_function(id, |b| {
+                    b.iter(|| {
+                        for &(lo, hi) in &boxes {
+                            found.clear();
+                            index.query(black_box(lo), black_box(hi), &mut found);
+                        }
+                        found.len()
+                    })
+                });
+            }
+        }
+    }
+    group.finish();
+}
+
+fn moves(c: &mut Criterion) {
+    let mut group = c.benchmark_group("move");
+    for (name, spread, _) in DISTRIBUTIONS {
+        for entities in ENTITIES {
+            let positions = spread(entities, &mut Rng(7));
+            group.throughput(Throughput::Elements(entities as u64));
+            for kind in KINDS {
+                let mut index = index(kind, &positions);
+                let mut step = 1;
+                let id = BenchmarkId::new(format!("{name}/{kind:?}"), entities);
+                group.bench_function(id, |b| {
+                    b.iter(|| {
+                        // Back and forth, so it doesn't drift
+                        step = -step;
+                        for (id, &(x, y, z)) in positions.iter().enumerate() {
+                            index.insert(id as u32, (x + step.max(0), y, z));
+                        }
+                    })
+                });
+            }
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, build, query, moves);
+criterion_main!(benches);
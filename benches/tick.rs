@@ -0,0 +1,114 @@
+//! The world loop's hot paths at 100, 1k and 10k simulated players:
+//!
+//! - `interest`: which players see a position (effects, static entities)
+//! - `snapshot`: encoding chunk snapshots into pooled frames, in raw
+//!   chunk bytes per second
+//! - `fan_out`: one `ENTITY` frame queued for every player and written
+//!
+//! `cargo bench --bench tick`, compare runs with `-- --save-baseline <name>`
+//! and `-- --baseline <name>`.
+
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use std::hint::black_box;
+use teleboxel::{
+    chunk::ChunkPos,
+    chunk_wire::{ChunkFormat, RAW_LEN},
+    effects,
+    entities::Entity,
+    interest,
+    lanes::{self, Lane},
+    protocol::FramePool,
+    terrain::{ChunkGenerator, NoiseGenerator},
+};
+
+const PLAYERS: [usize; 3] = [100, 1_000, 10_000];
+
+// Spread over a 64x64 chunk area around spawn, radius 4 like most clients
+fn interests(players: usize) -> Vec<Option<(ChunkPos, u16)>> {
+    (0..players as i32)
+        .map(|i| Some(((i * 7 % 64 - 32, 2, i * 13 % 64 - 32), 4)))
+        .collect()
+}
+
+fn interest(c: &mut Criterion) {
+    let mut group = c.benchmark_group("interest");
+    for players in PLAYERS {
+        let interests = interests(players);
+        group.throughput(Throughput::Elements(players as u64));
+        group.bench_with_input(BenchmarkId::new("in_view", players), &interests, |b, i| {
+            b.iter(|| {
+                let position = black_box((40, 40, -8));
+                i.iter().filter(|&&i| effects::in_view(i, position)).count()
+            })
+        });
+    }
+    group.bench_function("offsets_radius_8", |b| {
+        b.iter(|| interest::offsets(black_box(8)).count())
+    });
+    group.finish();
+}
+
+fn snapshot(c: &mut Criterion) {
+    let noise = NoiseGenerator::new(1);
+    let chunks: Vec<_> = (-2..2)
+        .flat_map(|x| (0..3).map(move |y| (x, y)))
+        .map(|(x, y)| ((x, y, 0), noise.generate((x, y, 0))))
+        .collect();
+
+    let mut group = c.benchmark_group("snapshot");
+    group.throughput(Throughput::Bytes((chunks.len() * RAW_LEN) as u64));
+    for format in [ChunkFormat::Compact, ChunkFormat::FlatBuffers] {
+        let mut pool = FramePool::default();
+        group.bench_function(format!("{format:?}"), |b| {
+            b.iter(|| {
+                let mut frame = pool.frame(0, format);
+                for (pos, chunk) in &chunks {
+                    frame.chunk_snapshot(*pos, 1, chunk);
+                }
+                let data = pool.finish(&mut frame);
+                pool.recycle(frame);
+                data
+            })
+        });
+    }
+    group.finish();
+}
+
+fn fan_out(c: &mut Criterion) {
+    let entity = Entity {
+        id: 1,
+        position: (0, 40, 0),
+        authority: None,
+        attached: None,
+        fixed: false,
+        always: false,
+        layer: 0,
+    };
+
+    let mut group = c.benchmark_group("fan_out");
+    for players in PLAYERS {
+        let mut lanes: Vec<_> = (0..players).map(|_| lanes::channel()).collect();
+        let mut pool = FramePool::default();
+        group.throughput(Throughput::Elements(players as u64));
+        group.bench_function(BenchmarkId::new("entity", players), |b| {
+            b.iter(|| {
+                let mut frame = pool.frame(0, ChunkFormat::default());
+                frame.entity(&entity);
+                let data = pool.finish(&mut frame);
+                pool.recycle(frame);
+                for (tx, _) in &lanes {
+                    tx.try_send_latest(Lane::Entities, entity.id, data.clone())
+                        .unwrap();
+                }
+                // The connections write it
+                for (_, rx) in &mut lanes {
+                    black_box(rx.try_recv());
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, interest, snapshot, fan_out);
+criterion_main!(benches);
@@ -0,0 +1,187 @@
+//! Generates protocol code from `schema/protocol.toml`:
+//!
+//! - Rust (`rust.rs`): message id constants, a writer per message and the
+//!   decoder for each direction's message enum, included by
+//!   `src/protocol.rs`.
+//! - TypeScript (`typescript.rs`): the same for the client SDK, checked in
+//!   as `sdk/typescript/src/protocol.ts`. A test in `src/protocol.rs` fails
+//!   when the checked-in copy is stale.
+
+mod rust;
+mod typescript;
+
+use serde::Deserialize;
+use std::{
+    collections::{BTreeMap, HashSet},
+    env, fs,
+    path::Path,
+};
+
+const SCHEMA: &str = "schema/protocol.toml";
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Schema {
+    frames: BTreeMap<String, u8>,
+    messages: Vec<Message>,
+    #[serde(default)]
+    structs: BTreeMap<String, Struct>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Message {
+    name: String,
+    id: u8,
+    dir: String,
+    doc: String,
+    fields: Vec<Field>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Struct {
+    rust: String,
+    #[serde(default)]
+    tuple: bool,
+    fields: Vec<Field>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Field {
+    name: String,
+    #[serde(rename = "type")]
+    ty: String,
+    count: Option<String>,
+    of: Option<String>,
+    max: Option<u64>,
+    coord: Option<String>,
+}
+
+fn main() {
+    println!("cargo:rerun-if-changed={SCHEMA}");
+    let schema: Schema = toml::from_str(&fs::read_to_string(SCHEMA).unwrap())
+        .unwrap_or_else(|e| panic!("{SCHEMA}: {e}"));
+    check(&schema);
+
+    let out = Path::new(&env::var("OUT_DIR").unwrap()).to_path_buf();
+    fs::write(out.join("protocol.rs"), rust::generate(&schema)).unwrap();
+    fs::write(out.join("protocol.ts"), typescript::generate(&schema)).unwrap();
+}
+
+fn check(schema: &Schema) {
+    let mut ids = HashSet::new();
+    for msg in &schema.messages {
+        assert!(
+            ids.insert(msg.id),
+            "message id {:#04x} is used twice",
+            msg.id
+        );
+        assert!(
+            schema.frames.contains_key(&msg.dir),
+            "{}: unknown dir {:?}",
+            msg.name,
+            msg.dir
+        );
+        for field in &msg.fields {
+            check_field(schema, &msg.name, field);
+        }
+        coords(msg);
+    }
+    for (name, s) in &schema.structs {
+        for field in &s.fields {
+            check_field(schema, name, field);
+            assert!(
+                field.coord.is_none(),
+                "{name}.{}: coord in a struct",
+                field.name
+            );
+        }
+    }
+}
+
+/// Positions of a message: where each `pos` or `x`, `y`, `z` run starts
+/// after the message id, and its `coord`.
+fn coords(msg: &Message) -> Vec<(usize, &str)> {
+    let mut found = Vec::new();
+    let mut at = 0;
+    let mut fixed = true;
+    let mut fields = msg.fields.iter().peekable();
+    while let Some(field) = fields.next() {
+        let here = format!("{}.{}", msg.name, field.name);
+        let Some(coord) = field.coord.as_deref() else {
+            match fixed_size(&field.ty) {
+                Some(size) => at += size,
+                None => fixed = false,
+            }
+            continue;
+        };
+        assert!(fixed, "{here}: coord after a variable-length field");
+        if field.ty == "pos" {
+            assert_eq!(coord, "chunk", "{here}: pos coords are chunks");
+        } else {
+            assert!(
+                matches!(coord, "block" | "offset") && field.ty == "i32" && field.name == "x",
+                "{here}: coord must be on `pos` or an i32 `x`, `y`, `z` run"
+            );
+            for axis in ["y", "z"] {
+                let next = fields.next();
+                assert!(
+                    next.is_some_and(|f| f.name == axis
+                        && f.ty == "i32"
+                        && f.coord.as_deref() == Some(coord)),
+                    "{here}: {axis} of the run must follow with the same coord"
+                );
+            }
+        }
+        found.push((at, coord));
+        at += 12;
+    }
+    found
+}
+
+// Encoded size of fixed-size types
+fn fixed_size(ty: &str) -> Option<usize> {
+    match ty {
+        "u8" | "bool" => Some(1),
+        "u16" => Some(2),
+        "u32" | "i32" => Some(4),
+        "pos" => Some(12),
+        _ => None,
+    }
+}
+
+fn check_field(schema: &Schema, owner: &str, field: &Field) {
+    let at = format!("{owner}.{}", field.name);
+    if field.ty == "list" {
+        let count = field.count.as_deref().unwrap_or_default();
+        assert!(is_int(count), "{at}: list count must be an integer type");
+        let of = field.of.as_deref().unwrap_or_default();
+        assert!(
+            schema.structs.contains_key(of) || is_scalar(of),
+            "{at}: unknown list item {of:?}"
+        );
+    } else {
+        assert!(is_scalar(&field.ty), "{at}: unknown type {:?}", field.ty);
+    }
+    assert!(
+        field.max.is_none() || is_int(&field.ty),
+        "{at}: max needs an integer type"
+    );
+}
+
+fn is_int(ty: &str) -> bool {
+    matches!(ty, "u8" | "u16" | "u32" | "i32")
+}
+
+fn is_scalar(ty: &str) -> bool {
+    is_int(ty) || matches!(ty, "bool" | "pos" | "str" | "chunk")
+}
+
+fn camel(snake: &str) -> String {
+    snake
+        .split('_')
+        .map(|w| w[..1].to_uppercase() + &w[1..])
+        .collect()
+}
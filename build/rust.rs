@@ -0,0 +1,311 @@
+//! Rust protocol code, included by `src/protocol.rs`.
+
+use crate::{Field, Message, SCHEMA, Schema, camel, coords};
+use std::fmt::Write;
+
+pub fn generate(schema: &Schema) -> String {
+    let mut out = format!("// Generated by build/main.rs from {SCHEMA}, do not edit.\n\n");
+
+    for (dir, id) in &schema.frames {
+        writeln!(
+            out,
+            "pub const {}_FRAME: u8 = {id:#04x};",
+            dir.to_uppercase()
+        )
+        .unwrap();
+    }
+    for msg in &schema.messages {
+        out += &doc_comment(&msg.doc, "");
+        writeln!(
+            out,
+            "pub const {}: u8 = {:#04x};",
+            msg.name.to_uppercase(),
+            msg.id
+        )
+        .unwrap();
+    }
+
+    for msg in &schema.messages {
+        let mut params: Vec<String> = msg
+            .fields
+            .iter()
+            .map(|f| format!("{}: {}", f.name, param_type(schema, f)))
+            .collect();
+        // Chunk payloads have several encodings, the caller picks
+        if msg.fields.iter().any(|f| f.ty == "chunk") {
+            params.push("chunk_format: ChunkFormat".into());
+        }
+        // Anonymous lifetimes aren't allowed in `impl Trait` params yet
+        let has_list = msg.fields.iter().any(|f| f.ty == "list");
+        // A parameter per field, long messages go over clippy's limit
+        if params.len() >= 7 {
+            out += "\n#[allow(clippy::too_many_arguments)]";
+        }
+        write!(
+            out,
+            "\nfn write_{}{}(buf: &mut Vec<u8>, {}) {{\n",
+            msg.name,
+            if has_list { "<'a>" } else { "" },
+            params.join(", ")
+        )
+        .unwrap();
+        for field in &msg.fields {
+            write_field(schema, &mut out, &field.name, field);
+        }
+        out += "}\n";
+    }
+
+    for (name, s) in &schema.structs {
+        write!(
+            out,
+            "\nfn write_{name}(buf: &mut Vec<u8>, v: &{}) {{\n",
+            s.rust
+        )
+        .unwrap();
+        for (i, field) in s.fields.iter().enumerate() {
+            let access = match s.tuple {
+                true => format!("v.{i}"),
+                false => format!("v.{}", field.name),
+            };
+            let value = match field.ty.as_str() {
+                "str" | "chunk" => format!("&{access}"),
+                "list" => format!("{access}.iter()"),
+                _ => access,
+            };
+            writeln!(out, "    let {} = {value};", field.name).unwrap();
+            write_field(schema, &mut out, &field.name, field);
+        }
+        out += "}\n";
+
+        write!(
+            out,
+            "\nfn read_{name}(r: &mut Reader) -> Result<{}, ProtocolError> {{\n",
+            s.rust
+        )
+        .unwrap();
+        for field in &s.fields {
+            read_field(schema, &mut out, name, field);
+        }
+        let names: Vec<&str> = s.fields.iter().map(|f| f.name.as_str()).collect();
+        match s.tuple {
+            true => writeln!(out, "    Ok(({}))", names.join(", ")),
+            false => writeln!(out, "    Ok({} {{ {} }})", s.rust, names.join(", ")),
+        }
+        .unwrap();
+        out += "}\n";
+    }
+
+    for dir in schema.frames.keys() {
+        let msgs: Vec<&Message> = schema.messages.iter().filter(|m| &m.dir == dir).collect();
+        if msgs.is_empty() {
+            continue;
+        }
+
+        let ty = format!("{}Msg", camel(dir));
+        write!(
+            out,
+            "\n/// Decoded {dir} submessage. The JSON form is tagged with the message\n\
+             /// name, e.g. `{{\"kind\": \"chunk_delta\", ...}}`.\n\
+             #[derive(Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]\n\
+             #[serde(tag = \"kind\", rename_all = \"snake_case\")]\npub enum {ty} {{\n"
+        )
+        .unwrap();
+        for msg in &msgs {
+            out += &doc_comment(&msg.doc, "    ");
+            writeln!(out, "    {} {{", camel(&msg.name)).unwrap();
+            for field in &msg.fields {
+                writeln!(
+                    out,
+                    "        {}: {},",
+                    field.name,
+                    owned_type(schema, field)
+                )
+                .unwrap();
+            }
+            out += "    },\n";
+        }
+        out += "}\n";
+
+        // Without the z of positions for 2D worlds' clients, see coords.rs
+        let positioned: Vec<(&Message, Vec<(usize, &str)>)> = msgs
+            .iter()
+            .map(|m| (*m, coords(m)))
+            .filter(|(_, c)| !c.is_empty())
+            .collect();
+        let flat = if positioned.is_empty() {
+            ""
+        } else {
+            ", flat: bool"
+        };
+        write!(
+            out,
+            "\nfn read_{dir}_msg(r: &mut Reader{flat}) -> Result<{ty}, ProtocolError> {{\n    \
+             Ok(match r.u8()? {{\n"
+        )
+        .unwrap();
+        for msg in &msgs {
+            writeln!(out, "        {} => {{", msg.name.to_uppercase()).unwrap();
+            let mut body = String::new();
+            for field in &msg.fields {
+                match (field.coord.is_some(), field.name.as_str()) {
+                    (true, "z") => body += "    let z = if flat { 0 } else { r.i32()? };\n",
+                    (true, name) if field.ty == "pos" => writeln!(
+                        body,
+                        "    let {name} = if flat {{ (r.i32()?, r.i32()?, 0) }} else {{ r.pos()? }};"
+                    )
+                    .unwrap(),
+                    _ => read_field(schema, &mut body, &msg.name, field),
+                }
+            }
+            for line in body.lines() {
+                writeln!(out, "        {line}").unwrap();
+            }
+            let names: Vec<&str> = msg.fields.iter().map(|f| f.name.as_str()).collect();
+            writeln!(
+                out,
+                "            {ty}::{} {{ {} }}\n        }}",
+                camel(&msg.name),
+                names.join(", ")
+            )
+            .unwrap();
+        }
+        out += "        k => return Err(ProtocolError::UnknownSubmessage(k)),\n    })\n}\n";
+
+        write!(
+            out,
+            "\n/// Schema name of a {dir} submessage id.\n\
+             pub fn {dir}_msg_name(id: u8) -> Option<&'static str> {{\n    \
+             Some(match id {{\n"
+        )
+        .unwrap();
+        for msg in &msgs {
+            writeln!(
+                out,
+                "        {} => \"{}\",",
+                msg.name.to_uppercase(),
+                msg.name
+            )
+            .unwrap();
+        }
+        out += "        _ => return None,\n    })\n}\n";
+
+        if positioned.is_empty() {
+            continue;
+        }
+        write!(
+            out,
+            "\n/// Positions in a {dir} submessage, where each starts after the id.\n\
+             fn {dir}_msg_coords(id: u8) -> &'static [(usize, Coord)] {{\n    \
+             match id {{\n"
+        )
+        .unwrap();
+        for (msg, coords) in &positioned {
+            let coords: Vec<String> = coords
+                .iter()
+                .map(|(at, coord)| format!("({at}, Coord::{})", camel(coord)))
+                .collect();
+            writeln!(
+                out,
+                "        {} => &[{}],",
+                msg.name.to_uppercase(),
+                coords.join(", ")
+            )
+            .unwrap();
+        }
+        out += "        _ => &[],\n    }\n}\n";
+    }
+
+    out
+}
+
+fn doc_comment(doc: &str, indent: &str) -> String {
+    doc.lines().map(|l| format!("{indent}/// {l}\n")).collect()
+}
+
+// Item type of a list, or a scalar's decoded type
+fn owned_scalar(schema: &Schema, ty: &str) -> String {
+    match ty {
+        "pos" => "ChunkPos".into(),
+        "str" => "String".into(),
+        "chunk" => "Chunk".into(),
+        ty => match schema.structs.get(ty) {
+            Some(s) => s.rust.clone(),
+            None => ty.into(),
+        },
+    }
+}
+
+fn owned_type(schema: &Schema, field: &Field) -> String {
+    match field.ty.as_str() {
+        "list" => format!("Vec<{}>", owned_scalar(schema, field.of.as_ref().unwrap())),
+        ty => owned_scalar(schema, ty),
+    }
+}
+
+// Writers borrow what they encode, lists are any exact-size iterator
+fn param_type(schema: &Schema, field: &Field) -> String {
+    match field.ty.as_str() {
+        "str" => "&str".into(),
+        "chunk" => "&Chunk".into(),
+        "list" => format!(
+            "impl ExactSizeIterator<Item = &'a {}>",
+            owned_scalar(schema, field.of.as_ref().unwrap())
+        ),
+        ty => owned_scalar(schema, ty),
+    }
+}
+
+// Writes the field bound to `value` (a parameter or local of `param_type`)
+fn write_field(schema: &Schema, out: &mut String, value: &str, field: &Field) {
+    let line = match field.ty.as_str() {
+        "bool" => format!("buf.push({value} as u8);"),
+        "pos" => format!("write_pos(buf, {value});"),
+        "str" => format!("write_str(buf, {value});"),
+        "chunk" => format!("chunk_wire::encode_as({value}, chunk_format, buf);"),
+        "list" => {
+            let count = field.count.as_ref().unwrap();
+            let of = field.of.as_ref().unwrap();
+            let item = match of.as_str() {
+                "str" => "write_str(buf, item)".to_string(),
+                of if schema.structs.contains_key(of) => format!("write_{of}(buf, item)"),
+                _ => "buf.extend_from_slice(&item.to_le_bytes())".to_string(),
+            };
+            format!(
+                "buf.extend_from_slice(&({value}.len() as {count}).to_le_bytes());\n    \
+                 for item in {value} {{\n        {item};\n    }}"
+            )
+        }
+        _ => format!("buf.extend_from_slice(&{value}.to_le_bytes());"),
+    };
+    writeln!(out, "    {line}").unwrap();
+}
+
+fn read_scalar(schema: &Schema, ty: &str) -> String {
+    match ty {
+        ty if schema.structs.contains_key(ty) => format!("read_{ty}(r)"),
+        "bool" => "r.u8().map(|b| b & 1 != 0)".into(),
+        ty => format!("r.{ty}()"),
+    }
+}
+
+fn read_field(schema: &Schema, out: &mut String, owner: &str, field: &Field) {
+    let name = &field.name;
+    let value = match field.ty.as_str() {
+        "list" => format!(
+            "(0..r.{}()?)\n        .map(|_| {})\n        .collect::<Result<_, _>>()?",
+            field.count.as_ref().unwrap(),
+            read_scalar(schema, field.of.as_ref().unwrap())
+        ),
+        ty => format!("{}?", read_scalar(schema, ty)),
+    };
+    writeln!(out, "    let {name} = {value};").unwrap();
+
+    if let Some(max) = field.max {
+        writeln!(
+            out,
+            "    if {name} > {max} {{\n        \
+             return Err(ProtocolError::Invalid(\"{owner} {name} out of range\"));\n    }}"
+        )
+        .unwrap();
+    }
+}
@@ -0,0 +1,246 @@
+//! TypeScript protocol code for the client SDK. Readers and writers come
+//! from the hand-written `sdk/typescript/src/wire.ts`.
+
+use crate::{Field, Message, SCHEMA, Schema, camel};
+use std::fmt::Write;
+
+pub fn generate(schema: &Schema) -> String {
+    let mut out = format!("// Generated by build/main.rs from {SCHEMA}, do not edit.\n\n");
+    out +=
+        "import { type ChunkPos, ProtocolError, type Reader, type Writer } from \"./wire.js\";\n\n";
+
+    for (dir, id) in &schema.frames {
+        writeln!(
+            out,
+            "export const {}_FRAME = {id:#04x};",
+            dir.to_uppercase()
+        )
+        .unwrap();
+    }
+    for msg in &schema.messages {
+        out += &doc_comment(&msg.doc, "");
+        writeln!(
+            out,
+            "export const {} = {:#04x};",
+            msg.name.to_uppercase(),
+            msg.id
+        )
+        .unwrap();
+    }
+
+    for (name, s) in &schema.structs {
+        let ty = camel(name);
+        if s.tuple {
+            let items: Vec<String> = s
+                .fields
+                .iter()
+                .map(|f| format!("{}: {}", lower_camel(&f.name), ts_type(schema, f)))
+                .collect();
+            write!(out, "\nexport type {ty} = [{}];\n", items.join(", ")).unwrap();
+        } else {
+            writeln!(out, "\nexport interface {ty} {{").unwrap();
+            for field in &s.fields {
+                writeln!(
+                    out,
+                    "    {}: {};",
+                    lower_camel(&field.name),
+                    ts_type(schema, field)
+                )
+                .unwrap();
+            }
+            out += "}\n";
+        }
+    }
+
+    for msg in &schema.messages {
+        assert!(
+            msg.fields.iter().all(|f| f.name != "kind"),
+            "{}: `kind` is reserved for the message id",
+            msg.name
+        );
+        out += "\n";
+        out += &doc_comment(&msg.doc, "");
+        writeln!(out, "export interface {} {{", camel(&msg.name)).unwrap();
+        writeln!(out, "    kind: typeof {};", msg.name.to_uppercase()).unwrap();
+        for field in &msg.fields {
+            writeln!(
+                out,
+                "    {}: {};",
+                lower_camel(&field.name),
+                ts_type(schema, field)
+            )
+            .unwrap();
+        }
+        out += "}\n";
+    }
+
+    for (name, s) in &schema.structs {
+        let ty = camel(name);
+        writeln!(out, "\nfunction write{ty}(w: Writer, v: {ty}): void {{").unwrap();
+        for (i, field) in s.fields.iter().enumerate() {
+            let value = match s.tuple {
+                true => format!("v[{i}]"),
+                false => format!("v.{}", lower_camel(&field.name)),
+            };
+            write_field(schema, &mut out, &value, field);
+        }
+        out += "}\n";
+
+        writeln!(out, "\nfunction read{ty}(r: Reader): {ty} {{").unwrap();
+        for field in &s.fields {
+            read_field(schema, &mut out, "    ", name, field);
+        }
+        let names: Vec<String> = s.fields.iter().map(|f| lower_camel(&f.name)).collect();
+        match s.tuple {
+            true => writeln!(out, "    return [{}];", names.join(", ")),
+            false => writeln!(out, "    return {{ {} }};", names.join(", ")),
+        }
+        .unwrap();
+        out += "}\n";
+    }
+
+    for dir in schema.frames.keys() {
+        let msgs: Vec<&Message> = schema.messages.iter().filter(|m| &m.dir == dir).collect();
+        if msgs.is_empty() {
+            continue;
+        }
+
+        let ty = format!("{}Msg", camel(dir));
+        let variants: Vec<String> = msgs.iter().map(|m| camel(&m.name)).collect();
+        write!(
+            out,
+            "\n/** Decoded {dir} submessage. */\nexport type {ty} = {};\n",
+            variants.join(" | ")
+        )
+        .unwrap();
+
+        write!(
+            out,
+            "\nexport function write{ty}(w: Writer, m: {ty}): void {{\n    \
+             w.u8(m.kind);\n    switch (m.kind) {{\n"
+        )
+        .unwrap();
+        for msg in &msgs {
+            writeln!(out, "        case {}:", msg.name.to_uppercase()).unwrap();
+            let mut body = String::new();
+            for field in &msg.fields {
+                let value = format!("m.{}", lower_camel(&field.name));
+                write_field(schema, &mut body, &value, field);
+            }
+            for line in body.lines() {
+                writeln!(out, "        {line}").unwrap();
+            }
+            out += "            break;\n";
+        }
+        out += "    }\n}\n";
+
+        write!(
+            out,
+            "\nexport function read{ty}(r: Reader): {ty} {{\n    \
+             const kind = r.u8();\n    switch (kind) {{\n"
+        )
+        .unwrap();
+        for msg in &msgs {
+            let kind = msg.name.to_uppercase();
+            writeln!(out, "        case {kind}: {{").unwrap();
+            for field in &msg.fields {
+                read_field(schema, &mut out, "            ", &msg.name, field);
+            }
+            let names: Vec<String> = msg.fields.iter().map(|f| lower_camel(&f.name)).collect();
+            writeln!(
+                out,
+                "            return {{ kind: {kind}, {} }};\n        }}",
+                names.join(", ")
+            )
+            .unwrap();
+        }
+        out += "        default:\n            \
+                throw new ProtocolError(`unknown submessage ${kind}`);\n    }\n}\n";
+    }
+
+    out
+}
+
+fn doc_comment(doc: &str, indent: &str) -> String {
+    if !doc.contains('\n') {
+        return format!("{indent}/** {doc} */\n");
+    }
+
+    let mut out = format!("{indent}/**\n");
+    for line in doc.lines() {
+        writeln!(out, "{indent} * {line}").unwrap();
+    }
+    out + indent + " */\n"
+}
+
+fn lower_camel(snake: &str) -> String {
+    let camel = camel(snake);
+    camel[..1].to_lowercase() + &camel[1..]
+}
+
+fn scalar_type(schema: &Schema, ty: &str) -> String {
+    match ty {
+        "bool" => "boolean".into(),
+        "pos" => "ChunkPos".into(),
+        "str" => "string".into(),
+        "chunk" => "Uint16Array".into(),
+        ty if schema.structs.contains_key(ty) => camel(ty),
+        _ => "number".into(),
+    }
+}
+
+fn ts_type(schema: &Schema, field: &Field) -> String {
+    match field.ty.as_str() {
+        "list" => format!("{}[]", scalar_type(schema, field.of.as_ref().unwrap())),
+        ty => scalar_type(schema, ty),
+    }
+}
+
+fn write_scalar(schema: &Schema, ty: &str, value: &str) -> String {
+    match ty {
+        ty if schema.structs.contains_key(ty) => format!("write{}(w, {value});", camel(ty)),
+        ty => format!("w.{ty}({value});"),
+    }
+}
+
+fn write_field(schema: &Schema, out: &mut String, value: &str, field: &Field) {
+    let line = match field.ty.as_str() {
+        "list" => format!(
+            "w.{}({value}.length);\n    for (const item of {value}) {{\n        {}\n    }}",
+            field.count.as_ref().unwrap(),
+            write_scalar(schema, field.of.as_ref().unwrap(), "item")
+        ),
+        ty => write_scalar(schema, ty, value),
+    };
+    writeln!(out, "    {line}").unwrap();
+}
+
+fn read_scalar(schema: &Schema, ty: &str) -> String {
+    match ty {
+        ty if schema.structs.contains_key(ty) => format!("read{}(r)", camel(ty)),
+        ty => format!("r.{ty}()"),
+    }
+}
+
+fn read_field(schema: &Schema, out: &mut String, indent: &str, owner: &str, field: &Field) {
+    let name = lower_camel(&field.name);
+    let value = match field.ty.as_str() {
+        "list" => format!(
+            "r.list(r.{}(), () => {})",
+            field.count.as_ref().unwrap(),
+            read_scalar(schema, field.of.as_ref().unwrap())
+        ),
+        ty => read_scalar(schema, ty),
+    };
+    writeln!(out, "{indent}const {name} = {value};").unwrap();
+
+    if let Some(max) = field.max {
+        writeln!(
+            out,
+            "{indent}if ({name} > {max}) {{\n{indent}    \
+             throw new ProtocolError(\"{owner} {} out of range\");\n{indent}}}",
+            field.name
+        )
+        .unwrap();
+    }
+}
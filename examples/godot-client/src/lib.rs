@@ -0,0 +1,365 @@
+//! Godot 4 example client: connects to a Teleboxel server with Godot's
+//! `WebSocketPeer`, feeds every message to `teleboxel::client::Client`, and
+//! renders the chunks in the interest as one `MultiMeshInstance3D` each.
+//! Arrow keys (the `ui_*` actions) walk the player, which moves the interest.
+//!
+//! It doubles as an end-to-end check: protocol errors, chunk versions going
+//! backwards and block ids missing from the registry are reported with
+//! `godot_error!` and counted in `errors`.
+
+use godot::{
+    classes::{
+        BoxMesh, INode3D, Input, MultiMesh, MultiMeshInstance3D, Node3D, StandardMaterial3D,
+        WebSocketPeer, base_material_3d::Flags, multi_mesh::TransformFormat,
+        web_socket_peer::State,
+    },
+    prelude::*,
+};
+use std::collections::HashMap;
+use teleboxel::{
+    chunk::{CHUNK_SIZE, Chunk, ChunkPos, split},
+    client::{self, Client, ClientEvent},
+};
+
+struct TeleboxelExtension;
+
+#[gdextension]
+unsafe impl ExtensionLibrary for TeleboxelExtension {}
+
+#[derive(GodotClass)]
+#[class(base = Node3D)]
+struct TeleboxelWorld {
+    #[export]
+    url: GString,
+    /// Loads and saves the player record when the server has storage.
+    #[export]
+    player_name: GString,
+    /// Interest radius in chunks.
+    #[export]
+    radius: i32,
+    /// Blocks per second.
+    #[export]
+    speed: f32,
+    /// Follows the player, e.g. a `Camera3D`.
+    #[export]
+    follow: Option<Gd<Node3D>>,
+    /// Protocol errors and inconsistencies seen so far.
+    #[var]
+    errors: i64,
+
+    ws: Gd<WebSocketPeer>,
+    client: Client,
+    connected: bool,
+    player: Vector3,
+    sent_position: Option<(i32, i32, i32)>,
+    sent_interest: Option<ChunkPos>,
+    meshes: HashMap<ChunkPos, Gd<MultiMeshInstance3D>>,
+    versions: HashMap<ChunkPos, u32>,
+    cube: Gd<BoxMesh>,
+    base: Base<Node3D>,
+}
+
+#[godot_api]
+impl INode3D for TeleboxelWorld {
+    fn init(base: Base<Node3D>) -> Self {
+        let mut material = StandardMaterial3D::new_gd();
+        material.set_flag(Flags::ALBEDO_FROM_VERTEX_COLOR, true);
+        let mut cube = BoxMesh::new_gd();
+        cube.set_material(&material);
+
+        Self {
+            url: "ws://127.0.0.1:8080/ws".into(),
+            player_name: GString::new(),
+            radius: 2,
+            speed: 8.0,
+            follow: None,
+            errors: 0,
+            ws: WebSocketPeer::new_gd(),
+            client: Client::new(),
+            connected: false,
+            player: Vector3::new(8.0, 24.0, 8.0),
+            sent_position: None,
+            sent_interest: None,
+            meshes: HashMap::new(),
+            versions: HashMap::new(),
+            cube,
+            base,
+        }
+    }
+
+    fn ready(&mut self) {
+        let mut url = self.url.to_string();
+        if !self.player_name.is_empty() {
+            url = format!("{url}?name={}", self.player_name);
+        }
+        let err = self.ws.connect_to_url(&url);
+        if err != godot::global::Error::OK {
+            godot_error!("can't connect to {url}: {err:?}");
+        }
+    }
+
+    fn process(&mut self, delta: f64) {
+        self.ws.poll();
+        match self.ws.get_ready_state() {
+            State::OPEN => {}
+            State::CLOSED if self.connected => {
+                self.connected = false;
+                godot_print!(
+                    "closed: {} {}",
+                    self.ws.get_close_code(),
+                    self.ws.get_close_reason()
+                );
+                return;
+            }
+            _ => return,
+        }
+
+        while self.ws.get_available_packet_count() > 0 {
+            let packet = self.ws.get_packet();
+            let result = if self.ws.was_string_packet() {
+                match std::str::from_utf8(packet.as_slice()) {
+                    Ok(text) => self.client.receive_text(text),
+                    Err(_) => {
+                        self.error("text message isn't UTF-8".into());
+                        continue;
+                    }
+                }
+            } else {
+                self.client.receive_binary(packet.as_slice())
+            };
+            if let Err(e) = result {
+                self.error(e.to_string());
+            }
+        }
+
+        while let Some(event) = self.client.next_event() {
+            self.handle(event);
+        }
+
+        if self.connected {
+            self.walk(delta as f32);
+        }
+    }
+}
+
+impl TeleboxelWorld {
+    fn handle(&mut self, event: ClientEvent) {
+        match event {
+            ClientEvent::Connected { id } => {
+                godot_print!("connected as player {id}");
+                self.connected = true;
+            }
+            ClientEvent::BlockRegistry => {
+                // Colors come from the registry, redraw what's already here
+                let held: Vec<_> = self.meshes.keys().copied().collect();
+                for pos in held {
+                    self.rebuild(pos);
+                }
+            }
+            ClientEvent::ChunkChanged { pos, version } => {
+                let last = self.versions.insert(pos, version);
+                if last.is_some_and(|last| last >= version) {
+                    self.error(format!(
+                        "chunk {pos:?} went from version {last:?} to {version}"
+                    ));
+                }
+                self.rebuild(pos);
+            }
+            ClientEvent::Reply(reply) => {
+                if reply.contains("Error") {
+                    godot_warn!("{reply}");
+                }
+            }
+            ClientEvent::Chat { from, text } => godot_print!("{from}: {text}"),
+            ClientEvent::Drain { seconds, address } => match address {
+                Some(address) => godot_warn!("Server moving to {address} within {seconds}s"),
+                None => godot_warn!("Server closing within {seconds}s"),
+            },
+            // This example doesn't reconnect
+            ClientEvent::Resume { .. } => {}
+            ClientEvent::Transfer { address, .. } => {
+                godot_warn!("Portal to {address}, reconnecting isn't supported")
+            }
+            // A voxel walker, not a lockstep or relay game
+            ClientEvent::LockstepTick { .. }
+            | ClientEvent::LockstepState { .. }
+            | ClientEvent::Relay { .. } => {}
+            // No microphone or Opus decoder here
+            ClientEvent::Voice { .. } => {}
+            // Edits that are off come back as error replies
+            ClientEvent::Features(features) => godot_print!("features: {features:?}"),
+            // No entity meshes yet
+            ClientEvent::Entity(_)
+            | ClientEvent::EntityGone { .. }
+            | ClientEvent::Mounted(_)
+            | ClientEvent::Dismounted { .. }
+            | ClientEvent::Attached { .. }
+            | ClientEvent::Detached { .. }
+            | ClientEvent::Effect(_)
+            | ClientEvent::Trigger { .. } => {}
+            // Moves aren't predicted, nothing to replay
+            ClientEvent::InputAck { .. } => {}
+            // Chunks aren't interpolated, no clock to keep
+            ClientEvent::TimeSync { .. } => {}
+            // Walks on from there, `walk` sends the new interest
+            ClientEvent::Teleported { position: (x, y, z) } => {
+                self.player = Vector3::new(x as f32, y as f32, z as f32);
+                self.sent_position = Some((x, y, z));
+            }
+            // Another room's chunks, with their own versions, follow
+            ClientEvent::RoomChanged { room, id } => {
+                godot_print!("moved to {} as player {id}", room.as_deref().unwrap_or("main"));
+                for (_, mut mesh) in self.meshes.drain() {
+                    mesh.queue_free();
+                }
+                self.versions.clear();
+            }
+        }
+    }
+
+    /// Moves the player with the `ui_*` actions, sending `SetPosition` when
+    /// it enters another block and `SetInterest` when it enters another chunk.
+    fn walk(&mut self, delta: f32) {
+        let input = Input::singleton();
+        let dir = input.get_vector("ui_left", "ui_right", "ui_up", "ui_down");
+        self.player += Vector3::new(dir.x, 0.0, dir.y) * self.speed * delta;
+        if let Some(follow) = &mut self.follow {
+            follow.set_position(self.player + Vector3::new(0.0, 12.0, 16.0));
+        }
+
+        let block = (
+            self.player.x.floor() as i32,
+            self.player.y.floor() as i32,
+            self.player.z.floor() as i32,
+        );
+        if self.sent_position != Some(block) {
+            self.sent_position = Some(block);
+            self.send(client::set_position_command(block));
+        }
+
+        let center = (split(block.0).0, split(block.1).0, split(block.2).0);
+        if self.sent_interest != Some(center) {
+            self.sent_interest = Some(center);
+            self.send(client::set_interest_command(center, self.radius as u16));
+            self.drop_outside(center);
+        }
+    }
+
+    /// Frees chunks that left the interest, the server stops updating them.
+    fn drop_outside(&mut self, (x, y, z): ChunkPos) {
+        let r = self.radius;
+        let outside: Vec<_> = self
+            .meshes
+            .keys()
+            .copied()
+            .filter(|&(cx, cy, cz)| (cx - x).abs() > r || (cy - y).abs() > r || (cz - z).abs() > r)
+            .collect();
+        for pos in outside {
+            if let Some(mut mesh) = self.meshes.remove(&pos) {
+                mesh.queue_free();
+            }
+            self.versions.remove(&pos);
+            self.client.forget(pos);
+        }
+    }
+
+    /// One cube per visible block (solid, next to a non-solid one).
+    fn rebuild(&mut self, pos: ChunkPos) {
+        let Some((_, chunk)) = self.client.chunk(pos) else {
+            return;
+        };
+
+        let mut cubes = Vec::new();
+        let mut unknown = None;
+        for y in 0..CHUNK_SIZE {
+            for z in 0..CHUNK_SIZE {
+                for x in 0..CHUNK_SIZE {
+                    let id = chunk.get(x, y, z);
+                    if id == 0 || !exposed(chunk, x, y, z) {
+                        continue;
+                    }
+                    let Some(def) = self.client.blocks().iter().find(|d| d.id == id) else {
+                        unknown = Some(id);
+                        continue;
+                    };
+                    cubes.push((Vector3::new(x as f32, y as f32, z as f32), color(&def.name)));
+                }
+            }
+        }
+        // The registry comes first, so this is a server bug
+        if let Some(id) = unknown.filter(|_| !self.client.blocks().is_empty()) {
+            self.error(format!("chunk {pos:?} has block {id}, not in the registry"));
+        }
+
+        let mut multimesh = MultiMesh::new_gd();
+        multimesh.set_transform_format(TransformFormat::TRANSFORM_3D);
+        multimesh.set_use_colors(true);
+        multimesh.set_mesh(&self.cube);
+        multimesh.set_instance_count(cubes.len() as i32);
+        for (i, (at, color)) in cubes.into_iter().enumerate() {
+            multimesh.set_instance_transform(i as i32, Transform3D::new(Basis::IDENTITY, at));
+            multimesh.set_instance_color(i as i32, color);
+        }
+
+        let mut mesh = match self.meshes.get(&pos) {
+            Some(mesh) => mesh.clone(),
+            None => {
+                let mut mesh = MultiMeshInstance3D::new_alloc();
+                let (x, y, z) = pos;
+                mesh.set_name(&format!("chunk {x} {y} {z}"));
+                mesh.set_position(Vector3::new(x as f32, y as f32, z as f32) * CHUNK_SIZE as f32);
+                self.base_mut().add_child(&mesh);
+                self.meshes.insert(pos, mesh.clone());
+                mesh
+            }
+        };
+        mesh.set_multimesh(&multimesh);
+    }
+
+    fn send(&mut self, text: String) {
+        self.ws.send_text(&text);
+    }
+
+    fn error(&mut self, message: String) {
+        self.errors += 1;
+        godot_error!("teleboxel: {message}");
+    }
+}
+
+/// Chunk edges count as exposed, neighbors may not be loaded yet.
+fn exposed(chunk: &Chunk, x: usize, y: usize, z: usize) -> bool {
+    let last = CHUNK_SIZE - 1;
+    if x == 0 || y == 0 || z == 0 || x == last || y == last || z == last {
+        return true;
+    }
+    [
+        (x - 1, y, z),
+        (x + 1, y, z),
+        (x, y - 1, z),
+        (x, y + 1, z),
+        (x, y, z - 1),
+        (x, y, z + 1),
+    ]
+    .into_iter()
+    .any(|(x, y, z)| chunk.get(x, y, z) == 0)
+}
+
+/// Stable color per block name, the registry carries no textures yet.
+fn color(name: &str) -> Color {
+    match name {
+        "stone" => Color::from_rgb(0.5, 0.5, 0.5),
+        "dirt" => Color::from_rgb(0.45, 0.3, 0.2),
+        "grass" => Color::from_rgb(0.3, 0.6, 0.25),
+        "water" => Color::from_rgb(0.2, 0.4, 0.8),
+        _ => {
+            let hash = name
+                .bytes()
+                .fold(2166136261u32, |h, b| (h ^ b as u32).wrapping_mul(16777619));
+            Color::from_rgb(
+                (hash & 0xFF) as f32 / 255.0,
+                (hash >> 8 & 0xFF) as f32 / 255.0,
+                (hash >> 16 & 0xFF) as f32 / 255.0,
+            )
+        }
+    }
+}
@@ -0,0 +1,242 @@
+/*
+ * Teleboxel client C API (ffi/src/lib.rs), for native engine plugins.
+ *
+ * Link against libteleboxel_ffi (cargo build -p teleboxel-ffi --release).
+ * The engine owns the websocket: pass every server message to
+ * tbx_client_receive_text / tbx_client_receive_binary, poll
+ * tbx_client_next_event, and send the text built by the tbx_*_command
+ * functions as text messages.
+ *
+ * A TbxClient isn't thread safe. Pointers it returns stay valid until the
+ * next call that changes it (receive, next_event, forget, free). Strings
+ * are UTF-8 with a length, not NUL-terminated, except tbx_client_last_error.
+ */
+
+#ifndef TELEBOXEL_H
+#define TELEBOXEL_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define TBX_OK 0
+#define TBX_ERR_HANDSHAKE (-1)
+#define TBX_ERR_NOT_CONNECTED (-2)
+#define TBX_ERR_PROTOCOL (-3)
+/* Null client or data, or text that isn't UTF-8 */
+#define TBX_ERR_INVALID (-4)
+
+#define TBX_EVENT_CONNECTED 1
+#define TBX_EVENT_BLOCK_REGISTRY 2
+#define TBX_EVENT_CHUNK_CHANGED 3
+#define TBX_EVENT_REPLY 4
+#define TBX_EVENT_CHAT 5
+#define TBX_EVENT_DRAIN 6
+#define TBX_EVENT_RESUME 7
+#define TBX_EVENT_ROOM 8
+#define TBX_EVENT_TRANSFER 9
+#define TBX_EVENT_LOCKSTEP 10
+#define TBX_EVENT_LOCKSTEP_STATE 11
+#define TBX_EVENT_RELAY 12
+#define TBX_EVENT_VOICE 13
+#define TBX_EVENT_TELEPORT 14
+#define TBX_EVENT_FEATURES 15
+#define TBX_EVENT_ENTITY 16
+#define TBX_EVENT_ENTITY_GONE 17
+#define TBX_EVENT_MOUNT 18
+#define TBX_EVENT_DISMOUNT 19
+#define TBX_EVENT_ATTACH 20
+#define TBX_EVENT_DETACH 21
+#define TBX_EVENT_EFFECT 22
+#define TBX_EVENT_TRIGGER 23
+#define TBX_EVENT_INPUT_ACK 24
+#define TBX_EVENT_TIME_SYNC 25
+#define TBX_EVENT_REBASE 26
+#define TBX_EVENT_COORDS 27
+#define TBX_EVENT_SIMULATION 28
+#define TBX_EVENT_TIME_SKIP 29
+#define TBX_EVENT_ACHIEVEMENT 30
+#define TBX_EVENT_ROLLBACK 31
+#define TBX_EVENT_ROLLBACK_INPUT 32
+#define TBX_EVENT_ROLLBACK_CONFIRM 33
+#define TBX_EVENT_INTERP_HINT 34
+#define TBX_EVENT_MINIMAP 35
+
+/* TbxEvent features bits, set when on */
+#define TBX_FEATURE_CHAT 1
+#define TBX_FEATURE_BUILD 2
+#define TBX_FEATURE_PVP 4
+
+typedef struct TbxClient TbxClient;
+
+/* Fields not used by an event kind are zero */
+typedef struct TbxEvent {
+    uint32_t kind;
+    /* TBX_EVENT_CONNECTED, TBX_EVENT_ROOM, TBX_EVENT_RELAY and
+       TBX_EVENT_VOICE sender, TBX_EVENT_ENTITY(_GONE) entity,
+       TBX_EVENT_MOUNT and TBX_EVENT_DISMOUNT player, TBX_EVENT_ATTACH and
+       TBX_EVENT_DETACH entity, TBX_EVENT_EFFECT effect,
+       TBX_EVENT_ROLLBACK_INPUT player */
+    uint32_t id;
+    /* TBX_EVENT_CHUNK_CHANGED, or the TBX_EVENT_TELEPORT, TBX_EVENT_ENTITY,
+       TBX_EVENT_DISMOUNT, TBX_EVENT_DETACH and TBX_EVENT_EFFECT block
+       position, or the TBX_EVENT_MOUNT and TBX_EVENT_ATTACH offset from
+       the parent, or the TBX_EVENT_REBASE origin, or the TBX_EVENT_MINIMAP
+       corner x and z with the height heights are above as y */
+    int32_t pos[3];
+    /* TBX_EVENT_CHUNK_CHANGED, the TBX_EVENT_LOCKSTEP(_STATE) relay tick,
+       the TBX_EVENT_INPUT_ACK sequence number or the TBX_EVENT_ROLLBACK,
+       TBX_EVENT_ROLLBACK_INPUT and TBX_EVENT_ROLLBACK_CONFIRM frame */
+    uint32_t version;
+    /* TBX_EVENT_REPLY, TBX_EVENT_CHAT, TBX_EVENT_DRAIN address (empty if
+       none), TBX_EVENT_RESUME and TBX_EVENT_TRANSFER token, TBX_EVENT_ROOM
+       room (empty for main), TBX_EVENT_LOCKSTEP_STATE state, TBX_EVENT_RELAY
+       data (bytes as sent), TBX_EVENT_VOICE Opus frame, TBX_EVENT_EFFECT
+       params, TBX_EVENT_TRIGGER trigger, TBX_EVENT_ACHIEVEMENT title,
+       TBX_EVENT_ROLLBACK_INPUT data, TBX_EVENT_MINIMAP heights */
+    const uint8_t *text;
+    size_t text_len;
+    /* TBX_EVENT_CHAT sender, TBX_EVENT_TRANSFER address,
+       TBX_EVENT_ACHIEVEMENT name, TBX_EVENT_MINIMAP RGB colors (3 bytes
+       per cell) */
+    const uint8_t *from;
+    size_t from_len;
+    /* TBX_EVENT_DRAIN, until the server closes the connection */
+    uint32_t seconds;
+    /* TBX_EVENT_LOCKSTEP and TBX_EVENT_ROLLBACK_CONFIRM, inputs from
+       tbx_client_lockstep_input */
+    uint32_t hash;
+    size_t input_count;
+    /* TBX_EVENT_VOICE, how far the speaker is in blocks */
+    uint32_t distance;
+    /* TBX_EVENT_FEATURES, TBX_FEATURE_* bits of what's on in the world */
+    uint32_t features;
+    /* TBX_EVENT_ENTITY, the player id moving it, 0 for the server */
+    uint32_t authority;
+    /* TBX_EVENT_MOUNT, the entity ridden */
+    uint32_t entity;
+    /* TBX_EVENT_ATTACH, the entity or (parent_is_player) player it hangs
+       from */
+    uint32_t parent;
+    bool parent_is_player;
+    /* TBX_EVENT_TRIGGER, walked in rather than out */
+    bool entered;
+    /* TBX_EVENT_TIME_SYNC: client time echoed (0 in the handshake), server
+       clock in ms, tick rate, recommended interpolation delay in ms (also
+       TBX_EVENT_INTERP_HINT's) */
+    uint32_t client_time;
+    uint32_t server_time;
+    uint32_t tick_hz;
+    uint32_t interp_delay;
+    /* TBX_EVENT_COORDS: positions have no z (always 0) when flat, and are
+       in units per block */
+    bool flat;
+    uint32_t units;
+    /* TBX_EVENT_SIMULATION: no ticks while paused, ticks at scale percent
+       of the tick rate; TBX_EVENT_MINIMAP, blocks per cell */
+    bool paused;
+    uint32_t scale;
+    /* TBX_EVENT_TIME_SKIP, ticks of game time the server didn't simulate */
+    uint32_t skipped;
+    /* TBX_EVENT_ROLLBACK, frames ahead to send inputs for */
+    uint32_t delay;
+    /* TBX_EVENT_INTERP_HINT, ms between updates and updates to keep
+       buffered */
+    uint32_t update_ms;
+    uint32_t buffer;
+    /* TBX_EVENT_VOICE, solid blocks between the speaker and this player, 0
+       unless the server counts them */
+    uint32_t occlusion;
+    /* TBX_EVENT_MINIMAP, cells on a side */
+    uint32_t size;
+} TbxEvent;
+
+typedef struct TbxBlock {
+    uint16_t id;
+    bool solid;
+    const uint8_t *name;
+    size_t name_len;
+    size_t tag_count;
+} TbxBlock;
+
+TbxClient *tbx_client_new(void);
+void tbx_client_free(TbxClient *c);
+
+/* Return TBX_OK or a TBX_ERR_* code, see tbx_client_last_error */
+int32_t tbx_client_receive_text(TbxClient *c, const uint8_t *text, size_t len);
+int32_t tbx_client_receive_binary(TbxClient *c, const uint8_t *data, size_t len);
+const char *tbx_client_last_error(const TbxClient *c);
+
+/* Returns false when there are no more events */
+bool tbx_client_next_event(TbxClient *c, TbxEvent *out);
+
+/* Input i of the last TBX_EVENT_LOCKSTEP or TBX_EVENT_ROLLBACK_CONFIRM, in
+ * player id order, or NULL if out of range */
+const uint8_t *tbx_client_lockstep_input(const TbxClient *c, size_t i, uint32_t *player,
+                                         size_t *len);
+
+/* 0 until connected */
+uint32_t tbx_client_id(const TbxClient *c);
+
+/* World block coordinates, 0 (air) if the chunk isn't held */
+uint16_t tbx_client_block(const TbxClient *c, int32_t x, int32_t y, int32_t z);
+
+/* 4096 block ids, index y * 256 + z * 16 + x, or NULL if not held.
+ * version may be NULL. */
+const uint16_t *tbx_client_chunk(const TbxClient *c, int32_t cx, int32_t cy, int32_t cz,
+                                 uint32_t *version);
+void tbx_client_forget(TbxClient *c, int32_t cx, int32_t cy, int32_t cz);
+
+/* Block registry, in id order */
+size_t tbx_client_block_count(const TbxClient *c);
+bool tbx_client_block_info(const TbxClient *c, size_t i, TbxBlock *out);
+const uint8_t *tbx_client_block_tag(const TbxClient *c, size_t i, size_t t, size_t *len);
+
+/* Write the command text into buf if it fits in cap bytes, return its
+ * length either way */
+size_t tbx_set_interest_command(int32_t cx, int32_t cy, int32_t cz, uint16_t radius,
+                                uint8_t *buf, size_t cap);
+/* A chunk corner, positions are from there both ways; also the answer to
+ * TBX_EVENT_REBASE */
+size_t tbx_origin_command(int32_t x, int32_t y, int32_t z, uint8_t *buf, size_t cap);
+size_t tbx_set_position_command(int32_t x, int32_t y, int32_t z, uint8_t *buf, size_t cap);
+/* velocity: three floats, blocks per second, or NULL */
+size_t tbx_set_transform_command(int32_t x, int32_t y, int32_t z, float yaw, float pitch,
+                                 const float *velocity, uint8_t *buf, size_t cap);
+size_t tbx_set_block_command(int32_t x, int32_t y, int32_t z, uint16_t block, uint8_t *buf,
+                             size_t cap);
+size_t tbx_move_entity_command(uint32_t id, int32_t x, int32_t y, int32_t z, uint8_t *buf,
+                               size_t cap);
+size_t tbx_mount_command(uint32_t entity, uint8_t *buf, size_t cap);
+size_t tbx_dismount_command(uint8_t *buf, size_t cap);
+size_t tbx_minimap_command(uint8_t size, uint8_t scale, uint8_t *buf, size_t cap);
+/* 0 for null or non-UTF-8 text */
+size_t tbx_say_command(const uint8_t *text, size_t len, uint8_t *buf, size_t cap);
+/* 0 for null or non-UTF-8 room */
+size_t tbx_join_room_command(const uint8_t *room, size_t len, uint8_t *buf, size_t cap);
+size_t tbx_leave_room_command(uint8_t *buf, size_t cap);
+/* rtt_ms: the last round trip, or NULL before the first TBX_EVENT_TIME_SYNC */
+size_t tbx_time_sync_command(uint32_t client_ms, const uint32_t *rtt_ms, uint8_t *buf,
+                             size_t cap);
+/* 0 for null or non-UTF-8 data */
+size_t tbx_input_command(uint32_t tick, const uint8_t *data, size_t len, uint8_t *buf,
+                         size_t cap);
+size_t tbx_lockstep_state_command(uint32_t tick, const uint8_t *data, size_t len, uint8_t *buf,
+                                  size_t cap);
+/* Binary client frame for relay rooms, to_count 0 sends to everyone else.
+ * 0 for null pointers, over 255 players or over 4096 bytes of data */
+size_t tbx_relay_frame(uint32_t seq, const uint32_t *to, size_t to_count, const uint8_t *data,
+                       size_t len, uint8_t *buf, size_t cap);
+/* Binary client frame with one Opus frame for the players in voice range.
+ * 0 for null data or over 1275 bytes */
+size_t tbx_voice_frame(uint32_t seq, const uint8_t *data, size_t len, uint8_t *buf, size_t cap);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
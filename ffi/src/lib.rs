@@ -0,0 +1,1301 @@
+//! C ABI over `teleboxel::client`, so Unity/Unreal/Godot native plugins reuse
+//! the server's own protocol decoding. Declarations are in
+//! `include/teleboxel.h`.
+//!
+//! The engine owns the websocket: it hands every server message to
+//! `tbx_client_receive_text` / `tbx_client_receive_binary`, polls
+//! `tbx_client_next_event`, and sends the text built by the `tbx_*_command`
+//! functions. A `TbxClient` isn't thread safe; use it from one thread at a
+//! time. Pointers it returns stay valid until the next call that takes the
+//! client mutably (receive, next_event, forget, free).
+
+use std::{
+    ffi::{CString, c_char},
+    ptr, slice,
+};
+use teleboxel::{
+    chunk::CHUNK_VOLUME,
+    client::{self, Client, ClientError, ClientEvent},
+    entities::Parent,
+    lockstep::LockstepInput,
+};
+
+pub const TBX_OK: i32 = 0;
+pub const TBX_ERR_HANDSHAKE: i32 = -1;
+pub const TBX_ERR_NOT_CONNECTED: i32 = -2;
+pub const TBX_ERR_PROTOCOL: i32 = -3;
+/// Null client or data, or text that isn't UTF-8.
+pub const TBX_ERR_INVALID: i32 = -4;
+
+pub const TBX_EVENT_CONNECTED: u32 = 1;
+pub const TBX_EVENT_BLOCK_REGISTRY: u32 = 2;
+pub const TBX_EVENT_CHUNK_CHANGED: u32 = 3;
+pub const TBX_EVENT_REPLY: u32 = 4;
+pub const TBX_EVENT_CHAT: u32 = 5;
+pub const TBX_EVENT_DRAIN: u32 = 6;
+pub const TBX_EVENT_RESUME: u32 = 7;
+pub const TBX_EVENT_ROOM: u32 = 8;
+pub const TBX_EVENT_TRANSFER: u32 = 9;
+pub const TBX_EVENT_LOCKSTEP: u32 = 10;
+pub const TBX_EVENT_LOCKSTEP_STATE: u32 = 11;
+pub const TBX_EVENT_RELAY: u32 = 12;
+pub const TBX_EVENT_VOICE: u32 = 13;
+pub const TBX_EVENT_TELEPORT: u32 = 14;
+pub const TBX_EVENT_FEATURES: u32 = 15;
+pub const TBX_EVENT_ENTITY: u32 = 16;
+pub const TBX_EVENT_ENTITY_GONE: u32 = 17;
+pub const TBX_EVENT_MOUNT: u32 = 18;
+pub const TBX_EVENT_DISMOUNT: u32 = 19;
+pub const TBX_EVENT_ATTACH: u32 = 20;
+pub const TBX_EVENT_DETACH: u32 = 21;
+pub const TBX_EVENT_EFFECT: u32 = 22;
+pub const TBX_EVENT_TRIGGER: u32 = 23;
+pub const TBX_EVENT_INPUT_ACK: u32 = 24;
+pub const TBX_EVENT_TIME_SYNC: u32 = 25;
+pub const TBX_EVENT_REBASE: u32 = 26;
+pub const TBX_EVENT_COORDS: u32 = 27;
+pub const TBX_EVENT_SIMULATION: u32 = 28;
+pub const TBX_EVENT_TIME_SKIP: u32 = 29;
+pub const TBX_EVENT_ACHIEVEMENT: u32 = 30;
+pub const TBX_EVENT_ROLLBACK: u32 = 31;
+pub const TBX_EVENT_ROLLBACK_INPUT: u32 = 32;
+pub const TBX_EVENT_ROLLBACK_CONFIRM: u32 = 33;
+pub const TBX_EVENT_INTERP_HINT: u32 = 34;
+pub const TBX_EVENT_MINIMAP: u32 = 35;
+
+/// `TbxEvent::features` bits, set when on.
+pub const TBX_FEATURE_CHAT: u32 = 1;
+pub const TBX_FEATURE_BUILD: u32 = 2;
+pub const TBX_FEATURE_PVP: u32 = 4;
+
+pub struct TbxClient {
+    client: Client,
+    error: CString,
+    // Text of the last reply, chat, drain, resume, room, lockstep state,
+    // relay or voice event, and the chat sender (or minimap colors)
+    reply: Vec<u8>,
+    from: Vec<u8>,
+    // Inputs of the last lockstep or rollback confirm event
+    inputs: Vec<LockstepInput>,
+}
+
+/// Fields not used by an event kind are zero.
+#[repr(C)]
+pub struct TbxEvent {
+    pub kind: u32,
+    /// `TBX_EVENT_CONNECTED` and `TBX_EVENT_ROOM`, the `TBX_EVENT_RELAY`
+    /// and `TBX_EVENT_VOICE` sender, the `TBX_EVENT_ENTITY` and
+    /// `TBX_EVENT_ENTITY_GONE` entity, the `TBX_EVENT_MOUNT` and
+    /// `TBX_EVENT_DISMOUNT` player, the `TBX_EVENT_ATTACH` and
+    /// `TBX_EVENT_DETACH` entity, the `TBX_EVENT_EFFECT` effect, the
+    /// `TBX_EVENT_ROLLBACK_INPUT` player
+    pub id: u32,
+    /// `TBX_EVENT_CHUNK_CHANGED`, or the `TBX_EVENT_TELEPORT`,
+    /// `TBX_EVENT_ENTITY`, `TBX_EVENT_DISMOUNT`, `TBX_EVENT_DETACH` and
+    /// `TBX_EVENT_EFFECT` block position, or the `TBX_EVENT_MOUNT` and
+    /// `TBX_EVENT_ATTACH` offset from the parent, or the `TBX_EVENT_REBASE`
+    /// origin, or the `TBX_EVENT_MINIMAP` corner x and z with the height
+    /// heights are above as y
+    pub pos: [i32; 3],
+    /// `TBX_EVENT_CHUNK_CHANGED`, the relay tick of `TBX_EVENT_LOCKSTEP`
+    /// and `TBX_EVENT_LOCKSTEP_STATE`, or the `TBX_EVENT_INPUT_ACK`
+    /// sequence number, or the `TBX_EVENT_ROLLBACK`,
+    /// `TBX_EVENT_ROLLBACK_INPUT` and `TBX_EVENT_ROLLBACK_CONFIRM` frame
+    pub version: u32,
+    /// `TBX_EVENT_REPLY` and `TBX_EVENT_CHAT`, the `TBX_EVENT_DRAIN`
+    /// replacement address (empty if none), the `TBX_EVENT_RESUME` and
+    /// `TBX_EVENT_TRANSFER` token, the `TBX_EVENT_ROOM` room (empty for
+    /// the main world), the `TBX_EVENT_LOCKSTEP_STATE` state, the
+    /// `TBX_EVENT_TRIGGER` trigger, the `TBX_EVENT_ACHIEVEMENT` title or the
+    /// `TBX_EVENT_ROLLBACK_INPUT` data. UTF-8, not NUL-terminated, except for
+    /// the `TBX_EVENT_RELAY` data as sent, the `TBX_EVENT_VOICE` Opus frame,
+    /// the `TBX_EVENT_EFFECT` params and the `TBX_EVENT_MINIMAP` heights
+    pub text: *const u8,
+    pub text_len: usize,
+    /// `TBX_EVENT_CHAT` sender, the `TBX_EVENT_TRANSFER` address or the
+    /// `TBX_EVENT_ACHIEVEMENT` name, UTF-8, not NUL-terminated, or the
+    /// `TBX_EVENT_MINIMAP` RGB colors, 3 bytes per cell
+    pub from: *const u8,
+    pub from_len: usize,
+    /// `TBX_EVENT_DRAIN`, until the server closes the connection
+    pub seconds: u32,
+    /// `TBX_EVENT_LOCKSTEP` and `TBX_EVENT_ROLLBACK_CONFIRM`, see
+    /// `tbx_client_lockstep_input` for the inputs
+    pub hash: u32,
+    pub input_count: usize,
+    /// `TBX_EVENT_VOICE`, how far the speaker is in blocks
+    pub distance: u32,
+    /// `TBX_EVENT_FEATURES`, `TBX_FEATURE_*` bits of what's on in the world
+    pub features: u32,
+    /// `TBX_EVENT_ENTITY`, the player id moving it, 0 for the server
+    pub authority: u32,
+    /// `TBX_EVENT_MOUNT`, the entity ridden
+    pub entity: u32,
+    /// `TBX_EVENT_ATTACH`, the entity or (`parent_is_player`) player it
+    /// hangs from
+    pub parent: u32,
+    pub parent_is_player: bool,
+    /// `TBX_EVENT_TRIGGER`, walked in rather than out
+    pub entered: bool,
+    /// `TBX_EVENT_TIME_SYNC`, the client time echoed (0 in the handshake),
+    /// the server clock in ms, the tick rate and the recommended
+    /// interpolation delay in ms; `TBX_EVENT_INTERP_HINT`, the delay too
+    pub client_time: u32,
+    pub server_time: u32,
+    pub tick_hz: u32,
+    pub interp_delay: u32,
+    /// `TBX_EVENT_COORDS`, positions have no z (always 0) when `flat`, and
+    /// are in `units` per block
+    pub flat: bool,
+    pub units: u32,
+    /// `TBX_EVENT_SIMULATION`, no ticks while `paused`, ticks at `scale`
+    /// percent of the tick rate; `TBX_EVENT_MINIMAP`, blocks per cell
+    pub paused: bool,
+    pub scale: u32,
+    /// `TBX_EVENT_TIME_SKIP`, ticks of game time the server didn't simulate
+    pub skipped: u32,
+    /// `TBX_EVENT_ROLLBACK`, frames ahead to send inputs for
+    pub delay: u32,
+    /// `TBX_EVENT_INTERP_HINT`, ms between updates and updates to keep
+    /// buffered
+    pub update_ms: u32,
+    pub buffer: u32,
+    /// `TBX_EVENT_VOICE`, solid blocks between the speaker and this player,
+    /// 0 unless the server counts them
+    pub occlusion: u32,
+    /// `TBX_EVENT_MINIMAP`, cells on a side
+    pub size: u32,
+}
+
+#[repr(C)]
+pub struct TbxBlock {
+    pub id: u16,
+    pub solid: bool,
+    /// UTF-8, not NUL-terminated
+    pub name: *const u8,
+    pub name_len: usize,
+    pub tag_count: usize,
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn tbx_client_new() -> *mut TbxClient {
+    Box::into_raw(Box::new(TbxClient {
+        client: Client::new(),
+        error: CString::default(),
+        reply: Vec::new(),
+        from: Vec::new(),
+        inputs: Vec::new(),
+    }))
+}
+
+/// # Safety
+///
+/// `c` must come from `tbx_client_new` and not be used afterwards. Null is
+/// ignored.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn tbx_client_free(c: *mut TbxClient) {
+    if !c.is_null() {
+        drop(unsafe { Box::from_raw(c) });
+    }
+}
+
+/// A text websocket message. Returns `TBX_OK` or a `TBX_ERR_*` code.
+///
+/// # Safety
+///
+/// `c` must be a live client and `text` point to `len` readable bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn tbx_client_receive_text(
+    c: *mut TbxClient,
+    text: *const u8,
+    len: usize,
+) -> i32 {
+    let (Some(c), Some(data)) = (unsafe { c.as_mut() }, unsafe { bytes(text, len) }) else {
+        return TBX_ERR_INVALID;
+    };
+    let Ok(text) = str::from_utf8(data) else {
+        return TBX_ERR_INVALID;
+    };
+    let result = c.client.receive_text(text);
+    c.result(result)
+}
+
+/// A binary websocket message (one server frame). Returns `TBX_OK` or a
+/// `TBX_ERR_*` code; nothing is applied when the frame is malformed.
+///
+/// # Safety
+///
+/// `c` must be a live client and `data` point to `len` readable bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn tbx_client_receive_binary(
+    c: *mut TbxClient,
+    data: *const u8,
+    len: usize,
+) -> i32 {
+    let (Some(c), Some(data)) = (unsafe { c.as_mut() }, unsafe { bytes(data, len) }) else {
+        return TBX_ERR_INVALID;
+    };
+    let result = c.client.receive_binary(data);
+    c.result(result)
+}
+
+/// Why the last receive failed, NUL-terminated, empty if it didn't.
+///
+/// # Safety
+///
+/// `c` must be a live client.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn tbx_client_last_error(c: *const TbxClient) -> *const c_char {
+    match unsafe { c.as_ref() } {
+        Some(c) => c.error.as_ptr(),
+        None => c"null client".as_ptr(),
+    }
+}
+
+/// Pops the next event into `out`. Returns `false` when there's none.
+///
+/// # Safety
+///
+/// `c` must be a live client and `out` writable.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn tbx_client_next_event(c: *mut TbxClient, out: *mut TbxEvent) -> bool {
+    let (Some(c), Some(out)) = (unsafe { c.as_mut() }, unsafe { out.as_mut() }) else {
+        return false;
+    };
+    let Some(event) = c.client.next_event() else {
+        return false;
+    };
+
+    *out = TbxEvent {
+        kind: 0,
+        id: 0,
+        pos: [0; 3],
+        version: 0,
+        text: ptr::null(),
+        text_len: 0,
+        from: ptr::null(),
+        from_len: 0,
+        seconds: 0,
+        hash: 0,
+        input_count: 0,
+        distance: 0,
+        features: 0,
+        authority: 0,
+        entity: 0,
+        parent: 0,
+        parent_is_player: false,
+        entered: false,
+        client_time: 0,
+        server_time: 0,
+        tick_hz: 0,
+        interp_delay: 0,
+        flat: false,
+        units: 0,
+        paused: false,
+        scale: 0,
+        skipped: 0,
+        delay: 0,
+        update_ms: 0,
+        buffer: 0,
+        occlusion: 0,
+        size: 0,
+    };
+    match event {
+        ClientEvent::Connected { id } => {
+            out.kind = TBX_EVENT_CONNECTED;
+            out.id = id;
+        }
+        ClientEvent::BlockRegistry => out.kind = TBX_EVENT_BLOCK_REGISTRY,
+        ClientEvent::ChunkChanged { pos, version } => {
+            out.kind = TBX_EVENT_CHUNK_CHANGED;
+            out.pos = [pos.0, pos.1, pos.2];
+            out.version = version;
+        }
+        ClientEvent::Reply(text) => {
+            c.reply = text.into_bytes();
+            out.kind = TBX_EVENT_REPLY;
+            out.text = c.reply.as_ptr();
+            out.text_len = c.reply.len();
+        }
+        ClientEvent::Chat { from, text } => {
+            c.reply = text.into_bytes();
+            c.from = from.into_bytes();
+            out.kind = TBX_EVENT_CHAT;
+            out.text = c.reply.as_ptr();
+            out.text_len = c.reply.len();
+            out.from = c.from.as_ptr();
+            out.from_len = c.from.len();
+        }
+        ClientEvent::Drain { seconds, address } => {
+            c.reply = address.unwrap_or_default().into_bytes();
+            out.kind = TBX_EVENT_DRAIN;
+            out.text = c.reply.as_ptr();
+            out.text_len = c.reply.len();
+            out.seconds = seconds.into();
+        }
+        ClientEvent::Resume { token } => {
+            c.reply = token.into_bytes();
+            out.kind = TBX_EVENT_RESUME;
+            out.text = c.reply.as_ptr();
+            out.text_len = c.reply.len();
+        }
+        ClientEvent::RoomChanged { room, id } => {
+            c.reply = room.unwrap_or_default().into_bytes();
+            out.kind = TBX_EVENT_ROOM;
+            out.id = id;
+            out.text = c.reply.as_ptr();
+            out.text_len = c.reply.len();
+        }
+        ClientEvent::Transfer { address, token } => {
+            c.reply = token.into_bytes();
+            c.from = address.into_bytes();
+            out.kind = TBX_EVENT_TRANSFER;
+            out.text = c.reply.as_ptr();
+            out.text_len = c.reply.len();
+            out.from = c.from.as_ptr();
+            out.from_len = c.from.len();
+        }
+        ClientEvent::LockstepTick { tick, hash, inputs } => {
+            c.inputs = inputs;
+            out.kind = TBX_EVENT_LOCKSTEP;
+            out.version = tick;
+            out.hash = hash;
+            out.input_count = c.inputs.len();
+        }
+        ClientEvent::Rollback { frame, delay } => {
+            out.kind = TBX_EVENT_ROLLBACK;
+            out.version = frame;
+            out.delay = delay;
+        }
+        ClientEvent::RollbackInput {
+            player,
+            frame,
+            data,
+        } => {
+            c.reply = data.into_bytes();
+            out.kind = TBX_EVENT_ROLLBACK_INPUT;
+            out.id = player;
+            out.version = frame;
+            out.text = c.reply.as_ptr();
+            out.text_len = c.reply.len();
+        }
+        ClientEvent::RollbackConfirm {
+            frame,
+            hash,
+            inputs,
+        } => {
+            c.inputs = inputs;
+            out.kind = TBX_EVENT_ROLLBACK_CONFIRM;
+            out.version = frame;
+            out.hash = hash;
+            out.input_count = c.inputs.len();
+        }
+        ClientEvent::LockstepState { tick, state } => {
+            c.reply = state;
+            out.kind = TBX_EVENT_LOCKSTEP_STATE;
+            out.version = tick;
+            out.text = c.reply.as_ptr();
+            out.text_len = c.reply.len();
+        }
+        ClientEvent::Relay { from, data } => {
+            c.reply = data;
+            out.kind = TBX_EVENT_RELAY;
+            out.id = from;
+            out.text = c.reply.as_ptr();
+            out.text_len = c.reply.len();
+        }
+        ClientEvent::Voice {
+            from,
+            distance,
+            occlusion,
+            data,
+        } => {
+            c.reply = data;
+            out.kind = TBX_EVENT_VOICE;
+            out.id = from;
+            out.distance = distance.into();
+            out.occlusion = occlusion.into();
+            out.text = c.reply.as_ptr();
+            out.text_len = c.reply.len();
+        }
+        ClientEvent::Teleported {
+            position: (x, y, z),
+        } => {
+            out.kind = TBX_EVENT_TELEPORT;
+            out.pos = [x, y, z];
+        }
+        ClientEvent::Features(features) => {
+            out.kind = TBX_EVENT_FEATURES;
+            for (on, bit) in [
+                (features.chat, TBX_FEATURE_CHAT),
+                (features.build, TBX_FEATURE_BUILD),
+                (features.pvp, TBX_FEATURE_PVP),
+            ] {
+                if on {
+                    out.features |= bit;
+                }
+            }
+        }
+        ClientEvent::Entity(entity) => {
+            let (x, y, z) = entity.position;
+            out.kind = TBX_EVENT_ENTITY;
+            out.id = entity.id;
+            out.pos = [x, y, z];
+            out.authority = entity.authority.unwrap_or(0);
+        }
+        ClientEvent::EntityGone { id } => {
+            out.kind = TBX_EVENT_ENTITY_GONE;
+            out.id = id;
+        }
+        ClientEvent::Mounted(mount) => {
+            let (x, y, z) = mount.offset;
+            out.kind = TBX_EVENT_MOUNT;
+            out.id = mount.player;
+            out.entity = mount.entity;
+            out.pos = [x, y, z];
+        }
+        ClientEvent::Dismounted {
+            player,
+            position: (x, y, z),
+        } => {
+            out.kind = TBX_EVENT_DISMOUNT;
+            out.id = player;
+            out.pos = [x, y, z];
+        }
+        ClientEvent::Attached { entity, attachment } => {
+            let (x, y, z) = attachment.offset;
+            out.kind = TBX_EVENT_ATTACH;
+            out.id = entity;
+            out.pos = [x, y, z];
+            (out.parent, out.parent_is_player) = match attachment.parent {
+                Parent::Entity(id) => (id, false),
+                Parent::Player(id) => (id, true),
+            };
+        }
+        ClientEvent::Detached {
+            entity,
+            position: (x, y, z),
+        } => {
+            out.kind = TBX_EVENT_DETACH;
+            out.id = entity;
+            out.pos = [x, y, z];
+        }
+        ClientEvent::Effect(effect) => {
+            let (x, y, z) = effect.position;
+            c.reply = effect.params;
+            out.kind = TBX_EVENT_EFFECT;
+            out.id = effect.id.into();
+            out.pos = [x, y, z];
+            out.text = c.reply.as_ptr();
+            out.text_len = c.reply.len();
+        }
+        ClientEvent::Trigger { trigger, entered } => {
+            c.reply = trigger.into_bytes();
+            out.kind = TBX_EVENT_TRIGGER;
+            out.text = c.reply.as_ptr();
+            out.text_len = c.reply.len();
+            out.entered = entered;
+        }
+        ClientEvent::InputAck { seq } => {
+            out.kind = TBX_EVENT_INPUT_ACK;
+            out.version = seq;
+        }
+        ClientEvent::TimeSync {
+            client_time,
+            server_time,
+            tick_hz,
+            interp_delay,
+        } => {
+            out.kind = TBX_EVENT_TIME_SYNC;
+            out.client_time = client_time;
+            out.server_time = server_time;
+            out.tick_hz = tick_hz.into();
+            out.interp_delay = interp_delay.into();
+        }
+        ClientEvent::Rebased { origin: (x, y, z) } => {
+            out.kind = TBX_EVENT_REBASE;
+            out.pos = [x, y, z];
+        }
+        ClientEvent::Simulation { paused, scale } => {
+            out.kind = TBX_EVENT_SIMULATION;
+            out.paused = paused;
+            out.scale = scale.into();
+        }
+        ClientEvent::TimeSkip { ticks } => {
+            out.kind = TBX_EVENT_TIME_SKIP;
+            out.skipped = ticks;
+        }
+        ClientEvent::InterpHint {
+            update_ms,
+            delay_ms,
+            buffer,
+        } => {
+            out.kind = TBX_EVENT_INTERP_HINT;
+            out.update_ms = update_ms.into();
+            out.interp_delay = delay_ms.into();
+            out.buffer = buffer.into();
+        }
+        ClientEvent::Minimap(map) => {
+            c.reply = map.heights;
+            c.from = map.colors;
+            out.kind = TBX_EVENT_MINIMAP;
+            out.pos = [map.x, map.bottom, map.z];
+            out.scale = map.scale.into();
+            out.size = map.size.into();
+            out.text = c.reply.as_ptr();
+            out.text_len = c.reply.len();
+            out.from = c.from.as_ptr();
+            out.from_len = c.from.len();
+        }
+        ClientEvent::Achievement { name, title } => {
+            c.reply = title.into_bytes();
+            c.from = name.into_bytes();
+            out.kind = TBX_EVENT_ACHIEVEMENT;
+            out.text = c.reply.as_ptr();
+            out.text_len = c.reply.len();
+            out.from = c.from.as_ptr();
+            out.from_len = c.from.len();
+        }
+        ClientEvent::Coords(coords) => {
+            out.kind = TBX_EVENT_COORDS;
+            out.flat = coords.flat;
+            out.units = coords.units.into();
+        }
+    }
+    true
+}
+
+/// Input `i` of the last `TBX_EVENT_LOCKSTEP` or
+/// `TBX_EVENT_ROLLBACK_CONFIRM`, in player id order: its
+/// data, with the length in `len` and the player in `player`, or null when
+/// out of range.
+///
+/// # Safety
+///
+/// `c` must be a live client and `player` and `len` writable.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn tbx_client_lockstep_input(
+    c: *const TbxClient,
+    i: usize,
+    player: *mut u32,
+    len: *mut usize,
+) -> *const u8 {
+    let input = unsafe { c.as_ref() }.and_then(|c| c.inputs.get(i));
+    match (input, unsafe { player.as_mut() }, unsafe { len.as_mut() }) {
+        (Some(input), Some(player), Some(len)) => {
+            *player = input.player;
+            *len = input.data.len();
+            input.data.as_ptr()
+        }
+        _ => ptr::null(),
+    }
+}
+
+/// Player id, `0` until connected (the server starts ids at 1).
+///
+/// # Safety
+///
+/// `c` must be a live client.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn tbx_client_id(c: *const TbxClient) -> u32 {
+    unsafe { c.as_ref() }
+        .and_then(|c| c.client.id())
+        .unwrap_or(0)
+}
+
+/// Block at world block coordinates, `0` (air) if the chunk isn't held.
+///
+/// # Safety
+///
+/// `c` must be a live client.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn tbx_client_block(c: *const TbxClient, x: i32, y: i32, z: i32) -> u16 {
+    unsafe { c.as_ref() }.map_or(0, |c| c.client.block(x, y, z))
+}
+
+/// The 4096 block ids of a held chunk (y-major: `y * 256 + z * 16 + x`),
+/// or null. Writes its version to `version` unless that's null.
+///
+/// # Safety
+///
+/// `c` must be a live client and `version` writable or null.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn tbx_client_chunk(
+    c: *const TbxClient,
+    cx: i32,
+    cy: i32,
+    cz: i32,
+    version: *mut u32,
+) -> *const u16 {
+    let Some((held, chunk)) = unsafe { c.as_ref() }.and_then(|c| c.client.chunk((cx, cy, cz)))
+    else {
+        return ptr::null();
+    };
+
+    debug_assert_eq!(chunk.blocks().len(), CHUNK_VOLUME);
+    if let Some(version) = unsafe { version.as_mut() } {
+        *version = held;
+    }
+    chunk.blocks().as_ptr()
+}
+
+/// Forgets a chunk, e.g. once it leaves the interest.
+///
+/// # Safety
+///
+/// `c` must be a live client.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn tbx_client_forget(c: *mut TbxClient, cx: i32, cy: i32, cz: i32) {
+    if let Some(c) = unsafe { c.as_mut() } {
+        c.client.forget((cx, cy, cz));
+    }
+}
+
+/// Number of block types in the registry.
+///
+/// # Safety
+///
+/// `c` must be a live client.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn tbx_client_block_count(c: *const TbxClient) -> usize {
+    unsafe { c.as_ref() }.map_or(0, |c| c.client.blocks().len())
+}
+
+/// The `i`th block type (id order) into `out`. Returns `false` when out of
+/// range.
+///
+/// # Safety
+///
+/// `c` must be a live client and `out` writable.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn tbx_client_block_info(
+    c: *const TbxClient,
+    i: usize,
+    out: *mut TbxBlock,
+) -> bool {
+    let (Some(c), Some(out)) = (unsafe { c.as_ref() }, unsafe { out.as_mut() }) else {
+        return false;
+    };
+    let Some(block) = c.client.blocks().get(i) else {
+        return false;
+    };
+
+    *out = TbxBlock {
+        id: block.id,
+        solid: block.solid,
+        name: block.name.as_ptr(),
+        name_len: block.name.len(),
+        tag_count: block.tags.len(),
+    };
+    true
+}
+
+/// Tag `t` of the `i`th block type, UTF-8 with its length in `len`, or null
+/// when out of range.
+///
+/// # Safety
+///
+/// `c` must be a live client and `len` writable.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn tbx_client_block_tag(
+    c: *const TbxClient,
+    i: usize,
+    t: usize,
+    len: *mut usize,
+) -> *const u8 {
+    let tag = unsafe { c.as_ref() }.and_then(|c| c.client.blocks().get(i)?.tags.get(t));
+    match (tag, unsafe { len.as_mut() }) {
+        (Some(tag), Some(len)) => {
+            *len = tag.len();
+            tag.as_ptr()
+        }
+        _ => ptr::null(),
+    }
+}
+
+/// Writes the `SetInterest` command text into `buf` if it fits in `cap`
+/// bytes (not NUL-terminated). Returns the text length either way.
+///
+/// # Safety
+///
+/// `buf` must have `cap` writable bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn tbx_set_interest_command(
+    cx: i32,
+    cy: i32,
+    cz: i32,
+    radius: u16,
+    buf: *mut u8,
+    cap: usize,
+) -> usize {
+    unsafe {
+        write_text(
+            &client::set_interest_command((cx, cy, cz), radius),
+            buf,
+            cap,
+        )
+    }
+}
+
+/// Like `tbx_set_interest_command`, for `Origin` (a chunk corner, in
+/// blocks): positions from there on, both ways. Also the answer to
+/// `TBX_EVENT_REBASE`.
+///
+/// # Safety
+///
+/// `buf` must have `cap` writable bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn tbx_origin_command(
+    x: i32,
+    y: i32,
+    z: i32,
+    buf: *mut u8,
+    cap: usize,
+) -> usize {
+    unsafe { write_text(&client::origin_command((x, y, z)), buf, cap) }
+}
+
+/// Like `tbx_set_interest_command`, for `SetPosition` (world block
+/// coordinates).
+///
+/// # Safety
+///
+/// `buf` must have `cap` writable bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn tbx_set_position_command(
+    x: i32,
+    y: i32,
+    z: i32,
+    buf: *mut u8,
+    cap: usize,
+) -> usize {
+    unsafe { write_text(&client::set_position_command((x, y, z)), buf, cap) }
+}
+
+/// Like `tbx_set_interest_command`, for `SetTransform` (world block
+/// coordinates, degrees). `velocity` is three floats in blocks per second,
+/// or null to leave it out.
+///
+/// # Safety
+///
+/// `buf` must have `cap` writable bytes, `velocity` be null or point to
+/// three floats.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn tbx_set_transform_command(
+    x: i32,
+    y: i32,
+    z: i32,
+    yaw: f32,
+    pitch: f32,
+    velocity: *const f32,
+    buf: *mut u8,
+    cap: usize,
+) -> usize {
+    let velocity = (!velocity.is_null()).then(|| {
+        let v = unsafe { std::slice::from_raw_parts(velocity, 3) };
+        (v[0], v[1], v[2])
+    });
+    let text = client::set_transform_command((x, y, z), (yaw, pitch), velocity);
+    unsafe { write_text(&text, buf, cap) }
+}
+
+/// Like `tbx_set_interest_command`, for `SetBlock` (world block
+/// coordinates).
+///
+/// # Safety
+///
+/// `buf` must have `cap` writable bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn tbx_set_block_command(
+    x: i32,
+    y: i32,
+    z: i32,
+    block: u16,
+    buf: *mut u8,
+    cap: usize,
+) -> usize {
+    unsafe { write_text(&client::set_block_command((x, y, z), block), buf, cap) }
+}
+
+/// Like `tbx_set_interest_command`, for `MoveEntity` (an entity this player
+/// has authority over, to world block coordinates).
+///
+/// # Safety
+///
+/// `buf` must have `cap` writable bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn tbx_move_entity_command(
+    id: u32,
+    x: i32,
+    y: i32,
+    z: i32,
+    buf: *mut u8,
+    cap: usize,
+) -> usize {
+    unsafe { write_text(&client::move_entity_command(id, (x, y, z)), buf, cap) }
+}
+
+/// Like `tbx_set_interest_command`, for `Mount` (an entity within
+/// reach).
+///
+/// # Safety
+///
+/// `buf` must have `cap` writable bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn tbx_mount_command(entity: u32, buf: *mut u8, cap: usize) -> usize {
+    unsafe { write_text(&client::mount_command(entity), buf, cap) }
+}
+
+/// Like `tbx_set_interest_command`, for `Dismount`.
+///
+/// # Safety
+///
+/// `buf` must have `cap` writable bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn tbx_dismount_command(buf: *mut u8, cap: usize) -> usize {
+    unsafe { write_text(&client::dismount_command(), buf, cap) }
+}
+
+/// Like `tbx_set_interest_command`, for `Minimap` (`size` cells of `scale`
+/// blocks around the player).
+///
+/// # Safety
+///
+/// `buf` must have `cap` writable bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn tbx_minimap_command(
+    size: u8,
+    scale: u8,
+    buf: *mut u8,
+    cap: usize,
+) -> usize {
+    unsafe { write_text(&client::minimap_command(size, scale), buf, cap) }
+}
+
+/// Like `tbx_set_interest_command`, for `Say` with `len` bytes of UTF-8
+/// `text`. Returns 0 for null or invalid text.
+///
+/// # Safety
+///
+/// `text` must point to `len` readable bytes and `buf` have `cap` writable
+/// bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn tbx_say_command(
+    text: *const u8,
+    len: usize,
+    buf: *mut u8,
+    cap: usize,
+) -> usize {
+    let Some(Ok(text)) = (unsafe { bytes(text, len) }).map(str::from_utf8) else {
+        return 0;
+    };
+    unsafe { write_text(&client::say_command(text), buf, cap) }
+}
+
+/// Like `tbx_say_command`, for `JoinRoom` (`main` for the main world).
+///
+/// # Safety
+///
+/// `room` must point to `len` readable bytes and `buf` have `cap` writable
+/// bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn tbx_join_room_command(
+    room: *const u8,
+    len: usize,
+    buf: *mut u8,
+    cap: usize,
+) -> usize {
+    let Some(Ok(room)) = (unsafe { bytes(room, len) }).map(str::from_utf8) else {
+        return 0;
+    };
+    unsafe { write_text(&client::join_room_command(room), buf, cap) }
+}
+
+/// Like `tbx_set_interest_command`, for `LeaveRoom`.
+///
+/// # Safety
+///
+/// `buf` must have `cap` writable bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn tbx_leave_room_command(buf: *mut u8, cap: usize) -> usize {
+    unsafe { write_text(&client::leave_room_command(), buf, cap) }
+}
+
+/// Like `tbx_set_interest_command`, for `TimeSync`: the client clock in ms
+/// and the last round trip in ms, or null before the first answer.
+///
+/// # Safety
+///
+/// `buf` must have `cap` writable bytes, `rtt_ms` be null or readable.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn tbx_time_sync_command(
+    client_ms: u32,
+    rtt_ms: *const u32,
+    buf: *mut u8,
+    cap: usize,
+) -> usize {
+    let rtt = (!rtt_ms.is_null()).then(|| unsafe { *rtt_ms });
+    unsafe { write_text(&client::time_sync_command(client_ms, rtt), buf, cap) }
+}
+
+/// Like `tbx_say_command`, for `Input` with `len` bytes of UTF-8 `data`
+/// for relay tick `tick`.
+///
+/// # Safety
+///
+/// `data` must point to `len` readable bytes and `buf` have `cap` writable
+/// bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn tbx_input_command(
+    tick: u32,
+    data: *const u8,
+    len: usize,
+    buf: *mut u8,
+    cap: usize,
+) -> usize {
+    let Some(Ok(data)) = (unsafe { bytes(data, len) }).map(str::from_utf8) else {
+        return 0;
+    };
+    unsafe { write_text(&client::input_command(tick, data), buf, cap) }
+}
+
+/// Like `tbx_input_command`, for `LockstepState`.
+///
+/// # Safety
+///
+/// `data` must point to `len` readable bytes and `buf` have `cap` writable
+/// bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn tbx_lockstep_state_command(
+    tick: u32,
+    data: *const u8,
+    len: usize,
+    buf: *mut u8,
+    cap: usize,
+) -> usize {
+    let Some(Ok(data)) = (unsafe { bytes(data, len) }).map(str::from_utf8) else {
+        return 0;
+    };
+    unsafe { write_text(&client::lockstep_state_command(tick, data), buf, cap) }
+}
+
+/// Writes a binary client frame for relay rooms into `buf` if it fits in
+/// `cap` bytes, sending `len` bytes of `data` to the `to_count` players in
+/// `to` (everyone else when 0). Returns the frame length either way, 0 for
+/// null pointers, more than 255 players or over 4096 bytes of data.
+///
+/// # Safety
+///
+/// `to` must point to `to_count` readable ids, `data` to `len` readable
+/// bytes and `buf` have `cap` writable bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn tbx_relay_frame(
+    seq: u32,
+    to: *const u32,
+    to_count: usize,
+    data: *const u8,
+    len: usize,
+    buf: *mut u8,
+    cap: usize,
+) -> usize {
+    let to = match (to.is_null(), to_count) {
+        (_, 0) => &[][..],
+        (true, _) => return 0,
+        (false, _) => unsafe { slice::from_raw_parts(to, to_count) },
+    };
+    let Some(frame) = (unsafe { bytes(data, len) }).and_then(|d| client::relay_frame(seq, to, d))
+    else {
+        return 0;
+    };
+    if !buf.is_null() && frame.len() <= cap {
+        unsafe { ptr::copy_nonoverlapping(frame.as_ptr(), buf, frame.len()) };
+    }
+    frame.len()
+}
+
+/// Writes a binary client frame with one Opus frame of `len` bytes for the
+/// players in voice range into `buf` if it fits in `cap` bytes. Returns
+/// the frame length either way, 0 for null data or over 1275 bytes.
+///
+/// # Safety
+///
+/// `data` must point to `len` readable bytes and `buf` have `cap` writable
+/// bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn tbx_voice_frame(
+    seq: u32,
+    data: *const u8,
+    len: usize,
+    buf: *mut u8,
+    cap: usize,
+) -> usize {
+    let Some(frame) = (unsafe { bytes(data, len) }).and_then(|d| client::voice_frame(seq, d))
+    else {
+        return 0;
+    };
+    if !buf.is_null() && frame.len() <= cap {
+        unsafe { ptr::copy_nonoverlapping(frame.as_ptr(), buf, frame.len()) };
+    }
+    frame.len()
+}
+
+impl TbxClient {
+    fn result(&mut self, result: Result<(), ClientError>) -> i32 {
+        let (code, error) = match result {
+            Ok(()) => (TBX_OK, String::new()),
+            Err(e) => {
+                let code = match e {
+                    ClientError::Handshake => TBX_ERR_HANDSHAKE,
+                    ClientError::NotConnected => TBX_ERR_NOT_CONNECTED,
+                    ClientError::Protocol(_) => TBX_ERR_PROTOCOL,
+                };
+                (code, e.to_string())
+            }
+        };
+        // Messages never contain NUL
+        self.error = CString::new(error).unwrap_or_default();
+        code
+    }
+}
+
+unsafe fn bytes<'a>(data: *const u8, len: usize) -> Option<&'a [u8]> {
+    match (data.is_null(), len) {
+        (_, 0) => Some(&[]),
+        (true, _) => None,
+        (false, _) => Some(unsafe { slice::from_raw_parts(data, len) }),
+    }
+}
+
+unsafe fn write_text(text: &str, buf: *mut u8, cap: usize) -> usize {
+    if !buf.is_null() && text.len() <= cap {
+        unsafe { ptr::copy_nonoverlapping(text.as_ptr(), buf, text.len()) };
+    }
+    text.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use teleboxel::{
+        blocks::BlockRegistry,
+        entities::{Attachment, Entity, Mount},
+        lockstep::LockstepTick,
+        protocol::ServerFrame,
+    };
+
+    #[test]
+    fn drives_a_client_through_the_c_api() {
+        let mut registry = ServerFrame::new(0);
+        registry.block_registry(&BlockRegistry::default());
+        let registry = registry.finish();
+
+        unsafe {
+            let c = tbx_client_new();
+            let mut event = std::mem::zeroed::<TbxEvent>();
+
+            assert_eq!(
+                tbx_client_receive_binary(c, registry.as_ptr(), registry.len()),
+                TBX_ERR_NOT_CONNECTED
+            );
+            assert_eq!(tbx_client_receive_text(c, b"12".as_ptr(), 2), TBX_OK);
+            assert!(tbx_client_next_event(c, &mut event));
+            assert_eq!((event.kind, event.id), (TBX_EVENT_CONNECTED, 12));
+
+            assert_eq!(
+                tbx_client_receive_binary(c, registry.as_ptr(), registry.len()),
+                TBX_OK
+            );
+            assert!(tbx_client_next_event(c, &mut event));
+            assert_eq!(event.kind, TBX_EVENT_BLOCK_REGISTRY);
+            assert!(!tbx_client_next_event(c, &mut event));
+            assert_eq!(tbx_client_block_count(c), 4);
+
+            let bad = [0x10, 0, 0];
+            assert_eq!(
+                tbx_client_receive_binary(c, bad.as_ptr(), bad.len()),
+                TBX_ERR_PROTOCOL
+            );
+            let error = std::ffi::CStr::from_ptr(tbx_client_last_error(c));
+            assert_eq!(error.to_str().unwrap(), "frame is truncated");
+
+            let mut buf = [0u8; 32];
+            let len = tbx_set_block_command(1, -2, 3, 7, buf.as_mut_ptr(), buf.len());
+            assert_eq!(&buf[..len], b"SetBlock 1 -2 3 7");
+            assert_eq!(tbx_set_block_command(1, -2, 3, 7, ptr::null_mut(), 0), len);
+            let velocity = [0.5, 0.0, -1.0];
+            let mut long = [0u8; 48];
+            let len = tbx_set_transform_command(
+                1,
+                2,
+                3,
+                90.0,
+                -10.5,
+                velocity.as_ptr(),
+                long.as_mut_ptr(),
+                long.len(),
+            );
+            assert_eq!(&long[..len], b"SetTransform 1 2 3 90 -10.5 0.5 0 -1");
+            let len = tbx_set_transform_command(
+                1,
+                2,
+                3,
+                90.0,
+                0.0,
+                ptr::null(),
+                buf.as_mut_ptr(),
+                buf.len(),
+            );
+            assert_eq!(&buf[..len], b"SetTransform 1 2 3 90 0");
+            let len = tbx_time_sync_command(500, &80, buf.as_mut_ptr(), buf.len());
+            assert_eq!(&buf[..len], b"TimeSync 500 80");
+            let len = tbx_input_command(4, b"go".as_ptr(), 2, buf.as_mut_ptr(), buf.len());
+            assert_eq!(&buf[..len], b"Input 4 go");
+
+            let mut frame = ServerFrame::new(1);
+            frame.lockstep(&LockstepTick {
+                tick: 4,
+                hash: 9,
+                inputs: vec![LockstepInput {
+                    player: 12,
+                    data: "go".into(),
+                }],
+            });
+            let frame = frame.finish();
+            assert_eq!(
+                tbx_client_receive_binary(c, frame.as_ptr(), frame.len()),
+                TBX_OK
+            );
+            assert!(tbx_client_next_event(c, &mut event));
+            assert_eq!(
+                (event.kind, event.version, event.hash, event.input_count),
+                (TBX_EVENT_LOCKSTEP, 4, 9, 1)
+            );
+            let (mut player, mut len) = (0, 0);
+            let data = tbx_client_lockstep_input(c, 0, &mut player, &mut len);
+            assert_eq!((player, slice::from_raw_parts(data, len)), (12, &b"go"[..]));
+            assert!(tbx_client_lockstep_input(c, 1, &mut player, &mut len).is_null());
+
+            let len = tbx_relay_frame(0, [2].as_ptr(), 1, b"hi".as_ptr(), 2, buf.as_mut_ptr(), 32);
+            assert_eq!((len, buf[0]), (16, 0x11));
+            let mut frame = ServerFrame::new(2);
+            frame.relay(2, b"hi");
+            let frame = frame.finish();
+            assert_eq!(
+                tbx_client_receive_binary(c, frame.as_ptr(), frame.len()),
+                TBX_OK
+            );
+            assert!(tbx_client_next_event(c, &mut event));
+            assert_eq!((event.kind, event.id), (TBX_EVENT_RELAY, 2));
+            assert_eq!(slice::from_raw_parts(event.text, event.text_len), b"hi");
+
+            let len = tbx_voice_frame(1, b"op".as_ptr(), 2, buf.as_mut_ptr(), 32);
+            assert_eq!((len, buf[6]), (11, 0x2a));
+            let mut frame = ServerFrame::new(3);
+            frame.voice(2, 7, 1, b"op");
+            let frame = frame.finish();
+            assert_eq!(
+                tbx_client_receive_binary(c, frame.as_ptr(), frame.len()),
+                TBX_OK
+            );
+            assert!(tbx_client_next_event(c, &mut event));
+            assert_eq!(
+                (event.kind, event.id, event.distance, event.occlusion),
+                (TBX_EVENT_VOICE, 2, 7, 1)
+            );
+
+            let mut frame = ServerFrame::new(4);
+            frame.teleport((1, 2, -3));
+            let frame = frame.finish();
+            assert_eq!(
+                tbx_client_receive_binary(c, frame.as_ptr(), frame.len()),
+                TBX_OK
+            );
+            assert!(tbx_client_next_event(c, &mut event));
+            assert_eq!((event.kind, event.pos), (TBX_EVENT_TELEPORT, [1, 2, -3]));
+
+            let mut frame = ServerFrame::new(5);
+            frame.features(true, false, true);
+            let frame = frame.finish();
+            assert_eq!(
+                tbx_client_receive_binary(c, frame.as_ptr(), frame.len()),
+                TBX_OK
+            );
+            assert!(tbx_client_next_event(c, &mut event));
+            assert_eq!(
+                (event.kind, event.features),
+                (TBX_EVENT_FEATURES, TBX_FEATURE_CHAT | TBX_FEATURE_PVP)
+            );
+
+            let mut frame = ServerFrame::new(6);
+            frame.entity(&Entity {
+                id: 3,
+                position: (4, 5, -6),
+                authority: Some(1),
+                attached: None,
+                fixed: false,
+                always: false,
+                layer: 0,
+            });
+            frame.entity_gone(3);
+            let frame = frame.finish();
+            assert_eq!(
+                tbx_client_receive_binary(c, frame.as_ptr(), frame.len()),
+                TBX_OK
+            );
+            assert!(tbx_client_next_event(c, &mut event));
+            assert_eq!(
+                (event.kind, event.id, event.pos, event.authority),
+                (TBX_EVENT_ENTITY, 3, [4, 5, -6], 1)
+            );
+            assert!(tbx_client_next_event(c, &mut event));
+            assert_eq!((event.kind, event.id), (TBX_EVENT_ENTITY_GONE, 3));
+            let len = tbx_move_entity_command(3, 4, 5, -6, buf.as_mut_ptr(), buf.len());
+            assert_eq!(&buf[..len], b"MoveEntity 3 4 5 -6");
+
+            let mut frame = ServerFrame::new(7);
+            frame.mount(&Mount {
+                player: 1,
+                entity: 3,
+                offset: (0, 1, 0),
+            });
+            frame.dismount(1, (4, 6, -6));
+            let frame = frame.finish();
+            assert_eq!(
+                tbx_client_receive_binary(c, frame.as_ptr(), frame.len()),
+                TBX_OK
+            );
+            assert!(tbx_client_next_event(c, &mut event));
+            assert_eq!(
+                (event.kind, event.id, event.entity, event.pos),
+                (TBX_EVENT_MOUNT, 1, 3, [0, 1, 0])
+            );
+            assert!(tbx_client_next_event(c, &mut event));
+            assert_eq!(
+                (event.kind, event.id, event.pos),
+                (TBX_EVENT_DISMOUNT, 1, [4, 6, -6])
+            );
+            let len = tbx_mount_command(3, buf.as_mut_ptr(), buf.len());
+            assert_eq!(&buf[..len], b"Mount 3");
+
+            let mut frame = ServerFrame::new(8);
+            let held = Attachment {
+                parent: Parent::Player(1),
+                offset: (1, 0, 0),
+            };
+            frame.attach(3, &held);
+            let frame = frame.finish();
+            assert_eq!(
+                tbx_client_receive_binary(c, frame.as_ptr(), frame.len()),
+                TBX_OK
+            );
+            assert!(tbx_client_next_event(c, &mut event));
+            assert_eq!(
+                (event.kind, event.id, event.pos),
+                (TBX_EVENT_ATTACH, 3, [1, 0, 0])
+            );
+            assert_eq!((event.parent, event.parent_is_player), (1, true));
+
+            tbx_client_free(c);
+        }
+    }
+}
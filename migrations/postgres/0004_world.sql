@@ -0,0 +1,16 @@
+-- The world's saves (see `save.rs`) when there's no world directory: a row
+-- per chunk, and the claims in a single row.
+CREATE TABLE IF NOT EXISTS chunks (
+    cx INTEGER NOT NULL,
+    cy INTEGER NOT NULL,
+    cz INTEGER NOT NULL,
+    data BYTEA NOT NULL,
+    updated_at BIGINT NOT NULL DEFAULT 0,
+    PRIMARY KEY (cx, cy, cz)
+);
+
+CREATE TABLE IF NOT EXISTS claims (
+    id INTEGER PRIMARY KEY NOT NULL CHECK (id = 1),
+    data BYTEA NOT NULL,
+    updated_at BIGINT NOT NULL DEFAULT 0
+);
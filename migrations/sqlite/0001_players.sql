@@ -0,0 +1,28 @@
+CREATE TABLE IF NOT EXISTS players (
+    name TEXT PRIMARY KEY NOT NULL,
+    pos_x INTEGER NOT NULL DEFAULT 0,
+    pos_y INTEGER NOT NULL DEFAULT 0,
+    pos_z INTEGER NOT NULL DEFAULT 0,
+    updated_at INTEGER NOT NULL DEFAULT 0
+);
+
+CREATE TABLE IF NOT EXISTS player_properties (
+    name TEXT NOT NULL REFERENCES players(name) ON DELETE CASCADE,
+    key TEXT NOT NULL,
+    value TEXT NOT NULL,
+    PRIMARY KEY (name, key)
+);
+
+CREATE TABLE IF NOT EXISTS player_inventory (
+    name TEXT NOT NULL REFERENCES players(name) ON DELETE CASCADE,
+    slot INTEGER NOT NULL,
+    item INTEGER NOT NULL,
+    count INTEGER NOT NULL,
+    PRIMARY KEY (name, slot)
+);
+
+CREATE TABLE IF NOT EXISTS bans (
+    name TEXT PRIMARY KEY NOT NULL,
+    reason TEXT NOT NULL DEFAULT '',
+    created_at INTEGER NOT NULL DEFAULT 0
+);
@@ -0,0 +1,12 @@
+-- Player statistics per world (room name, `main` for the main world).
+-- Players in rooms may never have a `players` row, so no foreign key.
+CREATE TABLE IF NOT EXISTS player_stats (
+    world TEXT NOT NULL,
+    name TEXT NOT NULL,
+    stat TEXT NOT NULL,
+    value INTEGER NOT NULL DEFAULT 0,
+    PRIMARY KEY (world, name, stat)
+);
+
+CREATE INDEX IF NOT EXISTS player_stats_by_value ON player_stats (stat, world, value DESC);
+CREATE INDEX IF NOT EXISTS player_stats_by_name ON player_stats (name);
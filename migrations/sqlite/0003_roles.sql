@@ -0,0 +1,7 @@
+-- Moderation roles (see `roles.rs`), only for players above `player`.
+-- Like bans, roles can be assigned to names that never connected.
+CREATE TABLE IF NOT EXISTS roles (
+    name TEXT PRIMARY KEY NOT NULL,
+    role TEXT NOT NULL,
+    updated_at INTEGER NOT NULL DEFAULT 0
+);
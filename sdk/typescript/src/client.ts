@@ -0,0 +1,634 @@
+// Browser/Node client for a Teleboxel server: connects, does the handshake,
+// decodes server frames and keeps the chunks in the interest up to date.
+//
+// The server still takes text commands (see src/command.rs) until the
+// client side of the binary protocol exists; `setInterest`, `setPosition`,
+// `setTransform` and `setBlock` send those. Only `relay` and `voice` send client frames
+// so far.
+
+import {
+    type Block,
+    type ChunkDelta,
+    type ChunkSnapshot,
+    type LockstepInput,
+    CLIENT_FRAME,
+    SERVER_FRAME,
+    type ClientMsg,
+    type ServerMsg,
+    BLOCK_REGISTRY,
+    CHAT,
+    DRAIN,
+    RESUME,
+    ROOM,
+    TRANSFER,
+    LOCKSTEP,
+    LOCKSTEP_STATE,
+    RELAY,
+    RELAY_SEND,
+    VOICE,
+    VOICE_SEND,
+    TELEPORT,
+    FEATURES,
+    ENTITY,
+    ENTITY_GONE,
+    MOUNT,
+    DISMOUNT,
+    ATTACH,
+    DETACH,
+    EFFECT,
+    TRIGGER,
+    INPUT_ACK,
+    TIME_SYNC,
+    REBASE,
+    COORDS,
+    SIMULATION,
+    TIME_SKIP,
+    ACHIEVEMENT,
+    INTERP_HINT,
+    MINIMAP,
+    ROLLBACK,
+    ROLLBACK_INPUT,
+    ROLLBACK_CONFIRM,
+    CHUNK_DELTA,
+    CHUNK_SNAPSHOT,
+    readServerMsg,
+    writeClientMsg,
+} from "./protocol.js";
+import { type ChunkPos, ProtocolError, Reader, Writer } from "./wire.js";
+
+/** Decodes a server frame into its tick and submessages. */
+export function decodeServerFrame(data: Uint8Array): { tick: number; msgs: ServerMsg[] } {
+    const r = new Reader(data);
+    const kind = r.u8();
+    if (kind !== SERVER_FRAME) {
+        throw new ProtocolError(`unknown frame type ${kind}`);
+    }
+
+    const tick = r.u32();
+    const msgs = r.list(r.u8(), () => readServerMsg(r));
+    if (r.remaining !== 0) {
+        throw new ProtocolError("trailing bytes");
+    }
+    return { tick, msgs };
+}
+
+/** Encodes a client frame, `seq` being the client's own counter. */
+export function encodeClientFrame(seq: number, msgs: ClientMsg[]): Uint8Array {
+    const w = new Writer();
+    w.u8(CLIENT_FRAME);
+    w.u32(seq);
+    w.u8(msgs.length);
+    for (const msg of msgs) {
+        writeClientMsg(w, msg);
+    }
+    return w.finish();
+}
+
+export interface ConnectOptions {
+    /** Loads and saves this player's record when the server has storage. */
+    name?: string;
+    /** Joins a forked room instead of the main world. */
+    room?: string;
+    /** Token from `onResume`, carries on where the last connection left off. */
+    resume?: string;
+    /**
+     * JWT from the identity provider, or a platform ticket with `platform`,
+     * for servers with logins: it names the player instead of `name`.
+     */
+    token?: string;
+    /** Where `token` comes from, e.g. `steam`. */
+    platform?: string;
+}
+
+export interface ClientChunk {
+    version: number;
+    /** 4096 block ids, indexed by `blockIndex`. */
+    blocks: Uint16Array;
+}
+
+export class TeleboxelClient {
+    /** Block types by id, from `BLOCK_REGISTRY`. */
+    readonly blocks = new Map<number, Block>();
+    /** Chunks received in the interest, keyed by `chunkKey`. */
+    readonly chunks = new Map<string, ClientChunk>();
+    // Counts client frames sent
+    private seq = 0;
+    // Round trip of the last `timeSync`, sent with the next one
+    private rtt?: number;
+    /**
+     * Server clock minus `performance.now()` in ms, from `timeSync`, and
+     * how far behind the newest snapshot to render (`TIME_SYNC`, also in
+     * the handshake).
+     */
+    clockOffset = 0;
+    interpDelay = 0;
+
+    /** Every decoded frame, after the client state is updated. */
+    onFrame: (tick: number, msgs: ServerMsg[]) => void = () => {};
+    /** Text replies to commands, e.g. `SetBlock Ok` or `SetBlock Error: ...`. */
+    onReply: (reply: string) => void = () => {};
+    /** Chat in this world or room, from a player or a bridge (`telegram:alice`). */
+    onChat: (from: string, text: string) => void = () => {};
+    /**
+     * The server closes the connection within `seconds`; reconnect to
+     * `address` (a websocket URL) if it isn't empty.
+     */
+    onDrain: (seconds: number, address: string) => void = () => {};
+    /**
+     * Before `onDrain`: reconnect with `{ resume: token }` to the drain
+     * address, or this one if it's empty, to keep the position and interest.
+     */
+    onResume: (token: string) => void = () => {};
+    /**
+     * Moved to `room` (empty for the main world) by `joinRoom`/`leaveRoom`
+     * or a portal, as a new player `id`. `chunks` was cleared, the new room's follow.
+     */
+    onRoom: (room: string, id: number) => void = () => {};
+    /**
+     * Walked into a portal to another server: connect to `address` with
+     * `{ resume: token }`. The server closes this connection.
+     */
+    onTransfer: (address: string, token: string) => void = () => {};
+    /**
+     * Lockstep rooms: relay tick `tick` closed with these inputs, in player
+     * id order, and their `hash` (see `LOCKSTEP` in protocol.ts).
+     */
+    onLockstep: (tick: number, hash: number, inputs: LockstepInput[]) => void = () => {};
+    /**
+     * Lockstep rooms: the state to start from on joining, as of `tick` (0
+     * and empty if nobody uploaded one). Ticks closed since follow.
+     */
+    onLockstepState: (tick: number, state: Uint8Array) => void = () => {};
+    /**
+     * Rollback rooms, on joining: the room's `frame` and the input `delay`,
+     * how many frames ahead to send inputs for.
+     */
+    onRollback: (frame: number, delay: number) => void = () => {};
+    /** Rollback rooms: `player`'s input for `frame`, to predict with. */
+    onRollbackInput: (player: number, frame: number, data: string) => void = () => {};
+    /**
+     * Rollback rooms: `frame` is final with these inputs, in player id
+     * order. Roll back to it if they aren't the ones predicted.
+     */
+    onRollbackConfirm: (frame: number, hash: number, inputs: LockstepInput[]) => void = () => {};
+    /** Relay rooms: `data` from player `from`, as it was sent. */
+    onRelay: (from: number, data: Uint8Array) => void = () => {};
+    /**
+     * An Opus frame from player `from`, `distance` blocks away with
+     * `occlusion` solid blocks in between (0 unless the server counts
+     * them), for fading and muffling voices. Only nearby players' voices
+     * arrive.
+     */
+    onVoice: (from: number, distance: number, occlusion: number, data: Uint8Array) => void =
+        () => {};
+    /**
+     * The server moved the player to this block position (`/tp`): move
+     * there and send positions from it, and a new interest if needed.
+     */
+    onTeleport: (x: number, y: number, z: number) => void = () => {};
+    /**
+     * What's on in the player's world, on joining it and whenever an admin
+     * toggles it. Chat and block edits that are off get an error reply;
+     * PvP is for the game to enforce.
+     */
+    onFeatures: (chat: boolean, build: boolean, pvp: boolean) => void = () => {};
+    /**
+     * The world's simulation changed, or isn't running normally on joining:
+     * no ticks while `paused`, and ticks at `scale` percent of the tick
+     * rate (slow motion under 100).
+     */
+    onSimulation: (paused: boolean, scale: number) => void = () => {};
+    /**
+     * The server stalled and `ticks` of game time weren't simulated: the
+     * tick goes on from where it was, so drop that much wall time from
+     * clocks synced to it.
+     */
+    onTimeSkip: (ticks: number) => void = () => {};
+    /**
+     * How to interpolate in this world, on joining and when it changes:
+     * updates come every `updateMs`, render `delayMs` behind the newest and
+     * keep `buffer` of them.
+     */
+    onInterpHint: (updateMs: number, delayMs: number, buffer: number) => void = () => {};
+    /** The player earned the world's achievement `name`. */
+    onAchievement: (name: string, title: string) => void = () => {};
+    /**
+     * The minimap `minimap` asked for: `size` by `size` cells of `scale`
+     * blocks from block column `x`, `z` on, in rows along x. Heights are
+     * above `bottom` (255 when unknown), colors RGB, 3 per cell.
+     */
+    onMinimap: (
+        x: number,
+        z: number,
+        bottom: number,
+        scale: number,
+        size: number,
+        heights: number[],
+        colors: number[],
+    ) => void = () => {};
+    /**
+     * A non-player entity spawned, moved or changed hands, and all of them
+     * on joining. `authority` is the player id moving it (0 for the
+     * server); when it's this player's, send its moves with `moveEntity`.
+     */
+    onEntity: (id: number, x: number, y: number, z: number, authority: number) => void = () => {};
+    /** The server removed an entity. */
+    onEntityGone: (id: number) => void = () => {};
+    /**
+     * A player rides an entity, `x`, `y`, `z` blocks from it: draw them
+     * there as it moves. While this player rides, its own moves are
+     * ignored.
+     */
+    onMount: (player: number, entity: number, x: number, y: number, z: number) => void = () => {};
+    /** A player got off, or left, at this block position. */
+    onDismount: (player: number, x: number, y: number, z: number) => void = () => {};
+    /**
+     * An entity hangs `x`, `y`, `z` blocks from `parent`, an entity or (when
+     * `player` is set) a player: place it from the parent as that moves.
+     * Its own `onEntity` only comes for player parents.
+     */
+    onAttach: (
+        entity: number,
+        parent: number,
+        player: boolean,
+        x: number,
+        y: number,
+        z: number,
+    ) => void = () => {};
+    /** An entity was let go at this block position. */
+    onDetach: (entity: number, x: number, y: number, z: number) => void = () => {};
+    /** A one-off effect at this block position, game-defined `params`. */
+    onEffect: (
+        effect: number,
+        x: number,
+        y: number,
+        z: number,
+        params: Uint8Array,
+    ) => void = () => {};
+    /** The player walked into (`entered`) or out of a trigger volume. */
+    onTrigger: (trigger: string, entered: boolean) => void = () => {};
+    /**
+     * The server applied this player's moves and edits up to `seq` (the
+     * optional last argument of `setPosition`, `setTransform` and
+     * `setBlock`).
+     */
+    onInputAck: (seq: number) => void = () => {};
+    /** A `timeSync` answered, `clockOffset` and `interpDelay` are updated. */
+    onTimeSync: (offset: number, rtt: number, interpDelay: number) => void = () => {};
+    onClose: (code: number, reason: string) => void = () => {};
+
+    private constructor(
+        private ws: WebSocket,
+        /** Changes with the room. */
+        public id: number,
+    ) {
+        ws.onmessage = (e) => this.receive(e.data);
+        ws.onclose = (e) => this.onClose(e.code, e.reason);
+    }
+
+    /** Resolves once the server assigned a player id. */
+    static connect(url: string, options: ConnectOptions = {}): Promise<TeleboxelClient> {
+        const target = new URL(url);
+        if (options.name) target.searchParams.set("name", options.name);
+        if (options.room) target.searchParams.set("room", options.room);
+        if (options.resume) target.searchParams.set("resume", options.resume);
+        if (options.token) target.searchParams.set("token", options.token);
+        if (options.platform) target.searchParams.set("platform", options.platform);
+
+        const ws = new WebSocket(target);
+        ws.binaryType = "arraybuffer";
+        return new Promise((resolve, reject) => {
+            // The handshake is the player id as text, for now
+            ws.onmessage = (e) => {
+                const id = typeof e.data === "string" ? Number(e.data) : NaN;
+                if (!Number.isInteger(id)) {
+                    ws.close();
+                    reject(new ProtocolError("expected the player id"));
+                    return;
+                }
+                resolve(new TeleboxelClient(ws, id));
+            };
+            ws.onclose = (e) => reject(new Error(`closed: ${e.code} ${e.reason}`));
+            ws.onerror = () => reject(new Error(`can't connect to ${target}`));
+        });
+    }
+
+    /** Interest cube around a chunk, radius in chunks (capped by the server). */
+    setInterest([x, y, z]: ChunkPos, radius: number): void {
+        this.ws.send(`SetInterest ${x} ${y} ${z} ${radius}`);
+    }
+
+    /**
+     * Measures the round trip and clock offset to the server (see
+     * src/clock.rs), call every few seconds.
+     */
+    timeSync(): void {
+        // 0 is the handshake's
+        const now = clientMs() || 1;
+        const rtt = this.rtt === undefined ? "" : ` ${this.rtt}`;
+        this.ws.send(`TimeSync ${now}${rtt}`);
+    }
+
+    /** World block coordinates. */
+    setPosition(x: number, y: number, z: number, seq?: number): void {
+        this.ws.send(`SetPosition ${x} ${y} ${z}${seqSuffix(seq)}`);
+    }
+
+    /**
+     * World block coordinates, yaw and pitch in degrees, velocity in blocks
+     * per second. Use instead of `setPosition` when the player turns.
+     */
+    setTransform(
+        x: number,
+        y: number,
+        z: number,
+        yaw: number,
+        pitch: number,
+        velocity?: [number, number, number],
+        seq?: number,
+    ): void {
+        const v = velocity ? ` ${velocity.join(" ")}` : "";
+        this.ws.send(`SetTransform ${x} ${y} ${z} ${yaw} ${pitch}${v}${seqSuffix(seq)}`);
+    }
+
+    /** World block coordinates. */
+    setBlock(x: number, y: number, z: number, block: number, seq?: number): void {
+        this.ws.send(`SetBlock ${x} ${y} ${z} ${block}${seqSuffix(seq)}`);
+    }
+
+    /** An entity this player has authority over, to world block coordinates. */
+    moveEntity(id: number, x: number, y: number, z: number): void {
+        this.ws.send(`MoveEntity ${id} ${x} ${y} ${z}`);
+    }
+
+    /** Rides an entity a few blocks away at most. */
+    mount(id: number): void {
+        this.ws.send(`Mount ${id}`);
+    }
+
+    dismount(): void {
+        this.ws.send("Dismount");
+    }
+
+    /** A minimap of `size` cells of `scale` blocks around the player. */
+    minimap(size = 64, scale = 1): void {
+        this.ws.send(`Minimap ${size} ${scale}`);
+    }
+
+    /** One chat line, for everyone in this world or room. */
+    say(text: string): void {
+        this.ws.send(`Say ${text}`);
+    }
+
+    /**
+     * A custom gameplay event for the server's analytics, with string
+     * fields (no spaces in names or values).
+     */
+    track(event: string, fields: Record<string, string> = {}): void {
+        const pairs = Object.entries(fields).map(([key, value]) => ` ${key}=${value}`);
+        this.ws.send(`Track ${event}${pairs.join("")}`);
+    }
+
+    /** Moves to a room over this connection, `main` for the main world. */
+    joinRoom(room: string): void {
+        this.ws.send(`JoinRoom ${room}`);
+    }
+
+    leaveRoom(): void {
+        this.ws.send("LeaveRoom");
+    }
+
+    /**
+     * Lockstep rooms, this player's input for relay tick `tick`. Rollback
+     * rooms, for frame `tick`.
+     */
+    input(tick: number, data: string): void {
+        this.ws.send(`Input ${tick} ${data}`);
+    }
+
+    /** Lockstep rooms, the state after relay tick `tick` for late joiners. */
+    lockstepState(tick: number, data: string): void {
+        this.ws.send(`LockstepState ${tick} ${data}`);
+    }
+
+    /**
+     * Relay rooms, sends `data` (at most 4096 bytes) to the players in `to`,
+     * or everyone else when it's empty.
+     */
+    relay(data: Uint8Array, to: number[] = []): void {
+        const msg: ClientMsg = { kind: RELAY_SEND, to, data: Array.from(data) };
+        this.ws.send(encodeClientFrame(this.seq++, [msg]));
+    }
+
+    /**
+     * One Opus frame (at most 1275 bytes) for the players in voice range.
+     * Frames over the server's bitrate cap are dropped.
+     */
+    voice(data: Uint8Array): void {
+        const msg: ClientMsg = { kind: VOICE_SEND, data: Array.from(data) };
+        this.ws.send(encodeClientFrame(this.seq++, [msg]));
+    }
+
+    /** Stops getting `name`'s chat, voice and relay messages, kept in the record. */
+    block(name: string): void {
+        this.ws.send(`Block ${name}`);
+    }
+
+    unblock(name: string): void {
+        this.ws.send(`Unblock ${name}`);
+    }
+
+    /** Moderators, closes `name`'s connections. */
+    kick(name: string, reason = ""): void {
+        this.ws.send(`Kick ${name} ${reason}`.trimEnd());
+    }
+
+    /** Moderators, keeps `name` out of chat and voice for `minutes`, `0` unmutes. */
+    mute(name: string, minutes: number): void {
+        this.ws.send(minutes > 0 ? `Mute ${name} ${minutes}` : `Unmute ${name}`);
+    }
+
+    /**
+     * Guests, binds this connection to the identity in `token` (see
+     * `ConnectOptions.token`), keeping the position. Replies with the name.
+     */
+    login(token: string, platform = ""): void {
+        this.ws.send(`Login ${token} ${platform}`.trimEnd());
+    }
+
+    /** Stops or resumes hearing player `id`'s voice. */
+    muteVoice(id: number, mute = true): void {
+        this.ws.send(`${mute ? "VoiceMute" : "VoiceUnmute"} ${id}`);
+    }
+
+    close(): void {
+        this.ws.close();
+    }
+
+    private receive(data: string | ArrayBuffer): void {
+        if (typeof data === "string") {
+            this.onReply(data);
+            return;
+        }
+
+        const { tick, msgs } = decodeServerFrame(new Uint8Array(data));
+        for (const msg of msgs) {
+            this.apply(msg);
+        }
+        this.onFrame(tick, msgs);
+    }
+
+    private apply(msg: ServerMsg): void {
+        switch (msg.kind) {
+            case CHUNK_SNAPSHOT:
+                this.applySnapshot(msg);
+                break;
+            case CHUNK_DELTA:
+                this.applyDelta(msg);
+                break;
+            case BLOCK_REGISTRY:
+                this.blocks.clear();
+                for (const block of msg.blocks) {
+                    this.blocks.set(block.id, block);
+                }
+                break;
+            case CHAT:
+                this.onChat(msg.from, msg.text);
+                break;
+            case DRAIN:
+                this.onDrain(msg.seconds, msg.address);
+                break;
+            case RESUME:
+                this.onResume(msg.token);
+                break;
+            case ROOM:
+                this.id = msg.id;
+                this.chunks.clear();
+                this.onRoom(msg.room, msg.id);
+                break;
+            case TRANSFER:
+                this.onTransfer(msg.address, msg.token);
+                break;
+            case LOCKSTEP:
+                this.onLockstep(msg.tick, msg.hash, msg.inputs);
+                break;
+            case LOCKSTEP_STATE:
+                this.onLockstepState(msg.tick, Uint8Array.from(msg.state));
+                break;
+            case ROLLBACK:
+                this.onRollback(msg.frame, msg.delay);
+                break;
+            case ROLLBACK_INPUT:
+                this.onRollbackInput(msg.player, msg.frame, msg.data);
+                break;
+            case ROLLBACK_CONFIRM:
+                this.onRollbackConfirm(msg.frame, msg.hash, msg.inputs);
+                break;
+            case RELAY:
+                this.onRelay(msg.from, Uint8Array.from(msg.data));
+                break;
+            case VOICE:
+                this.onVoice(msg.from, msg.distance, msg.occlusion, Uint8Array.from(msg.data));
+                break;
+            case TELEPORT:
+                this.onTeleport(msg.x, msg.y, msg.z);
+                break;
+            case FEATURES:
+                this.onFeatures(msg.chat, msg.build, msg.pvp);
+                break;
+            case ENTITY:
+                this.onEntity(msg.id, msg.x, msg.y, msg.z, msg.authority);
+                break;
+            case ENTITY_GONE:
+                this.onEntityGone(msg.id);
+                break;
+            case MOUNT:
+                this.onMount(msg.player, msg.entity, msg.x, msg.y, msg.z);
+                break;
+            case DISMOUNT:
+                this.onDismount(msg.player, msg.x, msg.y, msg.z);
+                break;
+            case ATTACH:
+                this.onAttach(msg.entity, msg.parent, msg.player, msg.x, msg.y, msg.z);
+                break;
+            case DETACH:
+                this.onDetach(msg.entity, msg.x, msg.y, msg.z);
+                break;
+            case EFFECT:
+                this.onEffect(msg.effect, msg.x, msg.y, msg.z, Uint8Array.from(msg.params));
+                break;
+            case TRIGGER:
+                this.onTrigger(msg.trigger, msg.entered);
+                break;
+            case INPUT_ACK:
+                this.onInputAck(msg.seq);
+                break;
+            case TIME_SYNC: {
+                this.interpDelay = msg.interpDelay;
+                // The handshake's isn't an answer
+                if (msg.clientTime === 0) {
+                    break;
+                }
+                const now = clientMs();
+                const rtt = (now - msg.clientTime) >>> 0;
+                this.rtt = rtt;
+                this.clockOffset = (msg.serverTime + rtt / 2 - now) | 0;
+                this.onTimeSync(this.clockOffset, rtt, msg.interpDelay);
+                break;
+            }
+            // Only after `Origin`, which this client doesn't send: numbers
+            // are doubles, exact well past the i32 range
+            case REBASE:
+                break;
+            // Only after connecting with `?coords=1`, which this client
+            // doesn't: positions stay three axes in blocks
+            case COORDS:
+                break;
+            case SIMULATION:
+                this.onSimulation(msg.paused, msg.scale);
+                break;
+            case TIME_SKIP:
+                this.onTimeSkip(msg.ticks);
+                break;
+            case INTERP_HINT:
+                this.onInterpHint(msg.updateMs, msg.delayMs, msg.buffer);
+                break;
+            case ACHIEVEMENT:
+                this.onAchievement(msg.name, msg.title);
+                break;
+            case MINIMAP:
+                this.onMinimap(msg.x, msg.z, msg.y, msg.scale, msg.size, msg.heights, msg.colors);
+                break;
+        }
+    }
+
+    private applySnapshot({ pos, version, chunk }: ChunkSnapshot): void {
+        this.chunks.set(chunkKey(pos), { version, blocks: chunk });
+    }
+
+    private applyDelta({ pos, baseVersion, version, edits }: ChunkDelta): void {
+        const chunk = this.chunks.get(chunkKey(pos));
+        // Not the version the delta builds on, the server resends a snapshot
+        if (!chunk || chunk.version !== baseVersion) {
+            return;
+        }
+        for (const [index, block] of edits) {
+            chunk.blocks[index] = block;
+        }
+        chunk.version = version;
+    }
+}
+
+export function chunkKey([x, y, z]: ChunkPos): string {
+    return `${x},${y},${z}`;
+}
+
+// The client clock `TimeSync` sends, wrapping ms
+function clientMs(): number {
+    return Math.floor(performance.now()) >>> 0;
+}
+
+// Moves and edits may end in `#<seq>`, acked by `INPUT_ACK`
+function seqSuffix(seq?: number): string {
+    return seq === undefined ? "" : ` #${seq}`;
+}
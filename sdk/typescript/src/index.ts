@@ -0,0 +1,3 @@
+export * from "./client.js";
+export * from "./protocol.js";
+export * from "./wire.js";
@@ -0,0 +1,1170 @@
+// Generated by build/main.rs from schema/protocol.toml, do not edit.
+
+import { type ChunkPos, ProtocolError, type Reader, type Writer } from "./wire.js";
+
+export const CLIENT_FRAME = 0x11;
+export const SERVER_FRAME = 0x10;
+/** Whole chunk at `version`. */
+export const CHUNK_SNAPSHOT = 0x08;
+/**
+ * Edits taking a chunk from `base_version` to `version`, applied in order.
+ * A client whose version of the chunk isn't `base_version` must drop the
+ * delta; the server resends a snapshot whenever it doesn't know the client
+ * holds the base.
+ */
+export const CHUNK_DELTA = 0x09;
+/** Block ids and properties, sent once right after the handshake. */
+export const BLOCK_REGISTRY = 0x0c;
+/**
+ * A chat line in the player's world or room. `from` is a player (name, or
+ * `#<id>` for anonymous players) or a bridged user, e.g. `telegram:alice`.
+ */
+export const CHAT = 0x20;
+/**
+ * The server is going away: it closes the connection within `seconds`, sooner
+ * if everyone leaves. `address` is the replacement server's websocket URL to
+ * reconnect to, empty if there's none.
+ */
+export const DRAIN = 0x21;
+/**
+ * Sent before `DRAIN` when the player can carry on elsewhere: reconnecting
+ * with `?resume=<token>` (to `DRAIN`'s address, or this one if it's empty)
+ * restores the name, position and interest. Good for 5 minutes.
+ */
+export const RESUME = 0x22;
+/**
+ * The player moved to another room over this connection (`JoinRoom`,
+ * `LeaveRoom`, a portal), with a new player `id`. `room` is empty for the
+ * main world. Chunks from the old room are stale: snapshots of the new one
+ * follow for the interest, which carries over along with the position unless
+ * a portal leaves it behind (send `SetInterest` again).
+ */
+export const ROOM = 0x23;
+/**
+ * The player walked into a portal to another server: connect to `address`
+ * with `?resume=<token>` to land there. The server closes this connection
+ * right after.
+ */
+export const TRANSFER = 0x24;
+/**
+ * A lockstep room closed relay tick `tick`: every input sent for it, in
+ * player id order, and their `hash` (32-bit FNV-1a of the tick, then each
+ * input's player, data length as u8 and data; see `src/lockstep.rs`). Players
+ * without an input in `inputs` didn't send one in time.
+ */
+export const LOCKSTEP = 0x25;
+/**
+ * Sent on joining a lockstep room: the latest state a player uploaded with
+ * `LockstepState`, as of relay tick `tick` (0 and empty if none yet). The
+ * `LOCKSTEP` ticks closed since follow.
+ */
+export const LOCKSTEP_STATE = 0x26;
+/**
+ * Relay rooms: `data` from player `from`, forwarded as it was sent with
+ * `RELAY_SEND`.
+ */
+export const RELAY = 0x27;
+/**
+ * Relay rooms: forwards `data` to the players in `to`, or to everyone else in
+ * the room when it's empty, as `RELAY`. See `src/relay.rs` for the limits.
+ */
+export const RELAY_SEND = 0x28;
+/**
+ * One Opus frame from player `from`, who is `distance` blocks away (rounded,
+ * at most the server's voice range) with `occlusion` solid blocks in between
+ * (0 unless the server counts them, see `src/voice.rs`), for clients to mix it
+ * by. Only players close enough, and not muting `from` with `VoiceMute`, get
+ * it.
+ */
+export const VOICE = 0x29;
+/**
+ * One Opus frame from this player's microphone, forwarded as `VOICE` to the
+ * players in voice range. Frames over the server's bitrate cap are dropped,
+ * see `src/voice.rs`.
+ */
+export const VOICE_SEND = 0x2a;
+/**
+ * The server moved the player to block position `x`, `y`, `z` (`/tp`, see
+ * `src/chat_commands.rs`). Clients jump there and send moves from it, the
+ * interest doesn't follow by itself.
+ */
+export const TELEPORT = 0x2b;
+/**
+ * What's on in the player's world or room, see `src/features.rs`: chat,
+ * block edits and PvP. Sent with the block registry, after a room change and
+ * whenever an admin toggles them. The server refuses chat and edits that are
+ * off; PvP is up to clients, the server has no combat.
+ */
+export const FEATURES = 0x2c;
+/**
+ * A non-player entity spawned, moved or changed hands, see
+ * `src/entities.rs`. `authority` is the player id moving it, 0 for the
+ * server; only that player's `MoveEntity` is accepted. Everyone in the world
+ * gets these, and all entities on joining.
+ */
+export const ENTITY = 0x2d;
+/** The server removed entity `id`. */
+export const ENTITY_GONE = 0x2e;
+/**
+ * Player `player` rides entity `entity`, `x`, `y`, `z` blocks from it: draw
+ * them there as the entity moves, see `src/entities.rs`. Everyone in the
+ * world gets these, and all mounts on joining.
+ */
+export const MOUNT = 0x2f;
+/**
+ * Player `player` got off at block position `x`, `y`, `z`, or left the
+ * world. The rider sends moves from there.
+ */
+export const DISMOUNT = 0x30;
+/**
+ * Entity `entity` hangs `x`, `y`, `z` blocks from `parent`, an entity or
+ * (`player` set) a player, see `src/entities.rs`: place it from the parent
+ * as that moves, the server doesn't send its `ENTITY` then, except for
+ * player parents. Everyone in the world gets these, and all attachments on
+ * joining.
+ */
+export const ATTACH = 0x31;
+/**
+ * Entity `entity` was let go at block position `x`, `y`, `z`, by the server
+ * or because its parent went away.
+ */
+export const DETACH = 0x32;
+/**
+ * A one-off visual or sound `effect` at block position `x`, `y`, `z`, with
+ * game-defined `params`, see `src/effects.rs`. Only players whose interest
+ * holds the position get it; nothing is kept for later.
+ */
+export const EFFECT = 0x33;
+/**
+ * The player walked into (`entered`) or out of the named trigger volume, see
+ * `src/triggers.rs`. Only for triggers set to replicate.
+ */
+export const TRIGGER = 0x34;
+/**
+ * The world has applied the player's moves and edits up to `seq`, the number
+ * the client ended them with (`SetPosition 1 2 3 #17`). Sent in the next
+ * tick's frame after it changes, so a predicting client can drop the inputs
+ * it no longer needs to replay. Inputs dropped by the input delay buffer
+ * are only acked by a later one.
+ */
+export const INPUT_ACK = 0x35;
+/**
+ * The answer to a `TimeSync <client_time> [rtt]` text command, see
+ * `src/clock.rs`: `server_time` is the server clock in milliseconds
+ * (wrapping), so the client's offset is `server_time + rtt / 2 - now`.
+ * `interp_delay` is how far behind the newest snapshot to render, in ms,
+ * from the tick rate and the jitter the server measured. Also in the
+ * handshake frame, with `client_time` 0.
+ */
+export const TIME_SYNC = 0x36;
+/**
+ * For clients that asked for relative positions (`Origin X Y Z`, see
+ * `src/origin.rs`): the positions in the rest of the frame are blocks (and
+ * chunks) from block `x`, `y`, `z`, a chunk corner. It goes first in every
+ * frame with positions, frames without one hold absolute positions. The
+ * origin only changes between ticks, so frames can come out of order: move
+ * to a new origin when its frame's tick is past the one the current origin
+ * came with, then send `Origin` with it back. The client's own positions are
+ * from the origin it last sent.
+ */
+export const REBASE = 0x37;
+/**
+ * For clients that connected with `?coords=1`, before any position of a
+ * world (see `src/coords.rs`): how its positions go on the wire from now on.
+ * In `flat` (2D) worlds, no position has a z, it's 0. Block positions and
+ * offsets are in `units` per block, chunk positions stay chunks.
+ */
+export const COORDS = 0x38;
+/**
+ * The world's simulation changed (see `src/simulation.rs`), or it isn't
+ * running normally on joining: while `paused` no ticks come and moves and
+ * edits wait, and ticks come at `scale` percent of the tick rate (slow motion
+ * under 100). Scale interpolation delays and timers with it.
+ */
+export const SIMULATION = 0x39;
+/**
+ * The world stalled longer than it catches up (`TELEBOXEL_CATCH_UP_TICKS`,
+ * see `src/simulation.rs`), and `ticks` of game time weren't simulated: the
+ * tick goes on from where it was, so clocks synced to it should drop that
+ * much wall time instead of speeding up to it.
+ */
+export const TIME_SKIP = 0x3a;
+/**
+ * The player earned an achievement of the world's (see
+ * `src/achievements.rs`): its `name` and a `title` to show.
+ */
+export const ACHIEVEMENT = 0x3b;
+/**
+ * Sent on joining a rollback room (see `src/rollback.rs`): the room's
+ * `frame`, one per server tick, and the input `delay`, the frames ahead of it
+ * players send their inputs for.
+ */
+export const ROLLBACK = 0x3c;
+/**
+ * A rollback room's player sent its input for `frame`, relayed as it comes
+ * for the others to predict with. Not final until `ROLLBACK_CONFIRM`.
+ */
+export const ROLLBACK_INPUT = 0x3d;
+/**
+ * A rollback room confirmed `frame`: every input for it in player id order,
+ * the server's repeat of a player's last one where it came too late, and
+ * their `hash` (as `LOCKSTEP`'s). Inputs for it are refused from now on.
+ */
+export const ROLLBACK_CONFIRM = 0x3e;
+/**
+ * How to interpolate (see `src/interp.rs`), on joining a world and when it
+ * changes: updates come every `update_ms`, render `delay_ms` behind the
+ * newest and keep `buffer` updates.
+ */
+export const INTERP_HINT = 0x3f;
+/**
+ * The minimap a `Minimap` asked for (see `src/minimap.rs`): `size` by `size`
+ * cells of `scale` blocks, from block column `x`, `z` on. Per cell, in rows
+ * along x from the lowest z, the height of its highest block above `y` (255
+ * when unknown) and that block's RGB color.
+ */
+export const MINIMAP = 0x40;
+
+export interface Block {
+    id: number;
+    solid: boolean;
+    name: string;
+    tags: string[];
+}
+
+export type Edit = [index: number, block: number];
+
+export interface LockstepInput {
+    player: number;
+    data: string;
+}
+
+/** Whole chunk at `version`. */
+export interface ChunkSnapshot {
+    kind: typeof CHUNK_SNAPSHOT;
+    pos: ChunkPos;
+    version: number;
+    chunk: Uint16Array;
+}
+
+/**
+ * Edits taking a chunk from `base_version` to `version`, applied in order.
+ * A client whose version of the chunk isn't `base_version` must drop the
+ * delta; the server resends a snapshot whenever it doesn't know the client
+ * holds the base.
+ */
+export interface ChunkDelta {
+    kind: typeof CHUNK_DELTA;
+    pos: ChunkPos;
+    baseVersion: number;
+    version: number;
+    edits: Edit[];
+}
+
+/** Block ids and properties, sent once right after the handshake. */
+export interface BlockRegistry {
+    kind: typeof BLOCK_REGISTRY;
+    blocks: Block[];
+}
+
+/**
+ * A chat line in the player's world or room. `from` is a player (name, or
+ * `#<id>` for anonymous players) or a bridged user, e.g. `telegram:alice`.
+ */
+export interface Chat {
+    kind: typeof CHAT;
+    from: string;
+    text: string;
+}
+
+/**
+ * The server is going away: it closes the connection within `seconds`, sooner
+ * if everyone leaves. `address` is the replacement server's websocket URL to
+ * reconnect to, empty if there's none.
+ */
+export interface Drain {
+    kind: typeof DRAIN;
+    seconds: number;
+    address: string;
+}
+
+/**
+ * Sent before `DRAIN` when the player can carry on elsewhere: reconnecting
+ * with `?resume=<token>` (to `DRAIN`'s address, or this one if it's empty)
+ * restores the name, position and interest. Good for 5 minutes.
+ */
+export interface Resume {
+    kind: typeof RESUME;
+    token: string;
+}
+
+/**
+ * The player moved to another room over this connection (`JoinRoom`,
+ * `LeaveRoom`, a portal), with a new player `id`. `room` is empty for the
+ * main world. Chunks from the old room are stale: snapshots of the new one
+ * follow for the interest, which carries over along with the position unless
+ * a portal leaves it behind (send `SetInterest` again).
+ */
+export interface Room {
+    kind: typeof ROOM;
+    room: string;
+    id: number;
+}
+
+/**
+ * The player walked into a portal to another server: connect to `address`
+ * with `?resume=<token>` to land there. The server closes this connection
+ * right after.
+ */
+export interface Transfer {
+    kind: typeof TRANSFER;
+    address: string;
+    token: string;
+}
+
+/**
+ * A lockstep room closed relay tick `tick`: every input sent for it, in
+ * player id order, and their `hash` (32-bit FNV-1a of the tick, then each
+ * input's player, data length as u8 and data; see `src/lockstep.rs`). Players
+ * without an input in `inputs` didn't send one in time.
+ */
+export interface Lockstep {
+    kind: typeof LOCKSTEP;
+    tick: number;
+    hash: number;
+    inputs: LockstepInput[];
+}
+
+/**
+ * Sent on joining a lockstep room: the latest state a player uploaded with
+ * `LockstepState`, as of relay tick `tick` (0 and empty if none yet). The
+ * `LOCKSTEP` ticks closed since follow.
+ */
+export interface LockstepState {
+    kind: typeof LOCKSTEP_STATE;
+    tick: number;
+    state: number[];
+}
+
+/**
+ * Relay rooms: `data` from player `from`, forwarded as it was sent with
+ * `RELAY_SEND`.
+ */
+export interface Relay {
+    kind: typeof RELAY;
+    from: number;
+    data: number[];
+}
+
+/**
+ * Relay rooms: forwards `data` to the players in `to`, or to everyone else in
+ * the room when it's empty, as `RELAY`. See `src/relay.rs` for the limits.
+ */
+export interface RelaySend {
+    kind: typeof RELAY_SEND;
+    to: number[];
+    data: number[];
+}
+
+/**
+ * One Opus frame from player `from`, who is `distance` blocks away (rounded,
+ * at most the server's voice range) with `occlusion` solid blocks in between
+ * (0 unless the server counts them, see `src/voice.rs`), for clients to mix it
+ * by. Only players close enough, and not muting `from` with `VoiceMute`, get
+ * it.
+ */
+export interface Voice {
+    kind: typeof VOICE;
+    from: number;
+    distance: number;
+    occlusion: number;
+    data: number[];
+}
+
+/**
+ * One Opus frame from this player's microphone, forwarded as `VOICE` to the
+ * players in voice range. Frames over the server's bitrate cap are dropped,
+ * see `src/voice.rs`.
+ */
+export interface VoiceSend {
+    kind: typeof VOICE_SEND;
+    data: number[];
+}
+
+/**
+ * The server moved the player to block position `x`, `y`, `z` (`/tp`, see
+ * `src/chat_commands.rs`). Clients jump there and send moves from it, the
+ * interest doesn't follow by itself.
+ */
+export interface Teleport {
+    kind: typeof TELEPORT;
+    x: number;
+    y: number;
+    z: number;
+}
+
+/**
+ * What's on in the player's world or room, see `src/features.rs`: chat,
+ * block edits and PvP. Sent with the block registry, after a room change and
+ * whenever an admin toggles them. The server refuses chat and edits that are
+ * off; PvP is up to clients, the server has no combat.
+ */
+export interface Features {
+    kind: typeof FEATURES;
+    chat: boolean;
+    build: boolean;
+    pvp: boolean;
+}
+
+/**
+ * A non-player entity spawned, moved or changed hands, see
+ * `src/entities.rs`. `authority` is the player id moving it, 0 for the
+ * server; only that player's `MoveEntity` is accepted. Everyone in the world
+ * gets these, and all entities on joining.
+ */
+export interface Entity {
+    kind: typeof ENTITY;
+    id: number;
+    x: number;
+    y: number;
+    z: number;
+    authority: number;
+}
+
+/** The server removed entity `id`. */
+export interface EntityGone {
+    kind: typeof ENTITY_GONE;
+    id: number;
+}
+
+/**
+ * Player `player` rides entity `entity`, `x`, `y`, `z` blocks from it: draw
+ * them there as the entity moves, see `src/entities.rs`. Everyone in the
+ * world gets these, and all mounts on joining.
+ */
+export interface Mount {
+    kind: typeof MOUNT;
+    player: number;
+    entity: number;
+    x: number;
+    y: number;
+    z: number;
+}
+
+/**
+ * Player `player` got off at block position `x`, `y`, `z`, or left the
+ * world. The rider sends moves from there.
+ */
+export interface Dismount {
+    kind: typeof DISMOUNT;
+    player: number;
+    x: number;
+    y: number;
+    z: number;
+}
+
+/**
+ * Entity `entity` hangs `x`, `y`, `z` blocks from `parent`, an entity or
+ * (`player` set) a player, see `src/entities.rs`: place it from the parent
+ * as that moves, the server doesn't send its `ENTITY` then, except for
+ * player parents. Everyone in the world gets these, and all attachments on
+ * joining.
+ */
+export interface Attach {
+    kind: typeof ATTACH;
+    entity: number;
+    parent: number;
+    player: boolean;
+    x: number;
+    y: number;
+    z: number;
+}
+
+/**
+ * Entity `entity` was let go at block position `x`, `y`, `z`, by the server
+ * or because its parent went away.
+ */
+export interface Detach {
+    kind: typeof DETACH;
+    entity: number;
+    x: number;
+    y: number;
+    z: number;
+}
+
+/**
+ * A one-off visual or sound `effect` at block position `x`, `y`, `z`, with
+ * game-defined `params`, see `src/effects.rs`. Only players whose interest
+ * holds the position get it; nothing is kept for later.
+ */
+export interface Effect {
+    kind: typeof EFFECT;
+    effect: number;
+    x: number;
+    y: number;
+    z: number;
+    params: number[];
+}
+
+/**
+ * The player walked into (`entered`) or out of the named trigger volume, see
+ * `src/triggers.rs`. Only for triggers set to replicate.
+ */
+export interface Trigger {
+    kind: typeof TRIGGER;
+    trigger: string;
+    entered: boolean;
+}
+
+/**
+ * The world has applied the player's moves and edits up to `seq`, the number
+ * the client ended them with (`SetPosition 1 2 3 #17`). Sent in the next
+ * tick's frame after it changes, so a predicting client can drop the inputs
+ * it no longer needs to replay. Inputs dropped by the input delay buffer
+ * are only acked by a later one.
+ */
+export interface InputAck {
+    kind: typeof INPUT_ACK;
+    seq: number;
+}
+
+/**
+ * The answer to a `TimeSync <client_time> [rtt]` text command, see
+ * `src/clock.rs`: `server_time` is the server clock in milliseconds
+ * (wrapping), so the client's offset is `server_time + rtt / 2 - now`.
+ * `interp_delay` is how far behind the newest snapshot to render, in ms,
+ * from the tick rate and the jitter the server measured. Also in the
+ * handshake frame, with `client_time` 0.
+ */
+export interface TimeSync {
+    kind: typeof TIME_SYNC;
+    clientTime: number;
+    serverTime: number;
+    tickHz: number;
+    interpDelay: number;
+}
+
+/**
+ * For clients that asked for relative positions (`Origin X Y Z`, see
+ * `src/origin.rs`): the positions in the rest of the frame are blocks (and
+ * chunks) from block `x`, `y`, `z`, a chunk corner. It goes first in every
+ * frame with positions, frames without one hold absolute positions. The
+ * origin only changes between ticks, so frames can come out of order: move
+ * to a new origin when its frame's tick is past the one the current origin
+ * came with, then send `Origin` with it back. The client's own positions are
+ * from the origin it last sent.
+ */
+export interface Rebase {
+    kind: typeof REBASE;
+    x: number;
+    y: number;
+    z: number;
+}
+
+/**
+ * For clients that connected with `?coords=1`, before any position of a
+ * world (see `src/coords.rs`): how its positions go on the wire from now on.
+ * In `flat` (2D) worlds, no position has a z, it's 0. Block positions and
+ * offsets are in `units` per block, chunk positions stay chunks.
+ */
+export interface Coords {
+    kind: typeof COORDS;
+    flat: boolean;
+    units: number;
+}
+
+/**
+ * The world's simulation changed (see `src/simulation.rs`), or it isn't
+ * running normally on joining: while `paused` no ticks come and moves and
+ * edits wait, and ticks come at `scale` percent of the tick rate (slow motion
+ * under 100). Scale interpolation delays and timers with it.
+ */
+export interface Simulation {
+    kind: typeof SIMULATION;
+    paused: boolean;
+    scale: number;
+}
+
+/**
+ * The world stalled longer than it catches up (`TELEBOXEL_CATCH_UP_TICKS`,
+ * see `src/simulation.rs`), and `ticks` of game time weren't simulated: the
+ * tick goes on from where it was, so clocks synced to it should drop that
+ * much wall time instead of speeding up to it.
+ */
+export interface TimeSkip {
+    kind: typeof TIME_SKIP;
+    ticks: number;
+}
+
+/**
+ * The player earned an achievement of the world's (see
+ * `src/achievements.rs`): its `name` and a `title` to show.
+ */
+export interface Achievement {
+    kind: typeof ACHIEVEMENT;
+    name: string;
+    title: string;
+}
+
+/**
+ * Sent on joining a rollback room (see `src/rollback.rs`): the room's
+ * `frame`, one per server tick, and the input `delay`, the frames ahead of it
+ * players send their inputs for.
+ */
+export interface Rollback {
+    kind: typeof ROLLBACK;
+    frame: number;
+    delay: number;
+}
+
+/**
+ * A rollback room's player sent its input for `frame`, relayed as it comes
+ * for the others to predict with. Not final until `ROLLBACK_CONFIRM`.
+ */
+export interface RollbackInput {
+    kind: typeof ROLLBACK_INPUT;
+    player: number;
+    frame: number;
+    data: string;
+}
+
+/**
+ * A rollback room confirmed `frame`: every input for it in player id order,
+ * the server's repeat of a player's last one where it came too late, and
+ * their `hash` (as `LOCKSTEP`'s). Inputs for it are refused from now on.
+ */
+export interface RollbackConfirm {
+    kind: typeof ROLLBACK_CONFIRM;
+    frame: number;
+    hash: number;
+    inputs: LockstepInput[];
+}
+
+/**
+ * How to interpolate (see `src/interp.rs`), on joining a world and when it
+ * changes: updates come every `update_ms`, render `delay_ms` behind the
+ * newest and keep `buffer` updates.
+ */
+export interface InterpHint {
+    kind: typeof INTERP_HINT;
+    updateMs: number;
+    delayMs: number;
+    buffer: number;
+}
+
+/**
+ * The minimap a `Minimap` asked for (see `src/minimap.rs`): `size` by `size`
+ * cells of `scale` blocks, from block column `x`, `z` on. Per cell, in rows
+ * along x from the lowest z, the height of its highest block above `y` (255
+ * when unknown) and that block's RGB color.
+ */
+export interface Minimap {
+    kind: typeof MINIMAP;
+    x: number;
+    y: number;
+    z: number;
+    scale: number;
+    size: number;
+    heights: number[];
+    colors: number[];
+}
+
+function writeBlock(w: Writer, v: Block): void {
+    w.u16(v.id);
+    w.bool(v.solid);
+    w.str(v.name);
+    w.u8(v.tags.length);
+    for (const item of v.tags) {
+        w.str(item);
+    }
+}
+
+function readBlock(r: Reader): Block {
+    const id = r.u16();
+    const solid = r.bool();
+    const name = r.str();
+    const tags = r.list(r.u8(), () => r.str());
+    return { id, solid, name, tags };
+}
+
+function writeEdit(w: Writer, v: Edit): void {
+    w.u16(v[0]);
+    w.u16(v[1]);
+}
+
+function readEdit(r: Reader): Edit {
+    const index = r.u16();
+    if (index > 4095) {
+        throw new ProtocolError("edit index out of range");
+    }
+    const block = r.u16();
+    return [index, block];
+}
+
+function writeLockstepInput(w: Writer, v: LockstepInput): void {
+    w.u32(v.player);
+    w.str(v.data);
+}
+
+function readLockstepInput(r: Reader): LockstepInput {
+    const player = r.u32();
+    const data = r.str();
+    return { player, data };
+}
+
+/** Decoded client submessage. */
+export type ClientMsg = RelaySend | VoiceSend;
+
+export function writeClientMsg(w: Writer, m: ClientMsg): void {
+    w.u8(m.kind);
+    switch (m.kind) {
+        case RELAY_SEND:
+            w.u8(m.to.length);
+            for (const item of m.to) {
+                w.u32(item);
+            }
+            w.u16(m.data.length);
+            for (const item of m.data) {
+                w.u8(item);
+            }
+            break;
+        case VOICE_SEND:
+            w.u16(m.data.length);
+            for (const item of m.data) {
+                w.u8(item);
+            }
+            break;
+    }
+}
+
+export function readClientMsg(r: Reader): ClientMsg {
+    const kind = r.u8();
+    switch (kind) {
+        case RELAY_SEND: {
+            const to = r.list(r.u8(), () => r.u32());
+            const data = r.list(r.u16(), () => r.u8());
+            return { kind: RELAY_SEND, to, data };
+        }
+        case VOICE_SEND: {
+            const data = r.list(r.u16(), () => r.u8());
+            return { kind: VOICE_SEND, data };
+        }
+        default:
+            throw new ProtocolError(`unknown submessage ${kind}`);
+    }
+}
+
+/** Decoded server submessage. */
+export type ServerMsg = ChunkSnapshot | ChunkDelta | BlockRegistry | Chat | Drain | Resume | Room | Transfer | Lockstep | LockstepState | Relay | Voice | Teleport | Features | Entity | EntityGone | Mount | Dismount | Attach | Detach | Effect | Trigger | InputAck | TimeSync | Rebase | Coords | Simulation | TimeSkip | Achievement | Rollback | RollbackInput | RollbackConfirm | InterpHint | Minimap;
+
+export function writeServerMsg(w: Writer, m: ServerMsg): void {
+    w.u8(m.kind);
+    switch (m.kind) {
+        case CHUNK_SNAPSHOT:
+            w.pos(m.pos);
+            w.u32(m.version);
+            w.chunk(m.chunk);
+            break;
+        case CHUNK_DELTA:
+            w.pos(m.pos);
+            w.u32(m.baseVersion);
+            w.u32(m.version);
+            w.u16(m.edits.length);
+            for (const item of m.edits) {
+                writeEdit(w, item);
+            }
+            break;
+        case BLOCK_REGISTRY:
+            w.u16(m.blocks.length);
+            for (const item of m.blocks) {
+                writeBlock(w, item);
+            }
+            break;
+        case CHAT:
+            w.str(m.from);
+            w.str(m.text);
+            break;
+        case DRAIN:
+            w.u16(m.seconds);
+            w.str(m.address);
+            break;
+        case RESUME:
+            w.str(m.token);
+            break;
+        case ROOM:
+            w.str(m.room);
+            w.u32(m.id);
+            break;
+        case TRANSFER:
+            w.str(m.address);
+            w.str(m.token);
+            break;
+        case LOCKSTEP:
+            w.u32(m.tick);
+            w.u32(m.hash);
+            w.u16(m.inputs.length);
+            for (const item of m.inputs) {
+                writeLockstepInput(w, item);
+            }
+            break;
+        case LOCKSTEP_STATE:
+            w.u32(m.tick);
+            w.u32(m.state.length);
+            for (const item of m.state) {
+                w.u8(item);
+            }
+            break;
+        case RELAY:
+            w.u32(m.from);
+            w.u16(m.data.length);
+            for (const item of m.data) {
+                w.u8(item);
+            }
+            break;
+        case VOICE:
+            w.u32(m.from);
+            w.u16(m.distance);
+            w.u8(m.occlusion);
+            w.u16(m.data.length);
+            for (const item of m.data) {
+                w.u8(item);
+            }
+            break;
+        case TELEPORT:
+            w.i32(m.x);
+            w.i32(m.y);
+            w.i32(m.z);
+            break;
+        case FEATURES:
+            w.bool(m.chat);
+            w.bool(m.build);
+            w.bool(m.pvp);
+            break;
+        case ENTITY:
+            w.u32(m.id);
+            w.i32(m.x);
+            w.i32(m.y);
+            w.i32(m.z);
+            w.u32(m.authority);
+            break;
+        case ENTITY_GONE:
+            w.u32(m.id);
+            break;
+        case MOUNT:
+            w.u32(m.player);
+            w.u32(m.entity);
+            w.i32(m.x);
+            w.i32(m.y);
+            w.i32(m.z);
+            break;
+        case DISMOUNT:
+            w.u32(m.player);
+            w.i32(m.x);
+            w.i32(m.y);
+            w.i32(m.z);
+            break;
+        case ATTACH:
+            w.u32(m.entity);
+            w.u32(m.parent);
+            w.bool(m.player);
+            w.i32(m.x);
+            w.i32(m.y);
+            w.i32(m.z);
+            break;
+        case DETACH:
+            w.u32(m.entity);
+            w.i32(m.x);
+            w.i32(m.y);
+            w.i32(m.z);
+            break;
+        case EFFECT:
+            w.u16(m.effect);
+            w.i32(m.x);
+            w.i32(m.y);
+            w.i32(m.z);
+            w.u16(m.params.length);
+            for (const item of m.params) {
+                w.u8(item);
+            }
+            break;
+        case TRIGGER:
+            w.str(m.trigger);
+            w.bool(m.entered);
+            break;
+        case INPUT_ACK:
+            w.u32(m.seq);
+            break;
+        case TIME_SYNC:
+            w.u32(m.clientTime);
+            w.u32(m.serverTime);
+            w.u16(m.tickHz);
+            w.u16(m.interpDelay);
+            break;
+        case REBASE:
+            w.i32(m.x);
+            w.i32(m.y);
+            w.i32(m.z);
+            break;
+        case COORDS:
+            w.bool(m.flat);
+            w.u16(m.units);
+            break;
+        case SIMULATION:
+            w.bool(m.paused);
+            w.u16(m.scale);
+            break;
+        case TIME_SKIP:
+            w.u32(m.ticks);
+            break;
+        case ACHIEVEMENT:
+            w.str(m.name);
+            w.str(m.title);
+            break;
+        case ROLLBACK:
+            w.u32(m.frame);
+            w.u8(m.delay);
+            break;
+        case ROLLBACK_INPUT:
+            w.u32(m.player);
+            w.u32(m.frame);
+            w.str(m.data);
+            break;
+        case ROLLBACK_CONFIRM:
+            w.u32(m.frame);
+            w.u32(m.hash);
+            w.u16(m.inputs.length);
+            for (const item of m.inputs) {
+                writeLockstepInput(w, item);
+            }
+            break;
+        case INTERP_HINT:
+            w.u16(m.updateMs);
+            w.u16(m.delayMs);
+            w.u8(m.buffer);
+            break;
+        case MINIMAP:
+            w.i32(m.x);
+            w.i32(m.y);
+            w.i32(m.z);
+            w.u8(m.scale);
+            w.u8(m.size);
+            w.u16(m.heights.length);
+            for (const item of m.heights) {
+                w.u8(item);
+            }
+            w.u16(m.colors.length);
+            for (const item of m.colors) {
+                w.u8(item);
+            }
+            break;
+    }
+}
+
+export function readServerMsg(r: Reader): ServerMsg {
+    const kind = r.u8();
+    switch (kind) {
+        case CHUNK_SNAPSHOT: {
+            const pos = r.pos();
+            const version = r.u32();
+            const chunk = r.chunk();
+            return { kind: CHUNK_SNAPSHOT, pos, version, chunk };
+        }
+        case CHUNK_DELTA: {
+            const pos = r.pos();
+            const baseVersion = r.u32();
+            const version = r.u32();
+            const edits = r.list(r.u16(), () => readEdit(r));
+            return { kind: CHUNK_DELTA, pos, baseVersion, version, edits };
+        }
+        case BLOCK_REGISTRY: {
+            const blocks = r.list(r.u16(), () => readBlock(r));
+            return { kind: BLOCK_REGISTRY, blocks };
+        }
+        case CHAT: {
+            const from = r.str();
+            const text = r.str();
+            return { kind: CHAT, from, text };
+        }
+        case DRAIN: {
+            const seconds = r.u16();
+            const address = r.str();
+            return { kind: DRAIN, seconds, address };
+        }
+        case RESUME: {
+            const token = r.str();
+            return { kind: RESUME, token };
+        }
+        case ROOM: {
+            const room = r.str();
+            const id = r.u32();
+            return { kind: ROOM, room, id };
+        }
+        case TRANSFER: {
+            const address = r.str();
+            const token = r.str();
+            return { kind: TRANSFER, address, token };
+        }
+        case LOCKSTEP: {
+            const tick = r.u32();
+            const hash = r.u32();
+            const inputs = r.list(r.u16(), () => readLockstepInput(r));
+            return { kind: LOCKSTEP, tick, hash, inputs };
+        }
+        case LOCKSTEP_STATE: {
+            const tick = r.u32();
+            const state = r.list(r.u32(), () => r.u8());
+            return { kind: LOCKSTEP_STATE, tick, state };
+        }
+        case RELAY: {
+            const from = r.u32();
+            const data = r.list(r.u16(), () => r.u8());
+            return { kind: RELAY, from, data };
+        }
+        case VOICE: {
+            const from = r.u32();
+            const distance = r.u16();
+            const occlusion = r.u8();
+            const data = r.list(r.u16(), () => r.u8());
+            return { kind: VOICE, from, distance, occlusion, data };
+        }
+        case TELEPORT: {
+            const x = r.i32();
+            const y = r.i32();
+            const z = r.i32();
+            return { kind: TELEPORT, x, y, z };
+        }
+        case FEATURES: {
+            const chat = r.bool();
+            const build = r.bool();
+            const pvp = r.bool();
+            return { kind: FEATURES, chat, build, pvp };
+        }
+        case ENTITY: {
+            const id = r.u32();
+            const x = r.i32();
+            const y = r.i32();
+            const z = r.i32();
+            const authority = r.u32();
+            return { kind: ENTITY, id, x, y, z, authority };
+        }
+        case ENTITY_GONE: {
+            const id = r.u32();
+            return { kind: ENTITY_GONE, id };
+        }
+        case MOUNT: {
+            const player = r.u32();
+            const entity = r.u32();
+            const x = r.i32();
+            const y = r.i32();
+            const z = r.i32();
+            return { kind: MOUNT, player, entity, x, y, z };
+        }
+        case DISMOUNT: {
+            const player = r.u32();
+            const x = r.i32();
+            const y = r.i32();
+            const z = r.i32();
+            return { kind: DISMOUNT, player, x, y, z };
+        }
+        case ATTACH: {
+            const entity = r.u32();
+            const parent = r.u32();
+            const player = r.bool();
+            const x = r.i32();
+            const y = r.i32();
+            const z = r.i32();
+            return { kind: ATTACH, entity, parent, player, x, y, z };
+        }
+        case DETACH: {
+            const entity = r.u32();
+            const x = r.i32();
+            const y = r.i32();
+            const z = r.i32();
+            return { kind: DETACH, entity, x, y, z };
+        }
+        case EFFECT: {
+            const effect = r.u16();
+            const x = r.i32();
+            const y = r.i32();
+            const z = r.i32();
+            const params = r.list(r.u16(), () => r.u8());
+            return { kind: EFFECT, effect, x, y, z, params };
+        }
+        case TRIGGER: {
+            const trigger = r.str();
+            const entered = r.bool();
+            return { kind: TRIGGER, trigger, entered };
+        }
+        case INPUT_ACK: {
+            const seq = r.u32();
+            return { kind: INPUT_ACK, seq };
+        }
+        case TIME_SYNC: {
+            const clientTime = r.u32();
+            const serverTime = r.u32();
+            const tickHz = r.u16();
+            const interpDelay = r.u16();
+            return { kind: TIME_SYNC, clientTime, serverTime, tickHz, interpDelay };
+        }
+        case REBASE: {
+            const x = r.i32();
+            const y = r.i32();
+            const z = r.i32();
+            return { kind: REBASE, x, y, z };
+        }
+        case COORDS: {
+            const flat = r.bool();
+            const units = r.u16();
+            return { kind: COORDS, flat, units };
+        }
+        case SIMULATION: {
+            const paused = r.bool();
+            const scale = r.u16();
+            return { kind: SIMULATION, paused, scale };
+        }
+        case TIME_SKIP: {
+            const ticks = r.u32();
+            return { kind: TIME_SKIP, ticks };
+        }
+        case ACHIEVEMENT: {
+            const name = r.str();
+            const title = r.str();
+            return { kind: ACHIEVEMENT, name, title };
+        }
+        case ROLLBACK: {
+            const frame = r.u32();
+            const delay = r.u8();
+            return { kind: ROLLBACK, frame, delay };
+        }
+        case ROLLBACK_INPUT: {
+            const player = r.u32();
+            const frame = r.u32();
+            const data = r.str();
+            return { kind: ROLLBACK_INPUT, player, frame, data };
+        }
+        case ROLLBACK_CONFIRM: {
+            const frame = r.u32();
+            const hash = r.u32();
+            const inputs = r.list(r.u16(), () => readLockstepInput(r));
+            return { kind: ROLLBACK_CONFIRM, frame, hash, inputs };
+        }
+        case INTERP_HINT: {
+            const updateMs = r.u16();
+            const delayMs = r.u16();
+            const buffer = r.u8();
+            return { kind: INTERP_HINT, updateMs, delayMs, buffer };
+        }
+        case MINIMAP: {
+            const x = r.i32();
+            const y = r.i32();
+            const z = r.i32();
+            const scale = r.u8();
+            const size = r.u8();
+            const heights = r.list(r.u16(), () => r.u8());
+            const colors = r.list(r.u16(), () => r.u8());
+            return { kind: MINIMAP, x, y, z, scale, size, heights, colors };
+        }
+        default:
+            throw new ProtocolError(`unknown submessage ${kind}`);
+    }
+}
@@ -0,0 +1,244 @@
+// Little-endian readers and writers for the generated protocol code, and the
+// chunk payload encoding (src/chunk_wire.rs on the server).
+
+export const CHUNK_SIZE = 16;
+export const CHUNK_VOLUME = CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE;
+
+const RAW = 0;
+const PALETTE_RLE = 1;
+const FLATBUFFERS = 2;
+
+const LITTLE_ENDIAN = new Uint8Array(new Uint16Array([1]).buffer)[0] === 1;
+
+/** Chunk coordinates (world block coordinates divided by `CHUNK_SIZE`). */
+export type ChunkPos = [x: number, y: number, z: number];
+
+/** Malformed or truncated data from the server. */
+export class ProtocolError extends Error {
+    constructor(message: string) {
+        super(message);
+        this.name = "ProtocolError";
+    }
+}
+
+/** Block index in a chunk, y-major like the server. */
+export function blockIndex(x: number, y: number, z: number): number {
+    return y * CHUNK_SIZE * CHUNK_SIZE + z * CHUNK_SIZE + x;
+}
+
+export class Reader {
+    at = 0;
+    private view: DataView;
+
+    constructor(private data: Uint8Array) {
+        this.view = new DataView(data.buffer, data.byteOffset, data.byteLength);
+    }
+
+    get remaining(): number {
+        return this.data.length - this.at;
+    }
+
+    private take(n: number): number {
+        if (this.at + n > this.data.length) {
+            throw new ProtocolError("data is truncated");
+        }
+        const at = this.at;
+        this.at += n;
+        return at;
+    }
+
+    u8(): number {
+        return this.view.getUint8(this.take(1));
+    }
+
+    u16(): number {
+        return this.view.getUint16(this.take(2), true);
+    }
+
+    u32(): number {
+        return this.view.getUint32(this.take(4), true);
+    }
+
+    i32(): number {
+        return this.view.getInt32(this.take(4), true);
+    }
+
+    /** Bit0 of a `u8`, the other bits are reserved. */
+    bool(): boolean {
+        return (this.u8() & 1) !== 0;
+    }
+
+    pos(): ChunkPos {
+        return [this.i32(), this.i32(), this.i32()];
+    }
+
+    str(): string {
+        const len = this.u8();
+        const at = this.take(len);
+        const bytes = this.data.subarray(at, at + len);
+        try {
+            return new TextDecoder("utf-8", { fatal: true }).decode(bytes);
+        } catch {
+            throw new ProtocolError("bad UTF-8");
+        }
+    }
+
+    list<T>(count: number, item: () => T): T[] {
+        const items = [];
+        for (let i = 0; i < count; i++) {
+            items.push(item());
+        }
+        return items;
+    }
+
+    /** Chunk payload, RAW, PALETTE_RLE or FLATBUFFERS. */
+    chunk(): Uint16Array {
+        const encoding = this.u8();
+        if (encoding === FLATBUFFERS) {
+            return this.flatChunk();
+        }
+
+        const blocks = new Uint16Array(CHUNK_VOLUME);
+        if (encoding === RAW) {
+            for (let i = 0; i < CHUNK_VOLUME; i++) {
+                blocks[i] = this.u16();
+            }
+            return blocks;
+        }
+        if (encoding !== PALETTE_RLE) {
+            throw new ProtocolError(`unknown chunk encoding ${encoding}`);
+        }
+
+        const paletteLen = this.u16();
+        if (paletteLen === 0 || paletteLen > CHUNK_VOLUME) {
+            throw new ProtocolError("palette length out of range");
+        }
+        const palette = this.list(paletteLen, () => this.u16());
+        const wide = paletteLen > 256;
+
+        let filled = 0;
+        const runs = this.u16();
+        for (let i = 0; i < runs; i++) {
+            const len = this.u8() + 1;
+            const index = wide ? this.u16() : this.u8();
+            if (index >= palette.length) {
+                throw new ProtocolError("palette index out of range");
+            }
+            if (filled + len > CHUNK_VOLUME) {
+                throw new ProtocolError("runs exceed chunk volume");
+            }
+            blocks.fill(palette[index], filled, filled + len);
+            filled += len;
+        }
+        if (filled !== CHUNK_VOLUME) {
+            throw new ProtocolError("runs don't cover the chunk");
+        }
+        return blocks;
+    }
+
+    /**
+     * FlatBuffer with the `schema/chunk.fbs` root table. The blocks are a
+     * view into the frame when it's aligned, so keep the chunk rather than
+     * the frame if memory matters.
+     */
+    private flatChunk(): Uint16Array {
+        const len = this.u32();
+        const start = this.take(len);
+        const fb = new Reader(this.data.subarray(start, this.at));
+        const seek = (at: number) => {
+            if (at < 0) {
+                throw new ProtocolError("bad flatbuffer");
+            }
+            fb.at = at;
+            return fb;
+        };
+
+        const table = seek(0).u32();
+        if (String.fromCharCode(...fb.data.subarray(4, 8)) !== "TBXC") {
+            throw new ProtocolError("wrong flatbuffer identifier");
+        }
+        const vtable = table - seek(table).i32();
+        const field = seek(vtable).u16() >= 6 ? seek(vtable + 4).u16() : 0;
+        if (field === 0) {
+            throw new ProtocolError("flatbuffer has no blocks");
+        }
+        const vector = table + field + seek(table + field).u32();
+        if (seek(vector).u32() !== CHUNK_VOLUME) {
+            throw new ProtocolError("wrong block count");
+        }
+        const blocks = fb.take(CHUNK_VOLUME * 2);
+
+        const offset = this.data.byteOffset + start + blocks;
+        if (LITTLE_ENDIAN && offset % 2 === 0) {
+            return new Uint16Array(this.data.buffer, offset, CHUNK_VOLUME);
+        }
+        seek(blocks);
+        return Uint16Array.from({ length: CHUNK_VOLUME }, () => fb.u16());
+    }
+}
+
+export class Writer {
+    private buf = new Uint8Array(256);
+    private view = new DataView(this.buf.buffer);
+    private len = 0;
+
+    private reserve(n: number): number {
+        if (this.len + n > this.buf.length) {
+            const buf = new Uint8Array(Math.max(this.buf.length * 2, this.len + n));
+            buf.set(this.buf.subarray(0, this.len));
+            this.buf = buf;
+            this.view = new DataView(buf.buffer);
+        }
+        const at = this.len;
+        this.len += n;
+        return at;
+    }
+
+    u8(v: number): void {
+        this.view.setUint8(this.reserve(1), v);
+    }
+
+    u16(v: number): void {
+        this.view.setUint16(this.reserve(2), v, true);
+    }
+
+    u32(v: number): void {
+        this.view.setUint32(this.reserve(4), v, true);
+    }
+
+    i32(v: number): void {
+        this.view.setInt32(this.reserve(4), v, true);
+    }
+
+    bool(v: boolean): void {
+        this.u8(v ? 1 : 0);
+    }
+
+    pos([x, y, z]: ChunkPos): void {
+        this.i32(x);
+        this.i32(y);
+        this.i32(z);
+    }
+
+    /** At most 255 bytes of UTF-8. */
+    str(v: string): void {
+        const bytes = new TextEncoder().encode(v);
+        if (bytes.length > 255) {
+            throw new RangeError(`${JSON.stringify(v)} is longer than 255 bytes`);
+        }
+        this.u8(bytes.length);
+        this.buf.set(bytes, this.reserve(bytes.length));
+    }
+
+    /** Chunk payload, always RAW (the server picks the smaller encoding). */
+    chunk(blocks: Uint16Array): void {
+        this.u8(RAW);
+        for (let i = 0; i < CHUNK_VOLUME; i++) {
+            this.u16(blocks[i]);
+        }
+    }
+
+    finish(): Uint8Array {
+        return this.buf.slice(0, this.len);
+    }
+}
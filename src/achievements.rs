@@ -0,0 +1,253 @@
+//! Achievements: rules over a world's journal events (`journal.rs`) that
+//! award players a flag once they've done something often enough. In a
+//! TOML file (`TELEBOXEL_ACHIEVEMENTS`, a world is `main` or a room name):
+//!
+//! ```toml
+//! [default.builder]
+//! title = "Placed 100 blocks"
+//! event = "block_set"    # blocks the player set
+//! count = 100
+//!
+//! [default.explorer]
+//! title = "Traveled 1km"
+//! event = "traveled"     # blocks moved
+//! count = 1000
+//!
+//! [room.arena.first_blood]
+//! title = "First blood"
+//! event = "custom"       # the game's `Track kill ...`
+//! name = "kill"
+//! ```
+//!
+//! Rooms get the default achievements and their own. Events counted:
+//! `player_joined`, `block_set`, `traveled`, `trigger_entered` and
+//! `trigger_exited` (`name` picks a trigger), `custom` (`name` picks the
+//! tracked event, required) and `achievement_earned` (`name` picks one).
+//!
+//! Progress and awards are player properties: `progress.<achievement>`
+//! and `achievement.<achievement>` (unix seconds when earned), saved with
+//! the record. Players without one (guests, and everyone in rooms, whose
+//! records stay with the main world) keep them for the visit. Earning one
+//! sends the player `ACHIEVEMENT` and records `achievement_earned`.
+
+use crate::{journal::JournalEvent, storage::unix_now};
+use serde::Deserialize;
+use std::{collections::HashMap, error::Error, fs, path::Path, sync::Arc};
+
+// Events rules can count
+const COUNTED: [&str; 7] = [
+    "player_joined",
+    "block_set",
+    "traveled",
+    "trigger_entered",
+    "trigger_exited",
+    "custom",
+    "achievement_earned",
+];
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Achievement {
+    pub name: String,
+    pub title: String,
+    event: String,
+    filter: Option<String>,
+    count: u64,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AchievementTable {
+    title: Option<String>,
+    event: String,
+    name: Option<String>,
+    count: Option<u64>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct AchievementsFile {
+    #[serde(default)]
+    default: HashMap<String, AchievementTable>,
+    #[serde(default)]
+    room: HashMap<String, HashMap<String, AchievementTable>>,
+}
+
+/// Achievements by world.
+#[derive(Default)]
+pub struct AchievementRules {
+    default: Arc<[Achievement]>,
+    rooms: HashMap<String, Arc<[Achievement]>>,
+}
+
+impl AchievementRules {
+    /// See the module docs for the file format.
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    fn parse(text: &str) -> Result<Self, Box<dyn Error>> {
+        let file: AchievementsFile = toml::from_str(text)?;
+        let achievements = |tables: &HashMap<String, AchievementTable>| {
+            let mut list = Vec::new();
+            for (name, table) in tables {
+                list.push(achievement(name, table)?);
+            }
+            Ok::<_, Box<dyn Error>>(list)
+        };
+        let default = achievements(&file.default)?;
+        let mut rooms = HashMap::new();
+        for (room, tables) in &file.room {
+            let own = achievements(tables)?;
+            let mut all: Vec<_> = default
+                .iter()
+                .filter(|a| !tables.contains_key(&a.name))
+                .cloned()
+                .collect();
+            all.extend(own);
+            all.sort_by(|a, b| a.name.cmp(&b.name));
+            rooms.insert(room.clone(), all.into());
+        }
+        let mut default = default;
+        default.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(Self {
+            default: default.into(),
+            rooms,
+        })
+    }
+
+    /// The achievements of `world`, `main` or a room name.
+    pub fn get(&self, world: &str) -> Arc<[Achievement]> {
+        self.rooms.get(world).unwrap_or(&self.default).clone()
+    }
+}
+
+fn achievement(name: &str, table: &AchievementTable) -> Result<Achievement, Box<dyn Error>> {
+    let valid = |b: u8| b.is_ascii_alphanumeric() || b == b'_' || b == b'-';
+    if name.is_empty() || name.len() > 32 || !name.bytes().all(valid) {
+        return Err(
+            format!("Achievement {name}: names are 1-32 letters, digits, '_' or '-'").into(),
+        );
+    }
+    if !COUNTED.contains(&table.event.as_str()) {
+        return Err(format!("Achievement {name}: can't count {} events", table.event).into());
+    }
+    if table.event == "custom" && table.name.is_none() {
+        return Err(format!("Achievement {name}: custom events need a name").into());
+    }
+    Ok(Achievement {
+        name: name.to_string(),
+        title: table.title.clone().unwrap_or_else(|| name.to_string()),
+        event: table.event.clone(),
+        filter: table.name.clone(),
+        count: table.count.unwrap_or(1).max(1),
+    })
+}
+
+/// The player an event counts for, and how much.
+pub fn counted(event: &JournalEvent) -> Option<(u32, u64)> {
+    match event {
+        JournalEvent::PlayerJoined { id, .. }
+        | JournalEvent::TriggerEntered { id, .. }
+        | JournalEvent::TriggerExited { id, .. }
+        | JournalEvent::Custom { id, .. }
+        | JournalEvent::AchievementEarned { id, .. } => Some((*id, 1)),
+        JournalEvent::BlockSet { by, .. } => by.map(|id| (id, 1)),
+        JournalEvent::Traveled { id, blocks } => Some((*id, *blocks as u64)),
+        _ => None,
+    }
+}
+
+// The name a rule's `name` picks out
+fn named(event: &JournalEvent) -> Option<&str> {
+    match event {
+        JournalEvent::TriggerEntered { trigger, .. }
+        | JournalEvent::TriggerExited { trigger, .. } => Some(trigger),
+        JournalEvent::Custom { name, .. } | JournalEvent::AchievementEarned { name, .. } => {
+            Some(name)
+        }
+        _ => None,
+    }
+}
+
+/// Counts `event`, worth `by`, towards `achievements` in a player's
+/// `properties`, returning the ones it earned.
+pub fn progress<'a>(
+    achievements: &'a [Achievement],
+    event: &JournalEvent,
+    by: u64,
+    properties: &mut HashMap<String, String>,
+) -> Vec<&'a Achievement> {
+    let mut earned = Vec::new();
+    for achievement in achievements {
+        let matches = achievement.event == event.name()
+            && (achievement.filter.is_none() || achievement.filter.as_deref() == named(event));
+        let flag = format!("achievement.{}", achievement.name);
+        if !matches || properties.contains_key(&flag) {
+            continue;
+        }
+        let key = format!("progress.{}", achievement.name);
+        let done = properties.get(&key).and_then(|n| n.parse::<u64>().ok());
+        let done = done.unwrap_or(0).saturating_add(by);
+        if done >= achievement.count {
+            properties.remove(&key);
+            properties.insert(flag, unix_now().to_string());
+            earned.push(achievement);
+        } else {
+            properties.insert(key, done.to_string());
+        }
+    }
+    earned
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_towards_achievements_once() {
+        let rules = AchievementRules::parse(
+            r#"
+            [default.builder]
+            event = "block_set"
+            count = 3
+
+            [room.arena.first_blood]
+            title = "First blood"
+            event = "custom"
+            name = "kill"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(rules.get("main").len(), 1);
+        let arena = rules.get("arena");
+        assert_eq!(arena.len(), 2);
+        assert!(AchievementRules::parse("[default.x]\nevent = \"chat\"").is_err());
+        assert!(AchievementRules::parse("[default.x]\nevent = \"custom\"").is_err());
+
+        let mut properties = HashMap::new();
+        let set = JournalEvent::BlockSet {
+            position: (0, 0, 0),
+            from: 0,
+            to: 1,
+            by: Some(7),
+        };
+        assert_eq!(counted(&set), Some((7, 1)));
+        assert!(progress(&arena, &set, 1, &mut properties).is_empty());
+        assert_eq!(properties["progress.builder"], "1");
+        let earned = progress(&arena, &set, 2, &mut properties);
+        assert_eq!(earned[0].name, "builder");
+        assert!(properties.contains_key("achievement.builder"));
+        assert!(!properties.contains_key("progress.builder"));
+        // Earned once
+        assert!(progress(&arena, &set, 5, &mut properties).is_empty());
+
+        let track = |name: &str| JournalEvent::Custom {
+            id: 7,
+            name: name.into(),
+            fields: Default::default(),
+        };
+        assert!(progress(&arena, &track("heal"), 1, &mut properties).is_empty());
+        let earned = progress(&arena, &track("kill"), 1, &mut properties);
+        assert_eq!(earned[0].title, "First blood");
+    }
+}
@@ -0,0 +1,1602 @@
+use crate::{
+    audit::{AuditEvent, AuditLog, AuditQuery},
+    backup::Backups,
+    claims::{BlockPos, ClaimError, Claims, Owner},
+    clock::ClockEstimate,
+    command,
+    effects::{self, Effect},
+    entities::{Attachment, EntityError, EntityOp, EntityReply, Parent},
+    features::{Features, Toggles},
+    history::{HistoryError, HistoryQuery, HistoryReply},
+    journal::Journal,
+    listeners::Listening,
+    minimap::{DEFAULT_SIZE, Minimap},
+    pathfinding::{PathError, Route},
+    quotas::Quotas,
+    roles::Role,
+    simulation::{SimulationChange, SimulationState},
+    storage::Storage,
+    templates::Templates,
+    tenants::{self, TenantError, Tenants},
+    traffic::Traffic,
+    webhooks::WebhookEvent,
+};
+use axum::{
+    Json, Router,
+    body::{Body, Bytes},
+    extract::{ConnectInfo, OriginalUri, Path, Query, Request, State},
+    http::{StatusCode, Uri, header},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{delete, get, post, put},
+};
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    fmt::{self, Write},
+    net::SocketAddr,
+    pin::Pin,
+    sync::Arc,
+    time::Duration,
+};
+use subtle::ConstantTimeEq;
+use tokio::sync::broadcast;
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+// The longest a reservation holds a place
+const MAX_RESERVATION_SECS: u64 = 3600;
+
+/// What the admin API can ask of a world task. `room` is `None` for the
+/// main world.
+pub trait WorldControl: Send + Sync {
+    /// Closes player `id`'s connection. `false` if there's no such player
+    /// (or room).
+    fn kick(&self, room: Option<&str>, id: u32, reason: String) -> BoxFuture<'_, bool>;
+    /// `None` if there's no such room.
+    fn state(&self, room: Option<&str>) -> BoxFuture<'_, Option<WorldState>>;
+    /// Chat line from the server to everyone in the world or room. `false`
+    /// if there's no such room.
+    fn say(&self, room: Option<&str>, text: String) -> BoxFuture<'_, bool>;
+    /// Queues saves of the main world's players and edited chunks now
+    /// (rooms aren't saved). How many of each, `None` if the world is gone.
+    fn save(&self) -> BoxFuture<'_, Option<(usize, usize)>>;
+    /// Refuses new connections and tells players the server closes within
+    /// `seconds`, moving to `address` if not empty. How many players were
+    /// told, `None` if already draining.
+    fn drain(&self, seconds: u16, address: String) -> BoxFuture<'_, Option<usize>>;
+    /// Hot restart, see `restart.rs`: saves, starts the successor on the
+    /// same socket, then drains for `seconds` handing out resume tokens.
+    /// How many players were told.
+    fn restart(&self, seconds: u16) -> BoxFuture<'_, Result<usize, RestartError>>;
+    /// Tick history, see `history.rs`. `None` if there's no such room.
+    fn history(
+        &self,
+        room: Option<&str>,
+        query: HistoryQuery,
+    ) -> BoxFuture<'_, Option<Result<HistoryReply, HistoryError>>>;
+    /// Player `id`'s frames of the last `secs` as a file, see
+    /// `recorder.rs`. `None` if there's no such player (or room).
+    fn frames(&self, room: Option<&str>, id: u32, secs: Duration)
+    -> BoxFuture<'_, Option<Vec<u8>>>;
+    /// Applies `toggles` to the world's feature flags and tells its players,
+    /// see `features.rs`. The flags now, `None` if there's no such room.
+    fn features(&self, room: Option<&str>, toggles: Toggles) -> BoxFuture<'_, Option<Features>>;
+    /// Pauses, steps or rescales the world's ticks and tells its players,
+    /// see `simulation.rs`. Where it is now, `None` if there's no such
+    /// room.
+    fn simulation(
+        &self,
+        room: Option<&str>,
+        change: SimulationChange,
+    ) -> BoxFuture<'_, Option<SimulationState>>;
+    /// Spawns, removes and hands out authority over non-player entities,
+    /// see `entities.rs`. `None` if there's no such room.
+    fn entities(
+        &self,
+        room: Option<&str>,
+        op: EntityOp,
+    ) -> BoxFuture<'_, Option<Result<EntityReply, EntityError>>>;
+    /// Sends a one-off effect to the players looking at its position, see
+    /// `effects.rs`. How many, `None` if there's no such room.
+    fn effect(&self, room: Option<&str>, effect: Effect) -> BoxFuture<'_, Option<usize>>;
+    /// A walking route between two block positions, see `pathfinding.rs`.
+    /// `None` if there's no such room.
+    fn path(
+        &self,
+        room: Option<&str>,
+        from: BlockPos,
+        to: BlockPos,
+    ) -> BoxFuture<'_, Option<Result<Route, PathError>>>;
+    /// Opens a room made with `template`, see `templates.rs`, named
+    /// `<template>-<n>` without `room`. The room's name, `None` if there's
+    /// no such template.
+    fn create_room(
+        &self,
+        template: &str,
+        room: Option<String>,
+    ) -> BoxFuture<'_, Option<Result<String, String>>>;
+    /// Holds a place in the world for `ttl`, for player `name` alone when
+    /// given, see `reservations.rs`. The ticket, `None` if there's no such
+    /// room.
+    fn reserve(
+        &self,
+        room: Option<&str>,
+        name: Option<String>,
+        ttl: Duration,
+    ) -> BoxFuture<'_, Option<Result<String, String>>>;
+    /// Moves a player to another layer of the world, see `relevance.rs`.
+    /// `false` if there's no such player, `None` if there's no such room.
+    fn layer(&self, room: Option<&str>, id: u32, layer: u32) -> BoxFuture<'_, Option<bool>>;
+    /// The minimap around block column `center`, see `minimap.rs`. `None`
+    /// if there's no such room.
+    fn minimap(
+        &self,
+        room: Option<&str>,
+        center: (i32, i32),
+        size: u8,
+        scale: u8,
+    ) -> BoxFuture<'_, Option<Minimap>>;
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum RestartError {
+    Draining,
+    /// Couldn't start, or it exited or hung before serving.
+    Successor(String),
+}
+
+impl fmt::Display for RestartError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RestartError::Draining => write!(f, "Already draining"),
+            RestartError::Successor(e) => write!(f, "Successor failed: {e}"),
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct WorldState {
+    pub tick: u64,
+    pub loaded_chunks: usize,
+    pub players: Vec<PlayerState>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct PlayerState {
+    pub id: u32,
+    pub name: Option<String>,
+    pub position: (i32, i32, i32),
+    /// Yaw and pitch in degrees, from `SetTransform`.
+    pub rotation: (f32, f32),
+    /// Blocks per second, when the last `SetTransform` had one.
+    pub velocity: Option<(f32, f32, f32)>,
+    /// Once the client sent a `TimeSync`, see `clock.rs`.
+    pub clock: Option<ClockEstimate>,
+    /// Center chunk and radius.
+    pub interest: Option<((i32, i32, i32), u16)>,
+    /// Moves and edits dropped by the input delay buffer, see `input.rs`.
+    pub dropped_inputs: u64,
+    /// Entity updates replaced by newer ones while waiting, see `lanes.rs`.
+    pub coalesced: u64,
+    /// Not playing for a while, see `afk.rs`.
+    pub afk: bool,
+    /// Instance in the world, see `relevance.rs`.
+    pub layer: u32,
+}
+
+/// Admin HTTP API, mounted under `/admin` when an admin token is configured.
+/// Every route requires `Authorization: Bearer <token>`, the moderator token
+/// only reaches `/world`, `/say` and kicks. Calls and token failures go to
+/// the audit log, when there's one.
+#[derive(Clone)]
+pub struct AdminState {
+    pub token: Arc<str>,
+    pub moderator_token: Option<Arc<str>>,
+    /// Role assignments, see `roles.rs`.
+    pub storage: Option<Arc<dyn Storage>>,
+    pub audit: Option<Arc<AuditLog>>,
+    pub backups: Option<Arc<Backups>>,
+    pub claims: Arc<Claims>,
+    pub traffic: Arc<Traffic>,
+    /// Usage and quotas hit per world, see `quotas.rs`.
+    pub quotas: Arc<Quotas>,
+    /// API keys, which also reach the moderation routes for their
+    /// tenant's rooms, see `tenants.rs`.
+    pub tenants: Arc<Tenants>,
+    /// Room templates for `POST /admin/rooms`, see `templates.rs`.
+    pub templates: Arc<Templates>,
+    /// Where the server listens, as bound, see `listeners.rs`.
+    pub listeners: Arc<Vec<Listening>>,
+    pub world: Arc<dyn WorldControl>,
+    /// Joins, leaves, room changes and server stopping, the same events
+    /// webhooks get.
+    pub events: broadcast::Sender<WebhookEvent>,
+    /// Everything that changes in worlds, see `journal.rs`.
+    pub journal: Arc<Journal>,
+}
+
+pub fn router(state: AdminState) -> Router {
+    let moderation = Router::new()
+        .route("/players/{id}/kick", post(kick))
+        .route("/reservations", post(reserve))
+        .route("/say", post(say))
+        .route("/world", get(world));
+    Router::new()
+        .route("/audit", get(audit))
+        .route("/backup", post(backup))
+        .route("/claims", get(list_claims).post(create_claim))
+        .route("/claims/{id}", delete(remove_claim))
+        .route("/claims/{id}/transfer", post(transfer_claim))
+        .route("/drain", post(drain))
+        .route("/entities", get(list_entities).post(spawn_entity))
+        .route("/entities/{id}", delete(remove_entity))
+        .route(
+            "/entities/{id}/authority",
+            put(grant_authority).delete(revoke_authority),
+        )
+        .route(
+            "/entities/{id}/parent",
+            put(attach_entity).delete(detach_entity),
+        )
+        .route("/entities/{id}/goal", put(set_goal).delete(clear_goal))
+        .route(
+            "/entities/{id}/always",
+            put(set_always).delete(clear_always),
+        )
+        .route(
+            "/entities/{id}/relevant",
+            put(set_relevant).delete(clear_relevant),
+        )
+        .route("/entities/{id}/layer", put(set_entity_layer))
+        .route("/effect", post(effect))
+        .route("/events", get(events))
+        .route("/features", get(get_features).put(set_features))
+        .route("/journal", get(journal))
+        .route("/simulation", get(get_simulation).put(set_simulation))
+        .route("/path", get(find_path))
+        .route("/players/{id}/frames", get(frames))
+        .route("/players/{id}/layer", put(set_player_layer))
+        .route("/groups", get(list_groups))
+        .route("/groups/{name}", put(set_group))
+        .route("/history", get(history_at))
+        .route("/history/diff", get(history_diff))
+        .route("/history/rewind", post(history_rewind))
+        .route("/listeners", get(list_listeners))
+        .route("/metrics", get(metrics))
+        .route("/minimap", get(minimap))
+        .route("/restart", post(restart))
+        .route("/roles/{name}", get(get_role).put(set_role))
+        .route("/rooms", post(create_room))
+        .route("/save", post(save))
+        .route("/tenants", get(list_tenants))
+        .route("/tenants/{name}", delete(remove_tenant))
+        .route("/tenants/{name}/keys", post(create_key))
+        .route("/tenants/{name}/keys/{id}", delete(revoke_key))
+        .route("/templates", get(list_templates))
+        .route("/traffic", get(traffic))
+        .route_layer(middleware::from_fn(require_admin))
+        .merge(moderation)
+        .route_layer(middleware::from_fn_with_state(state.clone(), require_token))
+        .with_state(state)
+}
+
+// In constant time, a token guessed byte by byte doesn't answer faster
+pub(crate) fn matches(token: &str, expected: &str) -> bool {
+    token.as_bytes().ct_eq(expected.as_bytes()).into()
+}
+
+/// The tenant whose API key made a request, see `tenants.rs`.
+#[derive(Clone)]
+pub(crate) struct Tenant(pub String);
+
+// Also in front of the gRPC service, see `grpc.rs`
+pub(crate) async fn require_token(
+    State(state): State<AdminState>,
+    mut req: Request,
+    next: Next,
+) -> Response {
+    let token = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+    let role = match token {
+        Some(token) if matches(token, &state.token) => Some(Role::Admin),
+        Some(token)
+            if state
+                .moderator_token
+                .as_ref()
+                .is_some_and(|t| matches(token, t)) =>
+        {
+            Some(Role::Moderator)
+        }
+        // Moderating its own rooms only
+        Some(token) => match state.tenants.check(token) {
+            Some(tenant) => {
+                let Some(uri) = scope_room(req.uri(), &tenant) else {
+                    return (StatusCode::BAD_REQUEST, "Invalid room").into_response();
+                };
+                *req.uri_mut() = uri;
+                req.extensions_mut().insert(Tenant(tenant));
+                Some(Role::Moderator)
+            }
+            None => None,
+        },
+        None => None,
+    };
+    let authorized = role.is_some();
+    if let Some(role) = role {
+        req.extensions_mut().insert(role);
+    }
+
+    let Some(audit) = state.audit else {
+        if !authorized {
+            return StatusCode::UNAUTHORIZED.into_response();
+        }
+        return next.run(req).await;
+    };
+
+    let method = req.method().to_string();
+    // Nested routes see their path without `/admin`
+    let path = req
+        .extensions()
+        .get::<OriginalUri>()
+        .map_or(req.uri(), |uri| &uri.0)
+        .to_string();
+    let remote = req
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|info| info.0.to_string());
+
+    if !authorized {
+        audit.record(AuditEvent::AuthFailure {
+            method,
+            path,
+            remote,
+        });
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+
+    let response = next.run(req).await;
+    audit.record(AuditEvent::AdminCall {
+        method,
+        path,
+        status: response.status().as_u16(),
+        remote,
+    });
+    response
+}
+
+// A tenant's `?room=` names one of its rooms, its lobby when left out.
+// `None` for names rooms can't have
+fn scope_room(uri: &Uri, tenant: &str) -> Option<Uri> {
+    let params = Params::try_from_uri(uri).ok()?;
+    if let Some(room) = params.get("room").filter(|room| !room.is_empty())
+        && !command::valid_room(room)
+    {
+        return None;
+    }
+    let query = uri.query().unwrap_or("");
+    let room = query
+        .split('&')
+        .find_map(|pair| pair.strip_prefix("room="))
+        .filter(|room| !room.is_empty())
+        .unwrap_or(tenants::LOBBY);
+    let mut pairs: Vec<_> = query
+        .split('&')
+        .filter(|pair| !pair.is_empty() && !pair.starts_with("room="))
+        .collect();
+    let scoped = format!("room={tenant}%2F{room}");
+    pairs.push(&scoped);
+    let path = format!("{}?{}", uri.path(), pairs.join("&"));
+    Some(path.parse().unwrap_or_else(|_| uri.clone()))
+}
+
+// Everything but the moderation routes, after `require_token`
+async fn require_admin(req: Request, next: Next) -> Response {
+    if req.extensions().get::<Role>() != Some(&Role::Admin) {
+        return (StatusCode::FORBIDDEN, "Needs the admin token").into_response();
+    }
+    next.run(req).await
+}
+
+// GET /admin/audit?event=<type>&since=<unix secs>&limit=<n>: the newest
+// matching entries (default 100), oldest first, as JSON lines
+async fn audit(State(state): State<AdminState>, Query(params): Params) -> Response {
+    let Some(audit) = &state.audit else {
+        return (StatusCode::NOT_FOUND, "Audit log is disabled").into_response();
+    };
+
+    let since = match params.get("since").map(|s| s.parse()) {
+        Some(Ok(since)) => Some(since),
+        Some(Err(_)) => return (StatusCode::BAD_REQUEST, "Invalid since").into_response(),
+        None => None,
+    };
+    let limit = match params.get("limit").map(|l| l.parse()) {
+        Some(Ok(limit)) => limit,
+        Some(Err(_)) => return (StatusCode::BAD_REQUEST, "Invalid limit").into_response(),
+        None => 100,
+    };
+    let query = AuditQuery {
+        event: params.get("event").cloned(),
+        since,
+        limit,
+    };
+
+    match audit.query(&query) {
+        Ok(entries) => {
+            let mut out = String::new();
+            for entry in entries {
+                writeln!(out, "{entry}").unwrap();
+            }
+            ([(header::CONTENT_TYPE, "application/x-ndjson")], out).into_response()
+        }
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+// POST /admin/backup: take a backup now, responds with its directory
+async fn backup(State(state): State<AdminState>) -> Response {
+    let Some(backups) = &state.backups else {
+        return (StatusCode::NOT_FOUND, "Backups are disabled").into_response();
+    };
+
+    match backups.run().await {
+        Ok(dir) => dir.display().to_string().into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+type Params = Query<HashMap<String, String>>;
+
+// GET /admin/claims: one claim per line, `<id> <owner> <x,y,z> <x,y,z>`
+async fn list_claims(State(state): State<AdminState>) -> String {
+    let mut out = String::new();
+    for claim in state.claims.list() {
+        let ((x1, y1, z1), (x2, y2, z2)) = (claim.min, claim.max);
+        writeln!(
+            out,
+            "{} {} {x1},{y1},{z1} {x2},{y2},{z2}",
+            claim.id, claim.owner
+        )
+        .unwrap();
+    }
+    out
+}
+
+// POST /admin/claims?owner=player:<name>&min=x,y,z&max=x,y,z: responds with
+// the new claim id. No size limit, unlike player claims.
+async fn create_claim(State(state): State<AdminState>, Query(params): Params) -> Response {
+    let parsed = (|| {
+        let owner = params.get("owner")?.parse::<Owner>().ok()?;
+        Some((
+            owner,
+            parse_pos(params.get("min")?)?,
+            parse_pos(params.get("max")?)?,
+        ))
+    })();
+    let Some((owner, min, max)) = parsed else {
+        return (StatusCode::BAD_REQUEST, "Expected owner, min and max").into_response();
+    };
+
+    match state.claims.create(owner, min, max) {
+        Ok(id) => id.to_string().into_response(),
+        Err(e) => claim_error(e),
+    }
+}
+
+// POST /admin/claims/{id}/transfer?owner=group:<name>
+async fn transfer_claim(
+    State(state): State<AdminState>,
+    Path(id): Path<u32>,
+    Query(params): Params,
+) -> Response {
+    let Some(owner) = params.get("owner").and_then(|o| o.parse::<Owner>().ok()) else {
+        return (StatusCode::BAD_REQUEST, ClaimError::BadOwner.to_string()).into_response();
+    };
+
+    match state.claims.transfer(id, owner) {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => claim_error(e),
+    }
+}
+
+// DELETE /admin/claims/{id}
+async fn remove_claim(State(state): State<AdminState>, Path(id): Path<u32>) -> Response {
+    match state.claims.remove(id) {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => claim_error(e),
+    }
+}
+
+// GET /admin/groups: one group per line, `<name> <member,member,...>`
+async fn list_groups(State(state): State<AdminState>) -> String {
+    let mut out = String::new();
+    for (name, members) in state.claims.groups() {
+        let members: Vec<_> = members.into_iter().collect();
+        writeln!(out, "{name} {}", members.join(",")).unwrap();
+    }
+    out
+}
+
+// PUT /admin/groups/{name}?members=a,b,c: replaces the members, an empty
+// list deletes the group
+async fn set_group(
+    State(state): State<AdminState>,
+    Path(name): Path<String>,
+    Query(params): Params,
+) -> Response {
+    if format!("group:{name}").parse::<Owner>().is_err() {
+        return (StatusCode::BAD_REQUEST, "Invalid group name").into_response();
+    }
+
+    let members = params
+        .get("members")
+        .map(|m| {
+            m.split(',')
+                .filter(|m| !m.is_empty())
+                .map(String::from)
+                .collect()
+        })
+        .unwrap_or_default();
+    state.claims.set_group(&name, members);
+    StatusCode::NO_CONTENT.into_response()
+}
+
+// GET /admin/roles/{name}: the player's role, see `roles.rs`
+async fn get_role(State(state): State<AdminState>, Path(name): Path<String>) -> Response {
+    let Some(storage) = &state.storage else {
+        return (StatusCode::NOT_FOUND, "Storage is disabled").into_response();
+    };
+    match storage.load_role(&name).await {
+        Ok(role) => role.to_string().into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+// PUT /admin/roles/{name}?role=player|moderator|admin: assigns the role,
+// `player` clears it
+async fn set_role(
+    State(state): State<AdminState>,
+    Path(name): Path<String>,
+    Query(params): Params,
+) -> Response {
+    let Some(storage) = &state.storage else {
+        return (StatusCode::NOT_FOUND, "Storage is disabled").into_response();
+    };
+    let role = match params.get("role").map(|r| r.parse::<Role>()) {
+        Some(Ok(role)) => role,
+        Some(Err(e)) => return (StatusCode::BAD_REQUEST, e).into_response(),
+        None => return (StatusCode::BAD_REQUEST, "Missing role").into_response(),
+    };
+    if let Err(e) = storage.save_role(&name, role).await {
+        return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response();
+    }
+
+    if let Some(audit) = &state.audit {
+        audit.record(AuditEvent::Moderation {
+            by: None,
+            action: "role".to_string(),
+            target: name,
+            detail: role.to_string(),
+        });
+    }
+    StatusCode::NO_CONTENT.into_response()
+}
+
+// GET /admin/metrics: message and byte counters by direction and type, in
+// GET /admin/tenants: key ids and creation times by tenant, as JSON
+async fn list_tenants(State(state): State<AdminState>) -> Response {
+    Json(state.tenants.list()).into_response()
+}
+
+// POST /admin/tenants/{name}/keys: a new API key, made with the tenant if
+// it's new, as `{"id": ..., "key": ...}`. The key isn't shown again.
+async fn create_key(State(state): State<AdminState>, Path(name): Path<String>) -> Response {
+    match state.tenants.create_key(&name) {
+        Ok((id, key)) => {
+            let created = serde_json::json!({ "id": id, "key": key });
+            (StatusCode::CREATED, Json(created)).into_response()
+        }
+        Err(e) => tenant_error(e),
+    }
+}
+
+// DELETE /admin/tenants/{name}/keys/{id}
+async fn revoke_key(
+    State(state): State<AdminState>,
+    Path((name, id)): Path<(String, String)>,
+) -> Response {
+    match state.tenants.revoke(&name, &id) {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => tenant_error(e),
+    }
+}
+
+// DELETE /admin/tenants/{name}: the tenant and its keys, its rooms stay
+// until they empty
+async fn remove_tenant(State(state): State<AdminState>, Path(name): Path<String>) -> Response {
+    match state.tenants.remove(&name) {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => tenant_error(e),
+    }
+}
+
+fn tenant_error(e: TenantError) -> Response {
+    let status = match e {
+        TenantError::InvalidName => StatusCode::BAD_REQUEST,
+        TenantError::NoTenant | TenantError::NoKey => StatusCode::NOT_FOUND,
+        TenantError::Io(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    (status, e.to_string()).into_response()
+}
+
+// Prometheus text format
+async fn metrics(State(state): State<AdminState>) -> String {
+    state.traffic.prometheus() + &state.quotas.prometheus()
+}
+
+// GET /admin/traffic: connected players, then one line per message type,
+// `  <in|out> <type> <messages> <bytes>`
+async fn traffic(State(state): State<AdminState>) -> String {
+    let mut out = String::new();
+    for player in state.traffic.players() {
+        writeln!(out, "{}", player.label).unwrap();
+        for ((dir, kind), counter) in player.counters() {
+            let dir = dir.as_str();
+            writeln!(out, "  {dir} {kind} {} {}", counter.msgs, counter.bytes).unwrap();
+        }
+    }
+    out
+}
+
+// GET /admin/events: live events as JSON lines until the client hangs up
+// or the server stops (`server_stopping` is the last line). Slow readers
+// skip what they missed, with a `{"event":"lagged"}` line.
+async fn events(State(state): State<AdminState>) -> Response {
+    let rx = state.events.subscribe();
+    let lines = futures_util::stream::unfold(Some(rx), |rx| async move {
+        let mut rx = rx?;
+        let line = match rx.recv().await {
+            // Ends the stream, or it would hold up graceful shutdown
+            Ok(event @ WebhookEvent::ServerStopping) => {
+                let line = serde_json::to_string(&event).unwrap() + "\n";
+                return Some((Ok(line), None));
+            }
+            Ok(event) => serde_json::to_string(&event).unwrap(),
+            Err(broadcast::error::RecvError::Lagged(n)) => {
+                format!(r#"{{"event":"lagged","skipped":{n}}}"#)
+            }
+            Err(broadcast::error::RecvError::Closed) => return None,
+        };
+        Some((Ok::<_, std::convert::Infallible>(line + "\n"), Some(rx)))
+    });
+    (
+        [(header::CONTENT_TYPE, "application/x-ndjson")],
+        Body::from_stream(lines),
+    )
+        .into_response()
+}
+
+// GET /admin/journal: the world event journal as JSON lines, from now
+// until the client hangs up or the server stops. Slow readers skip what
+// they missed, with a `{"event":"lagged"}` line.
+async fn journal(State(state): State<AdminState>) -> Response {
+    let rx = (state.journal.subscribe(), state.events.subscribe());
+    let lines = futures_util::stream::unfold(rx, |(mut journal, mut events)| async move {
+        let line = loop {
+            tokio::select! {
+                entry = journal.recv() => break match entry {
+                    Ok(entry) => serde_json::to_string(&*entry).unwrap(),
+                    Err(broadcast::error::RecvError::Lagged(n)) => {
+                        format!(r#"{{"event":"lagged","skipped":{n}}}"#)
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                },
+                // Ends with the server, like `/admin/events`
+                event = events.recv() => match event {
+                    Ok(WebhookEvent::ServerStopping) | Err(broadcast::error::RecvError::Closed) => {
+                        return None;
+                    }
+                    _ => {}
+                },
+            }
+        };
+        Some((
+            Ok::<_, std::convert::Infallible>(line + "\n"),
+            (journal, events),
+        ))
+    });
+    (
+        [(header::CONTENT_TYPE, "application/x-ndjson")],
+        Body::from_stream(lines),
+    )
+        .into_response()
+}
+
+// POST /admin/players/{id}/kick?room=<name>&reason=<text>: closes the
+// player's connection with the reason. The main world without ?room=.
+async fn kick(
+    State(state): State<AdminState>,
+    Path(id): Path<u32>,
+    Query(params): Params,
+) -> Response {
+    let room = params.get("room").map(String::as_str);
+    let reason = params.get("reason").cloned().unwrap_or_default();
+    if !state.world.kick(room, id, reason.clone()).await {
+        return (StatusCode::NOT_FOUND, format!("No player {id}")).into_response();
+    }
+
+    if let Some(audit) = &state.audit {
+        audit.record(AuditEvent::Kick {
+            room: room.unwrap_or("main").to_string(),
+            id,
+            reason,
+        });
+    }
+    StatusCode::NO_CONTENT.into_response()
+}
+
+// POST /admin/say?room=<name>&text=<text>: chat line from `server`
+async fn say(State(state): State<AdminState>, Query(params): Params) -> Response {
+    let Some(text) = params.get("text").filter(|t| !t.is_empty()) else {
+        return (StatusCode::BAD_REQUEST, "Missing text").into_response();
+    };
+    let room = params.get("room").map(String::as_str);
+    match state.world.say(room, text.clone()).await {
+        true => StatusCode::NO_CONTENT.into_response(),
+        false => (StatusCode::NOT_FOUND, "No such room").into_response(),
+    }
+}
+
+// POST /admin/rooms?template=<name>&room=<name>: a room from a template,
+// replies its name
+async fn create_room(State(state): State<AdminState>, Query(params): Params) -> Response {
+    let Some(template) = params.get("template") else {
+        return (StatusCode::BAD_REQUEST, "Missing template").into_response();
+    };
+    let room = params.get("room").cloned();
+    if room
+        .as_deref()
+        .is_some_and(|room| !command::valid_room(room))
+    {
+        return (StatusCode::BAD_REQUEST, "Invalid room name").into_response();
+    }
+    match state.world.create_room(template, room).await {
+        Some(Ok(room)) => (StatusCode::CREATED, room).into_response(),
+        Some(Err(e)) => (StatusCode::CONFLICT, e).into_response(),
+        None => (StatusCode::NOT_FOUND, "No such template").into_response(),
+    }
+}
+
+// POST /admin/reservations?room=<name>&name=<player>&seconds=<n>: holds a
+// place in the room for `seconds` (default 30), see reservations.rs. The
+// ticket, for the player's `?ticket=`.
+async fn reserve(State(state): State<AdminState>, Query(params): Params) -> Response {
+    let seconds = match params.get("seconds").map(|s| s.parse()) {
+        Some(Ok(seconds @ 1..=MAX_RESERVATION_SECS)) => seconds,
+        Some(_) => return (StatusCode::BAD_REQUEST, "Invalid seconds").into_response(),
+        None => 30,
+    };
+    let name = params.get("name").cloned();
+    if name.as_ref().is_some_and(|n| n.is_empty() || n.len() > 32) {
+        return (StatusCode::BAD_REQUEST, "Invalid name").into_response();
+    }
+    let room = params.get("room").map(String::as_str);
+    let ttl = Duration::from_secs(seconds);
+    match state.world.reserve(room, name, ttl).await {
+        Some(Ok(ticket)) => (StatusCode::CREATED, ticket).into_response(),
+        Some(Err(e)) => (StatusCode::CONFLICT, e).into_response(),
+        None => (StatusCode::NOT_FOUND, "No such room").into_response(),
+    }
+}
+
+// GET /admin/templates: template names, as JSON
+async fn list_templates(State(state): State<AdminState>) -> Response {
+    Json(state.templates.names().collect::<Vec<_>>()).into_response()
+}
+
+// GET /admin/listeners: addresses as bound, with their family (dual-stack,
+// ipv6, ipv4, unix), TLS and routes
+async fn list_listeners(State(state): State<AdminState>) -> Response {
+    Json(&*state.listeners).into_response()
+}
+
+// POST /admin/drain?seconds=<n>&address=<ws url>: drain mode, see drain.rs.
+// Shuts down once empty or after `seconds` (default 60).
+async fn drain(State(state): State<AdminState>, Query(params): Params) -> Response {
+    let seconds = match params.get("seconds").map(|s| s.parse()) {
+        Some(Ok(seconds)) => seconds,
+        Some(Err(_)) => return (StatusCode::BAD_REQUEST, "Invalid seconds").into_response(),
+        None => 60,
+    };
+    let address = params.get("address").cloned().unwrap_or_default();
+    match state.world.drain(seconds, address).await {
+        Some(players) => {
+            (StatusCode::ACCEPTED, format!("Draining {players} players")).into_response()
+        }
+        None => (StatusCode::CONFLICT, "Already draining").into_response(),
+    }
+}
+
+// POST /admin/restart?seconds=<n>: hot restart, see restart.rs. Responds
+// once the successor is serving, the old server drains for `seconds`
+// (default 60).
+async fn restart(State(state): State<AdminState>, Query(params): Params) -> Response {
+    let seconds = match params.get("seconds").map(|s| s.parse()) {
+        Some(Ok(seconds)) => seconds,
+        Some(Err(_)) => return (StatusCode::BAD_REQUEST, "Invalid seconds").into_response(),
+        None => 60,
+    };
+    match state.world.restart(seconds).await {
+        Ok(players) => (
+            StatusCode::ACCEPTED,
+            format!("Restarted, draining {players} players"),
+        )
+            .into_response(),
+        Err(e @ RestartError::Draining) => (StatusCode::CONFLICT, e.to_string()).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+// POST /admin/save: saves players and edited chunks now, responds with
+// `<players> <chunks>` queued
+async fn save(State(state): State<AdminState>) -> Response {
+    match state.world.save().await {
+        Some((players, chunks)) => format!("{players} {chunks}").into_response(),
+        None => (StatusCode::SERVICE_UNAVAILABLE, "World is gone").into_response(),
+    }
+}
+
+// GET /admin/players/{id}/frames?room=<name>&secs=<n>: the frames sent to
+// the player over the last `secs` (default everything kept) as a file to
+// download, see recorder.rs
+async fn frames(
+    State(state): State<AdminState>,
+    Path(id): Path<u32>,
+    Query(params): Params,
+) -> Response {
+    let secs = match params.get("secs").map(|s| s.parse()) {
+        Some(Ok(secs)) => Duration::from_secs(secs),
+        Some(Err(_)) => return (StatusCode::BAD_REQUEST, "Invalid secs").into_response(),
+        None => Duration::MAX,
+    };
+    let room = params.get("room").map(String::as_str);
+    match state.world.frames(room, id, secs).await {
+        Some(file) => {
+            let disposition = format!("attachment; filename=\"frames-{id}.tbx\"");
+            (
+                [
+                    (header::CONTENT_TYPE, "application/octet-stream".to_string()),
+                    (header::CONTENT_DISPOSITION, disposition),
+                ],
+                file,
+            )
+                .into_response()
+        }
+        None => (StatusCode::NOT_FOUND, format!("No player {id}")).into_response(),
+    }
+}
+
+// GET /admin/world?room=<name>: tick, loaded chunks and players, as JSON
+async fn world(State(state): State<AdminState>, Query(params): Params) -> Response {
+    let room = params.get("room").map(String::as_str);
+    match state.world.state(room).await {
+        Some(world) => Json(world).into_response(),
+        None => (StatusCode::NOT_FOUND, "No such room").into_response(),
+    }
+}
+
+// GET /admin/history?room=<name>&tick=<n>: players and the blocks edited in
+// the history window at tick `n`, as JSON
+async fn history_at(State(state): State<AdminState>, Query(params): Params) -> Response {
+    let Some(tick) = params.get("tick").and_then(|t| t.parse().ok()) else {
+        return (StatusCode::BAD_REQUEST, "Invalid tick").into_response();
+    };
+    let room = params.get("room").map(String::as_str);
+    history_response(state.world.history(room, HistoryQuery::At(tick)).await)
+}
+
+// GET /admin/history/diff?room=<name>&from=<n>&to=<n>: players that joined,
+// left or moved and blocks that changed between two ticks
+async fn history_diff(State(state): State<AdminState>, Query(params): Params) -> Response {
+    let tick = |key: &str| params.get(key).and_then(|t| t.parse().ok());
+    let (Some(from), Some(to)) = (tick("from"), tick("to")) else {
+        return (StatusCode::BAD_REQUEST, "Invalid from or to").into_response();
+    };
+    let room = params.get("room").map(String::as_str);
+    history_response(
+        state
+            .world
+            .history(room, HistoryQuery::Diff(from, to))
+            .await,
+    )
+}
+
+// GET /admin/features?room=<name>: chat, build and pvp flags, as JSON
+async fn get_features(State(state): State<AdminState>, Query(params): Params) -> Response {
+    let room = params.get("room").map(String::as_str);
+    match state.world.features(room, Toggles::default()).await {
+        Some(features) => Json(features).into_response(),
+        None => (StatusCode::NOT_FOUND, "No such room").into_response(),
+    }
+}
+
+// PUT /admin/features?room=<name> with e.g. `{"chat": false}`: toggles
+// flags until the room closes or the server restarts, replies with all of
+// them
+async fn set_features(
+    State(state): State<AdminState>,
+    Query(params): Params,
+    Json(toggles): Json<Toggles>,
+) -> Response {
+    let room = params.get("room").map(String::as_str);
+    let Some(features) = state.world.features(room, toggles).await else {
+        return (StatusCode::NOT_FOUND, "No such room").into_response();
+    };
+    if let Some(audit) = &state.audit {
+        audit.record(AuditEvent::Moderation {
+            by: None,
+            action: "features".to_string(),
+            target: room.unwrap_or("main").to_string(),
+            detail: serde_json::to_string(&features).unwrap(),
+        });
+    }
+    Json(features).into_response()
+}
+
+// GET /admin/simulation?room=<name>: paused, time scale, steps left and
+// tick, as JSON
+async fn get_simulation(State(state): State<AdminState>, Query(params): Params) -> Response {
+    let room = params.get("room").map(String::as_str);
+    match state
+        .world
+        .simulation(room, SimulationChange::default())
+        .await
+    {
+        Some(simulation) => Json(simulation).into_response(),
+        None => (StatusCode::NOT_FOUND, "No such room").into_response(),
+    }
+}
+
+// PUT /admin/simulation?room=<name> with e.g. `{"paused": true}`,
+// `{"scale": 25}` or `{"step": 3}`: pauses, rescales or steps the world's
+// ticks, replies where it is now
+async fn set_simulation(
+    State(state): State<AdminState>,
+    Query(params): Params,
+    Json(change): Json<SimulationChange>,
+) -> Response {
+    if let Err(e) = change.check() {
+        return (StatusCode::BAD_REQUEST, e).into_response();
+    }
+    let room = params.get("room").map(String::as_str);
+    let Some(simulation) = state.world.simulation(room, change).await else {
+        return (StatusCode::NOT_FOUND, "No such room").into_response();
+    };
+    if let Some(audit) = &state.audit {
+        audit.record(AuditEvent::Moderation {
+            by: None,
+            action: "simulation".to_string(),
+            target: room.unwrap_or("main").to_string(),
+            detail: serde_json::to_string(&change).unwrap(),
+        });
+    }
+    Json(simulation).into_response()
+}
+
+// POST /admin/effect?room=<name>&effect=<id>&pos=x,y,z[&layer=<n>] with the
+// params as the body: replies how many players it reached
+async fn effect(State(state): State<AdminState>, Query(params): Params, body: Bytes) -> Response {
+    let Some(id) = params.get("effect").and_then(|e| e.parse().ok()) else {
+        return (StatusCode::BAD_REQUEST, "Invalid effect").into_response();
+    };
+    let Some(position) = params.get("pos").and_then(|p| parse_pos(p)) else {
+        return (StatusCode::BAD_REQUEST, "Invalid pos").into_response();
+    };
+    let Ok(layer) = params.get("layer").map_or(Ok(0), |l| l.parse()) else {
+        return (StatusCode::BAD_REQUEST, "Invalid layer").into_response();
+    };
+    if body.len() > effects::MAX_PARAMS_LEN {
+        let e = format!("Params are at most {} bytes", effects::MAX_PARAMS_LEN);
+        return (StatusCode::PAYLOAD_TOO_LARGE, e).into_response();
+    }
+    let effect = Effect {
+        id,
+        position,
+        params: body.to_vec(),
+        layer,
+    };
+    let room = params.get("room").map(String::as_str);
+    match state.world.effect(room, effect).await {
+        Some(reached) => reached.to_string().into_response(),
+        None => (StatusCode::NOT_FOUND, "No such room").into_response(),
+    }
+}
+
+// GET /admin/path?room=<name>&from=x,y,z&to=x,y,z: block positions to walk
+// through, both ends included, as JSON
+async fn find_path(State(state): State<AdminState>, Query(params): Params) -> Response {
+    let (Some(from), Some(to)) = (
+        params.get("from").and_then(|p| parse_pos(p)),
+        params.get("to").and_then(|p| parse_pos(p)),
+    ) else {
+        return (StatusCode::BAD_REQUEST, "Invalid from or to").into_response();
+    };
+    let room = params.get("room").map(String::as_str);
+    match state.world.path(room, from, to).await {
+        None => (StatusCode::NOT_FOUND, "No such room").into_response(),
+        Some(Ok(route)) => Json(route.as_slice()).into_response(),
+        Some(Err(e @ PathError::NoRoute)) => (StatusCode::NOT_FOUND, e.to_string()).into_response(),
+        Some(Err(e)) => (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+// GET /admin/minimap?room=<name>&x=<n>&z=<n>[&size=<n>][&scale=<n>]
+// [&format=json]: the minimap around a block column, a PNG unless asked
+// for the grid as JSON
+async fn minimap(State(state): State<AdminState>, Query(params): Params) -> Response {
+    let coord = |key: &str| params.get(key).and_then(|n| n.parse().ok());
+    let (Some(x), Some(z)) = (coord("x"), coord("z")) else {
+        return (StatusCode::BAD_REQUEST, "Invalid x or z").into_response();
+    };
+    let Ok(size) = params.get("size").map_or(Ok(DEFAULT_SIZE), |s| s.parse()) else {
+        return (StatusCode::BAD_REQUEST, "Invalid size").into_response();
+    };
+    let Ok(scale) = params.get("scale").map_or(Ok(1), |s| s.parse()) else {
+        return (StatusCode::BAD_REQUEST, "Invalid scale").into_response();
+    };
+    let room = params.get("room").map(String::as_str);
+    let Some(map) = state.world.minimap(room, (x, z), size, scale).await else {
+        return (StatusCode::NOT_FOUND, "No such room").into_response();
+    };
+    match params.get("format").map(String::as_str) {
+        Some("json") => Json(map).into_response(),
+        _ => ([(header::CONTENT_TYPE, "image/png")], map.png()).into_response(),
+    }
+}
+
+// GET /admin/entities?room=<name>: the world's entities and who has
+// authority over them, as JSON
+async fn list_entities(State(state): State<AdminState>, Query(params): Params) -> Response {
+    let room = params.get("room").map(String::as_str);
+    entity_response(state.world.entities(room, EntityOp::List).await)
+}
+
+// POST /admin/entities?room=<name>&pos=x,y,z[&static=true][&layer=<n>]:
+// spawns one, server authority, replies with it
+async fn spawn_entity(State(state): State<AdminState>, Query(params): Params) -> Response {
+    let Some(pos) = params.get("pos").and_then(|p| parse_pos(p)) else {
+        return (StatusCode::BAD_REQUEST, "Invalid pos").into_response();
+    };
+    let Ok(fixed) = params.get("static").map_or(Ok(false), |s| s.parse()) else {
+        return (StatusCode::BAD_REQUEST, "Invalid static").into_response();
+    };
+    let Ok(layer) = params.get("layer").map_or(Ok(0), |l| l.parse()) else {
+        return (StatusCode::BAD_REQUEST, "Invalid layer").into_response();
+    };
+    let room = params.get("room").map(String::as_str);
+    let op = EntityOp::Spawn(pos, fixed, layer);
+    entity_response(state.world.entities(room, op).await)
+}
+
+// DELETE /admin/entities/<id>?room=<name>
+async fn remove_entity(
+    State(state): State<AdminState>,
+    Path(id): Path<u32>,
+    Query(params): Params,
+) -> Response {
+    let room = params.get("room").map(String::as_str);
+    entity_response(state.world.entities(room, EntityOp::Remove(id)).await)
+}
+
+// PUT /admin/entities/<id>/authority?room=<name>&player=<id>: that player
+// moves it from now on, taking it from whoever did
+async fn grant_authority(
+    State(state): State<AdminState>,
+    Path(id): Path<u32>,
+    Query(params): Params,
+) -> Response {
+    let Some(player) = params.get("player").and_then(|p| p.parse().ok()) else {
+        return (StatusCode::BAD_REQUEST, "Invalid player").into_response();
+    };
+    let room = params.get("room").map(String::as_str);
+    let op = EntityOp::Grant(id, Some(player));
+    entity_response(state.world.entities(room, op).await)
+}
+
+// DELETE /admin/entities/<id>/authority?room=<name>: back to the server
+async fn revoke_authority(
+    State(state): State<AdminState>,
+    Path(id): Path<u32>,
+    Query(params): Params,
+) -> Response {
+    let room = params.get("room").map(String::as_str);
+    entity_response(state.world.entities(room, EntityOp::Grant(id, None)).await)
+}
+
+// PUT /admin/entities/<id>/parent?room=<name>&entity=<id>|player=<id>
+// [&offset=x,y,z]: hangs it from that entity or player, offset blocks away
+async fn attach_entity(
+    State(state): State<AdminState>,
+    Path(id): Path<u32>,
+    Query(params): Params,
+) -> Response {
+    let parse = |key: &str| params.get(key).and_then(|p| p.parse().ok());
+    let parent = match (parse("entity"), parse("player")) {
+        (Some(entity), None) => Parent::Entity(entity),
+        (None, Some(player)) => Parent::Player(player),
+        _ => return (StatusCode::BAD_REQUEST, "Needs one of entity and player").into_response(),
+    };
+    let offset = match params.get("offset") {
+        Some(offset) => parse_pos(offset),
+        None => Some((0, 0, 0)),
+    };
+    let Some(offset) = offset else {
+        return (StatusCode::BAD_REQUEST, "Invalid offset").into_response();
+    };
+    let room = params.get("room").map(String::as_str);
+    let op = EntityOp::Attach(id, Some(Attachment { parent, offset }));
+    entity_response(state.world.entities(room, op).await)
+}
+
+// DELETE /admin/entities/<id>/parent?room=<name>: lets go of it where it is
+async fn detach_entity(
+    State(state): State<AdminState>,
+    Path(id): Path<u32>,
+    Query(params): Params,
+) -> Response {
+    let room = params.get("room").map(String::as_str);
+    entity_response(state.world.entities(room, EntityOp::Attach(id, None)).await)
+}
+
+// PUT /admin/entities/<id>/goal?room=<name>&pos=x,y,z: the server walks it
+// there, see brains.rs
+async fn set_goal(
+    State(state): State<AdminState>,
+    Path(id): Path<u32>,
+    Query(params): Params,
+) -> Response {
+    let Some(pos) = params.get("pos").and_then(|p| parse_pos(p)) else {
+        return (StatusCode::BAD_REQUEST, "Invalid pos").into_response();
+    };
+    let room = params.get("room").map(String::as_str);
+    entity_response(
+        state
+            .world
+            .entities(room, EntityOp::Goal(id, Some(pos)))
+            .await,
+    )
+}
+
+// DELETE /admin/entities/<id>/goal?room=<name>: stops where it is
+async fn clear_goal(
+    State(state): State<AdminState>,
+    Path(id): Path<u32>,
+    Query(params): Params,
+) -> Response {
+    let room = params.get("room").map(String::as_str);
+    entity_response(state.world.entities(room, EntityOp::Goal(id, None)).await)
+}
+
+// PUT /admin/entities/<id>/always?room=<name>: a static entity goes to every
+// player, wherever they are
+async fn set_always(
+    State(state): State<AdminState>,
+    Path(id): Path<u32>,
+    Query(params): Params,
+) -> Response {
+    let room = params.get("room").map(String::as_str);
+    entity_response(state.world.entities(room, EntityOp::Always(id, true)).await)
+}
+
+// DELETE /admin/entities/<id>/always?room=<name>: back to their interest
+async fn clear_always(
+    State(state): State<AdminState>,
+    Path(id): Path<u32>,
+    Query(params): Params,
+) -> Response {
+    let room = params.get("room").map(String::as_str);
+    entity_response(
+        state
+            .world
+            .entities(room, EntityOp::Always(id, false))
+            .await,
+    )
+}
+
+// PUT /admin/entities/<id>/relevant?room=<name>&player=<id>: a static entity
+// goes to that player wherever they are, while they're in the world
+async fn set_relevant(
+    State(state): State<AdminState>,
+    Path(id): Path<u32>,
+    Query(params): Params,
+) -> Response {
+    relevant(state, id, params, true).await
+}
+
+// DELETE /admin/entities/<id>/relevant?room=<name>&player=<id>
+async fn clear_relevant(
+    State(state): State<AdminState>,
+    Path(id): Path<u32>,
+    Query(params): Params,
+) -> Response {
+    relevant(state, id, params, false).await
+}
+
+// PUT /admin/entities/<id>/layer?room=<name>&layer=<n>: only players in that
+// layer get it from now on, see relevance.rs
+async fn set_entity_layer(
+    State(state): State<AdminState>,
+    Path(id): Path<u32>,
+    Query(params): Params,
+) -> Response {
+    let Some(layer) = params.get("layer").and_then(|l| l.parse().ok()) else {
+        return (StatusCode::BAD_REQUEST, "Invalid layer").into_response();
+    };
+    let room = params.get("room").map(String::as_str);
+    entity_response(state.world.entities(room, EntityOp::Layer(id, layer)).await)
+}
+
+// PUT /admin/players/<id>/layer?room=<name>&layer=<n>: they only get the
+// entities, effects and voice of that layer from now on
+async fn set_player_layer(
+    State(state): State<AdminState>,
+    Path(id): Path<u32>,
+    Query(params): Params,
+) -> Response {
+    let Some(layer) = params.get("layer").and_then(|l| l.parse().ok()) else {
+        return (StatusCode::BAD_REQUEST, "Invalid layer").into_response();
+    };
+    let room = params.get("room").map(String::as_str);
+    match state.world.layer(room, id, layer).await {
+        None => (StatusCode::NOT_FOUND, "No such room").into_response(),
+        Some(false) => (StatusCode::NOT_FOUND, format!("No player {id}")).into_response(),
+        Some(true) => StatusCode::NO_CONTENT.into_response(),
+    }
+}
+
+async fn relevant(
+    state: AdminState,
+    id: u32,
+    params: HashMap<String, String>,
+    relevant: bool,
+) -> Response {
+    let Some(player) = params.get("player").and_then(|p| p.parse().ok()) else {
+        return (StatusCode::BAD_REQUEST, "Invalid player").into_response();
+    };
+    let room = params.get("room").map(String::as_str);
+    let op = EntityOp::Relevant(id, player, relevant);
+    entity_response(state.world.entities(room, op).await)
+}
+
+fn entity_response(result: Option<Result<EntityReply, EntityError>>) -> Response {
+    match result {
+        None => (StatusCode::NOT_FOUND, "No such room").into_response(),
+        Some(Ok(reply)) => Json(reply).into_response(),
+        Some(Err(e)) => {
+            let status = match e {
+                EntityError::NotFound(_) | EntityError::NoPlayer(_) => StatusCode::NOT_FOUND,
+                EntityError::Cycle(_) | EntityError::NotAttached(_) => StatusCode::CONFLICT,
+                _ => StatusCode::FORBIDDEN,
+            };
+            (status, e.to_string()).into_response()
+        }
+    }
+}
+
+// POST /admin/history/rewind?room=<name>&ticks=<n>: puts the live world back
+// `n` ticks, only with TELEBOXEL_DEV_REWIND
+async fn history_rewind(State(state): State<AdminState>, Query(params): Params) -> Response {
+    let Some(ticks) = params.get("ticks").and_then(|t| t.parse().ok()) else {
+        return (StatusCode::BAD_REQUEST, "Invalid ticks").into_response();
+    };
+    let room = params.get("room").map(String::as_str);
+    history_response(state.world.history(room, HistoryQuery::Rewind(ticks)).await)
+}
+
+fn history_response(result: Option<Result<HistoryReply, HistoryError>>) -> Response {
+    let e = match result {
+        None => return (StatusCode::NOT_FOUND, "No such room").into_response(),
+        Some(Ok(reply)) => return Json(reply).into_response(),
+        Some(Err(e)) => e,
+    };
+    let status = match e {
+        HistoryError::Off | HistoryError::RewindOff => StatusCode::FORBIDDEN,
+        HistoryError::Gone { .. } => StatusCode::GONE,
+        HistoryError::Future { .. } => StatusCode::BAD_REQUEST,
+    };
+    (status, e.to_string()).into_response()
+}
+
+fn parse_pos(s: &str) -> Option<BlockPos> {
+    let mut parts = s.split(',').map(|p| p.parse::<i32>().ok());
+    let pos = (parts.next()??, parts.next()??, parts.next()??);
+    parts.next().is_none().then_some(pos)
+}
+
+fn claim_error(e: ClaimError) -> Response {
+    let status = match e {
+        ClaimError::NotFound(_) => StatusCode::NOT_FOUND,
+        ClaimError::Overlaps(_) => StatusCode::CONFLICT,
+        _ => StatusCode::BAD_REQUEST,
+    };
+    (status, e.to_string()).into_response()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::{
+        config::AuditConfig,
+        http::{self, HttpError},
+    };
+    use std::sync::Mutex;
+
+    // A main world with player 1, recording kicks
+    #[derive(Default)]
+    pub(crate) struct FakeWorld {
+        pub(crate) kicked: Mutex<Vec<(u32, String)>>,
+    }
+
+    impl WorldControl for FakeWorld {
+        fn kick(&self, room: Option<&str>, id: u32, reason: String) -> BoxFuture<'_, bool> {
+            let found = room.is_none() && id == 1;
+            if found {
+                self.kicked.lock().unwrap().push((id, reason));
+            }
+            Box::pin(async move { found })
+        }
+
+        fn state(&self, room: Option<&str>) -> BoxFuture<'_, Option<WorldState>> {
+            let state = room.is_none().then(|| WorldState {
+                tick: 812,
+                loaded_chunks: 27,
+                players: vec![PlayerState {
+                    id: 1,
+                    name: Some("bob".into()),
+                    position: (0, 40, -3),
+                    rotation: (90.0, 0.0),
+                    velocity: None,
+                    clock: None,
+                    interest: None,
+                    dropped_inputs: 0,
+                    coalesced: 0,
+                    afk: false,
+                    layer: 0,
+                }],
+            });
+            Box::pin(async move { state })
+        }
+
+        fn say(&self, room: Option<&str>, _: String) -> BoxFuture<'_, bool> {
+            let found = room.is_none();
+            Box::pin(async move { found })
+        }
+
+        fn save(&self) -> BoxFuture<'_, Option<(usize, usize)>> {
+            Box::pin(async { Some((1, 0)) })
+        }
+
+        fn drain(&self, _: u16, _: String) -> BoxFuture<'_, Option<usize>> {
+            Box::pin(async { None })
+        }
+
+        fn restart(&self, _: u16) -> BoxFuture<'_, Result<usize, RestartError>> {
+            Box::pin(async { Ok(0) })
+        }
+
+        fn history(
+            &self,
+            _: Option<&str>,
+            _: HistoryQuery,
+        ) -> BoxFuture<'_, Option<Result<HistoryReply, HistoryError>>> {
+            Box::pin(async { None })
+        }
+
+        fn frames(&self, _: Option<&str>, _: u32, _: Duration) -> BoxFuture<'_, Option<Vec<u8>>> {
+            Box::pin(async { None })
+        }
+
+        fn features(&self, _: Option<&str>, _: Toggles) -> BoxFuture<'_, Option<Features>> {
+            Box::pin(async { None })
+        }
+
+        fn simulation(
+            &self,
+            _: Option<&str>,
+            _: SimulationChange,
+        ) -> BoxFuture<'_, Option<SimulationState>> {
+            Box::pin(async { None })
+        }
+
+        fn entities(
+            &self,
+            _: Option<&str>,
+            _: EntityOp,
+        ) -> BoxFuture<'_, Option<Result<EntityReply, EntityError>>> {
+            Box::pin(async { None })
+        }
+
+        fn effect(&self, _: Option<&str>, _: Effect) -> BoxFuture<'_, Option<usize>> {
+            Box::pin(async { None })
+        }
+
+        fn path(
+            &self,
+            _: Option<&str>,
+            _: BlockPos,
+            _: BlockPos,
+        ) -> BoxFuture<'_, Option<Result<Route, PathError>>> {
+            Box::pin(async { None })
+        }
+
+        fn create_room(
+            &self,
+            _: &str,
+            _: Option<String>,
+        ) -> BoxFuture<'_, Option<Result<String, String>>> {
+            Box::pin(async { None })
+        }
+
+        fn reserve(
+            &self,
+            _: Option<&str>,
+            _: Option<String>,
+            _: Duration,
+        ) -> BoxFuture<'_, Option<Result<String, String>>> {
+            Box::pin(async { None })
+        }
+
+        fn layer(&self, _: Option<&str>, _: u32, _: u32) -> BoxFuture<'_, Option<bool>> {
+            Box::pin(async { None })
+        }
+
+        fn minimap(
+            &self,
+            _: Option<&str>,
+            _: (i32, i32),
+            _: u8,
+            _: u8,
+        ) -> BoxFuture<'_, Option<Minimap>> {
+            Box::pin(async { None })
+        }
+    }
+
+    // The API on a local port, with tokens `admin` and `mod` and an audit
+    // log in `dir`
+    async fn serve(world: Arc<FakeWorld>, dir: &std::path::Path) -> (String, Arc<AuditLog>) {
+        std::fs::remove_dir_all(dir).ok();
+        let audit = Arc::new(
+            AuditLog::open(AuditConfig {
+                path: dir.join("audit.jsonl"),
+                max_bytes: 1 << 20,
+                keep: 0,
+            })
+            .unwrap(),
+        );
+        let state = AdminState {
+            token: "admin".into(),
+            moderator_token: Some("mod".into()),
+            storage: None,
+            audit: Some(audit.clone()),
+            backups: None,
+            claims: Claims::load(None).unwrap(),
+            traffic: Arc::default(),
+            quotas: Arc::default(),
+            tenants: Arc::new(Tenants::load(None).unwrap()),
+            templates: Arc::default(),
+            listeners: Arc::default(),
+            world,
+            events: broadcast::channel(16).0,
+            journal: Arc::default(),
+        };
+        let app = Router::new().nest("/admin", router(state));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        (format!("http://{addr}/admin"), audit)
+    }
+
+    // The status, 200 for any success
+    fn status(result: Result<Bytes, HttpError>) -> u16 {
+        match result {
+            Ok(_) => 200,
+            Err(HttpError::Status(status, _)) => status,
+            Err(e) => panic!("{e}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn kicks_players_and_reports_the_world() {
+        let world = Arc::new(FakeWorld::default());
+        let dir = std::env::temp_dir().join(format!("teleboxel-admin-{}", std::process::id()));
+        let (url, audit) = serve(world.clone(), &dir).await;
+        let auth = [("authorization", "Bearer admin")];
+        let state = http::get(&format!("{url}/world"), &auth).await.unwrap();
+        let state: serde_json::Value = serde_json::from_slice(&state).unwrap();
+        assert_eq!(state["tick"], 812);
+        assert_eq!(state["loaded_chunks"], 27);
+        assert_eq!(state["players"][0]["name"], "bob");
+        assert_eq!(
+            state["players"][0]["position"],
+            serde_json::json!([0, 40, -3])
+        );
+        let arena = http::get(&format!("{url}/world?room=arena"), &auth).await;
+        assert_eq!(status(arena), 404);
+
+        // Moderators reach kicks too
+        let moderator = [("authorization", "Bearer mod")];
+        let kick = format!("{url}/players/1/kick?reason=griefing");
+        assert_eq!(status(http::post(&kick, &moderator, "").await), 200);
+        let missing = http::post(&format!("{url}/players/2/kick"), &auth, "").await;
+        assert!(matches!(missing, Err(HttpError::Status(404, body)) if body == "No player 2"));
+        assert_eq!(*world.kicked.lock().unwrap(), [(1, "griefing".to_string())]);
+
+        let query = AuditQuery {
+            event: Some("kick".into()),
+            limit: 10,
+            ..Default::default()
+        };
+        let kicks = audit.query(&query).unwrap();
+        assert_eq!(kicks.len(), 1);
+        assert_eq!(kicks[0]["reason"], "griefing");
+
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[tokio::test]
+    async fn checks_tokens_and_audits_failures() {
+        let dir = std::env::temp_dir().join(format!("teleboxel-tokens-{}", std::process::id()));
+        let (url, audit) = serve(Arc::default(), &dir).await;
+        let save = format!("{url}/save");
+
+        assert_eq!(status(http::post(&save, &[], "").await), 401);
+        // Near misses fail like any other token
+        for token in ["Bearer admi", "Bearer admin2", "Bearer ADMIN", "admin"] {
+            let auth = [("authorization", token)];
+            assert_eq!(status(http::post(&save, &auth, "").await), 401, "{token}");
+        }
+        let moderator = [("authorization", "Bearer mod")];
+        assert_eq!(status(http::post(&save, &moderator, "").await), 403);
+        let admin = [("authorization", "Bearer admin")];
+        assert_eq!(status(http::post(&save, &admin, "").await), 200);
+
+        let count = |event: &str| {
+            let query = AuditQuery {
+                event: Some(event.into()),
+                limit: 100,
+                ..Default::default()
+            };
+            audit.query(&query).unwrap().len()
+        };
+        assert_eq!(count("auth_failure"), 5);
+        assert_eq!(count("admin_call"), 2);
+
+        assert!(matches("admin", "admin"));
+        assert!(!matches("", "admin") && !matches("admin", ""));
+
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn scopes_tenant_rooms() {
+        let scoped = |uri: &str| {
+            let uri = scope_room(&uri.parse().unwrap(), "acme")?;
+            Some(uri.to_string())
+        };
+        assert_eq!(
+            scoped("/kick?id=3&room=arena").as_deref(),
+            Some("/kick?id=3&room=acme%2Farena")
+        );
+        assert_eq!(
+            scoped("/world").as_deref(),
+            Some(format!("/world?room=acme%2F{}", tenants::LOBBY).as_str())
+        );
+        // Not out of its namespace, nor names too long for a room
+        assert_eq!(scoped("/world?room=other%2Farena"), None);
+        assert_eq!(scoped("/world?room=../arena"), None);
+        assert_eq!(scoped(&format!("/world?room={}", "a".repeat(33))), None);
+    }
+}
@@ -0,0 +1,114 @@
+//! Connect admission: under a connect storm, joins queue here instead of
+//! racing each other into the world's message channel. At most
+//! `TELEBOXEL_CONNECT_CONCURRENCY` (64) connections are between the
+//! websocket upgrade and the world's `Connect` reply at once, logins and
+//! record loads included. Up to `TELEBOXEL_CONNECT_QUEUE` (512) more wait
+//! for a place, for `TELEBOXEL_CONNECT_WAIT_MS` (2000) at most.
+//!
+//! A full queue or a wait running out answers `503` with `Retry-After`,
+//! before the upgrade.
+
+use crate::config::AdmissionConfig;
+use std::sync::{
+    Arc,
+    atomic::{AtomicUsize, Ordering},
+};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+pub struct Admission {
+    config: AdmissionConfig,
+    permits: Arc<Semaphore>,
+    waiting: AtomicUsize,
+}
+
+/// A place among the connections handshaking, given back when dropped.
+pub struct Admitted {
+    _permit: OwnedSemaphorePermit,
+}
+
+impl Admission {
+    pub fn new(config: AdmissionConfig) -> Self {
+        Self {
+            permits: Arc::new(Semaphore::new(config.concurrency.max(1))),
+            waiting: AtomicUsize::new(0),
+            config,
+        }
+    }
+
+    /// A place, once one is free. `None` when the queue is full or the
+    /// wait runs out.
+    pub async fn admit(&self) -> Option<Admitted> {
+        if let Ok(permit) = self.permits.clone().try_acquire_owned() {
+            return Some(Admitted { _permit: permit });
+        }
+        let _waiting = Waiting::join(&self.waiting, self.config.queue)?;
+        let permit = self.permits.clone().acquire_owned();
+        let permit = tokio::time::timeout(self.config.wait, permit).await;
+        let permit = permit.ok()?.ok()?;
+        Some(Admitted { _permit: permit })
+    }
+
+    /// For `Retry-After`: the longest wait, in whole seconds.
+    pub fn retry_after(&self) -> u64 {
+        self.config.wait.as_secs_f64().ceil().max(1.0) as u64
+    }
+
+    pub fn waiting(&self) -> usize {
+        self.waiting.load(Ordering::Relaxed)
+    }
+}
+
+// Counted in the queue until dropped, a client hanging up included
+struct Waiting<'a>(&'a AtomicUsize);
+
+impl<'a> Waiting<'a> {
+    fn join(waiting: &'a AtomicUsize, queue: usize) -> Option<Self> {
+        waiting
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| {
+                (n < queue).then_some(n + 1)
+            })
+            .ok()?;
+        Some(Self(waiting))
+    }
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn queues_then_refuses() {
+        let admission = Arc::new(Admission::new(AdmissionConfig {
+            concurrency: 1,
+            queue: 1,
+            wait: Duration::from_millis(100),
+        }));
+        let first = admission.admit().await.unwrap();
+
+        // One waits, the next finds the queue full
+        let queued = tokio::spawn({
+            let admission = admission.clone();
+            async move { admission.admit().await.is_some() }
+        });
+        while admission.waiting() == 0 {
+            tokio::task::yield_now().await;
+        }
+        assert!(admission.admit().await.is_none());
+        drop(first);
+        assert!(queued.await.unwrap());
+        assert_eq!(admission.waiting(), 0);
+
+        // And a wait runs out
+        let _held = admission.admit().await.unwrap();
+        assert!(admission.admit().await.is_none());
+        assert_eq!(admission.waiting(), 0);
+        assert_eq!(admission.retry_after(), 1);
+    }
+}
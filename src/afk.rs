@@ -0,0 +1,183 @@
+//! AFK detection: players whose connection is fine but who stopped playing.
+//! Moving, turning, block edits and room inputs (lockstep, relay) count as
+//! playing, chat, interest updates and pings don't. Off unless a TOML file
+//! (`TELEBOXEL_AFK`) sets it, per world (`main` or a room name):
+//!
+//! ```toml
+//! [default]
+//! after_secs = 300         # 0 turns it off
+//! action = "mark"
+//!
+//! [room.arena]             # settings left out come from [default]
+//! after_secs = 60
+//! action = "kick"
+//! ```
+//!
+//! Every second a world checks its players. Past `after_secs` without
+//! playing, they're marked AFK (`afk` on `/admin/world` and in presence,
+//! and a chat line), and then:
+//!
+//! - `mark` (the default): nothing more, the mark goes once they play.
+//! - `lobby`: out of a room to the main world, a tenant's players to their
+//!   lobby. In the main world it only marks.
+//! - `kick`: the connection is closed.
+
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    error::Error,
+    fs,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum AfkAction {
+    #[default]
+    Mark,
+    Lobby,
+    Kick,
+}
+
+/// One world's setting, `after` is `None` when it's off.
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+pub struct AfkRule {
+    pub after: Option<Duration>,
+    pub action: AfkAction,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct AfkTable {
+    after_secs: Option<u64>,
+    action: Option<AfkAction>,
+}
+
+impl AfkTable {
+    fn over(&self, base: &AfkRule) -> AfkRule {
+        let after = match self.after_secs {
+            Some(0) => None,
+            Some(secs) => Some(Duration::from_secs(secs)),
+            None => base.after,
+        };
+        AfkRule {
+            after,
+            action: self.action.unwrap_or(base.action),
+        }
+    }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct AfkFile {
+    #[serde(default)]
+    default: AfkTable,
+    #[serde(default)]
+    room: HashMap<String, AfkTable>,
+}
+
+/// Settings by world.
+#[derive(Default)]
+pub struct AfkRules {
+    default: AfkRule,
+    rooms: HashMap<String, AfkRule>,
+}
+
+impl AfkRules {
+    /// See the module docs for the file format.
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    fn parse(text: &str) -> Result<Self, Box<dyn Error>> {
+        let file: AfkFile = toml::from_str(text)?;
+        let default = file.default.over(&AfkRule::default());
+        let rooms = file
+            .room
+            .iter()
+            .map(|(room, table)| (room.clone(), table.over(&default)))
+            .collect();
+        Ok(Self { default, rooms })
+    }
+
+    /// The setting of `world`, `main` or a room name.
+    pub fn get(&self, world: &str) -> AfkRule {
+        *self.rooms.get(world).unwrap_or(&self.default)
+    }
+}
+
+/// When a player last played, and whether they're marked AFK.
+pub struct Activity {
+    last: Instant,
+    afk: bool,
+}
+
+impl Activity {
+    pub fn new(now: Instant) -> Self {
+        Self {
+            last: now,
+            afk: false,
+        }
+    }
+
+    pub fn is_afk(&self) -> bool {
+        self.afk
+    }
+
+    /// They played. `true` if that ends their AFK mark.
+    pub fn played(&mut self, now: Instant) -> bool {
+        self.last = now;
+        std::mem::take(&mut self.afk)
+    }
+
+    /// Marks them AFK past `after` without playing. `true` when that's
+    /// new.
+    pub fn check(&mut self, now: Instant, after: Duration) -> bool {
+        let away = !self.afk && now.duration_since(self.last) >= after;
+        self.afk |= away;
+        away
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn marks_players_who_stop_playing() {
+        let rules = AfkRules::parse(
+            r#"
+            [default]
+            after_secs = 300
+
+            [room.arena]
+            after_secs = 60
+            action = "kick"
+
+            [room.lobby]
+            after_secs = 0
+            "#,
+        )
+        .unwrap();
+        let minutes = |m: u64| Some(Duration::from_secs(m * 60));
+        assert_eq!(rules.get("main").after, minutes(5));
+        assert_eq!(rules.get("main").action, AfkAction::Mark);
+        assert_eq!(rules.get("arena").after, minutes(1));
+        assert_eq!(rules.get("arena").action, AfkAction::Kick);
+        assert_eq!(rules.get("lobby").after, None);
+        assert_eq!(AfkRules::default().get("main").after, None);
+        assert!(AfkRules::parse("[default]\naction = \"sleep\"").is_err());
+
+        let now = Instant::now();
+        let after = Duration::from_secs(60);
+        let mut activity = Activity::new(now);
+        assert!(!activity.check(now + after / 2, after));
+        assert!(activity.check(now + after, after));
+        assert!(!activity.check(now + after * 2, after));
+        assert!(activity.is_afk());
+        assert!(activity.played(now + after * 2));
+        assert!(!activity.is_afk());
+        assert!(!activity.played(now + after * 2));
+    }
+}
@@ -0,0 +1,131 @@
+//! Agones lifecycle, for fleets of game servers on Kubernetes. With
+//! `TELEBOXEL_AGONES=true` the server talks to the SDK sidecar's REST API
+//! on `localhost:$AGONES_SDK_HTTP_PORT` (9358):
+//!
+//! - `Ready` once the listener is up.
+//! - `Health` every `TELEBOXEL_AGONES_HEALTH_SECS` (5) while the main world
+//!   answers within a second. A wedged tick loop stops the pings and Agones
+//!   replaces the pod.
+//! - `Allocated` when the first player connects, so fleet scale downs pass
+//!   over a server someone is playing on. Allocators can allocate it
+//!   before, as usual.
+//! - `Shutdown` once the server stops, after draining if it drained.
+//!
+//! Agones deleting the game server sends SIGTERM, which shuts down like
+//! any other. For a graceful end, drain first (`POST /admin/drain`).
+
+use crate::{config::AgonesConfig, http};
+use std::{
+    future::Future,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+pub struct Agones {
+    config: AgonesConfig,
+    allocated: AtomicBool,
+}
+
+impl Agones {
+    pub fn new(config: AgonesConfig) -> Self {
+        Self {
+            config,
+            allocated: AtomicBool::new(false),
+        }
+    }
+
+    pub async fn ready(&self) {
+        self.call("ready").await;
+    }
+
+    pub async fn shutdown(&self) {
+        self.call("shutdown").await;
+    }
+
+    /// Health pings while `alive`, and `Allocated` once `players` is above
+    /// zero, until the process exits.
+    pub async fn run<A, F>(&self, alive: A, players: impl Fn() -> usize)
+    where
+        A: Fn() -> F,
+        F: Future<Output = bool>,
+    {
+        let mut ticker = tokio::time::interval(self.config.health_interval);
+        loop {
+            ticker.tick().await;
+            if players() > 0 && !self.allocated.swap(true, Ordering::Relaxed) {
+                self.call("allocate").await;
+            }
+            match tokio::time::timeout(Duration::from_secs(1), alive()).await {
+                Ok(true) => self.call("health").await,
+                _ => eprintln!("Agones: the main world isn't answering, no health ping"),
+            }
+        }
+    }
+
+    // Errors are logged, Agones acts on missing health pings anyway
+    async fn call(&self, path: &str) {
+        let url = format!("http://{}/{path}", self.config.address);
+        let headers = [("Content-Type", "application/json")];
+        if let Err(e) = http::post(&url, &headers, "{}").await {
+            eprintln!("Agones {path}: {e}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Router, extract::Path, http::StatusCode, routing::post};
+    use std::sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    };
+    use tokio::sync::mpsc;
+
+    #[tokio::test]
+    async fn reports_lifecycle_to_the_sidecar() {
+        let (calls_tx, mut calls) = mpsc::unbounded_channel();
+        let app = Router::new().route(
+            "/{call}",
+            post(move |Path(call): Path<String>, body: String| async move {
+                assert_eq!(body, "{}");
+                calls_tx.send(call).ok();
+                StatusCode::OK
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let agones = Arc::new(Agones::new(AgonesConfig {
+            address,
+            health_interval: Duration::from_millis(10),
+        }));
+        agones.ready().await;
+        assert_eq!(calls.recv().await.unwrap(), "ready");
+
+        // Healthy and empty, then a player joins, then the world wedges
+        let ticks = Arc::new(AtomicUsize::new(0));
+        let run = {
+            let agones = agones.clone();
+            let (checked, joined) = (ticks.clone(), ticks);
+            let alive = move || {
+                let tick = checked.fetch_add(1, Ordering::SeqCst);
+                async move { tick < 3 }
+            };
+            let players = move || usize::from(joined.load(Ordering::SeqCst) >= 1);
+            tokio::spawn(async move { agones.run(alive, players).await })
+        };
+        let mut seen = Vec::new();
+        while seen.len() < 4 {
+            seen.push(calls.recv().await.unwrap());
+        }
+        assert_eq!(seen, ["health", "allocate", "health", "health"]);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(calls.try_recv().is_err());
+        run.abort();
+
+        agones.shutdown().await;
+        assert_eq!(calls.recv().await.unwrap(), "shutdown");
+    }
+}
@@ -0,0 +1,339 @@
+//! Analytics export: gameplay events from the journal (`journal.rs`) in
+//! batches to a sink, so game teams get telemetry without tapping the
+//! server themselves. Enabled by `TELEBOXEL_ANALYTICS_SINK`:
+//!
+//! - `https://host/path` (or `http://`) — a JSON POST per batch, an array
+//!   of entries
+//! - `kafka://host:8082/topic` — the same through a Kafka REST proxy, as
+//!   `{"records": [{"key": <world>, "value": <entry>}]}`
+//! - `file:events.jsonl` — appended, one entry per line
+//!
+//! Entries are the journal's (`/admin/journal` shows them), including the
+//! custom events games send with `Track Event [Key=Value...]` and the
+//! simulation's pauses and time skips. A batch goes out every
+//! `TELEBOXEL_ANALYTICS_FLUSH_SECS` (10), or once it holds
+//! `TELEBOXEL_ANALYTICS_BATCH` (500) entries. Failed batches are retried
+//! at the next flush, past `MAX_PENDING` entries waiting the oldest go.
+//!
+//! `TELEBOXEL_ANALYTICS_SAMPLE` keeps a share of each event at random, as
+//! `block_set=0.1,custom=1,*=0.5` (`*` for the events not listed, `0`
+//! drops an event); everything is kept when unset.
+
+use crate::{
+    config::AnalyticsConfig,
+    http,
+    journal::{JournalEntry, JournalEvent},
+    telemetry::random_u64,
+};
+use serde_json::json;
+use std::{
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    io::Write,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    sync::{broadcast, watch},
+    task::JoinHandle,
+};
+
+/// Entries kept for a failing sink.
+pub const MAX_PENDING: usize = 100_000;
+
+#[derive(PartialEq, Debug)]
+pub enum Sink {
+    Http(String),
+    /// The REST proxy's `/topics/<topic>` URL.
+    Kafka(String),
+    File(PathBuf),
+}
+
+impl Sink {
+    pub fn parse(sink: &str) -> Result<Self, String> {
+        if let Some(path) = sink.strip_prefix("file:") {
+            return Ok(Sink::File(PathBuf::from(path)));
+        }
+        if let Some(rest) = sink.strip_prefix("kafka://") {
+            return match rest.split_once('/') {
+                Some((proxy, topic)) if !proxy.is_empty() && !topic.is_empty() => {
+                    Ok(Sink::Kafka(format!("http://{proxy}/topics/{topic}")))
+                }
+                _ => Err(format!("Expected kafka://<host:port>/<topic>, got {sink}")),
+            };
+        }
+        if sink.starts_with("http://") || sink.starts_with("https://") {
+            return Ok(Sink::Http(sink.to_string()));
+        }
+        Err(format!(
+            "Expected an http[s]://, kafka:// or file: sink, got {sink}"
+        ))
+    }
+}
+
+/// Shares of each event kept, see the module docs.
+#[derive(PartialEq, Debug)]
+pub struct Sampling {
+    rates: HashMap<String, f64>,
+    rest: f64,
+}
+
+impl Default for Sampling {
+    fn default() -> Self {
+        Self {
+            rates: HashMap::new(),
+            rest: 1.0,
+        }
+    }
+}
+
+impl Sampling {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut sampling = Sampling::default();
+        for pair in text.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let Some((event, rate)) = pair.split_once('=') else {
+                return Err(format!("Expected <event>=<rate>, got {pair}"));
+            };
+            let rate = rate
+                .trim()
+                .parse::<f64>()
+                .ok()
+                .filter(|rate| (0.0..=1.0).contains(rate))
+                .ok_or_else(|| format!("Rate for {event} must be 0-1"))?;
+            match event.trim() {
+                "*" => sampling.rest = rate,
+                event => {
+                    sampling.rates.insert(event.to_string(), rate);
+                }
+            }
+        }
+        Ok(sampling)
+    }
+
+    /// Whether to keep an `event`, `roll` in `0..1`.
+    pub fn keeps(&self, event: &JournalEvent, roll: f64) -> bool {
+        let rate = self.rates.get(event.name()).copied().unwrap_or(self.rest);
+        roll < rate
+    }
+}
+
+pub struct Analytics {
+    stop: watch::Sender<bool>,
+    worker: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl Analytics {
+    /// Starts following `journal`, or fails on a bad sink or sampling.
+    pub fn start(
+        config: AnalyticsConfig,
+        journal: broadcast::Receiver<Arc<JournalEntry>>,
+    ) -> Result<Self, String> {
+        let sink = Sink::parse(&config.sink)?;
+        let sampling = match &config.sample {
+            Some(sample) => Sampling::parse(sample)?,
+            None => Sampling::default(),
+        };
+        let file = match &sink {
+            Sink::File(path) => Some(
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .map_err(|e| format!("{}: {e}", path.display()))?,
+            ),
+            _ => None,
+        };
+        let exporter = Exporter {
+            sink,
+            file,
+            sampling,
+            batch: config.batch,
+            pending: Vec::new(),
+        };
+        let (stop, stopped) = watch::channel(false);
+        let worker = tokio::spawn(exporter.run(journal, config.flush, stopped));
+        Ok(Self {
+            stop,
+            worker: Mutex::new(Some(worker)),
+        })
+    }
+
+    /// Sends what's pending, waiting up to `timeout`.
+    pub async fn shutdown(&self, timeout: Duration) {
+        self.stop.send_replace(true);
+        let Some(worker) = self.worker.lock().unwrap().take() else {
+            return;
+        };
+        if tokio::time::timeout(timeout, worker).await.is_err() {
+            eprintln!("Analytics still pending at shutdown, dropped");
+        }
+    }
+}
+
+struct Exporter {
+    sink: Sink,
+    file: Option<File>,
+    sampling: Sampling,
+    batch: usize,
+    pending: Vec<Arc<JournalEntry>>,
+}
+
+impl Exporter {
+    async fn run(
+        mut self,
+        mut journal: broadcast::Receiver<Arc<JournalEntry>>,
+        flush: Duration,
+        mut stopped: watch::Receiver<bool>,
+    ) {
+        let mut ticker = tokio::time::interval(flush);
+        loop {
+            tokio::select! {
+                entry = journal.recv() => match entry {
+                    Ok(entry) => {
+                        self.take(entry);
+                        if self.pending.len() >= self.batch {
+                            self.flush().await;
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Lagged(n)) => {
+                        eprintln!("Analytics fell behind the journal, {n} entries skipped");
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+                _ = ticker.tick() => self.flush().await,
+                // Only ever set, or the `Analytics` is gone
+                _ = stopped.changed() => {
+                    while let Ok(entry) = journal.try_recv() {
+                        self.take(entry);
+                    }
+                    break;
+                }
+            }
+        }
+        self.flush().await;
+    }
+
+    fn take(&mut self, entry: Arc<JournalEntry>) {
+        let roll = (random_u64() >> 11) as f64 / (1u64 << 53) as f64;
+        if self.sampling.keeps(&entry.event, roll) {
+            self.pending.push(entry);
+        }
+    }
+
+    // Batch by batch, the rest wait for the next flush after a failure
+    async fn flush(&mut self) {
+        while !self.pending.is_empty() {
+            let n = self.pending.len().min(self.batch);
+            if let Err(e) = self.ship(n).await {
+                eprintln!("Analytics export failed, retrying later: {e}");
+                break;
+            }
+            self.pending.drain(..n);
+        }
+        if self.pending.len() > MAX_PENDING {
+            let dropped = self.pending.len() - MAX_PENDING;
+            self.pending.drain(..dropped);
+            eprintln!("Analytics dropped {dropped} entries the sink didn't take");
+        }
+    }
+
+    // The first `n` pending entries
+    async fn ship(&mut self, n: usize) -> Result<(), String> {
+        let entries = &self.pending[..n];
+        match &self.sink {
+            Sink::Http(url) => {
+                let body = serde_json::to_string(entries).unwrap();
+                let headers = [("content-type", "application/json")];
+                http::post(url, &headers, body)
+                    .await
+                    .map_err(|e| e.to_string())?;
+            }
+            Sink::Kafka(url) => {
+                let records: Vec<_> = entries
+                    .iter()
+                    .map(|entry| json!({ "key": entry.world, "value": entry }))
+                    .collect();
+                let body = json!({ "records": records }).to_string();
+                let headers = [("content-type", "application/vnd.kafka.json.v2+json")];
+                http::post(url, &headers, body)
+                    .await
+                    .map_err(|e| e.to_string())?;
+            }
+            Sink::File(_) => {
+                let mut lines = String::new();
+                for entry in entries {
+                    lines += &serde_json::to_string(entry).unwrap();
+                    lines.push('\n');
+                }
+                let file = self.file.as_mut().unwrap();
+                file.write_all(lines.as_bytes())
+                    .map_err(|e| e.to_string())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::journal::Journal;
+    use std::fs;
+
+    #[tokio::test]
+    async fn batches_sampled_entries_to_a_file() {
+        assert_eq!(
+            Sink::parse("kafka://proxy:8082/game-events"),
+            Ok(Sink::Kafka("http://proxy:8082/topics/game-events".into()))
+        );
+        assert!(Sink::parse("kafka://proxy").is_err());
+        assert_eq!(
+            Sink::parse("https://example.com/events"),
+            Ok(Sink::Http("https://example.com/events".into()))
+        );
+        assert!(Sink::parse("ftp://example.com").is_err());
+        assert!(Sampling::parse("block_set=2").is_err());
+
+        let sampling = Sampling::parse("block_set=0.25, *=0").unwrap();
+        let block = JournalEvent::BlockSet {
+            position: (0, 0, 0),
+            from: 0,
+            to: 1,
+            by: None,
+        };
+        assert!(sampling.keeps(&block, 0.2) && !sampling.keeps(&block, 0.3));
+        assert!(!sampling.keeps(&JournalEvent::RoomCreated, 0.0));
+
+        let path = std::env::temp_dir().join(format!("teleboxel-analytics-{}", std::process::id()));
+        let journal = Journal::default();
+        let config = AnalyticsConfig {
+            sink: format!("file:{}", path.display()),
+            batch: 2,
+            flush: Duration::from_secs(3600),
+            sample: Some("room_created=0".into()),
+        };
+        let analytics = Analytics::start(config, journal.subscribe()).unwrap();
+        for name in ["level_done", "boss_beaten", "quit"] {
+            let event = JournalEvent::Custom {
+                id: 1,
+                name: name.into(),
+                fields: [("level".to_string(), "3".to_string())].into(),
+            };
+            journal.record("main", 9, event);
+        }
+        journal.record("main", 9, JournalEvent::RoomCreated);
+        analytics.shutdown(Duration::from_secs(5)).await;
+
+        let text = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).ok();
+        let lines: Vec<serde_json::Value> = text
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0]["event"], "custom");
+        assert_eq!(lines[2]["name"], "quit");
+        assert_eq!(lines[2]["fields"]["level"], "3");
+    }
+}
@@ -0,0 +1,247 @@
+//! Append-only audit log of administrative and security events, one JSON
+//! object per line, for moderation and abuse investigations. Enabled by
+//! `TELEBOXEL_AUDIT_LOG`; queried with `GET /admin/audit`.
+//!
+//! The file rotates when it passes `max_bytes`: `audit.jsonl` becomes
+//! `audit.jsonl.1`, older files shift up and the one past `keep` is deleted.
+
+use crate::config::AuditConfig;
+use serde::Serialize;
+use serde_json::Value;
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::PathBuf,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum AuditEvent {
+    /// Admin API request that passed the token check.
+    AdminCall {
+        method: String,
+        path: String,
+        status: u16,
+        remote: Option<String>,
+    },
+    /// Admin API request with a missing or wrong token.
+    AuthFailure {
+        method: String,
+        path: String,
+        remote: Option<String>,
+    },
+    /// Connection refused because the player is banned.
+    BannedJoin {
+        name: String,
+        reason: String,
+        remote: Option<String>,
+    },
+    /// Player kicked from the admin API, console or control socket.
+    Kick {
+        room: String,
+        id: u32,
+        reason: String,
+    },
+    /// Kick, mute or role change by a moderator or admin (see `roles.rs`),
+    /// `by` is `None` from the admin API.
+    Moderation {
+        by: Option<String>,
+        action: String,
+        target: String,
+        detail: String,
+    },
+    /// Player muted or kicked by flood control.
+    Flood {
+        room: String,
+        id: u32,
+        name: Option<String>,
+        action: String,
+        penalty: String,
+        remote: Option<String>,
+    },
+}
+
+#[derive(Serialize)]
+struct Entry<'a> {
+    /// Unix seconds
+    time: u64,
+    #[serde(flatten)]
+    event: &'a AuditEvent,
+}
+
+/// Filters for `AuditLog::query`.
+#[derive(Default)]
+pub struct AuditQuery {
+    /// Only this event type, e.g. `auth_failure`.
+    pub event: Option<String>,
+    /// Only entries at or after this unix time.
+    pub since: Option<u64>,
+    /// The newest `limit` matching entries.
+    pub limit: usize,
+}
+
+pub struct AuditLog {
+    config: AuditConfig,
+    // The current file and its size
+    file: Mutex<(File, u64)>,
+}
+
+impl AuditLog {
+    pub fn open(config: AuditConfig) -> io::Result<Self> {
+        if let Some(dir) = config.path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&config.path)?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            config,
+            file: Mutex::new((file, size)),
+        })
+    }
+
+    /// Appends an entry. Write errors are logged, not returned: auditing
+    /// never fails the action being audited.
+    pub fn record(&self, event: AuditEvent) {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let mut line = serde_json::to_string(&Entry {
+            time,
+            event: &event,
+        })
+        .unwrap();
+        line.push('\n');
+
+        if let Err(e) = self.append(line.as_bytes()) {
+            eprintln!("Audit log {}: {e}", self.config.path.display());
+        }
+    }
+
+    fn append(&self, line: &[u8]) -> io::Result<()> {
+        let mut file = self.file.lock().unwrap();
+        if file.1 > 0 && file.1 + line.len() as u64 > self.config.max_bytes {
+            self.rotate()?;
+            *file = (File::create(&self.config.path)?, 0);
+        }
+        file.0.write_all(line)?;
+        file.1 += line.len() as u64;
+        Ok(())
+    }
+
+    fn rotate(&self) -> io::Result<()> {
+        if self.config.keep == 0 {
+            return fs::remove_file(&self.config.path);
+        }
+        fs::remove_file(self.rotated(self.config.keep)).ok();
+        for n in (1..self.config.keep).rev() {
+            fs::rename(self.rotated(n), self.rotated(n + 1)).ok();
+        }
+        fs::rename(&self.config.path, self.rotated(1))
+    }
+
+    // `audit.jsonl.<n>`, higher is older
+    fn rotated(&self, n: usize) -> PathBuf {
+        let mut path = self.config.path.clone().into_os_string();
+        path.push(format!(".{n}"));
+        path.into()
+    }
+
+    /// Matching entries from the current and rotated files, oldest first.
+    pub fn query(&self, query: &AuditQuery) -> io::Result<Vec<Value>> {
+        // Hold the lock so a rotation can't move files mid-read
+        let _file = self.file.lock().unwrap();
+        let mut paths: Vec<_> = (1..=self.config.keep)
+            .rev()
+            .map(|n| self.rotated(n))
+            .collect();
+        paths.push(self.config.path.clone());
+
+        let mut entries = Vec::new();
+        for path in paths {
+            let file = match File::open(&path) {
+                Ok(file) => file,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+            for line in BufReader::new(file).lines() {
+                // A torn last line (crash mid-write) is skipped
+                let Ok(entry) = serde_json::from_str::<Value>(&line?) else {
+                    continue;
+                };
+                let event_matches = query.event.as_ref().is_none_or(|e| entry["event"] == **e);
+                let time_matches = query
+                    .since
+                    .is_none_or(|since| entry["time"].as_u64().is_some_and(|t| t >= since));
+                if event_matches && time_matches {
+                    entries.push(entry);
+                }
+            }
+        }
+
+        let skip = entries.len().saturating_sub(query.limit);
+        entries.drain(..skip);
+        Ok(entries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rotates_and_queries_across_files() {
+        let dir = std::env::temp_dir().join(format!("teleboxel-audit-{}", std::process::id()));
+        fs::remove_dir_all(&dir).ok();
+        let log = AuditLog::open(AuditConfig {
+            path: dir.join("audit.jsonl"),
+            max_bytes: 200,
+            keep: 2,
+        })
+        .unwrap();
+
+        for i in 0..10 {
+            log.record(AuditEvent::AuthFailure {
+                method: "GET".into(),
+                path: format!("/admin/claims?{i}"),
+                remote: Some("10.0.0.1:5000".into()),
+            });
+        }
+        log.record(AuditEvent::BannedJoin {
+            name: "mallory".into(),
+            reason: "griefing".into(),
+            remote: None,
+        });
+
+        assert!(dir.join("audit.jsonl.2").exists());
+        assert!(!dir.join("audit.jsonl.3").exists());
+
+        let all = log
+            .query(&AuditQuery {
+                limit: 100,
+                ..Default::default()
+            })
+            .unwrap();
+        // Oldest entries were rotated away
+        assert!(all.len() < 11);
+        assert_eq!(all.last().unwrap()["event"], "banned_join");
+        assert_eq!(all.last().unwrap()["name"], "mallory");
+
+        let failures = log
+            .query(&AuditQuery {
+                event: Some("auth_failure".into()),
+                since: Some(0),
+                limit: 2,
+            })
+            .unwrap();
+        assert_eq!(failures.len(), 2);
+        assert_eq!(failures[1]["path"], "/admin/claims?9");
+
+        fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,220 @@
+//! Who connects, checked during the handshake by an `Authenticator`:
+//! JWTs (`jwt.rs`, `TELEBOXEL_JWKS_URL`) or an HTTP callout
+//! (`TELEBOXEL_AUTH_URL`) for Steam session tickets and other platform
+//! tokens. Clients send `?token=` (or a Bearer header) and `?platform=`,
+//! e.g. `steam`, and the identity replaces `?name=`.
+//!
+//! The callout gets `{"platform": "steam", "token": "..."}` as a JSON POST
+//! (with `Authorization: Bearer <TELEBOXEL_AUTH_SECRET>` when set) and
+//! answers 2xx `{"id": "...", "name": "...", "role": "moderator"}`, only
+//! `id` required, or 401/403 to refuse the player.
+//!
+//! Checks run in the connection's task after the upgrade, so slow
+//! providers never hold up accepting other players. Each gets
+//! `TELEBOXEL_AUTH_TIMEOUT_MS`, then the connection is closed.
+//!
+//! With `TELEBOXEL_GUESTS=true`, connections without credentials get in as
+//! anonymous guests who can't build, claim or create rooms. `Login Token
+//! [Platform]` later binds the connection to the identity without
+//! reconnecting: the player's record takes over, keeping where the guest
+//! stands, and the guest's block list is merged into it.
+
+use crate::{
+    config::AuthCallout,
+    http::{self, HttpError},
+    roles::Role,
+};
+use serde::Deserialize;
+use std::{fmt, future::Future, pin::Pin};
+
+/// Longest player id an authenticator hands out, in bytes, the same as
+/// `?name=`.
+pub const MAX_ID_LEN: usize = 32;
+// Longest name shown in chat, in characters
+const MAX_DISPLAY_LEN: usize = 32;
+
+pub type AuthFuture<'a> = Pin<Box<dyn Future<Output = Result<Identity, AuthError>> + Send + 'a>>;
+
+/// What the client sent to prove who it is.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Credentials {
+    pub token: String,
+    /// `?platform=`, e.g. `steam`, `None` for JWTs.
+    pub platform: Option<String>,
+}
+
+/// Who the player is, per the authenticator.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Identity {
+    /// Persistent player id, used as the player name.
+    pub id: String,
+    /// Shown in chat.
+    pub display_name: String,
+    /// Saved as the player's role on login, `None` leaves it alone.
+    pub role: Option<Role>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum AuthError {
+    /// Bad credentials, the client shouldn't retry with them.
+    Rejected(String),
+    /// The provider couldn't be asked, retrying later may work.
+    Unavailable(String),
+    Timeout,
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuthError::Rejected(e) => write!(f, "{e}"),
+            AuthError::Unavailable(e) => write!(f, "authentication unavailable: {e}"),
+            AuthError::Timeout => write!(f, "authentication timed out"),
+        }
+    }
+}
+
+impl std::error::Error for AuthError {}
+
+pub trait Authenticator: Send + Sync {
+    fn authenticate<'a>(&'a self, credentials: &'a Credentials) -> AuthFuture<'a>;
+}
+
+/// Player ids go in text commands and storage keys: not empty, at most
+/// `MAX_ID_LEN` and without spaces.
+pub fn valid_id(id: &str) -> bool {
+    !id.is_empty() && id.len() <= MAX_ID_LEN && !id.contains(char::is_whitespace)
+}
+
+/// `name` trimmed and cut short for chat, the id when there's none.
+pub fn display_name(name: Option<&str>, id: &str) -> String {
+    name.map(str::trim)
+        .filter(|name| !name.is_empty())
+        .unwrap_or(id)
+        .chars()
+        .take(MAX_DISPLAY_LEN)
+        .collect()
+}
+
+/// Asks a backend, e.g. one checking Steam tickets with the Web API key
+/// the game server shouldn't hold.
+pub struct HttpAuthenticator {
+    config: AuthCallout,
+}
+
+#[derive(Deserialize)]
+struct CalloutReply {
+    id: String,
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    role: Option<Role>,
+}
+
+impl HttpAuthenticator {
+    pub fn new(config: AuthCallout) -> Self {
+        Self { config }
+    }
+
+    async fn call(&self, credentials: &Credentials) -> Result<Identity, AuthError> {
+        let body = serde_json::json!({
+            "platform": credentials.platform,
+            "token": credentials.token,
+        });
+        let bearer = self.config.secret.as_ref().map(|s| format!("Bearer {s}"));
+        let mut headers = vec![("content-type", "application/json")];
+        if let Some(bearer) = &bearer {
+            headers.push(("authorization", bearer));
+        }
+
+        let reply = match http::post(&self.config.url, &headers, body.to_string()).await {
+            Ok(reply) => reply,
+            Err(HttpError::Status(401 | 403, reason)) => {
+                let reason = match reason.trim() {
+                    "" => "rejected".to_string(),
+                    reason => reason.to_string(),
+                };
+                return Err(AuthError::Rejected(reason));
+            }
+            Err(HttpError::Timeout) => return Err(AuthError::Timeout),
+            Err(e) => return Err(AuthError::Unavailable(e.to_string())),
+        };
+        let reply: CalloutReply = serde_json::from_slice(&reply)
+            .map_err(|e| AuthError::Unavailable(format!("bad reply: {e}")))?;
+        if !valid_id(&reply.id) {
+            return Err(AuthError::Unavailable(format!("bad id {:?}", reply.id)));
+        }
+        let display_name = display_name(reply.name.as_deref(), &reply.id);
+        Ok(Identity {
+            id: reply.id,
+            display_name,
+            role: reply.role,
+        })
+    }
+}
+
+impl Authenticator for HttpAuthenticator {
+    fn authenticate<'a>(&'a self, credentials: &'a Credentials) -> AuthFuture<'a> {
+        Box::pin(self.call(credentials))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Json, Router, http::StatusCode, routing::post};
+    use serde_json::Value;
+
+    #[tokio::test]
+    async fn calls_out_for_tickets() {
+        let app = Router::new().route(
+            "/auth",
+            post(|Json(body): Json<Value>| async move {
+                match (body["platform"].as_str(), body["token"].as_str()) {
+                    (Some("steam"), Some("good")) => Ok(Json(serde_json::json!({
+                        "id": "76561197960287930",
+                        "name": "Gabe",
+                        "role": "admin",
+                    }))),
+                    (None, Some("plain")) => Ok(Json(serde_json::json!({"id": "u1"}))),
+                    (_, Some("broken")) => Ok(Json(serde_json::json!({"id": "has space"}))),
+                    _ => Err((StatusCode::FORBIDDEN, "Ticket expired")),
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let auth = &HttpAuthenticator::new(AuthCallout {
+            url: format!("http://{addr}/auth"),
+            secret: Some("s3cret".into()),
+        });
+        let check = |token: &str, platform: Option<&str>| {
+            let credentials = Credentials {
+                token: token.into(),
+                platform: platform.map(String::from),
+            };
+            async move { auth.authenticate(&credentials).await }
+        };
+        assert_eq!(
+            check("good", Some("steam")).await,
+            Ok(Identity {
+                id: "76561197960287930".into(),
+                display_name: "Gabe".into(),
+                role: Some(Role::Admin),
+            })
+        );
+        assert_eq!(
+            check("plain", None).await.map(|i| (i.display_name, i.role)),
+            Ok(("u1".into(), None))
+        );
+        assert_eq!(
+            check("old", Some("steam")).await,
+            Err(AuthError::Rejected("Ticket expired".into()))
+        );
+        assert!(matches!(
+            check("broken", None).await,
+            Err(AuthError::Unavailable(_))
+        ));
+    }
+}
@@ -0,0 +1,401 @@
+use crate::{
+    config::{BackupConfig, Retention},
+    storage::{Storage, StorageError, unix_now},
+};
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tokio::{sync::Mutex, time::MissedTickBehavior};
+
+/// Snapshots storage and the world's saved chunks and claims into
+/// timestamped directories under the backup dir
+/// (`<dir>/20260115T093000Z/players.db`, `.../world/chunks/0_0_0.tbx`) and
+/// prunes old ones.
+pub struct Backups {
+    config: BackupConfig,
+    storage: Arc<dyn Storage>,
+    world_dir: Option<PathBuf>,
+    // Serializes scheduled and on-demand runs
+    running: Mutex<()>,
+}
+
+impl Backups {
+    pub fn new(
+        config: BackupConfig,
+        storage: Arc<dyn Storage>,
+        world_dir: Option<PathBuf>,
+    ) -> Arc<Self> {
+        #[cfg(not(feature = "s3"))]
+        if config.s3.is_some() {
+            eprintln!("S3 bucket configured but the s3 feature is disabled, uploads skipped");
+        }
+
+        Arc::new(Self {
+            config,
+            storage,
+            world_dir,
+            running: Mutex::new(()),
+        })
+    }
+
+    /// Runs backups every `interval` until the process exits.
+    pub async fn schedule(self: Arc<Self>) {
+        let mut ticker = tokio::time::interval(self.config.interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+        // The first tick completes immediately, don't back up on startup
+        ticker.tick().await;
+
+        loop {
+            ticker.tick().await;
+            match self.run().await {
+                Ok(dir) => println!("Backup written to {}", dir.display()),
+                Err(e) => eprintln!("Backup failed: {e}"),
+            }
+        }
+    }
+
+    /// Takes one backup now, uploads it if configured, then applies retention.
+    pub async fn run(&self) -> Result<PathBuf, StorageError> {
+        self.run_at(unix_now()).await
+    }
+
+    async fn run_at(&self, now: i64) -> Result<PathBuf, StorageError> {
+        let _guard = self.running.lock().await;
+
+        let dir = self.config.dir.join(format_timestamp(now));
+        tokio::fs::create_dir_all(&self.config.dir).await?;
+        // Names only go down to the second, never write into another backup
+        if let Err(e) = tokio::fs::create_dir(&dir).await {
+            return Err(match e.kind() {
+                std::io::ErrorKind::AlreadyExists => {
+                    format!("{} already exists, one backup per second", dir.display()).into()
+                }
+                _ => e.into(),
+            });
+        }
+
+        if let Err(e) = self.write(&dir).await {
+            // Don't leave a half-written backup behind for retention to keep
+            tokio::fs::remove_dir_all(&dir).await.ok();
+            return Err(e);
+        }
+
+        #[cfg(feature = "s3")]
+        if let Some(s3) = &self.config.s3 {
+            upload_s3(s3, &dir).await?;
+        }
+
+        let root = self.config.dir.clone();
+        let retention = self.config.retention;
+        tokio::task::spawn_blocking(move || prune(&root, retention)).await??;
+
+        Ok(dir)
+    }
+
+    async fn write(&self, dir: &Path) -> Result<(), StorageError> {
+        self.storage.backup(&dir.join("players.db")).await?;
+        if let Some(world_dir) = self.world_dir.clone() {
+            let dest = dir.join("world");
+            tokio::task::spawn_blocking(move || copy_world(&world_dir, &dest)).await??;
+        }
+        Ok(())
+    }
+}
+
+// Chunk saves and claims. Both are written to a temporary file and renamed
+// into place, so each copy is whole, if maybe older than its neighbours'
+fn copy_world(world_dir: &Path, dest: &Path) -> std::io::Result<()> {
+    std::fs::create_dir_all(dest.join("chunks"))?;
+    match std::fs::read_dir(world_dir.join("chunks")) {
+        Ok(entries) => {
+            for entry in entries {
+                let path = entry?.path();
+                if path.extension().is_some_and(|e| e == "tbx") {
+                    let name = path.file_name().unwrap_or_default();
+                    std::fs::copy(&path, dest.join("chunks").join(name))?;
+                }
+            }
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+    match std::fs::copy(world_dir.join("claims.tbx"), dest.join("claims.tbx")) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+fn prune(root: &Path, retention: Retention) -> std::io::Result<()> {
+    let mut backups: Vec<(i64, PathBuf)> = std::fs::read_dir(root)?
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            let time = parse_timestamp(entry.file_name().to_str()?)?;
+            Some((time, entry.path()))
+        })
+        .collect();
+
+    let times: Vec<i64> = backups.iter().map(|(t, _)| *t).collect();
+    let keep = retained(&times, retention);
+    backups.retain(|(t, _)| !keep.contains(t));
+
+    for (_, path) in backups {
+        std::fs::remove_dir_all(&path)?;
+    }
+
+    Ok(())
+}
+
+/// Picks which backup timestamps survive the retention policy.
+fn retained(times: &[i64], retention: Retention) -> HashSet<i64> {
+    let mut times = times.to_vec();
+    times.sort_unstable_by(|a, b| b.cmp(a));
+
+    let mut keep: HashSet<i64> = times.iter().take(retention.keep_last).copied().collect();
+
+    // Newest backup per bucket, for the newest N buckets
+    let mut keep_per = |bucket: fn(i64) -> i64, n: usize| {
+        let mut last = None;
+        let mut taken = 0;
+        for &t in times.iter() {
+            if taken == n {
+                break;
+            }
+            if last != Some(bucket(t)) {
+                last = Some(bucket(t));
+                keep.insert(t);
+                taken += 1;
+            }
+        }
+    };
+
+    keep_per(|t| t.div_euclid(86_400), retention.keep_daily);
+    // Unix day 0 was a Thursday, shift so weeks start on Monday
+    keep_per(
+        |t| (t.div_euclid(86_400) + 3).div_euclid(7),
+        retention.keep_weekly,
+    );
+
+    keep
+}
+
+#[cfg(feature = "s3")]
+async fn upload_s3(config: &crate::config::S3Config, dir: &Path) -> Result<(), StorageError> {
+    use s3::{Bucket, Region, creds::Credentials};
+
+    let region = Region::Custom {
+        region: config.region.clone(),
+        endpoint: config.endpoint.clone(),
+    };
+    let bucket = Bucket::new(&config.bucket, region, Credentials::default()?)?.with_path_style();
+    let name = dir.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+
+    let root = dir.to_path_buf();
+    for path in tokio::task::spawn_blocking(move || files(&root)).await?? {
+        let relative = path.strip_prefix(dir)?.components();
+        let relative: Vec<_> = relative.map(|c| c.as_os_str().to_string_lossy()).collect();
+        let key = format!("{}{}/{}", config.prefix, name, relative.join("/"));
+        let data = tokio::fs::read(&path).await?;
+        bucket.put_object(&key, &data).await?;
+    }
+
+    Ok(())
+}
+
+// Every file under `dir`, in subdirectories too
+#[cfg(feature = "s3")]
+fn files(dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut found = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            found.extend(files(&entry.path())?);
+        } else {
+            found.push(entry.path());
+        }
+    }
+    Ok(found)
+}
+
+/// Formats unix seconds as a UTC `YYYYMMDDTHHMMSSZ` name.
+fn format_timestamp(secs: i64) -> String {
+    let (days, rem) = (secs.div_euclid(86_400), secs.rem_euclid(86_400));
+    let (y, m, d) = civil_from_days(days);
+    format!(
+        "{y:04}{m:02}{d:02}T{:02}{:02}{:02}Z",
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+fn parse_timestamp(name: &str) -> Option<i64> {
+    let b = name.as_bytes();
+    if b.len() != 16 || b[8] != b'T' || b[15] != b'Z' {
+        return None;
+    }
+
+    let num = |r: std::ops::Range<usize>| name.get(r)?.parse::<i64>().ok();
+    let days = days_from_civil(num(0..4)?, num(4..6)?, num(6..8)?);
+    Some(days * 86_400 + num(9..11)? * 3600 + num(11..13)? * 60 + num(13..15)?)
+}
+
+// Proleptic Gregorian conversions from Howard Hinnant's date algorithms
+fn civil_from_days(z: i64) -> (i64, i64, i64) {
+    let z = z + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = doy - (153 * mp + 2) / 5 + 1;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 };
+    let y = yoe + era * 400 + i64::from(m <= 2);
+    (y, m, d)
+}
+
+fn days_from_civil(y: i64, m: i64, d: i64) -> i64 {
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = y.div_euclid(400);
+    let yoe = y.rem_euclid(400);
+    let mp = if m > 2 { m - 3 } else { m + 9 };
+    let doy = (153 * mp + 2) / 5 + d - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY: i64 = 86_400;
+    // Monday 2026-01-05, 12:00
+    const MONDAY: i64 = 20_458 * DAY + 12 * 3600;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("teleboxel-{name}-{}", std::process::id()));
+        std::fs::remove_dir_all(&dir).ok();
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn formats_and_parses_timestamps() {
+        assert_eq!(format_timestamp(0), "19700101T000000Z");
+        assert_eq!(format_timestamp(MONDAY + 3723), "20260105T130203Z");
+        // Leap day, and before the epoch
+        assert_eq!(format_timestamp(11_016 * DAY), "20000229T000000Z");
+        assert_eq!(format_timestamp(-1), "19691231T235959Z");
+        for t in [0, -1, MONDAY + 3723, 11_016 * DAY, 4_102_444_799] {
+            assert_eq!(parse_timestamp(&format_timestamp(t)), Some(t));
+        }
+        assert_eq!(parse_timestamp("20260105T130203"), None);
+        assert_eq!(parse_timestamp("20260105-130203Z"), None);
+        assert_eq!(parse_timestamp("players.db"), None);
+    }
+
+    #[test]
+    fn retains_last_daily_and_weekly() {
+        let retention = |keep_last, keep_daily, keep_weekly| Retention {
+            keep_last,
+            keep_daily,
+            keep_weekly,
+        };
+        // Hourly over the last two days, then daily for three weeks back
+        let mut times: Vec<i64> = (0..48).map(|h| MONDAY - h * 3600).collect();
+        times.extend((2..21).map(|d| MONDAY - d * DAY));
+
+        let keep = retained(&times, retention(3, 0, 0));
+        assert_eq!(keep, HashSet::from([MONDAY, MONDAY - 3600, MONDAY - 7200]));
+
+        // The newest of each day: Monday 12:00, Sunday and Saturday 23:00
+        let keep = retained(&times, retention(0, 3, 0));
+        assert_eq!(
+            keep,
+            HashSet::from([MONDAY, MONDAY - 13 * 3600, MONDAY - 37 * 3600])
+        );
+
+        // Weeks start on Monday: this one, then the last backup of each
+        // Sunday before
+        let keep = retained(&times, retention(0, 0, 3));
+        assert_eq!(
+            keep,
+            HashSet::from([MONDAY, MONDAY - 13 * 3600, MONDAY - 8 * DAY])
+        );
+
+        // Overlapping rules keep the union
+        assert_eq!(
+            retained(&times, retention(1, 1, 1)),
+            HashSet::from([MONDAY])
+        );
+        assert!(retained(&times, retention(0, 0, 0)).is_empty());
+        assert_eq!(retained(&times, retention(100, 0, 0)).len(), times.len());
+    }
+
+    #[test]
+    fn prunes_only_backups_out_of_retention() {
+        let root = temp_dir("prune");
+        for t in [MONDAY, MONDAY - 3600, MONDAY - DAY] {
+            std::fs::create_dir(root.join(format_timestamp(t))).unwrap();
+        }
+        // Not a backup, never touched
+        std::fs::create_dir(root.join("notes")).unwrap();
+
+        let retention = Retention {
+            keep_last: 1,
+            keep_daily: 2,
+            keep_weekly: 0,
+        };
+        prune(&root, retention).unwrap();
+        let mut left: Vec<_> = std::fs::read_dir(&root)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect();
+        left.sort();
+        assert_eq!(left, ["20260104T120000Z", "20260105T120000Z", "notes"]);
+
+        std::fs::remove_dir_all(root).ok();
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn backs_up_players_and_the_world_once_a_second() {
+        use crate::{chunk::Chunk, save::write_chunk};
+
+        let root = temp_dir("backups");
+        let world_dir = root.join("world");
+        std::fs::create_dir_all(world_dir.join("chunks")).unwrap();
+        write_chunk(&world_dir, (1, -2, 3), &Chunk::empty()).unwrap();
+        std::fs::write(world_dir.join("claims.tbx"), b"claims").unwrap();
+
+        let url = format!("sqlite://{}", root.join("players.db").display());
+        let storage = crate::storage::connect(&url).await.unwrap();
+        let config = BackupConfig {
+            dir: root.join("backups"),
+            interval: std::time::Duration::from_secs(3600),
+            retention: Retention {
+                keep_last: 5,
+                keep_daily: 0,
+                keep_weekly: 0,
+            },
+            s3: None,
+        };
+        let backups = Backups::new(config, storage, Some(world_dir));
+
+        let dir = backups.run_at(MONDAY).await.unwrap();
+        assert!(dir.join("players.db").is_file());
+        assert!(dir.join("world/chunks/1_-2_3.tbx").is_file());
+        assert_eq!(
+            std::fs::read(dir.join("world/claims.tbx")).unwrap(),
+            b"claims"
+        );
+
+        // Same second: refused, and the first backup is left alone
+        assert!(backups.run_at(MONDAY).await.is_err());
+        assert!(dir.join("players.db").is_file());
+        assert!(backups.run_at(MONDAY + 1).await.is_ok());
+
+        std::fs::remove_dir_all(root).ok();
+    }
+}
@@ -0,0 +1,87 @@
+//! Players blocking other players (`Block Name`). The server stops routing
+//! a blocked player's chat, voice and relay messages to the one who blocked
+//! them, in every world and room. Lists are by name, so they need `?name=`
+//! and only reach named players; anonymous ones can still be muted with
+//! `VoiceMute` for the session. They persist in the player record, as the
+//! comma separated `blocked` property.
+
+use std::collections::{BTreeSet, HashMap};
+
+/// Names each player can block.
+pub const MAX_BLOCKED: usize = 200;
+
+#[derive(Clone, Default, Debug, PartialEq, Eq)]
+pub struct Blocked(BTreeSet<String>);
+
+impl Blocked {
+    /// From the `blocked` record property, empty when missing.
+    pub fn from_properties(properties: &HashMap<String, String>) -> Self {
+        let names = properties
+            .get("blocked")
+            .map(|b| b.split(',').filter(|n| !n.is_empty()).map(String::from))
+            .into_iter()
+            .flatten()
+            .take(MAX_BLOCKED)
+            .collect();
+        Self(names)
+    }
+
+    pub fn to_properties(&self) -> HashMap<String, String> {
+        let names: Vec<_> = self.0.iter().map(String::as_str).collect();
+        HashMap::from([("blocked".to_string(), names.join(","))])
+    }
+
+    /// Whether messages from `sender` are kept from this player. Anonymous
+    /// senders can't be blocked.
+    pub fn blocks(&self, sender: Option<&str>) -> bool {
+        sender.is_some_and(|name| self.0.contains(name))
+    }
+
+    /// `false` if the list is full.
+    pub fn insert(&mut self, name: String) -> bool {
+        if self.0.len() >= MAX_BLOCKED && !self.0.contains(&name) {
+            return false;
+        }
+        self.0.insert(name);
+        true
+    }
+
+    /// `false` if `name` wasn't blocked.
+    pub fn remove(&mut self, name: &str) -> bool {
+        self.0.remove(name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &str> {
+        self.0.iter().map(String::as_str)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_through_properties() {
+        let mut blocked = Blocked::default();
+        assert!(blocked.insert("mallory".into()));
+        assert!(blocked.insert("eve".into()));
+        assert!(blocked.blocks(Some("eve")));
+        assert!(!blocked.blocks(Some("alice")));
+        assert!(!blocked.blocks(None));
+
+        let properties = blocked.to_properties();
+        assert_eq!(properties["blocked"], "eve,mallory");
+        assert_eq!(Blocked::from_properties(&properties), blocked);
+        assert_eq!(
+            Blocked::from_properties(&HashMap::new()),
+            Blocked::default()
+        );
+
+        assert!(blocked.remove("eve"));
+        assert!(!blocked.remove("eve"));
+        for i in 0..MAX_BLOCKED {
+            blocked.insert(format!("p{i}"));
+        }
+        assert!(!blocked.insert("one-more".into()));
+    }
+}
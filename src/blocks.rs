@@ -0,0 +1,316 @@
+//! Block type registry, loaded at startup from a TOML or JSON file
+//! (`TELEBOXEL_BLOCKS`, picked by extension):
+//!
+//! ```toml
+//! [[blocks]]
+//! id = 1
+//! name = "stone"
+//! solid = true
+//! tags = ["natural", "mineable"]
+//! color = "#7f7f7f"  # on minimaps, see minimap.rs
+//! ```
+//!
+//! JSON uses the same shape: `{ "blocks": [{ "id": 1, "name": "stone", ... }] }`.
+//! Id `0` is always air (not solid) and is added when the file omits it.
+//! Without a file the built-in terrain blocks are used. Clients get the
+//! registry at handshake (`BLOCK_REGISTRY`), so ids never drift apart.
+//! Colors stay on the server, blocks without one get one made up from
+//! their id.
+
+use crate::terrain::{AIR, DIRT, GRASS, STONE};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    error::Error,
+    fmt, fs,
+    path::Path,
+};
+
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BlockDef {
+    pub id: u16,
+    pub name: String,
+    #[serde(default = "default_solid")]
+    pub solid: bool,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+fn default_solid() -> bool {
+    true
+}
+
+// A `BlockDef` and its color, which isn't sent
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BlockEntry {
+    id: u16,
+    name: String,
+    #[serde(default = "default_solid")]
+    solid: bool,
+    #[serde(default)]
+    tags: Vec<String>,
+    color: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RegistryFile {
+    blocks: Vec<BlockEntry>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum RegistryError {
+    DuplicateId(u16),
+    DuplicateName(String),
+    /// Names and tags go on the wire with a `u8` length.
+    TooLong(String),
+    /// At most 255 tags per block.
+    TooManyTags(String),
+    /// Id `0` must be non-solid air.
+    SolidAir,
+    /// Colors are `#rrggbb`.
+    BadColor(String),
+}
+
+impl fmt::Display for RegistryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RegistryError::DuplicateId(id) => write!(f, "block id {id} is defined twice"),
+            RegistryError::DuplicateName(name) => write!(f, "block name {name:?} is used twice"),
+            RegistryError::TooLong(s) => write!(f, "{s:?} is longer than 255 bytes"),
+            RegistryError::TooManyTags(name) => write!(f, "block {name:?} has over 255 tags"),
+            RegistryError::SolidAir => write!(f, "block 0 is air and can't be solid"),
+            RegistryError::BadColor(name) => write!(f, "block {name:?} color isn't #rrggbb"),
+        }
+    }
+}
+
+impl Error for RegistryError {}
+
+pub struct BlockRegistry {
+    blocks: BTreeMap<u16, BlockDef>,
+    colors: HashMap<u16, [u8; 3]>,
+}
+
+impl Default for BlockRegistry {
+    /// The blocks the terrain generators place.
+    fn default() -> Self {
+        let def = |id, name: &str, solid| BlockDef {
+            id,
+            name: name.into(),
+            solid,
+            tags: Vec::new(),
+        };
+        let mut registry = Self::new(vec![
+            def(AIR, "air", false),
+            def(STONE, "stone", true),
+            def(DIRT, "dirt", true),
+            def(GRASS, "grass", true),
+        ])
+        .unwrap();
+        registry.colors = HashMap::from([
+            (STONE, [0x7f, 0x7f, 0x7f]),
+            (DIRT, [0x86, 0x60, 0x43]),
+            (GRASS, [0x5a, 0x9e, 0x3a]),
+        ]);
+        registry
+    }
+}
+
+impl BlockRegistry {
+    pub fn new(defs: Vec<BlockDef>) -> Result<Self, RegistryError> {
+        let mut blocks = BTreeMap::new();
+        let mut names = HashSet::new();
+
+        for def in defs {
+            if def.name.len() > 255 {
+                return Err(RegistryError::TooLong(def.name));
+            }
+            if def.tags.len() > 255 {
+                return Err(RegistryError::TooManyTags(def.name));
+            }
+            if let Some(tag) = def.tags.iter().find(|t| t.len() > 255) {
+                return Err(RegistryError::TooLong(tag.clone()));
+            }
+            if def.id == AIR && def.solid {
+                return Err(RegistryError::SolidAir);
+            }
+            if !names.insert(def.name.clone()) {
+                return Err(RegistryError::DuplicateName(def.name));
+            }
+            if let Some(def) = blocks.insert(def.id, def) {
+                return Err(RegistryError::DuplicateId(def.id));
+            }
+        }
+
+        blocks.entry(AIR).or_insert_with(|| BlockDef {
+            id: AIR,
+            name: "air".into(),
+            solid: false,
+            tags: Vec::new(),
+        });
+
+        Ok(Self {
+            blocks,
+            colors: HashMap::new(),
+        })
+    }
+
+    /// Loads a `.toml` or `.json` registry file.
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let text = fs::read_to_string(path)?;
+        let file: RegistryFile = match path.extension().and_then(|e| e.to_str()) {
+            Some("toml") => toml::from_str(&text)?,
+            Some("json") => serde_json::from_str(&text)?,
+            _ => return Err("block registry must be a .toml or .json file".into()),
+        };
+        let mut colors = HashMap::new();
+        let mut defs = Vec::new();
+        for entry in file.blocks {
+            if let Some(color) = &entry.color {
+                let color =
+                    parse_color(color).ok_or(RegistryError::BadColor(entry.name.clone()))?;
+                colors.insert(entry.id, color);
+            }
+            defs.push(BlockDef {
+                id: entry.id,
+                name: entry.name,
+                solid: entry.solid,
+                tags: entry.tags,
+            });
+        }
+        let mut registry = Self::new(defs)?;
+        registry.colors = colors;
+        Ok(registry)
+    }
+
+    pub fn get(&self, id: u16) -> Option<&BlockDef> {
+        self.blocks.get(&id)
+    }
+
+    pub fn contains(&self, id: u16) -> bool {
+        self.blocks.contains_key(&id)
+    }
+
+    /// Unknown ids are solid, so stray data never lets players pass.
+    pub fn is_solid(&self, id: u16) -> bool {
+        self.get(id).is_none_or(|b| b.solid)
+    }
+
+    pub fn has_tag(&self, id: u16, tag: &str) -> bool {
+        self.get(id)
+            .is_some_and(|b| b.tags.iter().any(|t| t == tag))
+    }
+
+    /// The block's minimap color, made up from the id without one.
+    pub fn color(&self, id: u16) -> [u8; 3] {
+        if let Some(&color) = self.colors.get(&id) {
+            return color;
+        }
+        // Spread ids apart, kept away from black (unknown on minimaps)
+        let [r, g, b, _] = (id as u32).wrapping_mul(0x9e37_79b9).to_le_bytes();
+        [r | 0x40, g | 0x40, b | 0x40]
+    }
+
+    /// Blocks in id order.
+    pub fn iter(&self) -> impl ExactSizeIterator<Item = &BlockDef> {
+        self.blocks.values()
+    }
+
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+}
+
+fn parse_color(color: &str) -> Option<[u8; 3]> {
+    let hex = color.strip_prefix('#')?;
+    if hex.len() != 6 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    let rgb = u32::from_str_radix(hex, 16).ok()?;
+    let [_, r, g, b] = rgb.to_be_bytes();
+    Some([r, g, b])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOML: &str = r##"
+        [[blocks]]
+        id = 1
+        name = "stone"
+        tags = ["natural", "mineable"]
+        color = "#808080"
+
+        [[blocks]]
+        id = 9
+        name = "water"
+        solid = false
+        tags = ["liquid"]
+    "##;
+
+    #[test]
+    fn loads_toml_and_json_alike() {
+        let dir = std::env::temp_dir().join(format!("teleboxel-blocks-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let toml_path = dir.join("blocks.toml");
+        fs::write(&toml_path, TOML).unwrap();
+        let json_path = dir.join("blocks.json");
+        fs::write(
+            &json_path,
+            r##"{ "blocks": [
+                { "id": 1, "name": "stone", "tags": ["natural", "mineable"], "color": "#808080" },
+                { "id": 9, "name": "water", "solid": false, "tags": ["liquid"] }
+            ] }"##,
+        )
+        .unwrap();
+
+        let from_toml = BlockRegistry::load(&toml_path).unwrap();
+        let from_json = BlockRegistry::load(&json_path).unwrap();
+        fs::remove_dir_all(&dir).ok();
+
+        for registry in [from_toml, from_json] {
+            let ids: Vec<u16> = registry.iter().map(|b| b.id).collect();
+            assert_eq!(ids, [0, 1, 9], "air is added");
+            assert!(registry.is_solid(1));
+            assert!(!registry.is_solid(9));
+            assert!(!registry.is_solid(0));
+            assert!(registry.is_solid(42), "unknown ids are solid");
+            assert!(registry.has_tag(1, "mineable"));
+            assert!(!registry.contains(2));
+            assert_eq!(registry.color(1), [0x80, 0x80, 0x80]);
+            assert_ne!(registry.color(9), [0, 0, 0]);
+        }
+    }
+
+    #[test]
+    fn rejects_inconsistent_registries() {
+        let def = |id, name: &str, solid| BlockDef {
+            id,
+            name: name.into(),
+            solid,
+            tags: Vec::new(),
+        };
+
+        assert_eq!(
+            BlockRegistry::new(vec![def(1, "a", true), def(1, "b", true)]).err(),
+            Some(RegistryError::DuplicateId(1))
+        );
+        assert_eq!(
+            BlockRegistry::new(vec![def(1, "a", true), def(2, "a", true)]).err(),
+            Some(RegistryError::DuplicateName("a".into()))
+        );
+        assert_eq!(
+            BlockRegistry::new(vec![def(0, "void", true)]).err(),
+            Some(RegistryError::SolidAir)
+        );
+    }
+}
@@ -0,0 +1,154 @@
+//! Brains for server-driven entities. A brain thinks `TELEBOXEL_THINK_HZ`
+//! times a second, not every tick: the turns are staggered by entity id, so
+//! a world's brains spread over the ticks in between instead of all
+//! thinking on the same one. Thinking is time-sliced too, each tick stops
+//! handing out turns once it spent `TELEBOXEL_THINK_BUDGET_US` on them; the
+//! brains left over go first on the next tick.
+//!
+//! The one brain so far walks an entity to a goal
+//! (`PUT /admin/entities/<id>/goal`): it asks for a route (see
+//! `pathfinding.rs`), steps one block along it per turn, searches again if
+//! something else moved the entity, and is dropped once there or when there's
+//! no route. Only entities under the server's authority walk.
+
+use crate::{
+    claims::BlockPos,
+    pathfinding::{PathError, Route},
+};
+use std::collections::{HashSet, VecDeque};
+use tokio::sync::oneshot::{self, error::TryRecvError};
+
+/// Ticks between a brain's turns.
+pub fn period(tick_hz: u32, think_hz: u32) -> u64 {
+    (tick_hz / think_hz.max(1)).max(1) as u64
+}
+
+/// Whose turn it is, per world.
+#[derive(Default)]
+pub struct Scheduler {
+    // Out of budget last tick
+    late: VecDeque<u32>,
+    /// Turns put off to a later tick so far, on slow tick spans.
+    pub deferred: u64,
+}
+
+impl Scheduler {
+    /// The brains to think at `tick`: the late ones, then those whose turn
+    /// it is among `ids`.
+    pub fn due(&mut self, tick: u64, period: u64, ids: impl Iterator<Item = u32>) -> Vec<u32> {
+        let mut due: Vec<_> = self.late.drain(..).collect();
+        let late: HashSet<_> = due.iter().copied().collect();
+        let turn = tick % period;
+        due.extend(ids.filter(|&id| id as u64 % period == turn && !late.contains(&id)));
+        due
+    }
+
+    /// Ran out of time before `ids` thought, they go first next tick.
+    pub fn defer(&mut self, ids: &[u32]) {
+        self.deferred += ids.len() as u64;
+        self.late.extend(ids);
+    }
+}
+
+/// What a brain wants done this turn.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Thought {
+    /// Start a route search to the goal, hand it over with `searching`.
+    Search(BlockPos),
+    /// Move the entity here.
+    Step(BlockPos),
+    /// A search is running.
+    Wait,
+    Arrived,
+    Stuck(PathError),
+}
+
+/// Walks an entity to `goal`.
+pub struct Walker {
+    pub goal: BlockPos,
+    // The route and where on it the entity is
+    route: Option<(Route, usize)>,
+    search: Option<oneshot::Receiver<Result<Route, PathError>>>,
+}
+
+impl Walker {
+    pub fn new(goal: BlockPos) -> Self {
+        Self {
+            goal,
+            route: None,
+            search: None,
+        }
+    }
+
+    /// The entity is `at`.
+    pub fn think(&mut self, at: BlockPos) -> Thought {
+        if at == self.goal {
+            return Thought::Arrived;
+        }
+        if let Some(search) = &mut self.search {
+            match search.try_recv() {
+                Ok(Ok(route)) => self.route = Some((route, 0)),
+                Ok(Err(e)) => return Thought::Stuck(e),
+                Err(TryRecvError::Empty) => return Thought::Wait,
+                Err(TryRecvError::Closed) => return Thought::Stuck(PathError::NoRoute),
+            }
+            self.search = None;
+        }
+        // Off the route, or none yet
+        let Some((route, step)) = &mut self.route else {
+            return Thought::Search(self.goal);
+        };
+        match route.iter().skip(*step).position(|&p| p == at) {
+            Some(i) if *step + i + 1 < route.len() => {
+                *step += i + 1;
+                Thought::Step(route[*step])
+            }
+            _ => {
+                self.route = None;
+                Thought::Search(self.goal)
+            }
+        }
+    }
+
+    /// Where the search asked for with `Thought::Search` will answer.
+    pub fn searching(&mut self, search: oneshot::Receiver<Result<Route, PathError>>) {
+        self.search = Some(search);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn staggers_turns_and_walks_routes() {
+        let mut scheduler = Scheduler::default();
+        let period = period(60, 10);
+        assert_eq!(period, 6);
+        let ids = || [1, 2, 7, 13].into_iter();
+        assert_eq!(scheduler.due(7, period, ids()), [1, 7, 13]);
+        assert_eq!(scheduler.due(8, period, ids()), [2]);
+        scheduler.defer(&[2]);
+        assert_eq!(scheduler.due(9, period, ids()), [2]);
+        assert!(scheduler.due(10, period, ids()).is_empty());
+        assert_eq!(scheduler.deferred, 1);
+
+        let mut walker = Walker::new((3, 1, 0));
+        assert_eq!(walker.think((0, 1, 0)), Thought::Search((3, 1, 0)));
+        let (tx, rx) = oneshot::channel();
+        walker.searching(rx);
+        assert_eq!(walker.think((0, 1, 0)), Thought::Wait);
+        let route = vec![(0, 1, 0), (1, 1, 0), (2, 2, 0), (3, 1, 0)];
+        tx.send(Ok(Arc::new(route))).unwrap();
+        assert_eq!(walker.think((0, 1, 0)), Thought::Step((1, 1, 0)));
+        assert_eq!(walker.think((1, 1, 0)), Thought::Step((2, 2, 0)));
+        // Pushed off the route
+        assert_eq!(walker.think((5, 1, 5)), Thought::Search((3, 1, 0)));
+        let (tx, rx) = oneshot::channel();
+        walker.searching(rx);
+        tx.send(Err(PathError::NoRoute)).unwrap();
+        assert_eq!(walker.think((5, 1, 5)), Thought::Stuck(PathError::NoRoute));
+        assert_eq!(walker.think((3, 1, 0)), Thought::Arrived);
+    }
+}
@@ -0,0 +1,320 @@
+//! Chat bridges to Telegram and Discord, configured per room in a TOML file
+//! (`TELEBOXEL_BRIDGES`):
+//!
+//! ```toml
+//! [[telegram]]
+//! room = "main"
+//! api = "https://api.telegram.org"  # or your own Bot API server
+//! token = "123456:ABC..."
+//! chat_id = -1001234567890
+//!
+//! [[discord]]
+//! room = "arena"
+//! webhook = "https://discord.com/api/webhooks/1/abc"
+//! ```
+//!
+//! Chat, joins and leaves in the room go to the channel, and every bridge
+//! posts server start and stop. Telegram messages in the chat come back into
+//! the room as `telegram:<user>` (long polling `getUpdates`). Discord is
+//! outbound only: reading a channel needs its gateway, a TLS websocket.
+
+use crate::http;
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::{
+    error::Error,
+    fs,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{sync::mpsc, task::JoinHandle};
+
+// Lines queued per bridge, newer lines are dropped when full
+const QUEUE: usize = 256;
+// `getUpdates` long poll, under the HTTP client timeout
+const POLL_SECS: u64 = 5;
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct BridgeConfig {
+    #[serde(default)]
+    pub telegram: Vec<TelegramConfig>,
+    #[serde(default)]
+    pub discord: Vec<DiscordConfig>,
+}
+
+impl BridgeConfig {
+    /// See the module docs for the file format.
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        Ok(toml::from_str(&fs::read_to_string(path)?)?)
+    }
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct TelegramConfig {
+    pub room: String,
+    pub api: String,
+    pub token: String,
+    pub chat_id: i64,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct DiscordConfig {
+    pub room: String,
+    pub webhook: String,
+}
+
+pub enum BridgeEvent<'a> {
+    Chat {
+        from: &'a str,
+        text: &'a str,
+        /// Index of the bridge the line came from, which doesn't get it back.
+        bridge: Option<usize>,
+    },
+    Joined {
+        name: &'a str,
+    },
+    Left {
+        name: &'a str,
+    },
+    ServerStarted,
+    ServerStopping,
+}
+
+/// A line from a bridged channel: room, bridge index, sender and text.
+pub type Inbound = Arc<dyn Fn(&str, usize, String, String) + Send + Sync>;
+
+struct Line {
+    from: Option<String>,
+    text: String,
+}
+
+enum Target {
+    Telegram(TelegramConfig),
+    Discord(DiscordConfig),
+}
+
+impl Target {
+    fn room(&self) -> &str {
+        match self {
+            Target::Telegram(t) => &t.room,
+            Target::Discord(d) => &d.room,
+        }
+    }
+}
+
+struct Bridge {
+    room: String,
+    tx: mpsc::Sender<Line>,
+}
+
+pub struct Bridges {
+    bridges: Mutex<Vec<Bridge>>,
+    workers: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl Bridges {
+    /// Starts a sender task per bridge, and a poller per Telegram bridge
+    /// handing incoming lines to `inbound`.
+    pub fn start(config: BridgeConfig, inbound: Inbound) -> Self {
+        let targets = config
+            .telegram
+            .into_iter()
+            .map(Target::Telegram)
+            .chain(config.discord.into_iter().map(Target::Discord));
+
+        let mut bridges = Vec::new();
+        let mut workers = Vec::new();
+        for (index, target) in targets.enumerate() {
+            let (tx, rx) = mpsc::channel(QUEUE);
+            bridges.push(Bridge {
+                room: target.room().to_string(),
+                tx,
+            });
+            if let Target::Telegram(config) = &target {
+                // Ends with the process, nothing to flush
+                tokio::spawn(poll_telegram(config.clone(), index, inbound.clone()));
+            }
+            workers.push(tokio::spawn(send(target, rx)));
+        }
+
+        Self {
+            bridges: Mutex::new(bridges),
+            workers: Mutex::new(workers),
+        }
+    }
+
+    /// Posts `event` to the bridges of `room`, or every bridge for server
+    /// start and stop.
+    pub fn notify(&self, room: &str, event: BridgeEvent) {
+        let (from, text, origin) = match event {
+            BridgeEvent::Chat { from, text, bridge } => {
+                (Some(from.to_string()), text.to_string(), bridge)
+            }
+            BridgeEvent::Joined { name } => (None, format!("{name} joined {room}"), None),
+            BridgeEvent::Left { name } => (None, format!("{name} left {room}"), None),
+            BridgeEvent::ServerStarted => (None, "Server started".to_string(), None),
+            BridgeEvent::ServerStopping => (None, "Server stopping".to_string(), None),
+        };
+        let everywhere = matches!(
+            event,
+            BridgeEvent::ServerStarted | BridgeEvent::ServerStopping
+        );
+
+        for (index, bridge) in self.bridges.lock().unwrap().iter().enumerate() {
+            if (!everywhere && bridge.room != room) || origin == Some(index) {
+                continue;
+            }
+            let line = Line {
+                from: from.clone(),
+                text: text.clone(),
+            };
+            if bridge.tx.try_send(line).is_err() {
+                eprintln!("Bridge queue full for room {}, dropped a line", bridge.room);
+            }
+        }
+    }
+
+    /// Stops taking lines and waits up to `timeout` for queued ones.
+    pub async fn shutdown(&self, timeout: Duration) {
+        self.bridges.lock().unwrap().clear();
+        let workers = std::mem::take(&mut *self.workers.lock().unwrap());
+        let all = async {
+            for worker in workers {
+                worker.await.ok();
+            }
+        };
+        tokio::time::timeout(timeout, all).await.ok();
+    }
+}
+
+async fn send(target: Target, mut rx: mpsc::Receiver<Line>) {
+    let headers = [("content-type", "application/json")];
+    while let Some(line) = rx.recv().await {
+        let (url, body) = request(&target, line);
+        if let Err(e) = http::post(&url, &headers, body.to_string()).await {
+            eprintln!("Bridge for room {} failed: {e}", target.room());
+        }
+    }
+}
+
+// URL and JSON body posting a line
+fn request(target: &Target, line: Line) -> (String, Value) {
+    match target {
+        Target::Telegram(t) => {
+            let text = match line.from {
+                Some(from) => format!("{from}: {}", line.text),
+                None => line.text,
+            };
+            let url = format!("{}/bot{}/sendMessage", t.api.trim_end_matches('/'), t.token);
+            (url, json!({ "chat_id": t.chat_id, "text": text }))
+        }
+        Target::Discord(d) => {
+            let mut body = json!({
+                "content": line.text,
+                // No pings from chat lines
+                "allowed_mentions": { "parse": [] },
+            });
+            if let Some(from) = line.from {
+                body["username"] = from.into();
+            }
+            (d.webhook.clone(), body)
+        }
+    }
+}
+
+async fn poll_telegram(config: TelegramConfig, index: usize, inbound: Inbound) {
+    let url = format!(
+        "{}/bot{}/getUpdates",
+        config.api.trim_end_matches('/'),
+        config.token
+    );
+    let headers = [("content-type", "application/json")];
+    // Skips what was said while the server was down
+    let mut offset: i64 = -1;
+    let mut skip = true;
+
+    loop {
+        let timeout = if skip { 0 } else { POLL_SECS };
+        let body = json!({ "offset": offset, "timeout": timeout, "allowed_updates": ["message"] });
+        let updates = match http::post(&url, &headers, body.to_string()).await {
+            Ok(response) => serde_json::from_slice::<Value>(&response).unwrap_or_default(),
+            Err(e) => {
+                eprintln!("Telegram bridge for room {}: {e}", config.room);
+                tokio::time::sleep(Duration::from_secs(POLL_SECS)).await;
+                continue;
+            }
+        };
+
+        let updates = updates["result"].as_array().cloned().unwrap_or_default();
+        for update in &updates {
+            if let Some(id) = update["update_id"].as_i64() {
+                offset = offset.max(id + 1);
+            }
+            if skip {
+                continue;
+            }
+            if let Some((from, text)) = telegram_line(&config, update) {
+                inbound(&config.room, index, from, text);
+            }
+        }
+        skip = false;
+    }
+}
+
+// Sender and text of a message in the bridged chat, not from a bot
+fn telegram_line(config: &TelegramConfig, update: &Value) -> Option<(String, String)> {
+    let message = &update["message"];
+    if message["chat"]["id"].as_i64()? != config.chat_id || message["from"]["is_bot"] == true {
+        return None;
+    }
+    let text = message["text"].as_str()?;
+    let user = message["from"]["username"]
+        .as_str()
+        .or(message["from"]["first_name"].as_str())?;
+    Some((format!("telegram:{user}"), text.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_and_filters_lines() {
+        let telegram = TelegramConfig {
+            room: "main".into(),
+            api: "http://localhost:8081/".into(),
+            token: "1:abc".into(),
+            chat_id: -42,
+        };
+        let (url, body) = request(
+            &Target::Telegram(telegram.clone()),
+            Line {
+                from: Some("alice".into()),
+                text: "hi".into(),
+            },
+        );
+        assert_eq!(url, "http://localhost:8081/bot1:abc/sendMessage");
+        assert_eq!(body, json!({ "chat_id": -42, "text": "alice: hi" }));
+
+        let update = |chat: i64, is_bot: bool| {
+            json!({
+                "update_id": 9,
+                "message": {
+                    "chat": { "id": chat },
+                    "from": { "is_bot": is_bot, "first_name": "Bob", "username": "bob" },
+                    "text": "hello",
+                },
+            })
+        };
+        assert_eq!(
+            telegram_line(&telegram, &update(-42, false)),
+            Some(("telegram:bob".into(), "hello".into()))
+        );
+        assert_eq!(telegram_line(&telegram, &update(7, false)), None);
+        assert_eq!(telegram_line(&telegram, &update(-42, true)), None);
+    }
+}
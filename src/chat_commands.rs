@@ -0,0 +1,462 @@
+//! Chat commands, typed as `Say /<name> args...`. Each one declares typed
+//! parameters, parsed before it runs so mistakes get the usage back, and
+//! the lowest role that may run it (see `roles.rs`). Built in:
+//!
+//! - `/help [command]`, `/where`
+//! - `/tp <x y z|player>`, `/give <item> [count]` for moderators
+//! - `/kick`, `/mute`, `/unmute` and `/role`, the moderation text commands
+//!
+//! More are added with `ChatCommands::register` before the server starts.
+//! There's no simulation trait or scripting to call it yet. Handlers run in
+//! the world task of the player's world or room, through `CommandWorld`.
+
+use crate::roles::{MAX_MUTE_MINUTES, Role};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Write,
+    sync::Arc,
+};
+
+/// Most items `/give` hands out at once.
+pub const MAX_GIVE: i64 = 999;
+
+pub type Handler =
+    Arc<dyn Fn(&mut dyn CommandWorld, &Call) -> Result<String, String> + Send + Sync>;
+
+/// What chat command handlers can do to the world they run in.
+pub trait CommandWorld {
+    /// `main` for the main world.
+    fn world_name(&self) -> String;
+    fn position(&self, id: u32) -> Option<(i32, i32, i32)>;
+    /// A player in this world by name.
+    fn find(&self, name: &str) -> Option<u32>;
+    /// Moves player `id` and tells its client.
+    fn teleport(&mut self, id: u32, to: (i32, i32, i32)) -> Result<(), String>;
+    /// Adds to player `id`'s inventory, kept in its record.
+    fn give(&mut self, id: u32, item: u16, count: u32) -> Result<(), String>;
+}
+
+pub enum Run {
+    /// In the world task the player is in, the Ok text is the reply.
+    World(Handler),
+    /// Expands to one of the moderation text commands (see `command.rs`),
+    /// run as if sent.
+    Command(fn(&Call) -> String),
+    /// Lists the commands, answered by `ChatCommands::help`.
+    Help,
+}
+
+pub struct ChatCommand {
+    pub name: String,
+    /// One line, for `/help <name>`.
+    pub help: String,
+    pub params: Vec<Param>,
+    pub role: Role,
+    pub run: Run,
+}
+
+pub struct Param {
+    pub name: String,
+    pub kind: Kind,
+    pub optional: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    /// Inclusive range.
+    Int { min: i64, max: i64 },
+    /// One word.
+    Word,
+    /// The rest of the line, last.
+    Text,
+    /// `x y z` block coordinates, or a player name.
+    Place,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Arg {
+    Int(i64),
+    Text(String),
+    Place(Place),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Place {
+    At((i32, i32, i32)),
+    Player(String),
+}
+
+/// One run of a command, by player `id`.
+pub struct Call {
+    pub id: u32,
+    pub name: Option<String>,
+    pub role: Role,
+    /// By parameter name, optional ones missing when not given.
+    pub args: HashMap<String, Arg>,
+}
+
+impl Param {
+    pub fn new(name: &str, kind: Kind) -> Self {
+        Self {
+            name: name.to_string(),
+            kind,
+            optional: false,
+        }
+    }
+
+    pub fn optional(name: &str, kind: Kind) -> Self {
+        Self {
+            optional: true,
+            ..Self::new(name, kind)
+        }
+    }
+}
+
+impl Call {
+    pub fn int(&self, param: &str) -> Option<i64> {
+        match self.args.get(param)? {
+            Arg::Int(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    pub fn text(&self, param: &str) -> Option<&str> {
+        match self.args.get(param)? {
+            Arg::Text(text) => Some(text),
+            _ => None,
+        }
+    }
+
+    pub fn place(&self, param: &str) -> Option<&Place> {
+        match self.args.get(param)? {
+            Arg::Place(place) => Some(place),
+            _ => None,
+        }
+    }
+}
+
+impl ChatCommand {
+    /// E.g. `/give <item> [count]`.
+    pub fn usage(&self) -> String {
+        let mut usage = format!("/{}", self.name);
+        for param in &self.params {
+            match param.optional {
+                true => write!(usage, " [{}]", param.name).unwrap(),
+                false => write!(usage, " <{}>", param.name).unwrap(),
+            }
+        }
+        usage
+    }
+
+    /// Typed arguments by parameter name, or what's wrong with the usage.
+    pub fn parse(&self, args: &str) -> Result<HashMap<String, Arg>, String> {
+        let fail = |e: String| format!("{e}. Usage: {}", self.usage());
+        let mut words = args.split(' ').filter(|w| !w.is_empty()).peekable();
+        let mut parsed = HashMap::new();
+
+        for param in &self.params {
+            let Some(&word) = words.peek() else {
+                if param.optional {
+                    continue;
+                }
+                return Err(fail(format!("Missing <{}>", param.name)));
+            };
+            let arg = match param.kind {
+                Kind::Text => Arg::Text(words.by_ref().collect::<Vec<_>>().join(" ")),
+                Kind::Place if word.parse::<i32>().is_ok() => {
+                    let xyz: Vec<_> = words.by_ref().take(3).map(str::parse::<i32>).collect();
+                    match xyz[..] {
+                        [Ok(x), Ok(y), Ok(z)] => Arg::Place(Place::At((x, y, z))),
+                        _ => {
+                            return Err(fail(format!(
+                                "<{}> must be x y z or a player name",
+                                param.name
+                            )));
+                        }
+                    }
+                }
+                Kind::Place => Arg::Place(Place::Player(words.next().unwrap().to_string())),
+                Kind::Word => Arg::Text(words.next().unwrap().to_string()),
+                Kind::Int { min, max } => match words.next().unwrap().parse::<i64>() {
+                    Ok(n) if (min..=max).contains(&n) => Arg::Int(n),
+                    _ => {
+                        return Err(fail(format!(
+                            "<{}> must be a number from {min} to {max}, not {word}",
+                            param.name
+                        )));
+                    }
+                },
+            };
+            parsed.insert(param.name.clone(), arg);
+        }
+
+        if words.next().is_some() {
+            return Err(fail("Too many arguments".to_string()));
+        }
+        Ok(parsed)
+    }
+}
+
+/// The chat commands by name.
+pub struct ChatCommands(BTreeMap<String, Arc<ChatCommand>>);
+
+impl Default for ChatCommands {
+    /// The built-in commands.
+    fn default() -> Self {
+        let mut commands = Self(BTreeMap::new());
+        for command in builtin() {
+            commands.register(command).unwrap();
+        }
+        commands
+    }
+}
+
+impl ChatCommands {
+    /// `Err` if the name is taken or isn't one word.
+    pub fn register(&mut self, command: ChatCommand) -> Result<(), String> {
+        if command.name.is_empty() || command.name.contains(' ') {
+            return Err(format!("Invalid command name {:?}", command.name));
+        }
+        if self.0.contains_key(&command.name) {
+            return Err(format!("/{} is already registered", command.name));
+        }
+        self.0.insert(command.name.clone(), Arc::new(command));
+        Ok(())
+    }
+
+    /// The command and its arguments from a chat line without the `/`.
+    pub fn parse(&self, line: &str) -> Result<(Arc<ChatCommand>, HashMap<String, Arg>), String> {
+        let (name, args) = line.split_once(' ').unwrap_or((line, ""));
+        let Some(command) = self.0.get(name) else {
+            return Err(format!("Unknown command /{name}, see /help"));
+        };
+        Ok((command.clone(), command.parse(args)?))
+    }
+
+    /// The commands `role` may run, or one command's usage and help.
+    pub fn help(&self, role: Role, name: Option<&str>) -> Result<String, String> {
+        let Some(name) = name else {
+            let names: Vec<_> = self
+                .0
+                .values()
+                .filter(|c| c.role <= role)
+                .map(|c| format!("/{}", c.name))
+                .collect();
+            return Ok(names.join(" "));
+        };
+        let name = name.trim_start_matches('/');
+        match self.0.get(name) {
+            Some(command) => Ok(format!("{}: {}", command.usage(), command.help)),
+            None => Err(format!("Unknown command /{name}")),
+        }
+    }
+}
+
+fn builtin() -> Vec<ChatCommand> {
+    let command = |name: &str, help: &str, params, role, run| ChatCommand {
+        name: name.to_string(),
+        help: help.to_string(),
+        params,
+        role,
+        run,
+    };
+    let name = || Param::new("player", Kind::Word);
+    vec![
+        command(
+            "help",
+            "Lists the commands you can run, or how to use one",
+            vec![Param::optional("command", Kind::Word)],
+            Role::Player,
+            Run::Help,
+        ),
+        command(
+            "where",
+            "Your block position and world",
+            vec![],
+            Role::Player,
+            Run::World(Arc::new(|world, call| {
+                let (x, y, z) = world.position(call.id).ok_or("Not in this world")?;
+                Ok(format!("{x} {y} {z} in {}", world.world_name()))
+            })),
+        ),
+        command(
+            "tp",
+            "Teleports you to a block position or a player in this world",
+            vec![Param::new("place", Kind::Place)],
+            Role::Moderator,
+            Run::World(Arc::new(|world, call| {
+                let to = match call.place("place").unwrap() {
+                    Place::At(position) => *position,
+                    Place::Player(name) => world
+                        .find(name)
+                        .and_then(|id| world.position(id))
+                        .ok_or_else(|| format!("{name} isn't in this world"))?,
+                };
+                world.teleport(call.id, to)?;
+                let (x, y, z) = to;
+                Ok(format!("Teleported to {x} {y} {z}"))
+            })),
+        ),
+        command(
+            "give",
+            "Puts items in your inventory",
+            vec![
+                Param::new(
+                    "item",
+                    Kind::Int {
+                        min: 0,
+                        max: u16::MAX.into(),
+                    },
+                ),
+                Param::optional(
+                    "count",
+                    Kind::Int {
+                        min: 1,
+                        max: MAX_GIVE,
+                    },
+                ),
+            ],
+            Role::Moderator,
+            Run::World(Arc::new(|world, call| {
+                let item = call.int("item").unwrap() as u16;
+                let count = call.int("count").unwrap_or(1) as u32;
+                world.give(call.id, item, count)?;
+                Ok(format!("Gave {count} of item {item}"))
+            })),
+        ),
+        command(
+            "kick",
+            "Disconnects a player",
+            vec![name(), Param::optional("reason", Kind::Text)],
+            Role::Moderator,
+            Run::Command(|call| {
+                let (name, reason) = (call.text("player").unwrap(), call.text("reason"));
+                format!("Kick {name} {}", reason.unwrap_or_default())
+                    .trim_end()
+                    .to_string()
+            }),
+        ),
+        command(
+            "mute",
+            "Keeps a player out of chat and voice",
+            vec![
+                name(),
+                Param::new(
+                    "minutes",
+                    Kind::Int {
+                        min: 1,
+                        max: MAX_MUTE_MINUTES.into(),
+                    },
+                ),
+            ],
+            Role::Moderator,
+            Run::Command(|call| {
+                let (name, minutes) = (call.text("player").unwrap(), call.int("minutes").unwrap());
+                format!("Mute {name} {minutes}")
+            }),
+        ),
+        command(
+            "unmute",
+            "Lets a muted player talk again",
+            vec![name()],
+            Role::Moderator,
+            Run::Command(|call| format!("Unmute {}", call.text("player").unwrap())),
+        ),
+        command(
+            "role",
+            "Sets a player's role: player, moderator or admin",
+            vec![name(), Param::new("role", Kind::Word)],
+            Role::Admin,
+            Run::Command(|call| {
+                let (name, role) = (call.text("player").unwrap(), call.text("role").unwrap());
+                format!("SetRole {name} {role}")
+            }),
+        ),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct TestWorld {
+        positions: HashMap<u32, (i32, i32, i32)>,
+    }
+
+    impl CommandWorld for TestWorld {
+        fn world_name(&self) -> String {
+            "main".into()
+        }
+
+        fn position(&self, id: u32) -> Option<(i32, i32, i32)> {
+            self.positions.get(&id).copied()
+        }
+
+        fn find(&self, name: &str) -> Option<u32> {
+            (name == "bob").then_some(2)
+        }
+
+        fn teleport(&mut self, id: u32, to: (i32, i32, i32)) -> Result<(), String> {
+            self.positions.insert(id, to);
+            Ok(())
+        }
+
+        fn give(&mut self, _: u32, _: u16, _: u32) -> Result<(), String> {
+            Err("No inventory".into())
+        }
+    }
+
+    #[test]
+    fn parses_typed_arguments_and_runs() {
+        let commands = ChatCommands::default();
+        let usage = |line| commands.parse(line).err().unwrap();
+        assert_eq!(
+            usage("give x"),
+            "<item> must be a number from 0 to 65535, not x. Usage: /give <item> [count]"
+        );
+        assert_eq!(
+            usage("tp 1 2"),
+            "<place> must be x y z or a player name. Usage: /tp <place>"
+        );
+        assert_eq!(usage("where now"), "Too many arguments. Usage: /where");
+        assert_eq!(
+            usage("mute bob"),
+            "Missing <minutes>. Usage: /mute <player> <minutes>"
+        );
+        assert_eq!(usage("fly"), "Unknown command /fly, see /help");
+
+        assert_eq!(commands.help(Role::Player, None).unwrap(), "/help /where");
+        assert!(commands.help(Role::Admin, None).unwrap().contains("/role"));
+        assert_eq!(
+            commands.help(Role::Player, Some("/kick")).unwrap(),
+            "/kick <player> [reason]: Disconnects a player"
+        );
+
+        let mut world = TestWorld::default();
+        world.positions.extend([(1, (0, 0, 0)), (2, (5, 6, 7))]);
+        let mut run = |line| {
+            let (command, args) = commands.parse(line).unwrap();
+            let call = Call {
+                id: 1,
+                name: Some("alice".into()),
+                role: Role::Admin,
+                args,
+            };
+            match &command.run {
+                Run::World(handler) => handler(&mut world, &call),
+                Run::Command(expand) => Ok(expand(&call)),
+                Run::Help => unreachable!(),
+            }
+        };
+        assert_eq!(run("tp bob"), Ok("Teleported to 5 6 7".into()));
+        assert_eq!(run("where"), Ok("5 6 7 in main".into()));
+        assert_eq!(run("tp 1 -2 3"), Ok("Teleported to 1 -2 3".into()));
+        assert_eq!(run("give 3 10"), Err("No inventory".into()));
+        assert_eq!(
+            run("kick bob  being rude"),
+            Ok("Kick bob being rude".into())
+        );
+        assert_eq!(run("mute bob 5"), Ok("Mute bob 5".into()));
+    }
+}
@@ -0,0 +1,146 @@
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, sync::Arc};
+
+/// Chunk edge length in blocks (v0: 16x16x16).
+pub const CHUNK_SIZE: usize = 16;
+pub const CHUNK_VOLUME: usize = CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE;
+
+/// Chunk coordinates (world block coordinates divided by `CHUNK_SIZE`).
+pub type ChunkPos = (i32, i32, i32);
+
+/// 4096 block ids, `0` is air. Indexed y-major: `y * 256 + z * 16 + x`.
+///
+/// Blocks are shared copy-on-write, so cloning is cheap: a snapshot handed
+/// to the saver or a sender costs nothing until the next `set` copies it.
+/// Serde (the JSON protocol) sees the block ids as a flat array.
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(try_from = "Vec<u16>", into = "Vec<u16>")]
+pub struct Chunk {
+    blocks: Arc<[u16]>,
+}
+
+impl Chunk {
+    pub fn empty() -> Self {
+        Self {
+            blocks: vec![0; CHUNK_VOLUME].into(),
+        }
+    }
+
+    /// Builds a chunk from exactly `CHUNK_VOLUME` block ids.
+    pub fn from_blocks(blocks: Vec<u16>) -> Option<Self> {
+        (blocks.len() == CHUNK_VOLUME).then(|| Self {
+            blocks: blocks.into(),
+        })
+    }
+
+    pub fn index(x: usize, y: usize, z: usize) -> usize {
+        debug_assert!(x < CHUNK_SIZE && y < CHUNK_SIZE && z < CHUNK_SIZE);
+        y * CHUNK_SIZE * CHUNK_SIZE + z * CHUNK_SIZE + x
+    }
+
+    pub fn get(&self, x: usize, y: usize, z: usize) -> u16 {
+        self.blocks[Self::index(x, y, z)]
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, z: usize, block: u16) {
+        Arc::make_mut(&mut self.blocks)[Self::index(x, y, z)] = block;
+    }
+
+    pub fn blocks(&self) -> &[u16] {
+        &self.blocks
+    }
+}
+
+impl TryFrom<Vec<u16>> for Chunk {
+    type Error = String;
+
+    fn try_from(blocks: Vec<u16>) -> Result<Self, String> {
+        let len = blocks.len();
+        Chunk::from_blocks(blocks)
+            .ok_or_else(|| format!("expected {CHUNK_VOLUME} blocks, got {len}"))
+    }
+}
+
+impl From<Chunk> for Vec<u16> {
+    fn from(chunk: Chunk) -> Self {
+        chunk.blocks.to_vec()
+    }
+}
+
+/// Splits a world block coordinate into (chunk coordinate, local offset).
+pub fn split(w: i32) -> (i32, usize) {
+    let size = CHUNK_SIZE as i32;
+    (w.div_euclid(size), w.rem_euclid(size) as usize)
+}
+
+/// All loaded chunks of a world, keyed by chunk position.
+#[derive(Default)]
+pub struct ChunkStore {
+    chunks: HashMap<ChunkPos, Chunk>,
+}
+
+impl ChunkStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.chunks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+
+    pub fn get(&self, pos: ChunkPos) -> Option<&Chunk> {
+        self.chunks.get(&pos)
+    }
+
+    pub fn insert(&mut self, pos: ChunkPos, chunk: Chunk) {
+        self.chunks.insert(pos, chunk);
+    }
+
+    pub fn remove(&mut self, pos: ChunkPos) -> Option<Chunk> {
+        self.chunks.remove(&pos)
+    }
+
+    pub fn contains(&self, pos: ChunkPos) -> bool {
+        self.chunks.contains_key(&pos)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&ChunkPos, &Chunk)> {
+        self.chunks.iter()
+    }
+
+    /// Block at world block coordinates, `0` (air) if the chunk isn't loaded.
+    pub fn block(&self, x: i32, y: i32, z: i32) -> u16 {
+        let ((cx, lx), (cy, ly), (cz, lz)) = (split(x), split(y), split(z));
+        self.get((cx, cy, cz)).map_or(0, |c| c.get(lx, ly, lz))
+    }
+
+    /// Sets a block at world block coordinates, creating the chunk if needed.
+    /// Returns the chunk that changed.
+    pub fn set_block(&mut self, x: i32, y: i32, z: i32, block: u16) -> ChunkPos {
+        let ((cx, lx), (cy, ly), (cz, lz)) = (split(x), split(y), split(z));
+        self.chunks
+            .entry((cx, cy, cz))
+            .or_insert_with(Chunk::empty)
+            .set(lx, ly, lz, block);
+        (cx, cy, cz)
+    }
+
+    /// Highest non-air block y in the column, among loaded chunks.
+    pub fn surface_height(&self, x: i32, z: i32) -> Option<i32> {
+        let ((cx, lx), (cz, lz)) = (split(x), split(z));
+        self.chunks
+            .iter()
+            .filter(|((px, _, pz), _)| *px == cx && *pz == cz)
+            .filter_map(|((_, cy, _), chunk)| {
+                (0..CHUNK_SIZE)
+                    .rev()
+                    .find(|&ly| chunk.get(lx, ly, lz) != 0)
+                    .map(|ly| cy * CHUNK_SIZE as i32 + ly as i32)
+            })
+            .max()
+    }
+}
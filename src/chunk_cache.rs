@@ -0,0 +1,711 @@
+//! In-memory chunk cache with lazy load and LRU eviction.
+//!
+//! Only chunks near players' interest regions stay loaded. Missing chunks are
+//! read from their saves (see `Saves`), or generated, on the blocking thread
+//! pool. When the cache is over its budget, the least recently used chunks
+//! outside every interest region are evicted; dirty ones are saved first by a
+//! background writer, so the tick loop never touches the disk or database.
+//! `save_dirty` flushes every edited chunk the same way, handing the writer
+//! copy-on-write snapshots so saving never stalls a tick.
+//!
+//! `fork` starts a new cache from this one's chunks without copying them, for
+//! instanced rooms built from a template map. Forks read missing chunks from
+//! the same saves but never write to them; their edited chunks stay loaded
+//! instead of being evicted.
+//!
+//! Every loaded chunk has a version. Versions come from one cache-wide
+//! counter, so a chunk reloaded after eviction never reuses a version a
+//! client already holds. Block edits are batched per chunk until
+//! `take_edits`, which bumps each edited chunk's version once.
+
+use crate::{
+    chunk::{CHUNK_VOLUME, Chunk, ChunkPos, ChunkStore, split},
+    interest::Reach,
+    protocol::BlockEdit,
+    save,
+    storage::Storage,
+    terrain::ChunkGenerator,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+use tokio::sync::{Semaphore, mpsc};
+
+/// Approximate memory per loaded chunk, for the budget.
+pub const CHUNK_BYTES: usize = CHUNK_VOLUME * 2;
+
+pub struct CacheConfig {
+    /// Chunks are read from and flushed to these. Without any, chunks are
+    /// generated and edits are lost on eviction.
+    pub saves: Option<Saves>,
+    pub memory_budget: usize,
+    /// Concurrent load/generate jobs.
+    pub workers: usize,
+}
+
+/// Where a world's chunk saves (see `save.rs`) are.
+#[derive(Clone)]
+pub enum Saves {
+    /// A world directory, a file per chunk.
+    Dir(Arc<PathBuf>),
+    /// The storage backend, for a world without a directory.
+    Storage(Arc<dyn Storage>),
+}
+
+/// Edits to one chunk since the last `take_edits`.
+pub struct ChunkEdits {
+    pub base_version: u32,
+    pub version: u32,
+    pub edits: Vec<BlockEdit>,
+}
+
+// Chunks handed to the writer but not on disk yet. Loads check here first so
+// a chunk evicted and requested again never reads a stale file.
+type InFlight = Arc<Mutex<HashMap<ChunkPos, (u64, Chunk)>>>;
+
+pub struct ChunkCache {
+    store: ChunkStore,
+    last_used: HashMap<ChunkPos, u64>,
+    dirty: HashSet<ChunkPos>,
+    max_chunks: usize,
+    clock: u64,
+
+    versions: HashMap<ChunkPos, u32>,
+    next_version: u32,
+    // Latest block per edited index, per chunk
+    edits: HashMap<ChunkPos, HashMap<u16, u16>>,
+
+    saves: Option<Saves>,
+    generator: Option<Arc<dyn ChunkGenerator>>,
+    loading: HashSet<ChunkPos>,
+    permits: Arc<Semaphore>,
+    // `None` for loads that failed
+    loaded_tx: mpsc::UnboundedSender<(ChunkPos, Option<Chunk>)>,
+    loaded_rx: mpsc::UnboundedReceiver<(ChunkPos, Option<Chunk>)>,
+
+    in_flight: InFlight,
+    write_seq: u64,
+    write_tx: Option<mpsc::UnboundedSender<(ChunkPos, u64)>>,
+    // Forks can't flush, so their dirty chunks are never evicted
+    pin_dirty: bool,
+    // Loaded chunks the world may have, see quotas.rs
+    limit: Option<usize>,
+}
+
+impl ChunkCache {
+    /// Must be called inside the Tokio runtime (spawns the writer task).
+    pub fn new(config: CacheConfig, generator: Option<Arc<dyn ChunkGenerator>>) -> Self {
+        let in_flight = InFlight::default();
+
+        let write_tx = config.saves.as_ref().map(|saves| {
+            let (write_tx, write_rx) = mpsc::unbounded_channel();
+            tokio::spawn(run_writer(saves.clone(), in_flight.clone(), write_rx));
+            write_tx
+        });
+
+        let permits = Arc::new(Semaphore::new(config.workers.max(1)));
+        let max_chunks = (config.memory_budget / CHUNK_BYTES).max(1);
+        Self::with_parts(
+            max_chunks,
+            config.saves,
+            generator,
+            permits,
+            in_flight,
+            write_tx,
+        )
+    }
+
+    fn with_parts(
+        max_chunks: usize,
+        saves: Option<Saves>,
+        generator: Option<Arc<dyn ChunkGenerator>>,
+        permits: Arc<Semaphore>,
+        in_flight: InFlight,
+        write_tx: Option<mpsc::UnboundedSender<(ChunkPos, u64)>>,
+    ) -> Self {
+        let (loaded_tx, loaded_rx) = mpsc::unbounded_channel();
+        Self {
+            store: ChunkStore::new(),
+            last_used: HashMap::new(),
+            dirty: HashSet::new(),
+            max_chunks,
+            clock: 0,
+            versions: HashMap::new(),
+            next_version: 1,
+            edits: HashMap::new(),
+            saves,
+            generator,
+            loading: HashSet::new(),
+            permits,
+            loaded_tx,
+            loaded_rx,
+            in_flight,
+            write_seq: 0,
+            write_tx,
+            pin_dirty: false,
+            limit: None,
+        }
+    }
+
+    /// A new cache holding this one's loaded chunks, shared copy-on-write.
+    /// Unloaded chunks come from the saves as they were at the fork
+    /// (including unflushed edits), or the generator. Edits to the fork never
+    /// reach this cache or the saves.
+    pub fn fork(&self) -> ChunkCache {
+        // Chunks are cheap to clone, so this is a snapshot of the writer queue
+        let in_flight = Arc::new(Mutex::new(self.in_flight.lock().unwrap().clone()));
+        // Same budget, and load jobs share the workers
+        let mut fork = Self::with_parts(
+            self.max_chunks,
+            self.saves.clone(),
+            self.generator.clone(),
+            self.permits.clone(),
+            in_flight,
+            None,
+        );
+        fork.pin_dirty = true;
+
+        for (&pos, chunk) in self.store.iter() {
+            fork.insert_loaded(pos, chunk.clone());
+        }
+        fork
+    }
+
+    /// Like `fork`, but for a map of its own: chunks are read from `map`
+    /// (a world directory, see `import-vox`) and empty where it has none.
+    /// Shares the budget and workers, and never writes either.
+    pub fn fork_map(&self, map: Arc<PathBuf>) -> ChunkCache {
+        let mut fork = Self::with_parts(
+            self.max_chunks,
+            Some(Saves::Dir(map)),
+            None,
+            self.permits.clone(),
+            InFlight::default(),
+            None,
+        );
+        fork.pin_dirty = true;
+        fork
+    }
+
+    /// Stops loading chunks once `limit` are loaded or loading, cold ones
+    /// are then all evicted to make room.
+    pub fn set_limit(&mut self, limit: Option<usize>) {
+        self.limit = limit;
+    }
+
+    pub fn len(&self) -> usize {
+        self.store.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.store.is_empty()
+    }
+
+    pub fn get(&self, pos: ChunkPos) -> Option<&Chunk> {
+        self.store.get(pos)
+    }
+
+    /// Loaded chunks, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = (&ChunkPos, &Chunk)> {
+        self.store.iter()
+    }
+
+    /// Block at world block coordinates, `0` (air) if the chunk isn't loaded.
+    pub fn block(&self, x: i32, y: i32, z: i32) -> u16 {
+        self.store.block(x, y, z)
+    }
+
+    /// Version of a loaded chunk, not counting edits since `take_edits`.
+    pub fn version(&self, pos: ChunkPos) -> Option<u32> {
+        self.versions.get(&pos).copied()
+    }
+
+    fn bump_version(&mut self) -> u32 {
+        let version = self.next_version;
+        self.next_version = self.next_version.wrapping_add(1).max(1);
+        version
+    }
+
+    /// Marks a loaded chunk as used, or starts loading it.
+    pub fn request(&mut self, pos: ChunkPos) {
+        if self.store.contains(pos) {
+            self.last_used.insert(pos, self.clock);
+            return;
+        }
+
+        let loads = self.store.len() + self.loading.len();
+        if self.limit.is_some_and(|limit| loads >= limit) || !self.loading.insert(pos) {
+            return;
+        }
+
+        let saves = self.saves.clone();
+        let generator = self.generator.clone();
+        let in_flight = self.in_flight.clone();
+        let permits = self.permits.clone();
+        let tx = self.loaded_tx.clone();
+        tokio::spawn(async move {
+            let Ok(_permit) = permits.acquire_owned().await else {
+                tx.send((pos, None)).ok();
+                return;
+            };
+            let chunk = load_chunk(pos, saves, generator, in_flight).await;
+            tx.send((pos, chunk)).ok();
+        });
+    }
+
+    /// Next chunk finished loading. Pending forever when nothing is loading.
+    pub async fn recv_loaded(&mut self) -> Option<(ChunkPos, Chunk)> {
+        loop {
+            // We hold a sender, so this never yields None
+            match self.loaded_rx.recv().await? {
+                (pos, Some(chunk)) => return Some((pos, chunk)),
+                // Failed, the next request loads it again
+                (pos, None) => {
+                    self.loading.remove(&pos);
+                }
+            }
+        }
+    }
+
+    pub fn insert_loaded(&mut self, pos: ChunkPos, chunk: Chunk) {
+        self.loading.remove(&pos);
+        self.store.insert(pos, chunk);
+        self.last_used.insert(pos, self.clock);
+        let version = self.bump_version();
+        self.versions.insert(pos, version);
+    }
+
+    /// Requests every chunk in the cube around `center`.
+    pub fn request_area(&mut self, center: ChunkPos, radius: u16) {
+        let lead = (0, 0, 0);
+        self.request_reach(Reach {
+            center,
+            radius,
+            lead,
+        });
+    }
+
+    /// Requests every chunk of an interest stretched ahead of a moving
+    /// player, see `interest.rs`.
+    pub fn request_reach(&mut self, reach: Reach) {
+        let (lo, hi) = reach.bounds();
+        for x in lo.0..=hi.0 {
+            for y in lo.1..=hi.1 {
+                for z in lo.2..=hi.2 {
+                    self.request((x, y, z));
+                }
+            }
+        }
+    }
+
+    /// Requests every chunk in the given interests and evicts the least
+    /// recently used chunks outside them while over budget.
+    pub fn retain_interests(&mut self, interests: impl IntoIterator<Item = Reach>) {
+        self.clock += 1;
+
+        for reach in interests {
+            self.request_reach(reach);
+        }
+
+        let budget = match self.limit {
+            // Whatever is out of view goes, for chunks players wait for
+            Some(limit) if self.store.len() >= limit => 0,
+            _ => self.max_chunks,
+        };
+        let excess = self.store.len().saturating_sub(budget);
+        if excess == 0 {
+            return;
+        }
+
+        let clock = self.clock;
+        let mut cold: Vec<(u64, ChunkPos)> = self
+            .last_used
+            .iter()
+            .filter(|&(_, &used)| used < clock)
+            .filter(|&(pos, _)| !(self.pin_dirty && self.dirty.contains(pos)))
+            .map(|(&pos, &used)| (used, pos))
+            .collect();
+        cold.sort_unstable();
+
+        for (_, pos) in cold.into_iter().take(excess) {
+            self.evict(pos);
+        }
+    }
+
+    fn evict(&mut self, pos: ChunkPos) {
+        self.last_used.remove(&pos);
+        self.versions.remove(&pos);
+        self.edits.remove(&pos);
+        let Some(chunk) = self.store.remove(pos) else {
+            return;
+        };
+
+        if self.dirty.remove(&pos) {
+            self.flush(pos, chunk);
+        }
+    }
+
+    /// Hands snapshots of every edited chunk to the writer. Returns how many.
+    pub fn save_dirty(&mut self) -> usize {
+        if self.write_tx.is_none() {
+            return 0;
+        }
+
+        let dirty = std::mem::take(&mut self.dirty);
+        for &pos in &dirty {
+            if let Some(chunk) = self.store.get(pos) {
+                self.flush(pos, chunk.clone());
+            }
+        }
+        dirty.len()
+    }
+
+    fn flush(&mut self, pos: ChunkPos, chunk: Chunk) {
+        let Some(write_tx) = &self.write_tx else {
+            return;
+        };
+
+        self.write_seq += 1;
+        self.in_flight
+            .lock()
+            .unwrap()
+            .insert(pos, (self.write_seq, chunk));
+        write_tx.send((pos, self.write_seq)).ok();
+    }
+
+    /// Sets a block in a loaded chunk and marks it dirty. Returns `false`
+    /// (and changes nothing) when the chunk isn't loaded.
+    pub fn set_block(&mut self, x: i32, y: i32, z: i32, block: u16) -> bool {
+        let pos = (split(x).0, split(y).0, split(z).0);
+        if !self.store.contains(pos) {
+            return false;
+        }
+
+        self.store.set_block(x, y, z, block);
+        let (lx, ly, lz) = (split(x).1, split(y).1, split(z).1);
+        self.edits
+            .entry(pos)
+            .or_default()
+            .insert(Chunk::index(lx, ly, lz) as u16, block);
+
+        // Without a world dir there's nowhere to flush to
+        if self.write_tx.is_some() || self.pin_dirty {
+            self.dirty.insert(pos);
+        }
+        true
+    }
+
+    /// Takes the edits made since the last call, giving each edited chunk a
+    /// new version.
+    pub fn take_edits(&mut self) -> HashMap<ChunkPos, ChunkEdits> {
+        let edits = std::mem::take(&mut self.edits);
+        edits
+            .into_iter()
+            .filter_map(|(pos, edits)| {
+                let base_version = self.versions.get(&pos).copied()?;
+                let version = self.bump_version();
+                self.versions.insert(pos, version);
+                Some((
+                    pos,
+                    ChunkEdits {
+                        base_version,
+                        version,
+                        edits: edits.into_iter().collect(),
+                    },
+                ))
+            })
+            .collect()
+    }
+
+    /// Surface height from loaded chunks, else from the generator.
+    pub fn surface_height(&self, x: i32, z: i32) -> Option<i32> {
+        self.store
+            .surface_height(x, z)
+            .or_else(|| self.generator.as_ref()?.surface_height(x, z))
+    }
+}
+
+// `None` when it failed, to be requested again
+async fn load_chunk(
+    pos: ChunkPos,
+    saves: Option<Saves>,
+    generator: Option<Arc<dyn ChunkGenerator>>,
+    in_flight: InFlight,
+) -> Option<Chunk> {
+    // Checked before reading: the writer saves a chunk before it leaves
+    // the flight, so a saved one is never stale
+    if let Some((_, chunk)) = in_flight.lock().unwrap().get(&pos) {
+        return Some(chunk.clone());
+    }
+
+    let stored = match &saves {
+        Some(Saves::Storage(storage)) => match storage.load_chunk(pos).await {
+            Ok(data) => data,
+            // Generated, it would replace the saved one at the next flush
+            Err(e) => {
+                eprintln!("Chunk {pos:?} load failed: {e}");
+                return None;
+            }
+        },
+        _ => None,
+    };
+
+    let load = move || {
+        let data = match &saves {
+            Some(Saves::Dir(dir)) => read_chunk(dir, pos),
+            _ => stored,
+        };
+        if let Some(data) = data {
+            match save::decode_chunk(&data) {
+                Ok((_, chunk)) => return chunk,
+                // Regenerating beats refusing to load the area
+                Err(e) => eprintln!("Chunk {pos:?} is corrupt, regenerating: {e}"),
+            }
+        }
+        generator.map_or_else(Chunk::empty, |g| g.generate(pos))
+    };
+    match tokio::task::spawn_blocking(load).await {
+        Ok(chunk) => Some(chunk),
+        Err(e) => {
+            eprintln!("Chunk {pos:?} load panicked: {e}");
+            None
+        }
+    }
+}
+
+fn read_chunk(dir: &Path, pos: ChunkPos) -> Option<Vec<u8>> {
+    match std::fs::read(save::chunk_path(dir, pos)) {
+        Ok(data) => Some(data),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+        Err(e) => {
+            eprintln!("Chunk {pos:?} read failed, regenerating: {e}");
+            None
+        }
+    }
+}
+
+// The chunk flush `seq` is for, unless a newer flush of it is queued and
+// will write instead
+fn flushed(in_flight: &InFlight, pos: ChunkPos, seq: u64) -> Option<Chunk> {
+    let in_flight = in_flight.lock().unwrap();
+    let (latest, chunk) = in_flight.get(&pos)?;
+    (*latest == seq).then(|| chunk.clone())
+}
+
+// Out of the flight once saved, unless flushed again since
+fn landed(in_flight: &InFlight, pos: ChunkPos, seq: u64) {
+    let mut in_flight = in_flight.lock().unwrap();
+    if in_flight.get(&pos).is_some_and(|(s, _)| *s == seq) {
+        in_flight.remove(&pos);
+    }
+}
+
+// Saves queued chunks in batches. Failed ones stay in flight, so loads
+// still see the edits.
+async fn run_writer(
+    saves: Saves,
+    in_flight: InFlight,
+    mut rx: mpsc::UnboundedReceiver<(ChunkPos, u64)>,
+) {
+    let mut batch = Vec::new();
+    while rx.recv_many(&mut batch, 256).await > 0 {
+        let writes = std::mem::take(&mut batch);
+        match &saves {
+            Saves::Dir(dir) => write_files(dir.clone(), in_flight.clone(), writes).await,
+            Saves::Storage(storage) => write_stored(storage.as_ref(), &in_flight, writes).await,
+        }
+    }
+}
+
+// One blocking task per batch
+async fn write_files(dir: Arc<PathBuf>, in_flight: InFlight, writes: Vec<(ChunkPos, u64)>) {
+    let result = tokio::task::spawn_blocking(move || {
+        std::fs::create_dir_all(dir.join("chunks"))?;
+        for (pos, seq) in writes {
+            let Some(chunk) = flushed(&in_flight, pos, seq) else {
+                continue;
+            };
+            if let Err(e) = save::write_chunk(&dir, pos, &chunk) {
+                eprintln!("Chunk {pos:?} flush failed: {e}");
+                continue;
+            }
+            landed(&in_flight, pos, seq);
+        }
+        Ok::<_, std::io::Error>(())
+    })
+    .await;
+
+    match result {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => eprintln!("Chunk flush failed: {e}"),
+        Err(e) => eprintln!("Chunk flush panicked: {e}"),
+    }
+}
+
+// One `save_chunks` per batch
+async fn write_stored(storage: &dyn Storage, in_flight: &InFlight, writes: Vec<(ChunkPos, u64)>) {
+    let writes: Vec<_> = writes
+        .into_iter()
+        .filter_map(|(pos, seq)| Some((pos, seq, flushed(in_flight, pos, seq)?)))
+        .collect();
+    if writes.is_empty() {
+        return;
+    }
+    let chunks: Vec<_> = writes
+        .iter()
+        .map(|(pos, _, chunk)| (*pos, save::encode_chunk(*pos, chunk)))
+        .collect();
+    match storage.save_chunks(&chunks).await {
+        Ok(()) => {
+            for (pos, seq, _) in writes {
+                landed(in_flight, pos, seq);
+            }
+        }
+        Err(e) => eprintln!("Chunk flush failed: {e}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terrain::FlatGenerator;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn save_dirty_writes_edits_in_background() {
+        let dir = std::env::temp_dir().join(format!("teleboxel-cache-{}", std::process::id()));
+        let config = CacheConfig {
+            saves: Some(Saves::Dir(Arc::new(dir.clone()))),
+            memory_budget: 64 * CHUNK_BYTES,
+            workers: 1,
+        };
+        let mut cache = ChunkCache::new(config, Some(Arc::new(FlatGenerator)));
+
+        cache.request((0, 0, 0));
+        let (pos, chunk) = cache.recv_loaded().await.unwrap();
+        cache.insert_loaded(pos, chunk);
+
+        assert!(cache.set_block(1, 2, 3, 7));
+        assert_eq!(cache.save_dirty(), 1);
+        // Edited again after the first snapshot, the newer save wins
+        assert!(cache.set_block(1, 2, 3, 8));
+        assert_eq!(cache.save_dirty(), 1);
+        assert_eq!(cache.save_dirty(), 0, "nothing left dirty");
+
+        let path = save::chunk_path(&dir, (0, 0, 0));
+        for _ in 0..100 {
+            if cache.in_flight.lock().unwrap().is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        let (_, saved) = save::decode_chunk(&std::fs::read(&path).unwrap()).unwrap();
+        std::fs::remove_dir_all(&dir).ok();
+        assert_eq!(saved.get(1, 2, 3), 8);
+        assert_eq!(cache.get((0, 0, 0)).unwrap().get(1, 2, 3), 8);
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn saves_to_storage_without_a_world_dir() {
+        let path = std::env::temp_dir().join(format!("teleboxel-cache-{}.db", std::process::id()));
+        let url = format!("sqlite://{}", path.display());
+        let storage = crate::storage::connect(&url).await.unwrap();
+        let config = || CacheConfig {
+            saves: Some(Saves::Storage(storage.clone())),
+            memory_budget: 64 * CHUNK_BYTES,
+            workers: 1,
+        };
+        let mut cache = ChunkCache::new(config(), Some(Arc::new(FlatGenerator)));
+        cache.request((0, -1, 0));
+        let (pos, chunk) = cache.recv_loaded().await.unwrap();
+        cache.insert_loaded(pos, chunk);
+        assert!(cache.set_block(1, -2, 3, 7));
+        assert_eq!(cache.save_dirty(), 1);
+        for _ in 0..100 {
+            if cache.in_flight.lock().unwrap().is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        // A new cache, as after a restart, loads the edit
+        let mut cache = ChunkCache::new(config(), Some(Arc::new(FlatGenerator)));
+        cache.request((0, -1, 0));
+        let (_, chunk) = cache.recv_loaded().await.unwrap();
+        drop(storage);
+        std::fs::remove_file(&path).ok();
+        assert_eq!(chunk.get(1, 14, 3), 7);
+    }
+
+    // Panics on its first chunk only
+    #[derive(Default)]
+    struct Flaky(std::sync::atomic::AtomicBool);
+
+    impl ChunkGenerator for Flaky {
+        fn generate(&self, pos: ChunkPos) -> Chunk {
+            if !self.0.swap(true, std::sync::atomic::Ordering::SeqCst) {
+                panic!("flaky generator");
+            }
+            FlatGenerator.generate(pos)
+        }
+    }
+
+    #[tokio::test]
+    async fn failed_loads_can_be_requested_again() {
+        let config = CacheConfig {
+            saves: None,
+            memory_budget: CHUNK_BYTES,
+            workers: 1,
+        };
+        let mut cache = ChunkCache::new(config, Some(Arc::new(Flaky::default())));
+        cache.request((0, 0, 0));
+        // However long the panic takes to come back
+        for _ in 0..100 {
+            let loaded = tokio::time::timeout(Duration::from_millis(50), cache.recv_loaded()).await;
+            assert!(loaded.is_err(), "the panicked load yields nothing");
+            if cache.loading.is_empty() {
+                break;
+            }
+        }
+        assert!(cache.loading.is_empty());
+
+        cache.request((0, 0, 0));
+        let (pos, chunk) = cache.recv_loaded().await.unwrap();
+        assert_eq!(pos, (0, 0, 0));
+        assert_eq!(chunk, FlatGenerator.generate(pos));
+    }
+
+    #[tokio::test]
+    async fn fork_shares_chunks_and_keeps_edits_apart() {
+        let config = CacheConfig {
+            saves: None,
+            memory_budget: CHUNK_BYTES,
+            workers: 1,
+        };
+        let mut template = ChunkCache::new(config, Some(Arc::new(FlatGenerator)));
+        for pos in [(0, 0, 0), (1, 0, 0)] {
+            template.request(pos);
+            let (pos, chunk) = template.recv_loaded().await.unwrap();
+            template.insert_loaded(pos, chunk);
+        }
+
+        let mut fork = template.fork();
+        assert_eq!(fork.len(), 2);
+        assert_eq!(fork.get((0, 0, 0)), template.get((0, 0, 0)));
+
+        assert!(fork.set_block(1, 2, 3, 7));
+        assert!(template.set_block(1, 2, 3, 9));
+        assert_eq!(fork.block(1, 2, 3), 7);
+        assert_eq!(template.block(1, 2, 3), 9);
+        assert_eq!(fork.save_dirty(), 0, "forks never write");
+
+        // Over a one-chunk budget, only the unedited chunk can go
+        fork.retain_interests([]);
+        assert!(fork.get((0, 0, 0)).is_some());
+        assert!(fork.get((1, 0, 0)).is_none());
+    }
+}
@@ -0,0 +1,423 @@
+//! Chunk payload encoding for the wire (`CHUNK_SNAPSHOT` voxel data).
+//!
+//! A payload starts with a `u8` encoding, followed by:
+//!
+//! - `0` RAW: 4096 `u16` block ids, LE (8193 bytes total).
+//! - `1` PALETTE_RLE: `u16` palette length `n` (1..=4096), `n` `u16` block
+//!   ids, `u16` run count, then runs of `u8` length minus one and a palette
+//!   index, `u8` when `n <= 256` else `u16`. Runs follow the chunk's y-major
+//!   block order and must cover exactly 4096 blocks.
+//! - `2` FLATBUFFERS: `u32` length, then a FlatBuffer with the
+//!   `schema/chunk.fbs` root table. Bigger than PALETTE_RLE, but clients can
+//!   read blocks in place (`FlatChunk`) without a decode pass.
+//!
+//! `encode` picks whichever of the first two is smaller, so a payload is
+//! never larger than RAW. Terrain is mostly long runs of few block types
+//! (air above the surface, stone below), which PALETTE_RLE shrinks to tens
+//! of bytes; see `benches/chunk_wire.rs` for sizes on representative
+//! terrain.
+//!
+//! Worlds opt into FLATBUFFERS with `ChunkFormat`.
+
+use crate::chunk::{CHUNK_VOLUME, Chunk};
+use std::{collections::HashMap, fmt, str::FromStr};
+
+pub const RAW: u8 = 0;
+pub const PALETTE_RLE: u8 = 1;
+pub const FLATBUFFERS: u8 = 2;
+
+/// `schema/chunk.fbs` file identifier.
+pub const FLATBUFFERS_ID: &[u8; 4] = b"TBXC";
+
+/// Size of a RAW payload, the upper bound for `encode`.
+pub const RAW_LEN: usize = 1 + CHUNK_VOLUME * 2;
+
+const MAX_RUN: usize = u8::MAX as usize + 1;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum WireError {
+    Truncated,
+    UnknownEncoding(u8),
+    Invalid(&'static str),
+}
+
+impl fmt::Display for WireError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WireError::Truncated => write!(f, "chunk payload is truncated"),
+            WireError::UnknownEncoding(e) => write!(f, "unknown chunk encoding {e}"),
+            WireError::Invalid(why) => write!(f, "invalid chunk payload: {why}"),
+        }
+    }
+}
+
+impl std::error::Error for WireError {}
+
+/// Snapshot encoding a world sends.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ChunkFormat {
+    /// RAW or PALETTE_RLE, whichever is smaller.
+    #[default]
+    Compact,
+    FlatBuffers,
+}
+
+impl FromStr for ChunkFormat {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        match s {
+            "compact" => Ok(ChunkFormat::Compact),
+            "flatbuffers" => Ok(ChunkFormat::FlatBuffers),
+            _ => Err(()),
+        }
+    }
+}
+
+pub fn encode_as(chunk: &Chunk, format: ChunkFormat, buf: &mut Vec<u8>) {
+    match format {
+        ChunkFormat::Compact => encode(chunk, buf),
+        ChunkFormat::FlatBuffers => encode_flatbuffers(chunk, buf),
+    }
+}
+
+/// Appends the smallest encoding of `chunk` to `buf`.
+pub fn encode(chunk: &Chunk, buf: &mut Vec<u8>) {
+    let start = buf.len();
+    encode_palette_rle(chunk, buf);
+    if buf.len() - start > RAW_LEN {
+        buf.truncate(start);
+        encode_raw(chunk, buf);
+    }
+}
+
+pub fn encode_raw(chunk: &Chunk, buf: &mut Vec<u8>) {
+    buf.reserve(RAW_LEN);
+    buf.push(RAW);
+    for block in chunk.blocks() {
+        buf.extend_from_slice(&block.to_le_bytes());
+    }
+}
+
+pub fn encode_palette_rle(chunk: &Chunk, buf: &mut Vec<u8>) {
+    let mut palette = Vec::new();
+    let mut indices = HashMap::new();
+    let mut runs: Vec<(u16, usize)> = Vec::new();
+
+    let mut prev = None;
+    for &block in chunk.blocks() {
+        if let Some((b, index)) = prev
+            && b == block
+            && let Some((i, len)) = runs.last_mut()
+            && *i == index
+            && *len < MAX_RUN
+        {
+            *len += 1;
+            continue;
+        }
+
+        let index = *indices.entry(block).or_insert_with(|| {
+            palette.push(block);
+            palette.len() as u16 - 1
+        });
+        prev = Some((block, index));
+        runs.push((index, 1));
+    }
+
+    let wide = palette.len() > 256;
+    buf.push(PALETTE_RLE);
+    buf.extend_from_slice(&(palette.len() as u16).to_le_bytes());
+    for block in &palette {
+        buf.extend_from_slice(&block.to_le_bytes());
+    }
+    // At most 4096 runs, one per block
+    buf.extend_from_slice(&(runs.len() as u16).to_le_bytes());
+    for (index, len) in runs {
+        buf.push((len - 1) as u8);
+        if wide {
+            buf.extend_from_slice(&index.to_le_bytes());
+        } else {
+            buf.push(index as u8);
+        }
+    }
+}
+
+// FlatBuffer layout, written front to back: root offset, file identifier,
+// vtable (size, table size, offset of `blocks`) + padding, table (vtable
+// soffset, `blocks` uoffset), then the vector (length, ids)
+const FB_TABLE: usize = 16;
+const FB_VECTOR: usize = 24;
+const FB_LEN: usize = FB_VECTOR + 4 + CHUNK_VOLUME * 2;
+
+pub fn encode_flatbuffers(chunk: &Chunk, buf: &mut Vec<u8>) {
+    buf.reserve(5 + FB_LEN);
+    buf.push(FLATBUFFERS);
+    buf.extend_from_slice(&(FB_LEN as u32).to_le_bytes());
+
+    buf.extend_from_slice(&(FB_TABLE as u32).to_le_bytes());
+    buf.extend_from_slice(FLATBUFFERS_ID);
+    for v in [6u16, 8, 4, 0] {
+        buf.extend_from_slice(&v.to_le_bytes());
+    }
+    buf.extend_from_slice(&8i32.to_le_bytes());
+    buf.extend_from_slice(&4u32.to_le_bytes());
+    buf.extend_from_slice(&(CHUNK_VOLUME as u32).to_le_bytes());
+    for block in chunk.blocks() {
+        buf.extend_from_slice(&block.to_le_bytes());
+    }
+}
+
+/// Block ids read in place from a FLATBUFFERS payload.
+#[derive(Clone, Copy)]
+pub struct FlatChunk<'a> {
+    blocks: &'a [u8],
+}
+
+impl<'a> FlatChunk<'a> {
+    /// Checks the FlatBuffer at the start of a FLATBUFFERS payload (after
+    /// the encoding byte), returning the view and the bytes it spans. Any
+    /// valid `schema/chunk.fbs` buffer is accepted, not only ours.
+    pub fn parse(data: &'a [u8]) -> Result<(Self, usize), WireError> {
+        let mut r = Reader { data, at: 0 };
+        let len = r.u32()? as usize;
+        let fb = data.get(4..4 + len).ok_or(WireError::Truncated)?;
+
+        let u32_at = |at: usize| -> Result<u32, WireError> {
+            let bytes = fb
+                .get(at..at + 4)
+                .ok_or(WireError::Invalid("bad flatbuffer"))?;
+            Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+        };
+        let u16_at = |at: usize| -> Result<u16, WireError> {
+            let bytes = fb
+                .get(at..at + 2)
+                .ok_or(WireError::Invalid("bad flatbuffer"))?;
+            Ok(u16::from_le_bytes(bytes.try_into().unwrap()))
+        };
+
+        if fb.get(4..8) != Some(FLATBUFFERS_ID) {
+            return Err(WireError::Invalid("wrong flatbuffer identifier"));
+        }
+        let table = u32_at(0)? as usize;
+        let vtable = (table as i64 - u32_at(table)? as i32 as i64)
+            .try_into()
+            .map_err(|_| WireError::Invalid("bad flatbuffer"))?;
+        let field = match u16_at(vtable)? {
+            size if size >= 6 => u16_at(vtable + 4)? as usize,
+            _ => 0,
+        };
+        if field == 0 {
+            return Err(WireError::Invalid("flatbuffer has no blocks"));
+        }
+
+        let at = table + field;
+        let vector = at + u32_at(at)? as usize;
+        if u32_at(vector)? as usize != CHUNK_VOLUME {
+            return Err(WireError::Invalid("wrong block count"));
+        }
+        let blocks = fb
+            .get(vector + 4..vector + 4 + CHUNK_VOLUME * 2)
+            .ok_or(WireError::Invalid("bad flatbuffer"))?;
+        Ok((Self { blocks }, 4 + len))
+    }
+
+    pub fn get(&self, x: usize, y: usize, z: usize) -> u16 {
+        let i = Chunk::index(x, y, z) * 2;
+        u16::from_le_bytes([self.blocks[i], self.blocks[i + 1]])
+    }
+
+    pub fn to_chunk(&self) -> Chunk {
+        let blocks = self
+            .blocks
+            .chunks_exact(2)
+            .map(|b| u16::from_le_bytes([b[0], b[1]]))
+            .collect();
+        Chunk::from_blocks(blocks).unwrap()
+    }
+}
+
+/// Decodes a whole payload; trailing bytes are an error.
+pub fn decode(data: &[u8]) -> Result<Chunk, WireError> {
+    let (chunk, len) = decode_prefix(data)?;
+    if len != data.len() {
+        return Err(WireError::Invalid("trailing bytes"));
+    }
+    Ok(chunk)
+}
+
+/// Decodes a payload at the start of `data`, returning the chunk and the
+/// payload length (payloads are self-delimiting).
+pub fn decode_prefix(data: &[u8]) -> Result<(Chunk, usize), WireError> {
+    let mut r = Reader { data, at: 0 };
+    let chunk = match r.u8()? {
+        RAW => {
+            let blocks = (0..CHUNK_VOLUME)
+                .map(|_| r.u16())
+                .collect::<Result<_, _>>()?;
+            Chunk::from_blocks(blocks).ok_or(WireError::Invalid("wrong block count"))?
+        }
+        PALETTE_RLE => decode_palette_rle(&mut r)?,
+        FLATBUFFERS => {
+            let (view, len) = FlatChunk::parse(&data[r.at..])?;
+            r.at += len;
+            view.to_chunk()
+        }
+        e => return Err(WireError::UnknownEncoding(e)),
+    };
+
+    Ok((chunk, r.at))
+}
+
+fn decode_palette_rle(r: &mut Reader) -> Result<Chunk, WireError> {
+    let palette_len = r.u16()? as usize;
+    if palette_len == 0 || palette_len > CHUNK_VOLUME {
+        return Err(WireError::Invalid("palette length out of range"));
+    }
+    let palette = (0..palette_len)
+        .map(|_| r.u16())
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let wide = palette_len > 256;
+    let run_count = r.u16()?;
+    let mut blocks = Vec::with_capacity(CHUNK_VOLUME);
+    for _ in 0..run_count {
+        let len = r.u8()? as usize + 1;
+        let index = if wide {
+            r.u16()? as usize
+        } else {
+            r.u8()? as usize
+        };
+        let block = *palette
+            .get(index)
+            .ok_or(WireError::Invalid("palette index out of range"))?;
+        if blocks.len() + len > CHUNK_VOLUME {
+            return Err(WireError::Invalid("runs exceed chunk volume"));
+        }
+        blocks.resize(blocks.len() + len, block);
+    }
+
+    Chunk::from_blocks(blocks).ok_or(WireError::Invalid("runs don't cover the chunk"))
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    at: usize,
+}
+
+impl Reader<'_> {
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], WireError> {
+        let bytes = self
+            .data
+            .get(self.at..self.at + N)
+            .ok_or(WireError::Truncated)?;
+        self.at += N;
+        Ok(bytes.try_into().unwrap())
+    }
+
+    fn u8(&mut self) -> Result<u8, WireError> {
+        Ok(self.take::<1>()?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, WireError> {
+        Ok(u16::from_le_bytes(self.take()?))
+    }
+
+    fn u32(&mut self) -> Result<u32, WireError> {
+        Ok(u32::from_le_bytes(self.take()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terrain::{ChunkGenerator, FlatGenerator, NoiseGenerator};
+
+    fn noisy() -> Chunk {
+        // Every block differs from its neighbour, worst case for RLE
+        let blocks = (0..CHUNK_VOLUME as u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 20) as u16)
+            .collect();
+        Chunk::from_blocks(blocks).unwrap()
+    }
+
+    fn round_trip(chunk: &Chunk) -> usize {
+        round_trip_as(chunk, ChunkFormat::Compact)
+    }
+
+    fn round_trip_as(chunk: &Chunk, format: ChunkFormat) -> usize {
+        let mut buf = Vec::new();
+        encode_as(chunk, format, &mut buf);
+        assert_eq!(&decode(&buf).unwrap(), chunk);
+        buf.len()
+    }
+
+    #[test]
+    fn terrain_round_trips_compressed() {
+        let noise = NoiseGenerator::new(7);
+        let chunks = [
+            Chunk::empty(),
+            FlatGenerator.generate((0, -1, 0)),
+            FlatGenerator.generate((0, 0, 0)),
+            noise.generate((0, 2, 0)),
+            noise.generate((3, 0, -5)),
+        ];
+        for chunk in &chunks {
+            assert!(round_trip(chunk) < RAW_LEN / 4);
+        }
+    }
+
+    #[test]
+    fn falls_back_to_raw() {
+        let chunk = noisy();
+        let mut buf = Vec::new();
+        encode(&chunk, &mut buf);
+        assert_eq!((buf[0], buf.len()), (RAW, RAW_LEN));
+        assert_eq!(round_trip(&chunk), RAW_LEN);
+
+        // Wide palette indices still decode
+        let mut buf = Vec::new();
+        encode_palette_rle(&chunk, &mut buf);
+        assert_eq!(decode(&buf).unwrap(), chunk);
+    }
+
+    #[test]
+    fn flatbuffers_read_in_place() {
+        let chunk = noisy();
+        let mut buf = Vec::new();
+        encode_flatbuffers(&chunk, &mut buf);
+        assert_eq!(round_trip_as(&chunk, ChunkFormat::FlatBuffers), buf.len());
+
+        let (view, len) = FlatChunk::parse(&buf[1..]).unwrap();
+        assert_eq!(len, buf.len() - 1);
+        assert_eq!(view.get(3, 9, 14), chunk.get(3, 9, 14));
+
+        for len in 0..buf.len() {
+            assert!(decode(&buf[..len]).is_err());
+        }
+        buf[9] = b'X';
+        assert_eq!(
+            decode(&buf),
+            Err(WireError::Invalid("wrong flatbuffer identifier"))
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_payloads() {
+        let mut buf = Vec::new();
+        encode(&FlatGenerator.generate((0, 0, 0)), &mut buf);
+
+        for len in 0..buf.len() {
+            assert!(decode(&buf[..len]).is_err());
+        }
+
+        let mut trailing = buf.clone();
+        trailing.push(0);
+        assert_eq!(decode(&trailing), Err(WireError::Invalid("trailing bytes")));
+
+        assert_eq!(decode(&[9]), Err(WireError::UnknownEncoding(9)));
+        // One air block, one run of one: doesn't cover the chunk
+        assert!(decode(&[PALETTE_RLE, 1, 0, 0, 0, 1, 0, 0, 0]).is_err());
+        // Index past the palette
+        assert!(decode(&[PALETTE_RLE, 1, 0, 0, 0, 1, 0, 255, 1]).is_err());
+    }
+}
@@ -0,0 +1,609 @@
+//! Land claims: boxes of world blocks owned by a player or a group. Blocks
+//! inside a claim can only be edited by its owner (or the group's members);
+//! unclaimed blocks are open to everyone.
+//!
+//! Claims are shared between the world task (edit checks, player commands)
+//! and the admin API, and saved to `<world_dir>/claims.tbx` (or the storage
+//! backend, for a world without a directory) by a background task after
+//! every change.
+//!
+//! Claims body, version 1 (all LE):
+//!
+//! - `u32` next claim id, `u32` claim count, then per claim: `u32` id, `i32`
+//!   min x, y, z, `i32` max x, y, z, `u8` owner kind (`0` player, `1`
+//!   group), owner name
+//! - `u32` group count, then per group: name, `u16` member count, member
+//!   names
+//!
+//! Names are a `u16` byte length followed by UTF-8.
+
+use crate::{
+    save::{self, SaveError, SaveKind},
+    storage::{Storage, StorageError},
+};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+use tokio::sync::Notify;
+
+/// Largest claim players can make themselves, in blocks per axis.
+pub const MAX_PLAYER_CLAIM_SIZE: i32 = 128;
+
+pub type BlockPos = (i32, i32, i32);
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Owner {
+    Player(String),
+    Group(String),
+}
+
+impl fmt::Display for Owner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Owner::Player(name) => write!(f, "player:{name}"),
+            Owner::Group(name) => write!(f, "group:{name}"),
+        }
+    }
+}
+
+impl FromStr for Owner {
+    type Err = ClaimError;
+
+    /// Parses `player:<name>` or `group:<name>`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some(("player", name)) if valid_name(name) => Ok(Owner::Player(name.into())),
+            Some(("group", name)) if valid_name(name) => Ok(Owner::Group(name.into())),
+            _ => Err(ClaimError::BadOwner),
+        }
+    }
+}
+
+fn valid_name(name: &str) -> bool {
+    !name.is_empty() && name.len() <= 32
+}
+
+/// Inclusive box of blocks.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Claim {
+    pub id: u32,
+    pub owner: Owner,
+    pub min: BlockPos,
+    pub max: BlockPos,
+}
+
+impl Claim {
+    pub fn contains(&self, (x, y, z): BlockPos) -> bool {
+        (self.min.0..=self.max.0).contains(&x)
+            && (self.min.1..=self.max.1).contains(&y)
+            && (self.min.2..=self.max.2).contains(&z)
+    }
+
+    fn overlaps(&self, min: BlockPos, max: BlockPos) -> bool {
+        self.min.0 <= max.0
+            && min.0 <= self.max.0
+            && self.min.1 <= max.1
+            && min.1 <= self.max.1
+            && self.min.2 <= max.2
+            && min.2 <= self.max.2
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ClaimError {
+    /// Anonymous players can't own or edit claimed land.
+    Anonymous,
+    BadOwner,
+    NotFound(u32),
+    NotOwner(u32),
+    Overlaps(u32),
+    TooLarge,
+}
+
+impl fmt::Display for ClaimError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClaimError::Anonymous => write!(f, "connect with ?name= to use claims"),
+            ClaimError::BadOwner => write!(f, "owner must be player:<name> or group:<name>"),
+            ClaimError::NotFound(id) => write!(f, "no claim {id}"),
+            ClaimError::NotOwner(id) => write!(f, "claim {id} belongs to someone else"),
+            ClaimError::Overlaps(id) => write!(f, "overlaps claim {id}"),
+            ClaimError::TooLarge => write!(
+                f,
+                "claims are at most {MAX_PLAYER_CLAIM_SIZE} blocks per axis"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ClaimError {}
+
+// Where the saver writes
+enum Saved {
+    File(PathBuf),
+    Storage(Arc<dyn Storage>),
+}
+
+#[derive(Default, Clone, PartialEq, Eq, Debug)]
+struct ClaimsState {
+    next_id: u32,
+    claims: BTreeMap<u32, Claim>,
+    groups: BTreeMap<String, BTreeSet<String>>,
+}
+
+pub struct Claims {
+    state: Mutex<ClaimsState>,
+    // Wakes the saver after a change
+    changed: Notify,
+}
+
+impl Claims {
+    /// Loads `<world_dir>/claims.tbx` and starts the saver task. Without a
+    /// world dir, claims live in memory only. Must be called inside the
+    /// Tokio runtime.
+    pub fn load(world_dir: Option<&Path>) -> io::Result<Arc<Self>> {
+        let path = world_dir.map(|dir| dir.join("claims.tbx"));
+        let state = match &path {
+            Some(path) => match fs::read(path) {
+                Ok(data) => decode(&data).map_err(|e| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("{}: {e}", path.display()),
+                    )
+                })?,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => ClaimsState::default(),
+                Err(e) => return Err(e),
+            },
+            None => ClaimsState::default(),
+        };
+
+        let claims = Arc::new(Self {
+            state: Mutex::new(state),
+            changed: Notify::new(),
+        });
+
+        if let Some(path) = path {
+            tokio::spawn(claims.clone().run_saver(Saved::File(path)));
+        }
+
+        Ok(claims)
+    }
+
+    /// Like `load`, for a world without a directory: the claims save is in
+    /// `storage`.
+    pub async fn load_stored(storage: Arc<dyn Storage>) -> Result<Arc<Self>, StorageError> {
+        let state = match storage.load_claims().await? {
+            Some(data) => decode(&data).map_err(|e| format!("stored claims: {e}"))?,
+            None => ClaimsState::default(),
+        };
+
+        let claims = Arc::new(Self {
+            state: Mutex::new(state),
+            changed: Notify::new(),
+        });
+        tokio::spawn(claims.clone().run_saver(Saved::Storage(storage)));
+        Ok(claims)
+    }
+
+    pub fn list(&self) -> Vec<Claim> {
+        self.state
+            .lock()
+            .unwrap()
+            .claims
+            .values()
+            .cloned()
+            .collect()
+    }
+
+    pub fn groups(&self) -> BTreeMap<String, BTreeSet<String>> {
+        self.state.lock().unwrap().groups.clone()
+    }
+
+    /// Whether `player` (`None` for anonymous) may edit the block.
+    pub fn can_edit(&self, player: Option<&str>, pos: BlockPos) -> bool {
+        let state = self.state.lock().unwrap();
+        state
+            .claims
+            .values()
+            .filter(|claim| claim.contains(pos))
+            .all(|claim| player.is_some_and(|p| state.is_owner(&claim.owner, p)))
+    }
+
+    /// Claims a box for `player`, limited to `MAX_PLAYER_CLAIM_SIZE`.
+    pub fn claim_for(
+        &self,
+        player: Option<&str>,
+        a: BlockPos,
+        b: BlockPos,
+    ) -> Result<u32, ClaimError> {
+        let player = player.ok_or(ClaimError::Anonymous)?;
+        let (min, max) = bounds(a, b);
+        let too_large = [max.0 - min.0, max.1 - min.1, max.2 - min.2]
+            .iter()
+            .any(|&d| d >= MAX_PLAYER_CLAIM_SIZE);
+        if too_large {
+            return Err(ClaimError::TooLarge);
+        }
+
+        self.create(Owner::Player(player.into()), min, max)
+    }
+
+    /// Claims a box for any owner, without a size limit (admin).
+    pub fn create(&self, owner: Owner, a: BlockPos, b: BlockPos) -> Result<u32, ClaimError> {
+        let (min, max) = bounds(a, b);
+        let mut state = self.state.lock().unwrap();
+        if let Some(other) = state.claims.values().find(|c| c.overlaps(min, max)) {
+            return Err(ClaimError::Overlaps(other.id));
+        }
+
+        state.next_id += 1;
+        let id = state.next_id;
+        state.claims.insert(
+            id,
+            Claim {
+                id,
+                owner,
+                min,
+                max,
+            },
+        );
+        drop(state);
+
+        self.changed.notify_one();
+        Ok(id)
+    }
+
+    /// Hands a claim `player` owns directly (not through a group) to a new
+    /// owner.
+    pub fn transfer_for(&self, player: Option<&str>, id: u32, to: Owner) -> Result<(), ClaimError> {
+        let player = player.ok_or(ClaimError::Anonymous)?;
+        self.set_owner(id, to, |owner| *owner == Owner::Player(player.into()))
+    }
+
+    /// Hands any claim to a new owner (admin).
+    pub fn transfer(&self, id: u32, to: Owner) -> Result<(), ClaimError> {
+        self.set_owner(id, to, |_| true)
+    }
+
+    fn set_owner(
+        &self,
+        id: u32,
+        to: Owner,
+        allowed: impl FnOnce(&Owner) -> bool,
+    ) -> Result<(), ClaimError> {
+        let mut state = self.state.lock().unwrap();
+        let claim = state.claims.get_mut(&id).ok_or(ClaimError::NotFound(id))?;
+        if !allowed(&claim.owner) {
+            return Err(ClaimError::NotOwner(id));
+        }
+
+        claim.owner = to;
+        drop(state);
+
+        self.changed.notify_one();
+        Ok(())
+    }
+
+    pub fn remove(&self, id: u32) -> Result<(), ClaimError> {
+        let removed = self.state.lock().unwrap().claims.remove(&id);
+        removed.ok_or(ClaimError::NotFound(id))?;
+        self.changed.notify_one();
+        Ok(())
+    }
+
+    /// Replaces a group's members; an empty list deletes the group.
+    pub fn set_group(&self, name: &str, members: BTreeSet<String>) {
+        let mut state = self.state.lock().unwrap();
+        if members.is_empty() {
+            state.groups.remove(name);
+        } else {
+            state.groups.insert(name.into(), members);
+        }
+        drop(state);
+
+        self.changed.notify_one();
+    }
+
+    async fn run_saver(self: Arc<Self>, saved: Saved) {
+        loop {
+            self.changed.notified().await;
+
+            let data = encode(&self.state.lock().unwrap());
+            let path = match &saved {
+                Saved::File(path) => path.clone(),
+                Saved::Storage(storage) => {
+                    if let Err(e) = storage.save_claims(&data).await {
+                        eprintln!("Claims save failed: {e}");
+                    }
+                    continue;
+                }
+            };
+            let result = tokio::task::spawn_blocking(move || {
+                let tmp = path.with_extension("tbx.tmp");
+                if let Some(dir) = path.parent() {
+                    fs::create_dir_all(dir)?;
+                }
+                fs::write(&tmp, data)?;
+                fs::rename(&tmp, &path)
+            })
+            .await;
+
+            match result {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => eprintln!("Claims save failed: {e}"),
+                Err(e) => eprintln!("Claims save panicked: {e}"),
+            }
+        }
+    }
+}
+
+impl ClaimsState {
+    fn is_owner(&self, owner: &Owner, player: &str) -> bool {
+        match owner {
+            Owner::Player(name) => name == player,
+            Owner::Group(group) => self.groups.get(group).is_some_and(|m| m.contains(player)),
+        }
+    }
+}
+
+fn bounds(a: BlockPos, b: BlockPos) -> (BlockPos, BlockPos) {
+    (
+        (a.0.min(b.0), a.1.min(b.1), a.2.min(b.2)),
+        (a.0.max(b.0), a.1.max(b.1), a.2.max(b.2)),
+    )
+}
+
+fn encode(state: &ClaimsState) -> Vec<u8> {
+    let mut buf = Vec::new();
+    save::write_header(&mut buf, SaveKind::Claims);
+
+    buf.extend_from_slice(&state.next_id.to_le_bytes());
+    buf.extend_from_slice(&(state.claims.len() as u32).to_le_bytes());
+    for claim in state.claims.values() {
+        buf.extend_from_slice(&claim.id.to_le_bytes());
+        for v in [claim.min, claim.max].iter().flat_map(|p| [p.0, p.1, p.2]) {
+            buf.extend_from_slice(&v.to_le_bytes());
+        }
+        let (kind, name) = match &claim.owner {
+            Owner::Player(name) => (0, name),
+            Owner::Group(name) => (1, name),
+        };
+        buf.push(kind);
+        write_name(&mut buf, name);
+    }
+
+    buf.extend_from_slice(&(state.groups.len() as u32).to_le_bytes());
+    for (name, members) in &state.groups {
+        write_name(&mut buf, name);
+        buf.extend_from_slice(&(members.len() as u16).to_le_bytes());
+        for member in members {
+            write_name(&mut buf, member);
+        }
+    }
+
+    buf
+}
+
+fn write_name(buf: &mut Vec<u8>, name: &str) {
+    buf.extend_from_slice(&(name.len() as u16).to_le_bytes());
+    buf.extend_from_slice(name.as_bytes());
+}
+
+fn decode(data: &[u8]) -> Result<ClaimsState, SaveError> {
+    let body = save::read(data, SaveKind::Claims)?;
+    let mut r = Reader { data: &body, at: 0 };
+    let mut state = ClaimsState {
+        next_id: r.u32()?,
+        ..Default::default()
+    };
+
+    for _ in 0..r.u32()? {
+        let id = r.u32()?;
+        let min = (r.i32()?, r.i32()?, r.i32()?);
+        let max = (r.i32()?, r.i32()?, r.i32()?);
+        let owner = match r.u8()? {
+            0 => Owner::Player(r.name()?),
+            1 => Owner::Group(r.name()?),
+            _ => return Err(SaveError::Invalid("unknown claim owner kind")),
+        };
+        state.claims.insert(
+            id,
+            Claim {
+                id,
+                owner,
+                min,
+                max,
+            },
+        );
+    }
+
+    for _ in 0..r.u32()? {
+        let name = r.name()?;
+        let members = (0..r.u16()?).map(|_| r.name()).collect::<Result<_, _>>()?;
+        state.groups.insert(name, members);
+    }
+
+    if r.at != body.len() {
+        return Err(SaveError::Invalid("trailing bytes after claims"));
+    }
+    Ok(state)
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    at: usize,
+}
+
+impl Reader<'_> {
+    fn take(&mut self, n: usize) -> Result<&[u8], SaveError> {
+        let bytes = self
+            .data
+            .get(self.at..self.at + n)
+            .ok_or(SaveError::Invalid("claims body is truncated"))?;
+        self.at += n;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, SaveError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, SaveError> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, SaveError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn i32(&mut self) -> Result<i32, SaveError> {
+        Ok(i32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn name(&mut self) -> Result<String, SaveError> {
+        let len = self.u16()? as usize;
+        String::from_utf8(self.take(len)?.to_vec()).map_err(|_| SaveError::Invalid("bad name"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Fixture claims files, one per format version ever released. Each holds
+    /// claim 1 from (0, 0, 0) to (9, 9, 9) owned by `player:alice`, claim 2
+    /// from (-20, 0, -20) to (-11, 5, -11) owned by `group:builders`, and
+    /// the group `builders` with members `bob` and `carol`.
+    const CLAIMS_FIXTURES: &[(u16, &[u8])] =
+        &[(1, include_bytes!("../tests/fixtures/claims_v1.tbx"))];
+
+    fn expected() -> ClaimsState {
+        let mut state = ClaimsState {
+            next_id: 2,
+            ..Default::default()
+        };
+        state.claims.insert(
+            1,
+            Claim {
+                id: 1,
+                owner: Owner::Player("alice".into()),
+                min: (0, 0, 0),
+                max: (9, 9, 9),
+            },
+        );
+        state.claims.insert(
+            2,
+            Claim {
+                id: 2,
+                owner: Owner::Group("builders".into()),
+                min: (-20, 0, -20),
+                max: (-11, 5, -11),
+            },
+        );
+        state
+            .groups
+            .insert("builders".into(), ["bob".into(), "carol".into()].into());
+        state
+    }
+
+    fn claims(state: ClaimsState) -> Claims {
+        Claims {
+            state: Mutex::new(state),
+            changed: Notify::new(),
+        }
+    }
+
+    #[test]
+    fn every_historical_claims_version_loads() {
+        let versions: Vec<u16> = CLAIMS_FIXTURES.iter().map(|(v, _)| *v).collect();
+        assert_eq!(versions, (1..=save::CURRENT_VERSION).collect::<Vec<_>>());
+
+        for (version, data) in CLAIMS_FIXTURES {
+            assert_eq!(decode(data), Ok(expected()), "fixture v{version}");
+        }
+        assert_eq!(decode(&encode(&expected())), Ok(expected()));
+    }
+
+    #[test]
+    fn edits_follow_ownership() {
+        let claims = claims(expected());
+
+        assert!(claims.can_edit(Some("alice"), (5, 5, 5)));
+        assert!(!claims.can_edit(Some("bob"), (5, 5, 5)));
+        assert!(!claims.can_edit(None, (5, 5, 5)));
+        assert!(claims.can_edit(Some("carol"), (-15, 0, -15)));
+        assert!(!claims.can_edit(Some("alice"), (-15, 0, -15)));
+        // Unclaimed land is open to everyone
+        assert!(claims.can_edit(None, (10, 0, 0)));
+
+        claims
+            .transfer_for(Some("alice"), 1, Owner::Group("builders".into()))
+            .unwrap();
+        assert!(claims.can_edit(Some("bob"), (5, 5, 5)));
+        assert!(!claims.can_edit(Some("alice"), (5, 5, 5)));
+    }
+
+    #[test]
+    fn claims_are_validated() {
+        let claims = claims(expected());
+
+        assert_eq!(
+            claims.claim_for(Some("dave"), (9, 0, 0), (12, 3, 3)),
+            Err(ClaimError::Overlaps(1))
+        );
+        assert_eq!(
+            claims.claim_for(
+                Some("dave"),
+                (100, 0, 0),
+                (100 + MAX_PLAYER_CLAIM_SIZE, 0, 0)
+            ),
+            Err(ClaimError::TooLarge)
+        );
+        assert_eq!(
+            claims.claim_for(None, (100, 0, 0), (101, 0, 0)),
+            Err(ClaimError::Anonymous)
+        );
+        assert_eq!(
+            claims.claim_for(Some("dave"), (110, 1, 1), (100, 0, 0)),
+            Ok(3)
+        );
+        assert_eq!(claims.list()[2].min, (100, 0, 0));
+
+        assert_eq!(
+            claims.transfer_for(Some("alice"), 3, Owner::Player("alice".into())),
+            Err(ClaimError::NotOwner(3))
+        );
+        assert_eq!(claims.transfer(3, Owner::Player("alice".into())), Ok(()));
+        assert_eq!(claims.remove(9), Err(ClaimError::NotFound(9)));
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn saves_to_storage_without_a_world_dir() {
+        let path = std::env::temp_dir().join(format!("teleboxel-claims-{}.db", std::process::id()));
+        let url = format!("sqlite://{}", path.display());
+        let storage = crate::storage::connect(&url).await.unwrap();
+        let claims = Claims::load_stored(storage.clone()).await.unwrap();
+        assert!(claims.list().is_empty());
+        let id = claims
+            .claim_for(Some("alice"), (0, 0, 0), (3, 3, 3))
+            .unwrap();
+
+        let mut loaded = Vec::new();
+        for _ in 0..100 {
+            loaded = Claims::load_stored(storage.clone()).await.unwrap().list();
+            if !loaded.is_empty() {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        drop(storage);
+        std::fs::remove_file(&path).ok();
+        assert_eq!(loaded, claims.list());
+        assert_eq!(loaded[0].id, id);
+    }
+}
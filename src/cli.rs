@@ -0,0 +1,89 @@
+//! Subcommands, run instead of the server:
+//!
+//! - `teleboxel import-vox <map.vox> <world_dir>`
+//! - `teleboxel export-vox <world_dir> <map.vox>`
+//! - `teleboxel console <socket>` attaches a console to a running server's
+//!   control socket
+//! - `teleboxel dump-frames <file>` prints a frame recording from the admin
+//!   API as JSON lines, see `recorder.rs`
+//! - `teleboxel server-key` makes a server key for encrypted and signed
+//!   sessions, and prints the public key for clients to pin (`secure.rs`)
+
+use crate::{protocol, recorder, save, secure::ServerKey, vox};
+use std::{error::Error, fs, path::Path, process::ExitCode};
+
+/// Runs a subcommand if `args` (including the program name) names one,
+/// otherwise returns `None` so the caller starts the server.
+pub fn run(args: &[String]) -> Option<ExitCode> {
+    let result = match args.get(1).map(String::as_str) {
+        Some("import-vox") => with_paths(args, import_vox),
+        Some("export-vox") => with_paths(args, export_vox),
+        #[cfg(unix)]
+        Some("console") => match args {
+            [_, _, socket] => crate::console::attach(Path::new(socket)).map_err(Into::into),
+            _ => Err("expected <socket>".into()),
+        },
+        Some("dump-frames") => match args {
+            [_, _, file] => dump_frames(Path::new(file)),
+            _ => Err("expected <file>".into()),
+        },
+        Some("server-key") => server_key(),
+        _ => return None,
+    };
+
+    Some(match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{}: {e}", args[1]);
+            ExitCode::FAILURE
+        }
+    })
+}
+
+type CliResult = Result<(), Box<dyn Error>>;
+
+fn with_paths(args: &[String], f: fn(&Path, &Path) -> CliResult) -> CliResult {
+    match args {
+        [_, _, from, to] => f(Path::new(from), Path::new(to)),
+        _ => Err("expected <from> <to>".into()),
+    }
+}
+
+fn import_vox(vox_file: &Path, world_dir: &Path) -> CliResult {
+    let store = vox::import(&fs::read(vox_file)?)?;
+    save::save_world(world_dir, &store)?;
+    println!(
+        "Imported {} chunks into {}",
+        store.len(),
+        world_dir.display()
+    );
+    Ok(())
+}
+
+fn export_vox(world_dir: &Path, vox_file: &Path) -> CliResult {
+    let store = save::load_world(world_dir)?;
+    let (data, skipped) = vox::export(&store);
+    fs::write(vox_file, data)?;
+
+    println!("Exported {} chunks to {}", store.len(), vox_file.display());
+    if skipped > 0 {
+        eprintln!("Skipped {skipped} blocks with ids above 255 (no palette index)");
+    }
+    Ok(())
+}
+
+// `<ms> <frame as JSON>` per line
+fn dump_frames(file: &Path) -> CliResult {
+    let data = fs::read(file)?;
+    for (ms, frame) in recorder::read(&data)? {
+        println!("{ms} {}", protocol::frame_to_json(frame)?);
+    }
+    Ok(())
+}
+
+fn server_key() -> CliResult {
+    let key = ServerKey::generate()?;
+    println!("TELEBOXEL_SERVER_KEY={}", key.secret());
+    println!("Public key for clients: {}", key.public());
+    Ok(())
+}
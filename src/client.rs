@@ -0,0 +1,1111 @@
+//! Minimal client state machine, for native engine bindings (`ffi/`) and
+//! tests. Feed it what the server sends; it tracks the handshake, the block
+//! registry and the chunks in the interest, applying snapshots and deltas
+//! the way the server expects, and queues events for the game to react to.
+//!
+//! Commands still go out as text (see `command.rs`), built by the
+//! `*_command` functions.
+//!
+//! After `origin_command`, positions and chunks are from the server's
+//! origin for the player (see `origin.rs`): it follows the newest one and
+//! says so with `ClientEvent::Rebased`. Connected with `?coords=1`,
+//! they're the world's way (see `coords.rs`), told with
+//! `ClientEvent::Coords`.
+
+use crate::{
+    blocks::BlockDef,
+    chunk::{CHUNK_SIZE, Chunk, ChunkPos, split},
+    claims::BlockPos,
+    coords::Coords,
+    effects::Effect,
+    entities::{Attachment, Entity, Mount, Parent},
+    features::Features,
+    lockstep::LockstepInput,
+    minimap::Minimap,
+    origin,
+    protocol::{self, ClientFrame, ProtocolError, ServerMsg},
+    relay, voice,
+};
+use bytes::Bytes;
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+};
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ClientEvent {
+    /// Handshake done, the server assigned this player id.
+    Connected {
+        id: u32,
+    },
+    /// The block registry arrived, see `Client::blocks`.
+    BlockRegistry,
+    /// A chunk was replaced by a snapshot or edited by a delta.
+    ChunkChanged {
+        pos: ChunkPos,
+        version: u32,
+    },
+    /// Text reply to a command, e.g. `SetBlock Ok`.
+    Reply(String),
+    /// A chat line from a player or a bridge, e.g. `telegram:alice`.
+    Chat {
+        from: String,
+        text: String,
+    },
+    /// The server closes the connection within `seconds`. Reconnect to
+    /// `address` if there is one.
+    Drain {
+        seconds: u16,
+        address: Option<String>,
+    },
+    /// Reconnect with `?resume=<token>` to carry on where this connection
+    /// left off, at the `Drain` address or this one. Comes before `Drain`.
+    Resume {
+        token: String,
+    },
+    /// Moved to `room` (`None` for the main world) as player `id`. The held
+    /// chunks were dropped, the new room's arrive as `ChunkChanged`.
+    RoomChanged {
+        room: Option<String>,
+        id: u32,
+    },
+    /// Walked into a portal to another server: connect to `address` with
+    /// `?resume=<token>`. The server closes this connection.
+    Transfer {
+        address: String,
+        token: String,
+    },
+    /// Lockstep rooms: relay tick `tick` closed with these inputs, in
+    /// player id order. Check `hash` against `lockstep::hash` of them.
+    LockstepTick {
+        tick: u32,
+        hash: u32,
+        inputs: Vec<LockstepInput>,
+    },
+    /// Lockstep rooms: the state to start from on joining, as of `tick`
+    /// (0 and empty if nobody uploaded one). Ticks closed since follow.
+    LockstepState {
+        tick: u32,
+        state: Vec<u8>,
+    },
+    /// Rollback rooms, on joining: the room's `frame` and the input
+    /// `delay`, how many frames ahead to send inputs for.
+    Rollback {
+        frame: u32,
+        delay: u32,
+    },
+    /// Rollback rooms: player `player`'s input for `frame`, to predict with
+    /// until it's confirmed.
+    RollbackInput {
+        player: u32,
+        frame: u32,
+        data: String,
+    },
+    /// Rollback rooms: `frame` is final with these inputs, in player id
+    /// order. Roll back to it if they aren't the ones predicted.
+    RollbackConfirm {
+        frame: u32,
+        hash: u32,
+        inputs: Vec<LockstepInput>,
+    },
+    /// Relay rooms: `data` from player `from`, as it was sent.
+    Relay {
+        from: u32,
+        data: Vec<u8>,
+    },
+    /// An Opus frame from player `from`, `distance` blocks away with
+    /// `occlusion` solid blocks in between (0 unless the server counts
+    /// them).
+    Voice {
+        from: u32,
+        distance: u16,
+        occlusion: u8,
+        data: Vec<u8>,
+    },
+    /// The server moved the player to this block position, move on from it.
+    Teleported {
+        position: (i32, i32, i32),
+    },
+    /// What's on in the player's world, on joining one and when toggled.
+    /// Chat and block edits that are off get an error reply.
+    Features(Features),
+    /// A non-player entity spawned, moved or changed hands. Only moves for
+    /// the ones this player has authority over are accepted. `attached`
+    /// is left `None`, that comes as `Attached`, and `fixed` false.
+    Entity(Entity),
+    EntityGone {
+        id: u32,
+    },
+    /// A player rides an entity: draw them at its position plus `offset`.
+    /// While this player rides, its own moves are ignored.
+    Mounted(Mount),
+    /// A player got off, or left, here.
+    Dismounted {
+        player: u32,
+        position: (i32, i32, i32),
+    },
+    /// An entity hangs from a parent: place it from the parent as that
+    /// moves, its own `Entity` events only come for player parents.
+    Attached {
+        entity: u32,
+        attachment: Attachment,
+    },
+    /// Let go here.
+    Detached {
+        entity: u32,
+        position: (i32, i32, i32),
+    },
+    /// A one-off effect in the interest, play it and forget it.
+    Effect(Effect),
+    /// The player walked into (`entered`) or out of a trigger volume.
+    Trigger {
+        trigger: String,
+        entered: bool,
+    },
+    /// The server applied this player's moves and edits up to `seq`.
+    InputAck {
+        seq: u32,
+    },
+    /// The answer to `time_sync_command`, or the handshake's with
+    /// `client_time` 0, see `clock.rs`.
+    TimeSync {
+        client_time: u32,
+        server_time: u32,
+        tick_hz: u16,
+        /// Milliseconds to render behind the newest snapshot.
+        interp_delay: u16,
+    },
+    /// Positions and held chunks are from block `origin` now, the old
+    /// ones were moved over: shift the scene by the difference and send
+    /// `origin_command` with it.
+    Rebased {
+        origin: BlockPos,
+    },
+    /// Positions from now on are in `units` per block, and without a z
+    /// (always 0) when `flat`. Chunk positions stay chunks.
+    Coords(Coords),
+    /// The world's simulation changed, or isn't running normally on
+    /// joining: no ticks while `paused`, ticks at `scale` percent of the
+    /// tick rate (slow motion under 100).
+    Simulation {
+        paused: bool,
+        scale: u16,
+    },
+    /// The server stalled and `ticks` of game time weren't simulated: the
+    /// tick goes on from where it was, drop that much wall time from
+    /// clocks synced to it.
+    TimeSkip {
+        ticks: u32,
+    },
+    /// How to interpolate in this world, on joining and when it changes:
+    /// updates come every `update_ms`, render `delay_ms` behind the newest
+    /// and keep `buffer` of them.
+    InterpHint {
+        update_ms: u16,
+        delay_ms: u16,
+        buffer: u8,
+    },
+    /// The player earned the world's achievement `name`, see
+    /// `achievements.rs`.
+    Achievement {
+        name: String,
+        title: String,
+    },
+    /// The minimap a `minimap_command` asked for, see `minimap.rs`.
+    Minimap(Minimap),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ClientError {
+    /// The first message wasn't the player id.
+    Handshake,
+    /// Binary frame before the handshake.
+    NotConnected,
+    Protocol(ProtocolError),
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::Handshake => write!(f, "expected the player id"),
+            ClientError::NotConnected => write!(f, "frame before the handshake"),
+            ClientError::Protocol(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+#[derive(Default)]
+pub struct Client {
+    id: Option<u32>,
+    tick: u32,
+    blocks: Vec<BlockDef>,
+    chunks: HashMap<ChunkPos, (u32, Chunk)>,
+    events: VecDeque<ClientEvent>,
+    // What positions are from and the tick of the frame it came in
+    origin: Option<(BlockPos, u32)>,
+    coords: Coords,
+}
+
+impl Client {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Player id, once connected.
+    pub fn id(&self) -> Option<u32> {
+        self.id
+    }
+
+    /// Tick of the last server frame.
+    pub fn tick(&self) -> u32 {
+        self.tick
+    }
+
+    /// The origin positions and chunks are from, `None` while absolute.
+    pub fn origin(&self) -> Option<BlockPos> {
+        self.origin.map(|(origin, _)| origin)
+    }
+
+    /// How positions come, see `ClientEvent::Coords`.
+    pub fn coords(&self) -> Coords {
+        self.coords
+    }
+
+    /// Block types in id order, empty until `ClientEvent::BlockRegistry`.
+    pub fn blocks(&self) -> &[BlockDef] {
+        &self.blocks
+    }
+
+    pub fn chunk(&self, pos: ChunkPos) -> Option<(u32, &Chunk)> {
+        self.chunks
+            .get(&pos)
+            .map(|(version, chunk)| (*version, chunk))
+    }
+
+    /// Block at block coordinates (from the origin, if any), `0` (air) if
+    /// the chunk isn't held.
+    pub fn block(&self, x: i32, y: i32, z: i32) -> u16 {
+        let ((cx, lx), (cy, ly), (cz, lz)) = (split(x), split(y), split(z));
+        self.chunk((cx, cy, cz))
+            .map_or(0, |(_, c)| c.get(lx, ly, lz))
+    }
+
+    /// Forgets a chunk, e.g. once it leaves the interest. The server sends
+    /// a snapshot if it comes back.
+    pub fn forget(&mut self, pos: ChunkPos) {
+        self.chunks.remove(&pos);
+    }
+
+    pub fn next_event(&mut self) -> Option<ClientEvent> {
+        self.events.pop_front()
+    }
+
+    /// A text websocket message: the handshake, then command replies.
+    pub fn receive_text(&mut self, text: &str) -> Result<(), ClientError> {
+        if self.id.is_some() {
+            self.events.push_back(ClientEvent::Reply(text.to_string()));
+            return Ok(());
+        }
+
+        let id = text.parse().map_err(|_| ClientError::Handshake)?;
+        self.id = Some(id);
+        self.events.push_back(ClientEvent::Connected { id });
+        Ok(())
+    }
+
+    /// A binary websocket message (one server frame). Nothing is applied
+    /// when the frame is malformed.
+    pub fn receive_binary(&mut self, data: &[u8]) -> Result<(), ClientError> {
+        if self.id.is_none() {
+            return Err(ClientError::NotConnected);
+        }
+
+        let (tick, msgs) = protocol::decode_server_frame_as(data, self.coords.flat)
+            .map_err(ClientError::Protocol)?;
+        self.tick = tick;
+        // The frame's origin, moved to if it's the newest
+        let from = match msgs.first() {
+            Some(&ServerMsg::Rebase { x, y, z }) => Some((x, y, z)),
+            _ => None,
+        };
+        if let Some(from) = from
+            && self
+                .origin
+                .is_none_or(|(at, since)| at != from && tick > since)
+        {
+            self.rebase(from, tick);
+        }
+        let (from, ours) = (from.unwrap_or_default(), self.origin().unwrap_or_default());
+        let shift = Shift {
+            blocks: self.coords.scale(origin::relative(ours, from)),
+            chunks: origin::relative(origin::chunk(ours), origin::chunk(from)),
+        };
+        for msg in msgs {
+            self.apply(msg, &shift);
+        }
+        Ok(())
+    }
+
+    fn rebase(&mut self, to: BlockPos, tick: u32) {
+        let ours = self.origin().unwrap_or_default();
+        let by = origin::relative(origin::chunk(to), origin::chunk(ours));
+        self.chunks = self
+            .chunks
+            .drain()
+            .map(|(pos, held)| (origin::absolute(by, pos), held))
+            .collect();
+        self.origin = Some((to, tick));
+        self.events.push_back(ClientEvent::Rebased { origin: to });
+    }
+
+    fn apply(&mut self, msg: ServerMsg, shift: &Shift) {
+        let at = |x, y, z| origin::absolute(shift.blocks, (x, y, z));
+        match msg {
+            ServerMsg::ChunkSnapshot {
+                pos,
+                version,
+                chunk,
+            } => {
+                let pos = origin::absolute(shift.chunks, pos);
+                self.chunks.insert(pos, (version, chunk));
+                self.events
+                    .push_back(ClientEvent::ChunkChanged { pos, version });
+            }
+            ServerMsg::ChunkDelta {
+                pos,
+                base_version,
+                version,
+                edits,
+            } => {
+                let pos = origin::absolute(shift.chunks, pos);
+                // Not the version the delta builds on, a snapshot will follow
+                let Some((held, chunk)) = self.chunks.get_mut(&pos) else {
+                    return;
+                };
+                if *held != base_version {
+                    return;
+                }
+
+                for (index, block) in edits {
+                    // y-major, see `Chunk::index`
+                    let i = index as usize;
+                    let (x, y, z) = (
+                        i % CHUNK_SIZE,
+                        i / (CHUNK_SIZE * CHUNK_SIZE),
+                        i / CHUNK_SIZE % CHUNK_SIZE,
+                    );
+                    chunk.set(x, y, z, block);
+                }
+                *held = version;
+                self.events
+                    .push_back(ClientEvent::ChunkChanged { pos, version });
+            }
+            ServerMsg::BlockRegistry { blocks } => {
+                self.blocks = blocks;
+                self.events.push_back(ClientEvent::BlockRegistry);
+            }
+            ServerMsg::Chat { from, text } => {
+                self.events.push_back(ClientEvent::Chat { from, text });
+            }
+            ServerMsg::Drain { seconds, address } => {
+                let address = Some(address).filter(|a| !a.is_empty());
+                self.events
+                    .push_back(ClientEvent::Drain { seconds, address });
+            }
+            ServerMsg::Resume { token } => {
+                self.events.push_back(ClientEvent::Resume { token });
+            }
+            ServerMsg::Transfer { address, token } => {
+                self.events
+                    .push_back(ClientEvent::Transfer { address, token });
+            }
+            ServerMsg::Room { room, id } => {
+                self.id = Some(id);
+                self.chunks.clear();
+                // Ticks start over in another world
+                if let Some((_, since)) = &mut self.origin {
+                    *since = 0;
+                }
+                let room = Some(room).filter(|r| !r.is_empty());
+                self.events.push_back(ClientEvent::RoomChanged { room, id });
+            }
+            ServerMsg::Lockstep { tick, hash, inputs } => {
+                self.events
+                    .push_back(ClientEvent::LockstepTick { tick, hash, inputs });
+            }
+            ServerMsg::LockstepState { tick, state } => {
+                self.events
+                    .push_back(ClientEvent::LockstepState { tick, state });
+            }
+            ServerMsg::Rollback { frame, delay } => {
+                let delay = delay.into();
+                self.events
+                    .push_back(ClientEvent::Rollback { frame, delay });
+            }
+            ServerMsg::RollbackInput {
+                player,
+                frame,
+                data,
+            } => {
+                self.events.push_back(ClientEvent::RollbackInput {
+                    player,
+                    frame,
+                    data,
+                });
+            }
+            ServerMsg::RollbackConfirm {
+                frame,
+                hash,
+                inputs,
+            } => {
+                self.events.push_back(ClientEvent::RollbackConfirm {
+                    frame,
+                    hash,
+                    inputs,
+                });
+            }
+            ServerMsg::Relay { from, data } => {
+                self.events.push_back(ClientEvent::Relay { from, data });
+            }
+            ServerMsg::Voice {
+                from,
+                distance,
+                occlusion,
+                data,
+            } => {
+                self.events.push_back(ClientEvent::Voice {
+                    from,
+                    distance,
+                    occlusion,
+                    data,
+                });
+            }
+            ServerMsg::Teleport { x, y, z } => {
+                let position = at(x, y, z);
+                self.events.push_back(ClientEvent::Teleported { position });
+            }
+            ServerMsg::Features { chat, build, pvp } => {
+                let features = Features { chat, build, pvp };
+                self.events.push_back(ClientEvent::Features(features));
+            }
+            ServerMsg::Entity {
+                id,
+                x,
+                y,
+                z,
+                authority,
+            } => {
+                let entity = Entity {
+                    id,
+                    position: at(x, y, z),
+                    authority: (authority != 0).then_some(authority),
+                    attached: None,
+                    fixed: false,
+                    always: false,
+                    layer: 0,
+                };
+                self.events.push_back(ClientEvent::Entity(entity));
+            }
+            ServerMsg::EntityGone { id } => {
+                self.events.push_back(ClientEvent::EntityGone { id });
+            }
+            ServerMsg::Mount {
+                player,
+                entity,
+                x,
+                y,
+                z,
+            } => {
+                let mount = Mount {
+                    player,
+                    entity,
+                    offset: (x, y, z),
+                };
+                self.events.push_back(ClientEvent::Mounted(mount));
+            }
+            ServerMsg::Dismount { player, x, y, z } => {
+                let position = at(x, y, z);
+                self.events
+                    .push_back(ClientEvent::Dismounted { player, position });
+            }
+            ServerMsg::Attach {
+                entity,
+                parent,
+                player,
+                x,
+                y,
+                z,
+            } => {
+                let parent = match player {
+                    true => Parent::Player(parent),
+                    false => Parent::Entity(parent),
+                };
+                let attachment = Attachment {
+                    parent,
+                    offset: (x, y, z),
+                };
+                self.events
+                    .push_back(ClientEvent::Attached { entity, attachment });
+            }
+            ServerMsg::Detach { entity, x, y, z } => {
+                let position = at(x, y, z);
+                self.events
+                    .push_back(ClientEvent::Detached { entity, position });
+            }
+            ServerMsg::Effect {
+                effect,
+                x,
+                y,
+                z,
+                params,
+            } => {
+                let effect = Effect {
+                    id: effect,
+                    position: at(x, y, z),
+                    params,
+                    layer: 0,
+                };
+                self.events.push_back(ClientEvent::Effect(effect));
+            }
+            ServerMsg::Trigger { trigger, entered } => {
+                self.events
+                    .push_back(ClientEvent::Trigger { trigger, entered });
+            }
+            ServerMsg::InputAck { seq } => self.events.push_back(ClientEvent::InputAck { seq }),
+            ServerMsg::TimeSync {
+                client_time,
+                server_time,
+                tick_hz,
+                interp_delay,
+            } => self.events.push_back(ClientEvent::TimeSync {
+                client_time,
+                server_time,
+                tick_hz,
+                interp_delay,
+            }),
+            // Read in `receive_binary`
+            ServerMsg::Rebase { .. } => {}
+            ServerMsg::Simulation { paused, scale } => {
+                self.events
+                    .push_back(ClientEvent::Simulation { paused, scale });
+            }
+            ServerMsg::TimeSkip { ticks } => {
+                self.events.push_back(ClientEvent::TimeSkip { ticks });
+            }
+            ServerMsg::InterpHint {
+                update_ms,
+                delay_ms,
+                buffer,
+            } => {
+                self.events.push_back(ClientEvent::InterpHint {
+                    update_ms,
+                    delay_ms,
+                    buffer,
+                });
+            }
+            ServerMsg::Achievement { name, title } => {
+                self.events
+                    .push_back(ClientEvent::Achievement { name, title });
+            }
+            ServerMsg::Minimap {
+                x,
+                y,
+                z,
+                scale,
+                size,
+                heights,
+                colors,
+            } => {
+                let (x, bottom, z) = at(x, y, z);
+                self.events.push_back(ClientEvent::Minimap(Minimap {
+                    x,
+                    z,
+                    bottom,
+                    scale,
+                    size,
+                    heights,
+                    colors,
+                }));
+            }
+            ServerMsg::Coords { flat, units } => {
+                self.coords = Coords { flat, units };
+                self.events.push_back(ClientEvent::Coords(self.coords));
+            }
+        }
+    }
+}
+
+// From a frame's origin to the client's, see `Client::receive_binary`
+struct Shift {
+    blocks: BlockPos,
+    chunks: ChunkPos,
+}
+
+/// Interest cube around a chunk, radius in chunks (capped by the server).
+pub fn set_interest_command((x, y, z): ChunkPos, radius: u16) -> String {
+    format!("SetInterest {x} {y} {z} {radius}")
+}
+
+/// Positions from block `origin` both ways from now on, a chunk corner,
+/// see `origin.rs`. Also the answer to `ClientEvent::Rebased`.
+pub fn origin_command((x, y, z): BlockPos) -> String {
+    format!("Origin {x} {y} {z}")
+}
+
+/// World block coordinates.
+pub fn set_position_command((x, y, z): (i32, i32, i32)) -> String {
+    format!("SetPosition {x} {y} {z}")
+}
+
+/// World block coordinates, yaw and pitch in degrees, velocity in blocks
+/// per second. One command where a `SetPosition` would need a rotation too.
+pub fn set_transform_command(
+    (x, y, z): (i32, i32, i32),
+    (yaw, pitch): (f32, f32),
+    velocity: Option<(f32, f32, f32)>,
+) -> String {
+    match velocity {
+        Some((vx, vy, vz)) => format!("SetTransform {x} {y} {z} {yaw} {pitch} {vx} {vy} {vz}"),
+        None => format!("SetTransform {x} {y} {z} {yaw} {pitch}"),
+    }
+}
+
+/// A move or edit command ended in a sequence number, acked back in
+/// `ClientEvent::InputAck` once the server applied it.
+pub fn sequenced(command: String, seq: u32) -> String {
+    format!("{command} #{seq}")
+}
+
+/// The client clock in ms (any start, wrapping) and the round trip the
+/// last `TimeSync` took, if any.
+pub fn time_sync_command(client_ms: u32, rtt_ms: Option<u32>) -> String {
+    match rtt_ms {
+        Some(rtt) => format!("TimeSync {client_ms} {rtt}"),
+        None => format!("TimeSync {client_ms}"),
+    }
+}
+
+/// One line, sent to everyone in the player's world or room.
+pub fn say_command(text: &str) -> String {
+    format!("Say {text}")
+}
+
+/// A custom gameplay event for the server's analytics, with `Key=Value`
+/// fields (no spaces in either).
+pub fn track_command(event: &str, fields: &[(&str, &str)]) -> String {
+    let mut command = format!("Track {event}");
+    for (key, value) in fields {
+        command += &format!(" {key}={value}");
+    }
+    command
+}
+
+/// World block coordinates.
+pub fn set_block_command((x, y, z): (i32, i32, i32), block: u16) -> String {
+    format!("SetBlock {x} {y} {z} {block}")
+}
+
+/// An entity this player has authority over, to world block coordinates.
+pub fn move_entity_command(id: u32, (x, y, z): (i32, i32, i32)) -> String {
+    format!("MoveEntity {id} {x} {y} {z}")
+}
+
+/// Rides an entity within `entities::MOUNT_RANGE` blocks.
+pub fn mount_command(entity: u32) -> String {
+    format!("Mount {entity}")
+}
+
+pub fn dismount_command() -> String {
+    "Dismount".to_string()
+}
+
+/// Moves to a room over this connection, `main` for the main world.
+pub fn join_room_command(room: &str) -> String {
+    format!("JoinRoom {room}")
+}
+
+pub fn leave_room_command() -> String {
+    "LeaveRoom".to_string()
+}
+
+/// A minimap of `size` cells of `scale` blocks around the player, answered
+/// with `ClientEvent::Minimap`. See `minimap.rs` for the limits.
+pub fn minimap_command(size: u8, scale: u8) -> String {
+    format!("Minimap {size} {scale}")
+}
+
+/// Lockstep rooms, this player's input for relay tick `tick`. Rollback
+/// rooms, for frame `tick`.
+pub fn input_command(tick: u32, data: &str) -> String {
+    format!("Input {tick} {data}")
+}
+
+/// Lockstep rooms, the state after relay tick `tick` for late joiners.
+pub fn lockstep_state_command(tick: u32, data: &str) -> String {
+    format!("LockstepState {tick} {data}")
+}
+
+/// Relay rooms, a binary client frame sending `data` to the players in `to`,
+/// or everyone else when it's empty. `None` when `to` has more than 255
+/// players or `data` is over `relay::MAX_DATA_LEN`.
+pub fn relay_frame(seq: u32, to: &[u32], data: &[u8]) -> Option<Bytes> {
+    if to.len() > u8::MAX as usize || data.len() > relay::MAX_DATA_LEN {
+        return None;
+    }
+    let mut frame = ClientFrame::new(seq);
+    frame.relay_send(to, data);
+    Some(frame.finish())
+}
+
+/// A binary client frame with one Opus frame for the players in voice
+/// range. `None` when `data` is over `voice::MAX_FRAME_LEN`.
+pub fn voice_frame(seq: u32, data: &[u8]) -> Option<Bytes> {
+    if data.len() > voice::MAX_FRAME_LEN {
+        return None;
+    }
+    let mut frame = ClientFrame::new(seq);
+    frame.voice_send(data);
+    Some(frame.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        lockstep::{self, LockstepTick},
+        protocol::ServerFrame,
+        terrain::{ChunkGenerator, FlatGenerator},
+    };
+
+    #[test]
+    fn applies_snapshots_and_matching_deltas() {
+        let mut client = Client::new();
+        assert_eq!(client.receive_binary(&[]), Err(ClientError::NotConnected));
+        client.receive_text("7").unwrap();
+        assert_eq!(client.next_event(), Some(ClientEvent::Connected { id: 7 }));
+
+        let mut frame = ServerFrame::new(3);
+        frame.chunk_snapshot((0, 0, 0), 1, &FlatGenerator.generate((0, 0, 0)));
+        frame.chunk_delta((0, 0, 0), 1, 2, &[(Chunk::index(1, 15, 3) as u16, 9)]);
+        // Wrong base, dropped
+        frame.chunk_delta((0, 0, 0), 1, 3, &[(Chunk::index(1, 15, 3) as u16, 4)]);
+        client.receive_binary(&frame.finish()).unwrap();
+
+        assert_eq!(client.tick(), 3);
+        assert_eq!(client.block(1, 15, 3), 9);
+        assert_eq!(client.chunk((0, 0, 0)).unwrap().0, 2);
+        let events: Vec<_> = std::iter::from_fn(|| client.next_event()).collect();
+        assert_eq!(
+            events,
+            [
+                ClientEvent::ChunkChanged {
+                    pos: (0, 0, 0),
+                    version: 1
+                },
+                ClientEvent::ChunkChanged {
+                    pos: (0, 0, 0),
+                    version: 2
+                },
+            ]
+        );
+
+        client.receive_text("SetBlock Ok").unwrap();
+        assert_eq!(
+            client.next_event(),
+            Some(ClientEvent::Reply("SetBlock Ok".into()))
+        );
+
+        let mut frame = ServerFrame::new(4);
+        frame.chat("telegram:alice", &"é".repeat(200));
+        client.receive_binary(&frame.finish()).unwrap();
+        // Cut to the `str` limit, on a char boundary
+        assert_eq!(
+            client.next_event(),
+            Some(ClientEvent::Chat {
+                from: "telegram:alice".into(),
+                text: "é".repeat(127)
+            })
+        );
+
+        let mut frame = ServerFrame::new(5);
+        frame.resume("1700000300.0,40,0..626f62.00ff");
+        frame.drain(30, "");
+        frame.drain(30, "ws://next:3000");
+        client.receive_binary(&frame.finish()).unwrap();
+        assert_eq!(
+            client.next_event(),
+            Some(ClientEvent::Resume {
+                token: "1700000300.0,40,0..626f62.00ff".into()
+            })
+        );
+        assert_eq!(
+            client.next_event(),
+            Some(ClientEvent::Drain {
+                seconds: 30,
+                address: None
+            })
+        );
+        assert_eq!(
+            client.next_event(),
+            Some(ClientEvent::Drain {
+                seconds: 30,
+                address: Some("ws://next:3000".into())
+            })
+        );
+
+        // Chunks from the old room are dropped
+        let mut frame = ServerFrame::new(6);
+        frame.room("arena", 2);
+        client.receive_binary(&frame.finish()).unwrap();
+        assert_eq!(
+            client.next_event(),
+            Some(ClientEvent::RoomChanged {
+                room: Some("arena".into()),
+                id: 2
+            })
+        );
+        assert_eq!(client.id(), Some(2));
+        assert!(client.chunk((0, 0, 0)).is_none());
+
+        let mut frame = ServerFrame::new(7);
+        frame.transfer("ws://eu:3000/", "1700000300...626f62.00ff");
+        client.receive_binary(&frame.finish()).unwrap();
+        assert_eq!(
+            client.next_event(),
+            Some(ClientEvent::Transfer {
+                address: "ws://eu:3000/".into(),
+                token: "1700000300...626f62.00ff".into()
+            })
+        );
+
+        let inputs = vec![LockstepInput {
+            player: 2,
+            data: "move 3 4".into(),
+        }];
+        let hash = lockstep::hash(12, &inputs);
+        let mut frame = ServerFrame::new(8);
+        frame.lockstep_state(11, b"units");
+        frame.lockstep(&LockstepTick {
+            tick: 12,
+            hash,
+            inputs: inputs.clone(),
+        });
+        client.receive_binary(&frame.finish()).unwrap();
+        assert_eq!(
+            client.next_event(),
+            Some(ClientEvent::LockstepState {
+                tick: 11,
+                state: b"units".to_vec()
+            })
+        );
+        assert_eq!(
+            client.next_event(),
+            Some(ClientEvent::LockstepTick {
+                tick: 12,
+                hash,
+                inputs
+            })
+        );
+
+        let mut frame = ServerFrame::new(9);
+        frame.relay(3, &[1, 2]);
+        client.receive_binary(&frame.finish()).unwrap();
+        assert_eq!(
+            client.next_event(),
+            Some(ClientEvent::Relay {
+                from: 3,
+                data: vec![1, 2]
+            })
+        );
+        assert!(relay_frame(0, &[], &[0; relay::MAX_DATA_LEN + 1]).is_none());
+
+        let mut frame = ServerFrame::new(10);
+        frame.voice(3, 12, 2, &[0xfc]);
+        client.receive_binary(&frame.finish()).unwrap();
+        assert_eq!(
+            client.next_event(),
+            Some(ClientEvent::Voice {
+                from: 3,
+                distance: 12,
+                occlusion: 2,
+                data: vec![0xfc]
+            })
+        );
+        assert!(voice_frame(0, &[0; voice::MAX_FRAME_LEN + 1]).is_none());
+
+        let mut frame = ServerFrame::new(11);
+        frame.teleport((4, -20, 9));
+        client.receive_binary(&frame.finish()).unwrap();
+        assert_eq!(
+            client.next_event(),
+            Some(ClientEvent::Teleported {
+                position: (4, -20, 9)
+            })
+        );
+
+        let mut frame = ServerFrame::new(12);
+        frame.features(false, true, false);
+        client.receive_binary(&frame.finish()).unwrap();
+        assert_eq!(
+            client.next_event(),
+            Some(ClientEvent::Features(Features {
+                chat: false,
+                build: true,
+                pvp: false,
+            }))
+        );
+
+        let mut frame = ServerFrame::new(13);
+        let cart = Entity {
+            id: 2,
+            position: (5, 41, -1),
+            authority: Some(7),
+            attached: None,
+            fixed: false,
+            always: false,
+            layer: 0,
+        };
+        frame.entity(&cart);
+        frame.entity_gone(3);
+        client.receive_binary(&frame.finish()).unwrap();
+        assert_eq!(client.next_event(), Some(ClientEvent::Entity(cart)));
+        assert_eq!(client.next_event(), Some(ClientEvent::EntityGone { id: 3 }));
+
+        let mut frame = ServerFrame::new(14);
+        let mount = Mount {
+            player: 7,
+            entity: 2,
+            offset: (0, 1, -1),
+        };
+        frame.mount(&mount);
+        frame.dismount(7, (5, 42, -2));
+        client.receive_binary(&frame.finish()).unwrap();
+        assert_eq!(client.next_event(), Some(ClientEvent::Mounted(mount)));
+        assert_eq!(
+            client.next_event(),
+            Some(ClientEvent::Dismounted {
+                player: 7,
+                position: (5, 42, -2)
+            })
+        );
+
+        let mut frame = ServerFrame::new(15);
+        let held = Attachment {
+            parent: Parent::Player(7),
+            offset: (1, 1, 0),
+        };
+        frame.attach(4, &held);
+        frame.detach(&Entity {
+            id: 4,
+            position: (6, 43, -2),
+            authority: None,
+            attached: None,
+            fixed: false,
+            always: false,
+            layer: 0,
+        });
+        client.receive_binary(&frame.finish()).unwrap();
+        assert_eq!(
+            client.next_event(),
+            Some(ClientEvent::Attached {
+                entity: 4,
+                attachment: held
+            })
+        );
+        assert_eq!(
+            client.next_event(),
+            Some(ClientEvent::Detached {
+                entity: 4,
+                position: (6, 43, -2)
+            })
+        );
+
+        let mut frame = ServerFrame::new(16);
+        let boom = Effect {
+            id: 3,
+            position: (0, 40, 0),
+            params: vec![1, 2],
+            layer: 0,
+        };
+        frame.effect(&boom);
+        client.receive_binary(&frame.finish()).unwrap();
+        assert_eq!(client.next_event(), Some(ClientEvent::Effect(boom)));
+
+        let mut frame = ServerFrame::new(17);
+        frame.trigger("lava", true);
+        client.receive_binary(&frame.finish()).unwrap();
+        assert_eq!(
+            client.next_event(),
+            Some(ClientEvent::Trigger {
+                trigger: "lava".into(),
+                entered: true
+            })
+        );
+
+        let mut frame = ServerFrame::new(18);
+        frame.input_ack(41);
+        client.receive_binary(&frame.finish()).unwrap();
+        assert_eq!(client.next_event(), Some(ClientEvent::InputAck { seq: 41 }));
+
+        let mut frame = ServerFrame::new(19);
+        frame.time_sync(7, 9000, 60, 34);
+        client.receive_binary(&frame.finish()).unwrap();
+        assert_eq!(
+            client.next_event(),
+            Some(ClientEvent::TimeSync {
+                client_time: 7,
+                server_time: 9000,
+                tick_hz: 60,
+                interp_delay: 34
+            })
+        );
+
+        // From an origin, the newest one is followed
+        let far = (1 << 30, 0, 0);
+        let mut frame = ServerFrame::new(20);
+        frame.chunk_snapshot(origin::chunk(far), 1, &Chunk::empty());
+        frame.teleport((far.0 + 5, 40, 0));
+        frame.rebase(far);
+        client.receive_binary(&frame.finish()).unwrap();
+        assert_eq!(client.origin(), Some(far));
+        let events: Vec<_> = std::iter::from_fn(|| client.next_event()).collect();
+        assert_eq!(
+            events,
+            [
+                ClientEvent::Rebased { origin: far },
+                ClientEvent::ChunkChanged {
+                    pos: (0, 0, 0),
+                    version: 1
+                },
+                ClientEvent::Teleported {
+                    position: (5, 40, 0)
+                },
+            ]
+        );
+        // An older frame from another one is moved over
+        let near = (far.0 - CHUNK_SIZE as i32, 0, 0);
+        let mut frame = ServerFrame::new(19);
+        frame.teleport(far);
+        frame.rebase(near);
+        client.receive_binary(&frame.finish()).unwrap();
+        assert_eq!(
+            client.next_event(),
+            Some(ClientEvent::Teleported {
+                position: (0, 0, 0)
+            })
+        );
+        // A newer one moves the client, and its chunks
+        let mut frame = ServerFrame::new(21);
+        frame.teleport(near);
+        frame.rebase(near);
+        client.receive_binary(&frame.finish()).unwrap();
+        assert_eq!(
+            client.next_event(),
+            Some(ClientEvent::Rebased { origin: near })
+        );
+        assert!(client.chunk((1, 0, 0)).is_some());
+        assert_eq!(origin_command(near), format!("Origin {} 0 0", near.0));
+    }
+}
@@ -0,0 +1,150 @@
+//! Clock sync, NTP style, for clients that interpolate or predict against
+//! server time. Now and then the client sends `TimeSync <ClientMs>
+//! [RttMs]`: its own clock in milliseconds (any start, wrapping) and the
+//! round trip it measured last time. The connection answers right away
+//! with `TIME_SYNC`: the client time echoed, the server clock, the tick
+//! rate and a recommended interpolation delay. The client's offset to
+//! the server is `server_time + rtt / 2 - now`.
+//!
+//! The server keeps its own estimate per player from the same exchanges:
+//! the offset (server minus client clock, half the round trip taken out),
+//! round trip and jitter as moving averages, and drift, how fast the
+//! offset moves in parts per million. They're in `GET /admin/world`.
+//!
+//! The recommended delay is `TELEBOXEL_INTERP_TICKS` ticks (2), plus twice
+//! the jitter once the client reported round trips. The handshake frame
+//! has a `TIME_SYNC` too, with client time 0 and the base delay.
+
+use serde::Serialize;
+use std::{sync::LazyLock, time::Instant};
+
+static STARTED: LazyLock<Instant> = LazyLock::new(Instant::now);
+
+// Round trips past this are clamped, a stalled client isn't a sample
+const MAX_RTT_MS: u32 = 10_000;
+// Offset samples this far apart (server ms) make a drift sample
+const DRIFT_WINDOW_MS: u32 = 1000;
+
+/// The server clock in milliseconds since it started, wrapping like the
+/// client's.
+pub fn server_ms() -> u32 {
+    STARTED.elapsed().as_millis() as u32
+}
+
+/// One player's smoothed clock estimate.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct ClockEstimate {
+    /// Server minus client clock, wrapping.
+    pub offset_ms: u32,
+    pub rtt_ms: f64,
+    /// Mean deviation of the round trip.
+    pub jitter_ms: f64,
+    /// Positive when the client clock runs slow.
+    pub drift_ppm: f64,
+}
+
+/// Kept by the connection, across room changes.
+#[derive(Default)]
+pub struct ClockSync {
+    // First offset sample, the smoothed offset is relative to it so drift
+    // keeps sub-millisecond precision
+    base: Option<u32>,
+    offset: f64,
+    rtt: Option<f64>,
+    jitter: f64,
+    drift: f64,
+    // Server time and offset of the last drift sample
+    last: Option<(u32, f64)>,
+}
+
+impl ClockSync {
+    /// A `TimeSync` from the client, received at `server_ms`.
+    pub fn sample(&mut self, server_ms: u32, client_ms: u32, rtt: Option<u32>) {
+        if let Some(rtt) = rtt.map(|r| r.min(MAX_RTT_MS) as f64) {
+            // Like TCP's smoothed round trip and its variation
+            match self.rtt {
+                Some(smoothed) => {
+                    self.jitter += ((rtt - smoothed).abs() - self.jitter) / 4.0;
+                    self.rtt = Some(smoothed + (rtt - smoothed) / 8.0);
+                }
+                None => {
+                    self.jitter = rtt / 2.0;
+                    self.rtt = Some(rtt);
+                }
+            }
+        }
+
+        let one_way = self.rtt.unwrap_or(0.0) / 2.0;
+        let raw = server_ms.wrapping_sub(client_ms);
+        let base = *self.base.get_or_insert(raw);
+        let sample = raw.wrapping_sub(base) as i32 as f64 - one_way;
+        self.offset = match self.last {
+            Some(_) => self.offset + (sample - self.offset) / 8.0,
+            None => sample,
+        };
+
+        match self.last {
+            Some((at, before)) if server_ms.wrapping_sub(at) >= DRIFT_WINDOW_MS => {
+                let elapsed = server_ms.wrapping_sub(at) as f64;
+                let ppm = (self.offset - before) * 1e6 / elapsed;
+                self.drift += (ppm - self.drift) / 8.0;
+                self.last = Some((server_ms, self.offset));
+            }
+            Some(_) => {}
+            None => self.last = Some((server_ms, self.offset)),
+        }
+    }
+
+    /// `None` before the first `TimeSync`.
+    pub fn estimate(&self) -> Option<ClockEstimate> {
+        let base = self.base?;
+        Some(ClockEstimate {
+            offset_ms: base.wrapping_add(self.offset.round() as i32 as u32),
+            rtt_ms: self.rtt.unwrap_or(0.0),
+            jitter_ms: self.jitter,
+            drift_ppm: self.drift,
+        })
+    }
+
+    /// How far behind the newest snapshot the client should render, in ms.
+    pub fn interp_delay(&self, tick_hz: u32, ticks: u32) -> u16 {
+        let base = (ticks * 1000).div_ceil(tick_hz.max(1)) as f64;
+        let margin = if self.rtt.is_some() {
+            self.jitter * 2.0
+        } else {
+            0.0
+        };
+        (base + margin).ceil().min(u16::MAX as f64) as u16
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn estimates_offset_round_trip_and_drift() {
+        let mut sync = ClockSync::default();
+        assert_eq!(sync.estimate(), None);
+        assert_eq!(sync.interp_delay(60, 2), 34);
+
+        // The client clock started 5000 ms after the server's, 40 ms away
+        // each way, and loses 100 ms every 1000 s
+        for i in 0..200u32 {
+            let server = 10_000 + i * 1000;
+            let client = (server as f64 - 5000.0 - 40.0 - i as f64 * 0.1) as u32;
+            sync.sample(server, client, (i > 0).then_some(80));
+        }
+        let estimate = sync.estimate().unwrap();
+        assert!(estimate.offset_ms.abs_diff(5020) <= 1, "{estimate:?}");
+        assert_eq!(estimate.rtt_ms, 80.0);
+        assert!(estimate.jitter_ms < 1.0);
+        assert!((estimate.drift_ppm - 100.0).abs() < 10.0, "{estimate:?}");
+        assert_eq!(sync.interp_delay(60, 2), 34);
+
+        // Wraps with the clocks
+        let mut sync = ClockSync::default();
+        sync.sample(5, u32::MAX - 4, None);
+        assert_eq!(sync.estimate().unwrap().offset_ms, 10);
+    }
+}
@@ -0,0 +1,812 @@
+// Temporary text protocol, until the binary protocol replaces it
+
+use crate::{
+    chunk::CHUNK_SIZE,
+    claims::{ClaimError, Owner},
+    coords::Coords,
+    minimap::{DEFAULT_SIZE, MAX_SCALE, MAX_SIZE},
+    origin,
+    presence::Visibility,
+    roles::{MAX_MUTE_MINUTES, Role},
+};
+use serde::Deserialize;
+use std::{collections::BTreeMap, fmt};
+
+pub enum Command {
+    /// SetInterest PosX PosY PosZ Radius
+    SetInterest {
+        center: (i32, i32, i32),
+        radius: u16,
+    },
+    /// SetPosition PosX PosY PosZ [#Seq]
+    ///
+    /// `#Seq` on moves and edits is the client's number for the input, the
+    /// server acks the last one it applied (`INPUT_ACK`).
+    SetPosition {
+        position: (i32, i32, i32),
+        seq: Option<u32>,
+    },
+    /// SetTransform PosX PosY PosZ Yaw Pitch [VelX VelY VelZ] [#Seq]
+    /// (degrees and blocks per second; the usual way to move, `SetPosition`
+    /// is for clients that don't turn)
+    SetTransform {
+        position: (i32, i32, i32),
+        rotation: (f32, f32),
+        velocity: Option<(f32, f32, f32)>,
+        seq: Option<u32>,
+    },
+    /// SetBlock PosX PosY PosZ Block [#Seq]
+    SetBlock {
+        position: (i32, i32, i32),
+        block: u16,
+        seq: Option<u32>,
+    },
+    /// MoveEntity Id PosX PosY PosZ (an entity this player has authority
+    /// over, see `entities.rs`)
+    MoveEntity {
+        entity: u32,
+        position: (i32, i32, i32),
+    },
+    /// Mount Id (rides that entity, when close enough)
+    Mount { entity: u32 },
+    /// Dismount
+    Dismount,
+    /// ClaimCreate X1 Y1 Z1 X2 Y2 Z2 (opposite corners, inclusive)
+    ClaimCreate {
+        a: (i32, i32, i32),
+        b: (i32, i32, i32),
+    },
+    /// ClaimTransfer Id player:<name>|group:<name>
+    ClaimTransfer { claim: u32, owner: Owner },
+    /// RoomCreate Name [lockstep|rollback|relay] (forks the current room, join with
+    /// ?room=Name; see `RoomMode` for the others)
+    RoomCreate { name: String, mode: RoomMode },
+    /// JoinRoom Name (moves this connection there, `main` for the main world)
+    JoinRoom { name: String },
+    /// LeaveRoom (back to the main world)
+    LeaveRoom,
+    /// Say Text... (chat, to everyone in the same world or room)
+    Say { text: String },
+    /// Say /Name Args... (a chat command, see `chat_commands.rs`)
+    Chat { line: String },
+    /// Presence everyone|friends|nobody (who sees this player online)
+    Presence { visibility: Visibility },
+    /// FriendAdd Name (lets them see this player, lists them in Friends)
+    FriendAdd { name: String },
+    /// FriendRemove Name
+    FriendRemove { name: String },
+    /// Friends (the ones online, as `name@room`)
+    Friends,
+    /// Input Tick Data... (lockstep and rollback rooms, this player's input
+    /// for the tick or frame)
+    Input { tick: u32, data: String },
+    /// LockstepState Tick Data... (lockstep rooms, the state after the tick
+    /// for late joiners)
+    LockstepState { tick: u32, data: String },
+    /// VoiceMute Id (stops hearing that player, see `voice.rs`)
+    VoiceMute { speaker: u32 },
+    /// VoiceUnmute Id
+    VoiceUnmute { speaker: u32 },
+    /// Block Name (stops getting their chat, voice and relay messages, see
+    /// `blocking.rs`)
+    Block { name: String },
+    /// Unblock Name
+    Unblock { name: String },
+    /// Blocked (the blocked names)
+    Blocked,
+    /// Kick Name [Reason...] (moderators, see `roles.rs`)
+    Kick { name: String, reason: String },
+    /// Mute Name Minutes (moderators, keeps them out of chat and voice)
+    Mute { name: String, minutes: u32 },
+    /// Unmute Name (moderators)
+    Unmute { name: String },
+    /// SetRole Name player|moderator|admin (admins)
+    SetRole { name: String, role: Role },
+    /// Login Token [Platform] (guests, binds the connection to that
+    /// identity, see `auth.rs`)
+    Login {
+        token: String,
+        platform: Option<String>,
+    },
+    /// TimeSync ClientMs [RttMs] (answered with `TIME_SYNC`, see
+    /// `clock.rs`)
+    TimeSync { client_time: u32, rtt: Option<u32> },
+    /// Origin X Y Z (a chunk corner: the positions this client sends are
+    /// from there on, and it gets them that way, see `origin.rs`)
+    Origin { origin: (i32, i32, i32) },
+    /// Track Event [Key=Value...] (a custom gameplay event for analytics,
+    /// see `analytics.rs`)
+    Track {
+        event: String,
+        fields: BTreeMap<String, String>,
+    },
+    /// Minimap [Size [Scale]] (cells on a side and blocks per cell,
+    /// answered with `MINIMAP`, see `minimap.rs`)
+    Minimap { size: u8, scale: u8 },
+}
+
+impl Command {
+    /// Positions sent from a client's `origin` made absolute.
+    pub fn from_origin(self, origin: (i32, i32, i32)) -> Command {
+        self.map_positions(
+            |position| origin::absolute(origin, position),
+            |center| origin::absolute(origin::chunk(origin), center),
+        )
+    }
+
+    /// Positions sent in a client's coordinates made blocks, see
+    /// `coords.rs`. Velocities too.
+    pub fn from_coords(self, coords: Coords) -> Command {
+        let cmd = self.map_positions(|p| coords.block(p), |c| coords.chunk(c));
+        match cmd {
+            Command::SetTransform {
+                position,
+                rotation,
+                velocity,
+                seq,
+            } => Command::SetTransform {
+                position,
+                rotation,
+                velocity: velocity.map(|(x, y, z)| {
+                    let units = coords.units as f32;
+                    let z = if coords.flat { 0.0 } else { z };
+                    (x / units, y / units, z / units)
+                }),
+                seq,
+            },
+            cmd => cmd,
+        }
+    }
+
+    // Each block position through `block`, interest centers (chunks)
+    // through `chunk`
+    fn map_positions(
+        self,
+        block: impl Fn((i32, i32, i32)) -> (i32, i32, i32),
+        chunk: impl Fn((i32, i32, i32)) -> (i32, i32, i32),
+    ) -> Command {
+        match self {
+            Command::SetInterest { center, radius } => Command::SetInterest {
+                center: chunk(center),
+                radius,
+            },
+            Command::SetPosition { position, seq } => Command::SetPosition {
+                position: block(position),
+                seq,
+            },
+            Command::SetTransform {
+                position,
+                rotation,
+                velocity,
+                seq,
+            } => Command::SetTransform {
+                position: block(position),
+                rotation,
+                velocity,
+                seq,
+            },
+            Command::SetBlock {
+                position,
+                block: id,
+                seq,
+            } => Command::SetBlock {
+                position: block(position),
+                block: id,
+                seq,
+            },
+            Command::MoveEntity { entity, position } => Command::MoveEntity {
+                entity,
+                position: block(position),
+            },
+            Command::ClaimCreate { a, b } => Command::ClaimCreate {
+                a: block(a),
+                b: block(b),
+            },
+            cmd => cmd,
+        }
+    }
+}
+
+/// What a room runs.
+#[derive(Deserialize, Clone, Copy, Default, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RoomMode {
+    /// The simulated world, forked.
+    #[default]
+    World,
+    /// An input relay for deterministic clients, see `lockstep.rs`.
+    Lockstep,
+    /// Relays inputs right away and confirms frames, see `rollback.rs`.
+    Rollback,
+    /// Forwards client messages between players, see `relay.rs`.
+    Relay,
+}
+
+impl fmt::Display for RoomMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            RoomMode::World => "world",
+            RoomMode::Lockstep => "lockstep",
+            RoomMode::Rollback => "rollback",
+            RoomMode::Relay => "relay",
+        })
+    }
+}
+
+// Inputs that can end in `#Seq`
+const SEQUENCED: [&str; 3] = ["SetPosition", "SetTransform", "SetBlock"];
+
+// Chat lines longer than this are rejected
+pub const MAX_CHAT_LEN: usize = 200;
+
+// Fields on a `Track`, and bytes for the whole line
+const MAX_TRACK_FIELDS: usize = 16;
+const MAX_TRACK_LEN: usize = 1024;
+
+const NAMES: [&str; 33] = [
+    "SetInterest",
+    "SetPosition",
+    "SetTransform",
+    "SetBlock",
+    "MoveEntity",
+    "Mount",
+    "Dismount",
+    "ClaimCreate",
+    "ClaimTransfer",
+    "RoomCreate",
+    "JoinRoom",
+    "LeaveRoom",
+    "Say",
+    "Presence",
+    "FriendAdd",
+    "FriendRemove",
+    "Friends",
+    "Input",
+    "LockstepState",
+    "VoiceMute",
+    "VoiceUnmute",
+    "Block",
+    "Unblock",
+    "Blocked",
+    "Kick",
+    "Mute",
+    "Unmute",
+    "SetRole",
+    "Login",
+    "TimeSync",
+    "Origin",
+    "Track",
+    "Minimap",
+];
+
+/// Several inputs in one text frame, one per line (`SetInterest`,
+/// `SetPosition`, `SetTransform`, `SetBlock`), for the world to get them
+/// together. `None` unless there are two lines or more and all are
+/// well-formed inputs.
+pub fn parse_inputs(text: &str) -> Option<Vec<(&'static str, Command)>> {
+    let inputs: Vec<_> = text
+        .lines()
+        .map(|line| match parse(line)? {
+            (
+                name,
+                Ok(
+                    cmd @ (Command::SetInterest { .. }
+                    | Command::SetPosition { .. }
+                    | Command::SetTransform { .. }
+                    | Command::SetBlock { .. }),
+                ),
+            ) => Some((name, cmd)),
+            _ => None,
+        })
+        .collect::<Option<_>>()?;
+    (inputs.len() > 1).then_some(inputs)
+}
+
+/// Parses a text command. Returns `None` for unknown commands, otherwise the
+/// command name (for the Ok/Error reply) and the parse result.
+pub fn parse(text: &str) -> Option<(&'static str, Result<Command, String>)> {
+    let mut parts: Vec<&str> = text.split(' ').collect();
+
+    let mut seq = None;
+    if let [first, .., last] = parts[..]
+        && let Some(&name) = SEQUENCED.iter().find(|&&n| n == first)
+        && let Some(n) = last.strip_prefix('#')
+    {
+        match n.parse::<u32>() {
+            Ok(n) => seq = Some(n),
+            Err(_) => return Some((name, Err("Invalid Seq".to_string()))),
+        }
+        parts.pop();
+    }
+
+    let result = match parts[0] {
+        "SetInterest" => {
+            if parts.len() != 5 {
+                Err("Expected 4 parameters (PosX PosY PosZ Radius)".to_string())
+            } else {
+                parse_xyz(&parts[1..4]).and_then(|center| {
+                    let radius = parts[4]
+                        .parse::<u16>()
+                        .map_err(|_| "Invalid Radius".to_string())?;
+                    Ok(Command::SetInterest { center, radius })
+                })
+            }
+        }
+        "SetPosition" => {
+            if parts.len() != 4 {
+                Err("Expected 3 parameters (PosX PosY PosZ)".to_string())
+            } else {
+                parse_xyz(&parts[1..4]).map(|position| Command::SetPosition { position, seq })
+            }
+        }
+        "SetTransform" => {
+            if parts.len() != 6 && parts.len() != 9 {
+                Err(
+                    "Expected 5 or 8 parameters (PosX PosY PosZ Yaw Pitch [VelX VelY VelZ])"
+                        .to_string(),
+                )
+            } else {
+                parse_xyz(&parts[1..4]).and_then(|position| {
+                    let yaw = parse_f32(parts[4], "Yaw")?;
+                    let pitch = parse_f32(parts[5], "Pitch")?;
+                    let velocity = match parts.get(6..9) {
+                        Some(v) => Some((
+                            parse_f32(v[0], "VelX")?,
+                            parse_f32(v[1], "VelY")?,
+                            parse_f32(v[2], "VelZ")?,
+                        )),
+                        None => None,
+                    };
+                    Ok(Command::SetTransform {
+                        position,
+                        rotation: (yaw, pitch),
+                        velocity,
+                        seq,
+                    })
+                })
+            }
+        }
+        "SetBlock" => {
+            if parts.len() != 5 {
+                Err("Expected 4 parameters (PosX PosY PosZ Block)".to_string())
+            } else {
+                parse_xyz(&parts[1..4]).and_then(|position| {
+                    let block = parts[4]
+                        .parse::<u16>()
+                        .map_err(|_| "Invalid Block".to_string())?;
+                    Ok(Command::SetBlock {
+                        position,
+                        block,
+                        seq,
+                    })
+                })
+            }
+        }
+        "MoveEntity" => {
+            if parts.len() != 5 {
+                Err("Expected 4 parameters (Id PosX PosY PosZ)".to_string())
+            } else {
+                let entity = parts[1]
+                    .parse::<u32>()
+                    .map_err(|_| "Invalid Id".to_string());
+                entity.and_then(|entity| {
+                    parse_xyz(&parts[2..5]).map(|position| Command::MoveEntity { entity, position })
+                })
+            }
+        }
+        "Dismount" => {
+            if parts.len() != 1 {
+                Err("Expected no parameters".to_string())
+            } else {
+                Ok(Command::Dismount)
+            }
+        }
+        "Mount" => {
+            if parts.len() != 2 {
+                Err("Expected 1 parameter (Id)".to_string())
+            } else {
+                parts[1]
+                    .parse::<u32>()
+                    .map_err(|_| "Invalid Id".to_string())
+                    .map(|entity| Command::Mount { entity })
+            }
+        }
+        "ClaimCreate" => {
+            if parts.len() != 7 {
+                Err("Expected 6 parameters (X1 Y1 Z1 X2 Y2 Z2)".to_string())
+            } else {
+                parse_xyz(&parts[1..4]).and_then(|a| {
+                    Ok(Command::ClaimCreate {
+                        a,
+                        b: parse_xyz(&parts[4..7])?,
+                    })
+                })
+            }
+        }
+        "ClaimTransfer" => {
+            if parts.len() != 3 {
+                Err("Expected 2 parameters (Id Owner)".to_string())
+            } else {
+                parts[1]
+                    .parse::<u32>()
+                    .map_err(|_| "Invalid Id".to_string())
+                    .and_then(|claim| {
+                        let owner = parts[2].parse().map_err(|e: ClaimError| e.to_string())?;
+                        Ok(Command::ClaimTransfer { claim, owner })
+                    })
+            }
+        }
+        "RoomCreate" => {
+            let mode = match parts.get(2) {
+                None => Ok(RoomMode::World),
+                Some(&"lockstep") => Ok(RoomMode::Lockstep),
+                Some(&"rollback") => Ok(RoomMode::Rollback),
+                Some(&"relay") => Ok(RoomMode::Relay),
+                Some(_) => Err("Invalid mode, expected lockstep, rollback or relay".to_string()),
+            };
+            if parts.len() != 2 && parts.len() != 3 {
+                Err("Expected 1 or 2 parameters (Name [lockstep|rollback|relay])".to_string())
+            } else if !valid_room(parts[1]) {
+                Err("Invalid Name".to_string())
+            } else {
+                mode.map(|mode| Command::RoomCreate {
+                    name: parts[1].to_string(),
+                    mode,
+                })
+            }
+        }
+        "JoinRoom" => {
+            if parts.len() != 2 {
+                Err("Expected 1 parameter (Name)".to_string())
+            } else if !valid_room(parts[1]) {
+                Err("Invalid Name".to_string())
+            } else {
+                Ok(Command::JoinRoom {
+                    name: parts[1].to_string(),
+                })
+            }
+        }
+        "LeaveRoom" => {
+            if parts.len() != 1 {
+                Err("Expected no parameters".to_string())
+            } else {
+                Ok(Command::LeaveRoom)
+            }
+        }
+        "Say" => {
+            let text = parts[1..].join(" ");
+            let text = text.trim();
+            if let Some(line) = text.strip_prefix('/') {
+                Ok(Command::Chat {
+                    line: line.to_string(),
+                })
+            } else if text.is_empty() {
+                Err("Expected Text".to_string())
+            } else if text.len() > MAX_CHAT_LEN {
+                Err(format!("Text longer than {MAX_CHAT_LEN} bytes"))
+            } else {
+                Ok(Command::Say {
+                    text: text.to_string(),
+                })
+            }
+        }
+        "Presence" => {
+            if parts.len() != 2 {
+                Err("Expected 1 parameter (everyone|friends|nobody)".to_string())
+            } else {
+                parts[1]
+                    .parse()
+                    .map(|visibility| Command::Presence { visibility })
+            }
+        }
+        "FriendAdd" | "FriendRemove" | "Block" | "Unblock" => {
+            if parts.len() != 2 {
+                Err("Expected 1 parameter (Name)".to_string())
+            } else if parts[1].is_empty() || parts[1].len() > 32 || parts[1].contains(',') {
+                Err("Invalid Name".to_string())
+            } else {
+                let name = parts[1].to_string();
+                Ok(match parts[0] {
+                    "FriendAdd" => Command::FriendAdd { name },
+                    "FriendRemove" => Command::FriendRemove { name },
+                    "Block" => Command::Block { name },
+                    _ => Command::Unblock { name },
+                })
+            }
+        }
+        "Friends" | "Blocked" => {
+            if parts.len() != 1 {
+                Err("Expected no parameters".to_string())
+            } else if parts[0] == "Friends" {
+                Ok(Command::Friends)
+            } else {
+                Ok(Command::Blocked)
+            }
+        }
+        "Input" | "LockstepState" => {
+            if parts.len() < 2 {
+                Err("Expected Tick and Data".to_string())
+            } else {
+                // Data may be empty or hold spaces, lengths are up to the relay
+                let data = parts[2..].join(" ");
+                parts[1]
+                    .parse::<u32>()
+                    .map_err(|_| "Invalid Tick".to_string())
+                    .map(|tick| match parts[0] {
+                        "Input" => Command::Input { tick, data },
+                        _ => Command::LockstepState { tick, data },
+                    })
+            }
+        }
+        "VoiceMute" | "VoiceUnmute" => {
+            if parts.len() != 2 {
+                Err("Expected 1 parameter (Id)".to_string())
+            } else {
+                parts[1]
+                    .parse::<u32>()
+                    .map_err(|_| "Invalid Id".to_string())
+                    .map(|speaker| match parts[0] {
+                        "VoiceMute" => Command::VoiceMute { speaker },
+                        _ => Command::VoiceUnmute { speaker },
+                    })
+            }
+        }
+        "Kick" => {
+            if parts.len() < 2 {
+                Err("Expected Name and an optional Reason".to_string())
+            } else if !valid_name(parts[1]) {
+                Err("Invalid Name".to_string())
+            } else {
+                Ok(Command::Kick {
+                    name: parts[1].to_string(),
+                    reason: parts[2..].join(" ").trim().to_string(),
+                })
+            }
+        }
+        "Mute" => {
+            if parts.len() != 3 {
+                Err("Expected 2 parameters (Name Minutes)".to_string())
+            } else if !valid_name(parts[1]) {
+                Err("Invalid Name".to_string())
+            } else {
+                match parts[2].parse::<u32>() {
+                    Ok(minutes @ 1..=MAX_MUTE_MINUTES) => Ok(Command::Mute {
+                        name: parts[1].to_string(),
+                        minutes,
+                    }),
+                    _ => Err(format!("Minutes must be 1 to {MAX_MUTE_MINUTES}")),
+                }
+            }
+        }
+        "Unmute" => {
+            if parts.len() != 2 {
+                Err("Expected 1 parameter (Name)".to_string())
+            } else if !valid_name(parts[1]) {
+                Err("Invalid Name".to_string())
+            } else {
+                Ok(Command::Unmute {
+                    name: parts[1].to_string(),
+                })
+            }
+        }
+        "SetRole" => {
+            if parts.len() != 3 {
+                Err("Expected 2 parameters (Name player|moderator|admin)".to_string())
+            } else if !valid_name(parts[1]) {
+                Err("Invalid Name".to_string())
+            } else {
+                parts[2].parse().map(|role| Command::SetRole {
+                    name: parts[1].to_string(),
+                    role,
+                })
+            }
+        }
+        "Login" => {
+            if parts.len() != 2 && parts.len() != 3 {
+                Err("Expected 1 or 2 parameters (Token [Platform])".to_string())
+            } else if parts[1].is_empty() {
+                Err("Invalid Token".to_string())
+            } else {
+                Ok(Command::Login {
+                    token: parts[1].to_string(),
+                    platform: parts.get(2).map(|p| p.to_string()),
+                })
+            }
+        }
+        "TimeSync" => {
+            if parts.len() != 2 && parts.len() != 3 {
+                Err("Expected 1 or 2 parameters (ClientMs [RttMs])".to_string())
+            } else {
+                let client_time = parts[1]
+                    .parse::<u32>()
+                    .map_err(|_| "Invalid ClientMs".to_string());
+                let rtt = parts
+                    .get(2)
+                    .map(|rtt| rtt.parse::<u32>().map_err(|_| "Invalid RttMs".to_string()));
+                client_time.and_then(|client_time| {
+                    Ok(Command::TimeSync {
+                        client_time,
+                        rtt: rtt.transpose()?,
+                    })
+                })
+            }
+        }
+        "Origin" => {
+            if parts.len() != 4 {
+                Err("Expected 3 parameters (X Y Z)".to_string())
+            } else {
+                parse_xyz(&parts[1..4]).and_then(|origin| {
+                    if origin::is_corner(origin) {
+                        Ok(Command::Origin { origin })
+                    } else {
+                        Err(format!("Origin must be a chunk corner, {CHUNK_SIZE} apart"))
+                    }
+                })
+            }
+        }
+        "Track" => {
+            if parts.len() < 2 {
+                Err("Expected Event".to_string())
+            } else if !valid_event(parts[1]) {
+                Err("Event must be 1-32 letters, digits, '_', '-' or '.'".to_string())
+            } else if parts.len() - 2 > MAX_TRACK_FIELDS {
+                Err(format!("At most {MAX_TRACK_FIELDS} fields"))
+            } else if text.len() > MAX_TRACK_LEN {
+                Err(format!("Track longer than {MAX_TRACK_LEN} bytes"))
+            } else {
+                parts[2..]
+                    .iter()
+                    .map(|field| match field.split_once('=') {
+                        Some((key, value)) if valid_event(key) => {
+                            Ok((key.to_string(), value.to_string()))
+                        }
+                        _ => Err(format!("Invalid field {field}, expected Key=Value")),
+                    })
+                    .collect::<Result<_, _>>()
+                    .map(|fields| Command::Track {
+                        event: parts[1].to_string(),
+                        fields,
+                    })
+            }
+        }
+        "Minimap" => {
+            let number = |part: Option<&&str>, max, default, what| match part {
+                None => Ok(default),
+                Some(n) => n
+                    .parse::<u8>()
+                    .ok()
+                    .filter(|n| (1..=max).contains(n))
+                    .ok_or(format!("{what} must be 1-{max}")),
+            };
+            if parts.len() > 3 {
+                Err("Expected at most 2 parameters (Size Scale)".to_string())
+            } else {
+                number(parts.get(1), MAX_SIZE, DEFAULT_SIZE, "Size").and_then(|size| {
+                    let scale = number(parts.get(2), MAX_SCALE, 1, "Scale")?;
+                    Ok(Command::Minimap { size, scale })
+                })
+            }
+        }
+        _ => return None,
+    };
+
+    let name = NAMES.iter().find(|&&n| n == parts[0])?;
+    Some((name, result))
+}
+
+fn valid_name(name: &str) -> bool {
+    !name.is_empty() && name.len() <= 32
+}
+
+// Event names and field keys
+fn valid_event(name: &str) -> bool {
+    valid_name(name)
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"_-.".contains(&b))
+}
+
+fn parse_xyz(parts: &[&str]) -> Result<(i32, i32, i32), String> {
+    let x = parts[0].parse::<i32>().map_err(|_| "Invalid PosX")?;
+    let y = parts[1].parse::<i32>().map_err(|_| "Invalid PosY")?;
+    let z = parts[2].parse::<i32>().map_err(|_| "Invalid PosZ")?;
+    Ok((x, y, z))
+}
+
+/// Room names players can use. `/` is for tenants' rooms, see `tenants.rs`.
+pub fn valid_room(name: &str) -> bool {
+    !name.is_empty() && name.len() <= 32 && !name.contains('/')
+}
+
+// NaN and infinities aren't valid either
+fn parse_f32(part: &str, name: &str) -> Result<f32, String> {
+    match part.parse::<f32>() {
+        Ok(n) if n.is_finite() => Ok(n),
+        _ => Err(format!("Invalid {name}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn batches_only_well_formed_inputs() {
+        let inputs = parse_inputs("SetPosition 1 2 3\nSetBlock 1 2 3 4").unwrap();
+        let names: Vec<_> = inputs.iter().map(|(name, _)| *name).collect();
+        assert_eq!(names, ["SetPosition", "SetBlock"]);
+        assert!(parse_inputs("SetPosition 1 2 3").is_none());
+        assert!(parse_inputs("SetPosition 1 2 3\n").is_none());
+        assert!(parse_inputs("SetPosition 1 2 3\nSetPosition x").is_none());
+        assert!(parse_inputs("SetPosition 1 2 3\nSay hi").is_none());
+    }
+
+    #[test]
+    fn transforms_have_an_optional_velocity() {
+        let Some((
+            "SetTransform",
+            Ok(Command::SetTransform {
+                position,
+                rotation,
+                velocity,
+                ..
+            }),
+        )) = parse("SetTransform 1 2 3 90 -15.5")
+        else {
+            panic!("not a transform");
+        };
+        assert_eq!(
+            (position, rotation, velocity),
+            ((1, 2, 3), (90.0, -15.5), None)
+        );
+        let Some((_, Ok(Command::SetTransform { velocity, .. }))) =
+            parse("SetTransform 1 2 3 90 0 0.5 0 -4")
+        else {
+            panic!("not a transform");
+        };
+        assert_eq!(velocity, Some((0.5, 0.0, -4.0)));
+        for bad in [
+            "SetTransform 1 2 3 90",
+            "SetTransform 1 2 3 NaN 0",
+            "SetTransform 1 2 3 0 0 1",
+        ] {
+            assert!(matches!(parse(bad), Some((_, Err(_)))), "{bad}");
+        }
+    }
+
+    #[test]
+    fn moves_and_edits_may_end_in_a_seq() {
+        let Some((_, Ok(Command::SetBlock { block, seq, .. }))) = parse("SetBlock 1 2 3 4 #17")
+        else {
+            panic!("not a block edit");
+        };
+        assert_eq!((block, seq), (4, Some(17)));
+        let Some((_, Ok(Command::SetPosition { seq, .. }))) = parse("SetPosition 1 2 3") else {
+            panic!("not a move");
+        };
+        assert_eq!(seq, None);
+        assert!(matches!(
+            parse("SetBlock 1 2 3 4 #x"),
+            Some(("SetBlock", Err(_)))
+        ));
+        let Some((_, Ok(Command::Say { text }))) = parse("Say hi #2") else {
+            panic!("not a chat line");
+        };
+        assert_eq!(text, "hi #2");
+    }
+
+    #[test]
+    fn tracks_carry_fields() {
+        let Some(("Track", Ok(Command::Track { event, fields }))) =
+            parse("Track level_done level=3 time=41.5 note=")
+        else {
+            panic!("not a track");
+        };
+        assert_eq!(event, "level_done");
+        assert_eq!(fields["time"], "41.5");
+        assert_eq!(fields["note"], "");
+        for bad in ["Track", "Track a=b", "Track done level", "Track done =3"] {
+            assert!(matches!(parse(bad), Some(("Track", Err(_)))), "{bad}");
+        }
+    }
+}
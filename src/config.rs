@@ -0,0 +1,648 @@
+use crate::{chunk_wire::ChunkFormat, secure::Policy, spatial::SpatialKind};
+use std::{collections::HashMap, env, fs, path::PathBuf, str::FromStr, time::Duration};
+
+/// Ceiling for `TELEBOXEL_MAX_INTEREST_RADIUS`, in chunks.
+pub const INTEREST_RADIUS_LIMIT: u16 = 16;
+
+/// Server settings, read from `TELEBOXEL_*` environment variables and the
+/// optional `TELEBOXEL_CONFIG` file, see `Vars`.
+pub struct Config {
+    /// Storage backend URL, e.g. `sqlite://teleboxel.db`. Persistence is off
+    /// when unset.
+    pub database_url: Option<String>,
+    /// World directory with chunk saves, loaded lazily around players.
+    /// Without one, chunks are saved in the storage backend, or only
+    /// generated and edits aren't kept when there's none either.
+    pub world_dir: Option<PathBuf>,
+    /// How often connected players and edited chunks are saved.
+    pub save_interval: Duration,
+    /// Memory budget for loaded chunks, in MiB.
+    pub chunk_cache_mb: usize,
+    /// Generator for chunks missing from the world directory.
+    pub generator: GeneratorKind,
+    pub world_seed: u64,
+    /// Snapshot encoding, `compact` or `flatbuffers` (zero-copy reads on
+    /// clients, bigger frames). Rooms forked from the world inherit it.
+    pub chunk_format: ChunkFormat,
+    /// Spatial index for entity lookups, `grid` or `octree` (sparse clusters
+    /// over huge coordinates), see `spatial.rs`. Templates can pick another.
+    pub spatial: SpatialKind,
+    /// Block registry file (`.toml` or `.json`), the built-in terrain blocks
+    /// when unset.
+    pub blocks: Option<PathBuf>,
+    /// Telegram/Discord chat bridges file, see `bridge.rs`.
+    pub bridges: Option<PathBuf>,
+    /// Portals to rooms and other servers, see `portals.rs`.
+    pub portals: Option<PathBuf>,
+    /// Trigger volumes and spawn regions, see `triggers.rs`.
+    pub triggers: Option<PathBuf>,
+    /// Flood control limits per world, see `flood.rs`. Built-in limits
+    /// when unset.
+    pub flood: Option<PathBuf>,
+    /// AFK detection per world, see `afk.rs`. Off when unset.
+    pub afk: Option<PathBuf>,
+    /// Relevance rules per world, see `relevance.rs`. Off when unset.
+    pub relevance: Option<PathBuf>,
+    /// 2D worlds and wire units per world, see `coords.rs`. 3D and blocks
+    /// when unset.
+    pub coords: Option<PathBuf>,
+    /// Achievement rules per world, see `achievements.rs`. None when unset.
+    pub achievements: Option<PathBuf>,
+    /// Chat, block edits and PvP per world, see `features.rs`. All on when
+    /// unset.
+    pub features: Option<PathBuf>,
+    /// Entity, chunk, bandwidth and brain time limits per world, see
+    /// `quotas.rs`. No limits when unset.
+    pub quotas: Option<PathBuf>,
+    /// Room templates for `/admin/rooms`, see `templates.rs`.
+    pub templates: Option<PathBuf>,
+    /// Sockets to serve on and their routes, see `listeners.rs`.
+    /// `[::]:3000`, dual-stack, with every route when unset.
+    pub listeners: Option<PathBuf>,
+    /// Tenants and their API key hashes, see `tenants.rs`. Keys made
+    /// over the admin API last until restart when unset.
+    pub tenants: Option<PathBuf>,
+    /// Bearer token for the `/admin` HTTP API. The API is not mounted when
+    /// unset.
+    pub admin_token: Option<String>,
+    /// Bearer token for the moderation routes of the admin API only, see
+    /// `roles.rs`.
+    pub moderator_token: Option<String>,
+    /// Bearer token for the `/presence` HTTP API, see `presence.rs`. Not
+    /// mounted when unset.
+    pub presence_token: Option<String>,
+    /// Interactive console on stdin, when it's a terminal.
+    pub console: bool,
+    /// Unix socket for newline-delimited JSON control requests, see
+    /// `control.rs`.
+    pub control_socket: Option<PathBuf>,
+    /// Key for session resume tokens, see `resume.rs`. Made up at startup
+    /// (and handed to the hot restart successor) when unset.
+    pub resume_secret: Option<String>,
+    /// How often the top talkers are logged, `0` to never log them.
+    pub traffic_log_interval: Duration,
+    /// Server frames each connection keeps for export, `0` to keep none,
+    /// see `recorder.rs`.
+    pub frame_history: Duration,
+    /// OTLP export is enabled by setting `TELEBOXEL_OTLP_ENDPOINT`.
+    pub otlp: Option<OtlpConfig>,
+    /// Agones lifecycle calls are enabled by `TELEBOXEL_AGONES=true`.
+    pub agones: Option<AgonesConfig>,
+    /// Tick phase timing is enabled by `TELEBOXEL_PROFILE=true`, or by
+    /// starting with `--profile`.
+    pub profile: Option<ProfileConfig>,
+    /// Where panics are reported, besides stderr.
+    pub crash: Option<CrashTarget>,
+    /// The audit log is enabled by setting `TELEBOXEL_AUDIT_LOG`.
+    pub audit: Option<AuditConfig>,
+    /// Usage records are enabled by setting `TELEBOXEL_USAGE_LOG`.
+    pub usage: Option<UsageConfig>,
+    /// Webhooks are enabled by setting `TELEBOXEL_WEBHOOK_URLS`.
+    pub webhooks: Option<WebhookConfig>,
+    /// Analytics export is enabled by setting `TELEBOXEL_ANALYTICS_SINK`.
+    pub analytics: Option<AnalyticsConfig>,
+    /// Backups are enabled by setting `TELEBOXEL_BACKUP_DIR`.
+    pub backup: Option<BackupConfig>,
+    /// JWT logins are required by setting `TELEBOXEL_JWKS_URL`.
+    pub jwt: Option<JwtConfig>,
+    /// Logins checked by a backend are required by setting
+    /// `TELEBOXEL_AUTH_URL`, instead of JWTs.
+    pub auth_callout: Option<AuthCallout>,
+    /// How long a login check may take before the connection is closed.
+    pub auth_timeout: Duration,
+    /// With logins required, lets connections without credentials in as
+    /// guests, who can't build until they `Login`.
+    pub guests: bool,
+    /// Whether sessions are encrypted, `off`, `optional` or `required`.
+    pub encryption: Policy,
+    /// Whether client messages are signed, `off`, `optional` or `required`.
+    pub message_auth: Policy,
+    /// Signs session keys, needed for either, see `secure.rs`.
+    pub server_key: Option<String>,
+    pub history: HistoryConfig,
+    pub input: InputConfig,
+    pub voice: VoiceConfig,
+    pub admission: AdmissionConfig,
+    /// Settings that change at runtime, see `reload.rs`.
+    pub tunables: Tunables,
+}
+
+/// Where settings come from: `TELEBOXEL_*` environment variables, overridden
+/// by the `KEY=value` lines of the `TELEBOXEL_CONFIG` file. The file wins so
+/// editing it reaches settings set in both places.
+#[derive(Clone, PartialEq, Eq, Default, Debug)]
+pub struct Vars(HashMap<String, String>);
+
+impl Vars {
+    pub fn load() -> Result<Self, String> {
+        let vars = env::vars().filter(|(k, _)| k.starts_with("TELEBOXEL_"));
+        let mut vars = Self(vars.collect());
+        if let Some(path) = vars.config_file() {
+            let text = fs::read_to_string(&path).map_err(|e| format!("{}: {e}", path.display()))?;
+            let file = Self::parse(&text).map_err(|e| format!("{}: {e}", path.display()))?;
+            vars.0.extend(file.0);
+        }
+        Ok(vars)
+    }
+
+    /// `KEY=value` lines, blank lines and `#` comments skipped. Values may
+    /// be quoted.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut vars = HashMap::new();
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| format!("line {}: expected KEY=value", n + 1))?;
+            let key = key.trim();
+            if !key.starts_with("TELEBOXEL_") {
+                return Err(format!("line {}: {key} isn't a TELEBOXEL_ setting", n + 1));
+            }
+            let value = value.trim();
+            let value = value
+                .strip_prefix('"')
+                .and_then(|v| v.strip_suffix('"'))
+                .unwrap_or(value);
+            vars.insert(key.to_string(), value.to_string());
+        }
+        Ok(Self(vars))
+    }
+
+    pub fn config_file(&self) -> Option<PathBuf> {
+        self.var("TELEBOXEL_CONFIG").map(PathBuf::from)
+    }
+
+    /// Keys set differently in `other`, sorted.
+    pub fn changed(&self, other: &Vars) -> Vec<String> {
+        let mut keys: Vec<_> = self
+            .0
+            .keys()
+            .chain(other.0.keys())
+            .filter(|k| self.var(k) != other.var(k))
+            .cloned()
+            .collect();
+        keys.sort();
+        keys.dedup();
+        keys
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).map(String::as_str)
+    }
+
+    fn var(&self, key: &str) -> Option<String> {
+        self.0.get(key).filter(|v| !v.is_empty()).cloned()
+    }
+
+    fn parse_or<T: FromStr>(&self, key: &str, default: T) -> T {
+        match self.var(key) {
+            Some(v) => v.parse().unwrap_or_else(|_| {
+                eprintln!("Invalid {key}={v}, using default");
+                default
+            }),
+            None => default,
+        }
+    }
+}
+
+/// Settings applied to running worlds when the config is reloaded. Every
+/// other setting needs a restart.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Tunables {
+    /// World ticks per second.
+    pub tick_hz: u32,
+    /// Cap on the interest radius players ask for, in chunks.
+    pub max_interest_radius: u16,
+    /// How far ahead fast movers' interest reaches, see `interest.rs`.
+    pub interest_lead: Duration,
+    /// Chunk snapshots each player gets per tick, nearest first.
+    pub snapshots_per_tick: usize,
+    /// Turns per second of each entity brain, see `brains.rs`.
+    pub think_hz: u32,
+    /// Time each tick may spend on brains, the rest wait a tick.
+    pub think_budget: Duration,
+    /// Ticks clients should render behind, see `clock.rs`.
+    pub interp_ticks: u32,
+    /// Missed ticks a stalled world runs to catch up, see `simulation.rs`.
+    pub catch_up_ticks: u32,
+}
+
+impl Tunables {
+    /// The variables behind the fields.
+    pub const KEYS: [&str; 8] = [
+        "TELEBOXEL_TICK_HZ",
+        "TELEBOXEL_MAX_INTEREST_RADIUS",
+        "TELEBOXEL_INTEREST_LEAD_MS",
+        "TELEBOXEL_SNAPSHOTS_PER_TICK",
+        "TELEBOXEL_THINK_HZ",
+        "TELEBOXEL_THINK_BUDGET_US",
+        "TELEBOXEL_INTERP_TICKS",
+        "TELEBOXEL_CATCH_UP_TICKS",
+    ];
+
+    pub fn from_vars(vars: &Vars) -> Self {
+        Self {
+            tick_hz: vars.parse_or("TELEBOXEL_TICK_HZ", 60u32).clamp(1, 1000),
+            max_interest_radius: vars
+                .parse_or("TELEBOXEL_MAX_INTEREST_RADIUS", 8u16)
+                .min(INTEREST_RADIUS_LIMIT),
+            interest_lead: Duration::from_millis(vars.parse_or("TELEBOXEL_INTEREST_LEAD_MS", 500)),
+            snapshots_per_tick: vars.parse_or("TELEBOXEL_SNAPSHOTS_PER_TICK", 16),
+            think_hz: vars.parse_or("TELEBOXEL_THINK_HZ", 10u32).clamp(1, 1000),
+            think_budget: Duration::from_micros(vars.parse_or("TELEBOXEL_THINK_BUDGET_US", 2000)),
+            interp_ticks: vars.parse_or("TELEBOXEL_INTERP_TICKS", 2u32).min(1000),
+            catch_up_ticks: vars.parse_or("TELEBOXEL_CATCH_UP_TICKS", 5u32).min(1000),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum GeneratorKind {
+    /// No generation, missing chunks stay missing.
+    None,
+    Flat,
+    Noise,
+}
+
+impl FromStr for GeneratorKind {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        match s {
+            "none" => Ok(GeneratorKind::None),
+            "flat" => Ok(GeneratorKind::Flat),
+            "noise" => Ok(GeneratorKind::Noise),
+            _ => Err(()),
+        }
+    }
+}
+
+/// OTLP/HTTP collector, see `telemetry.rs`.
+pub struct OtlpConfig {
+    /// Base URL, `/v1/traces` and `/v1/metrics` are appended.
+    pub endpoint: String,
+    /// Ticks, world messages and commands at least this slow get a span.
+    pub slow: Duration,
+    /// How often metrics are exported (spans go out in batches too).
+    pub interval: Duration,
+}
+
+/// The Agones SDK sidecar, see `agones.rs`.
+pub struct AgonesConfig {
+    /// `host:port` of its REST API.
+    pub address: String,
+    /// How often health is reported.
+    pub health_interval: Duration,
+}
+
+/// Profiling mode, see `profile.rs`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ProfileConfig {
+    /// How often each world reports its tick phases.
+    pub interval: Duration,
+    /// File the reports are appended to as folded stacks, for flame graphs.
+    pub folded: Option<PathBuf>,
+}
+
+impl ProfileConfig {
+    /// Regardless of `TELEBOXEL_PROFILE`, for `--profile`.
+    pub fn from_vars(vars: &Vars) -> Self {
+        Self {
+            interval: Duration::from_secs(vars.parse_or("TELEBOXEL_PROFILE_SECS", 10).max(1)),
+            folded: vars.var("TELEBOXEL_PROFILE_FOLDED").map(PathBuf::from),
+        }
+    }
+}
+
+/// Crash report destination, see `crash.rs`.
+pub enum CrashTarget {
+    /// Sentry DSN, `https://<key>@<host>/<project>`.
+    Sentry(String),
+    /// Any URL taking a JSON POST per panic.
+    Webhook(String),
+}
+
+/// JSON lines audit log, see `audit.rs`.
+pub struct AuditConfig {
+    pub path: PathBuf,
+    /// Size at which the file rotates.
+    pub max_bytes: u64,
+    /// Rotated files kept.
+    pub keep: usize,
+}
+
+/// JSON lines usage records, see `usage.rs`.
+pub struct UsageConfig {
+    pub path: PathBuf,
+    /// How long each record covers.
+    pub interval: Duration,
+}
+
+/// Outbound webhooks, see `webhooks.rs`.
+pub struct WebhookConfig {
+    pub urls: Vec<String>,
+    /// HMAC-SHA256 key for `X-Teleboxel-Signature`, unsigned when unset.
+    pub secret: Option<String>,
+    /// Event names to send, all of them when unset.
+    pub events: Option<Vec<String>>,
+    /// Attempts after the first one, with exponential backoff.
+    pub retries: u32,
+}
+
+/// Batched gameplay events, see `analytics.rs`.
+pub struct AnalyticsConfig {
+    /// An `http[s]://` URL, `kafka://<REST proxy>/<topic>` or `file:<path>`.
+    pub sink: String,
+    /// Entries per batch, sent before the flush interval when full.
+    pub batch: usize,
+    pub flush: Duration,
+    /// `<event>=<rate>` pairs, `*` for the other events; all when unset.
+    pub sample: Option<String>,
+}
+
+/// JWT logins against an identity provider, see `jwt.rs`.
+pub struct JwtConfig {
+    /// Where the signing keys are, `https://` only.
+    pub jwks_url: String,
+    /// How long fetched keys are used before fetching them again.
+    pub jwks_ttl: Duration,
+    /// Required `iss`, any when unset.
+    pub issuer: Option<String>,
+    /// Required in `aud`, any when unset.
+    pub audience: Option<String>,
+    /// Claim with the persistent player id records are kept under.
+    pub id_claim: String,
+    /// Claim with the name shown in chat, the id when missing.
+    pub name_claim: String,
+    /// Claim with role names, a string or a list. Dots reach into objects,
+    /// e.g. `realm_access.roles`.
+    pub roles_claim: String,
+    /// Clock skew allowed for `exp` and `nbf`.
+    pub leeway: Duration,
+}
+
+/// Platform ticket checks by a backend, see `auth.rs`.
+pub struct AuthCallout {
+    /// Gets a JSON POST per login.
+    pub url: String,
+    /// Sent as a Bearer token, so the backend knows it's us.
+    pub secret: Option<String>,
+}
+
+/// Tick history kept by every world, see `history.rs`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct HistoryConfig {
+    /// How many of the latest ticks, `0` to keep none.
+    pub ticks: u64,
+    /// Lets the admin API rewind live worlds. Development only.
+    pub rewind: bool,
+}
+
+/// Fixed input delay, see `input.rs`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct InputConfig {
+    /// Ticks moves and block edits wait, `0` to apply them right away.
+    pub delay_ticks: u64,
+    /// Inputs each player can have waiting, more are dropped.
+    pub buffer: usize,
+}
+
+/// The connect queue, see `admission.rs`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct AdmissionConfig {
+    /// Connections handshaking at once.
+    pub concurrency: usize,
+    /// Connections waiting for a place at most, refused beyond.
+    pub queue: usize,
+    /// How long one waits before it's refused.
+    pub wait: Duration,
+}
+
+/// Proximity voice chat, see `voice.rs`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct VoiceConfig {
+    /// How far voices carry, in blocks.
+    pub range: u32,
+    /// Voice each connection may send, in kilobits a second, `0` to turn
+    /// voice off.
+    pub kbps: u32,
+    /// Whether `VOICE` counts the solid blocks in between.
+    pub occlusion: bool,
+}
+
+pub struct BackupConfig {
+    pub dir: PathBuf,
+    pub interval: Duration,
+    pub retention: Retention,
+    pub s3: Option<S3Config>,
+}
+
+/// Backups kept on prune: the newest `keep_last`, plus the newest backup of
+/// each of the last `keep_daily` days and `keep_weekly` weeks.
+#[derive(Clone, Copy)]
+pub struct Retention {
+    pub keep_last: usize,
+    pub keep_daily: usize,
+    pub keep_weekly: usize,
+}
+
+/// S3-compatible upload target. Credentials come from the usual
+/// `AWS_ACCESS_KEY_ID` / `AWS_SECRET_ACCESS_KEY` variables.
+#[cfg_attr(not(feature = "s3"), allow(dead_code))]
+pub struct S3Config {
+    pub bucket: String,
+    pub endpoint: String,
+    pub region: String,
+    pub prefix: String,
+}
+
+impl Config {
+    pub fn from_vars(vars: &Vars) -> Self {
+        let backup = vars.var("TELEBOXEL_BACKUP_DIR").map(|dir| BackupConfig {
+            dir: PathBuf::from(dir),
+            interval: Duration::from_secs(vars.parse_or("TELEBOXEL_BACKUP_INTERVAL_SECS", 3600)),
+            retention: Retention {
+                keep_last: vars.parse_or("TELEBOXEL_BACKUP_KEEP_LAST", 5),
+                keep_daily: vars.parse_or("TELEBOXEL_BACKUP_KEEP_DAILY", 7),
+                keep_weekly: vars.parse_or("TELEBOXEL_BACKUP_KEEP_WEEKLY", 4),
+            },
+            s3: vars
+                .var("TELEBOXEL_BACKUP_S3_BUCKET")
+                .map(|bucket| S3Config {
+                    bucket,
+                    endpoint: vars
+                        .var("TELEBOXEL_BACKUP_S3_ENDPOINT")
+                        .unwrap_or_else(|| "https://s3.amazonaws.com".to_string()),
+                    region: vars
+                        .var("TELEBOXEL_BACKUP_S3_REGION")
+                        .unwrap_or_else(|| "us-east-1".to_string()),
+                    prefix: vars.var("TELEBOXEL_BACKUP_S3_PREFIX").unwrap_or_default(),
+                }),
+        });
+
+        let otlp = vars
+            .var("TELEBOXEL_OTLP_ENDPOINT")
+            .map(|endpoint| OtlpConfig {
+                endpoint,
+                slow: Duration::from_millis(vars.parse_or("TELEBOXEL_OTLP_SLOW_MS", 5)),
+                interval: Duration::from_secs(vars.parse_or("TELEBOXEL_OTLP_INTERVAL_SECS", 10)),
+            });
+
+        let agones = vars
+            .parse_or("TELEBOXEL_AGONES", false)
+            .then(|| AgonesConfig {
+                address: format!(
+                    "localhost:{}",
+                    vars.parse_or("AGONES_SDK_HTTP_PORT", 9358u16)
+                ),
+                health_interval: Duration::from_secs(
+                    vars.parse_or("TELEBOXEL_AGONES_HEALTH_SECS", 5).max(1),
+                ),
+            });
+
+        let crash = vars
+            .var("TELEBOXEL_SENTRY_DSN")
+            .map(CrashTarget::Sentry)
+            .or_else(|| {
+                vars.var("TELEBOXEL_CRASH_WEBHOOK")
+                    .map(CrashTarget::Webhook)
+            });
+
+        let audit = vars.var("TELEBOXEL_AUDIT_LOG").map(|path| AuditConfig {
+            path: PathBuf::from(path),
+            max_bytes: vars.parse_or("TELEBOXEL_AUDIT_MAX_MB", 10) * 1024 * 1024,
+            keep: vars.parse_or("TELEBOXEL_AUDIT_KEEP", 5),
+        });
+
+        let usage = vars.var("TELEBOXEL_USAGE_LOG").map(|path| UsageConfig {
+            path: PathBuf::from(path),
+            interval: Duration::from_secs(vars.parse_or("TELEBOXEL_USAGE_SECS", 300).max(1)),
+        });
+
+        let webhooks = vars
+            .var("TELEBOXEL_WEBHOOK_URLS")
+            .map(|urls| WebhookConfig {
+                urls: list(&urls),
+                secret: vars.var("TELEBOXEL_WEBHOOK_SECRET"),
+                events: vars
+                    .var("TELEBOXEL_WEBHOOK_EVENTS")
+                    .map(|events| list(&events)),
+                retries: vars.parse_or("TELEBOXEL_WEBHOOK_RETRIES", 5),
+            });
+
+        let analytics = vars
+            .var("TELEBOXEL_ANALYTICS_SINK")
+            .map(|sink| AnalyticsConfig {
+                sink,
+                batch: vars.parse_or("TELEBOXEL_ANALYTICS_BATCH", 500).max(1),
+                flush: Duration::from_secs(
+                    vars.parse_or("TELEBOXEL_ANALYTICS_FLUSH_SECS", 10).max(1),
+                ),
+                sample: vars.var("TELEBOXEL_ANALYTICS_SAMPLE"),
+            });
+
+        let jwt = vars.var("TELEBOXEL_JWKS_URL").map(|jwks_url| JwtConfig {
+            jwks_url,
+            jwks_ttl: Duration::from_secs(vars.parse_or("TELEBOXEL_JWKS_TTL_SECS", 600)),
+            issuer: vars.var("TELEBOXEL_JWT_ISSUER"),
+            audience: vars.var("TELEBOXEL_JWT_AUDIENCE"),
+            id_claim: vars
+                .var("TELEBOXEL_JWT_ID_CLAIM")
+                .unwrap_or_else(|| "sub".to_string()),
+            name_claim: vars
+                .var("TELEBOXEL_JWT_NAME_CLAIM")
+                .unwrap_or_else(|| "name".to_string()),
+            roles_claim: vars
+                .var("TELEBOXEL_JWT_ROLES_CLAIM")
+                .unwrap_or_else(|| "roles".to_string()),
+            leeway: Duration::from_secs(vars.parse_or("TELEBOXEL_JWT_LEEWAY_SECS", 60)),
+        });
+
+        Self {
+            database_url: vars.var("TELEBOXEL_DATABASE_URL"),
+            world_dir: vars.var("TELEBOXEL_WORLD_DIR").map(PathBuf::from),
+            save_interval: Duration::from_secs(vars.parse_or("TELEBOXEL_SAVE_INTERVAL_SECS", 30)),
+            chunk_cache_mb: vars.parse_or("TELEBOXEL_CHUNK_CACHE_MB", 256),
+            generator: vars.parse_or("TELEBOXEL_GENERATOR", GeneratorKind::Noise),
+            world_seed: vars.parse_or("TELEBOXEL_WORLD_SEED", 0),
+            chunk_format: vars.parse_or("TELEBOXEL_CHUNK_FORMAT", ChunkFormat::Compact),
+            spatial: vars.parse_or("TELEBOXEL_SPATIAL_INDEX", SpatialKind::Grid),
+            blocks: vars.var("TELEBOXEL_BLOCKS").map(PathBuf::from),
+            bridges: vars.var("TELEBOXEL_BRIDGES").map(PathBuf::from),
+            portals: vars.var("TELEBOXEL_PORTALS").map(PathBuf::from),
+            triggers: vars.var("TELEBOXEL_TRIGGERS").map(PathBuf::from),
+            flood: vars.var("TELEBOXEL_FLOOD").map(PathBuf::from),
+            afk: vars.var("TELEBOXEL_AFK").map(PathBuf::from),
+            relevance: vars.var("TELEBOXEL_RELEVANCE").map(PathBuf::from),
+            coords: vars.var("TELEBOXEL_COORDS").map(PathBuf::from),
+            achievements: vars.var("TELEBOXEL_ACHIEVEMENTS").map(PathBuf::from),
+            features: vars.var("TELEBOXEL_FEATURES").map(PathBuf::from),
+            quotas: vars.var("TELEBOXEL_QUOTAS").map(PathBuf::from),
+            templates: vars.var("TELEBOXEL_TEMPLATES").map(PathBuf::from),
+            listeners: vars.var("TELEBOXEL_LISTENERS").map(PathBuf::from),
+            tenants: vars.var("TELEBOXEL_TENANTS").map(PathBuf::from),
+            admin_token: vars.var("TELEBOXEL_ADMIN_TOKEN"),
+            moderator_token: vars.var("TELEBOXEL_MODERATOR_TOKEN"),
+            presence_token: vars.var("TELEBOXEL_PRESENCE_TOKEN"),
+            console: vars.parse_or("TELEBOXEL_CONSOLE", true),
+            control_socket: vars.var("TELEBOXEL_CONTROL_SOCKET").map(PathBuf::from),
+            resume_secret: vars.var("TELEBOXEL_RESUME_SECRET"),
+            traffic_log_interval: Duration::from_secs(
+                vars.parse_or("TELEBOXEL_TRAFFIC_LOG_SECS", 60),
+            ),
+            frame_history: Duration::from_secs(vars.parse_or("TELEBOXEL_FRAME_HISTORY_SECS", 0)),
+            otlp,
+            agones,
+            profile: vars
+                .parse_or("TELEBOXEL_PROFILE", false)
+                .then(|| ProfileConfig::from_vars(vars)),
+            crash,
+            audit,
+            usage,
+            webhooks,
+            analytics,
+            backup,
+            jwt,
+            auth_callout: vars.var("TELEBOXEL_AUTH_URL").map(|url| AuthCallout {
+                url,
+                secret: vars.var("TELEBOXEL_AUTH_SECRET"),
+            }),
+            auth_timeout: Duration::from_millis(vars.parse_or("TELEBOXEL_AUTH_TIMEOUT_MS", 5000)),
+            guests: vars.parse_or("TELEBOXEL_GUESTS", false),
+            encryption: vars.parse_or("TELEBOXEL_ENCRYPTION", Policy::Off),
+            message_auth: vars.parse_or("TELEBOXEL_MESSAGE_AUTH", Policy::Off),
+            server_key: vars.var("TELEBOXEL_SERVER_KEY"),
+            history: HistoryConfig {
+                ticks: vars.parse_or("TELEBOXEL_HISTORY_TICKS", 600),
+                rewind: vars.parse_or("TELEBOXEL_DEV_REWIND", false),
+            },
+            input: InputConfig {
+                delay_ticks: vars.parse_or("TELEBOXEL_INPUT_DELAY_TICKS", 0),
+                buffer: vars.parse_or("TELEBOXEL_INPUT_BUFFER", 64),
+            },
+            voice: VoiceConfig {
+                range: vars.parse_or("TELEBOXEL_VOICE_RANGE", 48),
+                kbps: vars.parse_or("TELEBOXEL_VOICE_KBPS", 32),
+                occlusion: vars.parse_or("TELEBOXEL_VOICE_OCCLUSION", false),
+            },
+            admission: AdmissionConfig {
+                concurrency: vars.parse_or("TELEBOXEL_CONNECT_CONCURRENCY", 64),
+                queue: vars.parse_or("TELEBOXEL_CONNECT_QUEUE", 512),
+                wait: Duration::from_millis(vars.parse_or("TELEBOXEL_CONNECT_WAIT_MS", 2000)),
+            },
+            tunables: Tunables::from_vars(vars),
+        }
+    }
+}
+
+// Comma separated, empty items skipped
+fn list(s: &str) -> Vec<String> {
+    s.split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(String::from)
+        .collect()
+}
@@ -0,0 +1,442 @@
+//! Interactive console for quick local ops. Runs on the server's stdin when
+//! it's a terminal (`TELEBOXEL_CONSOLE=false` turns it off), or attached to
+//! a running server's control socket with `teleboxel console <socket>`.
+//! Lines become `control::Request`s, the same calls the admin API makes.
+
+use crate::control::{Control, Request};
+use serde_json::Value;
+use std::{fmt::Write, sync::Arc};
+use tokio::io::{AsyncBufReadExt, BufReader};
+
+const HELP: &str = "\
+players              connected players
+kick <id> [reason]   close a player's connection
+say <message>        chat line from `server`
+save                 save players and edited chunks now
+tickrate <hz>        change the tick rate until the next config reload
+pause, resume        stop and start the world's ticks
+step [ticks]         run paused ticks (default 1)
+speed <percent>      slow motion under 100, 5-400
+drain [secs] [url]   stop taking players, shut down once empty (default 60s)
+restart [secs]       start a new server on the same port, then drain
+room [name]          act on a room, or the main world without a name";
+
+/// Console state between lines: the room commands act on.
+#[derive(Default)]
+pub struct Console {
+    room: Option<String>,
+}
+
+impl Console {
+    /// The request for a command line, or what to print instead (help,
+    /// usage, room changes).
+    pub fn parse(&mut self, line: &str) -> Result<Request, String> {
+        let line = line.trim();
+        let (command, args) = line.split_once(' ').unwrap_or((line, ""));
+        let args = args.trim();
+        let room = self.room.clone();
+
+        match command {
+            "" => Err(String::new()),
+            "help" => Err(HELP.to_string()),
+            "players" => Ok(Request::World { room }),
+            "kick" => {
+                let (id, reason) = args.split_once(' ').unwrap_or((args, ""));
+                let id = id.parse().map_err(|_| "Usage: kick <id> [reason]")?;
+                let reason = reason.trim().to_string();
+                Ok(Request::Kick { room, id, reason })
+            }
+            "say" if args.is_empty() => Err("Usage: say <message>".to_string()),
+            "say" => Ok(Request::Say {
+                room,
+                text: args.to_string(),
+            }),
+            "save" => Ok(Request::Save),
+            "tickrate" => {
+                let hz = args.parse().map_err(|_| "Usage: tickrate <hz>")?;
+                Ok(Request::Tickrate { hz })
+            }
+            "drain" => {
+                let mut args = args.split_whitespace();
+                let seconds = match args.next().map(str::parse) {
+                    Some(Ok(seconds)) => seconds,
+                    Some(Err(_)) => return Err("Usage: drain [seconds] [address]".to_string()),
+                    None => 60,
+                };
+                let address = args.next().map(String::from);
+                Ok(Request::Drain { seconds, address })
+            }
+            "restart" if args.is_empty() => Ok(Request::Restart { seconds: 60 }),
+            "restart" => {
+                let seconds = args.parse().map_err(|_| "Usage: restart [seconds]")?;
+                Ok(Request::Restart { seconds })
+            }
+            "pause" | "resume" => Ok(Request::Simulation {
+                room,
+                paused: Some(command == "pause"),
+                scale: None,
+                step: None,
+            }),
+            "step" => {
+                let ticks = match args {
+                    "" => 1,
+                    args => args.parse().map_err(|_| "Usage: step [ticks]")?,
+                };
+                Ok(Request::Simulation {
+                    room,
+                    paused: None,
+                    scale: None,
+                    step: Some(ticks),
+                })
+            }
+            "speed" => {
+                let scale = args.parse().map_err(|_| "Usage: speed <percent>")?;
+                Ok(Request::Simulation {
+                    room,
+                    paused: None,
+                    scale: Some(scale),
+                    step: None,
+                })
+            }
+            "room" => {
+                self.room = Some(args)
+                    .filter(|r| !r.is_empty() && *r != "main")
+                    .map(String::from);
+                Err(format!("Using {}", self.room.as_deref().unwrap_or("main")))
+            }
+            _ => Err(format!("Unknown command {command}, try help")),
+        }
+    }
+
+    /// Runs one command line, returning what to print.
+    pub async fn execute(&mut self, control: &Control, line: &str) -> String {
+        match self.parse(line) {
+            Ok(request) => show(&request, control.handle(request.clone()).await),
+            Err(out) => out,
+        }
+    }
+
+    /// Runs commands from stdin until it closes.
+    pub async fn run(mut self, control: Arc<Control>) {
+        println!("Console ready, type help for commands");
+        let mut lines = BufReader::new(tokio::io::stdin()).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            let out = self.execute(&control, &line).await;
+            if !out.is_empty() {
+                println!("{out}");
+            }
+        }
+    }
+}
+
+/// A request's result as console text.
+pub fn show(request: &Request, result: Result<Value, String>) -> String {
+    let result = match result {
+        Ok(result) => result,
+        Err(e) => return e,
+    };
+
+    match request {
+        Request::World { .. } => {
+            let players = result["players"].as_array().cloned().unwrap_or_default();
+            if players.is_empty() {
+                return "No players".to_string();
+            }
+            let mut out = String::new();
+            for p in players {
+                let position = &p["position"];
+                let (x, y, z) = (&position[0], &position[1], &position[2]);
+                let name = p["name"].as_str().unwrap_or("-");
+                writeln!(out, "{} {name} {x},{y},{z}", p["id"]).unwrap();
+            }
+            out.pop();
+            out
+        }
+        Request::Kick { id, .. } => format!("Kicked {id}"),
+        Request::Say { .. } => String::new(),
+        Request::Save => format!(
+            "Saving {} players, {} chunks",
+            result["players"], result["chunks"]
+        ),
+        Request::Tickrate { hz } => format!("Tick rate {hz} Hz"),
+        Request::Drain { seconds, .. } => format!(
+            "Draining {} players, shutting down within {seconds}s",
+            result["players"]
+        ),
+        Request::Restart { seconds } => format!(
+            "Restarted, draining {} players within {seconds}s",
+            result["players"]
+        ),
+        Request::Simulation { .. } => {
+            let (tick, steps) = (&result["tick"], result["steps"].as_u64().unwrap_or(0));
+            match result["paused"].as_bool().unwrap_or(false) {
+                true if steps > 0 => format!("Paused at tick {tick}, {steps} steps to go"),
+                true => format!("Paused at tick {tick}"),
+                false => format!("Running at {}%, tick {tick}", result["scale"]),
+            }
+        }
+    }
+}
+
+/// Console over a running server's control socket, on this terminal.
+#[cfg(unix)]
+pub fn attach(socket: &std::path::Path) -> std::io::Result<()> {
+    use crate::control::parse_reply;
+    use std::{
+        io::{BufRead, BufReader, Write},
+        os::unix::net::UnixStream,
+    };
+
+    let stream = UnixStream::connect(socket)?;
+    let mut replies = BufReader::new(stream.try_clone()?).lines();
+    let mut stream = stream;
+    let mut console = Console::default();
+    println!("Attached to {}, type help for commands", socket.display());
+
+    for line in std::io::stdin().lines() {
+        let out = match console.parse(&line?) {
+            Ok(request) => {
+                writeln!(stream, "{}", serde_json::to_string(&request).unwrap())?;
+                let Some(reply) = replies.next() else {
+                    println!("Server closed the connection");
+                    break;
+                };
+                show(&request, parse_reply(&reply?))
+            }
+            Err(out) => out,
+        };
+        if !out.is_empty() {
+            println!("{out}");
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        admin::{BoxFuture, PlayerState, RestartError, WorldControl, WorldState},
+        claims::BlockPos,
+        config::Tunables,
+        effects::Effect,
+        entities::{EntityError, EntityOp, EntityReply},
+        features::{Features, Toggles},
+        history::{HistoryError, HistoryQuery, HistoryReply},
+        minimap::Minimap,
+        pathfinding::{PathError, Route},
+        simulation::{SimulationChange, SimulationState},
+    };
+    use std::{sync::Mutex, time::Duration};
+    use tokio::sync::watch;
+
+    // A main world with player 1, recording kicks
+    #[derive(Default)]
+    struct FakeWorld {
+        kicked: Mutex<Vec<(u32, String)>>,
+    }
+
+    impl WorldControl for FakeWorld {
+        fn kick(&self, room: Option<&str>, id: u32, reason: String) -> BoxFuture<'_, bool> {
+            let found = room.is_none() && id == 1;
+            if found {
+                self.kicked.lock().unwrap().push((id, reason));
+            }
+            Box::pin(async move { found })
+        }
+
+        fn state(&self, room: Option<&str>) -> BoxFuture<'_, Option<WorldState>> {
+            let state = room.is_none().then(|| WorldState {
+                tick: 1,
+                loaded_chunks: 0,
+                players: vec![PlayerState {
+                    id: 1,
+                    name: Some("bob".into()),
+                    position: (0, 40, -3),
+                    rotation: (0.0, 0.0),
+                    velocity: None,
+                    clock: None,
+                    interest: None,
+                    dropped_inputs: 0,
+                    coalesced: 0,
+                    afk: false,
+                    layer: 0,
+                }],
+            });
+            Box::pin(async move { state })
+        }
+
+        fn say(&self, room: Option<&str>, _text: String) -> BoxFuture<'_, bool> {
+            let found = room.is_none();
+            Box::pin(async move { found })
+        }
+
+        fn save(&self) -> BoxFuture<'_, Option<(usize, usize)>> {
+            Box::pin(async { Some((1, 4)) })
+        }
+
+        fn drain(&self, _seconds: u16, _address: String) -> BoxFuture<'_, Option<usize>> {
+            Box::pin(async { Some(1) })
+        }
+
+        fn restart(&self, _seconds: u16) -> BoxFuture<'_, Result<usize, RestartError>> {
+            Box::pin(async { Ok(1) })
+        }
+
+        fn history(
+            &self,
+            _: Option<&str>,
+            _: HistoryQuery,
+        ) -> BoxFuture<'_, Option<Result<HistoryReply, HistoryError>>> {
+            Box::pin(async { None })
+        }
+
+        fn frames(&self, _: Option<&str>, _: u32, _: Duration) -> BoxFuture<'_, Option<Vec<u8>>> {
+            Box::pin(async { None })
+        }
+
+        fn features(&self, _: Option<&str>, _: Toggles) -> BoxFuture<'_, Option<Features>> {
+            Box::pin(async { None })
+        }
+
+        fn simulation(
+            &self,
+            room: Option<&str>,
+            change: SimulationChange,
+        ) -> BoxFuture<'_, Option<SimulationState>> {
+            let state = room.is_none().then(|| SimulationState {
+                paused: change.paused.unwrap_or(false) || change.step.is_some(),
+                scale: change.scale.unwrap_or(100),
+                steps: change.step.unwrap_or(0),
+                tick: 812,
+            });
+            Box::pin(async move { state })
+        }
+
+        fn entities(
+            &self,
+            _: Option<&str>,
+            _: EntityOp,
+        ) -> BoxFuture<'_, Option<Result<EntityReply, EntityError>>> {
+            Box::pin(async { None })
+        }
+
+        fn effect(&self, _: Option<&str>, _: Effect) -> BoxFuture<'_, Option<usize>> {
+            Box::pin(async { None })
+        }
+
+        fn path(
+            &self,
+            _: Option<&str>,
+            _: BlockPos,
+            _: BlockPos,
+        ) -> BoxFuture<'_, Option<Result<Route, PathError>>> {
+            Box::pin(async { None })
+        }
+
+        fn create_room(
+            &self,
+            _: &str,
+            _: Option<String>,
+        ) -> BoxFuture<'_, Option<Result<String, String>>> {
+            Box::pin(async { None })
+        }
+
+        fn reserve(
+            &self,
+            _: Option<&str>,
+            _: Option<String>,
+            _: Duration,
+        ) -> BoxFuture<'_, Option<Result<String, String>>> {
+            Box::pin(async { None })
+        }
+        fn layer(&self, _: Option<&str>, _: u32, _: u32) -> BoxFuture<'_, Option<bool>> {
+            Box::pin(async { None })
+        }
+
+        fn minimap(
+            &self,
+            _: Option<&str>,
+            _: (i32, i32),
+            _: u8,
+            _: u8,
+        ) -> BoxFuture<'_, Option<Minimap>> {
+            Box::pin(async { None })
+        }
+    }
+
+    #[tokio::test]
+    async fn runs_commands_against_the_world() {
+        let world = Arc::new(FakeWorld::default());
+        let tunables = Tunables::from_vars(&Default::default());
+        let (tx, rx) = watch::channel(tunables);
+        let control = Control::new(world.clone(), tx, None);
+        let mut console = Console::default();
+
+        assert_eq!(console.execute(&control, "players").await, "1 bob 0,40,-3");
+        assert_eq!(
+            console.execute(&control, "kick 1 spamming chat").await,
+            "Kicked 1"
+        );
+        assert_eq!(console.execute(&control, "kick 2").await, "No player 2");
+        assert_eq!(
+            console.execute(&control, "kick x").await,
+            "Usage: kick <id> [reason]"
+        );
+        assert_eq!(
+            *world.kicked.lock().unwrap(),
+            [(1, "spamming chat".to_string())]
+        );
+        assert_eq!(console.execute(&control, "say hi").await, "");
+        assert_eq!(
+            console.execute(&control, "save").await,
+            "Saving 1 players, 4 chunks"
+        );
+
+        assert_eq!(
+            console.execute(&control, "tickrate 20").await,
+            "Tick rate 20 Hz"
+        );
+        assert_eq!(rx.borrow().tick_hz, 20);
+        assert_eq!(
+            console.execute(&control, "tickrate 0").await,
+            "Tick rate must be 1-1000"
+        );
+
+        assert_eq!(
+            console.execute(&control, "drain 30 ws://next:3000").await,
+            "Draining 1 players, shutting down within 30s"
+        );
+        assert_eq!(
+            console.execute(&control, "restart").await,
+            "Restarted, draining 1 players within 60s"
+        );
+
+        assert_eq!(
+            console.execute(&control, "pause").await,
+            "Paused at tick 812"
+        );
+        assert_eq!(
+            console.execute(&control, "step 3").await,
+            "Paused at tick 812, 3 steps to go"
+        );
+        assert_eq!(
+            console.execute(&control, "speed 25").await,
+            "Running at 25%, tick 812"
+        );
+        assert_eq!(
+            console.execute(&control, "speed 1").await,
+            "Scale must be 5-400 percent"
+        );
+
+        assert_eq!(console.execute(&control, "room arena").await, "Using arena");
+        assert_eq!(console.execute(&control, "players").await, "No such room");
+        assert_eq!(console.execute(&control, "room").await, "Using main");
+        assert!(
+            console
+                .execute(&control, "dance")
+                .await
+                .starts_with("Unknown command")
+        );
+    }
+}
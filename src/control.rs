@@ -0,0 +1,417 @@
+//! Local control interface: a Unix socket (`TELEBOXEL_CONTROL_SOCKET`)
+//! taking newline-delimited JSON requests, for ops tooling on the same host
+//! without the admin HTTP API on a network port. `teleboxel console <socket>`
+//! attaches the interactive console to it.
+//!
+//! ```text
+//! > {"command":"kick","id":3,"reason":"griefing"}
+//! < {"ok":true,"result":null}
+//! > {"command":"world","room":"arena"}
+//! < {"ok":true,"result":{"tick":812,"loaded_chunks":27,"players":[...]}}
+//! > {"command":"tickrate","hz":0}
+//! < {"error":"Tick rate must be 1-1000","ok":false}
+//! ```
+//!
+//! `room` is optional everywhere, the main world when missing.
+
+use crate::{
+    admin::WorldControl,
+    audit::{AuditEvent, AuditLog},
+    config::Tunables,
+    simulation::SimulationChange,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::sync::Arc;
+use tokio::sync::watch;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum Request {
+    /// Tick, loaded chunks and players.
+    World {
+        #[serde(default)]
+        room: Option<String>,
+    },
+    Kick {
+        #[serde(default)]
+        room: Option<String>,
+        id: u32,
+        #[serde(default)]
+        reason: String,
+    },
+    /// Chat line from `server`.
+    Say {
+        #[serde(default)]
+        room: Option<String>,
+        text: String,
+    },
+    /// Saves the main world's players and edited chunks now.
+    Save,
+    /// Until the next config reload.
+    Tickrate { hz: u32 },
+    /// Drain mode, see `drain.rs`.
+    Drain {
+        #[serde(default = "default_drain_seconds")]
+        seconds: u16,
+        /// Replacement server's websocket URL.
+        #[serde(default)]
+        address: Option<String>,
+    },
+    /// Hot restart, see `restart.rs`.
+    Restart {
+        #[serde(default = "default_drain_seconds")]
+        seconds: u16,
+    },
+    /// Pauses, rescales (percent) or steps the world's ticks, see
+    /// `simulation.rs`. Nothing set just asks.
+    Simulation {
+        #[serde(default)]
+        room: Option<String>,
+        #[serde(default)]
+        paused: Option<bool>,
+        #[serde(default)]
+        scale: Option<u16>,
+        #[serde(default)]
+        step: Option<u32>,
+    },
+}
+
+fn default_drain_seconds() -> u16 {
+    60
+}
+
+/// Runs requests against the worlds, for the control socket and the console.
+pub struct Control {
+    world: Arc<dyn WorldControl>,
+    tunables: watch::Sender<Tunables>,
+    audit: Option<Arc<AuditLog>>,
+}
+
+impl Control {
+    pub fn new(
+        world: Arc<dyn WorldControl>,
+        tunables: watch::Sender<Tunables>,
+        audit: Option<Arc<AuditLog>>,
+    ) -> Self {
+        Self {
+            world,
+            tunables,
+            audit,
+        }
+    }
+
+    pub async fn handle(&self, request: Request) -> Result<Value, String> {
+        match request {
+            Request::World { room } => match self.world.state(room.as_deref()).await {
+                Some(state) => Ok(serde_json::to_value(state).unwrap()),
+                None => Err("No such room".to_string()),
+            },
+            Request::Kick { room, id, reason } => {
+                if !self.world.kick(room.as_deref(), id, reason.clone()).await {
+                    return Err(format!("No player {id}"));
+                }
+                if let Some(audit) = &self.audit {
+                    audit.record(AuditEvent::Kick {
+                        room: room.unwrap_or_else(|| "main".to_string()),
+                        id,
+                        reason,
+                    });
+                }
+                Ok(Value::Null)
+            }
+            Request::Say { room, text } => match self.world.say(room.as_deref(), text).await {
+                true => Ok(Value::Null),
+                false => Err("No such room".to_string()),
+            },
+            Request::Save => match self.world.save().await {
+                Some((players, chunks)) => Ok(json!({ "players": players, "chunks": chunks })),
+                None => Err("World is gone".to_string()),
+            },
+            Request::Tickrate { hz } => {
+                if !(1..=1000).contains(&hz) {
+                    return Err("Tick rate must be 1-1000".to_string());
+                }
+                self.tunables.send_modify(|t| t.tick_hz = hz);
+                Ok(json!({ "tick_hz": hz }))
+            }
+            Request::Drain { seconds, address } => {
+                match self.world.drain(seconds, address.unwrap_or_default()).await {
+                    Some(players) => Ok(json!({ "players": players })),
+                    None => Err("Already draining".to_string()),
+                }
+            }
+            Request::Restart { seconds } => match self.world.restart(seconds).await {
+                Ok(players) => Ok(json!({ "players": players })),
+                Err(e) => Err(e.to_string()),
+            },
+            Request::Simulation {
+                room,
+                paused,
+                scale,
+                step,
+            } => {
+                let change = SimulationChange {
+                    paused,
+                    scale,
+                    step,
+                };
+                change.check()?;
+                match self.world.simulation(room.as_deref(), change).await {
+                    Some(state) => Ok(serde_json::to_value(state).unwrap()),
+                    None => Err("No such room".to_string()),
+                }
+            }
+        }
+    }
+
+    // One JSON line in, one out
+    async fn handle_line(&self, line: &str) -> String {
+        let result = match serde_json::from_str(line) {
+            Ok(request) => self.handle(request).await,
+            Err(e) => Err(format!("Invalid request: {e}")),
+        };
+        reply_line(result)
+    }
+}
+
+fn reply_line(result: Result<Value, String>) -> String {
+    let reply = match result {
+        Ok(result) => json!({ "ok": true, "result": result }),
+        Err(error) => json!({ "ok": false, "error": error }),
+    };
+    reply.to_string() + "\n"
+}
+
+/// The result in a reply line, as `Control::handle` returned it.
+pub fn parse_reply(line: &str) -> Result<Value, String> {
+    let reply: Value = serde_json::from_str(line).map_err(|e| format!("Invalid reply: {e}"))?;
+    match reply["ok"].as_bool() {
+        Some(true) => Ok(reply["result"].clone()),
+        _ => Err(reply["error"]
+            .as_str()
+            .unwrap_or("Unknown error")
+            .to_string()),
+    }
+}
+
+/// Binds the socket (owner-only permissions) and serves it in the
+/// background. A stale socket file from a previous run is replaced.
+#[cfg(unix)]
+pub fn listen(path: &std::path::Path, control: Arc<Control>) -> std::io::Result<()> {
+    use std::{fs, os::unix::fs::PermissionsExt};
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+        net::{UnixListener, UnixStream},
+    };
+
+    let listener = crate::listeners::bind_unix(path)?;
+    listener.set_nonblocking(true)?;
+    let listener = UnixListener::from_std(listener)?;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+
+    async fn serve(stream: UnixStream, control: Arc<Control>) -> std::io::Result<()> {
+        let (read, mut write) = stream.into_split();
+        let mut lines = BufReader::new(read).lines();
+        while let Some(line) = lines.next_line().await? {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let reply = control.handle_line(&line).await;
+            write.write_all(reply.as_bytes()).await?;
+        }
+        Ok(())
+    }
+
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    tokio::spawn(serve(stream, control.clone()));
+                }
+                Err(e) => eprintln!("Control socket accept: {e}"),
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::{
+        admin::{BoxFuture, RestartError, WorldState},
+        claims::BlockPos,
+        effects::Effect,
+        entities::{EntityError, EntityOp, EntityReply},
+        features::{Features, Toggles},
+        history::{HistoryError, HistoryQuery, HistoryReply},
+        minimap::Minimap,
+        pathfinding::{PathError, Route},
+        simulation::SimulationState,
+    };
+    use std::time::Duration;
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+        net::UnixStream,
+    };
+
+    // An empty main world
+    struct EmptyWorld;
+
+    impl WorldControl for EmptyWorld {
+        fn kick(&self, _: Option<&str>, _: u32, _: String) -> BoxFuture<'_, bool> {
+            Box::pin(async { false })
+        }
+
+        fn state(&self, room: Option<&str>) -> BoxFuture<'_, Option<WorldState>> {
+            let state = room.is_none().then(|| WorldState {
+                tick: 5,
+                loaded_chunks: 0,
+                players: Vec::new(),
+            });
+            Box::pin(async move { state })
+        }
+
+        fn say(&self, room: Option<&str>, _: String) -> BoxFuture<'_, bool> {
+            let found = room.is_none();
+            Box::pin(async move { found })
+        }
+
+        fn save(&self) -> BoxFuture<'_, Option<(usize, usize)>> {
+            Box::pin(async { Some((0, 2)) })
+        }
+
+        fn drain(&self, _: u16, _: String) -> BoxFuture<'_, Option<usize>> {
+            Box::pin(async { Some(0) })
+        }
+
+        fn restart(&self, _: u16) -> BoxFuture<'_, Result<usize, RestartError>> {
+            Box::pin(async { Err(RestartError::Draining) })
+        }
+
+        fn history(
+            &self,
+            _: Option<&str>,
+            _: HistoryQuery,
+        ) -> BoxFuture<'_, Option<Result<HistoryReply, HistoryError>>> {
+            Box::pin(async { None })
+        }
+
+        fn frames(&self, _: Option<&str>, _: u32, _: Duration) -> BoxFuture<'_, Option<Vec<u8>>> {
+            Box::pin(async { None })
+        }
+
+        fn features(&self, _: Option<&str>, _: Toggles) -> BoxFuture<'_, Option<Features>> {
+            Box::pin(async { None })
+        }
+
+        fn simulation(
+            &self,
+            _: Option<&str>,
+            _: SimulationChange,
+        ) -> BoxFuture<'_, Option<SimulationState>> {
+            Box::pin(async { None })
+        }
+
+        fn entities(
+            &self,
+            _: Option<&str>,
+            _: EntityOp,
+        ) -> BoxFuture<'_, Option<Result<EntityReply, EntityError>>> {
+            Box::pin(async { None })
+        }
+
+        fn effect(&self, _: Option<&str>, _: Effect) -> BoxFuture<'_, Option<usize>> {
+            Box::pin(async { None })
+        }
+
+        fn path(
+            &self,
+            _: Option<&str>,
+            _: BlockPos,
+            _: BlockPos,
+        ) -> BoxFuture<'_, Option<Result<Route, PathError>>> {
+            Box::pin(async { None })
+        }
+
+        fn create_room(
+            &self,
+            _: &str,
+            _: Option<String>,
+        ) -> BoxFuture<'_, Option<Result<String, String>>> {
+            Box::pin(async { None })
+        }
+
+        fn reserve(
+            &self,
+            _: Option<&str>,
+            _: Option<String>,
+            _: Duration,
+        ) -> BoxFuture<'_, Option<Result<String, String>>> {
+            Box::pin(async { None })
+        }
+        fn layer(&self, _: Option<&str>, _: u32, _: u32) -> BoxFuture<'_, Option<bool>> {
+            Box::pin(async { None })
+        }
+
+        fn minimap(
+            &self,
+            _: Option<&str>,
+            _: (i32, i32),
+            _: u8,
+            _: u8,
+        ) -> BoxFuture<'_, Option<Minimap>> {
+            Box::pin(async { None })
+        }
+    }
+
+    #[tokio::test]
+    async fn serves_json_lines_over_the_socket() {
+        let path = std::env::temp_dir().join(format!("teleboxel-{}.sock", std::process::id()));
+        let (tx, rx) = watch::channel(Tunables::from_vars(&Default::default()));
+        let control = Arc::new(Control::new(Arc::new(EmptyWorld), tx, None));
+        listen(&path, control.clone()).unwrap();
+        // Taken while the server is up
+        assert!(listen(&path, control).is_err());
+
+        let stream = UnixStream::connect(&path).await.unwrap();
+        let (read, mut write) = stream.into_split();
+        let mut lines = BufReader::new(read).lines();
+        let mut ask = async |request: &str| {
+            write.write_all(request.as_bytes()).await.unwrap();
+            write.write_all(b"\n").await.unwrap();
+            parse_reply(&lines.next_line().await.unwrap().unwrap())
+        };
+
+        let world = ask(r#"{"command":"world"}"#).await.unwrap();
+        assert_eq!(world["tick"], 5);
+        assert_eq!(
+            ask(r#"{"command":"world","room":"arena"}"#).await,
+            Err("No such room".to_string())
+        );
+        assert_eq!(
+            ask(r#"{"command":"kick","id":3}"#).await,
+            Err("No player 3".to_string())
+        );
+        assert_eq!(
+            ask(r#"{"command":"save"}"#).await,
+            Ok(json!({ "players": 0, "chunks": 2 }))
+        );
+        assert!(ask(r#"{"command":"tickrate","hz":0}"#).await.is_err());
+        ask(r#"{"command":"tickrate","hz":30}"#).await.unwrap();
+        assert_eq!(rx.borrow().tick_hz, 30);
+        assert_eq!(
+            ask(r#"{"command":"restart"}"#).await,
+            Err("Already draining".to_string())
+        );
+        assert!(
+            ask(r#"{"command":"dance"}"#)
+                .await
+                .unwrap_err()
+                .starts_with("Invalid request")
+        );
+
+        std::fs::remove_file(&path).ok();
+    }
+}
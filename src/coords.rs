@@ -0,0 +1,234 @@
+//! Wire coordinates per world: 2D worlds and fixed-point units, so a
+//! top-down game doesn't pay for an axis it doesn't use. In a TOML file
+//! (`TELEBOXEL_COORDS`, a world is `main` or a room name):
+//!
+//! ```toml
+//! [default]
+//! flat = false       # 2D: the z axis is always 0
+//! units = 1          # wire units per block (a meter)
+//!
+//! [room.arena]
+//! flat = true
+//! units = 16
+//! ```
+//!
+//! In a flat world, the z of every position a player sends is 0. Clients
+//! that connect with `?coords=1` (binary encoding only) also get them the
+//! world's way, told with a `COORDS` message before any of its positions:
+//!
+//! - Flat: positions go without z, and only chunks at chunk z 0 are sent.
+//! - `units`: block positions and offsets are multiplied by it both ways,
+//!   chunk positions stay chunks. Positions sent are divided (rounding
+//!   down), the server keeps whole blocks.
+//!
+//! Other clients get positions in three axes and blocks. Text commands
+//! still carry a z (ignored), origins (`origin.rs`) are in blocks and
+//! applied before units, and admin, console and webhook positions stay
+//! blocks in three axes. Chunks still hold their 16 z layers, and scaled
+//! positions past `i32` wrap, use an origin for far worlds.
+
+use crate::{chunk::ChunkPos, claims::BlockPos};
+use serde::Deserialize;
+use std::{collections::HashMap, error::Error, fs, path::Path};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Coords {
+    pub flat: bool,
+    pub units: u16,
+}
+
+impl Default for Coords {
+    fn default() -> Self {
+        Self {
+            flat: false,
+            units: 1,
+        }
+    }
+}
+
+impl Coords {
+    /// Whether positions go on the wire as they are.
+    pub fn is_plain(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// A block position from the wire, in blocks.
+    pub fn block(&self, (x, y, z): BlockPos) -> BlockPos {
+        let units = self.units as i32;
+        let z = if self.flat { 0 } else { z };
+        (
+            x.div_euclid(units),
+            y.div_euclid(units),
+            z.div_euclid(units),
+        )
+    }
+
+    /// A chunk position from the wire.
+    pub fn chunk(&self, (x, y, z): ChunkPos) -> ChunkPos {
+        (x, y, if self.flat { 0 } else { z })
+    }
+
+    /// A block position or offset in units, wrapping.
+    pub fn scale(&self, (x, y, z): BlockPos) -> BlockPos {
+        let units = self.units as i32;
+        (
+            x.wrapping_mul(units),
+            y.wrapping_mul(units),
+            z.wrapping_mul(units),
+        )
+    }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct CoordsTable {
+    flat: Option<bool>,
+    units: Option<u16>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct CoordsFile {
+    #[serde(default)]
+    default: CoordsTable,
+    #[serde(default)]
+    room: HashMap<String, CoordsTable>,
+}
+
+/// Coordinates by world.
+#[derive(Default)]
+pub struct CoordsRules {
+    default: Coords,
+    rooms: HashMap<String, Coords>,
+}
+
+impl CoordsRules {
+    /// See the module docs for the file format.
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    fn parse(text: &str) -> Result<Self, Box<dyn Error>> {
+        let file: CoordsFile = toml::from_str(text)?;
+        let coords = |table: &CoordsTable, base: Coords| -> Result<Coords, Box<dyn Error>> {
+            let units = table.units.unwrap_or(base.units);
+            if units == 0 {
+                return Err("units must be at least 1".into());
+            }
+            Ok(Coords {
+                flat: table.flat.unwrap_or(base.flat),
+                units,
+            })
+        };
+        let default = coords(&file.default, Coords::default())?;
+        let rooms = file
+            .room
+            .iter()
+            .map(|(room, table)| Ok((room.clone(), coords(table, default)?)))
+            .collect::<Result<_, Box<dyn Error>>>()?;
+        Ok(Self { default, rooms })
+    }
+
+    /// The coordinates of `world`, `main` or a room name.
+    pub fn get(&self, world: &str) -> Coords {
+        *self.rooms.get(world).unwrap_or(&self.default)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        chunk::Chunk,
+        entities::{Entity, Mount},
+        protocol::{ServerFrame, ServerMsg, decode_server_frame_as},
+    };
+
+    #[test]
+    fn flat_worlds_drop_z() {
+        let rules = CoordsRules::parse(
+            r#"
+            [default]
+            units = 4
+
+            [room.topdown]
+            flat = true
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            rules.get("main"),
+            Coords {
+                flat: false,
+                units: 4
+            }
+        );
+        let flat = rules.get("topdown");
+        assert_eq!(
+            flat,
+            Coords {
+                flat: true,
+                units: 4
+            }
+        );
+        assert!(CoordsRules::parse("[default]\nunits = 0").is_err());
+        assert!(Coords::default().is_plain());
+
+        // Positions sent are whole blocks on the plane
+        assert_eq!(flat.block((9, -1, 40)), (2, -1, 0));
+        assert_eq!(flat.chunk((1, 2, 3)), (1, 2, 0));
+
+        let mut frame = ServerFrame::new(3);
+        frame.chunk_snapshot((0, 1, 0), 1, &Chunk::empty());
+        frame.chunk_snapshot((0, 1, 2), 1, &Chunk::empty());
+        frame.entity(&Entity {
+            id: 9,
+            position: (5, -6, 7),
+            authority: None,
+            attached: None,
+            fixed: false,
+            always: false,
+            layer: 0,
+        });
+        frame.chat("ann", "hi");
+        frame.mount(&Mount {
+            player: 1,
+            entity: 9,
+            offset: (0, 2, 1),
+        });
+        let mut plain = frame.clone();
+        plain.apply_coords(Coords::default());
+        let plain = plain.finish();
+        frame.apply_coords(flat);
+        let data = frame.finish();
+        // The off-plane chunk and three z's
+        assert!(data.len() < plain.len());
+        assert_eq!(decode_server_frame_as(&plain, false).unwrap().1.len(), 5);
+
+        let (_, msgs) = decode_server_frame_as(&data, true).unwrap();
+        assert_eq!(msgs.len(), 4);
+        assert!(matches!(
+            msgs[0],
+            ServerMsg::ChunkSnapshot { pos: (0, 1, 0), .. }
+        ));
+        assert!(matches!(
+            msgs[1],
+            ServerMsg::Entity {
+                x: 20,
+                y: -24,
+                z: 0,
+                ..
+            }
+        ));
+        assert!(matches!(msgs[2], ServerMsg::Chat { .. }));
+        assert!(matches!(
+            msgs[3],
+            ServerMsg::Mount {
+                x: 0,
+                y: 8,
+                z: 0,
+                ..
+            }
+        ));
+    }
+}
@@ -0,0 +1,256 @@
+//! Optional crash reporting: panics go to Sentry (`TELEBOXEL_SENTRY_DSN`) or
+//! a generic JSON webhook (`TELEBOXEL_CRASH_WEBHOOK`) on top of stderr.
+//!
+//! Connection and world tasks run inside `scope`, and keep their `Context`
+//! current with `update`. The panic hook reads it, so reports say which
+//! player, room and message were involved.
+
+use crate::{config::CrashTarget, http, telemetry::random_u64};
+use serde_json::{Value, json};
+use std::{
+    backtrace::Backtrace,
+    cell::RefCell,
+    panic::PanicHookInfo,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::mpsc;
+
+tokio::task_local! {
+    static CONTEXT: RefCell<Context>;
+}
+
+/// What a task was doing, for its panic reports.
+#[derive(Clone, Default, Debug, PartialEq, Eq)]
+pub struct Context {
+    /// `connection` or `world`
+    pub task: &'static str,
+    /// Room name, `main` for the main world.
+    pub room: String,
+    pub player_id: Option<u32>,
+    /// Last command a connection read, or message a world handled.
+    pub last_message: Option<&'static str>,
+}
+
+impl Context {
+    pub fn new(task: &'static str, room: impl Into<String>) -> Self {
+        Self {
+            task,
+            room: room.into(),
+            ..Default::default()
+        }
+    }
+}
+
+/// Runs `fut` with `context` as its crash context.
+pub async fn scope<F: Future>(context: Context, fut: F) -> F::Output {
+    CONTEXT.scope(RefCell::new(context), fut).await
+}
+
+/// Changes the current task's crash context, if it has one.
+pub fn update(f: impl FnOnce(&mut Context)) {
+    CONTEXT
+        .try_with(|c| {
+            if let Ok(mut c) = c.try_borrow_mut() {
+                f(&mut c);
+            }
+        })
+        .ok();
+}
+
+fn current() -> Option<Context> {
+    CONTEXT
+        .try_with(|c| c.try_borrow().ok().map(|c| c.clone()))
+        .ok()
+        .flatten()
+}
+
+pub struct Report {
+    pub message: String,
+    pub location: Option<String>,
+    pub backtrace: String,
+    /// `None` for panics outside a scoped task, e.g. chunk workers.
+    pub context: Option<Context>,
+    pub time: SystemTime,
+}
+
+impl Report {
+    fn new(info: &PanicHookInfo) -> Self {
+        Self {
+            message: info.payload_as_str().unwrap_or("Box<dyn Any>").to_string(),
+            location: info.location().map(|l| l.to_string()),
+            backtrace: Backtrace::force_capture().to_string(),
+            context: current(),
+            time: SystemTime::now(),
+        }
+    }
+}
+
+enum Sink {
+    /// Store endpoint URL and `X-Sentry-Auth` header.
+    Sentry(String, String),
+    Webhook(String),
+}
+
+impl Sink {
+    fn new(target: CrashTarget) -> Result<Self, String> {
+        match target {
+            CrashTarget::Sentry(dsn) => {
+                let (url, auth) = sentry_store(&dsn)?;
+                Ok(Sink::Sentry(url, auth))
+            }
+            CrashTarget::Webhook(url) => Ok(Sink::Webhook(url)),
+        }
+    }
+}
+
+/// Installs the panic hook (keeping the default stderr output) and starts
+/// the task sending reports.
+pub fn install(target: CrashTarget) -> Result<(), String> {
+    let sink = Sink::new(target)?;
+    let (tx, rx) = mpsc::unbounded_channel();
+    let default = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        default(info);
+        tx.send(Report::new(info)).ok();
+    }));
+    tokio::spawn(send_reports(sink, rx));
+    Ok(())
+}
+
+async fn send_reports(sink: Sink, mut rx: mpsc::UnboundedReceiver<Report>) {
+    while let Some(report) = rx.recv().await {
+        let result = match &sink {
+            Sink::Sentry(url, auth) => {
+                let headers = [
+                    ("content-type", "application/json"),
+                    ("x-sentry-auth", auth.as_str()),
+                ];
+                http::post(url, &headers, sentry_event(&report).to_string()).await
+            }
+            Sink::Webhook(url) => {
+                let headers = [("content-type", "application/json")];
+                http::post(url, &headers, webhook_body(&report).to_string()).await
+            }
+        };
+        if let Err(e) = result {
+            eprintln!("Crash report failed: {e}");
+        }
+    }
+}
+
+// `http[s]://<key>@<host>[/<path>]/<project>` to the store endpoint URL
+// and auth header
+fn sentry_store(dsn: &str) -> Result<(String, String), String> {
+    let invalid = || format!("invalid Sentry DSN {dsn:?}");
+    let (scheme, rest) = dsn.split_once("://").ok_or_else(invalid)?;
+    if scheme != "http" && scheme != "https" {
+        return Err(invalid());
+    }
+    let (key, rest) = rest.split_once('@').ok_or_else(invalid)?;
+    // Secret keys are deprecated, only the public one is sent
+    let key = key.split(':').next().unwrap_or(key);
+    let (host, project) = rest.rsplit_once('/').ok_or_else(invalid)?;
+    if key.is_empty() || host.is_empty() || project.is_empty() {
+        return Err(invalid());
+    }
+
+    let url = format!("{scheme}://{host}/api/{project}/store/");
+    let auth = format!(
+        "Sentry sentry_version=7, sentry_key={key}, sentry_client=teleboxel/{}",
+        env!("CARGO_PKG_VERSION")
+    );
+    Ok((url, auth))
+}
+
+fn unix_secs(t: SystemTime) -> f64 {
+    t.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64()
+}
+
+fn sentry_event(report: &Report) -> Value {
+    let context = report.context.clone().unwrap_or_default();
+    let mut tags = json!({});
+    if !context.task.is_empty() {
+        tags["task"] = context.task.into();
+        tags["room"] = context.room.into();
+    }
+    if let Some(id) = context.player_id {
+        tags["player_id"] = id.to_string().into();
+    }
+
+    json!({
+        "event_id": format!("{:016x}{:016x}", random_u64(), random_u64()),
+        "timestamp": unix_secs(report.time),
+        "platform": "native",
+        "level": "fatal",
+        "logger": "teleboxel",
+        "release": concat!("teleboxel@", env!("CARGO_PKG_VERSION")),
+        "exception": { "values": [{
+            "type": "panic",
+            "value": report.message,
+            "mechanism": { "type": "panic", "handled": false },
+        }] },
+        "tags": tags,
+        "extra": {
+            "location": report.location,
+            "last_message": context.last_message,
+            "backtrace": report.backtrace,
+        },
+    })
+}
+
+fn webhook_body(report: &Report) -> Value {
+    let context = report.context.as_ref();
+    json!({
+        "event": "panic",
+        "message": report.message,
+        "location": report.location,
+        "task": context.map(|c| c.task),
+        "room": context.map(|c| c.room.as_str()),
+        "player_id": context.and_then(|c| c.player_id),
+        "last_message": context.and_then(|c| c.last_message),
+        "backtrace": report.backtrace,
+        "version": env!("CARGO_PKG_VERSION"),
+        "timestamp": unix_secs(report.time),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn reports_carry_the_task_context() {
+        let context = scope(Context::new("connection", "arena"), async {
+            update(|c| c.player_id = Some(7));
+            update(|c| c.last_message = Some("SetBlock"));
+            current()
+        })
+        .await;
+        assert_eq!(current(), None);
+
+        let report = Report {
+            message: "boom".into(),
+            location: Some("src/main.rs:1:1".into()),
+            backtrace: String::new(),
+            context,
+            time: SystemTime::now(),
+        };
+        let event = sentry_event(&report);
+        assert_eq!(
+            event["tags"],
+            json!({ "task": "connection", "room": "arena", "player_id": "7" })
+        );
+        assert_eq!(event["extra"]["last_message"], "SetBlock");
+        assert_eq!(event["exception"]["values"][0]["value"], "boom");
+        assert_eq!(webhook_body(&report)["player_id"], 7);
+
+        let (url, auth) = sentry_store("http://abc@localhost:3001/sentry/42").unwrap();
+        assert_eq!(url, "http://localhost:3001/sentry/api/42/store/");
+        assert!(auth.contains("sentry_key=abc"));
+        let (url, _) = sentry_store("https://abc@o1.ingest.sentry.io/42").unwrap();
+        assert_eq!(url, "https://o1.ingest.sentry.io/api/42/store/");
+        assert!(sentry_store("ftp://abc@o1.ingest.sentry.io/42").is_err());
+    }
+}
@@ -0,0 +1,77 @@
+//! Drain mode for rolling deploys: new connections are refused, connected
+//! players get a `DRAIN` message (countdown and replacement address), and
+//! the server shuts down once everyone has left or the deadline passes.
+
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use tokio::sync::Notify;
+
+const CHECK_EVERY: Duration = Duration::from_millis(500);
+
+#[derive(Default)]
+pub struct Drain {
+    deadline: Mutex<Option<Instant>>,
+    started: Notify,
+}
+
+impl Drain {
+    /// Starts draining with `timeout` to go. `false` if already draining.
+    pub fn start(&self, timeout: Duration) -> bool {
+        let mut deadline = self.deadline.lock().unwrap();
+        if deadline.is_some() {
+            return false;
+        }
+        *deadline = Some(Instant::now() + timeout);
+        // Stores a permit if `finished` isn't waiting yet
+        self.started.notify_one();
+        true
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.deadline.lock().unwrap().is_some()
+    }
+
+    /// Resolves once draining has started and `players` is zero, or the
+    /// deadline passed. Pending forever if draining never starts.
+    pub async fn finished(&self, players: impl Fn() -> usize) {
+        self.started.notified().await;
+        let deadline = self.deadline.lock().unwrap().unwrap();
+        while players() > 0 && Instant::now() < deadline {
+            tokio::time::sleep(CHECK_EVERY).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[tokio::test]
+    async fn finishes_when_empty_or_at_the_deadline() {
+        let drain = Drain::default();
+        assert!(!drain.is_draining());
+        assert!(drain.start(Duration::from_secs(5)));
+        assert!(!drain.start(Duration::from_secs(1)));
+        assert!(drain.is_draining());
+
+        // Everyone leaves soon after
+        let players = AtomicUsize::new(2);
+        let started = Instant::now();
+        let leave = async {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            players.store(0, Ordering::Relaxed);
+        };
+        tokio::join!(drain.finished(|| players.load(Ordering::Relaxed)), leave);
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        // Nobody leaves
+        let drain = Drain::default();
+        drain.start(Duration::from_millis(300));
+        let started = Instant::now();
+        drain.finished(|| 1).await;
+        assert!(started.elapsed() >= Duration::from_millis(300));
+    }
+}
@@ -0,0 +1,57 @@
+//! Effect events: transient visuals and sounds (an explosion, a chime) at a
+//! block position, emitted by server logic or over the admin API
+//! (`POST /admin/effect`). They aren't entities and aren't kept: each goes
+//! out once as `EFFECT`, only to players whose interest holds the
+//! position's chunk. Players who aren't looking there miss it.
+//!
+//! What the effect id and params mean is up to the game, the server passes
+//! them on. Params are at most `MAX_PARAMS_LEN` bytes.
+
+use crate::{
+    chunk::{self, ChunkPos},
+    claims::BlockPos,
+};
+
+/// Longest effect params, in bytes.
+pub const MAX_PARAMS_LEN: usize = 256;
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Effect {
+    pub id: u16,
+    pub position: BlockPos,
+    pub params: Vec<u8>,
+    /// Only players in this layer see it, see `relevance.rs`.
+    pub layer: u32,
+}
+
+/// Whether a player with `interest` (center chunk and radius) sees an
+/// effect at `position`.
+pub fn in_view(interest: Option<(ChunkPos, u16)>, position: BlockPos) -> bool {
+    let Some((center, radius)) = interest else {
+        return false;
+    };
+    let (x, y, z) = position;
+    let at = [chunk::split(x).0, chunk::split(y).0, chunk::split(z).0];
+    let center = [center.0, center.1, center.2];
+    at.iter()
+        .zip(center)
+        .all(|(a, c)| (a - c).abs() <= radius as i32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::CHUNK_SIZE;
+
+    #[test]
+    fn seen_inside_the_interest_only() {
+        let size = CHUNK_SIZE as i32;
+        let interest = Some(((0, 0, 0), 1));
+        assert!(in_view(interest, (0, 0, 0)));
+        assert!(in_view(interest, (-size, 2 * size - 1, 5)));
+        assert!(!in_view(interest, (2 * size, 0, 0)));
+        assert!(!in_view(interest, (0, -size - 1, 0)));
+        // No SetInterest yet
+        assert!(!in_view(None, (0, 0, 0)));
+    }
+}
@@ -0,0 +1,546 @@
+//! Non-player entities in a world or room, e.g. a vehicle: a position, and
+//! maybe one player with authority over it. The server spawns and removes
+//! them and grants, transfers or revokes authority (`/admin/entities`). Only
+//! the player with authority moves one, with `MoveEntity`; moves from anyone
+//! else are refused. Authority goes back to the server when that player
+//! leaves the world or room.
+//!
+//! Players within `MOUNT_RANGE` blocks of one can ride it, with `Mount`:
+//! from then on they're carried along at the offset they got on at, and
+//! their own moves are ignored until `Dismount`, the entity's removal or a
+//! teleport. Riders don't go through portals.
+//!
+//! The server can also attach an entity to another entity or a player at an
+//! offset, e.g. a turret on a vehicle or a held item (`/admin/entities/<id>/parent`).
+//! Attached entities follow their parent down the tree, riders included, and
+//! can't be moved on their own until detached; they're detached where they
+//! are when the parent is removed or leaves.
+//!
+//! Everyone in the world gets an `ENTITY` message when one spawns, moves or
+//! changes hands, `ENTITY_GONE` when one is removed, `MOUNT` and `DISMOUNT`
+//! when players get on and off and `ATTACH` and `DETACH` for attachments
+//! (all of them on joining). Static entities (props, signs) are the
+//! exception: players get their `ENTITY` once the entity is in their
+//! interest, and again only when it changed. Which ones are in an interest
+//! comes from the world's spatial index (`spatial.rs`). Objectives, bosses
+//! and markers can be made always relevant, sent to everyone wherever they
+//! are, or relevant to some players only (forced per player, for as long
+//! as they're in the world). Clients place children from their parent, so
+//! moving a parent entity sends only its own `ENTITY`; player positions
+//! aren't sent, so entities attached to a player get theirs.
+//! The server walks entities it has authority over to a goal, see
+//! `brains.rs`. Entities aren't saved, they last until the room closes or
+//! the server restarts.
+
+use crate::{
+    claims::BlockPos,
+    spatial::{SpatialIndex, SpatialKind},
+};
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+};
+
+/// How far from an entity players can get on it, in blocks.
+pub const MOUNT_RANGE: u32 = 4;
+
+/// What the server does with a world's entities.
+#[derive(Debug)]
+pub enum EntityOp {
+    List,
+    /// Static (`true`) for props and signs, in a layer.
+    Spawn(BlockPos, bool, u32),
+    Remove(u32),
+    /// Authority over an entity to a player id, `None` back to the server.
+    Grant(u32, Option<u32>),
+    /// `None` detaches.
+    Attach(u32, Option<Attachment>),
+    /// Walks it there while the server has authority, see `brains.rs`.
+    /// `None` stops it.
+    Goal(u32, Option<BlockPos>),
+    /// A static entity to every player, interest or not (`true`).
+    Always(u32, bool),
+    /// A static entity to player `.1`, interest or not (`true`).
+    Relevant(u32, u32, bool),
+    /// Moves it to another layer, see `relevance.rs`.
+    Layer(u32, u32),
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Entity {
+    pub id: u32,
+    pub position: BlockPos,
+    /// The player id moving it, `None` for the server.
+    pub authority: Option<u32>,
+    pub attached: Option<Attachment>,
+    /// Props, signs: sent to each player once it's in their interest, and
+    /// again only when it changed.
+    #[serde(rename = "static")]
+    pub fixed: bool,
+    /// Static, but sent to every player wherever it is.
+    pub always: bool,
+    /// Only players in the same layer get it, see `relevance.rs`.
+    pub layer: u32,
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Parent {
+    Entity(u32),
+    Player(u32),
+}
+
+/// Where an entity hangs, `offset` blocks from its parent.
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Attachment {
+    pub parent: Parent,
+    pub offset: BlockPos,
+}
+
+/// A player riding an entity, `offset` blocks from it.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Mount {
+    pub player: u32,
+    pub entity: u32,
+    pub offset: BlockPos,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(untagged)]
+pub enum EntityReply {
+    List(Vec<Entity>),
+    One(Entity),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum EntityError {
+    NotFound(u32),
+    /// Granting to a player who isn't in the world.
+    NoPlayer(u32),
+    NotAuthority(u32),
+    TooFar(u32),
+    Mounted,
+    NotMounted,
+    /// Moved while attached, it follows its parent.
+    Attached(u32),
+    NotAttached(u32),
+    /// Attaching to itself or one of its children.
+    Cycle(u32),
+    /// Spawning past the world's entity quota, see `quotas.rs`.
+    Quota,
+}
+
+impl fmt::Display for EntityError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EntityError::NotFound(id) => write!(f, "no entity {id}"),
+            EntityError::NoPlayer(id) => write!(f, "no player {id} here"),
+            EntityError::NotAuthority(id) => write!(f, "no authority over entity {id}"),
+            EntityError::TooFar(id) => {
+                write!(f, "entity {id} is more than {MOUNT_RANGE} blocks away")
+            }
+            EntityError::Mounted => write!(f, "already mounted, Dismount first"),
+            EntityError::NotMounted => write!(f, "not mounted"),
+            EntityError::Attached(id) => {
+                write!(f, "entity {id} is attached, it follows its parent")
+            }
+            EntityError::NotAttached(id) => write!(f, "entity {id} isn't attached"),
+            EntityError::Cycle(id) => write!(f, "entity {id} can't hang from itself"),
+            EntityError::Quota => write!(f, "entity quota reached"),
+        }
+    }
+}
+
+impl std::error::Error for EntityError {}
+
+/// One world's entities, kept by its task.
+pub struct Entities {
+    next_id: u32,
+    entities: BTreeMap<u32, Entity>,
+    // By player
+    mounts: BTreeMap<u32, Mount>,
+    // Positions, kept in step with `entities`
+    index: Box<dyn SpatialIndex>,
+    // Ids of the `always` ones
+    always: BTreeSet<u32>,
+}
+
+impl Default for Entities {
+    fn default() -> Self {
+        Self::new(SpatialKind::default())
+    }
+}
+
+impl Entities {
+    pub fn new(index: SpatialKind) -> Self {
+        Self {
+            next_id: 0,
+            entities: BTreeMap::new(),
+            mounts: BTreeMap::new(),
+            index: index.build(),
+            always: BTreeSet::new(),
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Entity> {
+        self.entities.values()
+    }
+
+    /// With the server's authority. Ids start at 1.
+    pub fn spawn(&mut self, position: BlockPos, fixed: bool) -> Entity {
+        self.next_id += 1;
+        let entity = Entity {
+            id: self.next_id,
+            position,
+            authority: None,
+            attached: None,
+            fixed,
+            always: false,
+            layer: 0,
+        };
+        self.entities.insert(entity.id, entity);
+        self.index.insert(entity.id, position);
+        entity
+    }
+
+    pub fn remove(&mut self, id: u32) -> Result<Entity, EntityError> {
+        let entity = self.entities.remove(&id).ok_or(EntityError::NotFound(id))?;
+        self.index.remove(id);
+        self.always.remove(&id);
+        Ok(entity)
+    }
+
+    /// Gives player `to` authority over `id`, taking it from whoever had
+    /// it. The caller checks `to` is in the world.
+    pub fn grant(&mut self, id: u32, to: Option<u32>) -> Result<Entity, EntityError> {
+        let entity = self
+            .entities
+            .get_mut(&id)
+            .ok_or(EntityError::NotFound(id))?;
+        entity.authority = to;
+        Ok(*entity)
+    }
+
+    /// A move from `player`, or the server for `None`, who must have
+    /// authority over `id`.
+    pub fn move_by(
+        &mut self,
+        player: Option<u32>,
+        id: u32,
+        position: BlockPos,
+    ) -> Result<Entity, EntityError> {
+        let entity = self
+            .entities
+            .get_mut(&id)
+            .ok_or(EntityError::NotFound(id))?;
+        if entity.authority != player {
+            return Err(EntityError::NotAuthority(id));
+        }
+        if entity.attached.is_some() {
+            return Err(EntityError::Attached(id));
+        }
+        entity.position = position;
+        self.index.insert(id, position);
+        Ok(*entity)
+    }
+
+    /// Hands what `player` had authority over back to the server, when they
+    /// leave. The entities that changed.
+    pub fn release(&mut self, player: u32) -> Vec<Entity> {
+        let held = self
+            .entities
+            .values_mut()
+            .filter(|e| e.authority == Some(player));
+        held.map(|e| {
+            e.authority = None;
+            *e
+        })
+        .collect()
+    }
+
+    /// Hangs `id` from a parent. The caller checks a player parent is in
+    /// the world, and places it with `place_children`.
+    pub fn attach(&mut self, id: u32, attachment: Attachment) -> Result<Entity, EntityError> {
+        if !self.entities.contains_key(&id) {
+            return Err(EntityError::NotFound(id));
+        }
+        // Up from the new parent, which mustn't lead back here
+        let mut up = attachment.parent;
+        while let Parent::Entity(above) = up {
+            if above == id {
+                return Err(EntityError::Cycle(id));
+            }
+            let parent = self
+                .entities
+                .get(&above)
+                .ok_or(EntityError::NotFound(above))?;
+            match parent.attached {
+                Some(a) => up = a.parent,
+                None => break,
+            }
+        }
+        let entity = self.entities.get_mut(&id).unwrap();
+        entity.attached = Some(attachment);
+        Ok(*entity)
+    }
+
+    pub fn set_always(&mut self, id: u32, always: bool) -> Result<Entity, EntityError> {
+        let entity = self
+            .entities
+            .get_mut(&id)
+            .ok_or(EntityError::NotFound(id))?;
+        entity.always = always;
+        if always {
+            self.always.insert(id);
+        } else {
+            self.always.remove(&id);
+        }
+        Ok(*entity)
+    }
+
+    pub fn set_layer(&mut self, id: u32, layer: u32) -> Result<Entity, EntityError> {
+        let entity = self
+            .entities
+            .get_mut(&id)
+            .ok_or(EntityError::NotFound(id))?;
+        entity.layer = layer;
+        Ok(*entity)
+    }
+
+    pub fn always(&self) -> impl Iterator<Item = &Entity> {
+        self.pick(&self.always)
+    }
+
+    /// Leaves `id` where it is.
+    pub fn detach(&mut self, id: u32) -> Result<Entity, EntityError> {
+        let entity = self
+            .entities
+            .get_mut(&id)
+            .ok_or(EntityError::NotFound(id))?;
+        if entity.attached.take().is_none() {
+            return Err(EntityError::NotAttached(id));
+        }
+        Ok(*entity)
+    }
+
+    /// Detaches everything hanging from `parent`, which is going away.
+    pub fn detach_from(&mut self, parent: Parent) -> Vec<Entity> {
+        let children = self
+            .entities
+            .values_mut()
+            .filter(|e| e.attached.is_some_and(|a| a.parent == parent));
+        children
+            .map(|e| {
+                e.attached = None;
+                *e
+            })
+            .collect()
+    }
+
+    /// Moves the entities hanging right under `parent`, now `at`. The ones
+    /// that moved, whose own children come next.
+    pub fn place_children(&mut self, parent: Parent, at: BlockPos) -> Vec<Entity> {
+        let children = self.entities.values_mut().filter_map(|e| {
+            let a = e.attached.filter(|a| a.parent == parent)?;
+            e.position = (at.0 + a.offset.0, at.1 + a.offset.1, at.2 + a.offset.2);
+            Some(*e)
+        });
+        let placed: Vec<_> = children.collect();
+        for child in &placed {
+            self.index.insert(child.id, child.position);
+        }
+        placed
+    }
+
+    /// The ones of `ids` that exist.
+    pub fn pick<'a>(
+        &'a self,
+        ids: impl IntoIterator<Item = &'a u32>,
+    ) -> impl Iterator<Item = &'a Entity> {
+        ids.into_iter().filter_map(|id| self.entities.get(id))
+    }
+
+    /// The entities between `lo` and `hi`, both included, by id. Their ids
+    /// go in `ids`, kept for the next call.
+    pub fn within<'a>(
+        &'a self,
+        lo: BlockPos,
+        hi: BlockPos,
+        ids: &'a mut Vec<u32>,
+    ) -> impl Iterator<Item = &'a Entity> {
+        ids.clear();
+        self.index.query(lo, hi, ids);
+        ids.sort_unstable();
+        ids.iter().filter_map(|id| self.entities.get(id))
+    }
+
+    pub fn get(&self, id: u32) -> Option<Entity> {
+        self.entities.get(&id).copied()
+    }
+
+    pub fn position(&self, id: u32) -> Option<BlockPos> {
+        self.entities.get(&id).map(|e| e.position)
+    }
+
+    /// Puts `player`, standing `at`, on entity `id` if it's in range.
+    pub fn mount(&mut self, player: u32, at: BlockPos, id: u32) -> Result<Mount, EntityError> {
+        let entity = self.entities.get(&id).ok_or(EntityError::NotFound(id))?;
+        if self.mounts.contains_key(&player) {
+            return Err(EntityError::Mounted);
+        }
+        let d = |a: i32, b: i32| (a as i64 - b as i64).pow(2);
+        let (x, y, z) = entity.position;
+        if d(at.0, x) + d(at.1, y) + d(at.2, z) > (MOUNT_RANGE as i64).pow(2) {
+            return Err(EntityError::TooFar(id));
+        }
+        let mount = Mount {
+            player,
+            entity: id,
+            offset: (at.0 - x, at.1 - y, at.2 - z),
+        };
+        self.mounts.insert(player, mount);
+        Ok(mount)
+    }
+
+    pub fn dismount(&mut self, player: u32) -> Result<Mount, EntityError> {
+        self.mounts.remove(&player).ok_or(EntityError::NotMounted)
+    }
+
+    pub fn mount_of(&self, player: u32) -> Option<&Mount> {
+        self.mounts.get(&player)
+    }
+
+    pub fn mounts(&self) -> impl Iterator<Item = &Mount> {
+        self.mounts.values()
+    }
+
+    /// Who rides entity `id` and where they are now.
+    pub fn riders(&self, id: u32) -> Vec<(u32, BlockPos)> {
+        let Some(entity) = self.entities.get(&id) else {
+            return Vec::new();
+        };
+        let (x, y, z) = entity.position;
+        let riders = self.mounts.values().filter(|m| m.entity == id);
+        riders
+            .map(|m| (m.player, (x + m.offset.0, y + m.offset.1, z + m.offset.2)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_the_authority_moves_an_entity() {
+        let mut entities = Entities::default();
+        let cart = entities.spawn((0, 40, 0), false).id;
+        assert_eq!(cart, 1);
+
+        // Server authority until granted
+        let refused = Err(EntityError::NotAuthority(cart));
+        assert_eq!(entities.move_by(Some(7), cart, (1, 40, 0)), refused);
+        assert!(entities.move_by(None, cart, (0, 41, 0)).is_ok());
+        entities.grant(cart, Some(7)).unwrap();
+        assert_eq!(entities.move_by(None, cart, (0, 40, 0)), refused);
+        assert_eq!(
+            entities
+                .move_by(Some(7), cart, (1, 40, 0))
+                .unwrap()
+                .position,
+            (1, 40, 0)
+        );
+
+        // Transferred: the old driver is refused
+        entities.grant(cart, Some(8)).unwrap();
+        assert_eq!(entities.move_by(Some(7), cart, (2, 40, 0)), refused);
+        assert!(entities.move_by(Some(8), cart, (2, 40, 0)).is_ok());
+
+        // Leaving hands it back
+        assert_eq!(entities.release(7), vec![]);
+        let released = entities.release(8);
+        assert_eq!(released.len(), 1);
+        assert_eq!(released[0].authority, None);
+        assert_eq!(released[0].position, (2, 40, 0));
+
+        assert!(entities.remove(cart).is_ok());
+        assert_eq!(entities.grant(cart, None), Err(EntityError::NotFound(cart)));
+    }
+
+    #[test]
+    fn riders_move_with_their_mount() {
+        let mut entities = Entities::default();
+        let boat = entities.spawn((10, 40, 10), false).id;
+        let far = (10, 40, 10 + MOUNT_RANGE as i32 + 1);
+        assert_eq!(entities.mount(1, far, boat), Err(EntityError::TooFar(boat)));
+
+        let mount = entities.mount(1, (11, 41, 10), boat).unwrap();
+        assert_eq!(mount.offset, (1, 1, 0));
+        assert_eq!(
+            entities.mount(1, (10, 40, 10), boat),
+            Err(EntityError::Mounted)
+        );
+
+        entities.grant(boat, Some(2)).unwrap();
+        entities.move_by(Some(2), boat, (20, 40, 10)).unwrap();
+        assert_eq!(entities.riders(boat), vec![(1, (21, 41, 10))]);
+
+        assert_eq!(entities.dismount(1), Ok(mount));
+        assert_eq!(entities.dismount(1), Err(EntityError::NotMounted));
+        assert_eq!(entities.riders(boat), vec![]);
+    }
+
+    #[test]
+    fn children_follow_their_parent() {
+        let mut entities = Entities::default();
+        let tank = entities.spawn((0, 40, 0), false).id;
+        let turret = entities.spawn((0, 40, 0), false).id;
+        let on = |parent, offset| Attachment { parent, offset };
+
+        entities
+            .attach(turret, on(Parent::Entity(tank), (0, 2, 0)))
+            .unwrap();
+        let cycle = on(Parent::Entity(turret), (0, 0, 0));
+        assert_eq!(entities.attach(tank, cycle), Err(EntityError::Cycle(tank)));
+
+        let placed = entities.place_children(Parent::Entity(tank), (5, 40, 5));
+        assert_eq!(placed.len(), 1);
+        assert_eq!(entities.position(turret), Some((5, 42, 5)));
+        let mut ids = Vec::new();
+        let moved_to: Vec<_> = entities.within((5, 42, 5), (5, 42, 5), &mut ids).collect();
+        assert_eq!(moved_to, vec![&entities.get(turret).unwrap()]);
+        entities.grant(turret, Some(1)).unwrap();
+        let moved = entities.move_by(Some(1), turret, (9, 9, 9));
+        assert_eq!(moved, Err(EntityError::Attached(turret)));
+
+        let detached = entities.detach_from(Parent::Entity(tank));
+        assert_eq!(detached[0].position, (5, 42, 5));
+        assert_eq!(
+            entities.detach(turret),
+            Err(EntityError::NotAttached(turret))
+        );
+        assert!(entities.move_by(Some(1), turret, (9, 9, 9)).is_ok());
+    }
+
+    #[test]
+    fn always_relevant_ones_are_listed_apart() {
+        let mut entities = Entities::default();
+        let flag = entities.spawn((0, 40, 0), true).id;
+        let prop = entities.spawn((900, 40, 0), true).id;
+        assert_eq!(entities.always().count(), 0);
+
+        assert!(entities.set_always(flag, true).unwrap().always);
+        let always: Vec<_> = entities.always().map(|e| e.id).collect();
+        assert_eq!(always, [flag]);
+        let picked: Vec<_> = entities.pick(&[prop, 99]).map(|e| e.id).collect();
+        assert_eq!(picked, [prop]);
+
+        entities.remove(flag).unwrap();
+        assert_eq!(entities.always().count(), 0);
+        assert_eq!(
+            entities.set_always(flag, false),
+            Err(EntityError::NotFound(flag))
+        );
+    }
+}
@@ -0,0 +1,156 @@
+//! Feature flags per world: chat, block edits and PvP, each on or off in the
+//! main world or a room. Everything is on unless a TOML file
+//! (`TELEBOXEL_FEATURES`) says otherwise, per world (`main` or a room name):
+//!
+//! ```toml
+//! [default]
+//! pvp = false
+//!
+//! [room.lobby]                 # flags left out come from [default]
+//! build = false
+//! ```
+//!
+//! Admins toggle them at runtime with `PUT /admin/features?room=<name>`
+//! (`{"chat": false}`), until the room closes or the server restarts.
+//!
+//! Chat and block edits are checked as they come in, and refused with an
+//! error reply; bridge chat into the world is dropped. The server has no
+//! combat, so `pvp` is for clients to honor. Players get the flags as
+//! `FEATURES` with the block registry, after changing rooms and whenever
+//! they're toggled.
+
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, error::Error, fs, path::Path, sync::Mutex};
+
+/// What's on in one world.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Features {
+    pub chat: bool,
+    pub build: bool,
+    pub pvp: bool,
+}
+
+impl Default for Features {
+    fn default() -> Self {
+        Self {
+            chat: true,
+            build: true,
+            pvp: true,
+        }
+    }
+}
+
+/// Flags to change, a file table or an admin request. Unset ones stay.
+#[derive(Deserialize, Default, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Toggles {
+    pub chat: Option<bool>,
+    pub build: Option<bool>,
+    pub pvp: Option<bool>,
+}
+
+impl Toggles {
+    pub fn over(&self, base: &Features) -> Features {
+        Features {
+            chat: self.chat.unwrap_or(base.chat),
+            build: self.build.unwrap_or(base.build),
+            pvp: self.pvp.unwrap_or(base.pvp),
+        }
+    }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct FeaturesFile {
+    #[serde(default)]
+    default: Toggles,
+    #[serde(default)]
+    room: HashMap<String, Toggles>,
+}
+
+/// Flags by world, as configured and as toggled since.
+#[derive(Default)]
+pub struct FeatureFlags {
+    default: Features,
+    rooms: HashMap<String, Features>,
+    toggled: Mutex<HashMap<String, Features>>,
+}
+
+impl FeatureFlags {
+    /// See the module docs for the file format.
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    fn parse(text: &str) -> Result<Self, Box<dyn Error>> {
+        let file: FeaturesFile = toml::from_str(text)?;
+        let default = file.default.over(&Features::default());
+        let rooms = file
+            .room
+            .iter()
+            .map(|(room, toggles)| (room.clone(), toggles.over(&default)))
+            .collect();
+        Ok(Self {
+            default,
+            rooms,
+            toggled: Mutex::default(),
+        })
+    }
+
+    /// The flags in `room`, `None` for the main world.
+    pub fn get(&self, room: Option<&str>) -> Features {
+        let room = room.unwrap_or("main");
+        match self.toggled.lock().unwrap().get(room) {
+            Some(features) => *features,
+            None => *self.rooms.get(room).unwrap_or(&self.default),
+        }
+    }
+
+    /// Applies `toggles` in `room`, returning the flags now.
+    pub fn toggle(&self, room: Option<&str>, toggles: &Toggles) -> Features {
+        let features = toggles.over(&self.get(room));
+        let room = room.unwrap_or("main").to_string();
+        self.toggled.lock().unwrap().insert(room, features);
+        features
+    }
+
+    /// Back to the configured flags, for a room that closed.
+    pub fn reset(&self, room: &str) {
+        self.toggled.lock().unwrap().remove(room);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn configured_then_toggled_per_world() {
+        let flags = FeatureFlags::parse(
+            r#"
+            [default]
+            pvp = false
+
+            [room.lobby]
+            build = false
+            "#,
+        )
+        .unwrap();
+        let on = |chat, build, pvp| Features { chat, build, pvp };
+        assert_eq!(flags.get(None), on(true, true, false));
+        assert_eq!(flags.get(Some("lobby")), on(true, false, false));
+        assert_eq!(flags.get(Some("arena")), on(true, true, false));
+
+        let mute = Toggles {
+            chat: Some(false),
+            ..Toggles::default()
+        };
+        assert_eq!(flags.toggle(Some("lobby"), &mute), on(false, false, false));
+        assert_eq!(flags.get(Some("lobby")), on(false, false, false));
+        assert_eq!(flags.get(None), on(true, true, false));
+        flags.reset("lobby");
+        assert_eq!(flags.get(Some("lobby")), on(true, false, false));
+
+        assert!(FeatureFlags::parse("[default]\nfly = true").is_err());
+    }
+}
@@ -0,0 +1,334 @@
+//! Flood control: per-player cooldowns on chat, block edits, the other text
+//! commands and relay room messages, on top of whatever limits the network has. Each kind of
+//! action allows a `burst` in a row, then one more every `every_ms`. Going
+//! over is a strike: the action is refused with a warning, at `mute_at`
+//! strikes that kind of action is muted for `mute_secs`, and at `kick_at`
+//! the player is kicked. Strikes are forgiven after `forgive_secs` without
+//! a new one. Movement, interest updates and lockstep inputs aren't limited.
+//!
+//! Built-in limits apply everywhere unless a TOML file (`TELEBOXEL_FLOOD`)
+//! sets others, per world (`main` or a room name):
+//!
+//! ```toml
+//! [default]
+//! chat = { burst = 5, every_ms = 1000 }
+//! edit = { burst = 50, every_ms = 20 }      # every_ms = 0 for no limit
+//! command = { burst = 10, every_ms = 250 }
+//! relay = { burst = 60, every_ms = 10 }
+//! penalties = { mute_at = 3, mute_secs = 30, kick_at = 6, forgive_secs = 60 }
+//!
+//! [room.arena]                               # tables left out come from [default]
+//! chat = { burst = 2, every_ms = 3000 }
+//! penalties = { kick_at = 0 }                # 0 never kicks, mute_at = 0 never mutes
+//! ```
+//!
+//! Strikes and mutes belong to the connection, so they follow players
+//! between rooms.
+
+use crate::command::Command;
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt, fs,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Action {
+    Chat,
+    Edit,
+    Command,
+    /// `RELAY_SEND`, see `relay.rs`.
+    Relay,
+}
+
+impl Action {
+    /// The kind of action `cmd` is, `None` when it isn't limited.
+    pub fn of(cmd: &Command) -> Option<Self> {
+        match cmd {
+            Command::SetInterest { .. }
+            | Command::SetPosition { .. }
+            | Command::SetTransform { .. }
+            | Command::Input { .. } => None,
+            Command::Say { .. } => Some(Action::Chat),
+            Command::SetBlock { .. } => Some(Action::Edit),
+            _ => Some(Action::Command),
+        }
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Action::Chat => "chat",
+            Action::Edit => "block edits",
+            Action::Command => "commands",
+            Action::Relay => "relay messages",
+        })
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Limit {
+    pub burst: u32,
+    /// `0` for no limit.
+    pub every_ms: u64,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Penalties {
+    /// `0` never mutes, the same for `kick_at`.
+    pub mute_at: u32,
+    pub mute_secs: u64,
+    pub kick_at: u32,
+    pub forgive_secs: u64,
+}
+
+impl Default for Penalties {
+    fn default() -> Self {
+        Self {
+            mute_at: 3,
+            mute_secs: 30,
+            kick_at: 6,
+            forgive_secs: 60,
+        }
+    }
+}
+
+/// The limits in one world.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Rules {
+    pub chat: Limit,
+    pub edit: Limit,
+    pub command: Limit,
+    pub relay: Limit,
+    pub penalties: Penalties,
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        Self {
+            chat: Limit {
+                burst: 5,
+                every_ms: 1000,
+            },
+            edit: Limit {
+                burst: 50,
+                every_ms: 20,
+            },
+            command: Limit {
+                burst: 10,
+                every_ms: 250,
+            },
+            relay: Limit {
+                burst: 60,
+                every_ms: 10,
+            },
+            penalties: Penalties::default(),
+        }
+    }
+}
+
+impl Rules {
+    fn limit(&self, action: Action) -> Limit {
+        match action {
+            Action::Chat => self.chat,
+            Action::Edit => self.edit,
+            Action::Command => self.command,
+            Action::Relay => self.relay,
+        }
+    }
+}
+
+// A `[default]` or `[room.<name>]` table, unset entries inherited
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct Overrides {
+    chat: Option<Limit>,
+    edit: Option<Limit>,
+    command: Option<Limit>,
+    relay: Option<Limit>,
+    penalties: Option<Penalties>,
+}
+
+impl Overrides {
+    fn over(&self, base: &Rules) -> Rules {
+        Rules {
+            chat: self.chat.unwrap_or(base.chat),
+            edit: self.edit.unwrap_or(base.edit),
+            command: self.command.unwrap_or(base.command),
+            relay: self.relay.unwrap_or(base.relay),
+            penalties: self.penalties.unwrap_or(base.penalties),
+        }
+    }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct FloodFile {
+    #[serde(default)]
+    default: Overrides,
+    #[serde(default)]
+    room: HashMap<String, Overrides>,
+}
+
+/// Limits by world.
+#[derive(Default)]
+pub struct Flood {
+    default: Rules,
+    rooms: HashMap<String, Rules>,
+}
+
+impl Flood {
+    /// See the module docs for the file format.
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    fn parse(text: &str) -> Result<Self, Box<dyn Error>> {
+        let file: FloodFile = toml::from_str(text)?;
+        let default = file.default.over(&Rules::default());
+        let rooms = file
+            .room
+            .iter()
+            .map(|(room, overrides)| (room.clone(), overrides.over(&default)))
+            .collect();
+        Ok(Self { default, rooms })
+    }
+
+    /// The rules in `room`, `None` for the main world.
+    pub fn rules(&self, room: Option<&str>) -> &Rules {
+        self.rooms
+            .get(room.unwrap_or("main"))
+            .unwrap_or(&self.default)
+    }
+}
+
+/// What happens to an action.
+#[derive(PartialEq, Eq, Debug)]
+pub enum Verdict {
+    Allow,
+    /// Over the limit, refused.
+    Warn,
+    /// Refused, and muted from now on for this long.
+    Mute(Duration),
+    /// Still muted for this long, refused.
+    Muted(Duration),
+    Kick,
+}
+
+/// One connection's cooldowns and strikes.
+pub struct FloodGuard {
+    // When each kind of action is next free, indexed by `Action`
+    next: [Instant; 4],
+    muted: [Option<Instant>; 4],
+    strikes: u32,
+    last_strike: Instant,
+}
+
+impl FloodGuard {
+    pub fn new(now: Instant) -> Self {
+        Self {
+            next: [now; 4],
+            muted: [None; 4],
+            strikes: 0,
+            last_strike: now,
+        }
+    }
+
+    pub fn check(&mut self, rules: &Rules, action: Action, now: Instant) -> Verdict {
+        let i = action as usize;
+        let penalties = rules.penalties;
+        if now.duration_since(self.last_strike) >= Duration::from_secs(penalties.forgive_secs) {
+            self.strikes = 0;
+        }
+        if let Some(until) = self.muted[i] {
+            if now < until {
+                return Verdict::Muted(until - now);
+            }
+            self.muted[i] = None;
+        }
+
+        // A burst in a row, then one every `every_ms`
+        let limit = rules.limit(action);
+        let every = Duration::from_millis(limit.every_ms);
+        let next = self.next[i].max(now);
+        if limit.every_ms == 0 || next <= now + every * limit.burst.saturating_sub(1) {
+            self.next[i] = next + every;
+            return Verdict::Allow;
+        }
+
+        self.strikes += 1;
+        self.last_strike = now;
+        let reached = |at: u32| at > 0 && self.strikes >= at;
+        if reached(penalties.kick_at) {
+            Verdict::Kick
+        } else if reached(penalties.mute_at) {
+            let muted = Duration::from_secs(penalties.mute_secs);
+            self.muted[i] = Some(now + muted);
+            Verdict::Mute(muted)
+        } else {
+            Verdict::Warn
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escalates_from_warnings_to_a_kick() {
+        let flood = Flood::parse(
+            r#"
+            [default]
+            chat = { burst = 2, every_ms = 1000 }
+            penalties = { mute_at = 2, mute_secs = 10, kick_at = 3 }
+
+            [room.arena]
+            chat = { burst = 1, every_ms = 0 }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(flood.rules(Some("arena")).chat.every_ms, 0);
+        assert_eq!(flood.rules(Some("arena")).penalties.kick_at, 3);
+        assert_eq!(flood.rules(Some("lobby")).edit, Rules::default().edit);
+        let rules = flood.rules(None);
+
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut guard = FloodGuard::new(start);
+        assert_eq!(guard.check(rules, Action::Chat, at(0)), Verdict::Allow);
+        assert_eq!(guard.check(rules, Action::Chat, at(0)), Verdict::Allow);
+        assert_eq!(guard.check(rules, Action::Chat, at(0)), Verdict::Warn);
+        // Other kinds of actions have their own cooldowns
+        assert_eq!(guard.check(rules, Action::Edit, at(0)), Verdict::Allow);
+        assert_eq!(guard.check(rules, Action::Relay, at(0)), Verdict::Allow);
+        assert_eq!(guard.check(rules, Action::Chat, at(1000)), Verdict::Allow);
+        assert_eq!(
+            guard.check(rules, Action::Chat, at(1000)),
+            Verdict::Mute(Duration::from_secs(10))
+        );
+        assert_eq!(
+            guard.check(rules, Action::Chat, at(6000)),
+            Verdict::Muted(Duration::from_secs(5))
+        );
+        assert_eq!(guard.check(rules, Action::Chat, at(11000)), Verdict::Allow);
+        assert_eq!(guard.check(rules, Action::Chat, at(11000)), Verdict::Allow);
+        assert_eq!(guard.check(rules, Action::Chat, at(11000)), Verdict::Kick);
+
+        // Forgiven after a quiet minute
+        let mut guard = FloodGuard::new(start);
+        for _ in 0..2 {
+            guard.check(rules, Action::Chat, at(0));
+        }
+        assert_eq!(guard.check(rules, Action::Chat, at(0)), Verdict::Warn);
+        assert_eq!(guard.check(rules, Action::Chat, at(61000)), Verdict::Allow);
+        assert_eq!(guard.check(rules, Action::Chat, at(61000)), Verdict::Allow);
+        assert_eq!(guard.check(rules, Action::Chat, at(61000)), Verdict::Warn);
+    }
+}
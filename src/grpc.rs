@@ -0,0 +1,507 @@
+//! The gRPC admin service, `teleboxel.admin.Admin` from
+//! `schema/admin.proto`, served next to `/admin` on the listeners with the
+//! `admin` route. Same tokens as the HTTP API, in `authorization` metadata
+//! (`Bearer <token>`): `Kick` and `WorldState` take the moderator token and
+//! tenant keys too, `Events` the admin token alone. Calls are audited like
+//! HTTP ones.
+//!
+//! Plaintext listeners speak HTTP/2 without TLS (prior knowledge), TLS ones
+//! offer `h2` when they serve `admin`. The messages are written out here
+//! rather than generated, there's no `protoc` in the build.
+
+use crate::{
+    admin::{AdminState, BoxFuture, Tenant},
+    audit::AuditEvent,
+    command,
+    roles::Role,
+    tenants,
+    webhooks::WebhookEvent,
+};
+use axum::{
+    Router,
+    extract::{Path, Request, State},
+    middleware,
+    response::{IntoResponse, Response},
+    routing::post,
+};
+use futures_util::Stream;
+use std::pin::Pin;
+use tokio::sync::broadcast;
+use tonic::{
+    Status,
+    server::{Grpc, ServerStreamingService, UnaryService},
+};
+use tonic_prost::ProstCodec;
+
+pub const SERVICE: &str = "teleboxel.admin.Admin";
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct EventsRequest {}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Event {
+    #[prost(string, tag = "1")]
+    pub event: String,
+    #[prost(string, tag = "2")]
+    pub room: String,
+    #[prost(uint32, optional, tag = "3")]
+    pub id: Option<u32>,
+    #[prost(string, optional, tag = "4")]
+    pub name: Option<String>,
+    #[prost(string, optional, tag = "5")]
+    pub trigger: Option<String>,
+    #[prost(uint64, optional, tag = "6")]
+    pub skipped: Option<u64>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct KickRequest {
+    #[prost(string, tag = "1")]
+    pub room: String,
+    #[prost(uint32, tag = "2")]
+    pub id: u32,
+    #[prost(string, tag = "3")]
+    pub reason: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct KickReply {
+    #[prost(bool, tag = "1")]
+    pub kicked: bool,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct WorldStateRequest {
+    #[prost(string, tag = "1")]
+    pub room: String,
+}
+
+#[derive(Clone, Copy, PartialEq, prost::Message)]
+pub struct Vec3 {
+    #[prost(sint32, tag = "1")]
+    pub x: i32,
+    #[prost(sint32, tag = "2")]
+    pub y: i32,
+    #[prost(sint32, tag = "3")]
+    pub z: i32,
+}
+
+#[derive(Clone, Copy, PartialEq, prost::Message)]
+pub struct Interest {
+    #[prost(message, optional, tag = "1")]
+    pub center: Option<Vec3>,
+    #[prost(uint32, tag = "2")]
+    pub radius: u32,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct PlayerState {
+    #[prost(uint32, tag = "1")]
+    pub id: u32,
+    #[prost(string, optional, tag = "2")]
+    pub name: Option<String>,
+    #[prost(message, optional, tag = "3")]
+    pub position: Option<Vec3>,
+    #[prost(message, optional, tag = "4")]
+    pub interest: Option<Interest>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct WorldStateReply {
+    #[prost(uint64, tag = "1")]
+    pub tick: u64,
+    #[prost(uint64, tag = "2")]
+    pub loaded_chunks: u64,
+    #[prost(message, repeated, tag = "3")]
+    pub players: Vec<PlayerState>,
+}
+
+impl From<(i32, i32, i32)> for Vec3 {
+    fn from((x, y, z): (i32, i32, i32)) -> Self {
+        Self { x, y, z }
+    }
+}
+
+type Reply<T> = Result<tonic::Response<T>, Status>;
+type EventStream = Pin<Box<dyn Stream<Item = Result<Event, Status>> + Send>>;
+
+/// The `Admin` service, at `/teleboxel.admin.Admin/<method>`.
+pub fn router(state: AdminState) -> Router {
+    Router::new()
+        .route(&format!("/{SERVICE}/{{method}}"), post(call))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            crate::admin::require_token,
+        ))
+        .with_state(state)
+}
+
+async fn call(
+    State(state): State<AdminState>,
+    Path(method): Path<String>,
+    req: Request,
+) -> Response {
+    match method.as_str() {
+        "Events" => {
+            let mut grpc = Grpc::new(ProstCodec::default());
+            grpc.server_streaming(Events(state), req)
+                .await
+                .into_response()
+        }
+        "Kick" => {
+            let mut grpc = Grpc::new(ProstCodec::default());
+            grpc.unary(Unary(state, kick), req).await.into_response()
+        }
+        "WorldState" => {
+            let mut grpc = Grpc::new(ProstCodec::default());
+            grpc.unary(Unary(state, world_state), req)
+                .await
+                .into_response()
+        }
+        _ => Status::unimplemented(format!("No method {method}"))
+            .into_http::<axum::body::Body>()
+            .into_response(),
+    }
+}
+
+type Method<M, R> = fn(AdminState, tonic::Request<M>) -> BoxFuture<'static, Reply<R>>;
+
+// A unary method, called with the admin state
+struct Unary<M, R>(AdminState, Method<M, R>);
+
+impl<M, R> UnaryService<M> for Unary<M, R> {
+    type Response = R;
+    type Future = BoxFuture<'static, Reply<R>>;
+
+    fn call(&mut self, request: tonic::Request<M>) -> Self::Future {
+        (self.1)(self.0.clone(), request)
+    }
+}
+
+// The room a request names, `None` for the main world. Tenant keys reach
+// their own rooms alone, their lobby for the main world
+fn room<T>(request: &tonic::Request<T>, room: &str) -> Result<Option<String>, Status> {
+    let Some(Tenant(tenant)) = request.extensions().get::<Tenant>() else {
+        return Ok((!room.is_empty()).then(|| room.to_string()));
+    };
+    if room.is_empty() {
+        return Ok(Some(format!("{tenant}/{}", tenants::LOBBY)));
+    }
+    if !command::valid_room(room) {
+        return Err(Status::invalid_argument("Invalid room"));
+    }
+    Ok(Some(format!("{tenant}/{room}")))
+}
+
+fn kick(
+    state: AdminState,
+    request: tonic::Request<KickRequest>,
+) -> BoxFuture<'static, Reply<KickReply>> {
+    Box::pin(async move {
+        let room = room(&request, &request.get_ref().room)?;
+        let KickRequest { id, reason, .. } = request.into_inner();
+        let kicked = state.world.kick(room.as_deref(), id, reason.clone()).await;
+        if kicked && let Some(audit) = &state.audit {
+            audit.record(AuditEvent::Kick {
+                room: room.unwrap_or_else(|| "main".into()),
+                id,
+                reason,
+            });
+        }
+        Ok(tonic::Response::new(KickReply { kicked }))
+    })
+}
+
+fn world_state(
+    state: AdminState,
+    request: tonic::Request<WorldStateRequest>,
+) -> BoxFuture<'static, Reply<WorldStateReply>> {
+    Box::pin(async move {
+        let room = room(&request, &request.get_ref().room)?;
+        let Some(world) = state.world.state(room.as_deref()).await else {
+            return Err(Status::not_found("No such room"));
+        };
+        let players = world
+            .players
+            .into_iter()
+            .map(|player| PlayerState {
+                id: player.id,
+                name: player.name,
+                position: Some(player.position.into()),
+                interest: player.interest.map(|(center, radius)| Interest {
+                    center: Some(center.into()),
+                    radius: radius.into(),
+                }),
+            })
+            .collect();
+        Ok(tonic::Response::new(WorldStateReply {
+            tick: world.tick,
+            loaded_chunks: world.loaded_chunks as u64,
+            players,
+        }))
+    })
+}
+
+// Like `GET /admin/events`: until the client hangs up or the server stops,
+// `server_stopping` last, with a `lagged` event for what slow readers missed
+struct Events(AdminState);
+
+impl ServerStreamingService<EventsRequest> for Events {
+    type Response = Event;
+    type ResponseStream = EventStream;
+    type Future = BoxFuture<'static, Reply<EventStream>>;
+
+    fn call(&mut self, request: tonic::Request<EventsRequest>) -> Self::Future {
+        let admin = request.extensions().get::<Role>() == Some(&Role::Admin);
+        let rx = self.0.events.subscribe();
+        Box::pin(async move {
+            if !admin {
+                return Err(Status::permission_denied("Needs the admin token"));
+            }
+            let events = futures_util::stream::unfold(Some(rx), |rx| async move {
+                let mut rx = rx?;
+                let event = match rx.recv().await {
+                    // Ends the stream, or it would hold up graceful shutdown
+                    Ok(event @ WebhookEvent::ServerStopping) => {
+                        return Some((Ok(event.into()), None));
+                    }
+                    Ok(event) => event.into(),
+                    Err(broadcast::error::RecvError::Lagged(n)) => Event {
+                        event: "lagged".into(),
+                        skipped: Some(n),
+                        ..Default::default()
+                    },
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                };
+                Some((Ok(event), Some(rx)))
+            });
+            Ok(tonic::Response::new(Box::pin(events) as EventStream))
+        })
+    }
+}
+
+impl From<WebhookEvent> for Event {
+    fn from(event: WebhookEvent) -> Self {
+        let name = event.name().to_string();
+        match event {
+            WebhookEvent::PlayerJoined { room, id, name: n }
+            | WebhookEvent::PlayerLeft { room, id, name: n } => Event {
+                event: name,
+                room,
+                id: Some(id),
+                name: n,
+                ..Default::default()
+            },
+            WebhookEvent::RoomCreated { room } | WebhookEvent::RoomDestroyed { room } => Event {
+                event: name,
+                room,
+                ..Default::default()
+            },
+            WebhookEvent::TriggerEntered { room, id, trigger }
+            | WebhookEvent::TriggerExited { room, id, trigger } => Event {
+                event: name,
+                room,
+                id: Some(id),
+                trigger: Some(trigger),
+                ..Default::default()
+            },
+            WebhookEvent::ServerStarted { .. } | WebhookEvent::ServerStopping => Event {
+                event: name,
+                ..Default::default()
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        admin::tests::FakeWorld,
+        audit::{AuditLog, AuditQuery},
+        claims::Claims,
+        config::AuditConfig,
+        tenants::Tenants,
+    };
+    use std::{sync::Arc, time::Duration};
+    use tonic::{Code, client, metadata::MetadataValue, transport::Channel};
+
+    // The service on a local port over HTTP/2, as the server mounts it, with
+    // tokens `admin` and `mod` and an audit log in `dir`
+    async fn serve(
+        world: Arc<FakeWorld>,
+        dir: &std::path::Path,
+    ) -> (Channel, AdminState, Arc<AuditLog>) {
+        std::fs::remove_dir_all(dir).ok();
+        let audit = Arc::new(
+            AuditLog::open(AuditConfig {
+                path: dir.join("audit.jsonl"),
+                max_bytes: 1 << 20,
+                keep: 0,
+            })
+            .unwrap(),
+        );
+        let state = AdminState {
+            token: "admin".into(),
+            moderator_token: Some("mod".into()),
+            storage: None,
+            audit: Some(audit.clone()),
+            backups: None,
+            claims: Claims::load(None).unwrap(),
+            traffic: Arc::default(),
+            quotas: Arc::default(),
+            tenants: Arc::new(Tenants::load(None).unwrap()),
+            templates: Arc::default(),
+            listeners: Arc::default(),
+            world,
+            events: broadcast::channel(16).0,
+            journal: Arc::default(),
+        };
+        let app = Router::new()
+            .nest("/admin", crate::admin::router(state.clone()))
+            .merge(router(state.clone()));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        let channel = Channel::from_shared(format!("http://{addr}"))
+            .unwrap()
+            .connect()
+            .await
+            .unwrap();
+        (channel, state, audit)
+    }
+
+    fn request<T>(message: T, token: &str) -> tonic::Request<T> {
+        let mut request = tonic::Request::new(message);
+        let token = MetadataValue::try_from(format!("Bearer {token}")).unwrap();
+        request.metadata_mut().insert("authorization", token);
+        request
+    }
+
+    async fn unary<M, R>(channel: &Channel, method: &str, request: tonic::Request<M>) -> Reply<R>
+    where
+        M: prost::Message + Send + Sync + 'static,
+        R: prost::Message + Default + Send + Sync + 'static,
+    {
+        let mut grpc = client::Grpc::new(channel.clone());
+        grpc.ready().await.unwrap();
+        let path = format!("/{SERVICE}/{method}").parse().unwrap();
+        grpc.unary(request, path, ProstCodec::default()).await
+    }
+
+    #[tokio::test]
+    async fn kicks_players_and_reports_the_world() {
+        let world = Arc::new(FakeWorld::default());
+        let dir = std::env::temp_dir().join(format!("teleboxel-grpc-{}", std::process::id()));
+        let (channel, _, audit) = serve(world.clone(), &dir).await;
+
+        let main = WorldStateRequest::default();
+        let reply: WorldStateReply = unary(&channel, "WorldState", request(main, "admin"))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!((reply.tick, reply.loaded_chunks), (812, 27));
+        assert_eq!(reply.players[0].name.as_deref(), Some("bob"));
+        assert_eq!(reply.players[0].position, Some((0, 40, -3).into()));
+        let arena = WorldStateRequest {
+            room: "arena".into(),
+        };
+        let arena = unary::<_, WorldStateReply>(&channel, "WorldState", request(arena, "admin"));
+        assert_eq!(arena.await.unwrap_err().code(), Code::NotFound);
+
+        // Moderators reach kicks too
+        let kick = |id| KickRequest {
+            id,
+            reason: "griefing".into(),
+            ..Default::default()
+        };
+        let reply: KickReply = unary(&channel, "Kick", request(kick(1), "mod"))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(reply.kicked);
+        let reply: KickReply = unary(&channel, "Kick", request(kick(2), "admin"))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(!reply.kicked);
+        assert_eq!(*world.kicked.lock().unwrap(), [(1, "griefing".to_string())]);
+
+        let query = AuditQuery {
+            event: Some("kick".into()),
+            limit: 10,
+            ..Default::default()
+        };
+        assert_eq!(audit.query(&query).unwrap().len(), 1);
+
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[tokio::test]
+    async fn checks_tokens() {
+        let dir =
+            std::env::temp_dir().join(format!("teleboxel-grpc-tokens-{}", std::process::id()));
+        let (channel, _, audit) = serve(Arc::default(), &dir).await;
+
+        for token in ["", "admi", "ADMIN"] {
+            let world = request(WorldStateRequest::default(), token);
+            let world = unary::<_, WorldStateReply>(&channel, "WorldState", world).await;
+            assert_eq!(world.unwrap_err().code(), Code::Unauthenticated, "{token}");
+        }
+        let query = AuditQuery {
+            event: Some("auth_failure".into()),
+            limit: 10,
+            ..Default::default()
+        };
+        assert_eq!(audit.query(&query).unwrap().len(), 3);
+
+        let mut grpc = client::Grpc::new(channel.clone());
+        grpc.ready().await.unwrap();
+        let path = format!("/{SERVICE}/Events").parse().unwrap();
+        let events = request(EventsRequest {}, "mod");
+        let events = grpc
+            .server_streaming::<_, Event, _>(events, path, ProstCodec::default())
+            .await;
+        assert_eq!(events.unwrap_err().code(), Code::PermissionDenied);
+
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[tokio::test]
+    async fn streams_events_until_the_server_stops() {
+        let dir =
+            std::env::temp_dir().join(format!("teleboxel-grpc-events-{}", std::process::id()));
+        let (channel, state, _) = serve(Arc::default(), &dir).await;
+
+        let mut grpc = client::Grpc::new(channel.clone());
+        grpc.ready().await.unwrap();
+        let path = format!("/{SERVICE}/Events").parse().unwrap();
+        let events = request(EventsRequest {}, "admin");
+        let mut events = grpc
+            .server_streaming::<_, Event, _>(events, path, ProstCodec::default())
+            .await
+            .unwrap()
+            .into_inner();
+        state
+            .events
+            .send(WebhookEvent::PlayerJoined {
+                room: "arena".into(),
+                id: 7,
+                name: Some("bob".into()),
+            })
+            .unwrap();
+        state.events.send(WebhookEvent::ServerStopping).unwrap();
+
+        let mut next =
+            async || tokio::time::timeout(Duration::from_secs(5), events.message()).await;
+        let joined = next().await.unwrap().unwrap().unwrap();
+        assert_eq!(joined.event, "player_joined");
+        assert_eq!((joined.room.as_str(), joined.id), ("arena", Some(7)));
+        assert_eq!(joined.name.as_deref(), Some("bob"));
+        let stopping = next().await.unwrap().unwrap().unwrap();
+        assert_eq!(stopping.event, "server_stopping");
+        assert!(next().await.unwrap().unwrap().is_none());
+
+        std::fs::remove_dir_all(dir).ok();
+    }
+}
@@ -0,0 +1,366 @@
+//! Tick history for desync investigations. Every world keeps the block
+//! edits, moves, joins and leaves of its last `TELEBOXEL_HISTORY_TICKS`
+//! ticks, and rebuilds how it looked at any of them by undoing the newer
+//! changes from the live state. A state is its players and the blocks
+//! edited within the window; the rest of the world didn't change. A tick's
+//! state includes the changes made during it, as its frame sent them.
+//!
+//! Rewinding (`TELEBOXEL_DEV_REWIND`, development only) puts the live world
+//! back: blocks go out to clients as ordinary chunk deltas and server-side
+//! positions move back, but joins and leaves stay, and the tick counter
+//! keeps counting up. Rewound ticks are dropped from the history.
+
+use crate::{claims::BlockPos, config::HistoryConfig};
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    fmt,
+};
+
+/// Something that happened during a tick.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Change {
+    Block {
+        position: BlockPos,
+        from: u16,
+        to: u16,
+    },
+    Moved {
+        id: u32,
+        from: BlockPos,
+        to: BlockPos,
+    },
+    Joined(PlayerAt),
+    Left(PlayerAt),
+}
+
+#[derive(Serialize, Clone, PartialEq, Eq, Debug)]
+pub struct PlayerAt {
+    pub id: u32,
+    pub name: Option<String>,
+    pub position: BlockPos,
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct BlockAt {
+    pub position: BlockPos,
+    pub block: u16,
+}
+
+/// A world at one tick. Sorted by player id and block position.
+#[derive(Serialize, Clone, PartialEq, Eq, Debug)]
+pub struct WorldAt {
+    pub tick: u64,
+    pub players: Vec<PlayerAt>,
+    pub blocks: Vec<BlockAt>,
+}
+
+#[derive(Serialize, PartialEq, Eq, Debug)]
+pub struct Moved {
+    pub id: u32,
+    pub name: Option<String>,
+    pub from: BlockPos,
+    pub to: BlockPos,
+}
+
+#[derive(Serialize, PartialEq, Eq, Debug)]
+pub struct BlockDiff {
+    pub position: BlockPos,
+    pub from: u16,
+    pub to: u16,
+}
+
+/// What changed from one tick to another.
+#[derive(Serialize, PartialEq, Eq, Debug)]
+pub struct Diff {
+    pub from: u64,
+    pub to: u64,
+    pub joined: Vec<PlayerAt>,
+    pub left: Vec<PlayerAt>,
+    pub moved: Vec<Moved>,
+    pub blocks: Vec<BlockDiff>,
+}
+
+/// What a rewind put back.
+#[derive(Serialize, PartialEq, Eq, Debug)]
+pub struct Rewound {
+    pub from: u64,
+    pub to: u64,
+    pub blocks: usize,
+    pub players: usize,
+}
+
+/// The admin API's questions, see `WorldControl::history`.
+pub enum HistoryQuery {
+    At(u64),
+    Diff(u64, u64),
+    Rewind(u64),
+}
+
+#[derive(Serialize, Debug)]
+#[serde(untagged)]
+pub enum HistoryReply {
+    At(WorldAt),
+    Diff(Diff),
+    Rewound(Rewound),
+}
+
+#[derive(PartialEq, Eq, Debug)]
+pub enum HistoryError {
+    /// `TELEBOXEL_HISTORY_TICKS` is 0.
+    Off,
+    /// `TELEBOXEL_DEV_REWIND` isn't set.
+    RewindOff,
+    /// Older than the oldest tick kept.
+    Gone { first: u64 },
+    /// Hasn't happened yet.
+    Future { now: u64 },
+}
+
+impl fmt::Display for HistoryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HistoryError::Off => write!(f, "History is off"),
+            HistoryError::RewindOff => write!(f, "Rewinding is off"),
+            HistoryError::Gone { first } => write!(f, "Gone, the oldest tick kept is {first}"),
+            HistoryError::Future { now } => write!(f, "Not there yet, the world is at {now}"),
+        }
+    }
+}
+
+pub struct History {
+    config: HistoryConfig,
+    // Changes by tick, oldest first, ticks without any left out
+    ticks: VecDeque<(u64, Vec<Change>)>,
+    // Oldest tick that can be rebuilt
+    first: u64,
+}
+
+impl History {
+    pub fn new(config: HistoryConfig, now: u64) -> Self {
+        Self {
+            config,
+            ticks: VecDeque::new(),
+            first: now,
+        }
+    }
+
+    pub fn record(&mut self, tick: u64, change: Change) {
+        if self.config.ticks == 0 {
+            return;
+        }
+        match self.ticks.back_mut() {
+            Some((last, changes)) if *last == tick => changes.push(change),
+            _ => self.ticks.push_back((tick, vec![change])),
+        }
+        while let Some(&(oldest, _)) = self.ticks.front()
+            && oldest + self.config.ticks <= tick
+        {
+            self.ticks.pop_front();
+            self.first = oldest;
+        }
+    }
+
+    /// Blocks edited within the window, which `live` states need.
+    pub fn edited(&self) -> BTreeSet<BlockPos> {
+        self.changes()
+            .filter_map(|(_, change)| match change {
+                Change::Block { position, .. } => Some(*position),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// The world at `tick`, from the `live` one.
+    pub fn at(&self, live: &WorldAt, tick: u64) -> Result<WorldAt, HistoryError> {
+        if self.config.ticks == 0 {
+            return Err(HistoryError::Off);
+        }
+        if tick > live.tick {
+            return Err(HistoryError::Future { now: live.tick });
+        }
+        if tick < self.first {
+            return Err(HistoryError::Gone { first: self.first });
+        }
+
+        let mut players: BTreeMap<_, _> = live.players.iter().map(|p| (p.id, p.clone())).collect();
+        let mut blocks: BTreeMap<_, _> =
+            live.blocks.iter().map(|b| (b.position, b.block)).collect();
+        let newer = self.changes().rev().take_while(|&(t, _)| t > tick);
+        for (_, change) in newer {
+            match change {
+                Change::Block { position, from, .. } => {
+                    blocks.insert(*position, *from);
+                }
+                Change::Moved { id, from, .. } => {
+                    if let Some(player) = players.get_mut(id) {
+                        player.position = *from;
+                    }
+                }
+                Change::Joined(player) => {
+                    players.remove(&player.id);
+                }
+                Change::Left(player) => {
+                    players.insert(player.id, player.clone());
+                }
+            }
+        }
+
+        Ok(WorldAt {
+            tick,
+            players: players.into_values().collect(),
+            blocks: blocks
+                .into_iter()
+                .map(|(position, block)| BlockAt { position, block })
+                .collect(),
+        })
+    }
+
+    pub fn diff(&self, live: &WorldAt, from: u64, to: u64) -> Result<Diff, HistoryError> {
+        let (a, b) = (self.at(live, from)?, self.at(live, to)?);
+        let players = |w: &WorldAt| -> BTreeMap<_, _> {
+            w.players.iter().map(|p| (p.id, p.clone())).collect()
+        };
+        let (before, after) = (players(&a), players(&b));
+        let mut diff = Diff {
+            from,
+            to,
+            joined: after
+                .values()
+                .filter(|p| !before.contains_key(&p.id))
+                .cloned()
+                .collect(),
+            left: before
+                .values()
+                .filter(|p| !after.contains_key(&p.id))
+                .cloned()
+                .collect(),
+            moved: Vec::new(),
+            blocks: Vec::new(),
+        };
+        for (id, player) in &after {
+            if let Some(was) = before.get(id)
+                && was.position != player.position
+            {
+                diff.moved.push(Moved {
+                    id: *id,
+                    name: player.name.clone(),
+                    from: was.position,
+                    to: player.position,
+                });
+            }
+        }
+        let before: BTreeMap<_, _> = a.blocks.iter().map(|b| (b.position, b.block)).collect();
+        for block in &b.blocks {
+            if let Some(&was) = before.get(&block.position)
+                && was != block.block
+            {
+                diff.blocks.push(BlockDiff {
+                    position: block.position,
+                    from: was,
+                    to: block.block,
+                });
+            }
+        }
+        Ok(diff)
+    }
+
+    /// The world `ticks` ago, for the caller to apply, forgetting everything
+    /// after it.
+    pub fn rewind(&mut self, live: &WorldAt, ticks: u64) -> Result<WorldAt, HistoryError> {
+        if !self.config.rewind {
+            return Err(HistoryError::RewindOff);
+        }
+        let target = self.at(live, live.tick.saturating_sub(ticks))?;
+        while self.ticks.back().is_some_and(|&(t, _)| t > target.tick) {
+            self.ticks.pop_back();
+        }
+        Ok(target)
+    }
+
+    fn changes(&self) -> impl DoubleEndedIterator<Item = (u64, &Change)> {
+        self.ticks
+            .iter()
+            .flat_map(|(tick, changes)| changes.iter().map(move |c| (*tick, c)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rebuilds_diffs_and_rewinds_ticks() {
+        let config = HistoryConfig {
+            ticks: 10,
+            rewind: true,
+        };
+        let mut history = History::new(config, 0);
+        let bob = |position| PlayerAt {
+            id: 1,
+            name: Some("bob".into()),
+            position,
+        };
+        history.record(1, Change::Joined(bob((0, 40, 0))));
+        history.record(
+            2,
+            Change::Moved {
+                id: 1,
+                from: (0, 40, 0),
+                to: (1, 40, 0),
+            },
+        );
+        let position = (1, 39, 0);
+        history.record(
+            3,
+            Change::Block {
+                position,
+                from: 1,
+                to: 0,
+            },
+        );
+        history.record(
+            5,
+            Change::Block {
+                position,
+                from: 0,
+                to: 2,
+            },
+        );
+
+        let live = WorldAt {
+            tick: 6,
+            players: vec![bob((1, 40, 0))],
+            blocks: vec![BlockAt { position, block: 2 }],
+        };
+        assert_eq!(history.edited().into_iter().collect::<Vec<_>>(), [position]);
+        let at = history.at(&live, 0).unwrap();
+        assert!(at.players.is_empty());
+        assert_eq!(at.blocks, [BlockAt { position, block: 1 }]);
+        assert_eq!(history.at(&live, 4).unwrap().blocks[0].block, 0);
+        assert_eq!(history.at(&live, 7), Err(HistoryError::Future { now: 6 }));
+
+        let diff = history.diff(&live, 1, 6).unwrap();
+        assert!(diff.joined.is_empty() && diff.left.is_empty());
+        assert_eq!(diff.moved[0].to, (1, 40, 0));
+        assert_eq!(
+            diff.blocks,
+            [BlockDiff {
+                position,
+                from: 1,
+                to: 2
+            }]
+        );
+
+        let target = history.rewind(&live, 3).unwrap();
+        assert_eq!((target.tick, target.blocks[0].block), (3, 0));
+        let live = WorldAt { tick: 7, ..target };
+        assert_eq!(history.at(&live, 6).unwrap().blocks[0].block, 0);
+
+        // Past the window
+        history.record(20, Change::Left(bob((1, 40, 0))));
+        assert_eq!(history.at(&live, 2), Err(HistoryError::Gone { first: 3 }));
+        history.config.rewind = false;
+        assert_eq!(history.rewind(&live, 1), Err(HistoryError::RewindOff));
+    }
+}
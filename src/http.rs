@@ -0,0 +1,281 @@
+//! Minimal HTTP/1.1 client for outbound integrations (OTLP export, ...).
+//!
+//! `http://` or `https://`: HTTPS servers must have a certificate from one
+//! of the Mozilla roots `webpki-roots` bundles, for the URL's host.
+
+use bytes::Bytes;
+use http_body_util::{BodyExt, Full};
+use hyper::{Method, Request, client::conn::http1};
+use hyper_util::rt::TokioIo;
+use std::{
+    fmt,
+    sync::{Arc, OnceLock},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpStream,
+};
+use tokio_rustls::{
+    TlsConnector,
+    rustls::{ClientConfig, RootCertStore, crypto::ring, pki_types::ServerName},
+};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug)]
+pub enum HttpError {
+    /// Not an `http[s]://host[:port][/path]` URL.
+    BadUrl(String),
+    Timeout,
+    Io(std::io::Error),
+    Hyper(hyper::Error),
+    /// Non-2xx response, with the start of its body.
+    Status(u16, String),
+}
+
+impl fmt::Display for HttpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HttpError::BadUrl(url) => write!(f, "unsupported URL {url:?}"),
+            HttpError::Timeout => write!(f, "request timed out"),
+            HttpError::Io(e) => write!(f, "{e}"),
+            HttpError::Hyper(e) => write!(f, "{e}"),
+            HttpError::Status(status, body) => write!(f, "HTTP {status}: {body}"),
+        }
+    }
+}
+
+impl std::error::Error for HttpError {}
+
+impl From<std::io::Error> for HttpError {
+    fn from(e: std::io::Error) -> Self {
+        HttpError::Io(e)
+    }
+}
+
+impl From<hyper::Error> for HttpError {
+    fn from(e: hyper::Error) -> Self {
+        HttpError::Hyper(e)
+    }
+}
+
+/// Where a request goes.
+struct Target<'a> {
+    https: bool,
+    /// The address to connect to.
+    addr: String,
+    /// The `Host` header.
+    host: &'a str,
+    path: &'a str,
+}
+
+/// Splits `http[s]://host[:port][/path]`.
+fn split_url(url: &str) -> Option<Target<'_>> {
+    let (https, rest) = match url.strip_prefix("https://") {
+        Some(rest) => (true, rest),
+        None => (false, url.strip_prefix("http://")?),
+    };
+    let (host, path) = match rest.find('/') {
+        Some(i) => rest.split_at(i),
+        None => (rest, "/"),
+    };
+    if host.is_empty() {
+        return None;
+    }
+    let addr = match port_at(host) {
+        Some(_) => host.to_string(),
+        None if https => format!("{host}:443"),
+        None => format!("{host}:80"),
+    };
+    Some(Target {
+        https,
+        addr,
+        host,
+        path,
+    })
+}
+
+// Where the port starts in `host[:port]`. IPv6 literals are bracketed, so a
+// port follows the last `:` after `]`
+fn port_at(host: &str) -> Option<usize> {
+    host.rfind(':').filter(|&i| !host[i..].contains(']'))
+}
+
+// The name a server's certificate must be for: the host without its port
+// or brackets
+fn server_name(host: &str) -> Option<ServerName<'static>> {
+    let name = &host[..port_at(host).unwrap_or(host.len())];
+    let name = name.trim_start_matches('[').trim_end_matches(']');
+    ServerName::try_from(name).ok().map(|name| name.to_owned())
+}
+
+fn connector() -> TlsConnector {
+    static CONFIG: OnceLock<Arc<ClientConfig>> = OnceLock::new();
+    let config = CONFIG.get_or_init(|| {
+        let roots = RootCertStore {
+            roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+        };
+        // ring like the listeners, whatever else is in the tree
+        let config = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        Arc::new(config)
+    });
+    TlsConnector::from(config.clone())
+}
+
+/// POSTs `body` and returns the response body. Anything but 2xx is an error.
+pub async fn post(
+    url: &str,
+    headers: &[(&str, &str)],
+    body: impl Into<Bytes>,
+) -> Result<Bytes, HttpError> {
+    let request = send(Method::POST, url, headers, body.into());
+    tokio::time::timeout(TIMEOUT, request)
+        .await
+        .map_err(|_| HttpError::Timeout)?
+}
+
+/// GETs `url` and returns the response body. Anything but 2xx is an error.
+pub async fn get(url: &str, headers: &[(&str, &str)]) -> Result<Bytes, HttpError> {
+    let request = send(Method::GET, url, headers, Bytes::new());
+    tokio::time::timeout(TIMEOUT, request)
+        .await
+        .map_err(|_| HttpError::Timeout)?
+}
+
+async fn send(
+    method: Method,
+    url: &str,
+    headers: &[(&str, &str)],
+    body: Bytes,
+) -> Result<Bytes, HttpError> {
+    let bad_url = || HttpError::BadUrl(url.into());
+    let target = split_url(url).ok_or_else(bad_url)?;
+    let mut request = Request::builder()
+        .method(method)
+        .uri(target.path)
+        .header("host", target.host);
+    for (name, value) in headers {
+        request = request.header(*name, *value);
+    }
+    let request = request.body(Full::new(body)).map_err(|_| bad_url())?;
+
+    let stream = TcpStream::connect(&target.addr).await?;
+    if !target.https {
+        return exchange(stream, request).await;
+    }
+    let name = server_name(target.host).ok_or_else(bad_url)?;
+    let stream = connector().connect(name, stream).await?;
+    exchange(stream, request).await
+}
+
+async fn exchange<S>(stream: S, request: Request<Full<Bytes>>) -> Result<Bytes, HttpError>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let (mut sender, conn) = http1::handshake(TokioIo::new(stream)).await?;
+    // Drives the connection until the response is read
+    tokio::spawn(conn);
+
+    let response = sender.send_request(request).await?;
+    let status = response.status();
+    let body = response.into_body().collect().await?.to_bytes();
+    if !status.is_success() {
+        let text = String::from_utf8_lossy(&body[..body.len().min(200)]).into_owned();
+        return Err(HttpError::Status(status.as_u16(), text));
+    }
+    Ok(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_urls() {
+        let split = |url| {
+            let target = split_url(url)?;
+            Some((target.https, target.addr, target.host, target.path))
+        };
+        assert_eq!(
+            split("http://localhost:4318/v1/traces"),
+            Some((
+                false,
+                "localhost:4318".into(),
+                "localhost:4318",
+                "/v1/traces"
+            ))
+        );
+        assert_eq!(
+            split("http://collector"),
+            Some((false, "collector:80".into(), "collector", "/"))
+        );
+        assert_eq!(
+            split("http://[::1]/hook"),
+            Some((false, "[::1]:80".into(), "[::1]", "/hook"))
+        );
+        assert_eq!(
+            split("https://id.example/jwks"),
+            Some((true, "id.example:443".into(), "id.example", "/jwks"))
+        );
+        assert_eq!(
+            split("https://[::1]:8443"),
+            Some((true, "[::1]:8443".into(), "[::1]:8443", "/"))
+        );
+        assert_eq!(split("ftp://example.com/"), None);
+        assert_eq!(split("http:///path"), None);
+
+        let name = |host| server_name(host).map(|name| name.to_str().into_owned());
+        assert_eq!(name("id.example:443").as_deref(), Some("id.example"));
+        assert_eq!(name("[::1]:8443").as_deref(), Some("::1"));
+        assert_eq!(name("127.0.0.1").as_deref(), Some("127.0.0.1"));
+        assert_eq!(name("bad name"), None);
+    }
+
+    #[tokio::test]
+    async fn checks_https_servers() {
+        // A plain HTTP server doesn't pass for an HTTPS one
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let reply = b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\n\r\n{}";
+            tokio::io::AsyncWriteExt::write_all(&mut stream, reply)
+                .await
+                .ok();
+        });
+        let got = get(&format!("https://{addr}/"), &[]).await;
+        assert!(matches!(got, Err(HttpError::Io(_))), "{got:?}");
+
+        // Nor one with a certificate no root vouches for
+        use tokio_rustls::{
+            TlsAcceptor,
+            rustls::{
+                ServerConfig,
+                pki_types::{PrivateKeyDer, PrivatePkcs8KeyDer},
+            },
+        };
+        let signed = rcgen::generate_simple_self_signed(vec!["localhost".into()]).unwrap();
+        let key = PrivatePkcs8KeyDer::from(signed.signing_key.serialize_der());
+        let config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(vec![signed.cert.der().clone()], PrivateKeyDer::from(key))
+            .unwrap();
+        let acceptor = TlsAcceptor::from(Arc::new(config));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let refused = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            acceptor.accept(stream).await.is_err()
+        });
+        let got = get(&format!("https://localhost:{port}/"), &[]).await;
+        assert!(matches!(got, Err(HttpError::Io(_))), "{got:?}");
+        assert!(refused.await.unwrap());
+    }
+}
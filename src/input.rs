@@ -0,0 +1,100 @@
+//! Fixed input delay, for modes that want every player's inputs applied on
+//! a steady schedule rather than as they arrive. With
+//! `TELEBOXEL_INPUT_DELAY_TICKS` set, moves and block edits wait that many
+//! ticks and are applied in arrival order at the start of their tick, so
+//! jitter up to the delay doesn't reorder or bunch them up. Each player can
+//! have `TELEBOXEL_INPUT_BUFFER` inputs waiting; more are dropped and
+//! counted (`dropped_inputs` in `GET /admin/world`).
+
+use std::collections::{HashMap, VecDeque};
+
+pub struct InputQueue<T> {
+    delay: u64,
+    capacity: usize,
+    // Due tick, player id and input, in arrival order
+    queue: VecDeque<(u64, u32, T)>,
+    waiting: HashMap<u32, usize>,
+    dropped: HashMap<u32, u64>,
+}
+
+impl<T> InputQueue<T> {
+    pub fn new(delay: u64, capacity: usize) -> Self {
+        Self {
+            delay,
+            capacity,
+            queue: VecDeque::new(),
+            waiting: HashMap::new(),
+            dropped: HashMap::new(),
+        }
+    }
+
+    /// Whether inputs are delayed at all.
+    pub fn is_on(&self) -> bool {
+        self.delay > 0
+    }
+
+    /// Queues player `id`'s input, arrived during `tick`. `false` if the
+    /// player's buffer is full and it was dropped.
+    pub fn push(&mut self, tick: u64, id: u32, input: T) -> bool {
+        let waiting = self.waiting.entry(id).or_default();
+        if *waiting >= self.capacity {
+            *self.dropped.entry(id).or_default() += 1;
+            return false;
+        }
+        *waiting += 1;
+        self.queue.push_back((tick + self.delay, id, input));
+        true
+    }
+
+    /// The inputs due by `tick`, in the order they arrived.
+    pub fn take_due(&mut self, tick: u64) -> Vec<T> {
+        let mut due = Vec::new();
+        while let Some(&(at, id, _)) = self.queue.front()
+            && at <= tick
+        {
+            if let Some(waiting) = self.waiting.get_mut(&id) {
+                *waiting -= 1;
+            }
+            due.extend(self.queue.pop_front().map(|(_, _, input)| input));
+        }
+        due
+    }
+
+    pub fn dropped(&self, id: u32) -> u64 {
+        self.dropped.get(&id).copied().unwrap_or(0)
+    }
+
+    /// Drops player `id`'s waiting inputs and counters, once they've left.
+    pub fn forget(&mut self, id: u32) {
+        self.queue.retain(|&(_, player, _)| player != id);
+        self.waiting.remove(&id);
+        self.dropped.remove(&id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delays_inputs_in_arrival_order() {
+        let mut inputs = InputQueue::new(2, 2);
+        assert!(inputs.is_on());
+        assert!(inputs.push(10, 1, "a"));
+        assert!(inputs.push(10, 2, "b"));
+        assert!(inputs.push(11, 1, "c"));
+        assert!(!inputs.push(11, 1, "d"));
+        assert_eq!(inputs.dropped(1), 1);
+
+        assert!(inputs.take_due(11).is_empty());
+        assert_eq!(inputs.take_due(12), ["a", "b"]);
+        // Room again once one was applied
+        assert!(inputs.push(12, 1, "e"));
+        assert_eq!(inputs.take_due(14), ["c", "e"]);
+
+        inputs.push(14, 2, "f");
+        inputs.forget(2);
+        assert!(inputs.take_due(20).is_empty());
+        assert!(!InputQueue::<()>::new(0, 1).is_on());
+    }
+}
@@ -0,0 +1,178 @@
+//! Interest math: a player's interest is a cube of chunks, `radius` chunks
+//! around a center chunk on every axis (Chebyshev distance), capped at
+//! `config::INTEREST_RADIUS_LIMIT`. The tick walks it nearest first to
+//! send chunks; effects check a position against it with `effects::in_view`,
+//! static entities are looked up in its `blocks` (see `spatial.rs`).
+//!
+//! For chunks, fast movers' cubes are stretched ahead of them (`Reach`):
+//! as many chunks along each axis as their `SetTransform` velocity covers
+//! in `TELEBOXEL_INTEREST_LEAD_MS`, at most the radius, so chunks on the
+//! way are loaded and sent before they get there.
+
+use crate::{
+    chunk::{CHUNK_SIZE, ChunkPos},
+    claims::BlockPos,
+    config,
+};
+use std::{sync::OnceLock, time::Duration};
+
+pub fn chebyshev(a: ChunkPos, b: ChunkPos) -> i32 {
+    (a.0 - b.0)
+        .abs()
+        .max((a.1 - b.1).abs())
+        .max((a.2 - b.2).abs())
+}
+
+/// Offsets of the interest cube for `radius`, nearest first. Sorted by cube
+/// shell, so each radius is a prefix.
+pub fn offsets(radius: u16) -> impl Iterator<Item = &'static ChunkPos> {
+    static OFFSETS: OnceLock<Vec<ChunkPos>> = OnceLock::new();
+    let offsets = OFFSETS.get_or_init(|| {
+        let r = config::INTEREST_RADIUS_LIMIT as i32;
+        let mut offsets = Vec::new();
+        for x in -r..=r {
+            for y in -r..=r {
+                for z in -r..=r {
+                    offsets.push((x, y, z));
+                }
+            }
+        }
+        offsets.sort_by_key(|&o| (chebyshev(o, (0, 0, 0)), o.0 * o.0 + o.1 * o.1 + o.2 * o.2));
+        offsets
+    });
+
+    let side = 2 * radius.min(config::INTEREST_RADIUS_LIMIT) as usize + 1;
+    offsets[..side.pow(3)].iter()
+}
+
+/// The blocks of the interest cube, lowest and highest corner, for
+/// `spatial.rs` queries. Clamped to the block range.
+pub fn blocks(center: ChunkPos, radius: u16) -> (BlockPos, BlockPos) {
+    let (size, r) = (CHUNK_SIZE as i64, radius as i64);
+    let block = |b: i64| b.clamp(i32::MIN as i64, i32::MAX as i64) as i32;
+    let lo = |c: i32| block((c as i64 - r) * size);
+    let hi = |c: i32| block((c as i64 + r + 1) * size - 1);
+    let (x, y, z) = center;
+    ((lo(x), lo(y), lo(z)), (hi(x), hi(y), hi(z)))
+}
+
+/// Chunks ahead on each axis of a player moving at `velocity` (blocks per
+/// second) for `lead`, at most `max` either way.
+pub fn lead(velocity: (f32, f32, f32), lead: Duration, max: u16) -> ChunkPos {
+    let max = max as f32;
+    let ahead = |v: f32| {
+        let chunks = v * lead.as_secs_f32() / CHUNK_SIZE as f32;
+        chunks.round().clamp(-max, max) as i32
+    };
+    (ahead(velocity.0), ahead(velocity.1), ahead(velocity.2))
+}
+
+/// An interest cube stretched `lead` chunks along each axis, the way the
+/// player is going.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Reach {
+    pub center: ChunkPos,
+    pub radius: u16,
+    pub lead: ChunkPos,
+}
+
+impl Reach {
+    /// The lowest and highest chunk on each axis.
+    pub fn bounds(&self) -> (ChunkPos, ChunkPos) {
+        let (c, r, l) = (self.center, self.radius as i32, self.lead);
+        let lo = (
+            c.0 - r + l.0.min(0),
+            c.1 - r + l.1.min(0),
+            c.2 - r + l.2.min(0),
+        );
+        let hi = (
+            c.0 + r + l.0.max(0),
+            c.1 + r + l.1.max(0),
+            c.2 + r + l.2.max(0),
+        );
+        (lo, hi)
+    }
+
+    pub fn contains(&self, pos: ChunkPos) -> bool {
+        let (lo, hi) = self.bounds();
+        (lo.0..=hi.0).contains(&pos.0)
+            && (lo.1..=hi.1).contains(&pos.1)
+            && (lo.2..=hi.2).contains(&pos.2)
+    }
+
+    /// Its chunks: the cube nearest first, then the stretch nearest the
+    /// cube first.
+    pub fn chunks(self) -> impl Iterator<Item = ChunkPos> {
+        let c = self.center;
+        let cube = offsets(self.radius).map(move |&(dx, dy, dz)| (c.0 + dx, c.1 + dy, c.2 + dz));
+        cube.chain(self.stretch())
+    }
+
+    // Nothing to sort for players standing still or walking
+    fn stretch(self) -> Vec<ChunkPos> {
+        if self.lead == (0, 0, 0) {
+            return Vec::new();
+        }
+        let (c, r) = (self.center, self.radius as i32);
+        let (lo, hi) = self.bounds();
+        let mut ahead = Vec::new();
+        for x in lo.0..=hi.0 {
+            for y in lo.1..=hi.1 {
+                for z in lo.2..=hi.2 {
+                    let beyond = chebyshev((x, y, z), c) - r;
+                    let (dx, dy, dz) = (x - c.0, y - c.1, z - c.2);
+                    if beyond > 0 {
+                        ahead.push(((beyond, dx * dx + dy * dy + dz * dz), (x, y, z)));
+                    }
+                }
+            }
+        }
+        ahead.sort_unstable();
+        ahead.into_iter().map(|(_, pos)| pos).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn offsets_go_out_shell_by_shell() {
+        let near: Vec<_> = offsets(1).copied().collect();
+        assert_eq!(near.len(), 27);
+        assert_eq!(near[0], (0, 0, 0));
+        assert!(near.iter().all(|&o| chebyshev(o, (0, 0, 0)) <= 1));
+        assert!(offsets(2).take(27).eq(near.iter()));
+        assert_eq!(offsets(u16::MAX).count(), 33usize.pow(3));
+    }
+
+    #[test]
+    fn stretches_ahead_of_fast_movers() {
+        let half = Duration::from_millis(500);
+        let size = CHUNK_SIZE as f32;
+        // Walking doesn't stretch, running two chunks a second does
+        assert_eq!(lead((4.0, 0.0, -1.0), half, 4), (0, 0, 0));
+        assert_eq!(lead((4.0 * size, 0.0, -2.0 * size), half, 4), (2, 0, -1));
+        assert_eq!(lead((f32::INFINITY, 0.0, 0.0), half, 4), (4, 0, 0));
+
+        let still = Reach {
+            center: (0, 0, 0),
+            radius: 1,
+            lead: (0, 0, 0),
+        };
+        assert!(still.chunks().eq(offsets(1).copied()));
+        let running = Reach {
+            lead: (2, 0, 0),
+            ..still
+        };
+        assert_eq!(running.bounds(), ((-1, -1, -1), (3, 1, 1)));
+        assert!(running.contains((3, 1, -1)));
+        assert!(!running.contains((-2, 0, 0)));
+        let chunks: Vec<_> = running.chunks().collect();
+        assert_eq!(chunks.len(), 5 * 3 * 3);
+        assert!(chunks[..27].iter().eq(offsets(1)));
+        assert_eq!(chunks[27], (2, 0, 0));
+        assert!(chunks[27..].iter().all(|&pos| running.contains(pos)));
+        assert!(chunks[36..].iter().all(|pos| pos.0 == 3));
+    }
+}
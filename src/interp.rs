@@ -0,0 +1,67 @@
+//! Interpolation hints, so clients don't hard-code how far behind to
+//! render. Players get `INTERP_HINT` on joining a world and whenever it
+//! changes for them, in the control lane:
+//!
+//! - `update_ms`: how often updates come, the world's tick period at its
+//!   current rate (halved while degraded, see quotas.rs) and time scale
+//!   (see simulation.rs). Entities are updated at most once a tick, and
+//!   there are no slower tiers for far ones yet, so it's one rate for all.
+//! - `delay_ms`: how far behind the newest update to render,
+//!   `TELEBOXEL_INTERP_TICKS` updates plus twice the player's jitter as the
+//!   clock sync measured it (see clock.rs).
+//! - `buffer`: updates to keep to cover that delay, one more than fit in it.
+//!
+//! The world checks once a second and on simulation changes. Delays are
+//! rounded up to `DELAY_STEP` ms so jitter noise doesn't resend them.
+
+/// Delays are multiples of this, in ms.
+pub const DELAY_STEP: u32 = 10;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct InterpHint {
+    pub update_ms: u16,
+    pub delay_ms: u16,
+    pub buffer: u8,
+}
+
+/// The hint for a world ticking at `tick_hz` and `scale` percent, for a
+/// player with `jitter_ms`.
+pub fn hint(tick_hz: u32, scale: u16, interp_ticks: u32, jitter_ms: f64) -> InterpHint {
+    let hz = tick_hz.max(1) as u64 * scale.max(1) as u64;
+    let update = 100_000u64.div_ceil(hz).max(1);
+    let delay = update * interp_ticks as u64 + (jitter_ms.max(0.0) * 2.0).ceil() as u64;
+    let delay = delay.next_multiple_of(DELAY_STEP as u64);
+    let buffer = delay.div_ceil(update) + 1;
+    InterpHint {
+        update_ms: update.min(u16::MAX as u64) as u16,
+        delay_ms: delay.min(u16::MAX as u64) as u16,
+        buffer: buffer.min(u8::MAX as u64) as u8,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hints_follow_rate_and_jitter() {
+        let steady = hint(60, 100, 2, 0.0);
+        assert_eq!(
+            steady,
+            InterpHint {
+                update_ms: 17,
+                delay_ms: 40,
+                buffer: 4,
+            }
+        );
+        // Small jitter changes don't move it
+        assert_eq!(hint(60, 100, 2, 2.5), steady);
+
+        let jittery = hint(60, 100, 2, 30.0);
+        assert_eq!((jittery.delay_ms, jittery.buffer), (100, 7));
+        // Slow motion and degraded worlds update less often
+        assert_eq!(hint(20, 25, 2, 0.0).update_ms, 200);
+        assert_eq!(hint(10, 100, 2, 0.0).update_ms, 100);
+        assert_eq!(hint(20, 100, 0, 0.0).buffer, 1);
+    }
+}
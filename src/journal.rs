@@ -0,0 +1,213 @@
+//! World event journal: what changes in worlds (players joining and
+//! leaving, blocks set, entities spawned and removed, player properties,
+//! triggers crossed, rooms opened and closed, simulation changes, custom
+//! events players `Track`, distance traveled each second and achievements)
+//! as a stream of entries on a broadcast
+//! channel. Worlds only record, subsystems follow it on their own tasks:
+//! webhooks and `/admin/events` (`webhooks::follow`), analytics
+//! (`analytics.rs`), and `GET /admin/journal` for change-data capture
+//! outside the server.
+//!
+//! Entries are numbered in the order recorded, across worlds. Followers
+//! that fall more than `CAPACITY` entries behind skip ahead, and learn how
+//! many they missed from the gap in `seq` (or `Lagged`). Nothing is
+//! recorded while nobody follows, and nothing is kept: a follower sees
+//! what happens after it subscribes.
+
+use crate::claims::BlockPos;
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::broadcast;
+
+/// Entries a follower can fall behind before it skips.
+pub const CAPACITY: usize = 4096;
+
+#[derive(Serialize, Clone, PartialEq, Eq, Debug)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum JournalEvent {
+    PlayerJoined {
+        id: u32,
+        name: Option<String>,
+    },
+    PlayerLeft {
+        id: u32,
+        name: Option<String>,
+    },
+    /// By player `by`, `None` for the server (admin, rewinds).
+    BlockSet {
+        position: BlockPos,
+        from: u16,
+        to: u16,
+        by: Option<u32>,
+    },
+    EntitySpawned {
+        id: u32,
+        position: BlockPos,
+    },
+    EntityRemoved {
+        id: u32,
+    },
+    /// Properties set on player `id`'s record, the others stay.
+    PropertiesSet {
+        id: u32,
+        properties: HashMap<String, String>,
+    },
+    // See triggers.rs
+    TriggerEntered {
+        id: u32,
+        trigger: String,
+    },
+    TriggerExited {
+        id: u32,
+        trigger: String,
+    },
+    RoomCreated,
+    RoomDestroyed,
+    /// Paused or its time scale changed, see `simulation.rs`.
+    SimulationChanged {
+        paused: bool,
+        scale: u16,
+    },
+    /// Ticks a stall cost, see `simulation.rs`.
+    TimeSkipped {
+        ticks: u32,
+    },
+    /// From player `id`'s `Track` command, a gameplay event of the game's.
+    Custom {
+        id: u32,
+        name: String,
+        fields: BTreeMap<String, String>,
+    },
+    /// Whole blocks player `id` moved in the last second or so.
+    Traveled {
+        id: u32,
+        blocks: u32,
+    },
+    /// See achievements.rs
+    AchievementEarned {
+        id: u32,
+        name: String,
+    },
+}
+
+impl JournalEvent {
+    /// The `event` name entries have in JSON.
+    pub fn name(&self) -> &'static str {
+        match self {
+            JournalEvent::PlayerJoined { .. } => "player_joined",
+            JournalEvent::PlayerLeft { .. } => "player_left",
+            JournalEvent::BlockSet { .. } => "block_set",
+            JournalEvent::EntitySpawned { .. } => "entity_spawned",
+            JournalEvent::EntityRemoved { .. } => "entity_removed",
+            JournalEvent::PropertiesSet { .. } => "properties_set",
+            JournalEvent::TriggerEntered { .. } => "trigger_entered",
+            JournalEvent::TriggerExited { .. } => "trigger_exited",
+            JournalEvent::RoomCreated => "room_created",
+            JournalEvent::RoomDestroyed => "room_destroyed",
+            JournalEvent::SimulationChanged { .. } => "simulation_changed",
+            JournalEvent::TimeSkipped { .. } => "time_skipped",
+            JournalEvent::Custom { .. } => "custom",
+            JournalEvent::Traveled { .. } => "traveled",
+            JournalEvent::AchievementEarned { .. } => "achievement_earned",
+        }
+    }
+}
+
+#[derive(Serialize, Clone, PartialEq, Eq, Debug)]
+pub struct JournalEntry {
+    pub seq: u64,
+    /// Unix time in ms.
+    pub time: u64,
+    /// `main` or the room name.
+    pub world: String,
+    /// The world's tick, 0 for rooms opening.
+    pub tick: u64,
+    #[serde(flatten)]
+    pub event: JournalEvent,
+}
+
+pub struct Journal {
+    tx: broadcast::Sender<Arc<JournalEntry>>,
+    seq: AtomicU64,
+}
+
+impl Default for Journal {
+    fn default() -> Self {
+        Self {
+            tx: broadcast::channel(CAPACITY).0,
+            seq: AtomicU64::new(0),
+        }
+    }
+}
+
+impl Journal {
+    /// Entries recorded from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<JournalEntry>> {
+        self.tx.subscribe()
+    }
+
+    /// Whether anyone follows, so worlds can skip building entries.
+    pub fn is_followed(&self) -> bool {
+        self.tx.receiver_count() > 0
+    }
+
+    pub fn record(&self, world: &str, tick: u64, event: JournalEvent) {
+        if !self.is_followed() {
+            return;
+        }
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis() as u64);
+        let entry = JournalEntry {
+            seq: self.seq.fetch_add(1, Ordering::Relaxed),
+            time,
+            world: world.to_string(),
+            tick,
+            event,
+        };
+        self.tx.send(Arc::new(entry)).ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn followers_get_entries_in_order() {
+        let journal = Journal::default();
+        // Unfollowed, not even numbered
+        journal.record("main", 1, JournalEvent::RoomCreated);
+        assert!(!journal.is_followed());
+
+        let mut rx = journal.subscribe();
+        journal.record(
+            "main",
+            7,
+            JournalEvent::BlockSet {
+                position: (1, 2, 3),
+                from: 0,
+                to: 5,
+                by: Some(4),
+            },
+        );
+        journal.record("arena", 0, JournalEvent::RoomDestroyed);
+
+        let first = rx.try_recv().unwrap();
+        assert_eq!((first.seq, first.tick), (0, 7));
+        let json = serde_json::to_value(&*first).unwrap();
+        assert_eq!(json["event"], first.event.name());
+        assert_eq!(json["world"], "main");
+        assert_eq!(json["position"], serde_json::json!([1, 2, 3]));
+        let second = rx.try_recv().unwrap();
+        assert_eq!((second.seq, second.world.as_str()), (1, "arena"));
+        assert!(rx.try_recv().is_err());
+    }
+}
@@ -0,0 +1,280 @@
+//! Per-player outgoing lanes. What the world sends a player waits in one of
+//! four queues by priority, control > events > entities > chunks, and the
+//! connection writes the most urgent frame first: a burst of chunk
+//! snapshots can't hold back a teleport or a chat line. Replies to commands
+//! and kicks don't queue here at all, the connection writes them itself.
+//!
+//! Lower lanes aren't starved either. A lane with frames waiting is owed
+//! the bytes written ahead of it, and once that reaches `FAIR_SHARE` its
+//! next frame goes first. Each lane holds `LANE_LEN` frames, more are
+//! dropped like a full channel always was.
+//!
+//! Order is only kept within a lane, so everything about entities shares
+//! one (an `ENTITY_GONE` can't overtake the `ENTITY` before it) and so do
+//! chunk snapshots and deltas.
+//!
+//! A backed up player doesn't need every state an entity went through. An
+//! `ENTITY` update takes the place of the one for the same entity still
+//! waiting, unless a gone, mount or anything else not replaceable was
+//! queued after that one: then both are sent, so the first sight of an
+//! entity still comes before a mount onto it and a new one still comes
+//! after its `ENTITY_GONE`. Joins, gones, mounts and the rest are never
+//! dropped this way (`coalesced` in `GET /admin/world` counts the updates
+//! that were).
+
+use crate::protocol::ServerFrame;
+use bytes::Bytes;
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+use tokio::sync::{Notify, mpsc::error::TrySendError};
+
+/// Frames each lane holds.
+pub const LANE_LEN: usize = 128;
+/// Bytes written ahead of a waiting lane before it gets a frame in.
+pub const FAIR_SHARE: usize = 64 * 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Lane {
+    /// Where the player is and what it may do: teleports, rooms, transfers,
+    /// drains, features, the block registry, input acks, interpolation
+    /// hints.
+    Control,
+    /// Chat, relay, voice, effects, triggers, lockstep and rollback.
+    Events,
+    /// Entity state, mounts and attachments.
+    Entities,
+    /// Chunk snapshots and deltas.
+    Chunks,
+}
+
+const LANES: usize = 4;
+
+impl Lane {
+    /// The most urgent lane of the frame's submessages.
+    pub fn of(frame: &ServerFrame) -> Lane {
+        let kinds = frame.sizes().skip(1).map(|(kind, _)| kind);
+        kinds.map(Lane::of_kind).min().unwrap_or(Lane::Events)
+    }
+
+    fn of_kind(kind: &str) -> Lane {
+        match kind {
+            "teleport" | "room" | "transfer" | "drain" | "resume" | "features"
+            | "block_registry" | "input_ack" | "interp_hint" => Lane::Control,
+            "entity" | "entity_gone" | "mount" | "dismount" | "attach" | "detach" => Lane::Entities,
+            "chunk_snapshot" | "chunk_delta" => Lane::Chunks,
+            _ => Lane::Events,
+        }
+    }
+}
+
+struct Shared {
+    queues: Mutex<Queues>,
+    ready: Notify,
+}
+
+#[derive(Default)]
+struct Queues {
+    // Frames with the key they replace, if any
+    lanes: [VecDeque<(Option<u32>, Bytes)>; LANES],
+    // Either end dropped
+    closed: bool,
+    coalesced: u64,
+}
+
+/// Held by the world, one per player.
+pub struct Sender {
+    shared: Arc<Shared>,
+}
+
+/// Held by the player's connection.
+pub struct Receiver {
+    shared: Arc<Shared>,
+    // Bytes written ahead of each lane while it had frames waiting
+    owed: [usize; LANES],
+}
+
+pub fn channel() -> (Sender, Receiver) {
+    let shared = Arc::new(Shared {
+        queues: Mutex::new(Queues::default()),
+        ready: Notify::new(),
+    });
+    let receiver = Receiver {
+        shared: shared.clone(),
+        owed: [0; LANES],
+    };
+    (Sender { shared }, receiver)
+}
+
+impl Sender {
+    /// Fails if the lane is full or the connection is gone.
+    pub fn try_send(&self, lane: Lane, frame: Bytes) -> Result<(), TrySendError<Bytes>> {
+        self.push(lane, None, frame)
+    }
+
+    /// Like `try_send`, but takes the place of a frame still waiting with
+    /// the same `key` if only frames sent with a key came after it: for
+    /// state where only the newest counts, like an entity's.
+    pub fn try_send_latest(
+        &self,
+        lane: Lane,
+        key: u32,
+        frame: Bytes,
+    ) -> Result<(), TrySendError<Bytes>> {
+        self.push(lane, Some(key), frame)
+    }
+
+    /// Frames dropped for a newer one with their key so far.
+    pub fn coalesced(&self) -> u64 {
+        self.shared.queues.lock().unwrap().coalesced
+    }
+
+    fn push(&self, lane: Lane, key: Option<u32>, frame: Bytes) -> Result<(), TrySendError<Bytes>> {
+        let mut queues = self.shared.queues.lock().unwrap();
+        if queues.closed {
+            return Err(TrySendError::Closed(frame));
+        }
+        let queue = &mut queues.lanes[lane as usize];
+        let older = key.and_then(|key| queue.iter().rposition(|&(k, _)| k == Some(key)));
+        // Frames without a key after it may depend on it or undo it
+        let older = older.filter(|&i| queue.iter().skip(i + 1).all(|(k, _)| k.is_some()));
+        match older {
+            Some(i) => {
+                queue[i].1 = frame;
+                queues.coalesced += 1;
+            }
+            None if queue.len() >= LANE_LEN => return Err(TrySendError::Full(frame)),
+            None => queue.push_back((key, frame)),
+        }
+        drop(queues);
+        self.shared.ready.notify_one();
+        Ok(())
+    }
+}
+
+impl Drop for Sender {
+    fn drop(&mut self) {
+        self.shared.queues.lock().unwrap().closed = true;
+        self.shared.ready.notify_one();
+    }
+}
+
+impl Receiver {
+    /// The next frame to write, `None` once the world dropped the sender
+    /// and everything queued was written.
+    pub async fn recv(&mut self) -> Option<Bytes> {
+        loop {
+            if let Some(frame) = self.try_recv() {
+                return Some(frame);
+            }
+            if self.shared.queues.lock().unwrap().closed {
+                return None;
+            }
+            // A notify since `try_recv` keeps its permit, so none is missed
+            self.shared.ready.notified().await;
+        }
+    }
+
+    /// The next frame to write, if one is waiting.
+    pub fn try_recv(&mut self) -> Option<Bytes> {
+        let mut queues = self.shared.queues.lock().unwrap();
+        let waiting = |queues: &Queues, i: usize| !queues.lanes[i].is_empty();
+        let owed = (0..LANES).find(|&i| self.owed[i] >= FAIR_SHARE && waiting(&queues, i));
+        let lane = owed.or_else(|| (0..LANES).find(|&i| waiting(&queues, i)))?;
+        let (_, frame) = queues.lanes[lane].pop_front()?;
+
+        self.owed[lane] = 0;
+        for i in 0..LANES {
+            if i != lane && waiting(&queues, i) {
+                self.owed[i] += frame.len();
+            }
+        }
+        Some(frame)
+    }
+}
+
+impl Drop for Receiver {
+    fn drop(&mut self) {
+        self.shared.queues.lock().unwrap().closed = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn drains_by_priority_shares_and_coalesces() {
+        let mut chunks = ServerFrame::new(0);
+        chunks.chunk_snapshot((0, 0, 0), 1, &crate::chunk::Chunk::empty());
+        assert_eq!(Lane::of(&chunks), Lane::Chunks);
+        let mut mixed = ServerFrame::new(0);
+        mixed.chat("a", "hi");
+        mixed.teleport((0, 0, 0));
+        assert_eq!(Lane::of(&mixed), Lane::Control);
+
+        let (tx, mut rx) = channel();
+        let frame = |tag: u8, len: usize| Bytes::from(vec![tag; len]);
+        tx.try_send(Lane::Chunks, frame(3, 10)).unwrap();
+        tx.try_send(Lane::Events, frame(1, 10)).unwrap();
+        tx.try_send(Lane::Control, frame(0, 10)).unwrap();
+        for tag in [0, 1, 3] {
+            assert_eq!(rx.recv().await.unwrap()[0], tag);
+        }
+
+        // Events written ahead of waiting chunks earn them a turn
+        let big = FAIR_SHARE / 2;
+        for _ in 0..4 {
+            tx.try_send(Lane::Events, frame(1, big)).unwrap();
+        }
+        tx.try_send(Lane::Chunks, frame(3, 10)).unwrap();
+        let mut got = Vec::new();
+        for _ in 0..5 {
+            got.push(rx.recv().await.unwrap()[0]);
+        }
+        assert_eq!(got, [1, 1, 3, 1, 1]);
+
+        for _ in 0..LANE_LEN {
+            tx.try_send(Lane::Chunks, frame(3, 1)).unwrap();
+        }
+        assert!(tx.try_send(Lane::Chunks, frame(3, 1)).is_err());
+        assert!(tx.try_send(Lane::Control, frame(0, 1)).is_ok());
+        // Only the newest of a key waits, where the first one was
+        tx.try_send_latest(Lane::Events, 7, frame(1, 1)).unwrap();
+        tx.try_send_latest(Lane::Events, 8, frame(2, 1)).unwrap();
+        tx.try_send_latest(Lane::Events, 7, frame(4, 1)).unwrap();
+        assert_eq!(tx.coalesced(), 1);
+        drop(tx);
+        assert_eq!(rx.recv().await.unwrap()[0], 0);
+        assert_eq!(rx.recv().await.unwrap()[0], 4);
+        assert_eq!(rx.recv().await.unwrap()[0], 2);
+        for _ in 0..LANE_LEN {
+            rx.recv().await.unwrap();
+        }
+        assert_eq!(rx.recv().await, None);
+    }
+
+    #[tokio::test]
+    async fn keeps_entities_ahead_of_their_mounts() {
+        let (tx, mut rx) = channel();
+        let frame = |tag: u8| Bytes::from(vec![tag]);
+        // ENTITY, MOUNT onto it, ENTITY: the first sight stays first
+        tx.try_send_latest(Lane::Entities, 5, frame(1)).unwrap();
+        tx.try_send(Lane::Entities, frame(2)).unwrap();
+        tx.try_send_latest(Lane::Entities, 5, frame(3)).unwrap();
+        assert_eq!(tx.coalesced(), 0);
+        // Later updates replace the newest one, after the mount
+        tx.try_send_latest(Lane::Entities, 5, frame(4)).unwrap();
+        assert_eq!(tx.coalesced(), 1);
+        // And one after ENTITY_GONE comes after it
+        tx.try_send(Lane::Entities, frame(5)).unwrap();
+        tx.try_send_latest(Lane::Entities, 5, frame(6)).unwrap();
+        drop(tx);
+        let mut got = Vec::new();
+        while let Some(frame) = rx.recv().await {
+            got.push(frame[0]);
+        }
+        assert_eq!(got, [1, 2, 4, 5, 6]);
+    }
+}
@@ -0,0 +1,79 @@
+pub mod achievements;
+pub mod admin;
+pub mod admission;
+pub mod afk;
+pub mod agones;
+pub mod analytics;
+pub mod audit;
+pub mod auth;
+pub mod backup;
+pub mod blocking;
+pub mod blocks;
+pub mod brains;
+pub mod bridge;
+pub mod chat_commands;
+pub mod chunk;
+pub mod chunk_cache;
+pub mod chunk_wire;
+pub mod claims;
+pub mod cli;
+pub mod client;
+pub mod clock;
+pub mod command;
+pub mod config;
+pub mod console;
+pub mod control;
+pub mod coords;
+pub mod crash;
+pub mod drain;
+pub mod effects;
+pub mod entities;
+pub mod features;
+pub mod flood;
+pub mod grpc;
+pub mod history;
+pub mod http;
+pub mod input;
+pub mod interest;
+pub mod interp;
+pub mod journal;
+pub mod jwt;
+pub mod lanes;
+pub mod listeners;
+pub mod lockstep;
+pub mod minimap;
+pub mod origin;
+pub mod pathfinding;
+pub mod pool;
+pub mod portals;
+pub mod presence;
+pub mod profile;
+pub mod protocol;
+pub mod quotas;
+pub mod recorder;
+pub mod relay;
+pub mod relevance;
+pub mod reload;
+pub mod reservations;
+pub mod restart;
+pub mod resume;
+pub mod roles;
+pub mod rollback;
+pub mod save;
+pub mod secure;
+pub mod simulation;
+pub mod spatial;
+pub mod stats;
+pub mod storage;
+#[cfg(all(unix, feature = "systemd"))]
+pub mod systemd;
+pub mod telemetry;
+pub mod templates;
+pub mod tenants;
+pub mod terrain;
+pub mod traffic;
+pub mod triggers;
+pub mod usage;
+pub mod voice;
+pub mod vox;
+pub mod webhooks;
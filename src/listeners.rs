@@ -0,0 +1,631 @@
+//! Listeners: the sockets the server serves on, several at once, from a
+//! TOML file (`TELEBOXEL_LISTENERS`):
+//!
+//! ```toml
+//! [[listener]]
+//! tcp = "0.0.0.0:3000"                        # the LAN, plaintext
+//!
+//! [[listener]]
+//! tcp = "[::]:3001"
+//! v6only = true                               # dual-stack when unset
+//!
+//! [[listener]]
+//! tcp = "0.0.0.0:3443"
+//! tls = { cert = "cert.pem", key = "key.pem" } # `tls` feature
+//! routes = ["ws"]
+//!
+//! [[listener]]
+//! unix = "/run/teleboxel/admin.sock"           # Unix only
+//! routes = ["admin", "stats"]
+//! ```
+//!
+//! `routes` limits what a listener serves: `ws` (the game websocket at
+//! `/`), `stats`, `presence` and `admin` (with the gRPC admin service,
+//! which TLS listeners offer `h2` for), all of them when unset. Routes
+//! that aren't mounted at all (no admin token, say) aren't served anywhere.
+//! Without the file the server listens on `[::]:3000` alone, dual-stack, or
+//! `0.0.0.0:3000` on hosts without IPv6. Where each listener ended up is
+//! printed at startup and listed on `GET /admin/listeners`.
+//!
+//! Certificate and key are PEM files, relative to the listeners file. Unix
+//! socket peers have no address, they show as `127.0.0.1`. On a hot restart
+//! the successor takes every socket over, in file order, so the file
+//! shouldn't change in between. Under systemd socket activation (see
+//! `systemd.rs`), the passed sockets are the first listeners, in order.
+
+use crate::restart::{Accept, Handover};
+use axum::{Router, extract::connect_info::IntoMakeServiceWithConnectInfo};
+use serde::{Deserialize, Serialize};
+use std::{
+    error::Error,
+    fs, io,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    path::{Path, PathBuf},
+};
+use tokio::net::{TcpListener, TcpStream};
+
+/// What a listener can serve.
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Route {
+    Ws,
+    Stats,
+    Presence,
+    Admin,
+}
+
+const ROUTES: [Route; 4] = [Route::Ws, Route::Stats, Route::Presence, Route::Admin];
+
+impl Route {
+    pub fn name(self) -> &'static str {
+        match self {
+            Route::Ws => "ws",
+            Route::Stats => "stats",
+            Route::Presence => "presence",
+            Route::Admin => "admin",
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, PartialEq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct ListenerConfig {
+    pub tcp: Option<SocketAddr>,
+    pub unix: Option<PathBuf>,
+    pub tls: Option<TlsFiles>,
+    /// IPv6 only, rather than dual-stack, for an IPv6 `tcp` address.
+    pub v6only: Option<bool>,
+    pub routes: Option<Vec<Route>>,
+}
+
+#[derive(Deserialize, Clone, PartialEq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct TlsFiles {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+impl ListenerConfig {
+    fn tcp(addr: SocketAddr) -> Self {
+        Self {
+            tcp: Some(addr),
+            unix: None,
+            tls: None,
+            v6only: None,
+            routes: None,
+        }
+    }
+
+    pub fn serves(&self, route: Route) -> bool {
+        self.routes
+            .as_ref()
+            .is_none_or(|routes| routes.contains(&route))
+    }
+}
+
+impl std::fmt::Display for ListenerConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (&self.tcp, &self.unix) {
+            (Some(addr), _) if self.tls.is_some() => write!(f, "{addr} (TLS)"),
+            (Some(addr), _) => write!(f, "{addr}"),
+            (None, Some(path)) => write!(f, "{}", path.display()),
+            (None, None) => write!(f, "nowhere"),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ListenersFile {
+    #[serde(default)]
+    listener: Vec<ListenerConfig>,
+}
+
+/// Listeners in file order.
+pub struct Listeners {
+    pub list: Vec<ListenerConfig>,
+}
+
+impl Default for Listeners {
+    fn default() -> Self {
+        Self {
+            list: vec![ListenerConfig::tcp(SocketAddr::from((
+                Ipv6Addr::UNSPECIFIED,
+                3000,
+            )))],
+        }
+    }
+}
+
+impl Listeners {
+    /// See the module docs for the file format.
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let mut listeners = Self::parse(&fs::read_to_string(path)?)?;
+        let dir = path.parent().unwrap_or(Path::new(""));
+        for tls in listeners.list.iter_mut().filter_map(|l| l.tls.as_mut()) {
+            tls.cert = dir.join(&tls.cert);
+            tls.key = dir.join(&tls.key);
+        }
+        Ok(listeners)
+    }
+
+    fn parse(text: &str) -> Result<Self, Box<dyn Error>> {
+        let file: ListenersFile = toml::from_str(text)?;
+        if file.listener.is_empty() {
+            return Err("no listeners".into());
+        }
+        for listener in &file.listener {
+            match (&listener.tcp, &listener.unix) {
+                (Some(_), None) => {}
+                (None, Some(_)) if cfg!(unix) => {}
+                (None, Some(path)) => {
+                    return Err(format!("{}: Unix sockets need Unix", path.display()).into());
+                }
+                _ => return Err("a listener needs one of tcp or unix".into()),
+            }
+            if listener.v6only.is_some() && !listener.tcp.is_some_and(|addr| addr.is_ipv6()) {
+                return Err(format!("{listener}: v6only needs an IPv6 address").into());
+            }
+            if listener.tls.is_some() && listener.tcp.is_none() {
+                return Err(format!("{listener}: TLS needs tcp").into());
+            }
+            if listener.tls.is_some() && !cfg!(feature = "tls") {
+                return Err(format!("{listener}: TLS needs the `tls` feature").into());
+            }
+            if listener.routes.as_ref().is_some_and(Vec::is_empty) {
+                return Err(format!("{listener}: no routes").into());
+            }
+        }
+        Ok(Self {
+            list: file.listener,
+        })
+    }
+}
+
+/// A bound listener, ready to serve.
+pub enum Socket {
+    Tcp(std::net::TcpListener),
+    #[cfg(feature = "tls")]
+    Tls(std::net::TcpListener, tokio_rustls::TlsAcceptor),
+    #[cfg(unix)]
+    Unix(std::os::unix::net::UnixListener),
+}
+
+impl Socket {
+    /// For the hot restart's successor, -1 without Unix.
+    pub fn fd(&self) -> i32 {
+        #[cfg(unix)]
+        {
+            use std::os::fd::AsRawFd;
+            match self {
+                Socket::Tcp(listener) => listener.as_raw_fd(),
+                #[cfg(feature = "tls")]
+                Socket::Tls(listener, _) => listener.as_raw_fd(),
+                Socket::Unix(listener) => listener.as_raw_fd(),
+            }
+        }
+        #[cfg(not(unix))]
+        -1
+    }
+}
+
+/// Where a listener ended up, for the startup log and
+/// `GET /admin/listeners`.
+#[derive(Serialize, Clone, PartialEq, Debug)]
+pub struct Listening {
+    /// `ip:port`, with the port a `0` was given, or the socket path.
+    pub address: String,
+    /// `dual-stack`, `ipv6`, `ipv4` or `unix`.
+    pub family: &'static str,
+    pub tls: bool,
+    pub routes: Vec<Route>,
+}
+
+impl std::fmt::Display for Listening {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let tls = if self.tls { ", TLS" } else { "" };
+        let routes = self.routes.iter().map(|r| r.name()).collect::<Vec<_>>();
+        let routes = routes.join(" ");
+        write!(f, "{} ({}{tls}): {routes}", self.address, self.family)
+    }
+}
+
+impl Socket {
+    pub fn listening(&self, listener: &ListenerConfig) -> io::Result<Listening> {
+        let tcp = match self {
+            Socket::Tcp(socket) => Some(socket),
+            #[cfg(feature = "tls")]
+            Socket::Tls(socket, _) => Some(socket),
+            #[cfg(unix)]
+            Socket::Unix(_) => None,
+        };
+        let (address, family) = match tcp {
+            Some(socket) => {
+                let addr = socket.local_addr()?;
+                let family = match addr {
+                    SocketAddr::V4(_) => "ipv4",
+                    _ if socket2::SockRef::from(socket).only_v6()? => "ipv6",
+                    _ => "dual-stack",
+                };
+                (addr.to_string(), family)
+            }
+            None => {
+                let path = listener.unix.as_deref().unwrap_or(Path::new(""));
+                (path.display().to_string(), "unix")
+            }
+        };
+        let routes = ROUTES.into_iter().filter(|&r| listener.serves(r)).collect();
+        Ok(Listening {
+            address,
+            family,
+            tls: listener.tls.is_some(),
+            routes,
+        })
+    }
+}
+
+/// The sockets inherited from the previous server, or passed by systemd, or
+/// new ones, one per listener.
+pub fn bind(listeners: &[ListenerConfig]) -> Result<Vec<Socket>, Box<dyn Error>> {
+    let inherited = crate::restart::inherited();
+    if let Some(fds) = &inherited
+        && fds.len() != listeners.len()
+    {
+        let (had, has) = (fds.len(), listeners.len());
+        return Err(format!("inherited {had} sockets for {has} listeners").into());
+    }
+    let passed = inherited.unwrap_or_else(activated);
+
+    let mut sockets = Vec::new();
+    for (i, listener) in listeners.iter().enumerate() {
+        let fd = passed.get(i).copied();
+        let socket = match (&listener.tcp, &listener.unix) {
+            (Some(addr), _) => tcp_socket(listener, *addr, fd),
+            #[cfg(unix)]
+            (None, Some(path)) => unix_socket(path, fd),
+            _ => Err("not supported here".into()),
+        };
+        sockets.push(socket.map_err(|e| format!("{listener}: {e}"))?);
+    }
+    Ok(sockets)
+}
+
+fn tcp_socket(
+    listener: &ListenerConfig,
+    addr: SocketAddr,
+    fd: Option<i32>,
+) -> Result<Socket, Box<dyn Error>> {
+    let socket = match fd {
+        #[cfg(unix)]
+        Some(fd) => {
+            use std::os::fd::FromRawFd;
+            // Ours alone: the previous server closes its copy when it exits
+            unsafe { std::net::TcpListener::from_raw_fd(fd) }
+        }
+        _ => bind_tcp(addr, listener.v6only)?,
+    };
+    socket.set_nonblocking(true)?;
+    Ok(match &listener.tls {
+        #[cfg(feature = "tls")]
+        Some(files) => Socket::Tls(socket, tls::acceptor(files, listener.serves(Route::Admin))?),
+        _ => Socket::Tcp(socket),
+    })
+}
+
+#[cfg(unix)]
+fn unix_socket(path: &Path, fd: Option<i32>) -> Result<Socket, Box<dyn Error>> {
+    let socket = match fd {
+        Some(fd) => {
+            use std::os::fd::FromRawFd;
+            unsafe { std::os::unix::net::UnixListener::from_raw_fd(fd) }
+        }
+        None => bind_unix(path)?,
+    };
+    socket.set_nonblocking(true)?;
+    Ok(Socket::Unix(socket))
+}
+
+/// A TCP socket on `addr`, IPv6 only or dual-stack as asked (dual-stack
+/// when unset). `[::]` falls back to `0.0.0.0` on hosts without IPv6,
+/// unless it's IPv6 only.
+pub fn bind_tcp(addr: SocketAddr, v6only: Option<bool>) -> io::Result<std::net::TcpListener> {
+    let bound = listen_tcp(addr, v6only.unwrap_or(false));
+    match bound {
+        Err(e) if ipv4_instead(addr, v6only, &e) => listen_tcp(
+            SocketAddr::from((Ipv4Addr::UNSPECIFIED, addr.port())),
+            false,
+        ),
+        bound => bound,
+    }
+}
+
+// Whether binding `addr` failing with `e` falls back to `0.0.0.0`
+fn ipv4_instead(addr: SocketAddr, v6only: Option<bool>, e: &io::Error) -> bool {
+    no_ipv6(e) && addr.ip() == Ipv6Addr::UNSPECIFIED && v6only != Some(true)
+}
+
+fn listen_tcp(addr: SocketAddr, v6only: bool) -> io::Result<std::net::TcpListener> {
+    use socket2::{Domain, Protocol, Type};
+    let socket =
+        socket2::Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(v6only)?;
+    }
+    // As std does, a restarted server rebinds past TIME_WAIT connections
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+    Ok(socket.into())
+}
+
+// IPv6 disabled or not built in
+fn no_ipv6(e: &io::Error) -> bool {
+    #[cfg(unix)]
+    if e.raw_os_error() == Some(libc::EAFNOSUPPORT) {
+        return true;
+    }
+    e.kind() == io::ErrorKind::AddrNotAvailable
+}
+
+// Socket activation, with the `systemd` feature
+fn activated() -> Vec<i32> {
+    #[cfg(all(unix, feature = "systemd"))]
+    return crate::systemd::listen_fds();
+    #[cfg(not(all(unix, feature = "systemd")))]
+    Vec::new()
+}
+
+/// A Unix socket at `path`, replacing a stale one, refusing one another
+/// server listens on.
+#[cfg(unix)]
+pub fn bind_unix(path: &Path) -> io::Result<std::os::unix::net::UnixListener> {
+    if path.exists() {
+        if std::os::unix::net::UnixStream::connect(path).is_ok() {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                "another server is listening",
+            ));
+        }
+        fs::remove_file(path)?;
+    }
+    std::os::unix::net::UnixListener::bind(path)
+}
+
+/// Serves `app` on `socket` until `shutdown`, or the handover.
+pub async fn serve<F>(
+    socket: Socket,
+    handover: &Handover,
+    app: IntoMakeServiceWithConnectInfo<Router, SocketAddr>,
+    shutdown: F,
+) -> io::Result<()>
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    use axum::serve::ListenerExt;
+    // `tap_io` is a no-op, axum only has the `ConnectInfo` address for
+    // plain and tapped listeners
+    match socket {
+        Socket::Tcp(listener) => {
+            let listener = handover.listener(TcpListener::from_std(listener)?);
+            let listener = listener.tap_io(|_| {});
+            axum::serve(listener, app)
+                .with_graceful_shutdown(shutdown)
+                .await
+        }
+        #[cfg(feature = "tls")]
+        Socket::Tls(listener, acceptor) => {
+            let listener = tls::TlsListener::new(TcpListener::from_std(listener)?, acceptor);
+            let listener = handover.listener(listener).tap_io(|_| {});
+            axum::serve(listener, app)
+                .with_graceful_shutdown(shutdown)
+                .await
+        }
+        #[cfg(unix)]
+        Socket::Unix(listener) => {
+            let listener = tokio::net::UnixListener::from_std(listener)?;
+            let listener = handover.listener(listener).tap_io(|_| {});
+            axum::serve(listener, app)
+                .with_graceful_shutdown(shutdown)
+                .await
+        }
+    }
+}
+
+impl Accept for TcpListener {
+    type Io = TcpStream;
+
+    // Dual-stack sockets see IPv4 peers as `::ffff:a.b.c.d`
+    async fn accept(&mut self) -> io::Result<(TcpStream, SocketAddr)> {
+        let (stream, addr) = TcpListener::accept(self).await?;
+        Ok((
+            stream,
+            SocketAddr::new(addr.ip().to_canonical(), addr.port()),
+        ))
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        TcpListener::local_addr(self)
+    }
+}
+
+// Peers are local, with no address of their own
+#[cfg(unix)]
+impl Accept for tokio::net::UnixListener {
+    type Io = tokio::net::UnixStream;
+
+    async fn accept(&mut self) -> io::Result<(Self::Io, SocketAddr)> {
+        let (stream, _) = tokio::net::UnixListener::accept(self).await?;
+        Ok((stream, SocketAddr::from(([127, 0, 0, 1], 0))))
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(SocketAddr::from(([127, 0, 0, 1], 0)))
+    }
+}
+
+/// The routers of each `Route` that's mounted, put together per listener.
+#[derive(Default)]
+pub struct Routes {
+    routers: Vec<(Route, Router)>,
+}
+
+impl Routes {
+    pub fn add(&mut self, route: Route, router: Router) {
+        self.routers.push((route, router));
+    }
+
+    pub fn app(&self, listener: &ListenerConfig) -> Router {
+        self.routers
+            .iter()
+            .filter(|(route, _)| listener.serves(*route))
+            .fold(Router::new(), |app, (_, router)| app.merge(router.clone()))
+    }
+}
+
+#[cfg(feature = "tls")]
+mod tls {
+    use super::{Accept, TlsFiles};
+    use std::{error::Error, io, net::SocketAddr, sync::Arc, time::Duration};
+    use tokio::{net::TcpListener, task::JoinSet};
+    use tokio_rustls::{
+        TlsAcceptor,
+        rustls::{
+            ServerConfig,
+            pki_types::{CertificateDer, PrivateKeyDer, pem::PemObject},
+        },
+        server::TlsStream,
+    };
+
+    // A client that doesn't finish its handshake by then is dropped
+    const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+    /// `h2` for the gRPC admin service, offered along with HTTP/1.1.
+    pub fn acceptor(files: &TlsFiles, h2: bool) -> Result<TlsAcceptor, Box<dyn Error>> {
+        let certs = CertificateDer::pem_file_iter(&files.cert)?.collect::<Result<Vec<_>, _>>()?;
+        let key = PrivateKeyDer::from_pem_file(&files.key)?;
+        let mut config = ServerConfig::builder()
+            .with_no_client_auth()
+            .with_single_cert(certs, key)?;
+        if h2 {
+            config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+        }
+        Ok(TlsAcceptor::from(Arc::new(config)))
+    }
+
+    /// Accepts TCP connections and hands them out once their handshake is
+    /// done, without slow ones holding up the rest.
+    pub struct TlsListener {
+        listener: TcpListener,
+        acceptor: TlsAcceptor,
+        handshakes: JoinSet<Option<(TlsStream<tokio::net::TcpStream>, SocketAddr)>>,
+    }
+
+    impl TlsListener {
+        pub fn new(listener: TcpListener, acceptor: TlsAcceptor) -> Self {
+            Self {
+                listener,
+                acceptor,
+                handshakes: JoinSet::new(),
+            }
+        }
+    }
+
+    impl Accept for TlsListener {
+        type Io = TlsStream<tokio::net::TcpStream>;
+
+        async fn accept(&mut self) -> io::Result<(Self::Io, SocketAddr)> {
+            loop {
+                tokio::select! {
+                    accepted = Accept::accept(&mut self.listener) => {
+                        let (stream, addr) = accepted?;
+                        let handshake = self.acceptor.accept(stream);
+                        self.handshakes.spawn(async move {
+                            let stream = tokio::time::timeout(HANDSHAKE_TIMEOUT, handshake).await;
+                            Some((stream.ok()?.ok()?, addr))
+                        });
+                    }
+                    Some(Ok(Some(done))) = self.handshakes.join_next() => return Ok(done),
+                }
+            }
+        }
+
+        fn local_addr(&self) -> io::Result<SocketAddr> {
+            self.listener.local_addr()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_listeners_and_routes() {
+        let listeners = Listeners::parse(
+            r#"
+            [[listener]]
+            tcp = "0.0.0.0:3000"
+
+            [[listener]]
+            tcp = "[::1]:3001"
+            routes = ["admin", "stats"]
+            "#,
+        )
+        .unwrap();
+        let (public, admin) = (&listeners.list[0], &listeners.list[1]);
+        assert!(public.serves(Route::Ws) && public.serves(Route::Admin));
+        assert!(admin.serves(Route::Admin) && !admin.serves(Route::Ws));
+        assert_eq!(admin.to_string(), "[::1]:3001");
+
+        assert!(Listeners::parse("").is_err());
+        assert!(Listeners::parse("[[listener]]\nroutes = [\"ws\"]").is_err());
+        assert!(Listeners::parse("[[listener]]\ntcp = \"0.0.0.0:1\"\nroutes = []").is_err());
+        assert!(Listeners::parse("[[listener]]\ntcp = \"0.0.0.0:1\"\nroutes = [\"x\"]").is_err());
+        let tls_on_unix = "[[listener]]\nunix = \"a.sock\"\ntls = { cert = \"c\", key = \"k\" }";
+        assert!(Listeners::parse(tls_on_unix).is_err());
+        assert!(Listeners::parse("[[listener]]\ntcp = \"0.0.0.0:1\"\nv6only = true").is_err());
+        assert!(Listeners::parse("[[listener]]\ntcp = \"[::]:1\"\nv6only = true").is_ok());
+
+        // Dual-stack, or IPv4 on a host without IPv6, with the port it got
+        let mut any = ListenerConfig::tcp("[::]:0".parse().unwrap());
+        any.routes = Some(vec![Route::Ws]);
+        let socket = Socket::Tcp(bind_tcp(any.tcp.unwrap(), None).unwrap());
+        let bound = socket.listening(&any).unwrap();
+        assert!(["dual-stack", "ipv4"].contains(&bound.family), "{bound}");
+        assert!(!bound.address.ends_with(":0"));
+        assert_eq!(bound.routes, [Route::Ws]);
+        let local = ListenerConfig::tcp("127.0.0.1:0".parse().unwrap());
+        let socket = Socket::Tcp(bind_tcp(local.tcp.unwrap(), None).unwrap());
+        assert_eq!(socket.listening(&local).unwrap().family, "ipv4");
+    }
+
+    #[test]
+    fn binds_ipv6_only_or_dual_stack() {
+        let any: SocketAddr = "[::]:0".parse().unwrap();
+        let Ok(only) = bind_tcp(any, Some(true)) else {
+            // No IPv6 here, and IPv6 only doesn't fall back
+            return;
+        };
+        let config = ListenerConfig::tcp(any);
+        let listening = |socket| Socket::Tcp(socket).listening(&config).unwrap().family;
+        let port = only.local_addr().unwrap().port();
+        assert_eq!(listening(only), "ipv6");
+        // Not reachable over IPv4, unlike dual-stack
+        assert!(std::net::TcpStream::connect(("127.0.0.1", port)).is_err());
+        let dual = bind_tcp(any, Some(false)).unwrap();
+        let port = dual.local_addr().unwrap().port();
+        assert_eq!(listening(dual.try_clone().unwrap()), "dual-stack");
+        assert!(std::net::TcpStream::connect(("127.0.0.1", port)).is_ok());
+        assert_eq!(listening(bind_tcp(any, None).unwrap()), "dual-stack");
+
+        // Hosts without IPv6 get IPv4 for `[::]`, unless it's IPv6 only
+        let no_ipv6 = io::Error::from(io::ErrorKind::AddrNotAvailable);
+        assert!(ipv4_instead(any, None, &no_ipv6));
+        assert!(ipv4_instead(any, Some(false), &no_ipv6));
+        assert!(!ipv4_instead(any, Some(true), &no_ipv6));
+        assert!(!ipv4_instead("[::1]:0".parse().unwrap(), None, &no_ipv6));
+        let in_use = io::Error::from(io::ErrorKind::AddrInUse);
+        assert!(!ipv4_instead(any, None, &in_use));
+    }
+}
@@ -0,0 +1,283 @@
+//! Lockstep rooms (`RoomCreate Name lockstep`) for deterministic clients,
+//! RTS-style: the server doesn't simulate them, it relays inputs. Players
+//! send `Input Tick Data` for numbered relay ticks, starting at 1. A tick
+//! closes once every player has sent theirs, or after `MAX_WAIT` server
+//! ticks without the missing ones, and goes out to everyone as `LOCKSTEP`:
+//! the inputs in player id order and their hash, so clients can check they
+//! all step the same inputs.
+//!
+//! Players joining late get `LOCKSTEP_STATE`, the latest state a client
+//! uploaded with `LockstepState Tick Data` (tick 0 and empty until then),
+//! followed by every tick closed since. Uploading now and then keeps that
+//! catch-up short; past `MAX_BACKLOG` ticks the oldest are dropped, and
+//! joiners see the gap. Joiners are waited for from the tick after the open
+//! one, which their catch-up ends just before.
+
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fmt,
+};
+
+/// Longest input, in bytes.
+pub const MAX_INPUT_LEN: usize = 200;
+/// Largest uploaded state, in bytes.
+pub const MAX_STATE_LEN: usize = 1 << 20;
+/// How far past the open tick inputs may be sent.
+pub const MAX_AHEAD: u32 = 64;
+/// Server ticks the open tick waits for missing inputs.
+pub const MAX_WAIT: u32 = 30;
+/// Closed ticks kept for joiners since the latest state.
+pub const MAX_BACKLOG: usize = 3600;
+
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct LockstepInput {
+    pub player: u32,
+    pub data: String,
+}
+
+/// A closed tick.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct LockstepTick {
+    pub tick: u32,
+    pub hash: u32,
+    pub inputs: Vec<LockstepInput>,
+}
+
+#[derive(PartialEq, Eq, Debug)]
+pub enum LockstepError {
+    /// The tick already closed.
+    Late {
+        open: u32,
+    },
+    /// More than `MAX_AHEAD` past the open tick.
+    TooFar {
+        open: u32,
+    },
+    Twice,
+    TooLong,
+    /// States are for closed ticks, no older than the latest one.
+    StateTick {
+        open: u32,
+        latest: u32,
+    },
+}
+
+impl fmt::Display for LockstepError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LockstepError::Late { open } => write!(f, "Too late, tick {open} is open"),
+            LockstepError::TooFar { open } => {
+                write!(
+                    f,
+                    "Too far ahead, tick {open} is open (max {MAX_AHEAD} ahead)"
+                )
+            }
+            LockstepError::Twice => write!(f, "Already sent input for that tick"),
+            LockstepError::TooLong => write!(f, "Too long"),
+            LockstepError::StateTick { open, latest } => {
+                write!(f, "State tick must be from {latest} to {}", open - 1)
+            }
+        }
+    }
+}
+
+pub struct Lockstep {
+    // The tick inputs are being collected for
+    open: u32,
+    // Server ticks it has waited
+    waited: u32,
+    // Player id and the first tick they're waited for
+    players: BTreeMap<u32, u32>,
+    // By tick, then player id
+    inputs: BTreeMap<u32, BTreeMap<u32, String>>,
+    state: (u32, Vec<u8>),
+    // Closed since the state
+    backlog: VecDeque<LockstepTick>,
+}
+
+impl Default for Lockstep {
+    fn default() -> Self {
+        Self {
+            open: 1,
+            waited: 0,
+            players: BTreeMap::new(),
+            inputs: BTreeMap::new(),
+            state: (0, Vec::new()),
+            backlog: VecDeque::new(),
+        }
+    }
+}
+
+impl Lockstep {
+    /// Adds player `id`, with what it needs to catch up: the latest state's
+    /// tick and data, and the ticks closed since.
+    pub fn join(&mut self, id: u32) -> (u32, &[u8], impl Iterator<Item = &LockstepTick>) {
+        self.players.insert(id, self.open + 1);
+        (self.state.0, &self.state.1, self.backlog.iter())
+    }
+
+    /// Stops waiting for player `id`. Inputs it already sent still count.
+    pub fn leave(&mut self, id: u32) {
+        self.players.remove(&id);
+    }
+
+    pub fn input(&mut self, id: u32, tick: u32, data: String) -> Result<(), LockstepError> {
+        let open = self.open;
+        if data.len() > MAX_INPUT_LEN {
+            return Err(LockstepError::TooLong);
+        }
+        if tick < open {
+            return Err(LockstepError::Late { open });
+        }
+        if tick - open > MAX_AHEAD {
+            return Err(LockstepError::TooFar { open });
+        }
+        let inputs = self.inputs.entry(tick).or_default();
+        if inputs.contains_key(&id) {
+            return Err(LockstepError::Twice);
+        }
+        inputs.insert(id, data);
+        Ok(())
+    }
+
+    /// Replaces the state joiners start from.
+    pub fn upload_state(&mut self, tick: u32, data: Vec<u8>) -> Result<(), LockstepError> {
+        let (open, latest) = (self.open, self.state.0);
+        if data.len() > MAX_STATE_LEN {
+            return Err(LockstepError::TooLong);
+        }
+        if tick >= open || tick < latest {
+            return Err(LockstepError::StateTick { open, latest });
+        }
+        self.state = (tick, data);
+        self.backlog.retain(|t| t.tick > tick);
+        Ok(())
+    }
+
+    /// Called every server tick, returns the ticks that closed: the open
+    /// one and any after it with every input in, or the open one alone
+    /// once it has waited `MAX_WAIT` server ticks.
+    pub fn advance(&mut self) -> Vec<LockstepTick> {
+        let mut closed = Vec::new();
+        if self.players.is_empty() {
+            return closed;
+        }
+        self.waited += 1;
+        while self.ready() || self.waited >= MAX_WAIT {
+            closed.push(self.close());
+        }
+        closed
+    }
+
+    fn ready(&self) -> bool {
+        let inputs = self.inputs.get(&self.open);
+        self.players
+            .iter()
+            .filter(|&(_, &from)| from <= self.open)
+            .all(|(id, _)| inputs.is_some_and(|inputs| inputs.contains_key(id)))
+    }
+
+    fn close(&mut self) -> LockstepTick {
+        let inputs: Vec<_> = self
+            .inputs
+            .remove(&self.open)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(player, data)| LockstepInput { player, data })
+            .collect();
+        let tick = LockstepTick {
+            tick: self.open,
+            hash: hash(self.open, &inputs),
+            inputs,
+        };
+        if self.backlog.len() == MAX_BACKLOG {
+            self.backlog.pop_front();
+        }
+        self.backlog.push_back(tick.clone());
+        self.open += 1;
+        self.waited = 0;
+        tick
+    }
+}
+
+/// 32-bit FNV-1a of the tick (u32 LE), then each input's player (u32 LE),
+/// data length (u8) and data.
+pub fn hash(tick: u32, inputs: &[LockstepInput]) -> u32 {
+    let mut h = fnv(0x811c_9dc5, &tick.to_le_bytes());
+    for input in inputs {
+        h = fnv(h, &input.player.to_le_bytes());
+        h = fnv(h, &[input.data.len() as u8]);
+        h = fnv(h, input.data.as_bytes());
+    }
+    h
+}
+
+fn fnv(mut h: u32, bytes: &[u8]) -> u32 {
+    for &b in bytes {
+        h ^= b as u32;
+        h = h.wrapping_mul(0x0100_0193);
+    }
+    h
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn relays_inputs_behind_tick_barriers() {
+        let mut relay = Lockstep::default();
+        assert!(relay.advance().is_empty());
+
+        // Nobody is waited for on the open tick yet
+        assert_eq!(relay.join(1).0, 0);
+        assert_eq!(relay.advance()[0].tick, 1);
+        assert_eq!(relay.join(2).2.count(), 1);
+        relay.input(1, 2, "move 3 4".into()).unwrap();
+        assert_eq!(relay.input(1, 2, "again".into()), Err(LockstepError::Twice));
+        assert_eq!(
+            relay.input(1, 1, String::new()),
+            Err(LockstepError::Late { open: 2 })
+        );
+        assert_eq!(
+            relay.input(1, 99, String::new()),
+            Err(LockstepError::TooFar { open: 2 })
+        );
+
+        // Player 2 is only waited for from tick 3
+        let closed = relay.advance();
+        assert_eq!(closed.len(), 1);
+        let inputs = vec![LockstepInput {
+            player: 1,
+            data: "move 3 4".into(),
+        }];
+        assert_eq!(closed[0].inputs, inputs);
+        assert_eq!(closed[0].hash, hash(2, &inputs));
+        assert_ne!(closed[0].hash, hash(3, &inputs));
+
+        relay.input(1, 3, String::new()).unwrap();
+        assert!(relay.advance().is_empty());
+        relay.input(2, 3, "build".into()).unwrap();
+        relay.input(1, 4, String::new()).unwrap();
+        relay.input(2, 4, String::new()).unwrap();
+        let closed = relay.advance();
+        assert_eq!(closed.iter().map(|t| t.tick).collect::<Vec<_>>(), [3, 4]);
+        assert_eq!(closed[0].inputs[1].data, "build");
+
+        // Player 2 stalls, tick 5 closes without it
+        relay.input(1, 5, String::new()).unwrap();
+        for _ in 1..MAX_WAIT {
+            assert!(relay.advance().is_empty());
+        }
+        assert_eq!(relay.advance()[0].inputs.len(), 1);
+
+        // Joiners catch up from the latest state
+        assert!(relay.upload_state(6, vec![1]).is_err());
+        relay.upload_state(3, vec![7, 7]).unwrap();
+        let (tick, state, backlog) = relay.join(3);
+        assert_eq!((tick, state), (3, &[7, 7][..]));
+        assert_eq!(backlog.map(|t| t.tick).collect::<Vec<_>>(), [4, 5]);
+        assert!(relay.upload_state(2, Vec::new()).is_err());
+    }
+}
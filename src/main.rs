@@ -1,249 +1,5908 @@
-use axum::{Router, extract::State, response::IntoResponse, routing::get};
-use bytes::Bytes;
+use axum::{
+    Router,
+    extract::{ConnectInfo, Query, State},
+    http::StatusCode,
+    http::{
+        HeaderMap, HeaderValue,
+        header::{AUTHORIZATION, RETRY_AFTER, SEC_WEBSOCKET_PROTOCOL},
+    },
+    response::IntoResponse,
+    response::Response,
+    routing::get,
+};
 use fastwebsockets::{FragmentCollector, Frame, OpCode, Payload, WebSocketError, upgrade};
 use std::{
-    collections::HashMap,
-    io::{Error as IoError, ErrorKind},
-    time::Duration,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    io::{Error as IoError, IsTerminal},
+    net::SocketAddr,
+    path::PathBuf,
+    process::ExitCode,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+#[cfg(all(unix, feature = "systemd"))]
+use teleboxel::systemd;
+use teleboxel::{
+    achievements::{self, Achievement, AchievementRules},
+    admin::{self, AdminState, BoxFuture, PlayerState, RestartError, WorldControl, WorldState},
+    admission::{Admission, Admitted},
+    afk::{Activity, AfkAction, AfkRules},
+    agones::Agones,
+    analytics::Analytics,
+    audit::{AuditEvent, AuditLog},
+    auth::{AuthError, Authenticator, Credentials, HttpAuthenticator, Identity},
+    backup::Backups,
+    blocking::{self, Blocked},
+    blocks::BlockRegistry,
+    brains::{self, Scheduler, Thought, Walker},
+    bridge::{self, BridgeConfig, BridgeEvent, Bridges},
+    chat_commands::{Call, ChatCommand, ChatCommands, CommandWorld, Run},
+    chunk::{self, ChunkPos},
+    chunk_cache::{CacheConfig, ChunkCache, Saves},
+    chunk_wire::ChunkFormat,
+    claims::{BlockPos, Claims},
+    cli,
+    clock::{self, ClockEstimate, ClockSync},
+    command::{self, Command, RoomMode},
+    config::{
+        Config, GeneratorKind, HistoryConfig, InputConfig, ProfileConfig, Tunables, Vars,
+        VoiceConfig,
+    },
+    console::Console,
+    control::{self, Control},
+    coords::{Coords, CoordsRules},
+    crash::{self, Context},
+    drain::Drain,
+    effects::{self, Effect},
+    entities::{Attachment, Entities, Entity, EntityError, EntityOp, EntityReply, Parent},
+    features::{FeatureFlags, Features, Toggles},
+    flood::{Action, Flood, FloodGuard, Verdict},
+    grpc,
+    history::{
+        BlockAt, Change, History, HistoryError, HistoryQuery, HistoryReply, PlayerAt, Rewound,
+        WorldAt,
+    },
+    input::InputQueue,
+    interest::{self, Reach},
+    interp::{self, InterpHint},
+    journal::{Journal, JournalEvent},
+    jwt::Jwt,
+    lanes::{self, Lane},
+    listeners::{self, Listeners, Routes, Socket},
+    lockstep::Lockstep,
+    minimap::{Minimap, Surfaces},
+    origin,
+    pathfinding::{PathError, Pathfinder, Route, Terrain},
+    pool::{self, RoomPools},
+    portals::{Destination, Portal, Portals},
+    presence::{self, Online, Presence, PresenceState, Privacy},
+    profile::{Phase, Profiler},
+    protocol::{self, ClientMsg, Encoding, FramePool, JsonMessage, ServerFrame},
+    quotas::{Meter, QuotaAction, Quotas},
+    recorder::Recorder,
+    relay,
+    relevance::{self, Relevance, RelevanceRule, RelevanceRules, Viewer},
+    reload,
+    reservations::Reservations,
+    restart::{self, Handover},
+    resume::{ResumeKey, Session},
+    roles::{Mutes, Permission, Role},
+    rollback::{self, Rollback},
+    secure::{self, Offer, Policy, Protection, SecureSocket, ServerKey},
+    simulation::{CatchUp, Simulation, SimulationChange, SimulationState, catch_up},
+    spatial::SpatialKind,
+    stats::{self, PlayerStats, StatsState},
+    storage::{self, PlayerRecord, SaveQueue, StatDelta, Storage},
+    telemetry::Telemetry,
+    templates::{Template, Templates},
+    tenants::{self, Tenants},
+    terrain::{ChunkGenerator, FlatGenerator, NoiseGenerator},
+    traffic::{Dir, PlayerTraffic, Traffic},
+    triggers::Triggers,
+    usage::UsageLog,
+    voice::{self, Bitrate},
+    webhooks::{self, WebhookEvent, Webhooks},
 };
 use tokio::{
     select,
-    sync::{mpsc, oneshot},
-    time::MissedTickBehavior,
+    sync::{broadcast, mpsc, oneshot, watch},
+    task::JoinSet,
+    time::{Interval, MissedTickBehavior},
 };
 
+// Forked rooms alive at once
+const MAX_ROOMS: usize = 64;
+// World events buffered for slow `/admin/events` readers
+const EVENT_BUFFER: usize = 1024;
+// Pending `Leave`s per player, more are dropped
+const LEAVE_BUFFER: usize = 4;
+// A world that hasn't taken a player in by then is wedged or swamped
+const JOIN_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Forked rooms by name, each with its own world task. Connections without
+// ?room= go to the main world, which isn't listed.
+type Rooms = Arc<Mutex<HashMap<String, mpsc::Sender<WorldMsg>>>>;
+type Moving = (Session, Option<PlayerRecord>, Blocked, Arc<Recorder>);
+
 enum WorldMsg {
     Connect {
-        reply: oneshot::Sender<PlayerHandshake>,
+        // From ?name=, also in rooms, where there's no record
+        name: Option<String>,
+        record: Option<PlayerRecord>,
+        // Kept by the connection, records don't reach rooms
+        blocked: Blocked,
+        // From ?resume=, where the player was before a restart
+        session: Option<Session>,
+        // Kept by the connection, see recorder.rs
+        frames: Arc<Recorder>,
+        // From ?ticket=, a place held in a full room, see reservations.rs
+        ticket: Option<String>,
+        // From ?coords=1, positions the world's way, see coords.rs
+        coords: bool,
+        reply: oneshot::Sender<Result<PlayerHandshake, JoinError>>,
     },
     Disconnect {
         id: u32,
+        // For a room change: where the player was, their record, block
+        // list and recorded frames
+        moving: Option<oneshot::Sender<Moving>>,
     },
     SetInterest {
         id: u32,
         center: (i32, i32, i32),
         radius: u16,
     },
+    // `seq` on moves and edits is the client's, acked once applied
+    SetPosition {
+        id: u32,
+        position: (i32, i32, i32),
+        seq: Option<u32>,
+    },
+    // A `SetPosition` with where the player faces and goes
+    SetTransform {
+        id: u32,
+        position: (i32, i32, i32),
+        rotation: (f32, f32),
+        velocity: Option<(f32, f32, f32)>,
+        seq: Option<u32>,
+    },
+    SetBlock {
+        id: u32,
+        position: (i32, i32, i32),
+        block: u16,
+        seq: Option<u32>,
+    },
+    // Merged into the player's record, e.g. presence settings
+    SetProperties {
+        id: u32,
+        properties: HashMap<String, String>,
+    },
+    // See clock.rs, from the connection on each `TimeSync`
+    Clock {
+        id: u32,
+        estimate: ClockEstimate,
+    },
+    // The client's `Origin`, from the connection on each and after joining
+    // a world. Only sets one that isn't set, see origin.rs
+    Origin {
+        id: u32,
+        origin: BlockPos,
+    },
+    // A custom event for the journal, see analytics.rs
+    Track {
+        id: u32,
+        name: String,
+        fields: BTreeMap<String, String>,
+    },
+    // Sent to player `id` as MINIMAP, see minimap.rs
+    Minimap {
+        id: u32,
+        size: u8,
+        scale: u8,
+    },
+    // Around any block column, for /admin/minimap
+    RenderMinimap {
+        center: (i32, i32),
+        size: u8,
+        scale: u8,
+        reply: oneshot::Sender<Minimap>,
+    },
+    // See blocking.rs
+    SetBlocked {
+        id: u32,
+        blocked: Blocked,
+    },
+    // A guest logged in: names them, with the record taking over from here
+    // (main world only) and the block lists merged
+    Login {
+        id: u32,
+        name: String,
+        record: Option<PlayerRecord>,
+        blocked: Blocked,
+    },
+    // Copy-on-write copy of the loaded chunks, for a new room, or a cache
+    // reading `map` instead (see templates.rs)
+    Fork {
+        map: Option<PathBuf>,
+        reply: oneshot::Sender<ChunkCache>,
+    },
+    Chat {
+        from: String,
+        text: String,
+        // Set when it came in from a chat bridge (its index)
+        bridge: Option<usize>,
+        // The named player who said it, for block lists
+        sender: Option<String>,
+    },
+    Kick {
+        id: u32,
+        reason: String,
+        reply: oneshot::Sender<bool>,
+    },
+    // See chat_commands.rs, only `Run::World` ones
+    ChatCommand {
+        command: Arc<ChatCommand>,
+        call: Call,
+        reply: oneshot::Sender<Result<String, String>>,
+    },
+    // By a moderator, see roles.rs. Replies how many connections had the name
+    KickNamed {
+        name: String,
+        reason: String,
+        reply: oneshot::Sender<usize>,
+    },
+    State {
+        reply: oneshot::Sender<WorldState>,
+    },
+    // Saves now, replies with the players and chunks queued
+    Save {
+        reply: oneshot::Sender<(usize, usize)>,
+    },
+    // See history.rs
+    History {
+        query: HistoryQuery,
+        reply: oneshot::Sender<Result<HistoryReply, HistoryError>>,
+    },
+    // The player's recent frames, see recorder.rs
+    Frames {
+        id: u32,
+        secs: Duration,
+        reply: oneshot::Sender<Option<Vec<u8>>>,
+    },
+    // Lockstep and rollback rooms, see lockstep.rs and rollback.rs
+    Input {
+        id: u32,
+        tick: u32,
+        data: String,
+        reply: oneshot::Sender<Result<(), String>>,
+    },
+    LockstepState {
+        tick: u32,
+        data: String,
+        reply: oneshot::Sender<Result<(), String>>,
+    },
+    // See entities.rs
+    MoveEntity {
+        id: u32,
+        entity: u32,
+        position: (i32, i32, i32),
+        reply: oneshot::Sender<Result<(), String>>,
+    },
+    // See pathfinding.rs, the search runs off the world task
+    FindPath {
+        from: (i32, i32, i32),
+        to: (i32, i32, i32),
+        reply: oneshot::Sender<Result<Route, PathError>>,
+    },
+    // See effects.rs. Replies with how many players got it
+    Effect {
+        effect: Effect,
+        reply: oneshot::Sender<usize>,
+    },
+    // `None` gets off
+    Mount {
+        id: u32,
+        entity: Option<u32>,
+        reply: oneshot::Sender<Result<(), String>>,
+    },
+    Entities {
+        op: EntityOp,
+        reply: oneshot::Sender<Result<EntityReply, EntityError>>,
+    },
+    // Ends an empty room, `false` if it has players or places held (see
+    // pool.rs)
+    Close {
+        reply: oneshot::Sender<bool>,
+    },
+    // Moves a player to another layer, `false` if there's no such player.
+    // See relevance.rs
+    Layer {
+        id: u32,
+        layer: u32,
+        reply: oneshot::Sender<bool>,
+    },
+    // Holds a place for `ttl`, replies the ticket. See reservations.rs
+    Reserve {
+        name: Option<String>,
+        ttl: Duration,
+        reply: oneshot::Sender<Result<String, String>>,
+    },
+    // See features.rs. Replies with the flags now
+    Features {
+        toggles: Toggles,
+        reply: oneshot::Sender<Features>,
+    },
+    // Pauses, rescales or steps the ticks, see simulation.rs. Replies
+    // where it is now
+    Simulation {
+        change: SimulationChange,
+        reply: oneshot::Sender<SimulationState>,
+    },
+    // Relay rooms, see relay.rs
+    Relay {
+        from: u32,
+        to: Vec<u32>,
+        data: Vec<u8>,
+    },
+    // See voice.rs
+    Voice {
+        from: u32,
+        data: Vec<u8>,
+    },
+    VoiceMute {
+        id: u32,
+        speaker: u32,
+        mute: bool,
+        reply: oneshot::Sender<Result<(), String>>,
+    },
+    // Tells everyone the server is going away, with resume tokens first
+    // when `resume` is set
+    Drain {
+        seconds: u16,
+        address: String,
+        resume: bool,
+    },
+    // A connection's inputs from one read, handled in order as if sent
+    // one by one
+    Batch(Vec<WorldMsg>),
+}
+
+impl WorldMsg {
+    fn kind(&self) -> &'static str {
+        match self {
+            WorldMsg::Batch(_) => "Batch",
+            WorldMsg::Connect { .. } => "Connect",
+            WorldMsg::Disconnect { .. } => "Disconnect",
+            WorldMsg::SetInterest { .. } => "SetInterest",
+            WorldMsg::SetPosition { .. } => "SetPosition",
+            WorldMsg::SetTransform { .. } => "SetTransform",
+            WorldMsg::SetBlock { .. } => "SetBlock",
+            WorldMsg::SetProperties { .. } => "SetProperties",
+            WorldMsg::SetBlocked { .. } => "SetBlocked",
+            WorldMsg::Clock { .. } => "Clock",
+            WorldMsg::Origin { .. } => "Origin",
+            WorldMsg::Track { .. } => "Track",
+            WorldMsg::Minimap { .. } => "Minimap",
+            WorldMsg::RenderMinimap { .. } => "RenderMinimap",
+            WorldMsg::Login { .. } => "Login",
+            WorldMsg::Fork { .. } => "Fork",
+            WorldMsg::Chat { .. } => "Chat",
+            WorldMsg::Kick { .. } => "Kick",
+            WorldMsg::KickNamed { .. } => "KickNamed",
+            WorldMsg::ChatCommand { .. } => "ChatCommand",
+            WorldMsg::State { .. } => "State",
+            WorldMsg::Save { .. } => "Save",
+            WorldMsg::History { .. } => "History",
+            WorldMsg::Frames { .. } => "Frames",
+            WorldMsg::Input { .. } => "Input",
+            WorldMsg::LockstepState { .. } => "LockstepState",
+            WorldMsg::Relay { .. } => "Relay",
+            WorldMsg::Voice { .. } => "Voice",
+            WorldMsg::VoiceMute { .. } => "VoiceMute",
+            WorldMsg::Drain { .. } => "Drain",
+            WorldMsg::Features { .. } => "Features",
+            WorldMsg::Simulation { .. } => "Simulation",
+            WorldMsg::MoveEntity { .. } => "MoveEntity",
+            WorldMsg::Mount { .. } => "Mount",
+            WorldMsg::Effect { .. } => "Effect",
+            WorldMsg::FindPath { .. } => "FindPath",
+            WorldMsg::Entities { .. } => "Entities",
+            WorldMsg::Close { .. } => "Close",
+            WorldMsg::Layer { .. } => "Layer",
+            WorldMsg::Reserve { .. } => "Reserve",
+        }
+    }
 }
 
 struct PlayerHandshake {
     id: u32,
-    rx: mpsc::Receiver<Bytes>,
+    rx: lanes::Receiver,
+    traffic: Arc<PlayerTraffic>,
+    leave: mpsc::Receiver<Leave>,
+    mode: RoomMode,
+    // What the player's positions are in, see coords.rs
+    coords: Coords,
+}
+
+// Why the world sends a player away, done by the connection
+enum Leave {
+    // By an admin, with the reason
+    Kicked(String),
+    // Through a portal to a room, `None` for the main world. Without a
+    // portal the room closed, see quotas.rs
+    Room {
+        to: Option<String>,
+        portal: Option<Arc<Portal>>,
+    },
+    // Through a portal to another server, with a resume token for it
+    Server {
+        address: String,
+        token: String,
+    },
 }
 
 struct Player {
-    tx: mpsc::Sender<Bytes>,
+    // See lanes.rs
+    tx: lanes::Sender,
     interest: Option<((i32, i32, i32), u16)>,
+    // Chunk versions this client holds, so edits go out as deltas
+    chunks: HashMap<ChunkPos, u32>,
+    position: (i32, i32, i32),
+    // Yaw and pitch in degrees, and velocity in blocks per second, as the
+    // client last sent them with `SetTransform`
+    rotation: (f32, f32),
+    velocity: Option<(f32, f32, f32)>,
+    // The last input `seq` applied, and the last sent in `INPUT_ACK`
+    acked: Option<u32>,
+    sent_ack: Option<u32>,
+    // The connection's latest, see clock.rs
+    clock: Option<ClockEstimate>,
+    // The last `INTERP_HINT` sent, see interp.rs
+    interp: Option<InterpHint>,
+    // Only named players with storage enabled are persisted
+    record: Option<PlayerRecord>,
+    name: Option<String>,
+    traffic: Arc<PlayerTraffic>,
+    frames: Arc<Recorder>,
+    leave: mpsc::Sender<Leave>,
+    // Added to storage on the save interval, for named players
+    stats: PlayerStats,
+    // Player ids whose voice this one doesn't get
+    muted: HashSet<u32>,
+    // Names whose chat, voice and relay messages this one doesn't get
+    blocked: Blocked,
+    // Static entities as this client last got them, see entities.rs
+    statics: HashMap<u32, Entity>,
+    // Static entities it gets wherever they are
+    relevant: BTreeSet<u32>,
+    // Entities the world's relevance rule hides from it and static ones it
+    // shows, as of the last tick, see relevance.rs
+    hidden: HashSet<u32>,
+    shown: BTreeSet<u32>,
+    // Instance in the world, see relevance.rs
+    layer: u32,
+    // See afk.rs
+    activity: Activity,
+    // Chunks the interest reaches ahead, from the velocity, see interest.rs
+    lead: ChunkPos,
+    // What its positions are from, if it asked, see origin.rs
+    origin: Option<BlockPos>,
+    // The world's, if it asked and they aren't plain, see coords.rs
+    coords: Option<Coords>,
+    // Achievement properties without a record, for the visit, see
+    // achievements.rs
+    achievements: HashMap<String, String>,
+}
+
+impl Player {
+    fn viewer(&self, id: u32) -> Viewer {
+        Viewer {
+            id,
+            position: self.position,
+            interest: self.interest,
+            layer: self.layer,
+        }
+    }
+
+    fn to_record(&self) -> Option<PlayerRecord> {
+        let mut record = self.record.clone()?;
+        record.position = self.position;
+        Some(record)
+    }
+
+    fn take_stats(&mut self, world: &str, now: Instant) -> Vec<StatDelta> {
+        match &self.name {
+            Some(name) => self.stats.take(world, name, now),
+            None => Vec::new(),
+        }
+    }
+
+    // Where chunks are loaded and sent for, see interest.rs
+    fn reach(&self) -> Option<Reach> {
+        let (center, radius) = self.interest?;
+        let lead = self.lead;
+        Some(Reach {
+            center,
+            radius,
+            lead,
+        })
+    }
+
+    fn session(&self) -> Session {
+        Session {
+            name: self.name.clone(),
+            position: Some(self.position),
+            interest: self.interest,
+        }
+    }
+
+    // Whether frames go to it as they are
+    fn is_plain(&self) -> bool {
+        self.origin.is_none() && self.coords.is_none()
+    }
+
+    // Positions in `frame` from its origin and in its coordinates, see
+    // origin.rs and coords.rs
+    fn fit(&self, frame: &mut ServerFrame) {
+        if let Some(origin) = self.origin {
+            frame.rebase(origin);
+        }
+        if let Some(coords) = self.coords {
+            frame.apply_coords(coords);
+        }
+    }
+
+    // Dropped if the channel is full
+    fn send(&self, mut frame: ServerFrame) {
+        self.fit(&mut frame);
+        let sizes: Vec<_> = frame.sizes().collect();
+        let lane = Lane::of(&frame);
+        if self.tx.try_send(lane, frame.finish()).is_ok() {
+            for (kind, bytes) in sizes {
+                self.traffic.record(Dir::Out, kind, bytes);
+            }
+        }
+    }
 }
 
 #[derive(Clone)]
 struct WorldHandle {
     tx: mpsc::Sender<WorldMsg>,
+    // The main world's, `tx` follows the connection into rooms
+    main: mpsc::Sender<WorldMsg>,
+    storage: Option<Arc<dyn Storage>>,
+    // Every player save goes through it, see `SaveQueue`
+    saves: Option<SaveQueue>,
+    claims: Arc<Claims>,
+    blocks: Arc<BlockRegistry>,
+    rooms: Rooms,
+    chunk_format: ChunkFormat,
+    spatial: SpatialKind,
+    traffic: Arc<Traffic>,
+    telemetry: Option<Arc<Telemetry>>,
+    audit: Option<Arc<AuditLog>>,
+    bridges: Option<Arc<Bridges>>,
+    portals: Option<Arc<Portals>>,
+    triggers: Option<Arc<Triggers>>,
+    flood: Arc<Flood>,
+    afk: Arc<AfkRules>,
+    relevance: Arc<RelevanceRules>,
+    coords: Arc<CoordsRules>,
+    achievements: Arc<AchievementRules>,
+    features: Arc<FeatureFlags>,
+    quotas: Arc<Quotas>,
+    tenants: Arc<Tenants>,
+    usage: Option<Arc<UsageLog>>,
+    templates: Arc<Templates>,
+    pools: Arc<RoomPools>,
+    // The connection's, see tenants.rs
+    tenant: Option<String>,
+    // The connection's, from ?coords=1, see coords.rs
+    wire_coords: bool,
+    history: HistoryConfig,
+    input: InputConfig,
+    voice: VoiceConfig,
+    profile: Option<ProfileConfig>,
+    // Kept per connection, see recorder.rs
+    frame_history: Duration,
+    // See journal.rs
+    journal: Arc<Journal>,
+    tunables: watch::Receiver<Tunables>,
+    drain: Arc<Drain>,
+    // Connections between upgrade and `Connect` reply, see admission.rs
+    admission: Arc<Admission>,
+    resume: Arc<ResumeKey>,
+    handover: Arc<Handover>,
+    presence: Arc<Presence>,
+    mutes: Arc<Mutes>,
+    chat: Arc<ChatCommands>,
+    // Set when connections need a login, see auth.rs
+    auth: Option<Arc<dyn Authenticator>>,
+    auth_timeout: Duration,
+    // Connections without credentials get in anonymously, see `log_in`
+    guests: bool,
+    encryption: Policy,
+    message_auth: Policy,
+    // Signs session keys, set when either policy is on
+    server_key: Option<Arc<ServerKey>>,
 }
 
 struct World {
     id_count: u32,
+    tick: u64,
     rx: mpsc::Receiver<WorldMsg>,
     players: HashMap<u32, Player>,
+    saves: Option<SaveQueue>,
+    // Rooms keep statistics too
+    stats: Option<Arc<dyn Storage>>,
+    chunks: ChunkCache,
+    blocks: Arc<BlockRegistry>,
+    // Set for forked rooms, which unlist themselves once empty
+    room: Option<(String, Rooms)>,
+    chunk_format: ChunkFormat,
+    traffic: Arc<Traffic>,
+    telemetry: Option<Arc<Telemetry>>,
+    bridges: Option<Arc<Bridges>>,
+    portals: Option<Arc<Portals>>,
+    triggers: Option<Arc<Triggers>>,
+    history: History,
+    inputs: InputQueue<WorldMsg>,
+    // Only `RoomMode::World` is simulated
+    mode: RoomMode,
+    // Set for lockstep rooms
+    lockstep: Option<Lockstep>,
+    // Set for rollback rooms
+    rollback: Option<Rollback>,
+    // In blocks
+    voice_range: u32,
+    // See voice.rs
+    voice_occlusion: bool,
+    journal: Arc<Journal>,
+    tunables: watch::Receiver<Tunables>,
+    resume: Arc<ResumeKey>,
+    features: Arc<FeatureFlags>,
+    entities: Entities,
+    paths: Pathfinder,
+    // See minimap.rs
+    surfaces: Surfaces,
+    // By entity, see brains.rs
+    brains: BTreeMap<u32, Walker>,
+    scheduler: Scheduler,
+    // Reused by every tick, see `FramePool`
+    frames: FramePool,
+    // Chunks in the frame `broadcast_tick` is building
+    framed: Vec<ChunkPos>,
+    // Entities gone and back for a player in `judge_relevance`
+    judged: (Vec<u32>, Vec<Entity>),
+    // Entities in a player's interest, for `send_statics`
+    near: Vec<u32>,
+    profiler: Profiler,
+    // See quotas.rs
+    quotas: Arc<Quotas>,
+    meter: Meter,
+    // See afk.rs, AFK marks show in presence
+    afk: Arc<AfkRules>,
+    presence: Arc<Presence>,
+    // See relevance.rs
+    relevance: Option<Arc<dyn RelevanceRule>>,
+    // See coords.rs
+    coords: Coords,
+    // See achievements.rs
+    achievements: Arc<[Achievement]>,
+    // See simulation.rs
+    simulation: Simulation,
+    // Once anything went into a layer other than 0
+    layered: bool,
+    // See usage.rs
+    usage: Option<Arc<UsageLog>>,
+    // The room template's, the server's when `None`
+    tick_hz: Option<u32>,
+    // The room template's, see reservations.rs
+    max_players: Option<usize>,
+    reservations: Reservations,
+    // Ticking at half rate, or sending everyone away, for going over
+    degraded: bool,
+    closing: bool,
 }
 
 impl World {
-    fn new(rx: mpsc::Receiver<WorldMsg>) -> Self {
+    // Shared services come from the handle. Rooms (`room` set) have no
+    // storage, players keep their main world record.
+    fn new(
+        rx: mpsc::Receiver<WorldMsg>,
+        handle: &WorldHandle,
+        mut chunks: ChunkCache,
+        room: Option<(String, Rooms)>,
+    ) -> Self {
+        let world = room.as_ref().map_or("main", |(name, _)| name);
+        let quota = handle.quotas.get(world);
+        let relevance = handle.relevance.get(world);
+        let coords = handle.coords.get(world);
+        let achievements = handle.achievements.get(world);
+        chunks.set_limit(quota.max_chunks);
         Self {
             id_count: 1,
+            tick: 0,
             rx,
             players: HashMap::new(),
+            saves: handle.saves.clone().filter(|_| room.is_none()),
+            stats: handle.storage.clone(),
+            chunks,
+            blocks: handle.blocks.clone(),
+            room,
+            chunk_format: handle.chunk_format,
+            traffic: handle.traffic.clone(),
+            telemetry: handle.telemetry.clone(),
+            bridges: handle.bridges.clone(),
+            portals: handle.portals.clone(),
+            triggers: handle.triggers.clone(),
+            history: History::new(handle.history, 0),
+            inputs: InputQueue::new(handle.input.delay_ticks, handle.input.buffer),
+            mode: RoomMode::World,
+            lockstep: None,
+            rollback: None,
+            voice_range: handle.voice.range,
+            voice_occlusion: handle.voice.occlusion,
+            journal: handle.journal.clone(),
+            tunables: handle.tunables.clone(),
+            resume: handle.resume.clone(),
+            features: handle.features.clone(),
+            entities: Entities::new(handle.spatial),
+            paths: Pathfinder::default(),
+            surfaces: Surfaces::default(),
+            brains: BTreeMap::new(),
+            scheduler: Scheduler::default(),
+            frames: FramePool::default(),
+            framed: Vec::new(),
+            judged: (Vec::new(), Vec::new()),
+            near: Vec::new(),
+            profiler: Profiler::new(handle.profile.clone()),
+            quotas: handle.quotas.clone(),
+            meter: Meter::new(quota),
+            afk: handle.afk.clone(),
+            presence: handle.presence.clone(),
+            relevance,
+            coords,
+            achievements,
+            simulation: Simulation::default(),
+            layered: false,
+            usage: handle.usage.clone(),
+            tick_hz: None,
+            max_players: None,
+            reservations: Reservations::default(),
+            degraded: false,
+            closing: false,
         }
     }
 
-    async fn run(mut self, tick_hz: u32) {
-        // Avoid float math + rounding drift
-        let tick = Duration::from_nanos(1_000_000_000u64 / tick_hz as u64);
-        let mut ticker = tokio::time::interval(tick);
-        ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    async fn run(mut self, save_interval: Duration) {
+        let mut tick_hz = self
+            .tick_hz
+            .unwrap_or(self.tunables.borrow_and_update().tick_hz);
+        let mut scale = self.simulation.scale();
+        let mut ticker = tick_interval(tick_hz, scale);
 
         loop {
+            // Slow motion or back, see simulation.rs
+            if self.simulation.scale() != scale {
+                scale = self.simulation.scale();
+                ticker = tick_interval(self.tick_rate(tick_hz), scale);
+            }
+            let save_every = (save_interval.as_secs() * tick_hz as u64).max(1);
             select! {
                 // Tick path: drain any queued messages, then update+broadcast once
-                _ = ticker.tick() => {
-                    while let Ok(msg) = self.rx.try_recv() {
-                        self.handle_msg(msg);
+                at = ticker.tick() => {
+                    // Paused, messages still go through as they arrive
+                    if !self.simulation.take_tick() {
+                        continue;
+                    }
+                    // Late after a stall, see simulation.rs
+                    let max = self.tunables.borrow().catch_up_ticks;
+                    let late = match self.simulation.is_paused() {
+                        true => CatchUp::Run(0),
+                        false => catch_up(at.elapsed(), ticker.period(), max),
+                    };
+                    let extra = match late {
+                        CatchUp::Run(ticks) => ticks,
+                        CatchUp::Skip(ticks) => {
+                            self.skip_time(ticks);
+                            0
+                        }
+                    };
+                    let degraded = self.degraded;
+                    for left in (0..=extra).rev() {
+                        self.run_tick(tick_hz, save_every, left == 0);
+                    }
+                    if self.degraded != degraded {
+                        ticker = tick_interval(self.tick_rate(tick_hz), scale);
                     }
-
-                    // World update logic
-                    self.broadcast_tick();
                 }
 
                 // Low-latency path: process messages as they arrive
-                Some(msg) = self.rx.recv() => {
-                    self.handle_msg(msg);
+                msg = self.rx.recv() => match msg {
+                    Some(msg) => self.handle_msg(msg),
+                    // Channel closed => shut down world task
+                    None => break,
+                },
+
+                // Chunks finished loading or generating on the blocking pool
+                Some((pos, chunk)) = self.chunks.recv_loaded() => {
+                    self.chunks.insert_loaded(pos, chunk);
+                    self.paths.invalidate();
+                    self.surfaces.invalidate_chunk(pos);
                 }
 
-                // Channel closed => shut down world task
-                else => break,
+                // Settings reloaded, see reload.rs
+                Ok(()) = self.tunables.changed() => {
+                    let tunables = *self.tunables.borrow_and_update();
+                    if self.tick_hz.unwrap_or(tunables.tick_hz) != tick_hz {
+                        tick_hz = self.tick_hz.unwrap_or(tunables.tick_hz);
+                        ticker = tick_interval(self.tick_rate(tick_hz), scale);
+                    }
+                    for player in self.players.values_mut() {
+                        if let Some((_, radius)) = &mut player.interest {
+                            *radius = (*radius).min(tunables.max_interest_radius);
+                        }
+                    }
+                }
             }
         }
     }
 
-    fn handle_msg(&mut self, msg: WorldMsg) {
-        match msg {
-            WorldMsg::Connect { reply } => {
-                let id = self.id_count;
-                self.id_count += 1;
-
-                let (tx, rx) = mpsc::channel::<Bytes>(128);
-                self.players.insert(id, Player { tx, interest: None });
+    // One tick: queued messages, update and broadcast, unless `send` is
+    // off while catching up
+    fn run_tick(&mut self, tick_hz: u32, save_every: u64, send: bool) {
+        let started = Instant::now();
+        let mut lap = self.profiler.start();
+        while let Ok(msg) = self.rx.try_recv() {
+            self.handle_msg(msg);
+        }
+        for msg in self.inputs.take_due(self.tick) {
+            self.process_msg(msg);
+        }
+        self.profiler.lap(Phase::Messages, &mut lap);
 
-                reply.send(PlayerHandshake { id, rx }).ok();
+        // World update logic, other rooms only relay
+        match self.mode {
+            RoomMode::World => {
+                self.think();
+                self.profiler.lap(Phase::Think, &mut lap);
+                // Timed by phase inside
+                if send {
+                    self.broadcast_tick();
+                }
+                lap = self.profiler.start();
             }
-            WorldMsg::Disconnect { id } => {
-                self.players.remove(&id);
+            RoomMode::Lockstep => {
+                self.relay_lockstep();
+                self.profiler.lap(Phase::Send, &mut lap);
             }
-            WorldMsg::SetInterest { id, center, radius } => {
-                if let Some(player) = self.players.get_mut(&id) {
-                    player.interest = Some((center, radius));
-                }
+            RoomMode::Rollback => {
+                self.confirm_rollback();
+                self.profiler.lap(Phase::Send, &mut lap);
             }
+            RoomMode::Relay => {}
         }
-    }
 
-    fn broadcast_tick(&mut self) {
-        for (id, player) in self.players.iter_mut() {
-            if player.interest.is_none() {
-                continue;
+        self.tick += 1;
+        self.rebase();
+        if self.tick.is_multiple_of(tick_hz as u64) {
+            let interests = self.players.values().filter_map(Player::reach);
+            self.chunks.retain_interests(interests);
+            self.check_afk();
+            self.record_travel();
+            self.send_interp_hints(None);
+            // Held for players who never came
+            if self.players.is_empty() && self.reservations.lapsed(Instant::now()) {
+                self.close_room();
             }
+        }
+        self.check_quota();
 
-            // We should filter by area of interest, then send
+        if self.tick.is_multiple_of(save_every) {
+            let records = self
+                .players
+                .values()
+                .filter_map(Player::to_record)
+                .collect();
+            self.save_players(records);
+            let (world, now) = (self.name(), Instant::now());
+            let stats = self
+                .players
+                .values_mut()
+                .flat_map(|p| p.take_stats(&world, now));
+            let stats = stats.collect();
+            self.save_stats(stats);
+            // Snapshots only, the chunk writer does the I/O
+            self.chunks.save_dirty();
         }
-    }
-}
+        self.profiler.lap(Phase::Other, &mut lap);
+        let budget = Duration::from_nanos(1_000_000_000 / tick_hz as u64);
+        self.profiler.tick(&self.name(), started.elapsed(), budget);
 
-#[tokio::main]
-async fn main() {
-    let (tx, rx) = mpsc::channel::<WorldMsg>(128);
-    let world = World::new(rx);
-    tokio::spawn(world.run(60));
+        if let Some(telemetry) = &self.telemetry {
+            telemetry.record_tick(started.elapsed());
+            if let Some(mut span) = telemetry.slow_span("tick", started, None) {
+                span.set("world", self.name());
+                span.set("tick", self.tick as i64);
+                span.set("players", self.players.len() as i64);
+                span.set("deferred_thinks", self.scheduler.deferred as i64);
+            }
+        }
+    }
 
-    let handle = WorldHandle { tx };
-    let app = Router::new().route("/", get(ws_handler)).with_state(handle);
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
-    axum::serve(listener, app).await.unwrap();
-}
+    fn handle_msg(&mut self, msg: WorldMsg) {
+        if let WorldMsg::Batch(msgs) = msg {
+            for msg in msgs {
+                self.handle_msg(msg);
+            }
+            return;
+        }
+        // Held back by the input delay, see input.rs
+        let input = match &msg {
+            WorldMsg::SetPosition { id, .. }
+            | WorldMsg::SetTransform { id, .. }
+            | WorldMsg::SetBlock { id, .. } => Some(*id),
+            _ => None,
+        };
+        // Or by a pause, see simulation.rs
+        match input {
+            Some(id) if self.inputs.is_on() || self.simulation.is_held() => {
+                self.inputs.push(self.tick, id, msg);
+            }
+            _ => self.process_msg(msg),
+        }
+    }
 
-async fn ws_handler(
-    State(handle): State<WorldHandle>,
-    ws: upgrade::IncomingUpgrade,
-) -> impl IntoResponse {
-    let (response, fut) = ws.upgrade().unwrap();
-    tokio::task::spawn(async move {
-        if let Err(e) = handle_client(handle, fut).await {
-            eprintln!("Error handling client: {}", e);
+    fn process_msg(&mut self, msg: WorldMsg) {
+        let (started, kind) = (Instant::now(), msg.kind());
+        crash::update(|c| c.last_message = Some(kind));
+        // Applied even if refused, like a move into a wall: the client
+        // learns the outcome from the world state
+        if let WorldMsg::SetPosition {
+            id, seq: Some(seq), ..
         }
-    });
+        | WorldMsg::SetTransform {
+            id, seq: Some(seq), ..
+        }
+        | WorldMsg::SetBlock {
+            id, seq: Some(seq), ..
+        } = msg
+            && let Some(player) = self.players.get_mut(&id)
+        {
+            player.acked = Some(seq);
+        }
+        if let Some(id) = self.playing(&msg) {
+            self.played(id);
+        }
+        self.apply_msg(msg);
 
-    response
-}
+        if let Some(telemetry) = &self.telemetry
+            && let Some(mut span) = telemetry.slow_span("world_message", started, None)
+        {
+            span.set("world", self.name());
+            span.set("message", kind);
+        }
+    }
 
-async fn handle_client(
-    handle: WorldHandle,
-    fut: upgrade::UpgradeFut,
-) -> Result<(), WebSocketError> {
-    let (reply_tx, reply_rx) = oneshot::channel::<PlayerHandshake>();
-    handle
-        .tx
-        .send(WorldMsg::Connect { reply: reply_tx })
-        .await
-        .map_err(|_| IoError::new(ErrorKind::BrokenPipe, "world task dead"))?;
+    fn apply_msg(&mut self, msg: WorldMsg) {
+        match msg {
+            // Unpacked by `handle_msg`
+            WorldMsg::Batch(msgs) => msgs.into_iter().for_each(|msg| self.apply_msg(msg)),
+            WorldMsg::Connect {
+                name,
+                record,
+                blocked,
+                session,
+                frames,
+                ticket,
+                coords,
+                reply,
+            } => {
+                // Held places count as taken, unless this is one of them
+                let now = Instant::now();
+                let held =
+                    ticket.is_some_and(|t| self.reservations.redeem(&t, name.as_deref(), now));
+                if !held && self.full(now) {
+                    reply.send(Err(JoinError::Full)).ok();
+                    return;
+                }
+                let id = self.id_count;
+                self.id_count += 1;
 
-    let PlayerHandshake { id, mut rx } = reply_rx
-        .await
-        .map_err(|_| IoError::new(ErrorKind::BrokenPipe, "world task dead"))?;
+                let (tx, rx) = lanes::channel();
+                let (leave_tx, leave) = mpsc::channel(LEAVE_BUFFER);
+                let position = match (&session, &record) {
+                    // Portals can leave the position behind
+                    (Some(s), _) => s.position.unwrap_or_else(|| self.spawn_point()),
+                    (None, Some(r)) if !r.is_new => r.position,
+                    _ => self.spawn_point(),
+                };
+                // Resumed players don't wait for a SetInterest, rooms that
+                // only relay have no chunks to send
+                let max_radius = self.tunables.borrow().max_interest_radius;
+                let interest = session
+                    .filter(|_| self.mode == RoomMode::World)
+                    .and_then(|s| s.interest)
+                    .map(|(center, radius)| (center, radius.min(max_radius)));
+                if let Some((center, radius)) = interest {
+                    self.chunks.request_area(center, radius);
+                }
 
-    let mut inner = fut.await?;
-    inner.set_auto_close(true);
-    inner.set_auto_pong(true);
-    inner.set_writev(true);
-    let mut ws = FragmentCollector::new(inner);
+                let mut label = match &self.room {
+                    Some((room, _)) => format!("{room}/{id}"),
+                    None => id.to_string(),
+                };
+                if let Some(r) = &record {
+                    label += &format!(" ({})", r.name);
+                }
+                let traffic = self.traffic.register(self.name(), label);
+                if let Some(bridges) = &self.bridges {
+                    let name = display_name(id, name.as_deref());
+                    bridges.notify(&self.name(), BridgeEvent::Joined { name: &name });
+                }
+                let joined = PlayerAt {
+                    id,
+                    name: name.clone(),
+                    position,
+                };
+                self.history.record(self.tick, Change::Joined(joined));
+                self.players.insert(
+                    id,
+                    Player {
+                        tx,
+                        interest,
+                        chunks: HashMap::new(),
+                        position,
+                        rotation: (0.0, 0.0),
+                        velocity: None,
+                        acked: None,
+                        sent_ack: None,
+                        clock: None,
+                        interp: None,
+                        record,
+                        name,
+                        traffic: traffic.clone(),
+                        frames,
+                        leave: leave_tx,
+                        stats: PlayerStats::new(Instant::now()),
+                        muted: HashSet::new(),
+                        blocked,
+                        statics: HashMap::new(),
+                        relevant: BTreeSet::new(),
+                        hidden: HashSet::new(),
+                        shown: BTreeSet::new(),
+                        layer: 0,
+                        activity: Activity::new(Instant::now()),
+                        lead: (0, 0, 0),
+                        origin: None,
+                        coords: Some(self.coords).filter(|c| coords && !c.is_plain()),
+                        achievements: HashMap::new(),
+                    },
+                );
+                self.record(JournalEvent::PlayerJoined {
+                    id,
+                    name: self.players[&id].name.clone(),
+                });
+                self.catch_up(id);
+                self.send_entities(id);
+                if self.simulation.is_unusual() {
+                    self.send_simulation(Some(id));
+                }
+                self.send_interp_hints(Some(id));
+                if self.closing {
+                    self.send_home(id);
+                }
 
-    let handshake_id = id.to_string();
-    let frame = Frame::text(Payload::from(handshake_id.as_bytes()));
-    ws.write_frame(frame).await?;
+                let handshake = PlayerHandshake {
+                    id,
+                    rx,
+                    traffic,
+                    leave,
+                    mode: self.mode,
+                    // Flat worlds are flat for everyone
+                    coords: match coords {
+                        true => self.coords,
+                        false => Coords {
+                            flat: self.coords.flat,
+                            ..Coords::default()
+                        },
+                    },
+                };
+                // The connection gave up waiting
+                if reply.send(Ok(handshake)).is_err() {
+                    self.apply_msg(WorldMsg::Disconnect { id, moving: None });
+                }
+            }
+            WorldMsg::Disconnect { id, moving } => {
+                self.inputs.forget(id);
+                if let Some(lockstep) = &mut self.lockstep {
+                    lockstep.leave(id);
+                }
+                if let Some(rollback) = &mut self.rollback {
+                    rollback.leave(id);
+                }
+                for entity in self.entities.release(id) {
+                    self.send_entity(&entity);
+                }
+                self.get_off(id).ok();
+                for entity in self.entities.detach_from(Parent::Player(id)) {
+                    self.send_detach(&entity);
+                }
+                if let Some(mut player) = self.players.remove(&id) {
+                    self.save_stats(player.take_stats(&self.name(), Instant::now()));
+                    if let Some(moving) = moving {
+                        let blocked = std::mem::take(&mut player.blocked);
+                        let frames = player.frames.clone();
+                        moving
+                            .send((player.session(), player.to_record(), blocked, frames))
+                            .ok();
+                    }
+                    if let Some(usage) = &self.usage {
+                        usage.left(&player.traffic);
+                    }
+                    self.traffic.unregister(&player.traffic);
+                    self.record(JournalEvent::PlayerLeft {
+                        id,
+                        name: player.name.clone(),
+                    });
+                    if let Some(bridges) = &self.bridges {
+                        let name = display_name(id, player.name.as_deref());
+                        bridges.notify(&self.name(), BridgeEvent::Left { name: &name });
+                    }
+                    let left = PlayerAt {
+                        id,
+                        name: player.name.clone(),
+                        position: player.position,
+                    };
+                    self.history.record(self.tick, Change::Left(left));
+                    if let Some(record) = player.to_record() {
+                        self.save_players(vec![record]);
+                    }
+                }
 
-    loop {
-        select! {
-            frame = ws.read_frame() => {
-                let frame = match frame {
-                    Ok(f) => f,
-                    Err(e) => {
-                        eprintln!("ws read_frame error: {e}");
-                        break;
+                if self.players.is_empty() && self.reservations.held(Instant::now()) == 0 {
+                    self.close_room();
+                }
+            }
+            WorldMsg::SetInterest { id, center, radius } => {
+                let radius = radius.min(self.tunables.borrow().max_interest_radius);
+                if let Some(player) = self.players.get_mut(&id) {
+                    player.interest = Some((center, radius));
+                    self.chunks.request_area(center, radius);
+                }
+            }
+            // Riders go where their mount takes them, see entities.rs
+            WorldMsg::SetPosition { id, .. } if self.entities.mount_of(id).is_some() => {}
+            WorldMsg::SetPosition { id, position, .. } => {
+                let blocked = self.inside_block(position);
+                if let Some(player) = self.players.get_mut(&id)
+                    && !blocked
+                {
+                    let from = std::mem::replace(&mut player.position, position);
+                    player.stats.moved(from, position);
+                    let moved = Change::Moved {
+                        id,
+                        from,
+                        to: position,
+                    };
+                    self.history.record(self.tick, moved);
+                    self.carry(Parent::Player(id));
+                    self.cross_triggers(id, from, position);
+                    let portal = self
+                        .portals
+                        .as_ref()
+                        .and_then(|portals| portals.entered(&self.name(), from, position).cloned());
+                    if let Some(portal) = portal {
+                        self.send_through(id, portal);
+                    }
+                }
+            }
+            WorldMsg::SetTransform {
+                id,
+                position,
+                rotation,
+                velocity,
+                ..
+            } => {
+                let ahead = self.tunables.borrow().interest_lead;
+                if let Some(player) = self.players.get_mut(&id) {
+                    player.rotation = rotation;
+                    player.velocity = velocity;
+                    let radius = player.interest.map_or(0, |(_, radius)| radius);
+                    let lead = interest::lead(velocity.unwrap_or_default(), ahead, radius);
+                    if std::mem::replace(&mut player.lead, lead) != lead
+                        && let Some(reach) = player.reach()
+                    {
+                        self.chunks.request_reach(reach);
                     }
+                }
+                // Riders turn in place
+                let seq = None;
+                self.apply_msg(WorldMsg::SetPosition { id, position, seq });
+            }
+            WorldMsg::SetBlock {
+                id,
+                position,
+                block,
+                ..
+            } => {
+                let (x, y, z) = position;
+                let from = self.chunks.block(x, y, z);
+                // Edits to chunks that aren't loaded yet are dropped
+                if !self.chunks.set_block(x, y, z, block) {
+                    return;
+                }
+                self.surfaces.invalidate(x, z);
+                if let Some(player) = self.players.get_mut(&id) {
+                    player.stats.count(stats::BLOCKS_PLACED, 1);
+                }
+                if from != block {
+                    let change = Change::Block {
+                        position,
+                        from,
+                        to: block,
+                    };
+                    self.history.record(self.tick, change);
+                    self.paths.invalidate();
+                    let by = self.players.contains_key(&id).then_some(id);
+                    self.record(JournalEvent::BlockSet {
+                        position,
+                        from,
+                        to: block,
+                        by,
+                    });
+                }
+            }
+            WorldMsg::SetProperties { id, properties } => {
+                let record = self.players.get_mut(&id).and_then(|p| p.record.as_mut());
+                let Some(record) = record else {
+                    return;
                 };
+                let followed = self.journal.is_followed().then(|| properties.clone());
+                record.properties.extend(properties);
+                if let Some(properties) = followed {
+                    self.record(JournalEvent::PropertiesSet { id, properties });
+                }
+            }
+            WorldMsg::Track { id, name, fields } => {
+                if self.players.contains_key(&id) {
+                    self.record(JournalEvent::Custom { id, name, fields });
+                }
+            }
+            WorldMsg::Clock { id, estimate } => {
+                if let Some(player) = self.players.get_mut(&id) {
+                    player.clock = Some(estimate);
+                }
+            }
+            WorldMsg::Origin { id, origin } => {
+                if let Some(player) = self.players.get_mut(&id) {
+                    player.origin.get_or_insert(origin);
+                }
+            }
+            WorldMsg::SetBlocked { id, blocked } => {
+                if let Some(player) = self.players.get_mut(&id) {
+                    player.blocked = blocked;
+                }
+            }
+            WorldMsg::Login {
+                id,
+                name,
+                record,
+                blocked,
+            } => {
+                // Saved from where the guest is, see `Player::to_record`
+                if let Some(player) = self.players.get_mut(&id) {
+                    player.name = Some(name);
+                    player.record = record;
+                    player.blocked = blocked;
+                }
+            }
+            WorldMsg::Fork { map, reply } => {
+                let chunks = match map {
+                    Some(map) => self.chunks.fork_map(Arc::new(map)),
+                    None => self.chunks.fork(),
+                };
+                reply.send(chunks).ok();
+            }
+            WorldMsg::Chat {
+                from,
+                text,
+                bridge,
+                sender,
+            } => {
+                // Players were refused already, see `handle_client`
+                if bridge.is_some() && !self.features.get(Some(&self.name())).chat {
+                    return;
+                }
+                let mut frame = ServerFrame::new(self.tick as u32);
+                frame.chat(&from, &text);
+                let ids = self
+                    .players
+                    .iter()
+                    .filter(|(_, p)| !p.blocked.blocks(sender.as_deref()));
+                self.send_to(frame, ids.map(|(&id, _)| id).collect::<Vec<_>>());
 
-                match frame.opcode {
-                    OpCode::Close => break,
-                    OpCode::Text => {
-                        let parts: Vec<&str> = str::from_utf8(&frame.payload)
-                            .unwrap_or("")
-                            .split(' ')
-                            .collect();
-
-                        // SetInterest PosX PosY PosZ Radius
-
-                        if parts[0] == "SetInterest" {
-                            if parts.len() != 5 {
-                                let payload = Payload::from(b"SetInterest Error: Expected 4 parameters (PosX PosY PosZ Radius)" as &[u8]);
-                                ws.write_frame(Frame::text(payload)).await?;
-                                continue;
-                            }
-
-                            // Parse coordinates and radius with proper error handling
-                            let parse_result = || -> Result<((i32, i32, i32), u16), &'static str> {
-                                let x = parts[1].parse::<i32>().map_err(|_| "Invalid PosX")?;
-                                let y = parts[2].parse::<i32>().map_err(|_| "Invalid PosY")?;
-                                let z = parts[3].parse::<i32>().map_err(|_| "Invalid PosZ")?;
-                                let radius = parts[4].parse::<u16>().map_err(|_| "Invalid Radius")?;
-                                Ok(((x, y, z), radius))
-                            };
-
-                            match parse_result() {
-                                Ok((center, radius)) => {
-                                    if handle
-                                        .tx
-                                        .send(WorldMsg::SetInterest { id, center, radius })
-                                        .await
-                                        .is_err()
-                                    {
-                                        // World task is dead, break the connection
-                                        break;
-                                    }
-
-                                    let payload = Payload::from(b"SetInterest Ok" as &[u8]);
-                                    ws.write_frame(Frame::text(payload)).await?;
-                                }
-                                Err(err_msg) => {
-                                    let response = format!("SetInterest Error: {}", err_msg);
-                                    let payload = Payload::from(response.as_bytes());
-                                    ws.write_frame(Frame::text(payload)).await?;
-                                }
-                            }
-                        }
+                if let Some(bridges) = &self.bridges {
+                    let event = BridgeEvent::Chat {
+                        from: &from,
+                        text: &text,
+                        bridge,
+                    };
+                    bridges.notify(&self.name(), event);
+                }
+            }
+            WorldMsg::Kick { id, reason, reply } => {
+                // The connection sends Disconnect once it has closed
+                let kicked = self
+                    .players
+                    .get(&id)
+                    .is_some_and(|p| p.leave.try_send(Leave::Kicked(reason)).is_ok());
+                reply.send(kicked).ok();
+            }
+            WorldMsg::ChatCommand {
+                command,
+                call,
+                reply,
+            } => {
+                let Run::World(handler) = &command.run else {
+                    unreachable!()
+                };
+                reply.send(handler(self, &call)).ok();
+            }
+            WorldMsg::KickNamed {
+                name,
+                reason,
+                reply,
+            } => {
+                let kicked = self
+                    .players
+                    .values()
+                    .filter(|p| p.name.as_ref() == Some(&name))
+                    .filter(|p| p.leave.try_send(Leave::Kicked(reason.clone())).is_ok())
+                    .count();
+                reply.send(kicked).ok();
+            }
+            WorldMsg::State { reply } => {
+                let mut players: Vec<_> = self
+                    .players
+                    .iter()
+                    .map(|(&id, player)| PlayerState {
+                        id,
+                        name: player.name.clone(),
+                        position: player.position,
+                        rotation: player.rotation,
+                        velocity: player.velocity,
+                        clock: player.clock,
+                        interest: player.interest,
+                        dropped_inputs: self.inputs.dropped(id),
+                        coalesced: player.tx.coalesced(),
+                        afk: player.activity.is_afk(),
+                        layer: player.layer,
+                    })
+                    .collect();
+                players.sort_by_key(|p| p.id);
+                reply
+                    .send(WorldState {
+                        tick: self.tick,
+                        loaded_chunks: self.chunks.len(),
+                        players,
+                    })
+                    .ok();
+            }
+            WorldMsg::Save { reply } => {
+                let records: Vec<_> = self
+                    .players
+                    .values()
+                    .filter_map(Player::to_record)
+                    .collect();
+                let players = records.len();
+                self.save_players(records);
+                let chunks = self.chunks.save_dirty();
+                reply.send((players, chunks)).ok();
+            }
+            WorldMsg::History { query, reply } => {
+                let live = self.live();
+                let result = match query {
+                    HistoryQuery::At(tick) => self.history.at(&live, tick).map(HistoryReply::At),
+                    HistoryQuery::Diff(from, to) => {
+                        self.history.diff(&live, from, to).map(HistoryReply::Diff)
                     }
-                    OpCode::Binary => {
-                        // Eventually, we need to translate the Text
-                        // protocol to binary
+                    HistoryQuery::Rewind(ticks) => self
+                        .history
+                        .rewind(&live, ticks)
+                        .map(|target| HistoryReply::Rewound(self.rewind_to(&live, target))),
+                };
+                reply.send(result).ok();
+            }
+            WorldMsg::Frames { id, secs, reply } => {
+                let file = self.players.get(&id).map(|p| p.frames.export(secs));
+                reply.send(file).ok();
+            }
+            WorldMsg::Input {
+                id,
+                tick,
+                data,
+                reply,
+            } => {
+                if let Some(rollback) = &mut self.rollback {
+                    let result = rollback.input(id, tick, data.clone());
+                    if result.is_ok() {
+                        let mut frame = ServerFrame::new(self.tick as u32);
+                        frame.rollback_input(id, tick, &data);
+                        let others = self.players.keys().filter(|&&p| p != id);
+                        self.send_to(frame, others.copied().collect::<Vec<_>>());
                     }
-                    _ => {}
+                    reply.send(result.map_err(|e| e.to_string())).ok();
+                    return;
                 }
+                let result = match &mut self.lockstep {
+                    Some(lockstep) => lockstep.input(id, tick, data).map_err(|e| e.to_string()),
+                    None => Err("Not a lockstep or rollback room".to_string()),
+                };
+                reply.send(result).ok();
             }
-            Some(bytes) = rx.recv() => {
-                let payload = Payload::Borrowed(&bytes);
-                ws.write_frame(Frame::binary(payload)).await?;
+            WorldMsg::LockstepState { tick, data, reply } => {
+                let result = match &mut self.lockstep {
+                    Some(lockstep) => lockstep
+                        .upload_state(tick, data.into_bytes())
+                        .map_err(|e| e.to_string()),
+                    None => Err("Not a lockstep room".to_string()),
+                };
+                reply.send(result).ok();
             }
-        }
+            WorldMsg::Features { toggles, reply } => {
+                let features = self.features.toggle(Some(&self.name()), &toggles);
+                let mut frame = ServerFrame::new(self.tick as u32);
+                frame.features(features.chat, features.build, features.pvp);
+                self.send_all(frame);
+                reply.send(features).ok();
+            }
+            WorldMsg::Simulation { change, reply } => {
+                let before = (self.simulation.is_paused(), self.simulation.scale());
+                self.simulation.change(change);
+                let (paused, scale) = (self.simulation.is_paused(), self.simulation.scale());
+                if (paused, scale) != before {
+                    self.send_simulation(None);
+                    self.send_interp_hints(None);
+                    self.record(JournalEvent::SimulationChanged { paused, scale });
+                }
+                reply.send(self.simulation.state(self.tick)).ok();
+            }
+            WorldMsg::MoveEntity {
+                id,
+                entity,
+                position,
+                reply,
+            } => {
+                let result = self.entities.move_by(Some(id), entity, position);
+                if let Ok(entity) = &result {
+                    self.send_entity(entity);
+                    self.carry(Parent::Entity(entity.id));
+                }
+                reply
+                    .send(result.map(|_| ()).map_err(|e| e.to_string()))
+                    .ok();
+            }
+            WorldMsg::Mount {
+                id,
+                entity: Some(entity),
+                reply,
+            } => {
+                let layer = self.entities.get(entity).map(|e| e.layer);
+                let result = match self.players.get(&id) {
+                    // Out of reach from another layer, see relevance.rs
+                    Some(player) if layer.is_some_and(|l| l != player.layer) => {
+                        Err(EntityError::NotFound(entity))
+                    }
+                    Some(player) => self.entities.mount(id, player.position, entity),
+                    None => Err(EntityError::NoPlayer(id)),
+                };
+                if let Ok(mount) = &result {
+                    let mut frame = ServerFrame::new(self.tick as u32);
+                    frame.mount(mount);
+                    self.send_all(frame);
+                }
+                reply
+                    .send(result.map(|_| ()).map_err(|e| e.to_string()))
+                    .ok();
+            }
+            WorldMsg::Mount {
+                id,
+                entity: None,
+                reply,
+            } => {
+                let result = self.get_off(id).map_err(|e| e.to_string());
+                reply.send(result).ok();
+            }
+            WorldMsg::Entities {
+                op: EntityOp::List,
+                reply,
+            } => {
+                let entities = self.entities.iter().copied().collect();
+                reply.send(Ok(EntityReply::List(entities))).ok();
+            }
+            WorldMsg::Effect { effect, reply } => {
+                reply.send(self.effect(&effect)).ok();
+            }
+            WorldMsg::FindPath { from, to, reply } => self.find_path(from, to, reply),
+            WorldMsg::Minimap { id, size, scale } => {
+                let Some(player) = self.players.get(&id) else {
+                    return;
+                };
+                let (x, _, z) = player.position;
+                let map = self
+                    .surfaces
+                    .minimap(&self.chunks, &self.blocks, (x, z), size, scale);
+                let mut frame = ServerFrame::new(self.tick as u32);
+                frame.minimap(&map);
+                player.send(frame);
+            }
+            WorldMsg::RenderMinimap {
+                center,
+                size,
+                scale,
+                reply,
+            } => {
+                let map = self
+                    .surfaces
+                    .minimap(&self.chunks, &self.blocks, center, size, scale);
+                reply.send(map).ok();
+            }
+            WorldMsg::Entities { op, reply } => {
+                let result = self.entity_op(op);
+                reply.send(result.map(EntityReply::One)).ok();
+            }
+            WorldMsg::Close { reply } => {
+                let empty = self.players.is_empty() && self.reservations.held(Instant::now()) == 0;
+                let empty = empty && self.room.is_some();
+                if empty {
+                    self.close_room();
+                }
+                reply.send(empty).ok();
+            }
+            WorldMsg::Layer { id, layer, reply } => {
+                let player = self.players.get_mut(&id);
+                let found = player.is_some();
+                if let Some(player) = player {
+                    player.layer = layer;
+                    self.layered |= layer != 0;
+                }
+                reply.send(found).ok();
+            }
+            WorldMsg::Reserve { name, ttl, reply } => {
+                let now = Instant::now();
+                let ticket = if self.full(now) {
+                    Err("Room full".to_string())
+                } else {
+                    let ticket = self.reservations.issue(name, ttl, now);
+                    ticket.ok_or_else(|| "No randomness for a ticket".to_string())
+                };
+                reply.send(ticket).ok();
+            }
+            WorldMsg::Relay { from, to, data } if self.mode == RoomMode::Relay => {
+                let mut frame = ServerFrame::new(self.tick as u32);
+                frame.relay(from, &data);
+                let sender = self.players.get(&from).and_then(|p| p.name.as_deref());
+                let players = self
+                    .players
+                    .iter()
+                    .filter(|(_, p)| !p.blocked.blocks(sender));
+                let ids = relay::recipients(from, &to, players.map(|(&id, _)| id));
+                self.send_to(frame, ids);
+            }
+            WorldMsg::Relay { .. } => {}
+            WorldMsg::Voice { from, data } if self.mode == RoomMode::World => {
+                let Some(speaker) = self.players.get(&from) else {
+                    return;
+                };
+                for (&id, player) in &self.players {
+                    if id == from
+                        || player.layer != speaker.layer
+                        || player.muted.contains(&from)
+                        || player.blocked.blocks(speaker.name.as_deref())
+                    {
+                        continue;
+                    }
+                    let heard = voice::distance(
+                        speaker.position,
+                        player.position,
+                        player.interest,
+                        self.voice_range,
+                    );
+                    if let Some(distance) = heard {
+                        let solid =
+                            |(x, y, z): BlockPos| self.blocks.is_solid(self.chunks.block(x, y, z));
+                        let occlusion = match self.voice_occlusion {
+                            true => voice::occlusion(speaker.position, player.position, solid),
+                            false => 0,
+                        };
+                        let mut frame = ServerFrame::new(self.tick as u32);
+                        frame.voice(from, distance, occlusion, &data);
+                        player.send(frame);
+                    }
+                }
+            }
+            WorldMsg::Voice { .. } => {}
+            WorldMsg::VoiceMute {
+                id,
+                speaker,
+                mute,
+                reply,
+            } => {
+                let result = match self.players.get_mut(&id) {
+                    Some(player) if !mute => {
+                        player.muted.remove(&speaker);
+                        Ok(())
+                    }
+                    Some(player) if player.muted.len() >= voice::MAX_MUTED => {
+                        Err(format!("Too many muted (max {})", voice::MAX_MUTED))
+                    }
+                    Some(player) => {
+                        player.muted.insert(speaker);
+                        Ok(())
+                    }
+                    None => Ok(()),
+                };
+                reply.send(result).ok();
+            }
+            // Rooms don't carry over, their players only get `DRAIN`
+            WorldMsg::Drain {
+                seconds,
+                address,
+                resume,
+            } if resume && self.room.is_none() => {
+                for player in self.players.values() {
+                    let token = self.resume.issue(&player.session());
+                    let mut frame = ServerFrame::new(self.tick as u32);
+                    frame.resume(&token);
+                    frame.drain(seconds, &address);
+                    player.send(frame);
+                }
+            }
+            WorldMsg::Drain {
+                seconds, address, ..
+            } => {
+                let mut frame = ServerFrame::new(self.tick as u32);
+                frame.drain(seconds, &address);
+                self.send_all(frame);
+            }
+        }
+    }
+
+    // Room name, `main` for the main world
+    // Where the simulation is, to player `id` or everyone
+    // A stall too long to catch up, see simulation.rs
+    fn skip_time(&mut self, ticks: u32) {
+        eprintln!("World {} stalled, {ticks} ticks skipped", self.name());
+        self.record(JournalEvent::TimeSkipped { ticks });
+        let mut frame = ServerFrame::new(self.tick as u32);
+        frame.time_skip(ticks);
+        self.send_all(frame);
+    }
+
+    fn send_simulation(&self, id: Option<u32>) {
+        let mut frame = ServerFrame::new(self.tick as u32);
+        frame.simulation(self.simulation.is_paused(), self.simulation.scale());
+        match id {
+            Some(id) => self.send_to(frame, [id]),
+            None => self.send_all(frame),
+        }
+    }
+
+    // `INTERP_HINT` to player `id`, or everyone, whose hint changed, see
+    // interp.rs. Only simulated worlds have anything to interpolate
+    fn send_interp_hints(&mut self, id: Option<u32>) {
+        if self.mode != RoomMode::World {
+            return;
+        }
+        let tunables = *self.tunables.borrow();
+        let tick_hz = self.tick_rate(self.tick_hz.unwrap_or(tunables.tick_hz));
+        let scale = self.simulation.scale();
+        let tick = self.tick as u32;
+        for (&player_id, player) in &mut self.players {
+            if id.is_some_and(|id| id != player_id) {
+                continue;
+            }
+            let jitter = player.clock.map_or(0.0, |clock| clock.jitter_ms);
+            let hint = interp::hint(tick_hz, scale, tunables.interp_ticks, jitter);
+            if player.interp == Some(hint) {
+                continue;
+            }
+            player.interp = Some(hint);
+            let mut frame = ServerFrame::new(tick);
+            frame.interp_hint(&hint);
+            player.send(frame);
+        }
+    }
+
+    // Half of `tick_hz` while degraded, see quotas.rs
+    fn tick_rate(&self, tick_hz: u32) -> u32 {
+        match self.degraded {
+            true => (tick_hz / 2).max(1),
+            false => tick_hz,
+        }
+    }
+
+    // Once a second: reports usage and acts on the quotas hit, see
+    // quotas.rs
+    fn check_quota(&mut self) {
+        let sent = self
+            .players
+            .iter()
+            .map(|(&id, p)| (id, p.traffic.bytes().1));
+        let entities = self.entities.iter().count();
+        let Some((usage, hit)) = self.meter.check(entities, self.chunks.len(), sent) else {
+            return;
+        };
+        let name = self.name();
+        self.quotas.report(&name, usage, &hit);
+        let over: Vec<_> = hit.iter().map(|r| r.as_str()).collect();
+        match self.meter.quota.action {
+            QuotaAction::Reject => {}
+            QuotaAction::Degrade if self.degraded == hit.is_empty() => {
+                self.degraded = !hit.is_empty();
+                match self.degraded {
+                    true => eprintln!("World {name} over its {} quota, degraded", over.join(", ")),
+                    false => eprintln!("World {name} back under its quotas"),
+                }
+            }
+            QuotaAction::Degrade => {}
+            QuotaAction::Close if !hit.is_empty() && !self.closing => {
+                eprintln!("Room {name} over its {} quota, closing", over.join(", "));
+                self.closing = true;
+                let mut frame = ServerFrame::new(self.tick as u32);
+                let text = format!("Room closed: over its {} quota", over.join(", "));
+                frame.chat("server", &text);
+                self.send_all(frame);
+                let ids: Vec<_> = self.players.keys().copied().collect();
+                for id in ids {
+                    self.send_home(id);
+                }
+            }
+            QuotaAction::Close => {}
+        }
+    }
+
+    // The player `msg` shows playing, see afk.rs. Standing still doesn't
+    // count, resending the same position or facing the same way.
+    fn playing(&self, msg: &WorldMsg) -> Option<u32> {
+        let still = |id, position, rotation: Option<(f32, f32)>| {
+            self.players
+                .get(&id)
+                .is_some_and(|p| p.position == position && rotation.is_none_or(|r| r == p.rotation))
+        };
+        match *msg {
+            WorldMsg::SetPosition { id, position, .. } if !still(id, position, None) => Some(id),
+            WorldMsg::SetTransform {
+                id,
+                position,
+                rotation,
+                ..
+            } if !still(id, position, Some(rotation)) => Some(id),
+            WorldMsg::SetBlock { id, .. } | WorldMsg::Input { id, .. } => Some(id),
+            WorldMsg::Relay { from, .. } => Some(from),
+            _ => None,
+        }
+    }
+
+    fn played(&mut self, id: u32) {
+        let Some(player) = self.players.get_mut(&id) else {
+            return;
+        };
+        if player.activity.played(Instant::now())
+            && let Some(name) = player.name.clone()
+        {
+            self.presence.set_afk(&name, &self.name(), false);
+        }
+    }
+
+    // Marks players who stopped playing and acts on it, see afk.rs
+    fn check_afk(&mut self) {
+        let world = self.name();
+        let rule = self.afk.get(&world);
+        let Some(after) = rule.after else {
+            return;
+        };
+        let now = Instant::now();
+        let away: Vec<_> = (self.players.iter_mut())
+            .filter_map(|(&id, player)| player.activity.check(now, after).then_some(id))
+            .collect();
+        for id in away {
+            let player = &self.players[&id];
+            if let Some(name) = &player.name {
+                self.presence.set_afk(name, &world, true);
+            }
+            let text = match rule.action {
+                AfkAction::Kick => {
+                    player.leave.try_send(Leave::Kicked("AFK".into())).ok();
+                    continue;
+                }
+                AfkAction::Lobby if self.room.is_some() => {
+                    self.send_home(id);
+                    "You're AFK, leaving the room"
+                }
+                _ => "You're AFK",
+            };
+            let mut frame = ServerFrame::new(self.tick as u32);
+            frame.chat("server", text);
+            self.send_to(frame, [id]);
+        }
+    }
+
+    // To the main world, out of a closing room
+    fn send_home(&self, id: u32) {
+        if let Some(player) = self.players.get(&id) {
+            let leave = Leave::Room {
+                to: None,
+                portal: None,
+            };
+            player.leave.try_send(leave).ok();
+        }
+    }
+
+    fn name(&self) -> String {
+        self.room
+            .as_ref()
+            .map_or("main".to_string(), |(name, _)| name.clone())
+    }
+
+    // Players and the blocks the history knows about, as they are now
+    fn live(&self) -> WorldAt {
+        let mut players: Vec<_> = self
+            .players
+            .iter()
+            .map(|(&id, player)| PlayerAt {
+                id,
+                name: player.name.clone(),
+                position: player.position,
+            })
+            .collect();
+        players.sort_by_key(|p| p.id);
+        let blocks = self.history.edited().into_iter().map(|position| {
+            let (x, y, z) = position;
+            let block = self.chunks.block(x, y, z);
+            BlockAt { position, block }
+        });
+        WorldAt {
+            tick: self.tick,
+            players,
+            blocks: blocks.collect(),
+        }
+    }
+
+    // Puts blocks and the positions of players still here back as they
+    // were, see history.rs. Blocks in chunks since unloaded stay.
+    fn rewind_to(&mut self, live: &WorldAt, target: WorldAt) -> Rewound {
+        let mut rewound = Rewound {
+            from: live.tick,
+            to: target.tick,
+            blocks: 0,
+            players: 0,
+        };
+        // The same positions in the same order, see `History::at`
+        for (now, then) in live.blocks.iter().zip(&target.blocks) {
+            let (x, y, z) = then.position;
+            if now.block != then.block && self.chunks.set_block(x, y, z, then.block) {
+                self.surfaces.invalidate(x, z);
+                rewound.blocks += 1;
+                self.record(JournalEvent::BlockSet {
+                    position: then.position,
+                    from: now.block,
+                    to: then.block,
+                    by: None,
+                });
+            }
+        }
+        self.paths.invalidate();
+        for then in &target.players {
+            if let Some(player) = self.players.get_mut(&then.id)
+                && player.position != then.position
+            {
+                player.position = then.position;
+                rewound.players += 1;
+            }
+        }
+        rewound
+    }
+
+    // Joins player `id` to the lockstep relay, sending it the latest state
+    // and the ticks closed since, or to the rollback room
+    fn catch_up(&mut self, id: u32) {
+        if let (Some(rollback), Some(player)) = (&mut self.rollback, self.players.get(&id)) {
+            let (at, delay) = rollback.join(id);
+            let mut frame = ServerFrame::new(self.tick as u32);
+            frame.rollback(at, delay);
+            player.send(frame);
+            return;
+        }
+        let (Some(lockstep), Some(player)) = (&mut self.lockstep, self.players.get(&id)) else {
+            return;
+        };
+        let tick = self.tick as u32;
+        let (state_tick, state, closed) = lockstep.join(id);
+        let mut frame = ServerFrame::new(tick);
+        frame.lockstep_state(state_tick, state);
+        for closed in closed {
+            if frame.is_full() {
+                player.send(std::mem::replace(&mut frame, ServerFrame::new(tick)));
+            }
+            frame.lockstep(closed);
+        }
+        player.send(frame);
+    }
+
+    // Every entity to player `id`, on joining, see entities.rs
+    fn send_entities(&mut self, id: u32) {
+        let Some(player) = self.players.get(&id) else {
+            return;
+        };
+        let tick = self.tick as u32;
+        let mut frame = ServerFrame::new(tick);
+        // Static ones come with the interest, see `send_statics`
+        let (hidden, shown): (Vec<&Entity>, Vec<_>) = self
+            .entities
+            .iter()
+            .filter(|e| !e.fixed)
+            .partition(|e| self.hides(id, player, e));
+        let hidden: Vec<_> = hidden.iter().map(|e| e.id).collect();
+        for entity in shown {
+            if frame.is_full() {
+                player.send(std::mem::replace(&mut frame, ServerFrame::new(tick)));
+            }
+            frame.entity(entity);
+        }
+        for mount in self.entities.mounts() {
+            if frame.is_full() {
+                player.send(std::mem::replace(&mut frame, ServerFrame::new(tick)));
+            }
+            frame.mount(mount);
+        }
+        let attached = self
+            .entities
+            .iter()
+            .filter_map(|e| Some((e.id, e.attached?)));
+        for (id, attachment) in attached {
+            if frame.is_full() {
+                player.send(std::mem::replace(&mut frame, ServerFrame::new(tick)));
+            }
+            frame.attach(id, &attachment);
+        }
+        if !frame.is_empty() {
+            player.send(frame);
+        }
+        // Never sent, so `judge_relevance` has nothing to take back
+        if let Some(player) = self.players.get_mut(&id) {
+            player.hidden.extend(hidden);
+        }
+    }
+
+    // After `parent` moved: what hangs from it and rides on it, all the
+    // way down, see entities.rs. Only entities held by players are sent,
+    // clients place the rest from their parents.
+    fn carry(&mut self, parent: Parent) {
+        let (mut next, mut seen) = (vec![parent], HashSet::new());
+        while let Some(parent) = next.pop() {
+            if !seen.insert(parent) {
+                continue;
+            }
+            let at = match parent {
+                Parent::Entity(id) => self.entities.position(id),
+                Parent::Player(id) => self.players.get(&id).map(|p| p.position),
+            };
+            let Some(at) = at else {
+                continue;
+            };
+            for child in self.entities.place_children(parent, at) {
+                if let Parent::Player(_) = parent {
+                    self.send_entity(&child);
+                }
+                next.push(Parent::Entity(child.id));
+            }
+            let Parent::Entity(id) = parent else {
+                continue;
+            };
+            for (rider, to) in self.entities.riders(id) {
+                if let Some(player) = self.players.get_mut(&rider) {
+                    let from = std::mem::replace(&mut player.position, to);
+                    player.stats.moved(from, to);
+                    let moved = Change::Moved {
+                        id: rider,
+                        from,
+                        to,
+                    };
+                    self.history.record(self.tick, moved);
+                    next.push(Parent::Player(rider));
+                }
+            }
+        }
+    }
+
+    // See `WorldControl::entities`, the entity as it is after
+    fn entity_op(&mut self, op: EntityOp) -> Result<Entity, EntityError> {
+        let id = match op {
+            EntityOp::List => unreachable!(),
+            EntityOp::Spawn(..) if !self.meter.can_spawn(self.entities.iter().count()) => {
+                return Err(EntityError::Quota);
+            }
+            EntityOp::Spawn(position, fixed, layer) => {
+                let id = self.entities.spawn(position, fixed).id;
+                let entity = self.entities.set_layer(id, layer)?;
+                self.layered |= layer != 0;
+                // Never sent to the ones it's hidden from, so
+                // `judge_relevance` has nothing to take back
+                let hidden: Vec<_> = self
+                    .players
+                    .iter()
+                    .filter(|&(&to, player)| self.hides(to, player, &entity))
+                    .map(|(&to, _)| to)
+                    .collect();
+                for to in hidden {
+                    self.players.get_mut(&to).unwrap().hidden.insert(id);
+                }
+                self.send_entity(&entity);
+                self.record(JournalEvent::EntitySpawned {
+                    id,
+                    position: entity.position,
+                });
+                return Ok(entity);
+            }
+            EntityOp::Remove(id) => {
+                for (rider, _) in self.entities.riders(id) {
+                    self.get_off(rider).ok();
+                }
+                for entity in self.entities.detach_from(Parent::Entity(id)) {
+                    self.send_detach(&entity);
+                }
+                let entity = self.entities.remove(id)?;
+                self.brains.remove(&id);
+                for player in self.players.values_mut() {
+                    player.statics.remove(&id);
+                    player.relevant.remove(&id);
+                    player.hidden.remove(&id);
+                }
+                let mut frame = ServerFrame::new(self.tick as u32);
+                frame.entity_gone(id);
+                self.send_all(frame);
+                self.record(JournalEvent::EntityRemoved { id });
+                return Ok(entity);
+            }
+            EntityOp::Grant(_, Some(to))
+            | EntityOp::Relevant(_, to, _)
+            | EntityOp::Attach(
+                _,
+                Some(Attachment {
+                    parent: Parent::Player(to),
+                    ..
+                }),
+            ) if !self.players.contains_key(&to) => {
+                return Err(EntityError::NoPlayer(to));
+            }
+            EntityOp::Grant(id, to) => {
+                let entity = self.entities.grant(id, to)?;
+                self.send_entity(&entity);
+                return Ok(entity);
+            }
+            // Placed by `carry`, which sends it for player parents
+            EntityOp::Attach(id, Some(attachment)) => {
+                self.entities.attach(id, attachment)?;
+                let mut frame = ServerFrame::new(self.tick as u32);
+                frame.attach(id, &attachment);
+                self.send_all(frame);
+                self.carry(attachment.parent);
+                id
+            }
+            EntityOp::Attach(id, None) => {
+                let entity = self.entities.detach(id)?;
+                self.send_detach(&entity);
+                id
+            }
+            // Sent with the next tick, see `send_statics`
+            EntityOp::Always(id, always) => return self.entities.set_always(id, always),
+            // Hidden and shown with the next tick, see `judge_relevance`
+            EntityOp::Layer(id, layer) => {
+                self.layered |= layer != 0;
+                return self.entities.set_layer(id, layer);
+            }
+            EntityOp::Relevant(id, to, relevant) => {
+                let entity = self.entities.get(id).ok_or(EntityError::NotFound(id))?;
+                let player = self.players.get_mut(&to).unwrap();
+                if relevant {
+                    player.relevant.insert(id);
+                } else {
+                    player.relevant.remove(&id);
+                }
+                return Ok(entity);
+            }
+            EntityOp::Goal(id, goal) => {
+                let entity = self.entities.get(id).ok_or(EntityError::NotFound(id))?;
+                match goal {
+                    Some(_) if entity.authority.is_some() => {
+                        return Err(EntityError::NotAuthority(id));
+                    }
+                    Some(goal) => self.brains.insert(id, Walker::new(goal)),
+                    None => self.brains.remove(&id),
+                };
+                return Ok(entity);
+            }
+        };
+        self.entities.get(id).ok_or(EntityError::NotFound(id))
+    }
+
+    // Searches off the world task, see pathfinding.rs
+    fn find_path(
+        &self,
+        from: (i32, i32, i32),
+        to: (i32, i32, i32),
+        reply: oneshot::Sender<Result<Route, PathError>>,
+    ) {
+        let terrain = Terrain::copy(&self.chunks, self.blocks.clone(), from, to);
+        let paths = self.paths.clone();
+        tokio::spawn(async move {
+            reply.send(paths.find(terrain, from, to).await).ok();
+        });
+    }
+
+    // The brains whose turn it is, until the tick's budget runs out, see
+    // brains.rs
+    fn think(&mut self) {
+        let tunables = *self.tunables.borrow();
+        let period = brains::period(tunables.tick_hz, tunables.think_hz);
+        let due = self
+            .scheduler
+            .due(self.tick, period, self.brains.keys().copied());
+        let budget = self.meter.think_budget(tunables.think_budget);
+        let started = Instant::now();
+        let mut deferred = false;
+        for (i, &id) in due.iter().enumerate() {
+            if started.elapsed() >= budget {
+                self.scheduler.defer(&due[i..]);
+                deferred = true;
+                break;
+            }
+            self.think_one(id);
+        }
+        self.meter
+            .thought(started.elapsed(), deferred, tunables.think_budget);
+    }
+
+    fn think_one(&mut self, id: u32) {
+        let (Some(brain), Some(at)) = (self.brains.get_mut(&id), self.entities.position(id)) else {
+            self.brains.remove(&id);
+            return;
+        };
+        match brain.think(at) {
+            Thought::Search(goal) => {
+                let (reply, search) = oneshot::channel();
+                brain.searching(search);
+                self.find_path(at, goal, reply);
+            }
+            Thought::Step(to) => match self.entities.move_by(None, id, to) {
+                Ok(entity) => {
+                    self.send_entity(&entity);
+                    self.carry(Parent::Entity(id));
+                }
+                // Handed to a player or attached meanwhile
+                Err(_) => {
+                    self.brains.remove(&id);
+                }
+            },
+            Thought::Wait => {}
+            Thought::Arrived | Thought::Stuck(_) => {
+                self.brains.remove(&id);
+            }
+        }
+    }
+
+    // To the players looking at it, see effects.rs. How many
+    fn effect(&self, effect: &Effect) -> usize {
+        let ids: Vec<_> = self
+            .players
+            .iter()
+            .filter(|(_, p)| p.layer == effect.layer)
+            .filter(|(_, p)| effects::in_view(p.interest, effect.position))
+            .map(|(&id, _)| id)
+            .collect();
+        let mut frame = ServerFrame::new(self.tick as u32);
+        frame.effect(effect);
+        let reached = ids.len();
+        self.send_to(frame, ids);
+        reached
+    }
+
+    fn send_detach(&self, entity: &Entity) {
+        let mut frame = ServerFrame::new(self.tick as u32);
+        frame.detach(entity);
+        self.send_all(frame);
+    }
+
+    // Players are two blocks tall and can't be in solid blocks. Unloaded
+    // chunks, and whatever is above the highest block, read as air
+    fn inside_block(&self, (x, y, z): (i32, i32, i32)) -> bool {
+        [Some(y), y.checked_add(1)]
+            .into_iter()
+            .flatten()
+            .any(|y| self.blocks.is_solid(self.chunks.block(x, y, z)))
+    }
+
+    // Player `id` off their mount, where they were carried to
+    fn get_off(&mut self, id: u32) -> Result<(), EntityError> {
+        self.entities.dismount(id)?;
+        let position = self.players.get(&id).map(|p| p.position);
+        if let Some(position) = position {
+            let mut frame = ServerFrame::new(self.tick as u32);
+            frame.dismount(id, position);
+            self.send_all(frame);
+        }
+        Ok(())
+    }
+
+    // New origins for players far from theirs, see origin.rs. Right after
+    // the tick moved on, so frames of a tick are all from one origin
+    fn rebase(&mut self) {
+        for player in self.players.values_mut() {
+            if let Some(at) = player.origin
+                && origin::is_far(at, player.position)
+            {
+                player.origin = Some(origin::corner(player.position));
+            }
+        }
+    }
+
+    // Static ones go out with the next tick, to the players looking
+    fn send_entity(&mut self, entity: &Entity) {
+        if entity.fixed {
+            return;
+        }
+        let tick = self.tick as u32;
+        let mut frame = self.frames.frame(tick, ChunkFormat::default());
+        frame.entity(entity);
+        // Encoded once for the plain players, on the first one
+        let mut plain = None;
+        for (&id, player) in &self.players {
+            if self.hides(id, player, entity) {
+                continue;
+            }
+            let mut own = None;
+            let data = match player.is_plain() {
+                true => plain
+                    .get_or_insert_with(|| self.frames.finish(&mut frame))
+                    .clone(),
+                false => {
+                    let own = own.insert(self.frames.frame(tick, ChunkFormat::default()));
+                    own.entity(entity);
+                    player.fit(own);
+                    self.frames.finish(own)
+                }
+            };
+            // Only its newest state waits for a backed up player, see lanes.rs
+            let sent = player.tx.try_send_latest(Lane::Entities, entity.id, data);
+            if sent.is_ok() {
+                for (kind, bytes) in own.as_ref().unwrap_or(&frame).sizes() {
+                    player.traffic.record(Dir::Out, kind, bytes);
+                }
+            }
+            if let Some(own) = own {
+                self.frames.recycle(own);
+            }
+        }
+        self.frames.recycle(frame);
+    }
+
+    // Sends everyone the lockstep ticks that closed, see lockstep.rs
+    fn relay_lockstep(&mut self) {
+        let Some(lockstep) = &mut self.lockstep else {
+            return;
+        };
+        let tick = self.tick as u32;
+        let mut frame = ServerFrame::new(tick);
+        for closed in lockstep.advance() {
+            if frame.is_full() {
+                self.send_all(std::mem::replace(&mut frame, ServerFrame::new(tick)));
+            }
+            frame.lockstep(&closed);
+        }
+        if !frame.is_empty() {
+            self.send_all(frame);
+        }
+    }
+
+    // Sends everyone the rollback frames confirmed, see rollback.rs
+    fn confirm_rollback(&mut self) {
+        let Some(rollback) = &mut self.rollback else {
+            return;
+        };
+        let tick = self.tick as u32;
+        let mut frame = ServerFrame::new(tick);
+        for confirmed in rollback.advance() {
+            if frame.is_full() {
+                self.send_all(std::mem::replace(&mut frame, ServerFrame::new(tick)));
+            }
+            frame.rollback_confirm(&confirmed);
+        }
+        if !frame.is_empty() {
+            self.send_all(frame);
+        }
+    }
+
+    // Webhooks and, if replicated, TRIGGER for the triggers player `id`
+    // walked into or out of, see triggers.rs
+    fn cross_triggers(&mut self, id: u32, from: (i32, i32, i32), to: (i32, i32, i32)) {
+        let Some(triggers) = self.triggers.clone() else {
+            return;
+        };
+        let room = self.name();
+        let mut frame = ServerFrame::new(self.tick as u32);
+        let mut replicated = false;
+        for crossing in triggers.crossed(&room, from, to) {
+            let trigger = crossing.trigger.name.clone();
+            self.record(match crossing.entered {
+                true => JournalEvent::TriggerEntered { id, trigger },
+                false => JournalEvent::TriggerExited { id, trigger },
+            });
+            if crossing.trigger.replicate {
+                frame.trigger(&crossing.trigger.name, crossing.entered);
+                replicated = true;
+            }
+        }
+        if replicated {
+            self.send_to(frame, [id]);
+        }
+    }
+
+    // Sends player `id` through `portal`. Connections move between rooms
+    // themselves, see `change_room`.
+    fn send_through(&mut self, id: u32, portal: Arc<Portal>) {
+        let Some(player) = self.players.get_mut(&id) else {
+            return;
+        };
+        player.stats.count(stats::PORTALS_USED, 1);
+        let leave = match portal.destination() {
+            Destination::Room(to) => Leave::Room {
+                to: to.map(String::from),
+                portal: Some(portal.clone()),
+            },
+            Destination::Server(address) => Leave::Server {
+                address: address.to_string(),
+                token: self.resume.issue(&portal.carry(player.session())),
+            },
+        };
+        player.leave.try_send(leave).ok();
+    }
+
+    // Webhooks and the rest follow, see journal.rs
+    fn record(&mut self, event: JournalEvent) {
+        self.progress(&event);
+        if self.journal.is_followed() {
+            self.journal.record(&self.name(), self.tick, event);
+        }
+    }
+
+    // Counts a player's event towards the world's achievements, see
+    // achievements.rs
+    fn progress(&mut self, event: &JournalEvent) {
+        if self.achievements.is_empty() {
+            return;
+        }
+        let Some((id, by)) = achievements::counted(event) else {
+            return;
+        };
+        let Some(player) = self.players.get_mut(&id) else {
+            return;
+        };
+        let properties = match &mut player.record {
+            Some(record) => &mut record.properties,
+            None => &mut player.achievements,
+        };
+        let all = self.achievements.clone();
+        for achievement in achievements::progress(&all, event, by, properties) {
+            let mut frame = ServerFrame::new(self.tick as u32);
+            frame.achievement(&achievement.name, &achievement.title);
+            self.send_to(frame, [id]);
+            let name = achievement.name.clone();
+            self.record(JournalEvent::AchievementEarned { id, name });
+        }
+    }
+
+    // Whole blocks each player moved since the last call, once a second
+    fn record_travel(&mut self) {
+        let traveled: Vec<_> = (self.players.iter_mut())
+            .map(|(&id, player)| (id, player.stats.take_traveled()))
+            .filter(|&(_, blocks)| blocks > 0)
+            .collect();
+        for (id, blocks) in traveled {
+            self.record(JournalEvent::Traveled { id, blocks });
+        }
+    }
+
+    // Same frame to every player, dropped for full channels
+    fn send_all(&self, frame: ServerFrame) {
+        self.send_to(frame, self.players.keys().copied());
+    }
+
+    // Same frame to players `ids`, dropped for full channels
+    fn send_to(&self, frame: ServerFrame, ids: impl IntoIterator<Item = u32>) {
+        let players = ids.into_iter().filter_map(|id| self.players.get(&id));
+        // Each from their own origin and in their coordinates
+        let (fitted, plain): (Vec<&Player>, Vec<_>) = players.partition(|p| !p.is_plain());
+        for player in fitted {
+            player.send(frame.clone());
+        }
+        let sizes: Vec<_> = frame.sizes().collect();
+        let lane = Lane::of(&frame);
+        let frame = frame.finish();
+        for player in plain {
+            if player.tx.try_send(lane, frame.clone()).is_ok() {
+                for &(kind, bytes) in &sizes {
+                    player.traffic.record(Dir::Out, kind, bytes);
+                }
+            }
+        }
+    }
+
+    // Players and held places at the template's `max_players`
+    fn full(&mut self, now: Instant) -> bool {
+        let taken = self.players.len() + self.reservations.held(now);
+        self.max_players.is_some_and(|max| taken >= max)
+    }
+
+    // Out of the rooms, the task ends once the last connection drops its
+    // sender
+    fn close_room(&mut self) {
+        if let Some((name, rooms)) = self.room.take() {
+            rooms.lock().unwrap().remove(&name);
+            self.features.reset(&name);
+            self.quotas.forget(&name);
+            self.journal
+                .record(&name, self.tick, JournalEvent::RoomDestroyed);
+        }
+    }
+
+    // Loads the chunks around the spawn point before anyone joins, for
+    // pooled rooms
+    fn warm(&mut self) {
+        let (x, y, z) = self.spawn_point();
+        let center = (chunk::split(x).0, chunk::split(y).0, chunk::split(z).0);
+        self.chunks.request_area(center, pool::WARM_RADIUS);
+    }
+
+    // On top of the terrain at the world origin, or at the center of its
+    // spawn trigger (see triggers.rs)
+    fn spawn_point(&self) -> (i32, i32, i32) {
+        let spawn = self.triggers.as_ref().and_then(|t| t.spawn(&self.name()));
+        let (x, y, z) = spawn.unwrap_or_default();
+        let y = self.chunks.surface_height(x, z).map_or(y, |y| y + 1);
+        (x, y, z)
+    }
+
+    // Storage is async, so saves run off the tick loop
+    fn save_players(&self, records: Vec<PlayerRecord>) {
+        if let Some(saves) = &self.saves {
+            saves.save(records);
+        }
+    }
+
+    fn save_stats(&self, deltas: Vec<StatDelta>) {
+        let Some(storage) = self.stats.clone() else {
+            return;
+        };
+
+        if deltas.is_empty() {
+            return;
+        }
+
+        tokio::spawn(async move {
+            if let Err(e) = storage.add_stats(&deltas).await {
+                eprintln!("Error saving stats: {e}");
+            }
+        });
+    }
+
+    // Whether a layer or the world's relevance rule hides `entity` from
+    // player `id`
+    fn hides(&self, id: u32, player: &Player, entity: &Entity) -> bool {
+        if self.relevance.is_none() && !self.layered {
+            return false;
+        }
+        let rule = self.relevance.as_deref();
+        relevance::judge(rule, &player.viewer(id), entity) == Relevance::Hide
+    }
+
+    // Asks layers and the world's relevance rule about every entity for
+    // every player: newly hidden ones go with `ENTITY_GONE`, dynamic ones
+    // shown again come back, static ones follow with `send_statics`
+    fn judge_relevance(&mut self) {
+        if self.relevance.is_none() && !self.layered {
+            return;
+        }
+        let rule = self.relevance.as_deref();
+        let tick = self.tick as u32;
+        let (gone, back) = &mut self.judged;
+        let pool = &mut self.frames;
+        for (&id, player) in &mut self.players {
+            let viewer = player.viewer(id);
+            player.shown.clear();
+            for entity in self.entities.iter() {
+                let relevance = relevance::judge(rule, &viewer, entity);
+                if relevance == Relevance::Hide {
+                    let held = !entity.fixed || player.statics.remove(&entity.id).is_some();
+                    if player.hidden.insert(entity.id) && held {
+                        gone.push(entity.id);
+                    }
+                    continue;
+                }
+                if player.hidden.remove(&entity.id) && !entity.fixed {
+                    back.push(*entity);
+                }
+                if relevance == Relevance::Show && entity.fixed {
+                    player.shown.insert(entity.id);
+                }
+            }
+            if gone.is_empty() && back.is_empty() {
+                continue;
+            }
+            let mut frame = pool.frame(tick, ChunkFormat::default());
+            for id in gone.drain(..) {
+                if frame.is_full() {
+                    let full =
+                        std::mem::replace(&mut frame, pool.frame(tick, ChunkFormat::default()));
+                    send_pooled(player, pool, full);
+                }
+                frame.entity_gone(id);
+            }
+            for entity in back.drain(..) {
+                if frame.is_full() {
+                    let full =
+                        std::mem::replace(&mut frame, pool.frame(tick, ChunkFormat::default()));
+                    send_pooled(player, pool, full);
+                }
+                frame.entity(&entity);
+            }
+            send_pooled(player, pool, frame);
+        }
+    }
+
+    fn broadcast_tick(&mut self) {
+        self.judge_relevance();
+        let edits = self.chunks.take_edits();
+        let tick = self.tick as u32;
+        let chunk_format = self.chunk_format;
+        // Snapshots are big, so each player gets a few per tick
+        let max_snapshots = self.tunables.borrow().snapshots_per_tick;
+        let (pool, positions, near) = (&mut self.frames, &mut self.framed, &mut self.near);
+        let profiler = &mut self.profiler;
+        let mut lap = profiler.start();
+        // Over the bandwidth quota chunks wait, see quotas.rs
+        let send_chunks_now = self.meter.can_send_chunks();
+
+        for player in self.players.values_mut() {
+            send_ack(player, pool, tick);
+            let Some(reach) = player.reach() else {
+                continue;
+            };
+            send_statics(player, &self.entities, near, pool, tick);
+            if !send_chunks_now {
+                continue;
+            }
+
+            // Chunks that left the interest are forgotten, so coming back
+            // into view sends a fresh snapshot
+            player.chunks.retain(|&pos, _| reach.contains(pos));
+
+            let mut frame = pool.frame(tick, chunk_format);
+            let mut snapshots = 0;
+
+            for pos in reach.chunks() {
+                let Some(version) = self.chunks.version(pos) else {
+                    continue;
+                };
+
+                let held = player.chunks.get(&pos).copied();
+                if held == Some(version) {
+                    continue;
+                }
+
+                if frame.is_full() {
+                    let full = std::mem::replace(&mut frame, pool.frame(tick, chunk_format));
+                    send_chunks(player, pool, full, positions, profiler, &mut lap);
+                }
+
+                profiler.lap(Phase::Interest, &mut lap);
+                match edits.get(&pos) {
+                    Some(e) if held == Some(e.base_version) => {
+                        frame.chunk_delta(pos, e.base_version, e.version, &e.edits);
+                    }
+                    // Unknown base: the client gets the whole chunk
+                    _ => {
+                        if snapshots == max_snapshots {
+                            continue;
+                        }
+                        snapshots += 1;
+                        let Some(chunk) = self.chunks.get(pos) else {
+                            continue;
+                        };
+                        frame.chunk_snapshot(pos, version, chunk);
+                    }
+                }
+
+                profiler.lap(Phase::Encode, &mut lap);
+                player.chunks.insert(pos, version);
+                positions.push(pos);
+            }
+
+            profiler.lap(Phase::Interest, &mut lap);
+            send_chunks(player, pool, frame, positions, profiler, &mut lap);
+        }
+        profiler.lap(Phase::Interest, &mut lap);
+    }
+}
+
+// Sends a frame of `broadcast_tick`'s, the client may not hold the chunks
+// in `positions` if it's dropped
+fn send_chunks(
+    player: &mut Player,
+    pool: &mut FramePool,
+    mut frame: ServerFrame,
+    positions: &mut Vec<ChunkPos>,
+    profiler: &mut Profiler,
+    lap: &mut Option<Instant>,
+) {
+    if !frame.is_empty() {
+        player.fit(&mut frame);
+        let data = pool.finish(&mut frame);
+        profiler.lap(Phase::Encode, lap);
+        if player.tx.try_send(Lane::Chunks, data).is_ok() {
+            for (kind, bytes) in frame.sizes() {
+                player.traffic.record(Dir::Out, kind, bytes);
+            }
+        } else {
+            for pos in positions.iter() {
+                player.chunks.remove(pos);
+            }
+        }
+    }
+    positions.clear();
+    pool.recycle(frame);
+    profiler.lap(Phase::Send, lap);
+}
+
+// `Player::send` with a frame from `pool`, handed back after
+fn send_pooled(player: &Player, pool: &mut FramePool, mut frame: ServerFrame) {
+    player.fit(&mut frame);
+    let data = pool.finish(&mut frame);
+    if player.tx.try_send(Lane::of(&frame), data).is_ok() {
+        for (kind, bytes) in frame.sizes() {
+            player.traffic.record(Dir::Out, kind, bytes);
+        }
+    }
+    pool.recycle(frame);
+}
+
+// The static entities in the player's interest it doesn't hold as they
+// are, see entities.rs. Dropped frames are sent again next tick.
+// `INPUT_ACK` when inputs were applied since the last one, ahead of the
+// tick's chunks and entities
+fn send_ack(player: &mut Player, pool: &mut FramePool, tick: u32) {
+    let Some(seq) = player.acked.filter(|_| player.acked != player.sent_ack) else {
+        return;
+    };
+    let mut frame = pool.frame(tick, ChunkFormat::default());
+    frame.input_ack(seq);
+    let data = pool.finish(&mut frame);
+    if player.tx.try_send(Lane::Control, data).is_ok() {
+        for (kind, bytes) in frame.sizes() {
+            player.traffic.record(Dir::Out, kind, bytes);
+        }
+        player.sent_ack = Some(seq);
+    }
+    pool.recycle(frame);
+}
+
+fn send_statics(
+    player: &mut Player,
+    entities: &Entities,
+    near: &mut Vec<u32>,
+    pool: &mut FramePool,
+    tick: u32,
+) {
+    let area = player
+        .interest
+        .map(|(center, radius)| interest::blocks(center, radius));
+    let near = area
+        .map(|(lo, hi)| entities.within(lo, hi, near))
+        .into_iter()
+        .flatten();
+    // Relevant anyway, the ones out of the interest
+    let forced = entities.pick(&player.relevant).filter(|e| !e.always);
+    let shown = entities.pick(&player.shown);
+    let shown = shown.filter(|e| !e.always && !player.relevant.contains(&e.id));
+    let far = entities.always().chain(forced).chain(shown);
+    let far = far.filter(|e| !effects::in_view(player.interest, e.position));
+    let mut in_view = near.chain(far).filter(|e| {
+        e.fixed && !player.hidden.contains(&e.id) && player.statics.get(&e.id) != Some(e)
+    });
+    // Nothing new most ticks
+    let Some(first) = in_view.next() else {
+        return;
+    };
+    let mut frames = Vec::new();
+    let mut frame = (pool.frame(tick, ChunkFormat::default()), Vec::new());
+    for entity in [first].into_iter().chain(in_view) {
+        if frame.0.is_full() {
+            let next = (pool.frame(tick, ChunkFormat::default()), Vec::new());
+            frames.push(std::mem::replace(&mut frame, next));
+        }
+        frame.0.entity(entity);
+        frame.1.push(*entity);
+    }
+    frames.push(frame);
+    for (mut frame, sent) in frames {
+        player.fit(&mut frame);
+        let data = pool.finish(&mut frame);
+        if player.tx.try_send(Lane::Entities, data).is_ok() {
+            for (kind, bytes) in frame.sizes() {
+                player.traffic.record(Dir::Out, kind, bytes);
+            }
+            player.statics.extend(sent.into_iter().map(|e| (e.id, e)));
+        }
+        pool.recycle(frame);
+    }
+}
+
+impl CommandWorld for World {
+    fn world_name(&self) -> String {
+        self.name()
+    }
+
+    fn position(&self, id: u32) -> Option<(i32, i32, i32)> {
+        self.players.get(&id).map(|p| p.position)
+    }
+
+    fn find(&self, name: &str) -> Option<u32> {
+        let mut players = self.players.iter();
+        players
+            .find(|(_, p)| p.name.as_deref() == Some(name))
+            .map(|(&id, _)| id)
+    }
+
+    fn teleport(&mut self, id: u32, to: (i32, i32, i32)) -> Result<(), String> {
+        if self.mode != RoomMode::World {
+            return Err(format!("Not simulated in {} rooms", self.mode));
+        }
+        if self.inside_block(to) {
+            let (x, y, z) = to;
+            return Err(format!("{x} {y} {z} is inside a block"));
+        }
+        self.get_off(id).ok();
+        // Moves like the player did, through portals too
+        self.apply_msg(WorldMsg::SetPosition {
+            id,
+            position: to,
+            seq: None,
+        });
+        if let Some(player) = self.players.get(&id) {
+            let mut frame = ServerFrame::new(self.tick as u32);
+            frame.teleport(to);
+            player.send(frame);
+        }
+        Ok(())
+    }
+
+    fn give(&mut self, id: u32, item: u16, count: u32) -> Result<(), String> {
+        if !self.blocks.contains(item) {
+            return Err(format!("Unknown item {item}"));
+        }
+        let record = self.players.get_mut(&id).and_then(|p| p.record.as_mut());
+        let Some(record) = record else {
+            return Err("Inventories need ?name= and storage, in the main world".to_string());
+        };
+        if !record.give(item, count) {
+            return Err("Inventory is full".to_string());
+        }
+        Ok(())
+    }
+}
+
+// Avoid float math + rounding drift
+// `scale` percent of `tick_hz`, see simulation.rs
+fn tick_interval(tick_hz: u32, scale: u16) -> Interval {
+    let tick = Duration::from_nanos(100_000_000_000u64 / (tick_hz as u64 * scale as u64));
+    let mut ticker = tokio::time::interval(tick);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    ticker
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().collect();
+    if let Some(code) = cli::run(&args) {
+        return code;
+    }
+
+    let vars = match Vars::load() {
+        Ok(vars) => vars,
+        Err(e) => {
+            eprintln!("Config {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let mut config = Config::from_vars(&vars);
+    if args.iter().skip(1).any(|a| a == "--profile") {
+        config.profile = Some(ProfileConfig::from_vars(&vars));
+    }
+
+    // Panics still print to stderr, reports carry the task context on top
+    if let Some(target) = config.crash
+        && let Err(e) = crash::install(target)
+    {
+        eprintln!("Crash reporting disabled: {e}");
+    }
+
+    let generator: Option<Arc<dyn ChunkGenerator>> = match config.generator {
+        GeneratorKind::None => None,
+        GeneratorKind::Flat => Some(Arc::new(FlatGenerator)),
+        GeneratorKind::Noise => Some(Arc::new(NoiseGenerator::new(config.world_seed))),
+    };
+
+    // Persistence is optional, e.g. TELEBOXEL_DATABASE_URL=sqlite://teleboxel.db
+    // (postgres:// and redis:// need the matching cargo feature)
+    let storage = match &config.database_url {
+        Some(url) => Some(storage::connect(url).await.unwrap()),
+        None => None,
+    };
+
+    // Chunks load lazily from the world dir (e.g. filled by
+    // `teleboxel import-vox map.vox <world_dir>`), else from storage, else
+    // get generated. Claims are saved next to them.
+    let saves = match (&config.world_dir, &storage) {
+        (Some(dir), _) => Some(Saves::Dir(Arc::new(dir.clone()))),
+        (None, Some(storage)) => Some(Saves::Storage(storage.clone())),
+        (None, None) => None,
+    };
+    let claims = match &saves {
+        Some(Saves::Storage(storage)) => Claims::load_stored(storage.clone()).await.unwrap(),
+        _ => Claims::load(config.world_dir.as_deref()).unwrap(),
+    };
+    let cache_config = CacheConfig {
+        saves,
+        memory_budget: config.chunk_cache_mb * 1024 * 1024,
+        workers: std::thread::available_parallelism().map_or(4, |n| n.get()),
+    };
+    let chunks = ChunkCache::new(cache_config, generator);
+
+    let backups = match (config.backup, &storage) {
+        (Some(backup), Some(storage)) => {
+            let backups = Backups::new(backup, storage.clone(), config.world_dir.clone());
+            tokio::spawn(backups.clone().schedule());
+            Some(backups)
+        }
+        (Some(_), None) => {
+            eprintln!("Backups need TELEBOXEL_DATABASE_URL, backups disabled");
+            None
+        }
+        _ => None,
+    };
+
+    let blocks = match &config.blocks {
+        Some(path) => match BlockRegistry::load(path) {
+            Ok(blocks) => blocks,
+            Err(e) => {
+                eprintln!("Block registry {}: {e}", path.display());
+                return ExitCode::FAILURE;
+            }
+        },
+        None => BlockRegistry::default(),
+    };
+    let blocks = Arc::new(blocks);
+    println!("Loaded {} block types", blocks.len());
+
+    let traffic = Arc::new(Traffic::default());
+    if !config.traffic_log_interval.is_zero() {
+        tokio::spawn(traffic.clone().log_top_talkers(config.traffic_log_interval));
+    }
+
+    let usage = match config.usage {
+        Some(usage) => match UsageLog::open(usage) {
+            Ok(log) => Some(Arc::new(log)),
+            Err(e) => {
+                eprintln!("Usage log: {e}");
+                return ExitCode::FAILURE;
+            }
+        },
+        None => None,
+    };
+    if let Some(usage) = &usage {
+        tokio::spawn(usage.clone().run(traffic.clone()));
+    }
+
+    let telemetry = config
+        .otlp
+        .map(|otlp| Telemetry::start(otlp, traffic.clone()));
+
+    let audit = match config.audit {
+        Some(audit) => match AuditLog::open(audit) {
+            Ok(log) => Some(Arc::new(log)),
+            Err(e) => {
+                eprintln!("Audit log: {e}");
+                return ExitCode::FAILURE;
+            }
+        },
+        None => None,
+    };
+
+    let webhooks = config.webhooks.map(|w| Arc::new(Webhooks::start(w)));
+    let (events, _) = broadcast::channel(EVENT_BUFFER);
+    let journal = Arc::new(Journal::default());
+    tokio::spawn(webhooks::follow(
+        journal.subscribe(),
+        events.clone(),
+        webhooks.clone(),
+    ));
+    let analytics = match config.analytics {
+        Some(analytics) => match Analytics::start(analytics, journal.subscribe()) {
+            Ok(analytics) => Some(analytics),
+            Err(e) => {
+                eprintln!("Analytics: {e}");
+                return ExitCode::FAILURE;
+            }
+        },
+        None => None,
+    };
+    let (tunables, tunables_rx) = watch::channel(config.tunables);
+    tokio::spawn(reload::run(vars, tunables.clone()));
+
+    let listeners = match &config.listeners {
+        Some(path) => match Listeners::load(path) {
+            Ok(listeners) => listeners,
+            Err(e) => {
+                eprintln!("Listeners {}: {e}", path.display());
+                return ExitCode::FAILURE;
+            }
+        },
+        None => Listeners::default(),
+    };
+    // Taken over from the previous server on a hot restart
+    let sockets = match listeners::bind(&listeners.list) {
+        Ok(sockets) => sockets,
+        Err(e) => {
+            eprintln!("Listen: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let resume = Arc::new(ResumeKey::new(config.resume_secret));
+    let successor_env = vec![(
+        "TELEBOXEL_RESUME_SECRET".to_string(),
+        resume.secret().to_string(),
+    )];
+    let mut listening = Vec::new();
+    for (listener, socket) in listeners.list.iter().zip(&sockets) {
+        match socket.listening(listener) {
+            Ok(bound) => {
+                println!("Listening on {bound}");
+                listening.push(bound);
+            }
+            Err(e) => {
+                eprintln!("Listen on {listener}: {e}");
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+    let fds = sockets.iter().map(Socket::fd).collect();
+    let handover = Arc::new(Handover::new(fds, successor_env));
+
+    let (tx, rx) = mpsc::channel::<WorldMsg>(128);
+    let rooms = Rooms::default();
+
+    let bridges = match &config.bridges {
+        Some(path) => match BridgeConfig::load(path) {
+            Ok(bridges) => Some(Arc::new(Bridges::start(
+                bridges,
+                inbound_chat(tx.clone(), rooms.clone()),
+            ))),
+            Err(e) => {
+                eprintln!("Bridges {}: {e}", path.display());
+                return ExitCode::FAILURE;
+            }
+        },
+        None => None,
+    };
+
+    let portals = match &config.portals {
+        Some(path) => match Portals::load(path) {
+            Ok(portals) => Some(Arc::new(portals)),
+            Err(e) => {
+                eprintln!("Portals {}: {e}", path.display());
+                return ExitCode::FAILURE;
+            }
+        },
+        None => None,
+    };
+
+    let triggers = match &config.triggers {
+        Some(path) => match Triggers::load(path) {
+            Ok(triggers) => Some(Arc::new(triggers)),
+            Err(e) => {
+                eprintln!("Triggers {}: {e}", path.display());
+                return ExitCode::FAILURE;
+            }
+        },
+        None => None,
+    };
+
+    let flood = match &config.flood {
+        Some(path) => match Flood::load(path) {
+            Ok(flood) => Arc::new(flood),
+            Err(e) => {
+                eprintln!("Flood {}: {e}", path.display());
+                return ExitCode::FAILURE;
+            }
+        },
+        None => Arc::default(),
+    };
+
+    let afk = match &config.afk {
+        Some(path) => match AfkRules::load(path) {
+            Ok(afk) => Arc::new(afk),
+            Err(e) => {
+                eprintln!("AFK {}: {e}", path.display());
+                return ExitCode::FAILURE;
+            }
+        },
+        None => Arc::default(),
+    };
+
+    let relevance = match &config.relevance {
+        Some(path) => match RelevanceRules::load(path) {
+            Ok(relevance) => Arc::new(relevance),
+            Err(e) => {
+                eprintln!("Relevance {}: {e}", path.display());
+                return ExitCode::FAILURE;
+            }
+        },
+        None => Arc::default(),
+    };
+
+    let coords = match &config.coords {
+        Some(path) => match CoordsRules::load(path) {
+            Ok(coords) => Arc::new(coords),
+            Err(e) => {
+                eprintln!("Coords {}: {e}", path.display());
+                return ExitCode::FAILURE;
+            }
+        },
+        None => Arc::default(),
+    };
+
+    let achievements = match &config.achievements {
+        Some(path) => match AchievementRules::load(path) {
+            Ok(achievements) => Arc::new(achievements),
+            Err(e) => {
+                eprintln!("Achievements {}: {e}", path.display());
+                return ExitCode::FAILURE;
+            }
+        },
+        None => Arc::default(),
+    };
+
+    let tenants = match Tenants::load(config.tenants.as_deref()) {
+        Ok(tenants) => Arc::new(tenants),
+        Err(e) => {
+            eprintln!("Tenants: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let quotas = match &config.quotas {
+        Some(path) => match Quotas::load(path) {
+            Ok(quotas) => Arc::new(quotas),
+            Err(e) => {
+                eprintln!("Quotas {}: {e}", path.display());
+                return ExitCode::FAILURE;
+            }
+        },
+        None => Arc::default(),
+    };
+
+    let templates = match &config.templates {
+        Some(path) => match Templates::load(path) {
+            Ok(templates) => Arc::new(templates),
+            Err(e) => {
+                eprintln!("Templates {}: {e}", path.display());
+                return ExitCode::FAILURE;
+            }
+        },
+        None => Arc::default(),
+    };
+
+    let features = match &config.features {
+        Some(path) => match FeatureFlags::load(path) {
+            Ok(features) => Arc::new(features),
+            Err(e) => {
+                eprintln!("Features {}: {e}", path.display());
+                return ExitCode::FAILURE;
+            }
+        },
+        None => Arc::default(),
+    };
+
+    // Clients pin it to trust the session keys, see secure.rs
+    let protected = config.encryption != Policy::Off || config.message_auth != Policy::Off;
+    let server_key = match (&config.server_key, protected) {
+        (Some(secret), _) => match ServerKey::parse(secret) {
+            Ok(key) => Some(Arc::new(key)),
+            Err(e) => {
+                eprintln!("TELEBOXEL_SERVER_KEY: {e}");
+                return ExitCode::FAILURE;
+            }
+        },
+        (None, true) => {
+            eprintln!(
+                "Encryption and message auth need TELEBOXEL_SERVER_KEY, see `teleboxel server-key`"
+            );
+            return ExitCode::FAILURE;
+        }
+        (None, false) => None,
+    };
+
+    let auth: Option<Arc<dyn Authenticator>> = match (config.jwt, config.auth_callout) {
+        (Some(_), Some(_)) => {
+            eprintln!("Set TELEBOXEL_JWKS_URL or TELEBOXEL_AUTH_URL, not both");
+            return ExitCode::FAILURE;
+        }
+        (Some(jwt), None) => match Jwt::new(jwt) {
+            Ok(jwt) => Some(Arc::new(jwt)),
+            Err(e) => {
+                eprintln!("TELEBOXEL_JWKS_URL: {e}");
+                return ExitCode::FAILURE;
+            }
+        },
+        (None, Some(callout)) => Some(Arc::new(HttpAuthenticator::new(callout))),
+        (None, None) => None,
+    };
+
+    let handle = WorldHandle {
+        main: tx.clone(),
+        tx,
+        saves: storage.clone().map(SaveQueue::new),
+        storage,
+        claims: claims.clone(),
+        blocks,
+        rooms,
+        chunk_format: config.chunk_format,
+        spatial: config.spatial,
+        traffic: traffic.clone(),
+        telemetry,
+        audit: audit.clone(),
+        bridges: bridges.clone(),
+        portals,
+        triggers,
+        flood,
+        afk,
+        relevance,
+        coords,
+        achievements,
+        features,
+        quotas: quotas.clone(),
+        tenants: tenants.clone(),
+        usage: usage.clone(),
+        templates: templates.clone(),
+        pools: Arc::default(),
+        tenant: None,
+        wire_coords: false,
+        history: config.history,
+        input: config.input,
+        voice: config.voice,
+        profile: config.profile.clone(),
+        frame_history: config.frame_history,
+        journal: journal.clone(),
+        tunables: tunables_rx,
+        drain: Arc::default(),
+        admission: Arc::new(Admission::new(config.admission)),
+        resume,
+        handover: handover.clone(),
+        presence: Arc::default(),
+        mutes: Arc::default(),
+        chat: Arc::default(),
+        auth,
+        auth_timeout: config.auth_timeout,
+        guests: config.guests,
+        encryption: config.encryption,
+        message_auth: config.message_auth,
+        server_key,
+    };
+    let world = World::new(rx, &handle, chunks, None);
+    let context = Context::new("world", world.name());
+    tokio::spawn(crash::scope(context, world.run(config.save_interval)));
+
+    let world_control = Arc::new(handle.clone());
+    let control = Arc::new(Control::new(world_control.clone(), tunables, audit.clone()));
+    if config.console && std::io::stdin().is_terminal() {
+        tokio::spawn(Console::default().run(control.clone()));
+    }
+    if let Some(path) = &config.control_socket {
+        // The previous server's socket, it's on its way out
+        if restart::inherited().is_some() {
+            std::fs::remove_file(path).ok();
+        }
+        #[cfg(unix)]
+        if let Err(e) = control::listen(path, control) {
+            eprintln!("Control socket {}: {e}", path.display());
+            return ExitCode::FAILURE;
+        }
+        #[cfg(not(unix))]
+        eprintln!("Control socket {} needs Unix, disabled", path.display());
+    }
+    #[cfg(unix)]
+    tokio::spawn(restart_on_signal(world_control.clone()));
+    if templates
+        .iter()
+        .any(|(_, template)| template.pool_max() > 0)
+    {
+        tokio::spawn(fill_pools(world_control.clone()));
+    }
+    let stopping = events.clone();
+    let (drain, players) = (handle.drain.clone(), traffic.clone());
+    let main_world = handle.main.clone();
+    let (online, storage) = (handle.presence.clone(), handle.storage.clone());
+    let mut routes = Routes::default();
+    let ws = Router::new().route("/", get(ws_handler)).with_state(handle);
+    routes.add(listeners::Route::Ws, ws);
+
+    if let Some(storage) = &storage {
+        let state = StatsState {
+            storage: storage.clone(),
+        };
+        routes.add(
+            listeners::Route::Stats,
+            Router::new().nest("/stats", stats::router(state)),
+        );
+    }
+
+    if let Some(token) = config.presence_token {
+        let state = PresenceState {
+            token: token.into(),
+            presence: online,
+            storage: storage.clone(),
+        };
+        let router = Router::new().nest("/presence", presence::router(state));
+        routes.add(listeners::Route::Presence, router);
+    }
+
+    if let Some(token) = config.admin_token {
+        let state = AdminState {
+            token: token.into(),
+            moderator_token: config.moderator_token.map(Into::into),
+            storage,
+            audit,
+            backups,
+            claims,
+            traffic: traffic.clone(),
+            quotas,
+            tenants,
+            templates,
+            listeners: Arc::new(listening),
+            world: world_control,
+            events,
+            journal,
+        };
+        let router = Router::new()
+            .nest("/admin", admin::router(state.clone()))
+            .merge(grpc::router(state));
+        routes.add(listeners::Route::Admin, router);
+    }
+
+    if let Some(webhooks) = &webhooks {
+        webhooks.notify(WebhookEvent::ServerStarted {
+            version: env!("CARGO_PKG_VERSION"),
+        });
+    }
+    if let Some(bridges) = &bridges {
+        bridges.notify("", BridgeEvent::ServerStarted);
+    }
+
+    // Peer addresses are kept for the audit log
+    let (stop, stopped) = watch::channel(false);
+    let mut servers = JoinSet::new();
+    for (listener, socket) in listeners.list.iter().zip(sockets) {
+        let app = routes.app(listener);
+        let app = app.into_make_service_with_connect_info::<SocketAddr>();
+        let mut stopped = stopped.clone();
+        let shutdown = async move {
+            stopped.wait_for(|stop| *stop).await.ok();
+        };
+        let handover = handover.clone();
+        servers.spawn(async move { listeners::serve(socket, &handover, app, shutdown).await });
+    }
+    restart::ready();
+    #[cfg(all(unix, feature = "systemd"))]
+    {
+        // A successor is the main process from now on
+        systemd::notify(&format!("READY=1\nMAINPID={}", std::process::id()));
+        if let Some(interval) = systemd::watchdog_interval() {
+            let main_world = main_world.clone();
+            tokio::spawn(async move {
+                let alive = || world_answers(main_world.clone());
+                systemd::watchdog(interval, alive).await;
+            });
+        }
+    }
+    let agones = config.agones.map(|config| Arc::new(Agones::new(config)));
+    if let Some(agones) = agones.clone() {
+        let traffic = traffic.clone();
+        tokio::spawn(async move {
+            agones.ready().await;
+            let alive = || world_answers(main_world.clone());
+            agones.run(alive, || traffic.players().len()).await;
+        });
+    }
+    select! {
+        _ = shutdown_signal() => {}
+        _ = drain.finished(|| players.players().len()) => {
+            println!("Drained, shutting down");
+        }
+    }
+    // Closes `/admin/events` streams
+    stopping.send(WebhookEvent::ServerStopping).ok();
+    stop.send_replace(true);
+    while let Some(served) = servers.join_next().await {
+        served.unwrap().unwrap();
+    }
+
+    // After a hot restart the successor is the service
+    #[cfg(all(unix, feature = "systemd"))]
+    if !handover.is_handed_over() {
+        systemd::notify("STOPPING=1");
+    }
+    if let Some(webhooks) = &webhooks {
+        webhooks.notify(WebhookEvent::ServerStopping);
+        webhooks.shutdown(Duration::from_secs(5)).await;
+    }
+    if let Some(analytics) = &analytics {
+        analytics.shutdown(Duration::from_secs(5)).await;
+    }
+    if let Some(bridges) = &bridges {
+        bridges.notify("", BridgeEvent::ServerStopping);
+        bridges.shutdown(Duration::from_secs(5)).await;
+    }
+    if let Some(usage) = &usage {
+        usage.export(&traffic.players());
+    }
+    // After a hot restart they're the successor's
+    if !handover.is_handed_over() {
+        let unix = listeners.list.iter().filter_map(|l| l.unix.as_ref());
+        for path in config.control_socket.iter().chain(unix) {
+            std::fs::remove_file(path).ok();
+        }
+    }
+    if let Some(agones) = &agones
+        && !handover.is_handed_over()
+    {
+        agones.shutdown().await;
+    }
+
+    ExitCode::SUCCESS
+}
+
+// Hands lines from chat bridges to the main world or the named room, if
+// it's open
+fn inbound_chat(main: mpsc::Sender<WorldMsg>, rooms: Rooms) -> bridge::Inbound {
+    Arc::new(move |room, bridge, from, text| {
+        let tx = match room {
+            "main" => Some(main.clone()),
+            room => rooms.lock().unwrap().get(room).cloned(),
+        };
+        if let Some(tx) = tx {
+            let bridge = Some(bridge);
+            let sender = None;
+            tx.try_send(WorldMsg::Chat {
+                from,
+                text,
+                bridge,
+                sender,
+            })
+            .ok();
+        }
+    })
+}
+
+// Whether the world task handles messages, for health checks
+async fn world_answers(tx: mpsc::Sender<WorldMsg>) -> bool {
+    let (reply, rx) = oneshot::channel();
+    tx.send(WorldMsg::State { reply }).await.is_ok() && rx.await.is_ok()
+}
+
+// Moves each template's warm pool a room a second toward its target, see
+// pool.rs
+async fn fill_pools(handle: Arc<WorldHandle>) {
+    let mut ticker = tokio::time::interval(Duration::from_secs(1));
+    loop {
+        ticker.tick().await;
+        if handle.drain.is_draining() {
+            continue;
+        }
+        for (name, template) in handle.templates.iter() {
+            let (min, max) = (template.pool, template.pool_max());
+            let target = handle.pools.target(name, min, max, Instant::now());
+            let idle = handle.pools.idle(name);
+            if idle < target {
+                let room = handle.free_room_name(name);
+                // Failing is likely being out of rooms, tried again next
+                // second
+                if let Ok(room) = handle.open_template(room, template).await {
+                    handle.pools.add(name, room);
+                }
+            } else if idle > target
+                && let Some(room) = handle.pools.trim(name)
+                && let Some(tx) = handle.world_tx(Some(&room))
+            {
+                let (reply, _) = oneshot::channel();
+                tx.send(WorldMsg::Close { reply }).await.ok();
+            }
+        }
+    }
+}
+
+// SIGUSR2 starts a hot restart, like the console's `restart`
+#[cfg(unix)]
+async fn restart_on_signal(world: Arc<WorldHandle>) {
+    use tokio::signal::unix::{SignalKind, signal};
+    let Ok(mut signals) = signal(SignalKind::user_defined2()) else {
+        return;
+    };
+    while signals.recv().await.is_some() {
+        if let Err(e) = world.restart(60).await {
+            eprintln!("Restart: {e}");
+        }
+    }
+}
+
+// Ctrl-C, or SIGTERM on Unix
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c().await.ok();
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{SignalKind, signal};
+        match signal(SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                sigterm.recv().await;
+            }
+            Err(_) => std::future::pending().await,
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+    println!("Shutting down");
+}
+
+async fn ws_handler(
+    State(mut handle): State<WorldHandle>,
+    ConnectInfo(remote): ConnectInfo<SocketAddr>,
+    Query(params): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+    ws: upgrade::IncomingUpgrade,
+) -> Response {
+    if handle.drain.is_draining() {
+        return (StatusCode::SERVICE_UNAVAILABLE, "Draining").into_response();
+    }
+
+    // ?api_key=<key> connects for one of the tenants, see tenants.rs
+    handle.tenant = match params.get("api_key") {
+        Some(key) => match handle.tenants.check(key) {
+            Some(tenant) => Some(tenant),
+            None => return (StatusCode::UNAUTHORIZED, "Unknown API key").into_response(),
+        },
+        None => None,
+    };
+    // ?resume=<token> carries on from before a restart or drain, in the
+    // main world (see resume.rs). Bad or expired tokens are a fresh join.
+    let session = params
+        .get("resume")
+        .filter(|_| handle.tenant.is_none())
+        .and_then(|t| handle.resume.open(t));
+    // Connecting with ?name=<name> loads and saves that player's record
+    let mut login = Login {
+        name: match &session {
+            Some(session) => session.name.clone(),
+            None => params
+                .get("name")
+                .filter(|n| !n.is_empty() && n.len() <= 32)
+                .cloned(),
+        },
+        display: None,
+        credentials: None,
+        ticket: params.get("ticket").cloned(),
+    };
+    // Unless an authenticator says who the player is, from ?token= or a
+    // bearer token, and ?platform=. Checked after the upgrade.
+    if handle.auth.is_some() {
+        let bearer = headers
+            .get(AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "));
+        let token = params.get("token").map(String::as_str).or(bearer);
+        if token.is_none() && !handle.guests {
+            return (StatusCode::UNAUTHORIZED, "Token required").into_response();
+        }
+        // Without one, a guest
+        login = Login {
+            name: None,
+            display: None,
+            credentials: token.map(|token| Credentials {
+                token: token.to_string(),
+                platform: params.get("platform").cloned().filter(|p| !p.is_empty()),
+            }),
+            ticket: login.ticket,
+        };
+    }
+    // ?room=<name> joins a forked room instead of the main world
+    let room = params.get("room").cloned().filter(|_| session.is_none());
+    if room.as_deref().is_some_and(|r| !command::valid_room(r)) {
+        return (StatusCode::BAD_REQUEST, "Invalid room").into_response();
+    }
+    let room = match &handle.tenant {
+        Some(tenant) => Some(tenants::scoped(
+            tenant,
+            room.as_deref().unwrap_or(tenants::LOBBY),
+        )),
+        None => room,
+    };
+    // Tenants' players go by a name in the tenant's namespace
+    if let (Some(tenant), Some(name)) = (&handle.tenant, &login.name) {
+        login.display = Some(name.clone());
+        login.name = Some(tenants::scoped(tenant, name));
+    }
+    // Binary unless the client offers the teleboxel.json subprotocol first
+    let offered = headers
+        .get(SEC_WEBSOCKET_PROTOCOL)
+        .and_then(|v| v.to_str().ok())
+        .and_then(Encoding::negotiate);
+    // ?key=<base64url> asks for an encrypted session, ?mac_key= for signed
+    // client messages, see secure.rs
+    let keys = match secure::negotiate(
+        handle.encryption,
+        handle.message_auth,
+        handle.server_key.as_deref(),
+        params.get("key").map(String::as_str),
+        params.get("mac_key").map(String::as_str),
+    ) {
+        Ok(keys) => keys,
+        Err(e) => return (StatusCode::BAD_REQUEST, format!("Refused: {e}")).into_response(),
+    };
+
+    // Joins wait their turn under a connect storm
+    let Some(admitted) = handle.admission.admit().await else {
+        let retry = handle.admission.retry_after().to_string();
+        let busy = (
+            StatusCode::SERVICE_UNAVAILABLE,
+            [(RETRY_AFTER, retry)],
+            "Busy",
+        );
+        return busy.into_response();
+    };
+
+    let (mut response, fut) = ws.upgrade().unwrap();
+    if let Some(encoding) = offered {
+        response.headers_mut().insert(
+            SEC_WEBSOCKET_PROTOCOL,
+            HeaderValue::from_static(encoding.subprotocol()),
+        );
+    }
+    let encoding = offered.unwrap_or(Encoding::Binary);
+    // ?coords=1 asks for the world's coordinates, binary frames only
+    handle.wire_coords =
+        encoding == Encoding::Binary && params.get("coords").is_some_and(|c| c == "1");
+    let wire = Wire { encoding, keys };
+
+    let context = Context::new("connection", room.as_deref().unwrap_or("main"));
+    tokio::task::spawn(crash::scope(context, async move {
+        let peer = Peer { remote, admitted };
+        let client = handle_client(handle, fut, peer, login, room, session, wire);
+        if let Err(e) = client.await {
+            eprintln!("Error handling client: {}", e);
+        }
+    }));
+
+    response.into_response()
+}
+
+async fn handle_client(
+    mut handle: WorldHandle,
+    fut: upgrade::UpgradeFut,
+    peer: Peer,
+    login: Login,
+    mut room: Option<String>,
+    mut session: Option<Session>,
+    wire: Wire,
+) -> Result<(), WebSocketError> {
+    let Peer { remote, admitted } = peer;
+    let Wire { encoding, keys } = wire;
+    let Login {
+        mut name,
+        mut display,
+        credentials,
+        ticket,
+    } = login;
+    if let Some(credentials) = credentials {
+        let identity = match check_login(&handle, &credentials).await {
+            Ok(identity) => identity,
+            Err(e) => {
+                // Try again later, unless the credentials are bad
+                let code = match e {
+                    AuthError::Rejected(_) => 1008,
+                    AuthError::Unavailable(_) | AuthError::Timeout => 1013,
+                };
+                let mut reason = format!("Unauthorized: {e}");
+                reason.truncate(reason.floor_char_boundary(120));
+                let mut ws = fut.await?;
+                ws.write_frame(Frame::close(code, reason.as_bytes()))
+                    .await?;
+                return Ok(());
+            }
+        };
+        // Someone else's session is a fresh join
+        if session
+            .as_ref()
+            .is_some_and(|s| s.name.as_ref() != Some(&identity.id))
+        {
+            session = None;
+        }
+        name = Some(identity.id);
+        display = Some(identity.display_name);
+    }
+    if let Some(room) = &room {
+        let Some(tx) = open_room(&handle, room).await else {
+            let mut ws = fut.await?;
+            ws.write_frame(Frame::close(1008, b"Unknown room")).await?;
+            return Ok(());
+        };
+        handle.tx = tx;
+    }
+
+    let mut record = match (&handle.storage, &name) {
+        (Some(storage), Some(name)) => Some(
+            storage
+                .load_player(name)
+                .await
+                .map_err(IoError::other)?
+                .unwrap_or_else(|| PlayerRecord::new(name.clone())),
+        ),
+        _ => None,
+    };
+
+    if let Some(reason) = record.as_ref().and_then(|r| r.ban.as_ref()) {
+        if let Some(audit) = &handle.audit {
+            audit.record(AuditEvent::BannedJoin {
+                name: name.clone().unwrap_or_default(),
+                reason: reason.clone(),
+                remote: Some(remote.to_string()),
+            });
+        }
+        let mut ws = fut.await?;
+        let reason = format!("Banned: {reason}");
+        ws.write_frame(Frame::close(1008, reason.as_bytes()))
+            .await?;
+        return Ok(());
+    }
+
+    // Block list, handed to each world joined: rooms get no record
+    let mut blocked = record
+        .as_ref()
+        .map(|r| Blocked::from_properties(&r.properties))
+        .unwrap_or_default();
+
+    let frames = Arc::new(Recorder::new(handle.frame_history));
+    let connect = |reply| WorldMsg::Connect {
+        name: name.clone(),
+        // Rooms are throwaway, players keep their main world record
+        record: record.clone().filter(|_| room.is_none()),
+        blocked: blocked.clone(),
+        session,
+        frames: frames.clone(),
+        ticket,
+        coords: handle.wire_coords,
+        reply,
+    };
+    let PlayerHandshake {
+        mut id,
+        mut rx,
+        mut traffic,
+        mut leave,
+        mut mode,
+        mut coords,
+    } = match join_world(&handle.tx, connect).await {
+        Ok(player) => player,
+        Err(e) => {
+            eprintln!("Join: {e}");
+            let mut ws = fut.await?;
+            ws.write_frame(Frame::close(e.close_code(), e.to_string().as_bytes()))
+                .await?;
+            return Ok(());
+        }
+    };
+    // Leaves the world however this function ends
+    let mut joined = Joined::new(&handle.tx, id);
+    // In the world, the next join can go
+    drop(admitted);
+    crash::update(|c| c.player_id = Some(id));
+
+    let mut inner = fut.await?;
+    inner.set_auto_close(true);
+    inner.set_auto_pong(true);
+    inner.set_writev(true);
+    let mut ws = SecureSocket::new(FragmentCollector::new(inner), handle.traffic.clone());
+
+    // Offline again once dropped, when the connection ends
+    let mut online = name.as_deref().map(|name| {
+        let privacy = record
+            .as_ref()
+            .map(|r| Privacy::from_properties(&r.properties))
+            .unwrap_or_default();
+        handle
+            .presence
+            .join(name, room.as_deref().unwrap_or("main"), privacy)
+    });
+
+    // Follows the player between rooms
+    let mut flood = FloodGuard::new(Instant::now());
+    let mut voice_cap = Bitrate::new(handle.voice.kbps);
+
+    // Ends (and is exported) when this function returns
+    let mut connection = handle.telemetry.as_ref().map(|telemetry| {
+        let mut span = telemetry.span("connection", None);
+        span.set("player.id", id);
+        span.set("room", room.as_deref().unwrap_or("main"));
+        if let Some(name) = &name {
+            span.set("player.name", name.as_str());
+        }
+        span.set("encoding", encoding.subprotocol());
+        span
+    });
+
+    // The server's key goes in the clear, everything after is protected
+    if let Some((protection, offer)) = keys {
+        let message = match encoding {
+            Encoding::Binary => offer.encode(),
+            Encoding::Json => JsonMessage::Key {
+                key: offer.key(),
+                signature: offer.signature(),
+            }
+            .to_json(),
+        };
+        ws.write_frame(Frame::text(Payload::from(message.as_bytes())))
+            .await?;
+        traffic.record(Dir::Out, "handshake", message.len());
+        ws.protect(protection);
+    }
+
+    let handshake = match encoding {
+        Encoding::Binary => id.to_string(),
+        Encoding::Json => JsonMessage::Hello { id }.to_json(),
+    };
+    ws.write_frame(Frame::text(Payload::from(handshake.as_bytes())))
+        .await?;
+    traffic.record(Dir::Out, "handshake", handshake.len());
+
+    // Block ids and properties, before any chunk uses them
+    let mut registry = ServerFrame::new(0);
+    registry.block_registry(&handle.blocks);
+    let features = handle.features.get(room.as_deref());
+    registry.features(features.chat, features.build, features.pvp);
+    // Before any of the world's positions, see coords.rs
+    if handle.wire_coords {
+        registry.coords(coords);
+    }
+    let mut sync = ClockSync::default();
+    // The client's last `Origin`, its positions are from there, see origin.rs
+    let mut client_origin = None;
+    write_time_sync(&mut registry, &handle, &sync, 0);
+    for (kind, bytes) in registry.sizes() {
+        traffic.record(Dir::Out, kind, bytes);
+    }
+    write_server_frame(&mut ws, encoding, &registry.finish()).await?;
+
+    'connection: loop {
+        select! {
+            frame = ws.read_frame() => {
+                let frame = match frame {
+                    Ok(f) => f,
+                    Err(e) => {
+                        eprintln!("ws read_frame error: {e}");
+                        if let Some(span) = &mut connection {
+                            span.error(e.to_string());
+                        }
+                        break;
+                    }
+                };
+
+                match frame.opcode {
+                    OpCode::Close => break,
+                    OpCode::Text => {
+                        let text = str::from_utf8(&frame.payload).unwrap_or("");
+                        // Inputs a line each go to the world as one message,
+                        // checked like alone and replied to in order
+                        if let Some(inputs) = command::parse_inputs(text) {
+                            traffic.record(Dir::In, "batch", frame.payload.len());
+                            crash::update(|c| c.last_message = Some("batch"));
+                            let mut msgs = Vec::new();
+                            let mut replies = Vec::new();
+                            for (command, cmd) in inputs {
+                                let cmd = cmd.from_coords(coords);
+                                let cmd = match client_origin {
+                                    Some(origin) => cmd.from_origin(origin),
+                                    None => cmd,
+                                };
+                                let edit = matches!(cmd, Command::SetBlock { .. });
+                                let verdict = match Action::of(&cmd) {
+                                    Some(action) => {
+                                        let verdict = flood.check(handle.flood.rules(room.as_deref()), action, Instant::now());
+                                        audit_flood(&handle, &room, id, &name, remote, action, &verdict);
+                                        match verdict {
+                                            Verdict::Allow => Ok(()),
+                                            Verdict::Kick => {
+                                                ws.write_frame(Frame::close(1008, b"Kicked: flooding")).await?;
+                                                break 'connection;
+                                            }
+                                            verdict => Err(flood_error(action, &verdict)),
+                                        }
+                                    }
+                                    None => Ok(()),
+                                };
+                                let result = verdict.and_then(|()| {
+                                    if mode != RoomMode::World {
+                                        Err(format!("Not simulated in {mode} rooms"))
+                                    } else if edit && handle.guests && handle.auth.is_some() && name.is_none() {
+                                        Err("Guests can't build, Login first".to_string())
+                                    } else if edit && !handle.features.get(room.as_deref()).build {
+                                        Err("Building is off here".to_string())
+                                    } else {
+                                        input_msg(&handle, id, name.as_deref(), room.is_some(), cmd)
+                                    }
+                                });
+                                let result = result.map(|msg| {
+                                    msgs.push(msg);
+                                    String::new()
+                                });
+                                replies.push(reply_text(encoding, command, result));
+                            }
+                            if !msgs.is_empty() && handle.tx.send(WorldMsg::Batch(msgs)).await.is_err() {
+                                break 'connection;
+                            }
+                            for response in replies {
+                                ws.write_frame(Frame::text(Payload::from(response.as_bytes()))).await?;
+                                traffic.record(Dir::Out, "reply", response.len());
+                            }
+                            continue;
+                        }
+                        let Some((command, parsed)) = command::parse(text) else {
+                            traffic.record(Dir::In, "unknown", frame.payload.len());
+                            continue;
+                        };
+                        traffic.record(Dir::In, command, frame.payload.len());
+                        crash::update(|c| c.last_message = Some(command));
+                        // In blocks, then from the origin, see coords.rs
+                        let parsed = parsed.map(|cmd| cmd.from_coords(coords)).map(|cmd| match client_origin {
+                            Some(origin) => cmd.from_origin(origin),
+                            None => cmd,
+                        });
+
+                        let started = Instant::now();
+                        // Floods are refused like bad commands, see `flood.rs`
+                        let parsed = match parsed.as_ref().ok().and_then(Action::of) {
+                            Some(action) => {
+                                let verdict = flood.check(handle.flood.rules(room.as_deref()), action, started);
+                                audit_flood(&handle, &room, id, &name, remote, action, &verdict);
+                                match verdict {
+                                    Verdict::Allow => parsed,
+                                    Verdict::Kick => {
+                                        ws.write_frame(Frame::close(1008, b"Kicked: flooding")).await?;
+                                        break;
+                                    }
+                                    verdict => Err(flood_error(action, &verdict)),
+                                }
+                            }
+                            None => parsed,
+                        };
+                        let result = match parsed {
+                            Ok(cmd @ (Command::JoinRoom { .. } | Command::LeaveRoom)) => {
+                                let to = match cmd {
+                                    Command::JoinRoom { name } => Some(name).filter(|r| r != "main"),
+                                    _ => None,
+                                };
+                                let to = tenant_room(&handle, to).await;
+                                let moved = change_room(&mut handle, id, &mut name, &mut record, &room, to.clone(), None);
+                                match moved.await {
+                                    Some(Ok(player)) => {
+                                        PlayerHandshake { id, rx, traffic, leave, mode, coords } = player;
+                                        joined.moved(&handle.tx, id);
+                                        if let Some(origin) = client_origin
+                                            && handle.tx.send(WorldMsg::Origin { id, origin }).await.is_err()
+                                        {
+                                            break;
+                                        }
+                                        room = to;
+                                        follow_room(&mut online, &name, &room);
+                                        let features = handle.features.get(room.as_deref());
+                                        write_room_frame(&mut ws, encoding, &traffic, &room, id, features, handle.wire_coords.then_some(coords)).await?;
+                                        Ok(String::new())
+                                    }
+                                    Some(Err(e)) => Err(e),
+                                    None => break,
+                                }
+                            }
+                            Ok(
+                                cmd @ (Command::Presence { .. }
+                                | Command::FriendAdd { .. }
+                                | Command::FriendRemove { .. }
+                                | Command::Friends),
+                            ) => match presence_command(&handle, id, online.as_ref(), &mut record, room.is_some(), cmd).await {
+                                Some(result) => result,
+                                None => break,
+                            },
+                            Ok(cmd @ (Command::Block { .. } | Command::Unblock { .. } | Command::Blocked)) => {
+                                match block_command(&handle, id, &name, &mut blocked, &mut record, room.is_some(), cmd).await {
+                                    Some(result) => result,
+                                    None => break,
+                                }
+                            }
+                            // Answered with `TIME_SYNC` rather than a reply
+                            Ok(Command::TimeSync { client_time, rtt }) => {
+                                sync.sample(clock::server_ms(), client_time, rtt);
+                                let mut frame = ServerFrame::new(0);
+                                write_time_sync(&mut frame, &handle, &sync, client_time);
+                                for (kind, bytes) in frame.sizes() {
+                                    traffic.record(Dir::Out, kind, bytes);
+                                }
+                                write_server_frame(&mut ws, encoding, &frame.finish()).await?;
+                                if let Some(estimate) = sync.estimate()
+                                    && handle.tx.send(WorldMsg::Clock { id, estimate }).await.is_err()
+                                {
+                                    break;
+                                }
+                                continue;
+                            }
+                            // Also the ack of a new origin
+                            Ok(Command::Origin { origin }) => {
+                                client_origin = Some(origin);
+                                if handle.tx.send(WorldMsg::Origin { id, origin }).await.is_err() {
+                                    break;
+                                }
+                                Ok(String::new())
+                            }
+                            Ok(Command::Login { .. }) if name.is_some() => Err("Already logged in".to_string()),
+                            Ok(Command::Login { token, platform }) => {
+                                let credentials = Credentials { token, platform };
+                                match log_in(&handle, id, room.is_some(), &blocked, &credentials).await {
+                                    Some(Ok((identity, logged_in, merged))) => {
+                                        name = Some(identity.id);
+                                        display = Some(identity.display_name);
+                                        record = logged_in;
+                                        blocked = merged;
+                                        online = name.as_deref().map(|name| {
+                                            let privacy = record
+                                                .as_ref()
+                                                .map(|r| Privacy::from_properties(&r.properties))
+                                                .unwrap_or_default();
+                                            handle.presence.join(name, room.as_deref().unwrap_or("main"), privacy)
+                                        });
+                                        Ok(name.clone().unwrap_or_default())
+                                    }
+                                    Some(Err(e)) => Err(e),
+                                    None => break,
+                                }
+                            }
+                            Ok(
+                                Command::SetBlock { .. }
+                                | Command::ClaimCreate { .. }
+                                | Command::ClaimTransfer { .. }
+                                | Command::RoomCreate { .. },
+                            ) if handle.guests && handle.auth.is_some() && name.is_none() => {
+                                Err("Guests can't build, Login first".to_string())
+                            }
+                            // Toggled per world, see `features.rs`
+                            Ok(Command::Say { .. }) if !handle.features.get(room.as_deref()).chat => {
+                                Err("Chat is off here".to_string())
+                            }
+                            Ok(Command::SetBlock { .. }) if !handle.features.get(room.as_deref()).build => {
+                                Err("Building is off here".to_string())
+                            }
+                            Ok(
+                                Command::SetInterest { .. }
+                                | Command::SetPosition { .. }
+                                | Command::SetTransform { .. }
+                                | Command::SetBlock { .. }
+                                | Command::MoveEntity { .. }
+                                | Command::Mount { .. }
+                                | Command::Dismount,
+                            )
+                                if mode != RoomMode::World =>
+                            {
+                                Err(format!("Not simulated in {mode} rooms"))
+                            }
+                            Ok(cmd) => match run_command(&handle, id, name.as_deref(), display.as_deref(), room.is_some(), cmd).await {
+                                Some(result) => result,
+                                // World task is dead, break the connection
+                                None => break,
+                            },
+                            Err(err_msg) => Err(err_msg),
+                        };
+
+                        if let Some(telemetry) = &handle.telemetry
+                            && let Some(mut span) = telemetry.slow_span("command", started, connection.as_ref())
+                        {
+                            span.set("command", command);
+                            if let Err(e) = &result {
+                                span.error(e.as_str());
+                            }
+                        }
+
+                        let response = reply_text(encoding, command, result);
+                        ws.write_frame(Frame::text(Payload::from(response.as_bytes()))).await?;
+                        traffic.record(Dir::Out, "reply", response.len());
+                    }
+                    OpCode::Binary => {
+                        traffic.record(Dir::In, "binary", frame.payload.len());
+                        crash::update(|c| c.last_message = Some("binary"));
+                        // Only relay messages and voice come as client
+                        // frames so far, the rest of the protocol is text
+                        // commands. Errors are replied to under the
+                        // submessage's name.
+                        let mut errors = Vec::new();
+                        let mut batch = Vec::new();
+                        let msgs = match protocol::decode_client_frame(&frame.payload) {
+                            Ok((_, msgs)) => msgs,
+                            Err(e) => {
+                                errors.push(("ClientFrame", e.to_string()));
+                                Vec::new()
+                            }
+                        };
+                        for msg in msgs {
+                            match msg {
+                                ClientMsg::RelaySend { to, data } if mode == RoomMode::Relay => {
+                                    let verdict = flood.check(handle.flood.rules(room.as_deref()), Action::Relay, Instant::now());
+                                    audit_flood(&handle, &room, id, &name, remote, Action::Relay, &verdict);
+                                    match verdict {
+                                        Verdict::Allow if data.len() > relay::MAX_DATA_LEN => {
+                                            errors.push(("RelaySend", format!("Data longer than {} bytes", relay::MAX_DATA_LEN)));
+                                        }
+                                        Verdict::Allow => batch.push(WorldMsg::Relay { from: id, to, data }),
+                                        Verdict::Kick => {
+                                            ws.write_frame(Frame::close(1008, b"Kicked: flooding")).await?;
+                                            break 'connection;
+                                        }
+                                        verdict => errors.push(("RelaySend", flood_error(Action::Relay, &verdict))),
+                                    }
+                                }
+                                ClientMsg::RelaySend { .. } => {
+                                    errors.push(("RelaySend", "Only in relay rooms".to_string()));
+                                }
+                                ClientMsg::VoiceSend { .. } if mode != RoomMode::World => {
+                                    errors.push(("VoiceSend", format!("No voice in {mode} rooms")));
+                                }
+                                ClientMsg::VoiceSend { .. } if voice_cap.is_off() => {
+                                    errors.push(("VoiceSend", "Voice is off".to_string()));
+                                }
+                                ClientMsg::VoiceSend { data } if data.len() > voice::MAX_FRAME_LEN => {
+                                    errors.push(("VoiceSend", format!("Frame longer than {} bytes", voice::MAX_FRAME_LEN)));
+                                }
+                                // Over the cap it's dropped, see `voice.rs`
+                                // So are muted players, see `roles.rs`
+                                ClientMsg::VoiceSend { data } => {
+                                    let now = Instant::now();
+                                    let muted = name.as_deref().and_then(|n| handle.mutes.remaining(n, now));
+                                    if muted.is_none() && voice_cap.allow(data.len(), now) {
+                                        batch.push(WorldMsg::Voice { from: id, data });
+                                    }
+                                }
+                            }
+                        }
+                        // One world message for the frame
+                        let msg = match batch.len() {
+                            0 => None,
+                            1 => batch.pop(),
+                            _ => Some(WorldMsg::Batch(batch)),
+                        };
+                        if let Some(msg) = msg
+                            && handle.tx.send(msg).await.is_err()
+                        {
+                            break 'connection;
+                        }
+                        for (command, error) in errors {
+                            let response = reply_text(encoding, command, Err(error));
+                            ws.write_frame(Frame::text(Payload::from(response.as_bytes()))).await?;
+                            traffic.record(Dir::Out, "reply", response.len());
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            Some(bytes) = rx.recv() => {
+                frames.record(&bytes);
+                write_server_frame(&mut ws, encoding, &bytes).await?;
+            }
+            // Also ends the connection if the world task is gone
+            order = leave.recv() => match order {
+                Some(Leave::Kicked(reason)) => {
+                    let reason = format!("Kicked: {reason}");
+                    ws.write_frame(Frame::close(1008, reason.as_bytes())).await?;
+                    break;
+                }
+                Some(Leave::Room { to, portal }) => {
+                    let to = tenant_room(&handle, to).await;
+                    let moved = change_room(&mut handle, id, &mut name, &mut record, &room, to.clone(), portal.as_deref());
+                    match moved.await {
+                        Some(Ok(player)) => {
+                            PlayerHandshake { id, rx, traffic, leave, mode, coords } = player;
+                            joined.moved(&handle.tx, id);
+                            if let Some(origin) = client_origin
+                                && handle.tx.send(WorldMsg::Origin { id, origin }).await.is_err()
+                            {
+                                break;
+                            }
+                            room = to;
+                            follow_room(&mut online, &name, &room);
+                            let features = handle.features.get(room.as_deref());
+                            write_room_frame(&mut ws, encoding, &traffic, &room, id, features, handle.wire_coords.then_some(coords)).await?;
+                        }
+                        // E.g. the room is gone, the player stays
+                        Some(Err(e)) => {
+                            let response = reply_text(encoding, "Portal", Err(e));
+                            ws.write_frame(Frame::text(Payload::from(response.as_bytes()))).await?;
+                            traffic.record(Dir::Out, "reply", response.len());
+                        }
+                        None => break,
+                    }
+                }
+                Some(Leave::Server { address, token }) => {
+                    let mut frame = ServerFrame::new(0);
+                    frame.transfer(&address, &token);
+                    for (kind, bytes) in frame.sizes() {
+                        traffic.record(Dir::Out, kind, bytes);
+                    }
+                    write_server_frame(&mut ws, encoding, &frame.finish()).await?;
+                    ws.write_frame(Frame::close(1000, b"Transferred")).await?;
+                    break;
+                }
+                None => break,
+            },
+        }
+    }
+
+    Ok(())
+}
+
+// Audits the mutes and kicks flood control hands out, see `flood.rs`
+fn audit_flood(
+    handle: &WorldHandle,
+    room: &Option<String>,
+    id: u32,
+    name: &Option<String>,
+    remote: SocketAddr,
+    action: Action,
+    verdict: &Verdict,
+) {
+    let penalty = match verdict {
+        Verdict::Mute(_) => "mute",
+        Verdict::Kick => "kick",
+        _ => return,
+    };
+    if let Some(audit) = &handle.audit {
+        audit.record(AuditEvent::Flood {
+            room: room.clone().unwrap_or_else(|| "main".to_string()),
+            id,
+            name: name.clone(),
+            action: action.to_string(),
+            penalty: penalty.to_string(),
+            remote: Some(remote.to_string()),
+        });
+    }
+}
+
+// Why flood control refused an action, short of a kick
+fn flood_error(action: Action, verdict: &Verdict) -> String {
+    match verdict {
+        Verdict::Mute(left) | Verdict::Muted(left) => {
+            format!("Muted from {action} for {}s", left.as_secs().max(1))
+        }
+        _ => format!("Slow down on {action}"),
+    }
+}
+
+// Text reply to a command, e.g. `SetBlock Ok` or a JSON `reply`
+fn reply_text(encoding: Encoding, command: &str, result: Result<String, String>) -> String {
+    match (encoding, result) {
+        (Encoding::Json, result) => JsonMessage::Reply {
+            command: command.to_string(),
+            ok: result.is_ok(),
+            detail: result.unwrap_or_else(|e| e),
+        }
+        .to_json(),
+        (_, Ok(detail)) if detail.is_empty() => format!("{command} Ok"),
+        (_, Ok(detail)) => format!("{command} Ok {detail}"),
+        (_, Err(err_msg)) => format!("{command} Error: {err_msg}"),
+    }
+}
+
+// `ROOM` once the connection moved to `room` as player `id`, with what's
+// on there
+async fn write_room_frame<S>(
+    ws: &mut SecureSocket<S>,
+    encoding: Encoding,
+    traffic: &PlayerTraffic,
+    room: &Option<String>,
+    id: u32,
+    features: Features,
+    coords: Option<Coords>,
+) -> Result<(), WebSocketError>
+where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+{
+    crash::update(|c| c.player_id = Some(id));
+    let mut frame = ServerFrame::new(0);
+    frame.room(room.as_deref().unwrap_or(""), id);
+    frame.features(features.chat, features.build, features.pvp);
+    if let Some(coords) = coords {
+        frame.coords(coords);
+    }
+    for (kind, bytes) in frame.sizes() {
+        traffic.record(Dir::Out, kind, bytes);
+    }
+    write_server_frame(ws, encoding, &frame.finish()).await
+}
+
+// The world always builds binary frames, JSON clients get them re-encoded
+// `TIME_SYNC` from the estimate so far, see clock.rs
+fn write_time_sync(
+    frame: &mut ServerFrame,
+    handle: &WorldHandle,
+    sync: &ClockSync,
+    client_time: u32,
+) {
+    let tunables = *handle.tunables.borrow();
+    let delay = sync.interp_delay(tunables.tick_hz, tunables.interp_ticks);
+    frame.time_sync(
+        client_time,
+        clock::server_ms(),
+        tunables.tick_hz as u16,
+        delay,
+    );
+}
+
+async fn write_server_frame<S>(
+    ws: &mut SecureSocket<S>,
+    encoding: Encoding,
+    frame: &[u8],
+) -> Result<(), WebSocketError>
+where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+{
+    match encoding {
+        Encoding::Binary => {
+            ws.write_frame(Frame::binary(Payload::Borrowed(frame)))
+                .await
+        }
+        Encoding::Json => {
+            let json = protocol::frame_to_json(frame).map_err(IoError::other)?;
+            ws.write_frame(Frame::text(Payload::from(json.as_bytes())))
+                .await
+        }
+    }
+}
+
+// Runs a text command for player `id`, answering with a detail for the Ok
+// reply or an error. `None` when the world task is gone.
+async fn run_command(
+    handle: &WorldHandle,
+    id: u32,
+    name: Option<&str>,
+    // From the login, shown in chat instead of the name
+    display: Option<&str>,
+    in_room: bool,
+    cmd: Command,
+) -> Option<Result<String, String>> {
+    let msg = match cmd {
+        Command::SetInterest { .. }
+        | Command::SetPosition { .. }
+        | Command::SetTransform { .. }
+        | Command::SetBlock { .. } => match input_msg(handle, id, name, in_room, cmd) {
+            Ok(msg) => msg,
+            Err(e) => return Some(Err(e)),
+        },
+        Command::MoveEntity { entity, position } => {
+            let (reply, rx) = oneshot::channel();
+            let msg = WorldMsg::MoveEntity {
+                id,
+                entity,
+                position,
+                reply,
+            };
+            handle.tx.send(msg).await.ok()?;
+            return Some(rx.await.ok()?.map(|()| String::new()));
+        }
+        Command::Mount { .. } | Command::Dismount => {
+            let entity = match cmd {
+                Command::Mount { entity } => Some(entity),
+                _ => None,
+            };
+            let (reply, rx) = oneshot::channel();
+            handle
+                .tx
+                .send(WorldMsg::Mount { id, entity, reply })
+                .await
+                .ok()?;
+            return Some(rx.await.ok()?.map(|()| String::new()));
+        }
+        Command::ClaimCreate { a, b } => {
+            let result = handle.claims.claim_for(name, a, b);
+            return Some(
+                result
+                    .map(|claim| claim.to_string())
+                    .map_err(|e| e.to_string()),
+            );
+        }
+        Command::ClaimTransfer { claim, owner } => {
+            let result = handle.claims.transfer_for(name, claim, owner);
+            return Some(result.map(|()| String::new()).map_err(|e| e.to_string()));
+        }
+        Command::Say { text } => {
+            let muted = name.and_then(|name| handle.mutes.remaining(name, Instant::now()));
+            if let Some(left) = muted {
+                let minutes = left.as_secs().div_ceil(60);
+                return Some(Err(format!("Muted for {minutes} more minutes")));
+            }
+            WorldMsg::Chat {
+                from: display_name(id, name.and(display).or(name)),
+                text,
+                bridge: None,
+                sender: name.map(String::from),
+            }
+        }
+        Command::RoomCreate { name, mode } => {
+            let name = match &handle.tenant {
+                Some(tenant) => tenants::scoped(tenant, &name),
+                None => name,
+            };
+            if handle.rooms.lock().unwrap().contains_key(&name) {
+                return Some(Err(format!("Room {name} already exists")));
+            }
+
+            let (reply, rx) = oneshot::channel();
+            let fork = WorldMsg::Fork { map: None, reply };
+            handle.tx.send(fork).await.ok()?;
+            let chunks = rx.await.ok()?;
+            let template = Template {
+                mode,
+                ..Template::default()
+            };
+            return Some(create_room(handle, name, chunks, &template));
+        }
+        Command::Input { tick, data } => {
+            let (reply, rx) = oneshot::channel();
+            let msg = WorldMsg::Input {
+                id,
+                tick,
+                data,
+                reply,
+            };
+            handle.tx.send(msg).await.ok()?;
+            return Some(rx.await.ok()?.map(|()| String::new()));
+        }
+        Command::LockstepState { tick, data } => {
+            let (reply, rx) = oneshot::channel();
+            let msg = WorldMsg::LockstepState { tick, data, reply };
+            handle.tx.send(msg).await.ok()?;
+            return Some(rx.await.ok()?.map(|()| String::new()));
+        }
+        Command::VoiceMute { speaker } | Command::VoiceUnmute { speaker } => {
+            let mute = matches!(cmd, Command::VoiceMute { .. });
+            let (reply, rx) = oneshot::channel();
+            let msg = WorldMsg::VoiceMute {
+                id,
+                speaker,
+                mute,
+                reply,
+            };
+            handle.tx.send(msg).await.ok()?;
+            return Some(rx.await.ok()?.map(|()| String::new()));
+        }
+        Command::Kick { .. }
+        | Command::Mute { .. }
+        | Command::Unmute { .. }
+        | Command::SetRole { .. } => return moderation_command(handle, name, cmd).await,
+        Command::Chat { line } => return chat_command(handle, id, name, &line).await,
+        Command::Track { event, fields } => WorldMsg::Track {
+            id,
+            name: event,
+            fields,
+        },
+        Command::Minimap { size, scale } => WorldMsg::Minimap { id, size, scale },
+        // They change the connection, see `change_room`, its presence, see
+        // `presence_command`, or its block list, see `block_command`.
+        // `TimeSync` is answered by the connection itself
+        Command::JoinRoom { .. }
+        | Command::LeaveRoom
+        | Command::Presence { .. }
+        | Command::FriendAdd { .. }
+        | Command::FriendRemove { .. }
+        | Command::Friends
+        | Command::Block { .. }
+        | Command::Unblock { .. }
+        | Command::Blocked
+        | Command::Login { .. }
+        | Command::TimeSync { .. }
+        | Command::Origin { .. } => unreachable!(),
+    };
+
+    handle.tx.send(msg).await.ok()?;
+    Some(Ok(String::new()))
+}
+
+// The world message for an input command, alone or in a batch (see
+// `command::parse_inputs`)
+fn input_msg(
+    handle: &WorldHandle,
+    id: u32,
+    name: Option<&str>,
+    in_room: bool,
+    cmd: Command,
+) -> Result<WorldMsg, String> {
+    match cmd {
+        Command::SetInterest { center, radius } => Ok(WorldMsg::SetInterest { id, center, radius }),
+        Command::SetPosition { position, seq } => Ok(WorldMsg::SetPosition { id, position, seq }),
+        Command::SetTransform {
+            position,
+            rotation,
+            velocity,
+            seq,
+        } => Ok(WorldMsg::SetTransform {
+            id,
+            position,
+            rotation,
+            velocity,
+            seq,
+        }),
+        Command::SetBlock {
+            position,
+            block,
+            seq,
+        } => {
+            if !handle.blocks.contains(block) {
+                return Err(format!("Unknown block {block}"));
+            }
+            // Claims guard the main world, rooms are free for all
+            if !in_room && !handle.claims.can_edit(name, position) {
+                return Err("Block is claimed by someone else".to_string());
+            }
+            Ok(WorldMsg::SetBlock {
+                id,
+                position,
+                block,
+                seq,
+            })
+        }
+        _ => unreachable!(),
+    }
+}
+
+// Runs a chat command line (without the `/`) for player `id`, checking
+// its role first, see `chat_commands.rs`. `None` when the world task is
+// gone.
+async fn chat_command(
+    handle: &WorldHandle,
+    id: u32,
+    name: Option<&str>,
+    line: &str,
+) -> Option<Result<String, String>> {
+    let (command, args) = match handle.chat.parse(line) {
+        Ok(parsed) => parsed,
+        Err(e) => return Some(Err(e)),
+    };
+    let role = match (&handle.storage, name) {
+        (Some(storage), Some(name)) => match storage.load_role(name).await {
+            Ok(role) => role,
+            Err(e) => return Some(Err(e.to_string())),
+        },
+        _ => Role::Player,
+    };
+    if role < command.role {
+        return Some(Err(format!(
+            "/{} needs the {} role",
+            command.name, command.role
+        )));
     }
 
-    handle.tx.send(WorldMsg::Disconnect { id }).await.ok();
+    let call = Call {
+        id,
+        name: name.map(String::from),
+        role,
+        args,
+    };
+    match &command.run {
+        Run::Help => Some(handle.chat.help(role, call.text("command"))),
+        // The moderation commands check the role again, and the target's
+        Run::Command(expand) => match command::parse(&expand(&call)) {
+            Some((
+                _,
+                Ok(
+                    cmd @ (Command::Kick { .. }
+                    | Command::Mute { .. }
+                    | Command::Unmute { .. }
+                    | Command::SetRole { .. }),
+                ),
+            )) => moderation_command(handle, name, cmd).await,
+            Some((_, Err(e))) => Some(Err(e)),
+            _ => Some(Err(format!("/{} can't run from chat", command.name))),
+        },
+        Run::World(_) => {
+            let (reply, rx) = oneshot::channel();
+            let msg = WorldMsg::ChatCommand {
+                command,
+                call,
+                reply,
+            };
+            handle.tx.send(msg).await.ok()?;
+            rx.await.ok()
+        }
+    }
+}
 
-    Ok(())
+// Kicks, mutes and role changes by player `by`, see `roles.rs`. Roles are
+// read from storage on every command, and only reach lower roles. `None`
+// when a world task is gone.
+async fn moderation_command(
+    handle: &WorldHandle,
+    by: Option<&str>,
+    cmd: Command,
+) -> Option<Result<String, String>> {
+    let Some(storage) = &handle.storage else {
+        return Some(Err("Roles need storage".to_string()));
+    };
+    let Some(by) = by else {
+        return Some(Err("connect with ?name= to moderate".to_string()));
+    };
+    let (permission, target) = match &cmd {
+        Command::Kick { name, .. } => (Permission::Kick, name.clone()),
+        Command::Mute { name, .. } | Command::Unmute { name } => (Permission::Mute, name.clone()),
+        Command::SetRole { name, .. } => (Permission::AssignRoles, name.clone()),
+        _ => unreachable!(),
+    };
+    let role = match storage.load_role(by).await {
+        Ok(role) => role,
+        Err(e) => return Some(Err(e.to_string())),
+    };
+    if !role.can(permission) {
+        return Some(Err(format!("Needs the {} role", permission.role())));
+    }
+    if target == by {
+        return Some(Err("Can't moderate yourself".to_string()));
+    }
+    let target_role = match storage.load_role(&target).await {
+        Ok(role) => role,
+        Err(e) => return Some(Err(e.to_string())),
+    };
+    if target_role >= role {
+        return Some(Err(format!("{target} has the {target_role} role")));
+    }
+
+    let (action, detail) = match cmd {
+        Command::Kick { reason, .. } => {
+            let reason = if reason.is_empty() {
+                format!("by {by}")
+            } else {
+                reason
+            };
+            let mut worlds = vec![handle.main.clone()];
+            worlds.extend(handle.rooms.lock().unwrap().values().cloned());
+            let mut kicked = 0;
+            for tx in worlds {
+                let (reply, rx) = oneshot::channel();
+                let msg = WorldMsg::KickNamed {
+                    name: target.clone(),
+                    reason: reason.clone(),
+                    reply,
+                };
+                // Rooms may close meanwhile, the main world can't
+                if tx.send(msg).await.is_ok() {
+                    kicked += rx.await.unwrap_or(0);
+                }
+            }
+            if kicked == 0 {
+                return Some(Err(format!("{target} isn't online")));
+            }
+            ("kick", reason)
+        }
+        Command::Mute { minutes, .. } => {
+            let until = Instant::now() + Duration::from_secs(u64::from(minutes) * 60);
+            handle.mutes.mute(target.clone(), until);
+            ("mute", format!("{minutes} minutes"))
+        }
+        Command::Unmute { .. } => {
+            if !handle.mutes.unmute(&target) {
+                return Some(Err(format!("{target} isn't muted")));
+            }
+            ("unmute", String::new())
+        }
+        Command::SetRole { role, .. } => {
+            if let Err(e) = storage.save_role(&target, role).await {
+                return Some(Err(e.to_string()));
+            }
+            ("role", role.to_string())
+        }
+        _ => unreachable!(),
+    };
+
+    if let Some(audit) = &handle.audit {
+        audit.record(AuditEvent::Moderation {
+            by: Some(by.to_string()),
+            action: action.to_string(),
+            target,
+            detail,
+        });
+    }
+    Some(Ok(String::new()))
+}
+
+// Presence settings and friends of the player behind `online`. Settings
+// persist in its record, see `save_properties`. `None` when the world task
+// is gone.
+async fn presence_command(
+    handle: &WorldHandle,
+    id: u32,
+    online: Option<&Online>,
+    record: &mut Option<PlayerRecord>,
+    in_room: bool,
+    cmd: Command,
+) -> Option<Result<String, String>> {
+    let Some(online) = online else {
+        return Some(Err("connect with ?name= to use presence".to_string()));
+    };
+    let mut privacy = online.privacy();
+    match cmd {
+        Command::Presence { visibility } => privacy.visibility = visibility,
+        Command::FriendAdd { name } => {
+            if privacy.friends.len() >= presence::MAX_FRIENDS {
+                return Some(Err(format!(
+                    "Too many friends (max {})",
+                    presence::MAX_FRIENDS
+                )));
+            }
+            privacy.friends.insert(name);
+        }
+        Command::FriendRemove { name } => {
+            if !privacy.friends.remove(&name) {
+                return Some(Err(format!("{name} isn't a friend")));
+            }
+        }
+        Command::Friends => {
+            let friends = handle.presence.friends(online.name(), &privacy.friends);
+            let friends: Vec<_> = friends
+                .iter()
+                .map(|f| format!("{}@{}", f.name, f.room))
+                .collect();
+            return Some(Ok(friends.join(" ")));
+        }
+        _ => unreachable!(),
+    }
+    online.set_privacy(privacy.clone());
+
+    save_properties(handle, id, record, in_room, privacy.to_properties()).await?;
+    Some(Ok(String::new()))
+}
+
+// Checks credentials with the authenticator, within the timeout. The
+// provider has the last word on roles, saved here.
+async fn check_login(
+    handle: &WorldHandle,
+    credentials: &Credentials,
+) -> Result<Identity, AuthError> {
+    let Some(auth) = &handle.auth else {
+        return Err(AuthError::Rejected("No logins on this server".to_string()));
+    };
+    let checked = tokio::time::timeout(handle.auth_timeout, auth.authenticate(credentials));
+    let mut identity = checked.await.unwrap_or(Err(AuthError::Timeout))?;
+    if let Some(tenant) = &handle.tenant {
+        identity.id = tenants::scoped(tenant, &identity.id);
+    }
+    if let (Some(storage), Some(role)) = (&handle.storage, identity.role)
+        && let Err(e) = storage.save_role(&identity.id, role).await
+    {
+        eprintln!("Saving {}'s role: {e}", identity.id);
+    }
+    Ok(identity)
+}
+
+// `Login` from a guest: checks the credentials and hands the world the
+// player's record, which keeps the guest's position, stats and blocks. The
+// connection gets the identity, the record and the merged block list back.
+// `None` when the world task is gone.
+async fn log_in(
+    handle: &WorldHandle,
+    id: u32,
+    in_room: bool,
+    blocked: &Blocked,
+    credentials: &Credentials,
+) -> Option<Result<(Identity, Option<PlayerRecord>, Blocked), String>> {
+    let identity = match check_login(handle, credentials).await {
+        Ok(identity) => identity,
+        Err(e) => return Some(Err(e.to_string())),
+    };
+    let mut record = match &handle.storage {
+        Some(storage) => match storage.load_player(&identity.id).await {
+            Ok(record) => Some(record.unwrap_or_else(|| PlayerRecord::new(identity.id.clone()))),
+            Err(e) => return Some(Err(e.to_string())),
+        },
+        None => None,
+    };
+    if let Some(reason) = record.as_ref().and_then(|r| r.ban.as_ref()) {
+        return Some(Err(format!("Banned: {reason}")));
+    }
+
+    let mut merged = record
+        .as_ref()
+        .map(|r| Blocked::from_properties(&r.properties))
+        .unwrap_or_default();
+    for name in blocked.iter() {
+        merged.insert(name.to_string());
+    }
+    if let Some(record) = &mut record {
+        record.properties.extend(merged.to_properties());
+    }
+    let msg = WorldMsg::Login {
+        id,
+        name: identity.id.clone(),
+        // Rooms are throwaway, the record waits for the main world
+        record: record.clone().filter(|_| !in_room),
+        blocked: merged.clone(),
+    };
+    handle.tx.send(msg).await.ok()?;
+    Some(Ok((identity, record, merged)))
+}
+
+// Block list commands, see `blocking.rs`. The list is routed by the world
+// the player is in and persists like presence settings. `None` when the
+// world task is gone.
+async fn block_command(
+    handle: &WorldHandle,
+    id: u32,
+    name: &Option<String>,
+    blocked: &mut Blocked,
+    record: &mut Option<PlayerRecord>,
+    in_room: bool,
+    cmd: Command,
+) -> Option<Result<String, String>> {
+    match cmd {
+        Command::Block { name: other } if name.as_ref() == Some(&other) => {
+            return Some(Err("Can't block yourself".to_string()));
+        }
+        Command::Block { name } => {
+            if !blocked.insert(name) {
+                return Some(Err(format!(
+                    "Too many blocked (max {})",
+                    blocking::MAX_BLOCKED
+                )));
+            }
+        }
+        Command::Unblock { name } => {
+            if !blocked.remove(&name) {
+                return Some(Err(format!("{name} isn't blocked")));
+            }
+        }
+        Command::Blocked => return Some(Ok(blocked.iter().collect::<Vec<_>>().join(" "))),
+        _ => unreachable!(),
+    }
+    let msg = WorldMsg::SetBlocked {
+        id,
+        blocked: blocked.clone(),
+    };
+    handle.tx.send(msg).await.ok()?;
+
+    save_properties(handle, id, record, in_room, blocked.to_properties()).await?;
+    Some(Ok(String::new()))
+}
+
+// Keeps `properties` in the player's record: the main world's copy, or
+// straight to storage from a room, where no world holds it. `None` when the
+// world task is gone.
+async fn save_properties(
+    handle: &WorldHandle,
+    id: u32,
+    record: &mut Option<PlayerRecord>,
+    in_room: bool,
+    properties: HashMap<String, String>,
+) -> Option<()> {
+    if let Some(record) = record {
+        record.properties.extend(properties.clone());
+        if in_room && let Some(saves) = &handle.saves {
+            saves.save(vec![record.clone()]);
+        }
+    }
+    if !in_room {
+        let msg = WorldMsg::SetProperties { id, properties };
+        handle.tx.send(msg).await.ok()?;
+    }
+    Some(())
+}
+
+// Follows a room change, or goes offline if a portal left the name behind
+fn follow_room(online: &mut Option<Online>, name: &Option<String>, room: &Option<String>) {
+    match (online.as_ref(), name) {
+        (Some(presence), Some(_)) => presence.moved(room.as_deref().unwrap_or("main")),
+        _ => *online = None,
+    }
+}
+
+// Moves player `id` from its world to room `to` (`None` for the main world)
+// over the same connection, carrying name, position, interest and block
+// list, or what `portal` lets through. `record` is the main world record,
+// kept while in rooms. `None` when a world task is gone.
+async fn change_room(
+    handle: &mut WorldHandle,
+    id: u32,
+    name: &mut Option<String>,
+    record: &mut Option<PlayerRecord>,
+    from: &Option<String>,
+    to: Option<String>,
+    portal: Option<&Portal>,
+) -> Option<Result<PlayerHandshake, String>> {
+    if *from == to {
+        return Some(Err(format!(
+            "Already in {}",
+            to.as_deref().unwrap_or("main")
+        )));
+    }
+    let Some(tx) = handle.world_tx(to.as_deref()) else {
+        return Some(Err(format!("No room {}", to.unwrap_or_default())));
+    };
+    // A place there first, so a full room leaves the player where they are
+    let (reply, reserved) = oneshot::channel();
+    let reserve = WorldMsg::Reserve {
+        name: None,
+        ttl: JOIN_TIMEOUT,
+        reply,
+    };
+    tx.send(reserve).await.ok()?;
+    let ticket = match reserved.await.ok()? {
+        Ok(ticket) => ticket,
+        Err(e) => return Some(Err(e)),
+    };
+
+    let (moving, left) = oneshot::channel();
+    let moving = Some(moving);
+    handle
+        .tx
+        .send(WorldMsg::Disconnect { id, moving })
+        .await
+        .ok()?;
+    let (session, left_record, blocked, frames) = left.await.ok()?;
+    if left_record.is_some() {
+        *record = left_record;
+    }
+    let session = match portal {
+        Some(portal) => portal.carry(session),
+        None => session,
+    };
+    // Left behind, the player goes on anonymous
+    *name = session.name.clone();
+
+    let connect = |reply| WorldMsg::Connect {
+        name: session.name.clone(),
+        record: record.clone().filter(|_| to.is_none() && name.is_some()),
+        blocked,
+        session: Some(session),
+        frames,
+        ticket: Some(ticket),
+        coords: handle.wire_coords,
+        reply,
+    };
+    let player = join_world(&tx, connect).await.ok()?;
+    handle.tx = tx;
+    Some(Ok(player))
+}
+
+// Why a connection didn't get into its world
+#[derive(Debug)]
+enum JoinError {
+    // The world task is gone
+    Gone,
+    // No reply within `JOIN_TIMEOUT`
+    Timeout,
+    // At `max_players` without a ticket, see reservations.rs
+    Full,
+}
+
+impl JoinError {
+    // Try again later for a busy world
+    fn close_code(&self) -> u16 {
+        match self {
+            JoinError::Gone => 1011,
+            JoinError::Timeout | JoinError::Full => 1013,
+        }
+    }
+}
+
+impl std::fmt::Display for JoinError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JoinError::Gone => write!(f, "world closed"),
+            JoinError::Timeout => write!(f, "world busy"),
+            JoinError::Full => write!(f, "room full"),
+        }
+    }
+}
+
+// Sends the `Connect` that `connect` makes around its reply channel and
+// waits for the handshake, `JOIN_TIMEOUT` at most. A world answering later
+// disconnects the player itself.
+async fn join_world(
+    tx: &mpsc::Sender<WorldMsg>,
+    connect: impl FnOnce(oneshot::Sender<Result<PlayerHandshake, JoinError>>) -> WorldMsg,
+) -> Result<PlayerHandshake, JoinError> {
+    let (reply, handshake) = oneshot::channel();
+    let joined = async {
+        tx.send(connect(reply)).await.map_err(|_| JoinError::Gone)?;
+        handshake.await.unwrap_or(Err(JoinError::Gone))
+    };
+    tokio::time::timeout(JOIN_TIMEOUT, joined)
+        .await
+        .map_err(|_| JoinError::Timeout)?
+}
+
+// Disconnects the player from its world when dropped, however the
+// connection ends: closed, an error, an early return or a panic
+struct Joined {
+    tx: mpsc::Sender<WorldMsg>,
+    id: u32,
+}
+
+impl Joined {
+    fn new(tx: &mpsc::Sender<WorldMsg>, id: u32) -> Self {
+        Self { tx: tx.clone(), id }
+    }
+
+    // After a room change, the old world already let go
+    fn moved(&mut self, tx: &mpsc::Sender<WorldMsg>, id: u32) {
+        *self = Self::new(tx, id);
+    }
+}
+
+impl Drop for Joined {
+    fn drop(&mut self) {
+        let leave = WorldMsg::Disconnect {
+            id: self.id,
+            moving: None,
+        };
+        // Waits for room rather than lose it, unless the runtime is gone
+        if let Err(mpsc::error::TrySendError::Full(leave)) = self.tx.try_send(leave)
+            && let Ok(runtime) = tokio::runtime::Handle::try_current()
+        {
+            let tx = self.tx.clone();
+            runtime.spawn(async move { tx.send(leave).await.ok() });
+        }
+    }
+}
+
+// Who connected: the player name from ?name= or, once checked, the
+// identity from the credentials, with its name for chat
+struct Login {
+    name: Option<String>,
+    display: Option<String>,
+    credentials: Option<Credentials>,
+    // From ?ticket=, see reservations.rs
+    ticket: Option<String>,
+}
+
+// Where the connection came from, and its place in the connect queue
+// until the world has it
+struct Peer {
+    remote: SocketAddr,
+    admitted: Admitted,
+}
+
+// How messages go over the connection: the encoding and, for encrypted or
+// signed sessions, the keys and the server's signed key for the client
+struct Wire {
+    encoding: Encoding,
+    keys: Option<(Protection, Offer)>,
+}
+
+// Player name in chat, `#<id>` for anonymous players
+fn display_name(id: u32, name: Option<&str>) -> String {
+    name.map_or_else(|| format!("#{id}"), String::from)
+}
+
+// The room's world, made by forking the main world for tenants' rooms
+// joined for the first time (see tenants.rs). `None` if there's no such
+// room.
+async fn open_room(handle: &WorldHandle, name: &str) -> Option<mpsc::Sender<WorldMsg>> {
+    if let Some(tx) = handle.rooms.lock().unwrap().get(name).cloned() {
+        return Some(tx);
+    }
+    handle.tenant.as_ref()?;
+    let (reply, rx) = oneshot::channel();
+    let fork = WorldMsg::Fork { map: None, reply };
+    handle.main.send(fork).await.ok()?;
+    let chunks = rx.await.ok()?;
+    // Someone else may have made it meanwhile
+    create_room(handle, name.to_string(), chunks, &Template::default()).ok();
+    handle.rooms.lock().unwrap().get(name).cloned()
+}
+
+// Where a move to `to` takes a tenant's player: a room of theirs, their
+// lobby rather than the main world. Opened if it isn't yet.
+async fn tenant_room(handle: &WorldHandle, to: Option<String>) -> Option<String> {
+    let Some(tenant) = &handle.tenant else {
+        return to;
+    };
+    let to = match to {
+        Some(room) if tenants::of_world(&room) == Some(tenant) => room,
+        room => tenants::scoped(tenant, room.as_deref().unwrap_or(tenants::LOBBY)),
+    };
+    open_room(handle, &to).await;
+    Some(to)
+}
+
+// Starts a world task for a forked room made with `template`. Rooms aren't
+// saved: no storage, and forked chunk caches never write.
+fn create_room(
+    handle: &WorldHandle,
+    name: String,
+    chunks: ChunkCache,
+    template: &Template,
+) -> Result<String, String> {
+    let mut rooms = handle.rooms.lock().unwrap();
+    if rooms.len() >= MAX_ROOMS {
+        return Err(format!("Too many rooms (max {MAX_ROOMS})"));
+    }
+    if rooms.contains_key(&name) {
+        return Err(format!("Room {name} already exists"));
+    }
+
+    let (tx, rx) = mpsc::channel::<WorldMsg>(128);
+    let room = Some((name.clone(), handle.rooms.clone()));
+    let mut world = World::new(rx, handle, chunks, room);
+    world.mode = template.mode;
+    if template.mode == RoomMode::Lockstep {
+        world.lockstep = Some(Lockstep::default());
+    }
+    if template.mode == RoomMode::Rollback {
+        let delay = template.input_delay.unwrap_or(rollback::DEFAULT_DELAY);
+        world.rollback = Some(Rollback::new(delay));
+    }
+    world.tick_hz = template.tick_hz;
+    world.max_players = template.max_players;
+    if let Some(spatial) = template.spatial {
+        world.entities = Entities::new(spatial);
+    }
+    let quota = template.quota.over(&world.meter.quota);
+    world.chunks.set_limit(quota.max_chunks);
+    world.meter = Meter::new(quota);
+    if template.pool > 0 && template.mode == RoomMode::World {
+        world.warm();
+    }
+    if template.features != Toggles::default() {
+        handle.features.toggle(Some(&name), &template.features);
+    }
+    let context = Context::new("world", world.name());
+    tokio::spawn(crash::scope(context, world.run(Duration::from_secs(60))));
+    rooms.insert(name.clone(), tx);
+    handle.journal.record(&name, 0, JournalEvent::RoomCreated);
+    Ok(String::new())
+}
+
+impl WorldHandle {
+    // `<template>-<n>`, the first not taken
+    fn free_room_name(&self, template: &str) -> String {
+        let rooms = self.rooms.lock().unwrap();
+        (1..)
+            .map(|n| format!("{template}-{n}"))
+            .find(|name| !rooms.contains_key(name))
+            .unwrap()
+    }
+
+    // Opens room `name` made with `template`, replies its name
+    async fn open_template(&self, name: String, template: &Template) -> Result<String, String> {
+        let (reply, rx) = oneshot::channel();
+        let fork = WorldMsg::Fork {
+            map: template.map.clone(),
+            reply,
+        };
+        let gone = || "The main world is gone".to_string();
+        self.main.send(fork).await.map_err(|_| gone())?;
+        let chunks = rx.await.map_err(|_| gone())?;
+        create_room(self, name.clone(), chunks, template).map(|_| name)
+    }
+
+    // An idle pooled room of `template`'s, skipping ones closed or joined
+    // by name since they were pooled
+    async fn take_pooled(&self, template: &str) -> Option<String> {
+        while let Some(room) = self.pools.take(template) {
+            let Some(tx) = self.world_tx(Some(&room)) else {
+                continue;
+            };
+            let (reply, rx) = oneshot::channel();
+            if tx.send(WorldMsg::State { reply }).await.is_err() {
+                continue;
+            }
+            if rx.await.is_ok_and(|state| state.players.is_empty()) {
+                return Some(room);
+            }
+        }
+        None
+    }
+
+    // The main world's sender, or the room's if it's open
+    fn world_tx(&self, room: Option<&str>) -> Option<mpsc::Sender<WorldMsg>> {
+        match room {
+            None | Some("main") => Some(self.main.clone()),
+            Some(room) => self.rooms.lock().unwrap().get(room).cloned(),
+        }
+    }
+
+    // Starts draining and tells every world. Players told, `None` if
+    // already draining.
+    async fn drain_worlds(&self, seconds: u16, address: String, resume: bool) -> Option<usize> {
+        if !self.drain.start(Duration::from_secs(seconds.into())) {
+            return None;
+        }
+        let mut worlds = vec![self.tx.clone()];
+        worlds.extend(self.rooms.lock().unwrap().values().cloned());
+        for tx in worlds {
+            let address = address.clone();
+            let msg = WorldMsg::Drain {
+                seconds,
+                address,
+                resume,
+            };
+            tx.send(msg).await.ok();
+        }
+        Some(self.traffic.players().len())
+    }
+}
+
+impl WorldControl for WorldHandle {
+    fn kick(&self, room: Option<&str>, id: u32, reason: String) -> BoxFuture<'_, bool> {
+        let tx = self.world_tx(room);
+        Box::pin(async move {
+            let Some(tx) = tx else { return false };
+            let (reply, rx) = oneshot::channel();
+            if tx.send(WorldMsg::Kick { id, reason, reply }).await.is_err() {
+                return false;
+            }
+            rx.await.unwrap_or(false)
+        })
+    }
+
+    fn state(&self, room: Option<&str>) -> BoxFuture<'_, Option<WorldState>> {
+        let tx = self.world_tx(room);
+        Box::pin(async move {
+            let (reply, rx) = oneshot::channel();
+            tx?.send(WorldMsg::State { reply }).await.ok()?;
+            rx.await.ok()
+        })
+    }
+
+    fn say(&self, room: Option<&str>, text: String) -> BoxFuture<'_, bool> {
+        let tx = self.world_tx(room);
+        Box::pin(async move {
+            let Some(tx) = tx else { return false };
+            let msg = WorldMsg::Chat {
+                from: "server".to_string(),
+                text,
+                bridge: None,
+                sender: None,
+            };
+            tx.send(msg).await.is_ok()
+        })
+    }
+
+    fn save(&self) -> BoxFuture<'_, Option<(usize, usize)>> {
+        Box::pin(async move {
+            let (reply, rx) = oneshot::channel();
+            self.tx.send(WorldMsg::Save { reply }).await.ok()?;
+            rx.await.ok()
+        })
+    }
+
+    fn drain(&self, seconds: u16, address: String) -> BoxFuture<'_, Option<usize>> {
+        // Tokens are only worth something with somewhere to go
+        let resume = !address.is_empty();
+        Box::pin(self.drain_worlds(seconds, address, resume))
+    }
+
+    fn restart(&self, seconds: u16) -> BoxFuture<'_, Result<usize, RestartError>> {
+        Box::pin(async move {
+            if self.drain.is_draining() {
+                return Err(RestartError::Draining);
+            }
+            // Queued before the successor is up, it loads chunks lazily
+            self.save().await;
+            let pid = self
+                .handover
+                .start()
+                .await
+                .map_err(|e| RestartError::Successor(e.to_string()))?;
+            // Players come back to this address
+            let players = self.drain_worlds(seconds, String::new(), true).await;
+            let players = players.ok_or(RestartError::Draining)?;
+            println!("Successor {pid} is serving, draining {players} players");
+            Ok(players)
+        })
+    }
+
+    fn history(
+        &self,
+        room: Option<&str>,
+        query: HistoryQuery,
+    ) -> BoxFuture<'_, Option<Result<HistoryReply, HistoryError>>> {
+        let tx = self.world_tx(room);
+        Box::pin(async move {
+            let (reply, rx) = oneshot::channel();
+            tx?.send(WorldMsg::History { query, reply }).await.ok()?;
+            rx.await.ok()
+        })
+    }
+
+    fn frames(
+        &self,
+        room: Option<&str>,
+        id: u32,
+        secs: Duration,
+    ) -> BoxFuture<'_, Option<Vec<u8>>> {
+        let tx = self.world_tx(room);
+        Box::pin(async move {
+            let (reply, rx) = oneshot::channel();
+            tx?.send(WorldMsg::Frames { id, secs, reply }).await.ok()?;
+            rx.await.ok()?
+        })
+    }
+
+    fn features(&self, room: Option<&str>, toggles: Toggles) -> BoxFuture<'_, Option<Features>> {
+        let tx = self.world_tx(room);
+        Box::pin(async move {
+            let (reply, rx) = oneshot::channel();
+            tx?.send(WorldMsg::Features { toggles, reply }).await.ok()?;
+            rx.await.ok()
+        })
+    }
+
+    fn simulation(
+        &self,
+        room: Option<&str>,
+        change: SimulationChange,
+    ) -> BoxFuture<'_, Option<SimulationState>> {
+        let tx = self.world_tx(room);
+        Box::pin(async move {
+            let (reply, rx) = oneshot::channel();
+            tx?.send(WorldMsg::Simulation { change, reply })
+                .await
+                .ok()?;
+            rx.await.ok()
+        })
+    }
+
+    fn effect(&self, room: Option<&str>, effect: Effect) -> BoxFuture<'_, Option<usize>> {
+        let tx = self.world_tx(room);
+        Box::pin(async move {
+            let (reply, rx) = oneshot::channel();
+            tx?.send(WorldMsg::Effect { effect, reply }).await.ok()?;
+            rx.await.ok()
+        })
+    }
+
+    fn path(
+        &self,
+        room: Option<&str>,
+        from: (i32, i32, i32),
+        to: (i32, i32, i32),
+    ) -> BoxFuture<'_, Option<Result<Route, PathError>>> {
+        let tx = self.world_tx(room);
+        Box::pin(async move {
+            let (reply, rx) = oneshot::channel();
+            tx?.send(WorldMsg::FindPath { from, to, reply })
+                .await
+                .ok()?;
+            rx.await.ok()
+        })
+    }
+
+    fn entities(
+        &self,
+        room: Option<&str>,
+        op: EntityOp,
+    ) -> BoxFuture<'_, Option<Result<EntityReply, EntityError>>> {
+        let tx = self.world_tx(room);
+        Box::pin(async move {
+            let (reply, rx) = oneshot::channel();
+            tx?.send(WorldMsg::Entities { op, reply }).await.ok()?;
+            rx.await.ok()
+        })
+    }
+
+    fn create_room(
+        &self,
+        template: &str,
+        room: Option<String>,
+    ) -> BoxFuture<'_, Option<Result<String, String>>> {
+        let found = self.templates.get(template).cloned();
+        let template = template.to_string();
+        Box::pin(async move {
+            let found = found?;
+            if room.is_none() && found.pool_max() > 0 {
+                self.pools.claimed(&template, Instant::now());
+                if let Some(room) = self.take_pooled(&template).await {
+                    return Some(Ok(room));
+                }
+            }
+            let room = room.unwrap_or_else(|| self.free_room_name(&template));
+            Some(self.open_template(room, &found).await)
+        })
+    }
+
+    fn reserve(
+        &self,
+        room: Option<&str>,
+        name: Option<String>,
+        ttl: Duration,
+    ) -> BoxFuture<'_, Option<Result<String, String>>> {
+        let tx = self.world_tx(room);
+        // A tenant's players go by scoped names, like its rooms
+        let tenant = room.and_then(tenants::of_world);
+        let name = match tenant {
+            Some(tenant) => name.map(|name| tenants::scoped(tenant, &name)),
+            None => name,
+        };
+        Box::pin(async move {
+            let (reply, rx) = oneshot::channel();
+            let reserve = WorldMsg::Reserve { name, ttl, reply };
+            tx?.send(reserve).await.ok()?;
+            rx.await.ok()
+        })
+    }
+
+    fn layer(&self, room: Option<&str>, id: u32, layer: u32) -> BoxFuture<'_, Option<bool>> {
+        let tx = self.world_tx(room);
+        Box::pin(async move {
+            let (reply, rx) = oneshot::channel();
+            tx?.send(WorldMsg::Layer { id, layer, reply }).await.ok()?;
+            rx.await.ok()
+        })
+    }
+
+    fn minimap(
+        &self,
+        room: Option<&str>,
+        center: (i32, i32),
+        size: u8,
+        scale: u8,
+    ) -> BoxFuture<'_, Option<Minimap>> {
+        let tx = self.world_tx(room);
+        Box::pin(async move {
+            let (reply, rx) = oneshot::channel();
+            let msg = WorldMsg::RenderMinimap {
+                center,
+                size,
+                scale,
+                reply,
+            };
+            tx?.send(msg).await.ok()?;
+            rx.await.ok()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        alloc::{GlobalAlloc, Layout, System},
+        cell::Cell,
+    };
+    #[cfg(feature = "sqlite")]
+    use teleboxel::auth::AuthFuture;
+    use teleboxel::protocol::ServerMsg;
+
+    // A world with the default config and no generator, not running yet
+    fn world(
+        auth: Option<Arc<dyn Authenticator>>,
+        storage: Option<Arc<dyn Storage>>,
+    ) -> (WorldHandle, World) {
+        let config = Config::from_vars(&Vars::default());
+        let cache_config = CacheConfig {
+            saves: None,
+            memory_budget: 1 << 20,
+            workers: 1,
+        };
+        let (tx, rx) = mpsc::channel::<WorldMsg>(128);
+        let (_, tunables) = watch::channel(config.tunables);
+        let handle = WorldHandle {
+            main: tx.clone(),
+            tx,
+            saves: storage.clone().map(SaveQueue::new),
+            storage,
+            claims: Claims::load(None).unwrap(),
+            blocks: Arc::default(),
+            rooms: Rooms::default(),
+            chunk_format: config.chunk_format,
+            spatial: config.spatial,
+            traffic: Arc::default(),
+            telemetry: None,
+            audit: None,
+            bridges: None,
+            portals: None,
+            triggers: None,
+            flood: Arc::default(),
+            afk: Arc::default(),
+            relevance: Arc::default(),
+            coords: Arc::default(),
+            achievements: Arc::default(),
+            features: Arc::default(),
+            quotas: Arc::default(),
+            tenants: Arc::default(),
+            usage: None,
+            templates: Arc::default(),
+            pools: Arc::default(),
+            tenant: None,
+            wire_coords: false,
+            history: config.history,
+            input: config.input,
+            voice: config.voice,
+            profile: None,
+            frame_history: config.frame_history,
+            journal: Arc::default(),
+            tunables,
+            drain: Arc::default(),
+            admission: Arc::new(Admission::new(config.admission)),
+            resume: Arc::new(ResumeKey::new(None)),
+            handover: Arc::new(Handover::new(Vec::new(), Vec::new())),
+            presence: Arc::default(),
+            mutes: Arc::default(),
+            chat: Arc::default(),
+            auth,
+            auth_timeout: config.auth_timeout,
+            guests: true,
+            encryption: config.encryption,
+            message_auth: config.message_auth,
+            server_key: None,
+        };
+        let world = World::new(rx, &handle, ChunkCache::new(cache_config, None), None);
+        (handle, world)
+    }
+
+    fn start(
+        auth: Option<Arc<dyn Authenticator>>,
+        storage: Option<Arc<dyn Storage>>,
+    ) -> WorldHandle {
+        let (handle, world) = world(auth, storage);
+        tokio::spawn(world.run(Duration::from_secs(60)));
+        handle
+    }
+
+    // The `Connect` of a new connection, for `join_world`
+    fn joining(
+        handle: &WorldHandle,
+        name: Option<&str>,
+    ) -> impl FnOnce(oneshot::Sender<Result<PlayerHandshake, JoinError>>) -> WorldMsg {
+        let name = name.map(String::from);
+        let frames = Arc::new(Recorder::new(handle.frame_history));
+        |reply| WorldMsg::Connect {
+            name,
+            record: None,
+            blocked: Blocked::default(),
+            session: None,
+            frames,
+            ticket: None,
+            coords: false,
+            reply,
+        }
+    }
+
+    async fn connect(handle: &WorldHandle, name: Option<&str>) -> PlayerHandshake {
+        join_world(&handle.tx, joining(handle, name)).await.unwrap()
+    }
+
+    async fn players(handle: &WorldHandle, room: Option<&str>) -> Vec<PlayerState> {
+        handle.state(room).await.unwrap().players
+    }
+
+    // What the world sent the player within `wait`
+    async fn received(player: &mut PlayerHandshake, wait: Duration) -> Vec<ServerMsg> {
+        let until = tokio::time::Instant::now() + wait;
+        let mut msgs = Vec::new();
+        while let Ok(Some(frame)) = tokio::time::timeout_at(until, player.rx.recv()).await {
+            msgs.extend(protocol::decode_server_frame(&frame).unwrap().1);
+        }
+        msgs
+    }
+
+    // Lets in one token as alice
+    #[cfg(feature = "sqlite")]
+    struct OneToken;
+
+    #[cfg(feature = "sqlite")]
+    impl Authenticator for OneToken {
+        fn authenticate<'a>(&'a self, credentials: &'a Credentials) -> AuthFuture<'a> {
+            Box::pin(async move {
+                if credentials.token != "alice-token" {
+                    return Err(AuthError::Rejected("Bad token".to_string()));
+                }
+                Ok(Identity {
+                    id: "alice".to_string(),
+                    display_name: "Alice".to_string(),
+                    role: None,
+                })
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn moves_between_rooms_over_one_connection() {
+        let mut handle = start(None, None);
+        handle
+            .open_template("arena".to_string(), &Template::default())
+            .await
+            .unwrap();
+        let player = connect(&handle, Some("alice")).await;
+        let position = (3, 4, 5);
+        let moved = WorldMsg::SetPosition {
+            id: player.id,
+            position,
+            seq: None,
+        };
+        handle.tx.send(moved).await.unwrap();
+
+        let (mut name, mut record) = (Some("alice".to_string()), None);
+        let main = None;
+        let arena = Some("arena".to_string());
+        let joined = change_room(
+            &mut handle,
+            player.id,
+            &mut name,
+            &mut record,
+            &main,
+            arena.clone(),
+            None,
+        );
+        let there = joined.await.unwrap().unwrap();
+        assert!(players(&handle, None).await.is_empty());
+        let state = players(&handle, Some("arena")).await;
+        assert_eq!(state.len(), 1);
+        assert_eq!(state[0].id, there.id);
+        assert_eq!(state[0].name.as_deref(), Some("alice"));
+        assert_eq!(state[0].position, position);
+        assert!(
+            handle
+                .tx
+                .same_channel(&handle.world_tx(Some("arena")).unwrap())
+        );
+
+        let stay = change_room(
+            &mut handle,
+            there.id,
+            &mut name,
+            &mut record,
+            &arena,
+            arena.clone(),
+            None,
+        );
+        assert_eq!(
+            stay.await.unwrap().err().as_deref(),
+            Some("Already in arena")
+        );
+        let nowhere = Some("nowhere".to_string());
+        let lost = change_room(
+            &mut handle,
+            there.id,
+            &mut name,
+            &mut record,
+            &arena,
+            nowhere,
+            None,
+        );
+        assert_eq!(
+            lost.await.unwrap().err().as_deref(),
+            Some("No room nowhere")
+        );
+        // Still there after both
+        assert_eq!(players(&handle, Some("arena")).await.len(), 1);
+
+        let left = change_room(
+            &mut handle,
+            there.id,
+            &mut name,
+            &mut record,
+            &arena,
+            main,
+            None,
+        );
+        let back = left.await.unwrap().unwrap();
+        assert!(handle.tx.same_channel(&handle.main));
+        let state = players(&handle, None).await;
+        assert_eq!(state.len(), 1);
+        assert_eq!(state[0].id, back.id);
+        assert_eq!(state[0].name.as_deref(), Some("alice"));
+        assert_eq!(state[0].position, position);
+        assert_eq!(name.as_deref(), Some("alice"));
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn logs_guests_in_without_reconnecting() {
+        let path = std::env::temp_dir().join(format!("teleboxel-login-{}.db", std::process::id()));
+        std::fs::remove_file(&path).ok();
+        let storage = storage::connect(&format!("sqlite://{}", path.display()))
+            .await
+            .unwrap();
+        let handle = start(Some(Arc::new(OneToken)), Some(storage.clone()));
+        let guest = connect(&handle, None).await;
+        let position = (7, 8, 9);
+        let moved = WorldMsg::SetPosition {
+            id: guest.id,
+            position,
+            seq: None,
+        };
+        handle.tx.send(moved).await.unwrap();
+        let mut blocked = Blocked::default();
+        blocked.insert("mallory".to_string());
+
+        // Turned away, still a guest
+        let wrong = Credentials {
+            token: "mallory-token".to_string(),
+            platform: None,
+        };
+        let refused = log_in(&handle, guest.id, false, &blocked, &wrong).await;
+        assert_eq!(refused.unwrap().err().as_deref(), Some("Bad token"));
+        let state = players(&handle, None).await;
+        assert_eq!((state[0].id, state[0].name.as_deref()), (guest.id, None));
+
+        let right = Credentials {
+            token: "alice-token".to_string(),
+            platform: None,
+        };
+        let logged_in = log_in(&handle, guest.id, false, &blocked, &right).await;
+        let (identity, record, merged) = logged_in.unwrap().unwrap();
+        assert_eq!(identity.id, "alice");
+        assert_eq!(record.unwrap().name, "alice");
+        assert!(merged.blocks(Some("mallory")));
+        // Same player, same place, now alice
+        let state = players(&handle, None).await;
+        assert_eq!(state.len(), 1);
+        assert_eq!(state[0].id, guest.id);
+        assert_eq!(state[0].name.as_deref(), Some("alice"));
+        assert_eq!(state[0].position, position);
+
+        // What the guest did is alice's from now on
+        drop(Joined::new(&handle.tx, guest.id));
+        let mut saved = None;
+        for _ in 0..100 {
+            saved = storage.load_player("alice").await.unwrap();
+            if saved.is_some() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let saved = saved.unwrap();
+        assert_eq!(saved.position, position);
+        assert!(Blocked::from_properties(&saved.properties).blocks(Some("mallory")));
+
+        drop(storage);
+        std::fs::remove_file(path).ok();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn disconnects_players_the_connection_gave_up_on() {
+        // Wedged past the timeout, the world reads the `Connect` later
+        let (handle, world) = world(None, None);
+        let late = join_world(&handle.tx, joining(&handle, Some("alice"))).await;
+        assert!(matches!(late, Err(JoinError::Timeout)));
+        tokio::spawn(world.run(Duration::from_secs(60)));
+        let player = connect(&handle, Some("bob")).await;
+        // Alice got the first id and left right away
+        assert_eq!(player.id, 2);
+        let state = players(&handle, None).await;
+        assert_eq!(state.len(), 1);
+        assert_eq!(state[0].name.as_deref(), Some("bob"));
+
+        // However the connection ends
+        drop(Joined::new(&handle.tx, player.id));
+        assert!(players(&handle, None).await.is_empty());
+        // Or the world is gone
+        let (closed, _) = mpsc::channel(1);
+        let gone = join_world(&closed, joining(&handle, None)).await;
+        assert!(matches!(gone, Err(JoinError::Gone)));
+    }
+
+    #[tokio::test]
+    async fn disconnects_through_a_full_channel() {
+        let (tx, mut rx) = mpsc::channel(1);
+        let (reply, _) = oneshot::channel();
+        tx.send(WorldMsg::State { reply }).await.unwrap();
+        drop(Joined::new(&tx, 7));
+        assert!(matches!(rx.recv().await, Some(WorldMsg::State { .. })));
+        assert!(matches!(
+            rx.recv().await,
+            Some(WorldMsg::Disconnect {
+                id: 7,
+                moving: None
+            })
+        ));
+    }
+
+    #[tokio::test]
+    async fn keeps_layers_apart() {
+        let handle = start(None, None);
+        let (mut above, mut below) = (connect(&handle, None).await, connect(&handle, None).await);
+        for player in [&above, &below] {
+            let interest = WorldMsg::SetInterest {
+                id: player.id,
+                center: (0, 0, 0),
+                radius: 1,
+            };
+            handle.tx.send(interest).await.unwrap();
+        }
+        assert_eq!(handle.layer(None, below.id, 1).await, Some(true));
+        assert_eq!(handle.layer(None, 99, 1).await, Some(false));
+        let state = players(&handle, None).await;
+        assert_eq!((state[0].layer, state[1].layer), (0, 1));
+        let wait = Duration::from_millis(200);
+        received(&mut above, wait).await;
+        received(&mut below, wait).await;
+
+        // Spawned and shown in one layer only
+        let spawn = EntityOp::Spawn((2, 3, 4), false, 1);
+        let Some(Ok(EntityReply::One(entity))) = handle.entities(None, spawn).await else {
+            panic!("not spawned");
+        };
+        for (layer, reached) in [(0, 1), (1, 1), (2, 0)] {
+            let effect = Effect {
+                id: layer as u16,
+                position: (1, 1, 1),
+                params: Vec::new(),
+                layer,
+            };
+            assert_eq!(handle.effect(None, effect).await, Some(reached));
+        }
+        let sees = |msgs: &[ServerMsg]| {
+            let entities = msgs
+                .iter()
+                .filter(|m| matches!(m, ServerMsg::Entity { id, .. } if *id == entity.id));
+            let effects = msgs.iter().filter_map(|m| match m {
+                ServerMsg::Effect { effect, .. } => Some(*effect),
+                _ => None,
+            });
+            (entities.count() > 0, effects.collect::<Vec<_>>())
+        };
+        assert_eq!(sees(&received(&mut above, wait).await), (false, vec![0]));
+        assert_eq!(sees(&received(&mut below, wait).await), (true, vec![1]));
+
+        // Moved over: gone from one, shown to the other
+        let moved = handle.entities(None, EntityOp::Layer(entity.id, 0)).await;
+        assert!(matches!(moved, Some(Ok(_))));
+        assert!(sees(&received(&mut above, wait).await).0);
+        let below = received(&mut below, wait).await;
+        assert!(
+            below
+                .iter()
+                .any(|m| matches!(m, ServerMsg::EntityGone { id } if *id == entity.id))
+        );
+    }
+
+    #[tokio::test]
+    async fn moves_to_the_edges_of_the_world() {
+        let handle = start(None, None);
+        let player = connect(&handle, Some("alice")).await;
+        for position in [(0, i32::MAX, 0), (i32::MIN, i32::MIN, i32::MAX)] {
+            let moved = WorldMsg::SetPosition {
+                id: player.id,
+                position,
+                seq: None,
+            };
+            handle.tx.send(moved).await.unwrap();
+            assert_eq!(players(&handle, None).await[0].position, position);
+        }
+    }
+
+    #[tokio::test]
+    async fn teleports_to_the_top_of_the_world() {
+        let (handle, mut world) = world(None, None);
+        let (tx, _joined) = oneshot::channel();
+        world.apply_msg(joining(&handle, Some("alice"))(tx));
+        let top = (0, i32::MAX, 0);
+        world.teleport(1, top).unwrap();
+        assert_eq!(world.players[&1].position, top);
+    }
+
+    // Counts the allocations of the thread it runs on
+    struct Counting;
+
+    thread_local! {
+        static ALLOCATIONS: Cell<u64> = const { Cell::new(0) };
+    }
+
+    fn allocations() -> u64 {
+        ALLOCATIONS.with(Cell::get)
+    }
+
+    fn count() {
+        ALLOCATIONS.try_with(|n| n.set(n.get() + 1)).ok();
+    }
+
+    unsafe impl GlobalAlloc for Counting {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            count();
+            unsafe { System.alloc(layout) }
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            unsafe { System.dealloc(ptr, layout) }
+        }
+
+        unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+            count();
+            unsafe { System.realloc(ptr, layout, new_size) }
+        }
+    }
+
+    #[global_allocator]
+    static GLOBAL: Counting = Counting;
+
+    // The steady tick of `benches/frame_pool.rs` through the world: moved
+    // entities, acks, and layers hiding and showing entities again
+    #[tokio::test]
+    async fn encodes_and_queues_without_allocating() {
+        let (handle, mut world) = world(None, None);
+        let mut joined = Vec::new();
+        for name in ["alice", "bob", "carol"] {
+            let (tx, mut rx) = oneshot::channel();
+            world.apply_msg(joining(&handle, Some(name))(tx));
+            joined.push(rx.try_recv().unwrap().unwrap());
+        }
+        for player in world.players.values_mut() {
+            player.interest = Some(((0, 0, 0), 2));
+        }
+        // Bob's frames are fitted to his origin
+        world.players.get_mut(&joined[1].id).unwrap().origin = Some((0, 0, 0));
+        let ids: Vec<_> = (0..16)
+            .map(|i| {
+                let id = world.entities.spawn((i, 40, 0), false).id;
+                world.entities.set_layer(id, i as u32 % 2).unwrap().id
+            })
+            .collect();
+        world.layered = true;
+
+        let (warmup, ticks) = (100, 1000);
+        let (mut counted, mut written) = (0, 0);
+        for t in 0..warmup + ticks {
+            let carol = world.players.get_mut(&joined[2].id).unwrap();
+            carol.layer = t % 2;
+            world.players.get_mut(&joined[0].id).unwrap().acked = Some(t);
+            let moved: Vec<_> = ids
+                .iter()
+                .map(|&id| world.entities.move_by(None, id, (t as i32 % 8, 40, 0)))
+                .collect();
+
+            let before = allocations();
+            for entity in moved {
+                world.send_entity(&entity.unwrap());
+            }
+            world.broadcast_tick();
+            if t >= warmup {
+                counted += allocations() - before;
+            }
+
+            world.tick += 1;
+            for player in &mut joined {
+                while player.rx.try_recv().is_some() {
+                    written += 1;
+                }
+            }
+        }
+        assert_eq!(counted, 0);
+        assert!(written >= 3 * (warmup + ticks));
+    }
 }
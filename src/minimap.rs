@@ -0,0 +1,357 @@
+//! Minimaps: a top-down overview of the world around a point, a cell per
+//! `scale` blocks on each side with the highest block of the cell's center
+//! column, its height and its color (`color` in the block registry, see
+//! blocks.rs). Players ask for one around them with `Minimap [Size
+//! [Scale]]` and get `MINIMAP`, the grid for clients to draw; `GET
+//! /admin/minimap` renders one anywhere as a PNG, a pixel per cell shaded
+//! by height against the cell north of it, or gives the grid as JSON.
+//!
+//! Worlds keep each chunk column's surface once worked out, until a block
+//! in it is set or one of its chunks loads, and remember columns without
+//! any loaded chunk until then. Only loaded chunks count, so those are
+//! unknown (`UNKNOWN`, black in PNGs): a minimap around a player shows what
+//! its interest holds. PNGs aren't compressed, 48KB at most.
+
+use crate::{
+    blocks::BlockRegistry,
+    chunk::{CHUNK_SIZE, Chunk, ChunkPos, split},
+    chunk_cache::ChunkCache,
+};
+use serde::Serialize;
+use std::{
+    cmp::Reverse,
+    collections::{HashMap, HashSet},
+};
+
+/// Most cells on a side.
+pub const MAX_SIZE: u8 = 128;
+pub const DEFAULT_SIZE: u8 = 64;
+/// Most blocks a cell covers on a side.
+pub const MAX_SCALE: u8 = 8;
+/// The height of cells nothing is known about.
+pub const UNKNOWN: u8 = 255;
+// Chunk columns kept, known empty ones too, all go when full. Enough for
+// the largest minimap
+const CACHE_LEN: usize = 4096;
+
+// The highest block and its y per column of a chunk column, `Chunk::index`
+// order without y
+type Surface = [Option<(i32, u16)>; CHUNK_SIZE * CHUNK_SIZE];
+
+#[derive(Serialize, Clone, PartialEq, Eq, Debug)]
+pub struct Minimap {
+    /// The north-west (lowest x and z) corner, in blocks.
+    pub x: i32,
+    pub z: i32,
+    /// Heights are above this, the lowest one.
+    pub bottom: i32,
+    pub scale: u8,
+    pub size: u8,
+    /// Rows along x, from the lowest z: heights above `bottom` (at most
+    /// 254) or `UNKNOWN`.
+    pub heights: Vec<u8>,
+    /// RGB, black for unknown cells.
+    pub colors: Vec<u8>,
+}
+
+/// One world's chunk column surfaces.
+#[derive(Default)]
+pub struct Surfaces {
+    columns: HashMap<(i32, i32), Box<Surface>>,
+    // Columns without a loaded chunk, so requests don't look for one again
+    empty: HashSet<(i32, i32)>,
+}
+
+impl Surfaces {
+    /// Forgets the surface of block column `x`, `z`.
+    pub fn invalidate(&mut self, x: i32, z: i32) {
+        self.invalidate_column((split(x).0, split(z).0));
+    }
+
+    /// Forgets the surface of the column chunk `pos` is in.
+    pub fn invalidate_chunk(&mut self, (cx, _, cz): ChunkPos) {
+        self.invalidate_column((cx, cz));
+    }
+
+    fn invalidate_column(&mut self, column: (i32, i32)) {
+        self.columns.remove(&column);
+        self.empty.remove(&column);
+    }
+
+    /// The minimap of `size` cells of `scale` blocks around block column
+    /// `center`, both clamped to the limits, moved in to fit where it's
+    /// near the edge of the world's coordinates.
+    pub fn minimap(
+        &mut self,
+        chunks: &ChunkCache,
+        blocks: &BlockRegistry,
+        center: (i32, i32),
+        size: u8,
+        scale: u8,
+    ) -> Minimap {
+        let size = size.clamp(1, MAX_SIZE);
+        let scale = scale.clamp(1, MAX_SCALE);
+        let span = size as i32 * scale as i32;
+        let corner = |c: i32| c.clamp(i32::MIN + span / 2, i32::MAX - span + span / 2) - span / 2;
+        let (x, z) = (corner(center.0), corner(center.1));
+        let sampled = |cell: u8, from: i32| from + cell as i32 * scale as i32 + scale as i32 / 2;
+        let cells: Vec<_> = (0..size)
+            .flat_map(|row| (0..size).map(move |col| (sampled(col, x), sampled(row, z))))
+            .collect();
+
+        let missing: HashSet<_> = cells
+            .iter()
+            .map(|&(x, z)| (split(x).0, split(z).0))
+            .filter(|column| !self.columns.contains_key(column) && !self.empty.contains(column))
+            .collect();
+        self.work_out(chunks, &missing);
+
+        let tops: Vec<_> = cells
+            .iter()
+            .map(|&(x, z)| {
+                let (cx, lx) = split(x);
+                let (cz, lz) = split(z);
+                let surface = self.columns.get(&(cx, cz))?;
+                surface[lz * CHUNK_SIZE + lx]
+            })
+            .collect();
+        let bottom = tops.iter().flatten().map(|&(y, _)| y).min().unwrap_or(0);
+        let mut heights = Vec::with_capacity(tops.len());
+        let mut colors = Vec::with_capacity(tops.len() * 3);
+        for top in tops {
+            match top {
+                Some((y, block)) => {
+                    heights.push(y.saturating_sub(bottom).min(UNKNOWN as i32 - 1) as u8);
+                    colors.extend(blocks.color(block));
+                }
+                None => {
+                    heights.push(UNKNOWN);
+                    colors.extend([0; 3]);
+                }
+            }
+        }
+        Minimap {
+            x,
+            z,
+            bottom,
+            scale,
+            size,
+            heights,
+            colors,
+        }
+    }
+
+    // In one pass over the loaded chunks. Columns without any are only
+    // remembered as such, they show once one loads
+    fn work_out(&mut self, chunks: &ChunkCache, missing: &HashSet<(i32, i32)>) {
+        if missing.is_empty() {
+            return;
+        }
+        let mut found: HashMap<_, Vec<(i32, &Chunk)>> = HashMap::new();
+        for (&(cx, cy, cz), chunk) in chunks.iter() {
+            if missing.contains(&(cx, cz)) {
+                found.entry((cx, cz)).or_default().push((cy, chunk));
+            }
+        }
+        if self.columns.len() + self.empty.len() + missing.len() > CACHE_LEN {
+            self.columns.clear();
+            self.empty.clear();
+        }
+        let empty = missing.iter().filter(|column| !found.contains_key(column));
+        self.empty.extend(empty);
+        for (column, mut stack) in found {
+            stack.sort_by_key(|&(cy, _)| Reverse(cy));
+            self.columns.insert(column, Box::new(surface(&stack)));
+        }
+    }
+}
+
+// Chunks from the top down
+fn surface(stack: &[(i32, &Chunk)]) -> Surface {
+    let mut surface = [None; CHUNK_SIZE * CHUNK_SIZE];
+    for lz in 0..CHUNK_SIZE {
+        for lx in 0..CHUNK_SIZE {
+            surface[lz * CHUNK_SIZE + lx] = stack.iter().find_map(|&(cy, chunk)| {
+                (0..CHUNK_SIZE).rev().find_map(|ly| {
+                    let block = chunk.get(lx, ly, lz);
+                    (block != 0).then_some((cy * CHUNK_SIZE as i32 + ly as i32, block))
+                })
+            });
+        }
+    }
+    surface
+}
+
+impl Minimap {
+    /// As an RGB PNG, a pixel per cell, lighter where the ground rises
+    /// going south and darker where it falls.
+    pub fn png(&self) -> Vec<u8> {
+        let size = self.size as usize;
+        let mut pixels = Vec::with_capacity(size * (size * 3 + 1));
+        for row in 0..size {
+            // No filter
+            pixels.push(0);
+            for col in 0..size {
+                let i = row * size + col;
+                let north = row.checked_sub(1).map(|row| self.heights[row * size + col]);
+                let shade = match (self.heights[i], north) {
+                    (UNKNOWN, _) | (_, None | Some(UNKNOWN)) => 220,
+                    (height, Some(north)) if height > north => 255,
+                    (height, Some(north)) if height < north => 180,
+                    _ => 220,
+                };
+                for &c in &self.colors[i * 3..i * 3 + 3] {
+                    pixels.push((c as u32 * shade / 255) as u8);
+                }
+            }
+        }
+
+        let mut header = Vec::new();
+        header.extend((size as u32).to_be_bytes());
+        header.extend((size as u32).to_be_bytes());
+        // 8 bits per channel, RGB, no interlacing
+        header.extend([8, 2, 0, 0, 0]);
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        png_chunk(&mut png, b"IHDR", &header);
+        png_chunk(&mut png, b"IDAT", &zlib_stored(&pixels));
+        png_chunk(&mut png, b"IEND", &[]);
+        png
+    }
+}
+
+fn png_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend((data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend(kind);
+    png.extend(data);
+    let crc = crc32(&png[start..]);
+    png.extend(crc.to_be_bytes());
+}
+
+// A zlib stream of stored (uncompressed) deflate blocks
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+    let mut blocks = data.chunks(u16::MAX as usize).peekable();
+    while let Some(block) = blocks.next() {
+        out.push(blocks.peek().is_none() as u8);
+        let len = block.len() as u16;
+        out.extend(len.to_le_bytes());
+        out.extend((!len).to_le_bytes());
+        out.extend(block);
+    }
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    out.extend(((b << 16) | a).to_be_bytes());
+    out
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = match crc & 1 {
+                1 => (crc >> 1) ^ 0xedb8_8320,
+                _ => crc >> 1,
+            };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        chunk_cache::{CHUNK_BYTES, CacheConfig},
+        terrain::{GRASS, STONE},
+    };
+
+    #[tokio::test]
+    async fn maps_loaded_surfaces_until_edited() {
+        let blocks = BlockRegistry::default();
+        let config = CacheConfig {
+            saves: None,
+            memory_budget: 8 * CHUNK_BYTES,
+            workers: 1,
+        };
+        let mut chunks = ChunkCache::new(config, None);
+        for pos in [(0, 0, 0), (0, -1, 0)] {
+            chunks.insert_loaded(pos, Chunk::empty());
+        }
+        for x in 0..16 {
+            for z in 0..16 {
+                chunks.set_block(x, -3, z, GRASS);
+            }
+        }
+        chunks.set_block(4, 5, 4, STONE);
+
+        let mut surfaces = Surfaces::default();
+        let map = surfaces.minimap(&chunks, &blocks, (4, 4), 4, 2);
+        assert_eq!((map.x, map.z, map.bottom), (0, 0, -3));
+        // Cells sample columns 1, 3, 5 and 7, the tower's in none
+        assert!(map.heights.iter().all(|&h| h == 0));
+        assert_eq!(&map.colors[..3], blocks.color(GRASS));
+
+        let map = surfaces.minimap(&chunks, &blocks, (0, 4), 8, 1);
+        // West of x 0 isn't loaded
+        assert_eq!(
+            &map.heights[..8],
+            [UNKNOWN, UNKNOWN, UNKNOWN, UNKNOWN, 0, 0, 0, 0]
+        );
+        assert_eq!(&map.colors[..3], [0, 0, 0]);
+
+        // Kept until a block in the column is set
+        chunks.set_block(5, 1, 5, STONE);
+        let stale = surfaces.minimap(&chunks, &blocks, (4, 4), 4, 2);
+        assert_eq!(stale.heights[2 * 4 + 2], 0);
+        surfaces.invalidate(5, 5);
+        let map = surfaces.minimap(&chunks, &blocks, (4, 4), 4, 2);
+        assert_eq!(map.heights[2 * 4 + 2], 4);
+        assert_eq!(&map.colors[(2 * 4 + 2) * 3..][..3], blocks.color(STONE));
+
+        let png = map.png();
+        assert!(png.starts_with(b"\x89PNG\r\n\x1a\n"));
+        assert_eq!(&png[16..24], [0, 0, 0, 4, 0, 0, 0, 4]);
+        // IEND and its well-known CRC
+        assert_eq!(&png[png.len() - 8..], b"IEND\xae\x42\x60\x82");
+    }
+
+    #[tokio::test]
+    async fn remembers_empty_columns_and_fits_the_edges() {
+        let blocks = BlockRegistry::default();
+        let config = CacheConfig {
+            saves: None,
+            memory_budget: 8 * CHUNK_BYTES,
+            workers: 1,
+        };
+        let mut chunks = ChunkCache::new(config, None);
+        let mut surfaces = Surfaces::default();
+        let map = surfaces.minimap(&chunks, &blocks, (8, 8), 1, 1);
+        assert_eq!(map.heights, [UNKNOWN]);
+        assert!(surfaces.empty.contains(&(0, 0)));
+
+        // Until a chunk loads there
+        chunks.insert_loaded((0, 0, 0), Chunk::empty());
+        chunks.set_block(8, 2, 8, STONE);
+        assert_eq!(
+            surfaces.minimap(&chunks, &blocks, (8, 8), 1, 1).heights,
+            [UNKNOWN]
+        );
+        surfaces.invalidate_chunk((0, 0, 0));
+        assert!(surfaces.empty.is_empty());
+        let map = surfaces.minimap(&chunks, &blocks, (8, 8), 1, 1);
+        assert_eq!((map.heights[0], map.bottom), (0, 2));
+
+        // The largest minimap at the far corners stays inside
+        for c in [i32::MAX, i32::MIN] {
+            let map = surfaces.minimap(&chunks, &blocks, (c, c), MAX_SIZE, MAX_SCALE);
+            assert!(map.heights.iter().all(|&h| h == UNKNOWN));
+            let span = MAX_SIZE as i64 * MAX_SCALE as i64;
+            assert!(map.x as i64 >= i32::MIN as i64 && map.x as i64 + span <= i32::MAX as i64);
+        }
+    }
+}
@@ -0,0 +1,151 @@
+//! Floating origin: far from spawn, i32 block positions still hold, but a
+//! client's f32 world space doesn't (past 2^24 blocks it can't tell blocks
+//! apart, and physics jitters well before that). A client can ask for
+//! positions from an origin near it instead, with the `Origin X Y Z`
+//! command (a chunk corner, in blocks):
+//!
+//! - Server frames with positions start with a `REBASE` holding the origin
+//!   they're from, see `ServerFrame::rebase`. Chunk positions are from the
+//!   origin's chunk, frames without a `REBASE` are absolute.
+//! - Once the player is more than `REBASE_DISTANCE` blocks from it on an
+//!   axis, the world moves the origin to the corner of the player's chunk,
+//!   between ticks.
+//! - Lanes (`lanes.rs`) can reorder frames, so a client moves to a new
+//!   origin once its frame's tick is past the tick its current one came
+//!   with, and sends it back with `Origin`. The positions it sends (moves,
+//!   edits, interest, claims, entity moves) are from the origin it last
+//!   sent, the connection adds it back before anything else sees them.
+//!
+//! Only the first `Origin` of a connection sets the world's origin, later
+//! ones are acks. The origin follows the connection into rooms. Admin,
+//! webhook and console positions stay absolute, and so do offsets (mounts,
+//! attachments) and `SetInterest` radii.
+
+use crate::{
+    chunk::{CHUNK_SIZE, ChunkPos},
+    claims::BlockPos,
+};
+
+/// Blocks from the origin on an axis before it moves, where an f32 is
+/// still finer than a thousandth of a block.
+pub const REBASE_DISTANCE: i32 = 4096;
+
+/// Whether `origin` is a chunk corner, as origins must be.
+pub fn is_corner(origin: BlockPos) -> bool {
+    let corner = |b: i32| b.rem_euclid(CHUNK_SIZE as i32) == 0;
+    corner(origin.0) && corner(origin.1) && corner(origin.2)
+}
+
+/// The corner of the chunk `position` is in, where a far player is
+/// rebased to.
+pub fn corner(position: BlockPos) -> BlockPos {
+    let down = |b: i32| b.div_euclid(CHUNK_SIZE as i32) * CHUNK_SIZE as i32;
+    (down(position.0), down(position.1), down(position.2))
+}
+
+/// The chunk an origin is the corner of.
+pub fn chunk(origin: BlockPos) -> ChunkPos {
+    let size = CHUNK_SIZE as i32;
+    (
+        origin.0.div_euclid(size),
+        origin.1.div_euclid(size),
+        origin.2.div_euclid(size),
+    )
+}
+
+/// Whether a player at `position` is due a new origin.
+pub fn is_far(origin: BlockPos, position: BlockPos) -> bool {
+    let far = |o: i32, p: i32| (p as i64 - o as i64).abs() > REBASE_DISTANCE as i64;
+    far(origin.0, position.0) || far(origin.1, position.1) || far(origin.2, position.2)
+}
+
+/// A position from `origin` made absolute. Wraps like the client's sums
+/// do, so any i32 position can be sent from any origin.
+pub fn absolute(origin: BlockPos, relative: BlockPos) -> BlockPos {
+    (
+        relative.0.wrapping_add(origin.0),
+        relative.1.wrapping_add(origin.1),
+        relative.2.wrapping_add(origin.2),
+    )
+}
+
+/// The other way around.
+pub fn relative(origin: BlockPos, absolute: BlockPos) -> BlockPos {
+    (
+        absolute.0.wrapping_sub(origin.0),
+        absolute.1.wrapping_sub(origin.1),
+        absolute.2.wrapping_sub(origin.2),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        chunk::Chunk,
+        effects::Effect,
+        entities::Entity,
+        protocol::{ServerFrame, ServerMsg, decode_server_frame},
+    };
+
+    #[test]
+    fn frames_are_rebased_to_the_origin() {
+        let far = (1 << 30, 64, -(1 << 30) - 5);
+        let origin = corner(far);
+        assert_eq!(origin, (1 << 30, 64, -(1 << 30) - 16));
+        assert!(is_corner(origin) && !is_corner(far));
+        assert!(!is_far(origin, far));
+        assert!(is_far(origin, (far.0 + REBASE_DISTANCE + 1, 64, 0)));
+        assert!(is_far((i32::MIN, 0, 0), (i32::MAX, 0, 0)));
+
+        let mut frame = ServerFrame::new(3);
+        frame.chat("ann", "hi");
+        frame.chunk_snapshot(chunk(origin), 1, &Chunk::empty());
+        frame.entity(&Entity {
+            id: 9,
+            position: far,
+            authority: None,
+            attached: None,
+            fixed: false,
+            always: false,
+            layer: 0,
+        });
+        frame.effect(&Effect {
+            id: 2,
+            position: (i32::MIN, 0, 0),
+            params: Vec::new(),
+            layer: 0,
+        });
+        frame.rebase(origin);
+        let (_, msgs) = decode_server_frame(&frame.finish()).unwrap();
+        let (x, y, z) = origin;
+        assert_eq!(msgs[0], ServerMsg::Rebase { x, y, z });
+        assert!(matches!(msgs[1], ServerMsg::Chat { .. }));
+        assert!(matches!(
+            msgs[2],
+            ServerMsg::ChunkSnapshot { pos: (0, 0, 0), .. }
+        ));
+        assert!(matches!(
+            msgs[3],
+            ServerMsg::Entity {
+                x: 0,
+                y: 0,
+                z: 11,
+                ..
+            }
+        ));
+        // Wrapped, the client's sum wraps back
+        let ServerMsg::Effect { x, y, z, .. } = msgs[4] else {
+            panic!("not an effect");
+        };
+        assert_eq!(absolute(origin, (x, y, z)), (i32::MIN, 0, 0));
+        assert_eq!(relative(origin, (i32::MIN, 0, 0)), (x, y, z));
+
+        // Nothing to rebase, no `REBASE`
+        let mut chat = ServerFrame::new(3);
+        chat.chat("ann", "hi");
+        chat.rebase(origin);
+        let (_, msgs) = decode_server_frame(&chat.finish()).unwrap();
+        assert_eq!(msgs.len(), 1);
+    }
+}
@@ -0,0 +1,335 @@
+//! Pathfinding for server logic (entities walking somewhere, NPCs once
+//! there are any): A* over the voxel grid for a walker two blocks tall,
+//! like players. It walks to the four neighboring columns, steps (jumps)
+//! up `STEP_UP` block, drops down at most `MAX_DROP` and never swims or
+//! flies: every node stands on a solid block with two free above it.
+//!
+//! Searches don't run on the world task. The world copies the chunks around
+//! the two ends into a `Terrain` and `Pathfinder::find` searches it on a
+//! blocking thread, so a long search never stalls the tick. Results, found
+//! or not, are cached per world until blocks change. Unloaded chunks read
+//! as air, so nothing is found through them.
+//!
+//! Ends are at most `MAX_DISTANCE` blocks apart on each axis, and the
+//! search stays within `MARGIN` blocks around them.
+
+use crate::{
+    blocks::BlockRegistry,
+    chunk::{self, ChunkStore},
+    chunk_cache::ChunkCache,
+    claims::BlockPos,
+};
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap, hash_map::Entry},
+    fmt,
+    sync::{Arc, Mutex},
+};
+
+pub const MAX_DISTANCE: i32 = 64;
+/// How far a path may stray from the box around its ends, in blocks.
+pub const MARGIN: i32 = 8;
+pub const STEP_UP: i32 = 1;
+pub const MAX_DROP: i32 = 3;
+// Nodes expanded before giving up
+const MAX_NODES: usize = 32_768;
+// Paths cached per world, the oldest half goes when full
+const CACHE_LEN: usize = 512;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PathError {
+    TooFar,
+    /// Nowhere to stand at the start or the goal.
+    NoFooting(BlockPos),
+    NoRoute,
+}
+
+impl fmt::Display for PathError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PathError::TooFar => write!(f, "ends are more than {MAX_DISTANCE} blocks apart"),
+            PathError::NoFooting((x, y, z)) => write!(f, "nowhere to stand at {x} {y} {z}"),
+            PathError::NoRoute => write!(f, "no route"),
+        }
+    }
+}
+
+impl std::error::Error for PathError {}
+
+/// Block positions from start to goal, both included.
+pub type Route = Arc<Vec<BlockPos>>;
+
+/// The blocks a search may look at, copied out of the world.
+pub struct Terrain {
+    chunks: ChunkStore,
+    blocks: Arc<BlockRegistry>,
+    min: BlockPos,
+    max: BlockPos,
+}
+
+impl Terrain {
+    /// Copies the loaded chunks a search from `from` to `to` can reach.
+    pub fn copy(
+        chunks: &ChunkCache,
+        blocks: Arc<BlockRegistry>,
+        from: BlockPos,
+        to: BlockPos,
+    ) -> Self {
+        let min = (
+            from.0.min(to.0) - MARGIN,
+            from.1.min(to.1) - MARGIN,
+            from.2.min(to.2) - MARGIN,
+        );
+        let max = (
+            from.0.max(to.0) + MARGIN,
+            from.1.max(to.1) + MARGIN,
+            from.2.max(to.2) + MARGIN,
+        );
+        let mut store = ChunkStore::new();
+        // One more below, to stand on the bottom row
+        let chunk = |w| chunk::split(w).0;
+        for cx in chunk(min.0)..=chunk(max.0) {
+            for cy in chunk(min.1 - 1)..=chunk(max.1 + 1) {
+                for cz in chunk(min.2)..=chunk(max.2) {
+                    if let Some(c) = chunks.get((cx, cy, cz)) {
+                        store.insert((cx, cy, cz), c.clone());
+                    }
+                }
+            }
+        }
+        Self {
+            chunks: store,
+            blocks,
+            min,
+            max,
+        }
+    }
+
+    fn solid(&self, (x, y, z): BlockPos) -> bool {
+        self.blocks.is_solid(self.chunks.block(x, y, z))
+    }
+
+    fn inside(&self, (x, y, z): BlockPos) -> bool {
+        (self.min.0..=self.max.0).contains(&x)
+            && (self.min.1..=self.max.1).contains(&y)
+            && (self.min.2..=self.max.2).contains(&z)
+    }
+
+    /// Solid below, two free blocks to stand in.
+    pub fn standable(&self, (x, y, z): BlockPos) -> bool {
+        self.solid((x, y - 1, z)) && !self.solid((x, y, z)) && !self.solid((x, y + 1, z))
+    }
+
+    // Where a walker at `at` ends up moving one block along x or z
+    fn neighbors(&self, (x, y, z): BlockPos) -> impl Iterator<Item = BlockPos> + '_ {
+        let headroom = (1..=STEP_UP).all(|up| !self.solid((x, y + 1 + up, z)));
+        [(1, 0), (-1, 0), (0, 1), (0, -1)]
+            .into_iter()
+            .filter_map(move |(dx, dz)| {
+                let (nx, nz) = (x + dx, z + dz);
+                if self.standable((nx, y, nz)) {
+                    return Some((nx, y, nz));
+                }
+                if !self.solid((nx, y, nz)) && !self.solid((nx, y + 1, nz)) {
+                    // Over the edge, down to the first block below
+                    let ground = (1..=MAX_DROP).find(|d| self.solid((nx, y - d - 1, nz)))?;
+                    return Some((nx, y - ground, nz));
+                }
+                (1..=STEP_UP)
+                    .find(|up| self.standable((nx, y + up, nz)))
+                    .filter(|_| headroom)
+                    .map(|up| (nx, y + up, nz))
+            })
+            .filter(|&p| self.inside(p))
+    }
+}
+
+/// A* from `from` to `to` over `terrain`.
+pub fn find_path(
+    terrain: &Terrain,
+    from: BlockPos,
+    to: BlockPos,
+) -> Result<Vec<BlockPos>, PathError> {
+    for end in [from, to] {
+        if !terrain.standable(end) {
+            return Err(PathError::NoFooting(end));
+        }
+    }
+
+    // Every move goes one block along x or z for at least 1, drops included
+    let estimate = |(x, _, z): BlockPos| (x - to.0).abs() + (z - to.2).abs();
+    // Position -> (cost so far, came from)
+    let mut seen: HashMap<BlockPos, (i32, BlockPos)> = HashMap::from([(from, (0, from))]);
+    let mut open = BinaryHeap::from([Reverse((estimate(from), 0, from))]);
+    let mut expanded = 0;
+    while let Some(Reverse((_, cost, at))) = open.pop() {
+        if at == to {
+            let mut path = vec![to];
+            let mut at = to;
+            while at != from {
+                at = seen[&at].1;
+                path.push(at);
+            }
+            path.reverse();
+            return Ok(path);
+        }
+        if cost > seen[&at].0 {
+            continue;
+        }
+        expanded += 1;
+        if expanded > MAX_NODES {
+            break;
+        }
+        for next in terrain.neighbors(at) {
+            // Climbing costs a block more, so flat detours win ties
+            let cost = cost + 1 + (next.1 - at.1).max(0);
+            match seen.entry(next) {
+                Entry::Occupied(e) if e.get().0 <= cost => continue,
+                Entry::Occupied(mut e) => *e.get_mut() = (cost, at),
+                Entry::Vacant(e) => {
+                    e.insert((cost, at));
+                }
+            }
+            open.push(Reverse((cost + estimate(next), cost, next)));
+        }
+    }
+    Err(PathError::NoRoute)
+}
+
+/// One world's path cache, searches running off its task.
+#[derive(Clone, Default)]
+pub struct Pathfinder {
+    cache: Arc<Mutex<Cache>>,
+}
+
+#[derive(Default)]
+struct Cache {
+    // Bumped on every change, so searches started before don't get cached
+    generation: u64,
+    // With the order they came in
+    paths: HashMap<(BlockPos, BlockPos), (u64, Result<Route, PathError>)>,
+    inserted: u64,
+}
+
+impl Pathfinder {
+    /// What a search would find, if it ran since blocks last changed.
+    pub fn cached(&self, from: BlockPos, to: BlockPos) -> Option<Result<Route, PathError>> {
+        let cache = self.cache.lock().unwrap();
+        cache.paths.get(&(from, to)).map(|(_, path)| path.clone())
+    }
+
+    /// Blocks changed, routes may have opened or closed.
+    pub fn invalidate(&self) {
+        let mut cache = self.cache.lock().unwrap();
+        cache.generation += 1;
+        cache.paths.clear();
+    }
+
+    /// Searches `terrain` on a blocking thread, checking the cache first.
+    pub async fn find(
+        &self,
+        terrain: Terrain,
+        from: BlockPos,
+        to: BlockPos,
+    ) -> Result<Route, PathError> {
+        let far = [from.0 - to.0, from.1 - to.1, from.2 - to.2];
+        if far.iter().any(|d| d.abs() > MAX_DISTANCE) {
+            return Err(PathError::TooFar);
+        }
+        if let Some(path) = self.cached(from, to) {
+            return path;
+        }
+
+        let generation = self.cache.lock().unwrap().generation;
+        let search = move || find_path(&terrain, from, to).map(Arc::new);
+        let path = tokio::task::spawn_blocking(search)
+            .await
+            .unwrap_or(Err(PathError::NoRoute));
+
+        let mut cache = self.cache.lock().unwrap();
+        if cache.generation == generation {
+            if cache.paths.len() >= CACHE_LEN {
+                let cutoff = cache.inserted - CACHE_LEN as u64 / 2;
+                cache.paths.retain(|_, (at, _)| *at >= cutoff);
+            }
+            cache.inserted += 1;
+            let at = cache.inserted;
+            cache.paths.insert((from, to), (at, path.clone()));
+        }
+        path
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        chunk_cache::{CHUNK_BYTES, CacheConfig},
+        terrain::STONE,
+    };
+
+    #[tokio::test]
+    async fn walks_steps_and_drops_around_walls() {
+        let blocks = Arc::new(BlockRegistry::default());
+        let config = CacheConfig {
+            saves: None,
+            memory_budget: 8 * CHUNK_BYTES,
+            workers: 1,
+        };
+        let mut chunks = ChunkCache::new(config, None);
+        // Floor at y 0 from x 0 to 9, a one block step at x 4, a wall at
+        // x 6 with a gap at z 3, a two block drop past x 8
+        for pos in [(0, 0, 0), (0, -1, 0)] {
+            chunks.insert_loaded(pos, chunk::Chunk::empty());
+        }
+        for x in 0..=9 {
+            for z in 0..=5 {
+                chunks.set_block(x, 0, z, STONE);
+            }
+        }
+        for z in 0..=5 {
+            chunks.set_block(4, 1, z, STONE);
+            if z != 3 {
+                for y in 1..=3 {
+                    chunks.set_block(6, y, z, STONE);
+                }
+            }
+        }
+        for x in 10..=11 {
+            for z in 0..=5 {
+                chunks.set_block(x, -2, z, STONE);
+            }
+        }
+
+        let paths = Pathfinder::default();
+        let (from, to) = ((0, 1, 0), (11, -1, 0));
+        let terrain = Terrain::copy(&chunks, blocks.clone(), from, to);
+        let path = paths.find(terrain, from, to).await.unwrap();
+        assert_eq!((path[0], *path.last().unwrap()), (from, to));
+        assert!(path.iter().any(|p| p.0 == 4 && p.1 == 2));
+        assert!(path.contains(&(6, 1, 3)));
+        for pair in path.windows(2) {
+            let (a, b) = (pair[0], pair[1]);
+            assert_eq!((a.0 - b.0).abs() + (a.2 - b.2).abs(), 1);
+            assert!((-MAX_DROP..=STEP_UP).contains(&(b.1 - a.1)));
+        }
+        assert_eq!(paths.cached(from, to), Some(Ok(path)));
+
+        // Walled off: no route, also cached until blocks change
+        for y in 1..=3 {
+            chunks.set_block(6, y, 3, STONE);
+        }
+        paths.invalidate();
+        assert_eq!(paths.cached(from, to), None);
+        let terrain = Terrain::copy(&chunks, blocks.clone(), from, to);
+        assert_eq!(paths.find(terrain, from, to).await, Err(PathError::NoRoute));
+        assert_eq!(paths.cached(from, to), Some(Err(PathError::NoRoute)));
+
+        let terrain = Terrain::copy(&chunks, blocks.clone(), from, (0, 5, 0));
+        let e = paths.find(terrain, from, (0, 5, 0)).await;
+        assert_eq!(e, Err(PathError::NoFooting((0, 5, 0))));
+        let terrain = Terrain::copy(&chunks, blocks, from, (100, 1, 0));
+        let e = paths.find(terrain, from, (100, 1, 0)).await;
+        assert_eq!(e, Err(PathError::TooFar));
+    }
+}
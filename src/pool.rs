@@ -0,0 +1,120 @@
+//! Warm room pools: idle rooms made ahead from a template (see
+//! `templates.rs`), so `POST /admin/rooms?template=` hands one out at once
+//! instead of forking the world and loading chunks as a match starts:
+//!
+//! ```toml
+//! [template.duel]
+//! pool = 2          # idle rooms kept ready
+//! pool_max = 10     # at most, while claims pick up (pool when unset)
+//! ```
+//!
+//! Every second each pool gains or loses a room toward its target: the
+//! rooms claimed over the last minute, between `pool` and `pool_max`.
+//! Pooled rooms load the chunks around their spawn point (`WARM_RADIUS`)
+//! before anyone joins. They're ordinary rooms named `<template>-<n>`, one
+//! that a player joined by name leaves the pool. Asking for a `room` name
+//! skips the pool, and nothing is refilled while draining.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Chunks loaded ahead around a pooled room's spawn point.
+pub const WARM_RADIUS: u16 = 2;
+
+// Claims count toward the target this long
+const CLAIM_WINDOW: Duration = Duration::from_secs(60);
+
+/// Idle rooms and recent claims, by template.
+#[derive(Default)]
+pub struct RoomPools {
+    pools: Mutex<HashMap<String, Pool>>,
+}
+
+#[derive(Default)]
+struct Pool {
+    // Oldest first, handed out first
+    idle: VecDeque<String>,
+    claims: VecDeque<Instant>,
+}
+
+impl RoomPools {
+    /// Counts a request for a room of `template`, pooled or not.
+    pub fn claimed(&self, template: &str, now: Instant) {
+        let mut pools = self.pools.lock().unwrap();
+        let pool = pools.entry(template.to_string()).or_default();
+        pool.claims.push_back(now);
+    }
+
+    /// The oldest idle room of `template`, out of the pool.
+    pub fn take(&self, template: &str) -> Option<String> {
+        let mut pools = self.pools.lock().unwrap();
+        pools.get_mut(template)?.idle.pop_front()
+    }
+
+    pub fn add(&self, template: &str, room: String) {
+        let mut pools = self.pools.lock().unwrap();
+        let pool = pools.entry(template.to_string()).or_default();
+        pool.idle.push_back(room);
+    }
+
+    /// The newest idle room, out of the pool, to shrink it.
+    pub fn trim(&self, template: &str) -> Option<String> {
+        let mut pools = self.pools.lock().unwrap();
+        pools.get_mut(template)?.idle.pop_back()
+    }
+
+    pub fn idle(&self, template: &str) -> usize {
+        let pools = self.pools.lock().unwrap();
+        pools.get(template).map_or(0, |pool| pool.idle.len())
+    }
+
+    /// Idle rooms `template` should have: its claims of the last minute,
+    /// between `min` and `max`.
+    pub fn target(&self, template: &str, min: usize, max: usize, now: Instant) -> usize {
+        let mut pools = self.pools.lock().unwrap();
+        let pool = pools.entry(template.to_string()).or_default();
+        while let Some(&at) = pool.claims.front() {
+            if now.duration_since(at) < CLAIM_WINDOW {
+                break;
+            }
+            pool.claims.pop_front();
+        }
+        pool.claims.len().clamp(min, max.max(min))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hands_out_idle_rooms_and_scales_with_claims() {
+        let pools = RoomPools::default();
+        let start = Instant::now();
+        assert_eq!(pools.target("duel", 2, 5, start), 2);
+        pools.add("duel", "duel-1".into());
+        pools.add("duel", "duel-2".into());
+        assert_eq!(pools.idle("duel"), 2);
+
+        assert_eq!(pools.take("duel").as_deref(), Some("duel-1"));
+        assert_eq!(pools.trim("duel").as_deref(), Some("duel-2"));
+        assert_eq!(pools.take("duel"), None);
+        assert_eq!(pools.take("other"), None);
+
+        // More claims, more rooms, up to the max
+        for _ in 0..4 {
+            pools.claimed("duel", start);
+        }
+        assert_eq!(pools.target("duel", 2, 5, start), 4);
+        for _ in 0..4 {
+            pools.claimed("duel", start);
+        }
+        assert_eq!(pools.target("duel", 2, 5, start), 5);
+        // And back down once they're a minute old
+        let later = start + CLAIM_WINDOW;
+        assert_eq!(pools.target("duel", 2, 5, later), 2);
+    }
+}
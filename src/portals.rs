@@ -0,0 +1,184 @@
+//! Portals: boxes of blocks in a world or room that send players walking
+//! into them elsewhere, configured in a TOML file (`TELEBOXEL_PORTALS`):
+//!
+//! ```toml
+//! [[portal]]
+//! room = "main"
+//! min = [0, 40, 0]
+//! max = [2, 42, 0]
+//! target = "arena"       # a room, `main` for the main world
+//! spawn = [10, 40, 10]   # where they land, else where they were
+//!
+//! [[portal]]
+//! room = "arena"
+//! min = [-4, 40, -4]
+//! max = [-4, 41, -4]
+//! address = "ws://eu.example.com:3000/"  # another server
+//! carry = ["name"]
+//! ```
+//!
+//! A room target works like `JoinRoom`. An `address` target gets the player
+//! a `TRANSFER` message with a resume token for that server (it needs the
+//! same `TELEBOXEL_RESUME_SECRET`, see `resume.rs`) and closes the
+//! connection. `carry` picks what goes along, by default everything:
+//! `name` (and with it the player record), `position` and `interest`.
+
+use crate::{claims::BlockPos, resume::Session};
+use serde::Deserialize;
+use std::{error::Error, fs, path::Path, sync::Arc};
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct PortalsFile {
+    #[serde(default)]
+    portal: Vec<Portal>,
+}
+
+#[derive(Default)]
+pub struct Portals {
+    portals: Vec<Arc<Portal>>,
+}
+
+impl Portals {
+    /// See the module docs for the file format.
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    fn parse(text: &str) -> Result<Self, Box<dyn Error>> {
+        let file: PortalsFile = toml::from_str(text)?;
+        let mut portals = Vec::new();
+        for (i, portal) in file.portal.into_iter().enumerate() {
+            if portal.target.is_some() == portal.address.is_some() {
+                return Err(format!("portal {i}: needs one of target and address").into());
+            }
+            portals.push(Arc::new(portal));
+        }
+        Ok(Self { portals })
+    }
+
+    /// The portal in `room` a player moving `from` -> `to` walked into.
+    pub fn entered(&self, room: &str, from: BlockPos, to: BlockPos) -> Option<&Arc<Portal>> {
+        self.portals
+            .iter()
+            .find(|p| p.room == room && p.contains(to) && !p.contains(from))
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Carry {
+    Name,
+    Position,
+    Interest,
+}
+
+/// Inclusive box of blocks.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Portal {
+    pub room: String,
+    pub min: BlockPos,
+    pub max: BlockPos,
+    target: Option<String>,
+    address: Option<String>,
+    spawn: Option<BlockPos>,
+    #[serde(default = "carry_all")]
+    carry: Vec<Carry>,
+}
+
+/// Where a portal leads.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Destination<'a> {
+    /// A room on this server, `None` for the main world.
+    Room(Option<&'a str>),
+    /// Another server's websocket address.
+    Server(&'a str),
+}
+
+fn carry_all() -> Vec<Carry> {
+    vec![Carry::Name, Carry::Position, Carry::Interest]
+}
+
+impl Portal {
+    pub fn contains(&self, (x, y, z): BlockPos) -> bool {
+        (self.min.0..=self.max.0).contains(&x)
+            && (self.min.1..=self.max.1).contains(&y)
+            && (self.min.2..=self.max.2).contains(&z)
+    }
+
+    pub fn destination(&self) -> Destination<'_> {
+        match (&self.target, &self.address) {
+            (_, Some(address)) => Destination::Server(address),
+            (target, None) => Destination::Room(target.as_deref().filter(|t| *t != "main")),
+        }
+    }
+
+    /// What of `session` goes through, landing at `spawn` if set.
+    pub fn carry(&self, session: Session) -> Session {
+        let carries = |c| self.carry.contains(&c);
+        Session {
+            name: session.name.filter(|_| carries(Carry::Name)),
+            position: self
+                .spawn
+                .or(session.position.filter(|_| carries(Carry::Position))),
+            interest: session.interest.filter(|_| carries(Carry::Interest)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_entered_portals_and_filters_sessions() {
+        let portals = Portals::parse(
+            r#"
+            [[portal]]
+            room = "main"
+            min = [0, 40, 0]
+            max = [2, 41, 0]
+            target = "arena"
+            spawn = [10, 40, 10]
+
+            [[portal]]
+            room = "arena"
+            min = [5, 40, 5]
+            max = [5, 40, 5]
+            address = "ws://eu:3000/"
+            carry = ["name"]
+            "#,
+        )
+        .unwrap();
+
+        let portal = portals.entered("main", (0, 40, -1), (1, 41, 0)).unwrap();
+        assert_eq!(portal.destination(), Destination::Room(Some("arena")));
+        // Already inside, or another room's
+        assert!(portals.entered("main", (0, 40, 0), (1, 40, 0)).is_none());
+        assert!(portals.entered("arena", (0, 40, -1), (1, 41, 0)).is_none());
+
+        let session = Session {
+            name: Some("bob".into()),
+            position: Some((1, 41, 0)),
+            interest: Some(((0, 2, 0), 4)),
+        };
+        assert_eq!(portal.carry(session.clone()).position, Some((10, 40, 10)));
+
+        let portal = portals.entered("arena", (5, 40, 4), (5, 40, 5)).unwrap();
+        assert_eq!(portal.destination(), Destination::Server("ws://eu:3000/"));
+        let carried = portal.carry(session);
+        assert_eq!(
+            carried,
+            Session {
+                name: Some("bob".into()),
+                position: None,
+                interest: None,
+            }
+        );
+
+        let both = "[[portal]]\nroom = \"main\"\nmin = [0, 0, 0]\nmax = [0, 0, 0]\n\
+                    target = \"a\"\naddress = \"ws://b/\"";
+        assert!(Portals::parse(both).is_err());
+    }
+}
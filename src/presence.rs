@@ -0,0 +1,497 @@
+//! Online presence of named players: which room they're in and since when,
+//! for launchers and companion apps showing "friends online".
+//!
+//! Each player picks who sees them with `Presence everyone|friends|nobody`
+//! (`friends` by default) and keeps a friend list with `FriendAdd` /
+//! `FriendRemove`. Friends are one way: listing someone lets them see you,
+//! and your friends view shows the listed players who let you see them.
+//! Both are saved as the `presence` and `friends` player record properties.
+//!
+//! The HTTP API is mounted under `/presence` when `TELEBOXEL_PRESENCE_TOKEN`
+//! is set. Callers are trusted backends (`Authorization: Bearer <token>`)
+//! that vouch for the viewer:
+//!
+//! - `GET /presence/players/{name}?viewer=<name>`: the player's status, 404
+//!   when offline or hidden from the viewer
+//! - `GET /presence/friends/{name}`: friends online, as `name` sees them
+//! - `GET /presence/friends/{name}/subscribe`: websocket, a `snapshot` of
+//!   friends online, then `online` (joins, room changes) and `offline`
+//!   updates as JSON text messages
+
+use crate::{admin, storage::Storage};
+use axum::{
+    Json, Router,
+    extract::{Path, Query, Request, State},
+    http::{StatusCode, header},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use fastwebsockets::{Frame, OpCode, Payload, WebSocketError, upgrade};
+use serde::Serialize;
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt,
+    str::FromStr,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::broadcast;
+
+/// Longest friend list.
+pub const MAX_FRIENDS: usize = 100;
+
+// Presence changes buffered for slow subscribers, which resync when they lag
+const CHANGE_BUFFER: usize = 256;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Visibility {
+    Everyone,
+    /// Only players on the friend list.
+    #[default]
+    Friends,
+    Nobody,
+}
+
+impl fmt::Display for Visibility {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Visibility::Everyone => write!(f, "everyone"),
+            Visibility::Friends => write!(f, "friends"),
+            Visibility::Nobody => write!(f, "nobody"),
+        }
+    }
+}
+
+impl FromStr for Visibility {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "everyone" => Ok(Visibility::Everyone),
+            "friends" => Ok(Visibility::Friends),
+            "nobody" => Ok(Visibility::Nobody),
+            _ => Err("Expected everyone, friends or nobody".to_string()),
+        }
+    }
+}
+
+#[derive(Clone, Default, Debug, PartialEq, Eq)]
+pub struct Privacy {
+    pub visibility: Visibility,
+    pub friends: BTreeSet<String>,
+}
+
+impl Privacy {
+    /// From the `presence` and `friends` (comma separated) record
+    /// properties, the defaults when missing or malformed.
+    pub fn from_properties(properties: &HashMap<String, String>) -> Self {
+        let visibility = properties
+            .get("presence")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or_default();
+        let friends = properties
+            .get("friends")
+            .map(|f| f.split(',').filter(|n| !n.is_empty()).map(String::from))
+            .into_iter()
+            .flatten()
+            .take(MAX_FRIENDS)
+            .collect();
+        Self {
+            visibility,
+            friends,
+        }
+    }
+
+    pub fn to_properties(&self) -> HashMap<String, String> {
+        let friends: Vec<_> = self.friends.iter().map(String::as_str).collect();
+        HashMap::from([
+            ("presence".to_string(), self.visibility.to_string()),
+            ("friends".to_string(), friends.join(",")),
+        ])
+    }
+
+    // Whether `viewer` (`None` for anyone) sees `name`, who has these settings
+    fn shows(&self, name: &str, viewer: Option<&str>) -> bool {
+        match (self.visibility, viewer) {
+            (_, Some(viewer)) if viewer == name => true,
+            (Visibility::Everyone, _) => true,
+            (Visibility::Friends, Some(viewer)) => self.friends.contains(viewer),
+            _ => false,
+        }
+    }
+}
+
+/// Where an online player is. Times are Unix seconds.
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct Status {
+    pub name: String,
+    /// `main` for the main world.
+    pub room: String,
+    /// In this room since.
+    pub since: u64,
+    pub online_since: u64,
+    /// Not playing for a while, see `afk.rs`.
+    pub afk: bool,
+}
+
+struct Entry {
+    status: Status,
+    privacy: Privacy,
+    // The connection that owns the entry, newer ones with the same name
+    // take it over
+    connection: u64,
+}
+
+/// Online named players, shared by connections and the HTTP API.
+pub struct Presence {
+    online: Mutex<HashMap<String, Entry>>,
+    connections: AtomicU64,
+    // Names whose status or settings changed
+    changes: broadcast::Sender<String>,
+}
+
+impl Default for Presence {
+    fn default() -> Self {
+        Self {
+            online: Mutex::default(),
+            connections: AtomicU64::new(0),
+            changes: broadcast::Sender::new(CHANGE_BUFFER),
+        }
+    }
+}
+
+impl Presence {
+    /// `name` came online in `room`, until the returned `Online` drops.
+    pub fn join(self: &Arc<Self>, name: &str, room: &str, privacy: Privacy) -> Online {
+        let connection = self.connections.fetch_add(1, Ordering::Relaxed);
+        let now = now();
+        let status = Status {
+            name: name.to_string(),
+            room: room.to_string(),
+            since: now,
+            online_since: now,
+            afk: false,
+        };
+        let entry = Entry {
+            status,
+            privacy,
+            connection,
+        };
+        self.online.lock().unwrap().insert(name.to_string(), entry);
+        self.changes.send(name.to_string()).ok();
+        Online {
+            presence: self.clone(),
+            name: name.to_string(),
+            connection,
+        }
+    }
+
+    /// `name`'s status as `viewer` (`None` for anyone) sees it, `None` when
+    /// offline or hidden.
+    pub fn get(&self, name: &str, viewer: Option<&str>) -> Option<Status> {
+        let online = self.online.lock().unwrap();
+        let entry = online.get(name)?;
+        entry
+            .privacy
+            .shows(name, viewer)
+            .then(|| entry.status.clone())
+    }
+
+    /// Settings of an online player.
+    pub fn privacy(&self, name: &str) -> Option<Privacy> {
+        let online = self.online.lock().unwrap();
+        online.get(name).map(|entry| entry.privacy.clone())
+    }
+
+    /// Players on `viewer`'s friend list that are online and let them see it.
+    pub fn friends(&self, viewer: &str, friends: &BTreeSet<String>) -> Vec<Status> {
+        friends
+            .iter()
+            .filter_map(|friend| self.get(friend, Some(viewer)))
+            .collect()
+    }
+
+    /// Marks `name` AFK in `room`, or not, from the world they're in.
+    pub fn set_afk(&self, name: &str, room: &str, afk: bool) {
+        let mut online = self.online.lock().unwrap();
+        if let Some(entry) = online.get_mut(name)
+            && entry.status.room == room
+            && entry.status.afk != afk
+        {
+            entry.status.afk = afk;
+            self.changes.send(name.to_string()).ok();
+        }
+    }
+
+    /// Names as their status or settings change.
+    pub fn subscribe(&self) -> broadcast::Receiver<String> {
+        self.changes.subscribe()
+    }
+
+    // Runs `f` on the entry if `connection` still owns it
+    fn update(&self, name: &str, connection: u64, f: impl FnOnce(&mut Entry)) {
+        let mut online = self.online.lock().unwrap();
+        if let Some(entry) = online.get_mut(name)
+            && entry.connection == connection
+        {
+            f(entry);
+            self.changes.send(name.to_string()).ok();
+        }
+    }
+}
+
+/// A connection's presence, offline once dropped.
+pub struct Online {
+    presence: Arc<Presence>,
+    name: String,
+    connection: u64,
+}
+
+impl Online {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn moved(&self, room: &str) {
+        self.presence.update(&self.name, self.connection, |entry| {
+            entry.status.room = room.to_string();
+            entry.status.since = now();
+            entry.status.afk = false;
+        });
+    }
+
+    pub fn privacy(&self) -> Privacy {
+        self.presence.privacy(&self.name).unwrap_or_default()
+    }
+
+    pub fn set_privacy(&self, privacy: Privacy) {
+        self.presence
+            .update(&self.name, self.connection, |entry| entry.privacy = privacy);
+    }
+}
+
+impl Drop for Online {
+    fn drop(&mut self) {
+        let mut online = self.presence.online.lock().unwrap();
+        if online
+            .get(&self.name)
+            .is_some_and(|entry| entry.connection == self.connection)
+        {
+            online.remove(&self.name);
+            self.presence.changes.send(self.name.clone()).ok();
+        }
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[derive(Clone)]
+pub struct PresenceState {
+    pub token: Arc<str>,
+    pub presence: Arc<Presence>,
+    /// Friend lists of players that aren't online.
+    pub storage: Option<Arc<dyn Storage>>,
+}
+
+pub fn router(state: PresenceState) -> Router {
+    Router::new()
+        .route("/players/{name}", get(player))
+        .route("/friends/{name}", get(friends))
+        .route("/friends/{name}/subscribe", get(subscribe))
+        .route_layer(middleware::from_fn_with_state(state.clone(), require_token))
+        .with_state(state)
+}
+
+async fn require_token(State(state): State<PresenceState>, req: Request, next: Next) -> Response {
+    let authorized = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .is_some_and(|token| admin::matches(token, &state.token));
+    if !authorized {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    next.run(req).await
+}
+
+// GET /presence/players/{name}?viewer=<name>
+async fn player(
+    State(state): State<PresenceState>,
+    Path(name): Path<String>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Response {
+    let viewer = params.get("viewer").map(String::as_str);
+    match state.presence.get(&name, viewer) {
+        Some(status) => Json(status).into_response(),
+        None => (StatusCode::NOT_FOUND, "Offline").into_response(),
+    }
+}
+
+// GET /presence/friends/{name}
+async fn friends(State(state): State<PresenceState>, Path(name): Path<String>) -> Response {
+    match friend_list(&state, &name).await {
+        Ok(friends) => Json(state.presence.friends(&name, &friends)).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e).into_response(),
+    }
+}
+
+// The online player's list, else the saved one
+async fn friend_list(state: &PresenceState, name: &str) -> Result<BTreeSet<String>, String> {
+    if let Some(privacy) = state.presence.privacy(name) {
+        return Ok(privacy.friends);
+    }
+    let Some(storage) = &state.storage else {
+        return Ok(BTreeSet::new());
+    };
+    let record = storage.load_player(name).await.map_err(|e| e.to_string())?;
+    Ok(record.map_or_else(BTreeSet::new, |r| {
+        Privacy::from_properties(&r.properties).friends
+    }))
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Update<'a> {
+    Snapshot { friends: Vec<Status> },
+    Online(Status),
+    Offline { name: &'a str },
+}
+
+// GET /presence/friends/{name}/subscribe
+async fn subscribe(
+    State(state): State<PresenceState>,
+    Path(name): Path<String>,
+    ws: upgrade::IncomingUpgrade,
+) -> Response {
+    let (response, fut) = match ws.upgrade() {
+        Ok(upgraded) => upgraded,
+        Err(e) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    };
+    tokio::spawn(async move {
+        if let Err(e) = watch_friends(state, name, fut).await {
+            eprintln!("Presence subscriber: {e}");
+        }
+    });
+    response.into_response()
+}
+
+async fn watch_friends(
+    state: PresenceState,
+    name: String,
+    fut: upgrade::UpgradeFut,
+) -> Result<(), WebSocketError> {
+    let mut ws = fut.await?;
+    ws.set_auto_close(true);
+    ws.set_auto_pong(true);
+    let mut changes = state.presence.subscribe();
+    // What the subscriber was last told, per friend
+    let mut seen: HashMap<String, Status> = HashMap::new();
+    let mut friends = BTreeSet::new();
+    let mut resync = true;
+
+    loop {
+        if resync {
+            friends = friend_list(&state, &name).await.unwrap_or_default();
+            let online = state.presence.friends(&name, &friends);
+            seen = online.iter().map(|s| (s.name.clone(), s.clone())).collect();
+            send(&mut ws, &Update::Snapshot { friends: online }).await?;
+            resync = false;
+        }
+
+        tokio::select! {
+            frame = ws.read_frame() => {
+                if frame?.opcode == OpCode::Close {
+                    return Ok(());
+                }
+            }
+            changed = changes.recv() => match changed {
+                // Their own list changed
+                Ok(changed) if changed == name => resync = true,
+                Ok(changed) if friends.contains(&changed) => {
+                    let status = state.presence.get(&changed, Some(&name));
+                    match status {
+                        Some(status) if seen.get(&changed) != Some(&status) => {
+                            seen.insert(changed, status.clone());
+                            send(&mut ws, &Update::Online(status)).await?;
+                        }
+                        None if seen.remove(&changed).is_some() => {
+                            send(&mut ws, &Update::Offline { name: &changed }).await?;
+                        }
+                        _ => {}
+                    }
+                }
+                Ok(_) => {}
+                Err(broadcast::error::RecvError::Lagged(_)) => resync = true,
+                Err(broadcast::error::RecvError::Closed) => return Ok(()),
+            },
+        }
+    }
+}
+
+async fn send<S>(
+    ws: &mut fastwebsockets::WebSocket<S>,
+    update: &Update<'_>,
+) -> Result<(), WebSocketError>
+where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+{
+    let json = serde_json::to_string(update).unwrap();
+    ws.write_frame(Frame::text(Payload::from(json.as_bytes())))
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tracks_players_as_their_settings_allow() {
+        let presence = Arc::new(Presence::default());
+        let mut changes = presence.subscribe();
+
+        let bob = Privacy::from_properties(&HashMap::from([
+            ("presence".to_string(), "friends".to_string()),
+            ("friends".to_string(), "alice,carol".to_string()),
+        ]));
+        assert_eq!(Privacy::from_properties(&bob.to_properties()), bob);
+        let online = presence.join("bob", "main", bob.clone());
+        assert_eq!(changes.try_recv().unwrap(), "bob");
+
+        online.moved("arena");
+        let status = presence.get("bob", Some("alice")).unwrap();
+        assert_eq!(status.room, "arena");
+        assert!(presence.get("bob", Some("dave")).is_none());
+        assert!(presence.get("bob", None).is_none());
+        let friends = BTreeSet::from(["bob".to_string(), "erin".to_string()]);
+        assert_eq!(presence.friends("alice", &friends), [status]);
+        presence.set_afk("bob", "main", true);
+        assert!(!presence.get("bob", Some("alice")).unwrap().afk);
+        presence.set_afk("bob", "arena", true);
+        assert!(presence.get("bob", Some("alice")).unwrap().afk);
+
+        online.set_privacy(Privacy {
+            visibility: Visibility::Nobody,
+            ..bob.clone()
+        });
+        assert!(presence.get("bob", Some("alice")).is_none());
+        assert!(presence.get("bob", Some("bob")).is_some());
+
+        // A second connection takes over, the first leaving doesn't log
+        // bob out
+        let again = presence.join("bob", "main", bob);
+        drop(online);
+        assert_eq!(presence.get("bob", Some("alice")).unwrap().room, "main");
+        drop(again);
+        assert!(presence.privacy("bob").is_none());
+    }
+}
@@ -0,0 +1,187 @@
+//! Profiling mode, for tick overruns: started with `--profile` (or
+//! `TELEBOXEL_PROFILE=true`), each world times the phases of its ticks and
+//! prints a breakdown every `TELEBOXEL_PROFILE_SECS` (10):
+//!
+//! ```text
+//! profile main: 600 ticks, 1.21 ms avg, 4.80 ms max, 2 over budget | messages 0.10 think 0.02 interest 0.31 encode 0.52 send 0.19 other 0.07 ms/tick
+//! ```
+//!
+//! The phases: `messages` (world messages and inputs due), `think` (entity
+//! brains), `interest` (what each player needs: chunks, static entities),
+//! `encode` (building frames), `send` (queueing them to the players) and
+//! `other` (interest cleanup, saves). Lockstep rooms count relaying as
+//! `send`.
+//!
+//! With `TELEBOXEL_PROFILE_FOLDED=<file>` each report is also appended as
+//! folded stacks, microseconds per phase (`teleboxel;main;tick;encode
+//! 31200`), which `inferno-flamegraph` or `flamegraph.pl` turn into a flame
+//! graph of where tick time goes. Off, the tick pays one branch per phase.
+
+use crate::config::ProfileConfig;
+use std::{
+    fmt::Write,
+    fs::OpenOptions,
+    io::Write as _,
+    time::{Duration, Instant},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Phase {
+    Messages,
+    Think,
+    Interest,
+    Encode,
+    Send,
+    Other,
+}
+
+const PHASES: [Phase; 6] = [
+    Phase::Messages,
+    Phase::Think,
+    Phase::Interest,
+    Phase::Encode,
+    Phase::Send,
+    Phase::Other,
+];
+
+impl Phase {
+    fn name(self) -> &'static str {
+        match self {
+            Phase::Messages => "messages",
+            Phase::Think => "think",
+            Phase::Interest => "interest",
+            Phase::Encode => "encode",
+            Phase::Send => "send",
+            Phase::Other => "other",
+        }
+    }
+}
+
+/// One world's tick timing, a no-op unless profiling is on.
+pub struct Profiler {
+    config: Option<ProfileConfig>,
+    // Since the last report
+    spent: [Duration; PHASES.len()],
+    ticks: u64,
+    total: Duration,
+    max: Duration,
+    over: u64,
+    since: Instant,
+}
+
+impl Profiler {
+    pub fn new(config: Option<ProfileConfig>) -> Self {
+        Self {
+            config,
+            spent: Default::default(),
+            ticks: 0,
+            total: Duration::ZERO,
+            max: Duration::ZERO,
+            over: 0,
+            since: Instant::now(),
+        }
+    }
+
+    /// When a phase starts, `None` when off.
+    pub fn start(&self) -> Option<Instant> {
+        self.config.as_ref().map(|_| Instant::now())
+    }
+
+    /// Adds the time since `at` to `phase`, the next phase starts now.
+    pub fn lap(&mut self, phase: Phase, at: &mut Option<Instant>) {
+        if let Some(start) = at {
+            let now = Instant::now();
+            self.spent[phase as usize] += now - *start;
+            *start = now;
+        }
+    }
+
+    /// A tick of `world` took `elapsed`, `budget` being the tick interval.
+    /// Reports when it's time.
+    pub fn tick(&mut self, world: &str, elapsed: Duration, budget: Duration) {
+        let Some(config) = &self.config else {
+            return;
+        };
+        self.ticks += 1;
+        self.total += elapsed;
+        self.max = self.max.max(elapsed);
+        self.over += (elapsed > budget) as u64;
+        if self.since.elapsed() < config.interval {
+            return;
+        }
+
+        let (summary, folded) = self.report(world);
+        eprintln!("{summary}");
+        if let Some(path) = config.folded.clone() {
+            // Off the tick, the world task doesn't do file I/O
+            tokio::task::spawn_blocking(move || {
+                let file = OpenOptions::new().create(true).append(true).open(&path);
+                if let Err(e) = file.and_then(|mut f| f.write_all(folded.as_bytes())) {
+                    eprintln!("Profile {}: {e}", path.display());
+                }
+            });
+        }
+        *self = Self::new(self.config.take());
+    }
+
+    // The summary line and the folded stacks
+    fn report(&self, world: &str) -> (String, String) {
+        let ticks = self.ticks.max(1) as f64;
+        let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+        let mut summary = format!(
+            "profile {world}: {} ticks, {:.2} ms avg, {:.2} ms max, {} over budget |",
+            self.ticks,
+            ms(self.total) / ticks,
+            ms(self.max),
+            self.over,
+        );
+        let mut folded = String::new();
+        for phase in PHASES {
+            let spent = self.spent[phase as usize];
+            write!(summary, " {} {:.2}", phase.name(), ms(spent) / ticks).unwrap();
+            let us = spent.as_micros();
+            if us > 0 {
+                writeln!(folded, "teleboxel;{world};tick;{} {us}", phase.name()).unwrap();
+            }
+        }
+        summary += " ms/tick";
+        (summary, folded)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_phases_per_tick_and_as_folded_stacks() {
+        let mut off = Profiler::new(None);
+        let mut at = off.start();
+        assert_eq!(at, None);
+        off.lap(Phase::Send, &mut at);
+        off.tick("main", Duration::from_millis(50), Duration::from_millis(16));
+        assert_eq!(off.ticks, 0);
+
+        let config = ProfileConfig {
+            interval: Duration::from_secs(3600),
+            folded: None,
+        };
+        let mut profiler = Profiler::new(Some(config));
+        profiler.spent[Phase::Encode as usize] = Duration::from_millis(3);
+        profiler.spent[Phase::Messages as usize] = Duration::from_micros(500);
+        let budget = Duration::from_millis(16);
+        profiler.tick("main", Duration::from_millis(2), budget);
+        profiler.tick("main", Duration::from_millis(20), budget);
+
+        let (summary, folded) = profiler.report("main");
+        assert_eq!(
+            summary,
+            "profile main: 2 ticks, 11.00 ms avg, 20.00 ms max, 1 over budget | messages 0.25 \
+             think 0.00 interest 0.00 encode 1.50 send 0.00 other 0.00 ms/tick"
+        );
+        assert_eq!(
+            folded,
+            "teleboxel;main;tick;messages 500\nteleboxel;main;tick;encode 3000\n"
+        );
+    }
+}
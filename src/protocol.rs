@@ -0,0 +1,993 @@
+//! Binary protocol (see `docs/protocol-draft.txt`), all fields LE.
+//!
+//! Message ids, field layouts and the decoders are generated by the build
+//! script (`build/`) from `schema/protocol.toml`, the source of truth for the wire format.
+//! Server frames are `u8 0x10`, `u32 tick`, `u8 submsg_count`, then the
+//! submessages back to back. Only the chunk and block registry submessages
+//! exist so far.
+//!
+//! Clients that ask for the `teleboxel.json` websocket subprotocol get the
+//! same messages as JSON text instead (`JsonMessage`), for tooling and
+//! prototyping. Commands are text in both modes.
+
+use crate::{
+    blocks::{BlockDef, BlockRegistry},
+    chunk::{Chunk, ChunkPos},
+    chunk_wire::{self, ChunkFormat, WireError},
+    claims::BlockPos,
+    coords::Coords,
+    effects::Effect,
+    entities::{Attachment, Entity, Mount, Parent},
+    interp::InterpHint,
+    lockstep::{LockstepInput, LockstepTick},
+    minimap::Minimap,
+    origin,
+    rollback::Confirmed,
+};
+use bytes::{Bytes, BytesMut};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+include!(concat!(env!("OUT_DIR"), "/protocol.rs"));
+
+const FRAME_HEADER_LEN: usize = 6;
+// Bytes `FramePool` allocates its arenas in, and how many full ones it
+// keeps for when their frames are written
+const ARENA_LEN: usize = 64 * 1024;
+const FULL_ARENAS: usize = 8;
+
+/// One block change: (index in the chunk, new block id).
+pub type BlockEdit = (u16, u16);
+
+/// What a position in a submessage is, see `coord` in the schema.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Coord {
+    Chunk,
+    Block,
+    Offset,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ProtocolError {
+    Truncated,
+    UnknownFrame(u8),
+    UnknownSubmessage(u8),
+    Chunk(WireError),
+    Invalid(&'static str),
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProtocolError::Truncated => write!(f, "frame is truncated"),
+            ProtocolError::UnknownFrame(t) => write!(f, "unknown frame type {t:#04x}"),
+            ProtocolError::UnknownSubmessage(k) => write!(f, "unknown submessage {k:#04x}"),
+            ProtocolError::Chunk(e) => write!(f, "{e}"),
+            ProtocolError::Invalid(why) => write!(f, "invalid frame: {why}"),
+        }
+    }
+}
+
+impl std::error::Error for ProtocolError {}
+
+/// Builds one server frame. At most 254 submessages fit, check `is_full`:
+/// the last one is kept for `rebase`.
+#[derive(Clone)]
+pub struct ServerFrame {
+    buf: Vec<u8>,
+    count: u8,
+    chunk_format: ChunkFormat,
+    // Submessage ids and where each starts, for traffic stats
+    starts: Vec<(u8, usize)>,
+}
+
+impl ServerFrame {
+    pub fn new(tick: u32) -> Self {
+        Self::with_chunk_format(tick, ChunkFormat::default())
+    }
+
+    /// Snapshots use `chunk_format` (the world's setting).
+    pub fn with_chunk_format(tick: u32, chunk_format: ChunkFormat) -> Self {
+        Self::start(Vec::with_capacity(256), Vec::new(), tick, chunk_format)
+    }
+
+    // Into `buf` and `starts`, emptied
+    fn start(
+        mut buf: Vec<u8>,
+        mut starts: Vec<(u8, usize)>,
+        tick: u32,
+        chunk_format: ChunkFormat,
+    ) -> Self {
+        buf.clear();
+        starts.clear();
+        buf.push(SERVER_FRAME);
+        buf.extend_from_slice(&tick.to_le_bytes());
+        buf.push(0);
+        Self {
+            buf,
+            count: 0,
+            chunk_format,
+            starts,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.count >= u8::MAX - 1
+    }
+
+    pub fn chunk_snapshot(&mut self, pos: ChunkPos, version: u32, chunk: &Chunk) {
+        self.begin(CHUNK_SNAPSHOT);
+        write_chunk_snapshot(&mut self.buf, pos, version, chunk, self.chunk_format);
+    }
+
+    /// `edits` must fit a `u16` count (at most one per block and tick).
+    pub fn chunk_delta(
+        &mut self,
+        pos: ChunkPos,
+        base_version: u32,
+        version: u32,
+        edits: &[BlockEdit],
+    ) {
+        self.begin(CHUNK_DELTA);
+        write_chunk_delta(&mut self.buf, pos, base_version, version, edits.iter());
+    }
+
+    pub fn block_registry(&mut self, registry: &BlockRegistry) {
+        self.begin(BLOCK_REGISTRY);
+        // Lengths are checked when the registry loads
+        write_block_registry(&mut self.buf, registry.iter());
+    }
+
+    /// Strings past 255 bytes (the `str` limit) are cut.
+    pub fn chat(&mut self, from: &str, text: &str) {
+        self.begin(CHAT);
+        write_chat(&mut self.buf, cut(from), cut(text));
+    }
+
+    /// `address` past 255 bytes is cut.
+    pub fn drain(&mut self, seconds: u16, address: &str) {
+        self.begin(DRAIN);
+        write_drain(&mut self.buf, seconds, cut(address));
+    }
+
+    /// Tokens from `resume.rs` always fit.
+    pub fn resume(&mut self, token: &str) {
+        self.begin(RESUME);
+        write_resume(&mut self.buf, cut(token));
+    }
+
+    /// Room names are at most 32 bytes.
+    pub fn room(&mut self, room: &str, id: u32) {
+        self.begin(ROOM);
+        write_room(&mut self.buf, cut(room), id);
+    }
+
+    pub fn transfer(&mut self, address: &str, token: &str) {
+        self.begin(TRANSFER);
+        write_transfer(&mut self.buf, cut(address), cut(token));
+    }
+
+    /// Inputs are at most 200 bytes, and there's at most one per player.
+    pub fn lockstep(&mut self, tick: &LockstepTick) {
+        self.begin(LOCKSTEP);
+        write_lockstep(&mut self.buf, tick.tick, tick.hash, tick.inputs.iter());
+    }
+
+    pub fn lockstep_state(&mut self, tick: u32, state: &[u8]) {
+        self.begin(LOCKSTEP_STATE);
+        write_lockstep_state(&mut self.buf, tick, state.iter());
+    }
+
+    /// Delays are at most `rollback::MAX_DELAY`.
+    pub fn rollback(&mut self, frame: u32, delay: u32) {
+        self.begin(ROLLBACK);
+        write_rollback(&mut self.buf, frame, delay as u8);
+    }
+
+    /// Inputs are at most 200 bytes.
+    pub fn rollback_input(&mut self, player: u32, frame: u32, data: &str) {
+        self.begin(ROLLBACK_INPUT);
+        write_rollback_input(&mut self.buf, player, frame, data);
+    }
+
+    /// Like `lockstep`.
+    pub fn rollback_confirm(&mut self, confirmed: &Confirmed) {
+        self.begin(ROLLBACK_CONFIRM);
+        let inputs = confirmed.inputs.iter();
+        write_rollback_confirm(&mut self.buf, confirmed.frame, confirmed.hash, inputs);
+    }
+
+    /// `data` comes from a `RELAY_SEND`, so it fits.
+    pub fn relay(&mut self, from: u32, data: &[u8]) {
+        self.begin(RELAY);
+        write_relay(&mut self.buf, from, data.iter());
+    }
+
+    /// `data` comes from a `VOICE_SEND`, so it fits.
+    pub fn voice(&mut self, from: u32, distance: u16, occlusion: u8, data: &[u8]) {
+        self.begin(VOICE);
+        write_voice(&mut self.buf, from, distance, occlusion, data.iter());
+    }
+
+    pub fn teleport(&mut self, (x, y, z): (i32, i32, i32)) {
+        self.begin(TELEPORT);
+        write_teleport(&mut self.buf, x, y, z);
+    }
+
+    pub fn features(&mut self, chat: bool, build: bool, pvp: bool) {
+        self.begin(FEATURES);
+        write_features(&mut self.buf, chat, build, pvp);
+    }
+
+    pub fn simulation(&mut self, paused: bool, scale: u16) {
+        self.begin(SIMULATION);
+        write_simulation(&mut self.buf, paused, scale);
+    }
+
+    pub fn achievement(&mut self, name: &str, title: &str) {
+        self.begin(ACHIEVEMENT);
+        write_achievement(&mut self.buf, cut(name), cut(title));
+    }
+
+    pub fn interp_hint(&mut self, hint: &InterpHint) {
+        self.begin(INTERP_HINT);
+        write_interp_hint(&mut self.buf, hint.update_ms, hint.delay_ms, hint.buffer);
+    }
+
+    pub fn minimap(&mut self, map: &Minimap) {
+        self.begin(MINIMAP);
+        write_minimap(
+            &mut self.buf,
+            map.x,
+            map.bottom,
+            map.z,
+            map.scale,
+            map.size,
+            map.heights.iter(),
+            map.colors.iter(),
+        );
+    }
+
+    pub fn time_skip(&mut self, ticks: u32) {
+        self.begin(TIME_SKIP);
+        write_time_skip(&mut self.buf, ticks);
+    }
+
+    pub fn coords(&mut self, coords: Coords) {
+        self.begin(COORDS);
+        write_coords(&mut self.buf, coords.flat, coords.units);
+    }
+
+    pub fn entity(&mut self, entity: &Entity) {
+        let (x, y, z) = entity.position;
+        self.begin(ENTITY);
+        write_entity(
+            &mut self.buf,
+            entity.id,
+            x,
+            y,
+            z,
+            entity.authority.unwrap_or(0),
+        );
+    }
+
+    pub fn entity_gone(&mut self, id: u32) {
+        self.begin(ENTITY_GONE);
+        write_entity_gone(&mut self.buf, id);
+    }
+
+    pub fn mount(&mut self, mount: &Mount) {
+        let (x, y, z) = mount.offset;
+        self.begin(MOUNT);
+        write_mount(&mut self.buf, mount.player, mount.entity, x, y, z);
+    }
+
+    pub fn dismount(&mut self, player: u32, (x, y, z): (i32, i32, i32)) {
+        self.begin(DISMOUNT);
+        write_dismount(&mut self.buf, player, x, y, z);
+    }
+
+    pub fn attach(&mut self, entity: u32, attachment: &Attachment) {
+        let (x, y, z) = attachment.offset;
+        let (parent, player) = match attachment.parent {
+            Parent::Entity(id) => (id, false),
+            Parent::Player(id) => (id, true),
+        };
+        self.begin(ATTACH);
+        write_attach(&mut self.buf, entity, parent, player, x, y, z);
+    }
+
+    pub fn detach(&mut self, entity: &Entity) {
+        let (x, y, z) = entity.position;
+        self.begin(DETACH);
+        write_detach(&mut self.buf, entity.id, x, y, z);
+    }
+
+    /// Params are at most `effects::MAX_PARAMS_LEN`, so they fit.
+    pub fn effect(&mut self, effect: &Effect) {
+        let (x, y, z) = effect.position;
+        self.begin(EFFECT);
+        write_effect(&mut self.buf, effect.id, x, y, z, effect.params.iter());
+    }
+
+    /// `trigger` past 255 bytes is cut.
+    pub fn trigger(&mut self, trigger: &str, entered: bool) {
+        self.begin(TRIGGER);
+        write_trigger(&mut self.buf, cut(trigger), entered);
+    }
+
+    pub fn input_ack(&mut self, seq: u32) {
+        self.begin(INPUT_ACK);
+        write_input_ack(&mut self.buf, seq);
+    }
+
+    pub fn time_sync(
+        &mut self,
+        client_time: u32,
+        server_time: u32,
+        tick_hz: u16,
+        interp_delay: u16,
+    ) {
+        self.begin(TIME_SYNC);
+        write_time_sync(
+            &mut self.buf,
+            client_time,
+            server_time,
+            tick_hz,
+            interp_delay,
+        );
+    }
+
+    /// Positions made relative to `origin`, a chunk corner, for a client
+    /// that asked for them (see `origin.rs`): shifted in place, with a
+    /// `REBASE` first. Frames without positions are left as they are.
+    /// Mount and attachment offsets aren't positions.
+    pub fn rebase(&mut self, origin: BlockPos) {
+        let chunk = origin::chunk(origin);
+        let mut shifted = false;
+        for &(kind, start) in &self.starts {
+            // Offsets are from somewhere else
+            for &(at, coord) in server_msg_coords(kind) {
+                let from = match coord {
+                    Coord::Chunk => chunk,
+                    Coord::Block => origin,
+                    Coord::Offset => continue,
+                };
+                let at = start + 1 + at;
+                let position = read_triple(&self.buf[at..]);
+                let (x, y, z) = origin::relative(from, position);
+                for (i, v) in [x, y, z].into_iter().enumerate() {
+                    self.buf[at + 4 * i..at + 4 * i + 4].copy_from_slice(&v.to_le_bytes());
+                }
+                shifted = true;
+            }
+        }
+        if !shifted {
+            return;
+        }
+        // Written at the end and rotated to the front, in the frame's buffer
+        let end = self.buf.len();
+        self.buf.push(REBASE);
+        write_rebase(&mut self.buf, origin.0, origin.1, origin.2);
+        let len = self.buf.len() - end;
+        self.buf[FRAME_HEADER_LEN..].rotate_right(len);
+        for (_, start) in &mut self.starts {
+            *start += len;
+        }
+        self.starts.insert(0, (REBASE, FRAME_HEADER_LEN));
+        self.count += 1;
+    }
+
+    /// Puts positions the way `coords` has them (see `coords.rs`): block
+    /// positions and offsets in units, and for flat worlds without z and
+    /// without chunks off chunk z 0. After `rebase`, its `REBASE` stays.
+    pub fn apply_coords(&mut self, coords: Coords) {
+        if coords.is_plain() {
+            return;
+        }
+        let mut buf = self.buf[..FRAME_HEADER_LEN].to_vec();
+        let mut starts = Vec::with_capacity(self.starts.len());
+        let ends = self.starts.iter().skip(1).map(|&(_, at)| at);
+        let ends = ends.chain([self.buf.len()]);
+        for (&(kind, start), end) in self.starts.iter().zip(ends) {
+            let msg = &self.buf[start..end];
+            let positions = server_msg_coords(kind);
+            let off_plane = positions
+                .iter()
+                .any(|&(at, coord)| coord == Coord::Chunk && read_triple(&msg[1 + at..]).2 != 0);
+            if coords.flat && off_plane {
+                self.count -= 1;
+                continue;
+            }
+            starts.push((kind, buf.len()));
+            let mut copied = 0;
+            for &(at, coord) in positions {
+                let at = 1 + at;
+                buf.extend_from_slice(&msg[copied..at]);
+                let mut position = read_triple(&msg[at..]);
+                if coord != Coord::Chunk {
+                    position = coords.scale(position);
+                }
+                let axes = if coords.flat { 2 } else { 3 };
+                for v in [position.0, position.1, position.2].into_iter().take(axes) {
+                    buf.extend_from_slice(&v.to_le_bytes());
+                }
+                copied = at + 12;
+            }
+            buf.extend_from_slice(&msg[copied..]);
+        }
+        self.buf.clear();
+        self.buf.extend_from_slice(&buf);
+        self.starts = starts;
+    }
+
+    /// Schema name and encoded size of each submessage so far, the frame
+    /// header counted as `frame`.
+    pub fn sizes(&self) -> impl Iterator<Item = (&'static str, usize)> + '_ {
+        let ends = self.starts.iter().skip(1).map(|&(_, at)| at);
+        let ends = ends.chain([self.buf.len()]);
+        let msgs =
+            self.starts.iter().zip(ends).map(|(&(kind, start), end)| {
+                (server_msg_name(kind).unwrap_or("unknown"), end - start)
+            });
+        [("frame", FRAME_HEADER_LEN)].into_iter().chain(msgs)
+    }
+
+    pub fn finish(mut self) -> Bytes {
+        self.buf[FRAME_HEADER_LEN - 1] = self.count;
+        Bytes::from(self.buf)
+    }
+
+    fn begin(&mut self, kind: u8) {
+        assert!(!self.is_full(), "server frame is full");
+        self.count += 1;
+        self.starts.push((kind, self.buf.len()));
+        self.buf.push(kind);
+    }
+}
+
+/// Reused buffers for the frames the world builds every tick, so a steady
+/// tick encodes without allocating. Frames from `frame` are copied into an
+/// arena by `finish`: the `Bytes` handed out are views of it. A full arena
+/// waits until every connection wrote (dropped) its frames and is then
+/// used again from the start. A frame a backed up player holds on to keeps
+/// its arena (`ARENA_LEN`, or the frame if bigger) alive until written.
+#[derive(Default)]
+pub struct FramePool {
+    // Recycled frames, for their buffers
+    free: Vec<ServerFrame>,
+    arena: Option<BytesMut>,
+    // Full arenas, frames may still point into them
+    full: Vec<BytesMut>,
+}
+
+impl FramePool {
+    pub fn frame(&mut self, tick: u32, chunk_format: ChunkFormat) -> ServerFrame {
+        match self.free.pop() {
+            Some(f) => ServerFrame::start(f.buf, f.starts, tick, chunk_format),
+            None => ServerFrame::with_chunk_format(tick, chunk_format),
+        }
+    }
+
+    /// The frame's bytes. `frame` still has its `sizes`, hand it back with
+    /// `recycle` after.
+    pub fn finish(&mut self, frame: &mut ServerFrame) -> Bytes {
+        frame.buf[FRAME_HEADER_LEN - 1] = frame.count;
+        let len = frame.buf.len();
+        let arena = match self.arena.take() {
+            Some(arena) if arena.capacity() >= len => arena,
+            full => {
+                // Past that it's freed once its frames are
+                if let Some(full) = full
+                    && self.full.len() < FULL_ARENAS
+                {
+                    self.full.push(full);
+                }
+                let want = len.max(ARENA_LEN);
+                let written = self.full.iter_mut().position(|a| a.try_reclaim(want));
+                match written {
+                    Some(i) => self.full.swap_remove(i),
+                    None => BytesMut::with_capacity(want),
+                }
+            }
+        };
+        let arena = self.arena.insert(arena);
+        arena.extend_from_slice(&frame.buf);
+        arena.split().freeze()
+    }
+
+    pub fn recycle(&mut self, frame: ServerFrame) {
+        self.free.push(frame);
+    }
+}
+
+/// Builds one client frame, for client code and tests. At most 255
+/// submessages fit, check `is_full`.
+pub struct ClientFrame {
+    buf: Vec<u8>,
+    count: u8,
+}
+
+impl ClientFrame {
+    /// `seq` is the client's own counter, the server doesn't read it.
+    pub fn new(seq: u32) -> Self {
+        let mut buf = Vec::with_capacity(64);
+        buf.push(CLIENT_FRAME);
+        buf.extend_from_slice(&seq.to_le_bytes());
+        buf.push(0);
+        Self { buf, count: 0 }
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.count == u8::MAX
+    }
+
+    /// `to` must fit a `u8` count and `data` a `u16` one.
+    pub fn relay_send(&mut self, to: &[u32], data: &[u8]) {
+        self.begin(RELAY_SEND);
+        write_relay_send(&mut self.buf, to.iter(), data.iter());
+    }
+
+    /// `data` must fit a `u16` count.
+    pub fn voice_send(&mut self, data: &[u8]) {
+        self.begin(VOICE_SEND);
+        write_voice_send(&mut self.buf, data.iter());
+    }
+
+    pub fn finish(mut self) -> Bytes {
+        self.buf[FRAME_HEADER_LEN - 1] = self.count;
+        Bytes::from(self.buf)
+    }
+
+    fn begin(&mut self, kind: u8) {
+        assert!(!self.is_full(), "client frame is full");
+        self.count += 1;
+        self.buf.push(kind);
+    }
+}
+
+// Longest prefix that fits a `str`, on a char boundary
+fn cut(s: &str) -> &str {
+    let mut end = s.len().min(u8::MAX as usize);
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
+}
+
+fn write_str(buf: &mut Vec<u8>, s: &str) {
+    buf.push(s.len() as u8);
+    buf.extend_from_slice(s.as_bytes());
+}
+
+fn write_pos(buf: &mut Vec<u8>, (cx, cy, cz): ChunkPos) {
+    buf.extend_from_slice(&cx.to_le_bytes());
+    buf.extend_from_slice(&cy.to_le_bytes());
+    buf.extend_from_slice(&cz.to_le_bytes());
+}
+
+// The three i32s `data` starts with
+fn read_triple(data: &[u8]) -> (i32, i32, i32) {
+    let v = |i: usize| i32::from_le_bytes(data[4 * i..4 * i + 4].try_into().unwrap());
+    (v(0), v(1), v(2))
+}
+
+/// Decodes a server frame into its tick and submessages.
+pub fn decode_server_frame(data: &[u8]) -> Result<(u32, Vec<ServerMsg>), ProtocolError> {
+    decode_server_frame_as(data, false)
+}
+
+/// The same, of a `flat` world for a client that asked for its way, see
+/// `coords.rs`: positions without a z, which decodes as 0.
+pub fn decode_server_frame_as(
+    data: &[u8],
+    flat: bool,
+) -> Result<(u32, Vec<ServerMsg>), ProtocolError> {
+    decode_frame(data, SERVER_FRAME, |r| read_server_msg(r, flat))
+}
+
+/// Decodes a client frame into its sequence number and submessages.
+pub fn decode_client_frame(data: &[u8]) -> Result<(u32, Vec<ClientMsg>), ProtocolError> {
+    decode_frame(data, CLIENT_FRAME, read_client_msg)
+}
+
+fn decode_frame<T>(
+    data: &[u8],
+    frame: u8,
+    read_msg: impl Fn(&mut Reader) -> Result<T, ProtocolError>,
+) -> Result<(u32, Vec<T>), ProtocolError> {
+    let mut r = Reader { data, at: 0 };
+    let kind = r.u8()?;
+    if kind != frame {
+        return Err(ProtocolError::UnknownFrame(kind));
+    }
+
+    let tick = r.u32()?;
+    let count = r.u8()?;
+    let mut msgs = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let msg = read_msg(&mut r)?;
+        msgs.push(msg);
+    }
+
+    if r.at != data.len() {
+        return Err(ProtocolError::Invalid("trailing bytes"));
+    }
+    Ok((tick, msgs))
+}
+
+/// Message encoding, picked with the websocket subprotocol.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Encoding {
+    Binary,
+    Json,
+}
+
+impl Encoding {
+    pub fn subprotocol(self) -> &'static str {
+        match self {
+            Encoding::Binary => "teleboxel.binary",
+            Encoding::Json => "teleboxel.json",
+        }
+    }
+
+    /// Picks from a `Sec-WebSocket-Protocol` request header (comma separated,
+    /// in the client's order). `None` when nothing known is offered, which
+    /// means binary without echoing a subprotocol.
+    pub fn negotiate(offered: &str) -> Option<Encoding> {
+        offered.split(',').map(str::trim).find_map(|p| {
+            [Encoding::Binary, Encoding::Json]
+                .into_iter()
+                .find(|e| e.subprotocol() == p)
+        })
+    }
+}
+
+/// One text websocket message in JSON mode, tagged with `type`.
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum JsonMessage {
+    /// Handshake, the binary mode sends the bare id as text.
+    Hello { id: u32 },
+    /// The server's public key and the server key's signature over it,
+    /// before the hello on encrypted and signed sessions.
+    Key { key: String, signature: String },
+    /// A server frame.
+    Frame { tick: u32, msgs: Vec<ServerMsg> },
+    /// Reply to a text command, `detail` is the error when not `ok`.
+    Reply {
+        command: String,
+        ok: bool,
+        detail: String,
+    },
+}
+
+impl JsonMessage {
+    pub fn to_json(&self) -> String {
+        // Every field serializes, map keys are all strings
+        serde_json::to_string(self).unwrap()
+    }
+}
+
+/// Re-encodes a binary server frame as a `JsonMessage::Frame`.
+pub fn frame_to_json(data: &[u8]) -> Result<String, ProtocolError> {
+    let (tick, msgs) = decode_server_frame(data)?;
+    Ok(JsonMessage::Frame { tick, msgs }.to_json())
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    at: usize,
+}
+
+impl Reader<'_> {
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], ProtocolError> {
+        let bytes = self
+            .data
+            .get(self.at..self.at + N)
+            .ok_or(ProtocolError::Truncated)?;
+        self.at += N;
+        Ok(bytes.try_into().unwrap())
+    }
+
+    fn u8(&mut self) -> Result<u8, ProtocolError> {
+        Ok(self.take::<1>()?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, ProtocolError> {
+        Ok(u16::from_le_bytes(self.take()?))
+    }
+
+    fn u32(&mut self) -> Result<u32, ProtocolError> {
+        Ok(u32::from_le_bytes(self.take()?))
+    }
+
+    fn i32(&mut self) -> Result<i32, ProtocolError> {
+        Ok(i32::from_le_bytes(self.take()?))
+    }
+
+    fn pos(&mut self) -> Result<ChunkPos, ProtocolError> {
+        Ok((self.i32()?, self.i32()?, self.i32()?))
+    }
+
+    fn chunk(&mut self) -> Result<Chunk, ProtocolError> {
+        let (chunk, len) =
+            chunk_wire::decode_prefix(&self.data[self.at..]).map_err(ProtocolError::Chunk)?;
+        self.at += len;
+        Ok(chunk)
+    }
+
+    fn str(&mut self) -> Result<String, ProtocolError> {
+        let len = self.u8()? as usize;
+        let bytes = self
+            .data
+            .get(self.at..self.at + len)
+            .ok_or(ProtocolError::Truncated)?;
+        self.at += len;
+        String::from_utf8(bytes.to_vec()).map_err(|_| ProtocolError::Invalid("bad UTF-8"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terrain::{ChunkGenerator, FlatGenerator};
+
+    #[test]
+    fn chunk_messages_round_trip() {
+        let chunk = FlatGenerator.generate((0, 0, 0));
+        let edits = vec![(Chunk::index(1, 2, 3) as u16, 7), (0, 0)];
+
+        let mut frame = ServerFrame::new(42);
+        frame.chunk_snapshot((1, -2, 3), 5, &chunk);
+        frame.chunk_delta((1, -2, 3), 5, 9, &edits);
+        let data = frame.finish();
+
+        let (tick, msgs) = decode_server_frame(&data).unwrap();
+        assert_eq!(tick, 42);
+        assert_eq!(
+            msgs,
+            [
+                ServerMsg::ChunkSnapshot {
+                    pos: (1, -2, 3),
+                    version: 5,
+                    chunk,
+                },
+                ServerMsg::ChunkDelta {
+                    pos: (1, -2, 3),
+                    base_version: 5,
+                    version: 9,
+                    edits,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn block_registry_round_trips() {
+        let registry = BlockRegistry::default();
+        let mut frame = ServerFrame::new(0);
+        frame.block_registry(&registry);
+
+        let (_, msgs) = decode_server_frame(&frame.finish()).unwrap();
+        assert_eq!(
+            msgs,
+            [ServerMsg::BlockRegistry {
+                blocks: registry.iter().cloned().collect(),
+            }]
+        );
+    }
+
+    // The chunk and registry submessages as written by hand before the
+    // schema, kept to pin the generated layout to what clients already read
+    fn hand_written(registry: &BlockRegistry, chunk: &Chunk, edits: &[BlockEdit]) -> Vec<u8> {
+        let string = |buf: &mut Vec<u8>, s: &str| {
+            buf.push(s.len() as u8);
+            buf.extend_from_slice(s.as_bytes());
+        };
+        let mut buf = vec![0x10];
+        buf.extend_from_slice(&7u32.to_le_bytes());
+        buf.push(3);
+
+        buf.push(0x08);
+        for n in [1, -2, 3] {
+            buf.extend_from_slice(&i32::to_le_bytes(n));
+        }
+        buf.extend_from_slice(&5u32.to_le_bytes());
+        chunk_wire::encode(chunk, &mut buf);
+
+        buf.push(0x09);
+        for n in [1, -2, 3] {
+            buf.extend_from_slice(&i32::to_le_bytes(n));
+        }
+        buf.extend_from_slice(&5u32.to_le_bytes());
+        buf.extend_from_slice(&9u32.to_le_bytes());
+        buf.extend_from_slice(&(edits.len() as u16).to_le_bytes());
+        for (index, block) in edits {
+            buf.extend_from_slice(&index.to_le_bytes());
+            buf.extend_from_slice(&block.to_le_bytes());
+        }
+
+        buf.push(0x0C);
+        buf.extend_from_slice(&(registry.len() as u16).to_le_bytes());
+        for block in registry.iter() {
+            buf.extend_from_slice(&block.id.to_le_bytes());
+            buf.push(block.solid as u8);
+            string(&mut buf, &block.name);
+            buf.push(block.tags.len() as u8);
+            for tag in &block.tags {
+                string(&mut buf, tag);
+            }
+        }
+        buf
+    }
+
+    #[test]
+    fn generated_code_matches_the_hand_written_layout() {
+        let registry = BlockRegistry::default();
+        let chunk = FlatGenerator.generate((0, -1, 0));
+        let edits = [(Chunk::index(1, 2, 3) as u16, 7), (4095, 0)];
+        let expected = hand_written(&registry, &chunk, &edits);
+
+        let mut frame = ServerFrame::new(7);
+        frame.chunk_snapshot((1, -2, 3), 5, &chunk);
+        frame.chunk_delta((1, -2, 3), 5, 9, &edits);
+        frame.block_registry(&registry);
+        let data = frame.finish();
+        assert_eq!(&data[..], expected);
+
+        // And the generated decoders read the hand-written bytes
+        let (tick, msgs) = decode_server_frame(&expected).unwrap();
+        assert_eq!(tick, 7);
+        assert_eq!(
+            msgs,
+            [
+                ServerMsg::ChunkSnapshot {
+                    pos: (1, -2, 3),
+                    version: 5,
+                    chunk,
+                },
+                ServerMsg::ChunkDelta {
+                    pos: (1, -2, 3),
+                    base_version: 5,
+                    version: 9,
+                    edits: edits.to_vec(),
+                },
+                ServerMsg::BlockRegistry {
+                    blocks: registry.iter().cloned().collect(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn json_mode_is_negotiated_and_round_trips() {
+        assert_eq!(Encoding::negotiate("teleboxel.json"), Some(Encoding::Json));
+        assert_eq!(
+            Encoding::negotiate("chat, teleboxel.binary, teleboxel.json"),
+            Some(Encoding::Binary)
+        );
+        assert_eq!(Encoding::negotiate("chat"), None);
+
+        let mut frame = ServerFrame::new(7);
+        frame.chunk_snapshot((0, 0, 0), 1, &Chunk::empty());
+        frame.chunk_delta((1, -2, 3), 5, 6, &[(4095, 1)]);
+        let data = frame.finish();
+        let json = frame_to_json(&data).unwrap();
+        assert!(json.contains(r#"{"kind":"chunk_delta","pos":[1,-2,3],"base_version":5,"#));
+
+        let (tick, msgs) = decode_server_frame(&data).unwrap();
+        let parsed: JsonMessage = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, JsonMessage::Frame { tick, msgs });
+    }
+
+    #[test]
+    fn client_frames_round_trip() {
+        let mut frame = ClientFrame::new(3);
+        frame.relay_send(&[], b"hi");
+        frame.relay_send(&[2, 5], &[0, 255]);
+        let data = frame.finish();
+
+        assert_eq!(
+            decode_client_frame(&data).unwrap(),
+            (
+                3,
+                vec![
+                    ClientMsg::RelaySend {
+                        to: vec![],
+                        data: b"hi".to_vec(),
+                    },
+                    ClientMsg::RelaySend {
+                        to: vec![2, 5],
+                        data: vec![0, 255],
+                    },
+                ]
+            )
+        );
+        assert_eq!(
+            decode_client_frame(&data[..data.len() - 1]),
+            Err(ProtocolError::Truncated)
+        );
+        assert_eq!(
+            decode_server_frame(&data),
+            Err(ProtocolError::UnknownFrame(CLIENT_FRAME))
+        );
+    }
+
+    #[test]
+    fn generated_typescript_is_up_to_date() {
+        let generated = include_str!(concat!(env!("OUT_DIR"), "/protocol.ts"));
+        let path = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/sdk/typescript/src/protocol.ts"
+        );
+        if std::env::var_os("TELEBOXEL_UPDATE_SDK").is_some() {
+            std::fs::write(path, generated).unwrap();
+        }
+
+        let checked_in = std::fs::read_to_string(path).unwrap_or_default();
+        assert!(
+            checked_in == generated,
+            "{path} is stale, rerun with TELEBOXEL_UPDATE_SDK=1"
+        );
+    }
+
+    #[test]
+    fn pooled_frames_reuse_the_arena() {
+        let mut pool = FramePool::default();
+        let encode = |pool: &mut FramePool, tick| {
+            let mut frame = pool.frame(tick, ChunkFormat::default());
+            frame.chunk_delta((0, 0, 0), 1, 2, &[(7, 1)]);
+            let data = pool.finish(&mut frame);
+            pool.recycle(frame);
+            data
+        };
+        let first = encode(&mut pool, 1);
+        let mut plain = ServerFrame::new(1);
+        plain.chunk_delta((0, 0, 0), 1, 2, &[(7, 1)]);
+        assert_eq!(first, plain.finish());
+
+        // Written and dropped, the arena starts over once full
+        let (start, len) = (first.as_ptr(), first.len());
+        let held = first;
+        let starts: Vec<_> = (0..2 * ARENA_LEN / len)
+            .map(|_| encode(&mut pool, 2).as_ptr())
+            .collect();
+        assert!(!starts.contains(&start));
+        drop(held);
+        let starts: Vec<_> = (0..4 * ARENA_LEN / len)
+            .map(|_| encode(&mut pool, 3).as_ptr())
+            .collect();
+        assert!(starts.iter().filter(|&&p| p == starts[0]).count() > 1);
+        assert!(pool.full.len() <= 2);
+        assert_eq!(pool.free.len(), 1);
+    }
+
+    #[test]
+    fn rejects_malformed_frames() {
+        let mut frame = ServerFrame::new(1);
+        frame.chunk_delta((0, 0, 0), 1, 2, &[(4095, 1)]);
+        let data = frame.finish().to_vec();
+
+        for len in 0..data.len() {
+            assert!(decode_server_frame(&data[..len]).is_err());
+        }
+
+        let mut bad_index = data.clone();
+        let at = data.len() - 4;
+        bad_index[at..at + 2].copy_from_slice(&4096u16.to_le_bytes());
+        assert_eq!(
+            decode_server_frame(&bad_index),
+            Err(ProtocolError::Invalid("edit index out of range"))
+        );
+
+        assert_eq!(
+            decode_server_frame(&[0x11, 0, 0, 0, 0, 0]),
+            Err(ProtocolError::UnknownFrame(0x11))
+        );
+    }
+}
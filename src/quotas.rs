@@ -0,0 +1,412 @@
+//! Resource quotas per world, so one runaway room can't take a shared host
+//! down. No limits unless a TOML file (`TELEBOXEL_QUOTAS`) sets them, per
+//! world (`main` or a room name):
+//!
+//! ```toml
+//! [default]
+//! max_entities = 500
+//! max_chunks = 4096
+//! max_kbps = 8000              # sent to all the room's players together
+//! max_think_ms = 2.0           # entity brains, per tick
+//!
+//! [room.arena]                 # limits left out come from [default]
+//! max_entities = 50
+//! action = "close"
+//! ```
+//!
+//! The limits always hold: spawns past `max_entities` are refused, chunks
+//! past `max_chunks` aren't loaded (ones out of every interest make room),
+//! chunk frames wait while the last second went over `max_kbps` and brains
+//! past `max_think_ms` wait for the next tick. Every second a world checks
+//! whether it hit any, and then acts:
+//!
+//! - `reject` (the default): nothing more.
+//! - `degrade`: the world ticks at half rate until a second goes by
+//!   without hitting one.
+//! - `close`: players go back to the main world with a chat line and the
+//!   room ends. The main world can't close, it only rejects.
+//!
+//! `/admin/metrics` has each world's usage and how many seconds it spent
+//! over each limit.
+
+use crate::tenants;
+use serde::Deserialize;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    error::Error,
+    fmt::Write,
+    fs,
+    path::Path,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum QuotaAction {
+    #[default]
+    Reject,
+    Degrade,
+    Close,
+}
+
+/// One world's limits, `None` for no limit.
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+pub struct Quota {
+    pub max_entities: Option<usize>,
+    pub max_chunks: Option<usize>,
+    pub max_kbps: Option<u64>,
+    pub max_think: Option<Duration>,
+    pub action: QuotaAction,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Resource {
+    Entities,
+    Chunks,
+    Bandwidth,
+    Think,
+}
+
+impl Resource {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Resource::Entities => "entities",
+            Resource::Chunks => "chunks",
+            Resource::Bandwidth => "bandwidth",
+            Resource::Think => "think",
+        }
+    }
+}
+
+/// A world's use of its resources over the last second.
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+pub struct Usage {
+    pub entities: usize,
+    pub chunks: usize,
+    pub kbps: u64,
+    /// The longest tick's brains.
+    pub think_ms: f64,
+}
+
+/// A file table, unset limits come from [default] (or the room's, for a
+/// template's, see `templates.rs`).
+#[derive(Deserialize, Default, Clone, Copy, PartialEq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct QuotaTable {
+    max_entities: Option<usize>,
+    max_chunks: Option<usize>,
+    max_kbps: Option<u64>,
+    max_think_ms: Option<f64>,
+    action: Option<QuotaAction>,
+}
+
+impl QuotaTable {
+    pub fn over(&self, base: &Quota) -> Quota {
+        Quota {
+            max_entities: self.max_entities.or(base.max_entities),
+            max_chunks: self.max_chunks.or(base.max_chunks),
+            max_kbps: self.max_kbps.or(base.max_kbps),
+            max_think: self
+                .max_think_ms
+                .map(|ms| Duration::from_secs_f64(ms.max(0.0) / 1000.0))
+                .or(base.max_think),
+            action: self.action.unwrap_or(base.action),
+        }
+    }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct QuotasFile {
+    #[serde(default)]
+    default: QuotaTable,
+    #[serde(default)]
+    room: HashMap<String, QuotaTable>,
+}
+
+/// Limits by world, and what the worlds reported.
+#[derive(Default)]
+pub struct Quotas {
+    default: Quota,
+    rooms: HashMap<String, Quota>,
+    usage: Mutex<BTreeMap<String, Usage>>,
+    // Seconds over each limit, by world
+    exceeded: Mutex<BTreeMap<(String, Resource), u64>>,
+}
+
+impl Quotas {
+    /// See the module docs for the file format.
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    fn parse(text: &str) -> Result<Self, Box<dyn Error>> {
+        let file: QuotasFile = toml::from_str(text)?;
+        let default = file.default.over(&Quota::default());
+        let rooms = file
+            .room
+            .iter()
+            .map(|(room, table)| (room.clone(), table.over(&default)))
+            .collect();
+        Ok(Self {
+            default,
+            rooms,
+            ..Self::default()
+        })
+    }
+
+    /// The limits of `world`, `main` or a room name.
+    pub fn get(&self, world: &str) -> Quota {
+        let quota = *self.rooms.get(world).unwrap_or(&self.default);
+        match quota.action {
+            QuotaAction::Close if world == "main" => Quota {
+                action: QuotaAction::Reject,
+                ..quota
+            },
+            _ => quota,
+        }
+    }
+
+    /// A world's second, see `Meter::check`.
+    pub fn report(&self, world: &str, usage: Usage, over: &BTreeSet<Resource>) {
+        self.usage.lock().unwrap().insert(world.to_string(), usage);
+        let mut exceeded = self.exceeded.lock().unwrap();
+        for &resource in over {
+            *exceeded.entry((world.to_string(), resource)).or_default() += 1;
+        }
+    }
+
+    /// A room ended, its usage goes, its counts stay.
+    pub fn forget(&self, world: &str) {
+        self.usage.lock().unwrap().remove(world);
+    }
+
+    /// Prometheus text format.
+    pub fn prometheus(&self) -> String {
+        let mut out = String::new();
+        out += "# HELP teleboxel_world_usage Resources each world used over the last second\n";
+        out += "# TYPE teleboxel_world_usage gauge\n";
+        for (world, usage) in self.usage.lock().unwrap().iter() {
+            let values = [
+                (Resource::Entities, usage.entities as f64),
+                (Resource::Chunks, usage.chunks as f64),
+                (Resource::Bandwidth, usage.kbps as f64),
+                (Resource::Think, usage.think_ms),
+            ];
+            let world = labels(world);
+            for (resource, value) in values {
+                let resource = resource.as_str();
+                writeln!(
+                    out,
+                    "teleboxel_world_usage{{{world},resource=\"{resource}\"}} {value}"
+                )
+                .unwrap();
+            }
+        }
+        out += "# HELP teleboxel_quota_exceeded_seconds_total Seconds each world hit a quota\n";
+        out += "# TYPE teleboxel_quota_exceeded_seconds_total counter\n";
+        for ((world, resource), seconds) in self.exceeded.lock().unwrap().iter() {
+            let (world, resource) = (labels(world), resource.as_str());
+            writeln!(
+                out,
+                "teleboxel_quota_exceeded_seconds_total{{{world},resource=\"{resource}\"}} {seconds}"
+            )
+            .unwrap();
+        }
+        out
+    }
+}
+
+// A tenant's worlds are labeled with it too
+fn labels(world: &str) -> String {
+    match tenants::of_world(world) {
+        Some(tenant) => format!("world=\"{world}\",tenant=\"{tenant}\""),
+        None => format!("world=\"{world}\""),
+    }
+}
+
+/// One world's side: the limits, and what it hit this second.
+pub struct Meter {
+    pub quota: Quota,
+    since: Instant,
+    // Out bytes of each player at the last check
+    sent: HashMap<u32, u64>,
+    think: Duration,
+    hit: BTreeSet<Resource>,
+    throttled: bool,
+}
+
+impl Meter {
+    pub fn new(quota: Quota) -> Self {
+        Self {
+            quota,
+            since: Instant::now(),
+            sent: HashMap::new(),
+            think: Duration::ZERO,
+            hit: BTreeSet::new(),
+            throttled: false,
+        }
+    }
+
+    /// Whether one more entity fits with `entities` already there.
+    pub fn can_spawn(&mut self, entities: usize) -> bool {
+        let fits = self.quota.max_entities.is_none_or(|max| entities < max);
+        if !fits {
+            self.hit.insert(Resource::Entities);
+        }
+        fits
+    }
+
+    /// Chunk frames wait while the last second went over `max_kbps`.
+    pub fn can_send_chunks(&mut self) -> bool {
+        if self.throttled {
+            self.hit.insert(Resource::Bandwidth);
+        }
+        !self.throttled
+    }
+
+    /// The brains' time this tick, `budget` unless the quota is tighter.
+    pub fn think_budget(&self, budget: Duration) -> Duration {
+        self.quota.max_think.map_or(budget, |max| max.min(budget))
+    }
+
+    /// Brains took `spent` this tick, some waiting for the next when
+    /// `deferred`.
+    pub fn thought(&mut self, spent: Duration, deferred: bool, budget: Duration) {
+        self.think = self.think.max(spent);
+        if deferred && self.quota.max_think.is_some_and(|max| max < budget) {
+            self.hit.insert(Resource::Think);
+        }
+    }
+
+    /// Once a second passed: the usage and the limits hit since the last
+    /// check. `sent` is each player's out bytes so far.
+    pub fn check(
+        &mut self,
+        entities: usize,
+        chunks: usize,
+        sent: impl IntoIterator<Item = (u32, u64)>,
+    ) -> Option<(Usage, BTreeSet<Resource>)> {
+        self.check_at(Instant::now(), entities, chunks, sent)
+    }
+
+    fn check_at(
+        &mut self,
+        now: Instant,
+        entities: usize,
+        chunks: usize,
+        sent: impl IntoIterator<Item = (u32, u64)>,
+    ) -> Option<(Usage, BTreeSet<Resource>)> {
+        let elapsed = now.duration_since(self.since);
+        if elapsed < Duration::from_secs(1) {
+            return None;
+        }
+        let mut bytes = 0;
+        let mut seen = HashMap::new();
+        for (id, out) in sent {
+            // Players that joined since count from zero
+            bytes += out.saturating_sub(self.sent.get(&id).copied().unwrap_or(0));
+            seen.insert(id, out);
+        }
+        self.sent = seen;
+        let kbps = (bytes as f64 * 8.0 / 1000.0 / elapsed.as_secs_f64()) as u64;
+        self.throttled = self.quota.max_kbps.is_some_and(|max| kbps > max);
+
+        if self.quota.max_chunks.is_some_and(|max| chunks >= max) {
+            self.hit.insert(Resource::Chunks);
+        }
+        if self.throttled {
+            self.hit.insert(Resource::Bandwidth);
+        }
+        let usage = Usage {
+            entities,
+            chunks,
+            kbps,
+            think_ms: self.think.as_secs_f64() * 1000.0,
+        };
+        self.since = now;
+        self.think = Duration::ZERO;
+        Some((usage, std::mem::take(&mut self.hit)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn configured_per_world_and_metered_per_second() {
+        let quotas = Quotas::parse(
+            r#"
+            [default]
+            max_kbps = 100
+            action = "close"
+
+            [room.arena]
+            max_entities = 2
+            max_think_ms = 1.5
+            action = "degrade"
+            "#,
+        )
+        .unwrap();
+        let arena = quotas.get("arena");
+        assert_eq!(arena.max_entities, Some(2));
+        assert_eq!(arena.max_kbps, Some(100));
+        assert_eq!(arena.max_think, Some(Duration::from_micros(1500)));
+        assert_eq!(arena.action, QuotaAction::Degrade);
+        assert_eq!(quotas.get("lobby").action, QuotaAction::Close);
+        // The main world can't close
+        assert_eq!(quotas.get("main").action, QuotaAction::Reject);
+        assert!(Quotas::parse("[default]\nmax_cpu = 1").is_err());
+        assert!(Quotas::parse("[default]\naction = \"panic\"").is_err());
+
+        let mut meter = Meter::new(arena);
+        let start = meter.since;
+        assert!(meter.can_spawn(1));
+        assert!(!meter.can_spawn(2));
+        let budget = Duration::from_millis(5);
+        assert_eq!(meter.think_budget(budget), Duration::from_micros(1500));
+        meter.thought(Duration::from_millis(1), false, budget);
+        assert!(meter.can_send_chunks());
+        assert_eq!(meter.check_at(start, 2, 10, []), None);
+
+        // 25 kB in a second is 200 kbps
+        let second = start + Duration::from_secs(1);
+        let (usage, hit) = meter
+            .check_at(second, 2, 10, [(1, 20_000), (2, 5_000)])
+            .unwrap();
+        assert_eq!(usage.kbps, 200);
+        assert_eq!(usage.think_ms, 1.0);
+        assert_eq!(
+            hit,
+            BTreeSet::from([Resource::Entities, Resource::Bandwidth])
+        );
+        assert!(!meter.can_send_chunks());
+
+        // Only what was sent since counts, player 3 is new
+        let (usage, hit) = meter
+            .check_at(
+                second + Duration::from_secs(1),
+                2,
+                10,
+                [(1, 21_000), (3, 1_500)],
+            )
+            .unwrap();
+        assert_eq!(usage.kbps, 20);
+        assert_eq!(hit, BTreeSet::from([Resource::Bandwidth]));
+        assert!(meter.can_send_chunks());
+
+        quotas.report("arena", usage, &hit);
+        quotas.report("arena", usage, &hit);
+        let metrics = quotas.prometheus();
+        assert!(metrics.contains(
+            "teleboxel_quota_exceeded_seconds_total{world=\"arena\",resource=\"bandwidth\"} 2"
+        ));
+        assert!(
+            metrics.contains("teleboxel_world_usage{world=\"arena\",resource=\"bandwidth\"} 20")
+        );
+        quotas.forget("arena");
+        assert!(!quotas.prometheus().contains("teleboxel_world_usage{"));
+    }
+}
@@ -0,0 +1,144 @@
+//! Recent frames per player, for offline analysis of what a client saw.
+//! With `TELEBOXEL_FRAME_HISTORY_SECS` set, each connection keeps the
+//! server frames the world sent it over the last that many seconds (up to
+//! `MAX_BYTES`), across room changes. `GET /admin/players/{id}/frames`
+//! exports the last `?secs=` of them as a file:
+//!
+//! ```text
+//! TBXFRAMES 1\n
+//! then per frame: <u32 LE ms since the first> <u32 LE length> <frame>
+//! ```
+//!
+//! Frames are the binary protocol whatever the client's encoding, `read`
+//! splits a file back up and `teleboxel dump-frames <file>` prints them as
+//! JSON. Off (`0`, the default) nothing is kept. Frames are copied, so a
+//! recording doesn't hold on to the world's frame arenas.
+
+use bytes::Bytes;
+use std::{
+    collections::VecDeque,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Bytes of frames each player keeps at most, the oldest go first.
+pub const MAX_BYTES: usize = 16 * 1024 * 1024;
+
+const MAGIC: &[u8] = b"TBXFRAMES 1\n";
+
+/// Shared by the connection, which records, and the world, which exports.
+pub struct Recorder {
+    keep: Duration,
+    frames: Mutex<Frames>,
+}
+
+#[derive(Default)]
+struct Frames {
+    sent: VecDeque<(Instant, Bytes)>,
+    bytes: usize,
+}
+
+impl Recorder {
+    /// Keeps `keep` worth of frames, none when zero.
+    pub fn new(keep: Duration) -> Self {
+        Self {
+            keep,
+            frames: Mutex::default(),
+        }
+    }
+
+    pub fn record(&self, frame: &[u8]) {
+        self.record_at(Instant::now(), frame);
+    }
+
+    fn record_at(&self, at: Instant, frame: &[u8]) {
+        if self.keep.is_zero() {
+            return;
+        }
+        let mut frames = self.frames.lock().unwrap();
+        frames.sent.push_back((at, Bytes::copy_from_slice(frame)));
+        frames.bytes += frame.len();
+        while let Some((sent, oldest)) = frames.sent.front() {
+            if frames.bytes <= MAX_BYTES && at.duration_since(*sent) <= self.keep {
+                break;
+            }
+            frames.bytes -= oldest.len();
+            frames.sent.pop_front();
+        }
+    }
+
+    /// The frames of the last `secs`, in the file format above.
+    pub fn export(&self, secs: Duration) -> Vec<u8> {
+        self.export_at(Instant::now(), secs)
+    }
+
+    fn export_at(&self, now: Instant, secs: Duration) -> Vec<u8> {
+        let frames = self.frames.lock().unwrap();
+        let recent = frames
+            .sent
+            .iter()
+            .filter(|(at, _)| now.duration_since(*at) <= secs);
+        let mut file = MAGIC.to_vec();
+        let mut first = None;
+        for (at, frame) in recent {
+            let first = *first.get_or_insert(*at);
+            let ms = at.duration_since(first).as_millis() as u32;
+            file.extend_from_slice(&ms.to_le_bytes());
+            file.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+            file.extend_from_slice(frame);
+        }
+        file
+    }
+}
+
+/// The frames of an exported file with their ms since the first.
+pub fn read(mut file: &[u8]) -> Result<Vec<(u32, &[u8])>, String> {
+    file = file.strip_prefix(MAGIC).ok_or("Not a frame recording")?;
+    let mut frames = Vec::new();
+    while !file.is_empty() {
+        let (header, rest) = file.split_at_checked(8).ok_or("Truncated header")?;
+        let ms = u32::from_le_bytes(header[..4].try_into().unwrap());
+        let len = u32::from_le_bytes(header[4..].try_into().unwrap()) as usize;
+        let (frame, rest) = rest.split_at_checked(len).ok_or("Truncated frame")?;
+        frames.push((ms, frame));
+        file = rest;
+    }
+    Ok(frames)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_recent_frames_and_reads_them_back() {
+        let off = Recorder::new(Duration::ZERO);
+        off.record(b"frame");
+        assert_eq!(read(&off.export(Duration::MAX)).unwrap(), []);
+
+        let recorder = Recorder::new(Duration::from_secs(10));
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        recorder.record_at(at(0), b"a");
+        recorder.record_at(at(5_000), b"bb");
+        recorder.record_at(at(12_000), b"ccc");
+        // The first is past the 10 s kept
+        let file = recorder.export_at(at(12_000), Duration::MAX);
+        assert_eq!(
+            read(&file).unwrap(),
+            [(0, &b"bb"[..]), (7_000, &b"ccc"[..])]
+        );
+        let file = recorder.export_at(at(13_000), Duration::from_secs(2));
+        assert_eq!(read(&file).unwrap(), [(0, &b"ccc"[..])]);
+
+        assert!(read(b"nope").is_err());
+        assert_eq!(read(&file[..file.len() - 1]), Err("Truncated frame".into()));
+
+        // Big frames push out old ones
+        let big = vec![0; MAX_BYTES / 2 + 1];
+        recorder.record_at(at(13_000), &big);
+        recorder.record_at(at(13_000), &big);
+        let file = recorder.export_at(at(13_000), Duration::MAX);
+        assert_eq!(read(&file).unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,35 @@
+//! Relay rooms (`RoomCreate Name relay`), for small sessions that want a
+//! dumb low-latency relay before taking on server authority: the server
+//! doesn't simulate them or look inside messages. Players send `RELAY_SEND`
+//! in binary client frames, to chosen players or everyone else in the room,
+//! and the server forwards each as `RELAY` right away, tagged with the
+//! sender's id.
+//!
+//! Messages carry at most `MAX_DATA_LEN` bytes, and sending is rate limited
+//! like the text commands (`relay` in `TELEBOXEL_FLOOD`). Nothing is queued
+//! or retried: players whose connection can't keep up miss messages.
+
+/// Longest `RELAY_SEND` data, in bytes.
+pub const MAX_DATA_LEN: usize = 4096;
+
+/// Who gets a message from player `from`: the `players` in `to`, or all of
+/// them when it's empty, never the sender.
+pub fn recipients(from: u32, to: &[u32], players: impl IntoIterator<Item = u32>) -> Vec<u32> {
+    players
+        .into_iter()
+        .filter(|&id| id != from && (to.is_empty() || to.contains(&id)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn picks_recipients() {
+        assert_eq!(recipients(1, &[], [1, 2, 3]), [2, 3]);
+        // Players not in the room and the sender are left out
+        assert_eq!(recipients(1, &[3, 1, 9], [1, 2, 3]), [3]);
+        assert!(recipients(1, &[], [1]).is_empty());
+    }
+}
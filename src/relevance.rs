@@ -0,0 +1,191 @@
+//! Relevance rules: game rules on top of interest deciding, per player,
+//! which entities they get. A rule answers for a viewer and an entity:
+//!
+//! - `Default`: as interest has it. Dynamic entities go to everyone,
+//!   static ones within the interest, always relevant or forced for the
+//!   player (see `entities.rs`).
+//! - `Hide`: not sent, team fog-of-war, stealth. A player who had the
+//!   entity gets `ENTITY_GONE`, and `ENTITY` again once it's shown.
+//! - `Show`: sent wherever it is, instanced content for some players.
+//!
+//! The built-in rule is a fog range per world, in a TOML file
+//! (`TELEBOXEL_RELEVANCE`, a world is `main` or a room name):
+//!
+//! ```toml
+//! [default]
+//! range = 0          # blocks, 0 turns it off
+//!
+//! [room.arena]
+//! range = 48
+//! ```
+//!
+//! Past `range` blocks from a player, entities are hidden from them,
+//! except always relevant ones and the ones the player has authority over.
+//!
+//! Layers come before any rule: instanced sub-spaces in one world, e.g.
+//! copies of a dungeon at the same coordinates. Players and entities are
+//! in layer 0 unless put in another (`/admin/players/<id>/layer`,
+//! `/admin/entities/<id>/layer`), and nothing crosses layers: entities
+//! and effects in another layer are hidden, voice doesn't carry and
+//! mounts are refused. Terrain, chat and block edits are shared by every
+//! layer, and a player's layer resets when they leave the world.
+//!
+//! Worlds with a rule or layers ask about every entity for every player
+//! each tick, so rules must be cheap. Mounts and attachments of hidden
+//! entities still go to everyone.
+
+use crate::{chunk::ChunkPos, claims::BlockPos, entities::Entity};
+use serde::Deserialize;
+use std::{collections::HashMap, error::Error, fs, path::Path, sync::Arc};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Relevance {
+    Default,
+    Hide,
+    Show,
+}
+
+/// The player an entity would be sent to.
+pub struct Viewer {
+    pub id: u32,
+    pub position: BlockPos,
+    pub interest: Option<(ChunkPos, u16)>,
+    pub layer: u32,
+}
+
+pub trait RelevanceRule: Send + Sync {
+    fn is_relevant(&self, viewer: &Viewer, entity: &Entity) -> Relevance;
+}
+
+/// Hidden in another layer, up to `rule` otherwise.
+pub fn judge(rule: Option<&dyn RelevanceRule>, viewer: &Viewer, entity: &Entity) -> Relevance {
+    if viewer.layer != entity.layer {
+        return Relevance::Hide;
+    }
+    rule.map_or(Relevance::Default, |rule| rule.is_relevant(viewer, entity))
+}
+
+/// Hides entities past `range` blocks.
+pub struct Fog {
+    pub range: u32,
+}
+
+impl RelevanceRule for Fog {
+    fn is_relevant(&self, viewer: &Viewer, entity: &Entity) -> Relevance {
+        if entity.always || entity.authority == Some(viewer.id) {
+            return Relevance::Default;
+        }
+        let d = |a: i32, b: i32| (a as i64 - b as i64).pow(2);
+        let (a, b) = (viewer.position, entity.position);
+        let far = d(a.0, b.0) + d(a.1, b.1) + d(a.2, b.2) > (self.range as i64).pow(2);
+        if far {
+            Relevance::Hide
+        } else {
+            Relevance::Default
+        }
+    }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct RelevanceTable {
+    range: Option<u32>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct RelevanceFile {
+    #[serde(default)]
+    default: RelevanceTable,
+    #[serde(default)]
+    room: HashMap<String, RelevanceTable>,
+}
+
+/// Fog ranges by world, `None` when off.
+#[derive(Default)]
+pub struct RelevanceRules {
+    default: Option<u32>,
+    rooms: HashMap<String, Option<u32>>,
+}
+
+impl RelevanceRules {
+    /// See the module docs for the file format.
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    fn parse(text: &str) -> Result<Self, Box<dyn Error>> {
+        let file: RelevanceFile = toml::from_str(text)?;
+        let range = |table: &RelevanceTable, base| match table.range {
+            Some(0) => None,
+            Some(range) => Some(range),
+            None => base,
+        };
+        let default = range(&file.default, None);
+        let rooms = file
+            .room
+            .iter()
+            .map(|(room, table)| (room.clone(), range(table, default)))
+            .collect();
+        Ok(Self { default, rooms })
+    }
+
+    /// The rule of `world`, `main` or a room name.
+    pub fn get(&self, world: &str) -> Option<Arc<dyn RelevanceRule>> {
+        let range = *self.rooms.get(world).unwrap_or(&self.default);
+        range.map(|range| Arc::new(Fog { range }) as Arc<dyn RelevanceRule>)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fog_hides_far_entities() {
+        let rules = RelevanceRules::parse(
+            r#"
+            [room.arena]
+            range = 48
+
+            [room.lobby]
+            range = 0
+            "#,
+        )
+        .unwrap();
+        assert!(rules.get("main").is_none());
+        assert!(rules.get("lobby").is_none());
+        assert!(RelevanceRules::parse("[default]\nrange = -1").is_err());
+        let fog = rules.get("arena").unwrap();
+
+        let viewer = Viewer {
+            id: 1,
+            position: (0, 40, 0),
+            interest: None,
+            layer: 0,
+        };
+        let mut entity = Entity {
+            id: 5,
+            position: (0, 40, 48),
+            authority: None,
+            attached: None,
+            fixed: false,
+            always: false,
+            layer: 0,
+        };
+        assert_eq!(fog.is_relevant(&viewer, &entity), Relevance::Default);
+        entity.position.2 = 49;
+        assert_eq!(fog.is_relevant(&viewer, &entity), Relevance::Hide);
+        entity.authority = Some(1);
+        assert_eq!(fog.is_relevant(&viewer, &entity), Relevance::Default);
+        entity.authority = None;
+        entity.always = true;
+        assert_eq!(fog.is_relevant(&viewer, &entity), Relevance::Default);
+
+        // Other layers are out of reach, rule or not
+        assert_eq!(judge(None, &viewer, &entity), Relevance::Default);
+        entity.layer = 2;
+        assert_eq!(judge(None, &viewer, &entity), Relevance::Hide);
+        assert_eq!(judge(Some(&*fog), &viewer, &entity), Relevance::Hide);
+    }
+}
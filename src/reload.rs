@@ -0,0 +1,147 @@
+//! Runtime config reloads. On SIGHUP, or when the `TELEBOXEL_CONFIG` file
+//! changes (its modification time is polled), settings are read again:
+//! `Tunables` go out to the running worlds, and other changed settings are
+//! reported as needing a restart.
+
+use crate::config::{Tunables, Vars};
+use std::{
+    fs,
+    path::Path,
+    time::{Duration, SystemTime},
+};
+use tokio::{select, sync::watch};
+
+const POLL: Duration = Duration::from_secs(2);
+
+/// What a reload changed.
+#[derive(Default, Debug, PartialEq, Eq)]
+pub struct Reload {
+    /// Tunable settings now running with their new values.
+    pub applied: Vec<String>,
+    /// Settings that differ from startup and apply on the next start.
+    pub restart: Vec<String>,
+}
+
+/// Applies `vars` over the `current` settings. Restart-only settings are
+/// compared with the ones the server `started` with, so they keep being
+/// reported until it restarts.
+pub fn reload(
+    started: &Vars,
+    current: &Vars,
+    vars: &Vars,
+    tunables: &watch::Sender<Tunables>,
+) -> Reload {
+    let tunable = |key: &String| Tunables::KEYS.contains(&key.as_str());
+    let applied = current.changed(vars).into_iter().filter(tunable).collect();
+    let restart = started
+        .changed(vars)
+        .into_iter()
+        .filter(|k| !tunable(k))
+        .collect();
+    tunables.send_if_modified(|t| {
+        let new = Tunables::from_vars(vars);
+        std::mem::replace(t, new) != new
+    });
+    Reload { applied, restart }
+}
+
+/// Reloads on SIGHUP or config file changes until the process ends.
+pub async fn run(started: Vars, tunables: watch::Sender<Tunables>) {
+    let path = started.config_file();
+    let mut modified = path.as_deref().and_then(mtime);
+    let mut current = started.clone();
+    let mut poll = tokio::time::interval(POLL);
+    #[cfg(unix)]
+    let mut hangup = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()).ok();
+    #[cfg(not(unix))]
+    let mut hangup = None;
+
+    loop {
+        select! {
+            _ = poll.tick() => {
+                let now = path.as_deref().and_then(mtime);
+                if now == modified {
+                    continue;
+                }
+                modified = now;
+            }
+            _ = hangup_signal(&mut hangup) => {}
+        }
+
+        let vars = match Vars::load() {
+            Ok(vars) => vars,
+            Err(e) => {
+                eprintln!("Config reload failed, keeping current settings: {e}");
+                continue;
+            }
+        };
+        let Reload { applied, restart } = reload(&started, &current, &vars, &tunables);
+        let applied: Vec<_> = applied
+            .iter()
+            .map(|k| format!("{k}={}", vars.get(k).unwrap_or_default()))
+            .collect();
+        match applied.is_empty() {
+            true => println!("Config reloaded, no runtime changes"),
+            false => println!("Config reloaded: {}", applied.join(", ")),
+        }
+        if !restart.is_empty() {
+            println!("Needs a restart to apply: {}", restart.join(", "));
+        }
+        current = vars;
+    }
+}
+
+fn mtime(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+#[cfg(unix)]
+async fn hangup_signal(signal: &mut Option<tokio::signal::unix::Signal>) {
+    match signal {
+        Some(signal) => {
+            signal.recv().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+#[cfg(not(unix))]
+async fn hangup_signal(_: &mut Option<()>) {
+    std::future::pending().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn applies_tunables_and_reports_the_rest() {
+        let started = Vars::parse("TELEBOXEL_TICK_HZ=60\nTELEBOXEL_WORLD_DIR=world").unwrap();
+        let (tx, rx) = watch::channel(Tunables::from_vars(&started));
+
+        let vars = Vars::parse(
+            "# edited\n\
+             TELEBOXEL_TICK_HZ = 30\n\
+             TELEBOXEL_MAX_INTEREST_RADIUS=\"99\"\n\
+             TELEBOXEL_WORLD_DIR=other\n",
+        )
+        .unwrap();
+        let result = reload(&started, &started, &vars, &tx);
+        assert_eq!(
+            result.applied,
+            ["TELEBOXEL_MAX_INTEREST_RADIUS", "TELEBOXEL_TICK_HZ"]
+        );
+        assert_eq!(result.restart, ["TELEBOXEL_WORLD_DIR"]);
+        assert_eq!(rx.borrow().tick_hz, 30);
+        // Clamped
+        assert_eq!(rx.borrow().max_interest_radius, 16);
+
+        // Restart-only changes are reported until the restart
+        let again = reload(&started, &vars, &vars, &tx);
+        assert!(again.applied.is_empty());
+        assert_eq!(again.restart, ["TELEBOXEL_WORLD_DIR"]);
+
+        assert!(Vars::parse("TICK_HZ=30").is_err());
+        assert!(Vars::parse("TELEBOXEL_TICK_HZ").is_err());
+    }
+}
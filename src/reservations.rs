@@ -0,0 +1,104 @@
+//! Slot reservations: a matchmaker that put a player in a room gets a
+//! ticket holding a place there, so someone joining by name in the
+//! meantime can't take it. Rooms made from a template with `max_players`
+//! (see `templates.rs`) count held tickets as players, joins past that are
+//! refused unless they bring a ticket:
+//!
+//! ```text
+//! POST /admin/reservations?room=duel-1&name=alice&seconds=30
+//! ws://host:3000/?room=duel-1&name=alice&ticket=<ticket>
+//! ```
+//!
+//! A ticket is good for one join within its time, by the named player when
+//! one was given. Rooms without a cap take tickets as any other join. An
+//! empty room stays open while it holds tickets, and closes once they run
+//! out with nobody having come. Moving between rooms
+//! reserves a place in the next one first, so a full room turns the player
+//! away before they leave.
+//!
+//! Tickets live in their world, they don't survive a restart.
+
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+struct Ticket {
+    name: Option<String>,
+    expires: Instant,
+}
+
+/// One world's tickets.
+#[derive(Default)]
+pub struct Reservations {
+    tickets: HashMap<String, Ticket>,
+    // Since the last `lapsed`
+    issued: bool,
+}
+
+impl Reservations {
+    /// A new ticket, for `name` alone when given. `None` if there's no
+    /// randomness to make one from.
+    pub fn issue(&mut self, name: Option<String>, ttl: Duration, now: Instant) -> Option<String> {
+        let mut random = [0; 16];
+        getrandom::getrandom(&mut random).ok()?;
+        let token: String = random.iter().map(|b| format!("{b:02x}")).collect();
+        let expires = now + ttl;
+        self.tickets.insert(token.clone(), Ticket { name, expires });
+        self.issued = true;
+        Some(token)
+    }
+
+    /// Tickets still good, forgetting the rest.
+    pub fn held(&mut self, now: Instant) -> usize {
+        self.tickets.retain(|_, ticket| ticket.expires > now);
+        self.tickets.len()
+    }
+
+    /// Uses up `token` for a join by `name`, if it's good.
+    pub fn redeem(&mut self, token: &str, name: Option<&str>, now: Instant) -> bool {
+        let good = self.tickets.get(token).is_some_and(|ticket| {
+            ticket.expires > now && (ticket.name.is_none() || ticket.name.as_deref() == name)
+        });
+        if good {
+            self.tickets.remove(token);
+        }
+        good
+    }
+
+    /// Whether the tickets held ran out, used or not, since last asked.
+    pub fn lapsed(&mut self, now: Instant) -> bool {
+        self.held(now) == 0 && std::mem::take(&mut self.issued)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn holds_places_until_used_or_expired() {
+        let mut reservations = Reservations::default();
+        let now = Instant::now();
+        let ttl = Duration::from_secs(30);
+        let alice = reservations.issue(Some("alice".into()), ttl, now).unwrap();
+        let anyone = reservations.issue(None, ttl, now).unwrap();
+        assert_ne!(alice, anyone);
+        assert_eq!(reservations.held(now), 2);
+
+        // Only alice's, only once
+        assert!(!reservations.redeem(&alice, Some("bob"), now));
+        assert!(!reservations.redeem(&alice, None, now));
+        assert!(reservations.redeem(&alice, Some("alice"), now));
+        assert!(!reservations.redeem(&alice, Some("alice"), now));
+        assert!(!reservations.redeem("made-up", None, now));
+        assert_eq!(reservations.held(now), 1);
+        assert!(!reservations.lapsed(now));
+
+        let later = now + ttl;
+        assert!(!reservations.redeem(&anyone, None, later));
+        assert_eq!(reservations.held(later), 0);
+        assert!(reservations.lapsed(later));
+        assert!(!reservations.lapsed(later));
+    }
+}
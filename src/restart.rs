@@ -0,0 +1,222 @@
+//! Hot restart: the server starts its successor (same binary, arguments and
+//! environment) on the same listening sockets, then drains. The sockets stay
+//! open throughout, so new connections wait in their backlog until the
+//! successor takes them, and players reconnect with their resume token (see
+//! `resume.rs`) to the same address. Unix only.
+//!
+//! The successor finds the sockets in `TELEBOXEL_LISTEN_FD`, in listener
+//! order (see `listeners.rs`), and reports that it's serving on the
+//! `TELEBOXEL_READY_FD` pipe. Until then the old server keeps accepting,
+//! and if it never does, the restart is called off.
+
+use std::{
+    io,
+    net::SocketAddr,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    sync::watch,
+};
+
+/// Inherited listening sockets, comma separated.
+pub const LISTEN_FD: &str = "TELEBOXEL_LISTEN_FD";
+/// Pipe the successor writes to once it's serving.
+pub const READY_FD: &str = "TELEBOXEL_READY_FD";
+
+// Loading storage and the block registry, before the successor serves
+const READY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The listening sockets inherited from the previous server, if this server
+/// took over from one.
+pub fn inherited() -> Option<Vec<i32>> {
+    let fds = std::env::var(LISTEN_FD).ok()?;
+    fds.split(',').map(|fd| fd.parse().ok()).collect()
+}
+
+/// Tells the previous server this one is serving, if there is one.
+pub fn ready() {
+    #[cfg(unix)]
+    if let Some(fd) = std::env::var(READY_FD).ok().and_then(|fd| fd.parse().ok()) {
+        use std::{io::Write, os::fd::FromRawFd};
+        let mut pipe = unsafe { std::fs::File::from_raw_fd(fd) };
+        pipe.write_all(b"1").ok();
+    }
+}
+
+/// Starts the successor and hands the listening socket over to it.
+pub struct Handover {
+    #[cfg_attr(not(unix), allow(dead_code))]
+    fds: Vec<i32>,
+    // Extra successor environment, e.g. the resume secret
+    #[cfg_attr(not(unix), allow(dead_code))]
+    env: Vec<(String, String)>,
+    // Set while a successor starts, so there's only one
+    starting: AtomicBool,
+    handed_over: watch::Sender<bool>,
+}
+
+impl Handover {
+    pub fn new(fds: Vec<i32>, env: Vec<(String, String)>) -> Self {
+        Self {
+            fds,
+            env,
+            starting: AtomicBool::new(false),
+            handed_over: watch::Sender::new(false),
+        }
+    }
+
+    pub fn is_handed_over(&self) -> bool {
+        *self.handed_over.borrow()
+    }
+
+    /// Starts the successor and waits for it to serve, then stops accepting
+    /// connections. Returns its process id.
+    pub async fn start(&self) -> io::Result<u32> {
+        if self.starting.swap(true, Ordering::SeqCst) {
+            return Err(io::Error::other("already restarting"));
+        }
+        let started = self.spawn().await;
+        match &started {
+            Ok(_) => {
+                self.handed_over.send_replace(true);
+            }
+            // Can be tried again
+            Err(_) => self.starting.store(false, Ordering::SeqCst),
+        }
+        started
+    }
+
+    #[cfg(unix)]
+    async fn spawn(&self) -> io::Result<u32> {
+        use std::{io::Read, os::fd::AsRawFd, os::unix::process::CommandExt, process::Stdio};
+
+        let (mut ready_rx, ready_tx) = io::pipe()?;
+        let ready_fd = ready_tx.as_raw_fd();
+        let listen_fds = self.fds.iter().map(i32::to_string).collect::<Vec<_>>();
+        // Collected ahead, nothing allocates between fork and exec
+        let kept = self
+            .fds
+            .iter()
+            .copied()
+            .chain([ready_fd])
+            .collect::<Vec<_>>();
+
+        let mut command = std::process::Command::new(std::env::current_exe()?);
+        command
+            .args(std::env::args_os().skip(1))
+            .envs(self.env.iter().map(|(k, v)| (k, v)))
+            .env(LISTEN_FD, listen_fds.join(","))
+            .env(READY_FD, ready_fd.to_string())
+            // The console stays with this server
+            .stdin(Stdio::null());
+        // All are close-on-exec, the successor keeps them
+        unsafe {
+            command.pre_exec(move || {
+                for &fd in &kept {
+                    if libc::fcntl(fd, libc::F_SETFD, 0) == -1 {
+                        return Err(io::Error::last_os_error());
+                    }
+                }
+                Ok(())
+            });
+        }
+        let mut child = command.spawn()?;
+        drop(ready_tx);
+
+        // A byte once it serves, end of file if it exits first
+        let ready = tokio::task::spawn_blocking(move || ready_rx.read(&mut [0]));
+        let ready = tokio::time::timeout(READY_TIMEOUT, ready).await;
+        if !matches!(ready, Ok(Ok(Ok(1)))) {
+            child.kill().ok();
+            child.wait().ok();
+            return Err(io::Error::other("successor didn't start"));
+        }
+
+        // Outlives this process, which never waits for it
+        Ok(child.id())
+    }
+
+    #[cfg(not(unix))]
+    async fn spawn(&self) -> io::Result<u32> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    /// `listener` for `axum::serve`, accepting until the handover.
+    pub fn listener<L: Accept>(&self, listener: L) -> HandoverListener<L> {
+        HandoverListener {
+            listener,
+            handed_over: self.handed_over.subscribe(),
+        }
+    }
+}
+
+/// A listening socket of any kind, see `listeners.rs`.
+pub trait Accept: Send + 'static {
+    type Io: AsyncRead + AsyncWrite + Unpin + Send + 'static;
+
+    fn accept(&mut self) -> impl Future<Output = io::Result<(Self::Io, SocketAddr)>> + Send;
+
+    fn local_addr(&self) -> io::Result<SocketAddr>;
+}
+
+pub struct HandoverListener<L> {
+    listener: L,
+    handed_over: watch::Receiver<bool>,
+}
+
+impl<L: Accept> axum::serve::Listener for HandoverListener<L> {
+    type Io = L::Io;
+    type Addr = SocketAddr;
+
+    async fn accept(&mut self) -> (L::Io, SocketAddr) {
+        loop {
+            let accepted = tokio::select! {
+                // Even with connections waiting
+                biased;
+                _ = self.handed_over.wait_for(|h| *h) => break,
+                accepted = self.listener.accept() => accepted,
+            };
+            match accepted {
+                Ok(accepted) => return accepted,
+                // Out of file descriptors and the like
+                Err(e) => {
+                    eprintln!("Accept: {e}");
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                }
+            }
+        }
+        // The successor takes everything from here
+        std::future::pending().await
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use axum::serve::Listener;
+    use tokio::net::{TcpListener, TcpStream};
+
+    #[tokio::test]
+    async fn stops_accepting_once_handed_over() {
+        let handover = Handover::new(Vec::new(), Vec::new());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut listener = handover.listener(listener);
+        let addr = listener.local_addr().unwrap();
+
+        let _client = TcpStream::connect(addr).await.unwrap();
+        listener.accept().await;
+
+        handover.handed_over.send_replace(true);
+        assert!(handover.is_handed_over());
+        // Waits in the backlog for the successor
+        let _client = TcpStream::connect(addr).await.unwrap();
+        let accept = tokio::time::timeout(Duration::from_millis(200), listener.accept());
+        assert!(accept.await.is_err());
+    }
+}
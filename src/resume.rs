@@ -0,0 +1,189 @@
+//! Session resume tokens. Before a hot restart (see `restart.rs`) or a drain
+//! to another server, main world players get a `RESUME` message with a
+//! signed token of where they were; connecting with `?resume=<token>` puts
+//! them back there (name, position, interest) instead of a fresh join.
+//!
+//! Servers sharing `TELEBOXEL_RESUME_SECRET` accept each other's tokens.
+//! Without one a server makes up a secret and hands it to its hot restart
+//! successor. Tokens fit a protocol `str` (255 bytes):
+//!
+//! ```text
+//! <expires>.<x>,<y>,<z>.<cx>,<cy>,<cz>,<radius>.<name hex>.<mac hex>
+//! ```
+//!
+//! Position, interest and name are empty when there's none to carry (a
+//! position-less session starts at the spawn point). The MAC is the
+//! first 16 bytes of HMAC-SHA256 over everything before it.
+
+use crate::telemetry::random_u64;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// How long a token is good for.
+pub const TOKEN_TTL: Duration = Duration::from_secs(300);
+
+const MAC_BYTES: usize = 16;
+
+/// Where a player was: what a resumed connection gets back, and what carries
+/// over to another room or through a portal.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Session {
+    pub name: Option<String>,
+    pub position: Option<(i32, i32, i32)>,
+    pub interest: Option<((i32, i32, i32), u16)>,
+}
+
+pub struct ResumeKey {
+    secret: String,
+}
+
+impl ResumeKey {
+    /// A random secret when `secret` is unset.
+    pub fn new(secret: Option<String>) -> Self {
+        let secret =
+            secret.unwrap_or_else(|| (0..4).map(|_| format!("{:016x}", random_u64())).collect());
+        Self { secret }
+    }
+
+    /// For the hot restart successor's environment.
+    pub fn secret(&self) -> &str {
+        &self.secret
+    }
+
+    /// A token for `session`, valid for `TOKEN_TTL`.
+    pub fn issue(&self, session: &Session) -> String {
+        self.issue_at(session, now() + TOKEN_TTL.as_secs())
+    }
+
+    fn issue_at(&self, session: &Session, expires: u64) -> String {
+        let position = match session.position {
+            Some((x, y, z)) => format!("{x},{y},{z}"),
+            None => String::new(),
+        };
+        let interest = match session.interest {
+            Some(((cx, cy, cz), radius)) => format!("{cx},{cy},{cz},{radius}"),
+            None => String::new(),
+        };
+        let name = hex(session.name.as_deref().unwrap_or("").as_bytes());
+        let body = format!("{expires}.{position}.{interest}.{name}");
+        let mac = hex(&self.mac(&body).finalize().into_bytes()[..MAC_BYTES]);
+        format!("{body}.{mac}")
+    }
+
+    /// The session in a token, `None` if it's malformed, forged or expired.
+    pub fn open(&self, token: &str) -> Option<Session> {
+        self.open_at(token, now())
+    }
+
+    fn open_at(&self, token: &str, now: u64) -> Option<Session> {
+        let (body, mac) = token.rsplit_once('.')?;
+        self.mac(body).verify_truncated_left(&unhex(mac)?).ok()?;
+
+        let mut fields = body.split('.');
+        let expires: u64 = fields.next()?.parse().ok()?;
+        if now > expires {
+            return None;
+        }
+        let position = match fields.next()? {
+            "" => None,
+            position => {
+                let [x, y, z] = numbers::<3>(position)?;
+                Some((x, y, z))
+            }
+        };
+        let interest = match fields.next()? {
+            "" => None,
+            interest => {
+                let [cx, cy, cz, radius] = numbers::<4>(interest)?;
+                Some(((cx, cy, cz), u16::try_from(radius).ok()?))
+            }
+        };
+        let name = String::from_utf8(unhex(fields.next()?)?).ok()?;
+        if fields.next().is_some() {
+            return None;
+        }
+
+        Some(Session {
+            name: Some(name).filter(|n| !n.is_empty()),
+            position,
+            interest,
+        })
+    }
+
+    fn mac(&self, body: &str) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.secret.as_bytes()).unwrap();
+        mac.update(body.as_bytes());
+        mac
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+// `N` comma-separated integers
+fn numbers<const N: usize>(text: &str) -> Option<[i32; N]> {
+    let mut out = [0; N];
+    let mut parts = text.split(',');
+    for n in &mut out {
+        *n = parts.next()?.parse().ok()?;
+    }
+    parts.next().is_none().then_some(out)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn unhex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_and_rejects_bad_tokens() {
+        let key = ResumeKey::new(Some("s3cret".into()));
+        let session = Session {
+            name: Some("é".repeat(16)),
+            position: Some((i32::MIN, -40, i32::MAX)),
+            interest: Some(((i32::MIN, i32::MIN, i32::MIN), 16)),
+        };
+        let token = key.issue_at(&session, 1000);
+        // The longest a token gets, still a protocol `str`
+        assert!(token.len() <= 255, "{} bytes", token.len());
+        assert_eq!(key.open_at(&token, 1000), Some(session));
+        assert_eq!(key.open_at(&token, 1001), None);
+
+        let anonymous = Session {
+            name: None,
+            position: Some((1, 2, 3)),
+            interest: None,
+        };
+        let token = key.issue_at(&anonymous, 1000);
+        assert_eq!(key.open_at(&token, 0), Some(anonymous.clone()));
+        let spawn = Session {
+            position: None,
+            ..anonymous
+        };
+        assert_eq!(key.open_at(&key.issue_at(&spawn, 1000), 0), Some(spawn));
+
+        // Another secret, or a changed position
+        assert_eq!(ResumeKey::new(None).open_at(&token, 0), None);
+        let forged = token.replacen(".1,2,3.", ".1,2,4.", 1);
+        assert_eq!(key.open_at(&forged, 0), None);
+        assert_eq!(key.open_at("garbage", 0), None);
+    }
+}
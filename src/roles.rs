@@ -0,0 +1,133 @@
+//! Moderation roles. Every named player is a `player` unless storage
+//! assigns them `moderator` or `admin` (`PUT /admin/roles/<name>`, or
+//! `SetRole` from another admin). Roles are looked up when a privileged
+//! command runs, so changes apply right away:
+//!
+//! - `Kick Name Reason...` and `Mute Name Minutes` / `Unmute Name` need a
+//!   moderator, and only reach players with a lower role. Chat takes them
+//!   as `/kick` and `/mute`, `/unmute`.
+//! - `SetRole Name Role` needs an admin.
+//!
+//! The admin API has its own tokens: `TELEBOXEL_ADMIN_TOKEN` for everything
+//! and `TELEBOXEL_MODERATOR_TOKEN` for the moderation routes.
+//!
+//! Names from `?name=` aren't verified, so without JWT logins (`jwt.rs`)
+//! roles are only as safe as whatever sits in front of the server checking
+//! them. With them, a token's roles claim is saved as the role on login.
+
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fmt,
+    str::FromStr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Longest mute, in minutes (a week).
+pub const MAX_MUTE_MINUTES: u32 = 7 * 24 * 60;
+
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    #[default]
+    Player,
+    Moderator,
+    Admin,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Permission {
+    Kick,
+    Mute,
+    AssignRoles,
+}
+
+impl Permission {
+    /// The lowest role that has it.
+    pub fn role(self) -> Role {
+        match self {
+            Permission::Kick | Permission::Mute => Role::Moderator,
+            Permission::AssignRoles => Role::Admin,
+        }
+    }
+}
+
+impl Role {
+    pub fn can(self, permission: Permission) -> bool {
+        self >= permission.role()
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Role::Player => "player",
+            Role::Moderator => "moderator",
+            Role::Admin => "admin",
+        })
+    }
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "player" => Ok(Role::Player),
+            "moderator" => Ok(Role::Moderator),
+            "admin" => Ok(Role::Admin),
+            _ => Err("Invalid role, expected player, moderator or admin".to_string()),
+        }
+    }
+}
+
+/// Chat mutes by player name, shared by every world. They last until they
+/// run out or the server stops.
+#[derive(Default)]
+pub struct Mutes(Mutex<HashMap<String, Instant>>);
+
+impl Mutes {
+    pub fn mute(&self, name: String, until: Instant) {
+        self.0.lock().unwrap().insert(name, until);
+    }
+
+    /// `false` if `name` wasn't muted.
+    pub fn unmute(&self, name: &str) -> bool {
+        self.0.lock().unwrap().remove(name).is_some()
+    }
+
+    /// How much longer `name` is muted at `now`, `None` if not muted.
+    pub fn remaining(&self, name: &str, now: Instant) -> Option<Duration> {
+        let mut mutes = self.0.lock().unwrap();
+        let until = *mutes.get(name)?;
+        if until <= now {
+            mutes.remove(name);
+            return None;
+        }
+        Some(until - now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checks_permissions_and_mutes() {
+        assert!(!Role::Player.can(Permission::Kick));
+        assert!(Role::Moderator.can(Permission::Mute));
+        assert!(!Role::Moderator.can(Permission::AssignRoles));
+        assert!(Role::Admin.can(Permission::AssignRoles));
+        assert_eq!("moderator".parse(), Ok(Role::Moderator));
+        assert!("root".parse::<Role>().is_err());
+
+        let mutes = Mutes::default();
+        let now = Instant::now();
+        mutes.mute("eve".into(), now + Duration::from_secs(60));
+        assert_eq!(mutes.remaining("eve", now), Some(Duration::from_secs(60)));
+        assert_eq!(mutes.remaining("eve", now + Duration::from_secs(60)), None);
+        assert!(!mutes.unmute("eve"));
+        assert_eq!(mutes.remaining("bob", now), None);
+    }
+}
@@ -0,0 +1,244 @@
+//! Rollback rooms (`RoomCreate Name rollback`) for fighting games and
+//! platformers, GGPO-style: clients simulate ahead on predicted inputs and
+//! roll back when the real ones arrive, the server relays and confirms.
+//! Like lockstep rooms (`lockstep.rs`) it doesn't simulate them, but it
+//! doesn't hold anyone back either:
+//!
+//! - The room counts frames, one per server tick, from 0. Players get
+//!   `ROLLBACK` on joining: the frame it's at and the input delay, the
+//!   frames ahead players send their inputs for (`input_delay` in
+//!   `templates.rs`, `DEFAULT_DELAY` otherwise).
+//! - `Input Frame Data` goes out to the other players as `ROLLBACK_INPUT`
+//!   right away, for them to predict with and roll back to.
+//! - Once the room reaches a frame with every input in, or `MAX_ROLLBACK`
+//!   frames later without the missing ones, it sends `ROLLBACK_CONFIRM`:
+//!   the frame's inputs in player id order and their hash (the lockstep
+//!   one). Missing inputs repeat the player's last confirmed one, and
+//!   inputs for confirmed frames are refused, so confirmations are final:
+//!   clients roll back to them, never past them.
+//!
+//! There's no state for joiners to start from: players are waited for from
+//! the frame their first input can reach, and sessions are meant to start
+//! together.
+
+use crate::lockstep::{LockstepInput, hash};
+use std::{collections::BTreeMap, fmt};
+
+/// Longest input, in bytes.
+pub const MAX_INPUT_LEN: usize = 200;
+/// Frames players send their inputs ahead without a template's.
+pub const DEFAULT_DELAY: u32 = 2;
+/// Largest input delay.
+pub const MAX_DELAY: u32 = 15;
+/// How far past the room's frame inputs may be sent.
+pub const MAX_AHEAD: u32 = 64;
+/// Frames a frame waits for missing inputs before they're filled in.
+pub const MAX_ROLLBACK: u32 = 8;
+
+/// A confirmed frame.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Confirmed {
+    pub frame: u32,
+    pub hash: u32,
+    pub inputs: Vec<LockstepInput>,
+}
+
+#[derive(PartialEq, Eq, Debug)]
+pub enum RollbackError {
+    /// The frame was already confirmed.
+    Late {
+        confirmed: u32,
+    },
+    /// More than `MAX_AHEAD` past the room's frame.
+    TooFar {
+        frame: u32,
+    },
+    Twice,
+    TooLong,
+}
+
+impl fmt::Display for RollbackError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RollbackError::Late { confirmed } => {
+                write!(f, "Too late, frame {confirmed} is confirmed")
+            }
+            RollbackError::TooFar { frame } => {
+                write!(f, "Too far ahead, at frame {frame} (max {MAX_AHEAD} ahead)")
+            }
+            RollbackError::Twice => write!(f, "Already sent input for that frame"),
+            RollbackError::TooLong => write!(f, "Too long"),
+        }
+    }
+}
+
+struct Waited {
+    // The first frame they're waited for
+    from: u32,
+    // Their last confirmed input, repeated when one is missing
+    last: String,
+}
+
+pub struct Rollback {
+    delay: u32,
+    // The room's frame
+    frame: u32,
+    // Confirmed up to here, `None` before the first
+    confirmed: Option<u32>,
+    players: BTreeMap<u32, Waited>,
+    // By frame, then player id
+    inputs: BTreeMap<u32, BTreeMap<u32, String>>,
+}
+
+impl Rollback {
+    pub fn new(delay: u32) -> Self {
+        Self {
+            delay: delay.min(MAX_DELAY),
+            frame: 0,
+            confirmed: None,
+            players: BTreeMap::new(),
+            inputs: BTreeMap::new(),
+        }
+    }
+
+    /// Adds player `id`, returning the room's frame and the input delay.
+    pub fn join(&mut self, id: u32) -> (u32, u32) {
+        let from = self.frame + self.delay;
+        let last = String::new();
+        self.players.insert(id, Waited { from, last });
+        (self.frame, self.delay)
+    }
+
+    /// Stops waiting for player `id`. Inputs it already sent still count.
+    pub fn leave(&mut self, id: u32) {
+        self.players.remove(&id);
+    }
+
+    /// Takes player `id`'s input for `frame`, to relay to the others.
+    pub fn input(&mut self, id: u32, frame: u32, data: String) -> Result<(), RollbackError> {
+        if data.len() > MAX_INPUT_LEN {
+            return Err(RollbackError::TooLong);
+        }
+        if let Some(confirmed) = self.confirmed.filter(|&c| frame <= c) {
+            return Err(RollbackError::Late { confirmed });
+        }
+        if frame.saturating_sub(self.frame) > MAX_AHEAD {
+            return Err(RollbackError::TooFar { frame: self.frame });
+        }
+        let inputs = self.inputs.entry(frame).or_default();
+        if inputs.contains_key(&id) {
+            return Err(RollbackError::Twice);
+        }
+        inputs.insert(id, data);
+        Ok(())
+    }
+
+    /// Called every server tick, moves on a frame and returns the frames
+    /// confirmed: the ones up to it with every input in, and any
+    /// `MAX_ROLLBACK` behind with the missing ones filled in.
+    pub fn advance(&mut self) -> Vec<Confirmed> {
+        let mut confirmed = Vec::new();
+        if self.players.is_empty() {
+            return confirmed;
+        }
+        self.frame += 1;
+        loop {
+            let next = self.confirmed.map_or(0, |c| c + 1);
+            if next > self.frame {
+                break;
+            }
+            if !self.ready(next) && self.frame - next < MAX_ROLLBACK {
+                break;
+            }
+            confirmed.push(self.confirm(next));
+        }
+        confirmed
+    }
+
+    fn ready(&self, frame: u32) -> bool {
+        let inputs = self.inputs.get(&frame);
+        self.players
+            .iter()
+            .filter(|(_, waited)| waited.from <= frame)
+            .all(|(id, _)| inputs.is_some_and(|inputs| inputs.contains_key(id)))
+    }
+
+    fn confirm(&mut self, frame: u32) -> Confirmed {
+        let mut inputs = self.inputs.remove(&frame).unwrap_or_default();
+        for (&id, waited) in &mut self.players {
+            if waited.from > frame {
+                continue;
+            }
+            match inputs.get(&id) {
+                Some(data) => waited.last.clone_from(data),
+                None => {
+                    inputs.insert(id, waited.last.clone());
+                }
+            }
+        }
+        let inputs: Vec<_> = inputs
+            .into_iter()
+            .map(|(player, data)| LockstepInput { player, data })
+            .collect();
+        self.confirmed = Some(frame);
+        Confirmed {
+            frame,
+            hash: hash(frame, &inputs),
+            inputs,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn confirms_frames_filling_late_inputs() {
+        let mut room = Rollback::new(2);
+        assert!(room.advance().is_empty());
+        assert_eq!(room.join(1), (0, 2));
+        assert_eq!(room.join(2), (0, 2));
+
+        // Nobody is waited for before the delay
+        let confirmed = room.advance();
+        assert_eq!(
+            confirmed.iter().map(|c| c.frame).collect::<Vec<_>>(),
+            [0, 1]
+        );
+        assert!(confirmed[0].inputs.is_empty());
+        assert_eq!(
+            room.input(1, 1, String::new()),
+            Err(RollbackError::Late { confirmed: 1 })
+        );
+        assert_eq!(
+            room.input(1, 99, String::new()),
+            Err(RollbackError::TooFar { frame: 1 })
+        );
+
+        room.input(1, 2, "punch".into()).unwrap();
+        room.input(2, 2, "block".into()).unwrap();
+        assert_eq!(room.input(2, 2, "kick".into()), Err(RollbackError::Twice));
+        room.input(1, 3, "punch".into()).unwrap();
+        let confirmed = room.advance();
+        assert_eq!(confirmed.len(), 1);
+        assert_eq!(confirmed[0].inputs[1].data, "block");
+        assert_eq!(confirmed[0].hash, hash(2, &confirmed[0].inputs));
+
+        // Player 2's input for frame 3 never comes, its last one stands in
+        for _ in 0..MAX_ROLLBACK {
+            assert!(room.advance().is_empty());
+        }
+        let confirmed = room.advance();
+        assert_eq!(confirmed[0].frame, 3);
+        let inputs: Vec<_> = confirmed[0].inputs.iter().map(|i| &i.data[..]).collect();
+        assert_eq!(inputs, ["punch", "block"]);
+
+        // Frames no player is waited for are confirmed as they're reached
+        room.leave(1);
+        room.leave(2);
+        room.join(3);
+        let frames: Vec<_> = room.advance().iter().map(|c| c.frame).collect();
+        assert_eq!(frames, (4..=12).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,315 @@
+//! Versioned on-disk save format.
+//!
+//! Every save file starts with an 8 byte header:
+//!
+//! | bytes | field                            |
+//! |-------|----------------------------------|
+//! | 0..4  | magic `TBXS`                     |
+//! | 4..6  | format version, `u16` LE         |
+//! | 6     | kind (`1` = chunk, `2` = claims) |
+//! | 7     | reserved, `0`                    |
+//!
+//! Older versions are upgraded on load by running the body through
+//! `MIGRATIONS` one version at a time, so a save written by any past release
+//! can still be read. When changing a body layout: bump `CURRENT_VERSION`,
+//! append a migration from the previous version, and add a fixture for the
+//! new version next to the existing ones in `tests/fixtures/`.
+//!
+//! Chunk body, version 1: `i32` cx, cy, cz, then 4096 `u16` block ids, all LE.
+//! The claims body is documented in `claims`.
+//!
+//! A world directory holds one file per chunk at
+//! `<world_dir>/chunks/<cx>_<cy>_<cz>.tbx`.
+
+use crate::chunk::{CHUNK_VOLUME, Chunk, ChunkPos, ChunkStore};
+use std::{
+    fmt, fs,
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+};
+
+pub const MAGIC: [u8; 4] = *b"TBXS";
+pub const CURRENT_VERSION: u16 = 1;
+pub const HEADER_LEN: usize = 8;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(u8)]
+pub enum SaveKind {
+    Chunk = 1,
+    Claims = 2,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum SaveError {
+    Truncated,
+    BadMagic,
+    /// Written by a newer release, or version 0.
+    UnsupportedVersion(u16),
+    WrongKind(u8),
+    Invalid(&'static str),
+}
+
+impl fmt::Display for SaveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SaveError::Truncated => write!(f, "save file is truncated"),
+            SaveError::BadMagic => write!(f, "not a teleboxel save file"),
+            SaveError::UnsupportedVersion(v) => write!(
+                f,
+                "unsupported save version {v} (this build reads 1..={CURRENT_VERSION})"
+            ),
+            SaveError::WrongKind(k) => write!(f, "unexpected save kind {k}"),
+            SaveError::Invalid(why) => write!(f, "invalid save body: {why}"),
+        }
+    }
+}
+
+impl std::error::Error for SaveError {}
+
+/// Upgrades a body of the given kind by exactly one version.
+type Migration = fn(SaveKind, Vec<u8>) -> Result<Vec<u8>, SaveError>;
+
+/// `MIGRATIONS[n]` upgrades a body from version `n + 1` to `n + 2`.
+const MIGRATIONS: &[Migration] = &[];
+
+const _: () = assert!(MIGRATIONS.len() + 1 == CURRENT_VERSION as usize);
+
+/// Checks the header and returns the body upgraded to `CURRENT_VERSION`.
+pub fn read(data: &[u8], kind: SaveKind) -> Result<Vec<u8>, SaveError> {
+    upgrade(data, kind, MIGRATIONS)
+}
+
+// With the migrations up to the current version passed in, for tests to
+// run a chain `MIGRATIONS` doesn't have yet
+fn upgrade(data: &[u8], kind: SaveKind, migrations: &[Migration]) -> Result<Vec<u8>, SaveError> {
+    let current = migrations.len() as u16 + 1;
+    let header = data.get(..HEADER_LEN).ok_or(SaveError::Truncated)?;
+    if header[..4] != MAGIC {
+        return Err(SaveError::BadMagic);
+    }
+
+    let version = u16::from_le_bytes([header[4], header[5]]);
+    if version == 0 || version > current {
+        return Err(SaveError::UnsupportedVersion(version));
+    }
+
+    if header[6] != kind as u8 {
+        return Err(SaveError::WrongKind(header[6]));
+    }
+
+    let mut body = data[HEADER_LEN..].to_vec();
+    for migration in &migrations[version as usize - 1..] {
+        body = migration(kind, body)?;
+    }
+
+    Ok(body)
+}
+
+/// Starts a save buffer with a current-version header.
+pub fn write_header(buf: &mut Vec<u8>, kind: SaveKind) {
+    buf.extend_from_slice(&MAGIC);
+    buf.extend_from_slice(&CURRENT_VERSION.to_le_bytes());
+    buf.push(kind as u8);
+    buf.push(0);
+}
+
+pub fn encode_chunk(pos: ChunkPos, chunk: &Chunk) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(HEADER_LEN + 12 + CHUNK_VOLUME * 2);
+    write_header(&mut buf, SaveKind::Chunk);
+
+    let (cx, cy, cz) = pos;
+    buf.extend_from_slice(&cx.to_le_bytes());
+    buf.extend_from_slice(&cy.to_le_bytes());
+    buf.extend_from_slice(&cz.to_le_bytes());
+    for block in chunk.blocks() {
+        buf.extend_from_slice(&block.to_le_bytes());
+    }
+
+    buf
+}
+
+pub fn decode_chunk(data: &[u8]) -> Result<(ChunkPos, Chunk), SaveError> {
+    let body = read(data, SaveKind::Chunk)?;
+    if body.len() != 12 + CHUNK_VOLUME * 2 {
+        return Err(SaveError::Invalid("chunk body has the wrong length"));
+    }
+
+    let i32_at = |i: usize| i32::from_le_bytes([body[i], body[i + 1], body[i + 2], body[i + 3]]);
+    let pos = (i32_at(0), i32_at(4), i32_at(8));
+
+    let blocks = body[12..]
+        .chunks_exact(2)
+        .map(|b| u16::from_le_bytes([b[0], b[1]]))
+        .collect();
+    let chunk = Chunk::from_blocks(blocks).ok_or(SaveError::Invalid("wrong block count"))?;
+
+    Ok((pos, chunk))
+}
+
+pub fn chunk_path(world_dir: &Path, pos: ChunkPos) -> PathBuf {
+    let (cx, cy, cz) = pos;
+    world_dir.join("chunks").join(format!("{cx}_{cy}_{cz}.tbx"))
+}
+
+/// Loads every chunk file in the world directory. A missing directory is an
+/// empty world.
+pub fn load_world(world_dir: &Path) -> io::Result<ChunkStore> {
+    let mut store = ChunkStore::new();
+
+    let entries = match fs::read_dir(world_dir.join("chunks")) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(store),
+        Err(e) => return Err(e),
+    };
+
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().is_none_or(|ext| ext != "tbx") {
+            continue;
+        }
+
+        let (pos, chunk) = decode_chunk(&fs::read(&path)?).map_err(|e| {
+            io::Error::new(ErrorKind::InvalidData, format!("{}: {e}", path.display()))
+        })?;
+        store.insert(pos, chunk);
+    }
+
+    Ok(store)
+}
+
+/// Writes every chunk of the store into the world directory. Each file is
+/// written to a temporary name first so a crash never leaves a torn chunk.
+pub fn save_world(world_dir: &Path, store: &ChunkStore) -> io::Result<()> {
+    fs::create_dir_all(world_dir.join("chunks"))?;
+
+    for (&pos, chunk) in store.iter() {
+        write_chunk(world_dir, pos, chunk)?;
+    }
+
+    Ok(())
+}
+
+pub fn write_chunk(world_dir: &Path, pos: ChunkPos, chunk: &Chunk) -> io::Result<()> {
+    let path = chunk_path(world_dir, pos);
+    let tmp = path.with_extension("tbx.tmp");
+    fs::write(&tmp, encode_chunk(pos, chunk))?;
+    fs::rename(&tmp, &path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Fixture chunk files, one per format version ever released. Each holds
+    /// chunk (1, -2, 3) with a stone (`1`) floor at y = 0 and block `7` at
+    /// (1, 2, 3).
+    const CHUNK_FIXTURES: &[(u16, &[u8])] =
+        &[(1, include_bytes!("../tests/fixtures/chunk_v1.tbx"))];
+
+    fn expected_chunk() -> Chunk {
+        let mut chunk = Chunk::empty();
+        for x in 0..16 {
+            for z in 0..16 {
+                chunk.set(x, 0, z, 1);
+            }
+        }
+        chunk.set(1, 2, 3, 7);
+        chunk
+    }
+
+    #[test]
+    fn every_historical_chunk_version_loads() {
+        let versions: Vec<u16> = CHUNK_FIXTURES.iter().map(|(v, _)| *v).collect();
+        assert_eq!(versions, (1..=CURRENT_VERSION).collect::<Vec<_>>());
+
+        for (version, data) in CHUNK_FIXTURES {
+            let (pos, chunk) = decode_chunk(data)
+                .unwrap_or_else(|e| panic!("fixture v{version} failed to load: {e}"));
+            assert_eq!(pos, (1, -2, 3), "fixture v{version}");
+            assert_eq!(chunk, expected_chunk(), "fixture v{version}");
+        }
+    }
+
+    #[test]
+    fn chunk_round_trips_at_current_version() {
+        let data = encode_chunk((-5, 0, 9), &expected_chunk());
+        assert_eq!(u16::from_le_bytes([data[4], data[5]]), CURRENT_VERSION);
+        assert_eq!(decode_chunk(&data), Ok(((-5, 0, 9), expected_chunk())));
+    }
+
+    #[test]
+    fn rejects_bad_headers() {
+        let mut data = encode_chunk((0, 0, 0), &Chunk::empty());
+
+        assert_eq!(decode_chunk(&data[..5]), Err(SaveError::Truncated));
+
+        data[4..6].copy_from_slice(&(CURRENT_VERSION + 1).to_le_bytes());
+        assert_eq!(
+            decode_chunk(&data),
+            Err(SaveError::UnsupportedVersion(CURRENT_VERSION + 1))
+        );
+
+        data[0] = b'X';
+        assert_eq!(decode_chunk(&data), Err(SaveError::BadMagic));
+    }
+
+    // Version 2 of a made up layout: a block count before the v1 body
+    fn counted(kind: SaveKind, body: Vec<u8>) -> Result<Vec<u8>, SaveError> {
+        if kind != SaveKind::Chunk {
+            return Err(SaveError::WrongKind(kind as u8));
+        }
+        let count = body.len().checked_sub(12).ok_or(SaveError::Truncated)? / 2;
+        let mut upgraded = (count as u32).to_le_bytes().to_vec();
+        upgraded.extend(body);
+        Ok(upgraded)
+    }
+
+    #[test]
+    fn migrates_through_the_chain() {
+        let v1 = encode_chunk((1, -2, 3), &expected_chunk());
+        let body = upgrade(&v1, SaveKind::Chunk, &[counted]).unwrap();
+        assert_eq!(body[..4], (CHUNK_VOLUME as u32).to_le_bytes());
+        assert_eq!(body[4..], v1[HEADER_LEN..]);
+
+        // Already at version 2, nothing to run
+        let mut v2 = v1[..HEADER_LEN].to_vec();
+        v2[4..6].copy_from_slice(&2u16.to_le_bytes());
+        v2.extend(&body);
+        assert_eq!(upgrade(&v2, SaveKind::Chunk, &[counted]), Ok(body));
+
+        // Migration errors stop the load
+        let mut claims = v1.clone();
+        claims[6] = SaveKind::Claims as u8;
+        assert_eq!(
+            upgrade(&claims, SaveKind::Claims, &[counted]),
+            Err(SaveError::WrongKind(SaveKind::Claims as u8))
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_versions() {
+        let mut data = encode_chunk((0, 0, 0), &Chunk::empty());
+        for version in [0, CURRENT_VERSION + 1, u16::MAX] {
+            data[4..6].copy_from_slice(&version.to_le_bytes());
+            assert_eq!(
+                read(&data, SaveKind::Chunk),
+                Err(SaveError::UnsupportedVersion(version))
+            );
+        }
+        // One past a longer chain too
+        data[4..6].copy_from_slice(&3u16.to_le_bytes());
+        assert_eq!(
+            upgrade(&data, SaveKind::Chunk, &[counted]),
+            Err(SaveError::UnsupportedVersion(3))
+        );
+    }
+
+    #[test]
+    fn rejects_truncated_body() {
+        let data = encode_chunk((0, 0, 0), &Chunk::empty());
+        assert!(matches!(
+            decode_chunk(&data[..data.len() - 1]),
+            Err(SaveError::Invalid(_))
+        ));
+    }
+}
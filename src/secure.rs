@@ -0,0 +1,720 @@
+//! Encrypted sessions, for deployments that can't trust what sits between
+//! clients and the server even over TLS. A client offers an X25519 public
+//! key as `?key=<base64url>`; the server answers with its own, signed with
+//! its server key, in plain text before the hello (`<key>.<signature>` in
+//! binary mode, `{"type":"key","key":...,"signature":...}` in JSON mode),
+//! and from then on every text and binary message each way is sealed with
+//! ChaCha20-Poly1305.
+//!
+//! The server key (`TELEBOXEL_SERVER_KEY`, an Ed25519 secret key made with
+//! `teleboxel server-key`) is what clients trust: they pin its public key
+//! and check the signature, over both X25519 public keys, before using the
+//! session (see `check_offer`). Something in the middle swapping in its own
+//! key can't sign it, and a signature from another session doesn't cover
+//! this client's key. Without a server key the server doesn't start with
+//! encryption or message authentication on.
+//!
+//! `TELEBOXEL_ENCRYPTION` is `off` (the default, `?key=` is ignored),
+//! `optional` or `required` (connections without `?key=` are refused).
+//!
+//! Keys come from HKDF-SHA256 over the shared secret, salted with both
+//! X25519 public keys and the server key's public key, so they only match
+//! for clients that checked the offer against the pinned key:
+//! `teleboxel client` seals client messages, `teleboxel server` the
+//! server's. A sealed message is a binary frame holding the ciphertext of
+//! the original opcode (1 text, 2 binary) and payload, then the 16 byte
+//! tag. Nonces are a counter per direction (4 zero bytes, then
+//! the count as u64 little endian), so a message that's dropped, repeated or
+//! reordered fails to open and ends the connection. Pings and closes aren't
+//! sealed.
+//!
+//! Deployments that only need to know who sent what, e.g. behind a proxy
+//! that inspects traffic, can have clients sign instead: with
+//! `TELEBOXEL_MESSAGE_AUTH`, `?mac_key=` makes the same key exchange but
+//! only client messages change. Each goes as a binary frame with the
+//! original opcode, a u64 little endian sequence number, the payload and an
+//! HMAC-SHA256 tag over all of it, keyed with `teleboxel mac`. Sequence
+//! numbers must go up, so forged and replayed messages are dropped (and
+//! counted in `/admin/metrics`) while the player stays connected. Sealed
+//! sessions satisfy `TELEBOXEL_MESSAGE_AUTH=required` too.
+//!
+//! Server X25519 keys are made up per session.
+
+use crate::traffic::Traffic;
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use chacha20poly1305::{AeadInPlace, ChaCha20Poly1305, Nonce};
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+use fastwebsockets::{FragmentCollector, Frame, OpCode, Payload, WebSocketError};
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::{fmt, io, str::FromStr, sync::Arc};
+use tokio::io::{AsyncRead, AsyncWrite};
+use x25519_dalek::{PublicKey, StaticSecret};
+
+pub const KEY_LEN: usize = 32;
+const SIGNATURE_LEN: usize = 64;
+// Opcode and sequence number, before the payload of signed messages
+const SIGNED_HEADER: usize = 9;
+const MAC_LEN: usize = 32;
+
+/// Whether clients may or must encrypt (`TELEBOXEL_ENCRYPTION`) or sign
+/// (`TELEBOXEL_MESSAGE_AUTH`).
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub enum Policy {
+    #[default]
+    Off,
+    Optional,
+    Required,
+}
+
+impl FromStr for Policy {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        match s {
+            "off" => Ok(Policy::Off),
+            "optional" => Ok(Policy::Optional),
+            "required" => Ok(Policy::Required),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum SecureError {
+    /// Not a base64url X25519 public key, or not a server key.
+    BadKey,
+    /// The server's key isn't signed by the pinned server key.
+    Forged,
+    /// The shared secret came out all zeros, the client key is degenerate.
+    WeakKey,
+    /// Tampered, replayed, reordered or sealed with another key.
+    Open,
+    /// Signed correctly, but the sequence number was already used.
+    Replay,
+    EncryptionRequired,
+    MessageAuthRequired,
+}
+
+impl SecureError {
+    /// Label for the dropped message counters.
+    pub fn reason(&self) -> &'static str {
+        match self {
+            SecureError::Replay => "replay",
+            _ => "mac",
+        }
+    }
+}
+
+impl fmt::Display for SecureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SecureError::BadKey => write!(f, "invalid key"),
+            SecureError::Forged => write!(f, "server key not signed by the pinned key"),
+            SecureError::WeakKey => write!(f, "weak key"),
+            SecureError::Open => write!(f, "message failed to open"),
+            SecureError::Replay => write!(f, "message replayed"),
+            SecureError::EncryptionRequired => write!(f, "encryption required"),
+            SecureError::MessageAuthRequired => write!(f, "message authentication required"),
+        }
+    }
+}
+
+impl std::error::Error for SecureError {}
+
+/// A public key as sent, base64url without padding.
+pub fn encode_key(key: &[u8; KEY_LEN]) -> String {
+    URL_SAFE_NO_PAD.encode(key)
+}
+
+/// The server's long-term Ed25519 key, `TELEBOXEL_SERVER_KEY`. Signs the
+/// key of every session.
+pub struct ServerKey(SigningKey);
+
+impl ServerKey {
+    /// A base64url secret key.
+    pub fn parse(secret: &str) -> Result<Self, SecureError> {
+        Ok(ServerKey(SigningKey::from_bytes(&parse_key(secret)?)))
+    }
+
+    pub fn generate() -> Result<Self, SecureError> {
+        let mut secret = [0; KEY_LEN];
+        getrandom::getrandom(&mut secret).map_err(|_| SecureError::BadKey)?;
+        Ok(ServerKey(SigningKey::from_bytes(&secret)))
+    }
+
+    /// For `TELEBOXEL_SERVER_KEY`.
+    pub fn secret(&self) -> String {
+        encode_key(self.0.as_bytes())
+    }
+
+    /// For clients to pin.
+    pub fn public(&self) -> String {
+        encode_key(self.0.verifying_key().as_bytes())
+    }
+}
+
+/// The server's side of the key exchange: its X25519 public key for the
+/// session and the server key's signature over it.
+pub struct Offer {
+    key: [u8; KEY_LEN],
+    signature: [u8; SIGNATURE_LEN],
+}
+
+impl Offer {
+    pub fn key(&self) -> String {
+        encode_key(&self.key)
+    }
+
+    pub fn signature(&self) -> String {
+        URL_SAFE_NO_PAD.encode(self.signature)
+    }
+
+    /// As sent in binary mode.
+    pub fn encode(&self) -> String {
+        format!("{}.{}", self.key(), self.signature())
+    }
+}
+
+// What the server key signs: both keys, so it holds for this client only
+fn signed_keys(client: &[u8; KEY_LEN], server: &[u8; KEY_LEN]) -> Vec<u8> {
+    [b"teleboxel key".as_slice(), client, server].concat()
+}
+
+/// The client's check of the server's offer, as sent in binary mode,
+/// against the pinned server public key and `client`, the key it offered.
+/// Returns the server's X25519 key once the signature holds.
+pub fn check_offer(
+    pinned: &str,
+    client: &[u8; KEY_LEN],
+    offer: &str,
+) -> Result<[u8; KEY_LEN], SecureError> {
+    let pinned = VerifyingKey::from_bytes(&parse_key(pinned)?).map_err(|_| SecureError::BadKey)?;
+    let (key, signature) = offer.split_once('.').ok_or(SecureError::BadKey)?;
+    let key = parse_key(key)?;
+    let signature = URL_SAFE_NO_PAD
+        .decode(signature)
+        .ok()
+        .and_then(|s| Signature::from_slice(&s).ok())
+        .ok_or(SecureError::BadKey)?;
+    pinned
+        .verify_strict(&signed_keys(client, &key), &signature)
+        .map_err(|_| SecureError::Forged)?;
+    Ok(key)
+}
+
+/// Picks the protection from `?key=` and `?mac_key=` and agrees on its
+/// keys. Returns it with the server's signed offer for the client, or
+/// `None` for a plain session. Nothing is protected without `server_key`.
+pub fn negotiate(
+    encryption: Policy,
+    message_auth: Policy,
+    server_key: Option<&ServerKey>,
+    key: Option<&str>,
+    mac_key: Option<&str>,
+) -> Result<Option<(Protection, Offer)>, SecureError> {
+    let mut secret = [0; KEY_LEN];
+    match (key, mac_key, server_key) {
+        (Some(key), _, Some(server_key)) if encryption != Policy::Off => {
+            getrandom::getrandom(&mut secret).map_err(|_| SecureError::BadKey)?;
+            let (keys, offer) = SessionKeys::accept_with(server_key, &parse_key(key)?, secret)?;
+            Ok(Some((Protection::Sealed(keys), offer)))
+        }
+        _ if encryption == Policy::Required => Err(SecureError::EncryptionRequired),
+        (_, Some(key), Some(server_key)) if message_auth != Policy::Off => {
+            getrandom::getrandom(&mut secret).map_err(|_| SecureError::BadKey)?;
+            let (mac, offer) = MessageMac::accept_with(server_key, &parse_key(key)?, secret)?;
+            Ok(Some((Protection::Signed(mac), offer)))
+        }
+        _ if message_auth == Policy::Required => Err(SecureError::MessageAuthRequired),
+        _ => Ok(None),
+    }
+}
+
+/// Parses `?key=`.
+pub fn parse_key(key: &str) -> Result<[u8; KEY_LEN], SecureError> {
+    let key = URL_SAFE_NO_PAD
+        .decode(key)
+        .map_err(|_| SecureError::BadKey)?;
+    key.try_into().map_err(|_| SecureError::BadKey)
+}
+
+// Sealing cipher and message count for one direction
+struct Direction {
+    cipher: ChaCha20Poly1305,
+    count: u64,
+}
+
+impl Direction {
+    fn new(key: [u8; 32]) -> Self {
+        Direction {
+            cipher: chacha20poly1305::KeyInit::new(&key.into()),
+            count: 0,
+        }
+    }
+
+    fn nonce(&mut self) -> Nonce {
+        let mut nonce = [0; 12];
+        nonce[4..].copy_from_slice(&self.count.to_le_bytes());
+        self.count += 1;
+        nonce.into()
+    }
+}
+
+/// A session's keys, the server side.
+pub struct SessionKeys {
+    send: Direction,
+    recv: Direction,
+}
+
+// The shared secret with a client, salted with both public keys and the
+// server key that signed them
+struct Agreement {
+    shared: [u8; KEY_LEN],
+    salt: Vec<u8>,
+}
+
+impl Agreement {
+    fn key(&self, info: &[u8]) -> [u8; 32] {
+        let mut key = [0; 32];
+        // One block, always short enough
+        Hkdf::<Sha256>::new(Some(&self.salt), &self.shared)
+            .expand(info, &mut key)
+            .unwrap();
+        key
+    }
+}
+
+// Agrees with a client's public key, returning the server's signed offer
+fn agree(
+    server_key: &ServerKey,
+    client: &[u8; KEY_LEN],
+    secret: [u8; KEY_LEN],
+) -> Result<(Agreement, Offer), SecureError> {
+    let secret = StaticSecret::from(secret);
+    let server = PublicKey::from(&secret).to_bytes();
+    let shared = secret.diffie_hellman(&PublicKey::from(*client));
+    if !shared.was_contributory() {
+        return Err(SecureError::WeakKey);
+    }
+    let offer = Offer {
+        key: server,
+        signature: server_key.0.sign(&signed_keys(client, &server)).to_bytes(),
+    };
+    let mut salt = client.to_vec();
+    salt.extend_from_slice(&server);
+    salt.extend_from_slice(server_key.0.verifying_key().as_bytes());
+    let agreement = Agreement {
+        shared: shared.to_bytes(),
+        salt,
+    };
+    Ok((agreement, offer))
+}
+
+/// How a session's messages are protected, see `negotiate`.
+pub enum Protection {
+    Sealed(SessionKeys),
+    Signed(MessageMac),
+}
+
+impl SessionKeys {
+    fn accept_with(
+        server_key: &ServerKey,
+        client: &[u8; KEY_LEN],
+        secret: [u8; KEY_LEN],
+    ) -> Result<(Self, Offer), SecureError> {
+        let (agreement, offer) = agree(server_key, client, secret)?;
+        let keys = SessionKeys {
+            send: Direction::new(agreement.key(b"teleboxel server")),
+            recv: Direction::new(agreement.key(b"teleboxel client")),
+        };
+        Ok((keys, offer))
+    }
+
+    /// The next message out: `opcode` and `payload` as the bytes of a
+    /// binary frame.
+    pub fn seal(&mut self, opcode: OpCode, payload: &[u8]) -> Vec<u8> {
+        let mut sealed = Vec::with_capacity(1 + payload.len() + 16);
+        sealed.push(opcode as u8);
+        sealed.extend_from_slice(payload);
+        let nonce = self.send.nonce();
+        // Only fails past 256 GB
+        self.send
+            .cipher
+            .encrypt_in_place(&nonce, &[], &mut sealed)
+            .unwrap();
+        sealed
+    }
+
+    /// The next message in, from a binary frame's bytes.
+    pub fn open(&mut self, sealed: &[u8]) -> Result<(OpCode, Vec<u8>), SecureError> {
+        let nonce = self.recv.nonce();
+        let mut plain = sealed.to_vec();
+        self.recv
+            .cipher
+            .decrypt_in_place(&nonce, &[], &mut plain)
+            .map_err(|_| SecureError::Open)?;
+        let opcode = match plain.first() {
+            Some(1) => OpCode::Text,
+            Some(2) => OpCode::Binary,
+            _ => return Err(SecureError::Open),
+        };
+        plain.remove(0);
+        Ok((opcode, plain))
+    }
+}
+
+/// Checks signed client messages, the server side.
+pub struct MessageMac {
+    key: [u8; 32],
+    // Lowest sequence number not seen yet
+    next: u64,
+}
+
+impl MessageMac {
+    fn accept_with(
+        server_key: &ServerKey,
+        client: &[u8; KEY_LEN],
+        secret: [u8; KEY_LEN],
+    ) -> Result<(Self, Offer), SecureError> {
+        let (agreement, offer) = agree(server_key, client, secret)?;
+        let key = agreement.key(b"teleboxel mac");
+        Ok((MessageMac { key, next: 0 }, offer))
+    }
+
+    /// The opcode and payload of a signed message, from a binary frame's
+    /// bytes.
+    pub fn check(&mut self, signed: &[u8]) -> Result<(OpCode, Vec<u8>), SecureError> {
+        let split = signed
+            .len()
+            .checked_sub(MAC_LEN)
+            .filter(|&split| split >= SIGNED_HEADER)
+            .ok_or(SecureError::Open)?;
+        let (message, tag) = signed.split_at(split);
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.key).unwrap();
+        mac.update(message);
+        mac.verify_slice(tag).map_err(|_| SecureError::Open)?;
+
+        let opcode = match message[0] {
+            1 => OpCode::Text,
+            2 => OpCode::Binary,
+            _ => return Err(SecureError::Open),
+        };
+        let sequence = u64::from_le_bytes(message[1..SIGNED_HEADER].try_into().unwrap());
+        if sequence < self.next {
+            return Err(SecureError::Replay);
+        }
+        // Gaps are fine, the last number can't be followed
+        self.next = sequence.checked_add(1).ok_or(SecureError::Replay)?;
+        Ok((opcode, message[SIGNED_HEADER..].to_vec()))
+    }
+}
+
+/// A websocket that seals and opens text and binary messages, or checks
+/// signed ones, once it has session keys, the same to use as the plain one.
+pub struct SecureSocket<S> {
+    ws: FragmentCollector<S>,
+    protection: Option<Protection>,
+    // Counts dropped messages
+    traffic: Arc<Traffic>,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> SecureSocket<S> {
+    pub fn new(ws: FragmentCollector<S>, traffic: Arc<Traffic>) -> Self {
+        Self {
+            ws,
+            protection: None,
+            traffic,
+        }
+    }
+
+    /// Protects everything from here on.
+    pub fn protect(&mut self, protection: Protection) {
+        self.protection = Some(protection);
+    }
+
+    pub async fn read_frame(&mut self) -> Result<Frame<'_>, WebSocketError> {
+        let Some(protection) = &mut self.protection else {
+            return self.ws.read_frame().await;
+        };
+        loop {
+            let frame = self.ws.read_frame().await?;
+            let (opcode, payload) = match (&mut *protection, frame.opcode) {
+                (Protection::Sealed(keys), OpCode::Binary) => {
+                    keys.open(&frame.payload).map_err(io::Error::other)?
+                }
+                // Text isn't sealed, only sent by clients that lost the keys
+                (Protection::Sealed(_), OpCode::Text) => {
+                    return Err(io::Error::other(SecureError::Open).into());
+                }
+                (Protection::Signed(mac), OpCode::Text | OpCode::Binary) => {
+                    match mac.check(&frame.payload) {
+                        Ok(message) => message,
+                        Err(e) => {
+                            self.traffic.reject(e.reason());
+                            continue;
+                        }
+                    }
+                }
+                (_, opcode) => (opcode, frame.payload.to_vec()),
+            };
+            return Ok(Frame::new(true, opcode, None, Payload::Owned(payload)));
+        }
+    }
+
+    pub async fn write_frame(&mut self, frame: Frame<'_>) -> Result<(), WebSocketError> {
+        match (&mut self.protection, frame.opcode) {
+            (Some(Protection::Sealed(keys)), OpCode::Text | OpCode::Binary) => {
+                let sealed = keys.seal(frame.opcode, &frame.payload);
+                self.ws
+                    .write_frame(Frame::binary(Payload::Owned(sealed)))
+                    .await
+            }
+            _ => self.ws.write_frame(frame).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // RFC 7748 section 6.1
+    const ALICE: &str = "77076d0a7318a57d3c16c17251b26645df4c2f87ebc0992ab177fba51db92c2a";
+    const BOB: &str = "5dab087e624a8a4b79e17f8b83800ee66f3bb1292618b6fd1c2f8b27ff88e0eb";
+
+    fn hex(s: &str) -> Vec<u8> {
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    fn key(s: &str) -> [u8; 32] {
+        hex(s).try_into().unwrap()
+    }
+
+    fn public(secret: &str) -> [u8; KEY_LEN] {
+        PublicKey::from(&StaticSecret::from(key(secret))).to_bytes()
+    }
+
+    // The client's side of the agreement, with Alice's key and the server
+    // key it pinned
+    fn client_agreement(pinned: &ServerKey, offer: &Offer) -> Agreement {
+        let alice = StaticSecret::from(key(ALICE));
+        let shared = alice.diffie_hellman(&PublicKey::from(offer.key));
+        Agreement {
+            shared: shared.to_bytes(),
+            salt: [
+                public(ALICE),
+                offer.key,
+                pinned.0.verifying_key().to_bytes(),
+            ]
+            .concat(),
+        }
+    }
+
+    // A client message signed with `key`
+    fn sign(key: &[u8; 32], opcode: u8, sequence: u64, payload: &[u8]) -> Vec<u8> {
+        let mut message = vec![opcode];
+        message.extend(sequence.to_le_bytes());
+        message.extend_from_slice(payload);
+        let mut mac = Hmac::<Sha256>::new_from_slice(key).unwrap();
+        mac.update(&message);
+        message.extend(mac.finalize().into_bytes());
+        message
+    }
+
+    #[test]
+    fn protects_sessions() {
+        let server_key = ServerKey::parse(&encode_key(&[7; KEY_LEN])).unwrap();
+        let alice_public = public(ALICE);
+        let (mut server, offer) =
+            SessionKeys::accept_with(&server_key, &alice_public, key(BOB)).unwrap();
+        assert_eq!(
+            offer.key.to_vec(),
+            hex("de9edb7d7b7dc1b4d35b61c2ece435373f8343c85b78674dadfc7e146f882b4f")
+        );
+        let agreement = client_agreement(&server_key, &offer);
+        assert_eq!(
+            agreement.shared.to_vec(),
+            hex("4a5d9d5ba4ce2de1728e3bf480350f25e07e21c947d19e3376f09b3c1e161742")
+        );
+
+        let mut client = SessionKeys {
+            send: Direction::new(agreement.key(b"teleboxel client")),
+            recv: Direction::new(agreement.key(b"teleboxel server")),
+        };
+        let hello = server.seal(OpCode::Text, b"7");
+        assert_eq!(client.open(&hello), Ok((OpCode::Text, b"7".to_vec())));
+        let first = client.seal(OpCode::Binary, &[1, 2, 3]);
+        let second = client.seal(OpCode::Text, b"Say hi");
+        // Out of order
+        assert_eq!(server.open(&second), Err(SecureError::Open));
+
+        let (mut server, _) =
+            SessionKeys::accept_with(&server_key, &alice_public, key(BOB)).unwrap();
+        assert_eq!(server.open(&first), Ok((OpCode::Binary, vec![1, 2, 3])));
+        let mut tampered = second.clone();
+        tampered[3] ^= 1;
+        assert_eq!(server.open(&tampered), Err(SecureError::Open));
+        let (mut server, _) =
+            SessionKeys::accept_with(&server_key, &alice_public, key(BOB)).unwrap();
+        server.open(&first).unwrap();
+        assert_eq!(server.open(&second), Ok((OpCode::Text, b"Say hi".to_vec())));
+        assert_eq!(server.open(&second), Err(SecureError::Open));
+
+        assert_eq!(
+            SessionKeys::accept_with(&server_key, &[0; 32], key(BOB)).err(),
+            Some(SecureError::WeakKey)
+        );
+        assert_eq!(parse_key("short"), Err(SecureError::BadKey));
+
+        // Signed messages
+        let (mut server, offer) =
+            MessageMac::accept_with(&server_key, &alice_public, key(BOB)).unwrap();
+        let key = client_agreement(&server_key, &offer).key(b"teleboxel mac");
+        let sign = |opcode, sequence, payload: &[u8]| sign(&key, opcode, sequence, payload);
+        let first = sign(1, 0, b"Say hi");
+        assert_eq!(server.check(&first), Ok((OpCode::Text, b"Say hi".to_vec())));
+        assert_eq!(server.check(&first), Err(SecureError::Replay));
+        assert_eq!(
+            server.check(&sign(2, 5, &[9])),
+            Ok((OpCode::Binary, vec![9]))
+        );
+        assert_eq!(server.check(&sign(2, 3, &[9])), Err(SecureError::Replay));
+        let mut forged = sign(1, 6, b"Say hi");
+        forged[10] = b'X';
+        assert_eq!(server.check(&forged), Err(SecureError::Open));
+        assert_eq!(server.check(b"short"), Err(SecureError::Open));
+
+        // Encryption wins when both are offered, and counts as signing
+        let offer = encode_key(&alice_public);
+        let picked = |encryption, message_auth, server_key, key, mac_key| {
+            negotiate(encryption, message_auth, server_key, key, mac_key).map(|p| match p {
+                Some((Protection::Sealed(_), _)) => "sealed",
+                Some((Protection::Signed(_), _)) => "signed",
+                None => "plain",
+            })
+        };
+        let (server_key, key) = (Some(&server_key), Some(offer.as_str()));
+        assert_eq!(
+            picked(Policy::Off, Policy::Off, server_key, key, key),
+            Ok("plain")
+        );
+        assert_eq!(
+            picked(Policy::Optional, Policy::Optional, server_key, key, key),
+            Ok("sealed")
+        );
+        assert_eq!(
+            picked(Policy::Off, Policy::Required, server_key, key, key),
+            Ok("signed")
+        );
+        assert_eq!(
+            picked(Policy::Optional, Policy::Required, server_key, key, None),
+            Ok("sealed")
+        );
+        assert_eq!(
+            picked(Policy::Optional, Policy::Required, server_key, None, None),
+            Err(SecureError::MessageAuthRequired)
+        );
+        assert_eq!(
+            picked(Policy::Required, Policy::Optional, server_key, None, key),
+            Err(SecureError::EncryptionRequired)
+        );
+        // Nothing to sign the offer with
+        assert_eq!(
+            picked(Policy::Required, Policy::Off, None, key, None),
+            Err(SecureError::EncryptionRequired)
+        );
+    }
+
+    #[test]
+    fn pins_the_server_key() {
+        let server_key = ServerKey::generate().unwrap();
+        let pinned = server_key.public();
+        assert_eq!(
+            ServerKey::parse(&server_key.secret()).unwrap().public(),
+            pinned
+        );
+        let alice = public(ALICE);
+        let offered = encode_key(&alice);
+        let negotiated = negotiate(
+            Policy::Optional,
+            Policy::Off,
+            Some(&server_key),
+            Some(&offered),
+            None,
+        );
+        let (_, offer) = negotiated.unwrap().unwrap();
+        assert_eq!(check_offer(&pinned, &alice, &offer.encode()), Ok(offer.key));
+
+        // Something in the middle swaps in its own key, signed or not
+        let impostor = ServerKey::generate().unwrap();
+        let (_, swapped) = SessionKeys::accept_with(&impostor, &alice, key(BOB)).unwrap();
+        assert_eq!(
+            check_offer(&pinned, &alice, &swapped.encode()),
+            Err(SecureError::Forged)
+        );
+        let resigned = format!("{}.{}", swapped.key(), offer.signature());
+        assert_eq!(
+            check_offer(&pinned, &alice, &resigned),
+            Err(SecureError::Forged)
+        );
+        // Or answers another client with this one's offer
+        assert_eq!(
+            check_offer(&pinned, &public(BOB), &offer.encode()),
+            Err(SecureError::Forged)
+        );
+        assert_eq!(
+            check_offer(&pinned, &alice, &offer.key()),
+            Err(SecureError::BadKey)
+        );
+    }
+
+    #[tokio::test]
+    async fn drops_forged_and_replayed_messages() {
+        use fastwebsockets::{Role, WebSocket};
+
+        let server_key = ServerKey::generate().unwrap();
+        let alice = public(ALICE);
+        let (mac, offer) = MessageMac::accept_with(&server_key, &alice, key(BOB)).unwrap();
+        let key = client_agreement(&server_key, &offer).key(b"teleboxel mac");
+        // Keys worked out against another server key, e.g. by a client that
+        // trusted a swapped offer, don't sign for this session
+        let impostor = ServerKey::generate().unwrap();
+        let forged_key = client_agreement(&impostor, &offer).key(b"teleboxel mac");
+        assert_ne!(key, forged_key);
+
+        let (client, server) = tokio::io::duplex(4096);
+        let traffic = Arc::new(Traffic::default());
+        let server = WebSocket::after_handshake(server, Role::Server);
+        let mut socket = SecureSocket::new(FragmentCollector::new(server), traffic.clone());
+        socket.protect(Protection::Signed(mac));
+        let mut client = WebSocket::after_handshake(client, Role::Client);
+        let first = sign(&key, 1, 0, b"Say hi");
+        for message in [
+            first.clone(),
+            sign(&forged_key, 1, 1, b"Say bye"),
+            first,
+            sign(&key, 2, 1, &[9]),
+        ] {
+            client
+                .write_frame(Frame::binary(Payload::Owned(message)))
+                .await
+                .unwrap();
+        }
+
+        let frame = socket.read_frame().await.unwrap();
+        assert_eq!(frame.opcode, OpCode::Text);
+        assert_eq!(&frame.payload[..], b"Say hi");
+        // The forged and replayed ones are skipped
+        let frame = socket.read_frame().await.unwrap();
+        assert_eq!(frame.opcode, OpCode::Binary);
+        assert_eq!(&frame.payload[..], [9]);
+        let metrics = traffic.prometheus();
+        assert!(metrics.contains("teleboxel_rejected_messages_total{reason=\"mac\"} 1"));
+        assert!(metrics.contains("teleboxel_rejected_messages_total{reason=\"replay\"} 1"));
+    }
+}
@@ -0,0 +1,223 @@
+//! Simulation controls per world, for debugging and "host paused the game":
+//!
+//! - Paused, the world doesn't tick: nothing thinks, moves or is sent.
+//!   Moves and block edits wait in the input queue (`input.rs`, up to
+//!   `TELEBOXEL_INPUT_BUFFER` each) and are applied once it ticks again.
+//!   Joins, chat, admin and other requests still go through.
+//! - Steps: while paused, run that many ticks and pause again.
+//! - Time scale: ticks come at `scale` percent of the tick rate, so the
+//!   game runs in slow motion (or faster) with every tick the same.
+//!
+//! `GET /admin/simulation?room=<name>`, `PUT` with e.g. `{"paused": true}`,
+//! `{"scale": 25}` or `{"step": 3}`, and the console's `pause`, `resume`,
+//! `step` and `speed`. Players get a `SIMULATION` message when it changes
+//! and on joining a world that isn't running normally. It resets when the
+//! server restarts, and per-second chores (AFK, saves) wait while paused.
+//!
+//! A stall (a long save, a swapped-out process) makes ticks late. Up to
+//! `TELEBOXEL_CATCH_UP_TICKS` missed ticks are run back to back, only the
+//! last one sending frames, so game time keeps up. A longer stall isn't
+//! caught up: players get a `TIME_SKIP` with the ticks of game time lost.
+
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Slowest and fastest time scale, in percent.
+pub const MIN_SCALE: u16 = 5;
+pub const MAX_SCALE: u16 = 400;
+
+/// A change to a world's simulation. Unset parts stay.
+#[derive(Serialize, Deserialize, Default, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct SimulationChange {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub paused: Option<bool>,
+    /// Percent of the tick rate.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scale: Option<u16>,
+    /// Ticks to run, then pause.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub step: Option<u32>,
+}
+
+impl SimulationChange {
+    pub fn check(&self) -> Result<(), String> {
+        match self.scale {
+            Some(scale) if !(MIN_SCALE..=MAX_SCALE).contains(&scale) => {
+                Err(format!("Scale must be {MIN_SCALE}-{MAX_SCALE} percent"))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Where a world's simulation is, as admins see it.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct SimulationState {
+    pub paused: bool,
+    pub scale: u16,
+    /// Steps left to run.
+    pub steps: u32,
+    pub tick: u64,
+}
+
+pub struct Simulation {
+    paused: bool,
+    scale: u16,
+    steps: u32,
+}
+
+impl Default for Simulation {
+    fn default() -> Self {
+        Self {
+            paused: false,
+            scale: 100,
+            steps: 0,
+        }
+    }
+}
+
+impl Simulation {
+    /// Applies a checked `change`. Steps pause.
+    pub fn change(&mut self, change: SimulationChange) {
+        if let Some(paused) = change.paused {
+            self.paused = paused;
+            self.steps = 0;
+        }
+        if let Some(scale) = change.scale {
+            self.scale = scale;
+        }
+        if let Some(step) = change.step {
+            self.paused = true;
+            self.steps = self.steps.saturating_add(step);
+        }
+    }
+
+    /// Whether this tick runs, taking a step if paused.
+    pub fn take_tick(&mut self) -> bool {
+        if !self.paused {
+            return true;
+        }
+        if self.steps == 0 {
+            return false;
+        }
+        self.steps -= 1;
+        true
+    }
+
+    /// Whether inputs wait: paused with no steps left.
+    pub fn is_held(&self) -> bool {
+        self.paused && self.steps == 0
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn scale(&self) -> u16 {
+        self.scale
+    }
+
+    /// Whether it's anything but running at full speed, worth telling
+    /// joining players.
+    pub fn is_unusual(&self) -> bool {
+        self.paused || self.scale != 100
+    }
+
+    pub fn state(&self, tick: u64) -> SimulationState {
+        SimulationState {
+            paused: self.paused,
+            scale: self.scale,
+            steps: self.steps,
+            tick,
+        }
+    }
+}
+
+/// What a world does about a late tick, see `catch_up`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CatchUp {
+    /// Run this many ticks more without sending frames first.
+    Run(u32),
+    /// Too many to catch up, this many are lost.
+    Skip(u32),
+}
+
+/// A tick due `late` ago, at one per `period`, with up to `max` missed
+/// ticks caught up.
+pub fn catch_up(late: Duration, period: Duration, max: u32) -> CatchUp {
+    let missed = late.as_nanos() / period.as_nanos().max(1);
+    let missed = u32::try_from(missed).unwrap_or(u32::MAX);
+    match missed <= max {
+        true => CatchUp::Run(missed),
+        false => CatchUp::Skip(missed),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn steps_run_then_pause() {
+        let mut sim = Simulation::default();
+        assert!(sim.take_tick() && !sim.is_unusual());
+
+        sim.change(SimulationChange {
+            paused: Some(true),
+            ..Default::default()
+        });
+        assert!(!sim.take_tick() && sim.is_held());
+
+        sim.change(SimulationChange {
+            step: Some(2),
+            ..Default::default()
+        });
+        assert!(!sim.is_held());
+        assert!(sim.take_tick() && sim.take_tick());
+        assert!(!sim.take_tick() && sim.is_paused());
+
+        // Stepping a running world pauses it after the steps
+        sim.change(SimulationChange {
+            paused: Some(false),
+            scale: Some(25),
+            ..Default::default()
+        });
+        assert_eq!(sim.state(7).scale, 25);
+        sim.change(SimulationChange {
+            step: Some(1),
+            ..Default::default()
+        });
+        assert!(sim.take_tick() && !sim.take_tick());
+
+        let slow = |scale| SimulationChange {
+            scale: Some(scale),
+            ..Default::default()
+        };
+        assert!(slow(MIN_SCALE).check().is_ok());
+        assert!(slow(1).check().is_err() && slow(1000).check().is_err());
+        let json: SimulationChange = serde_json::from_str(r#"{"step": 3}"#).unwrap();
+        assert_eq!(json.step, Some(3));
+    }
+
+    #[test]
+    fn short_stalls_catch_up() {
+        let tick = Duration::from_millis(20);
+        assert_eq!(
+            catch_up(Duration::from_millis(19), tick, 5),
+            CatchUp::Run(0)
+        );
+        assert_eq!(
+            catch_up(Duration::from_millis(61), tick, 5),
+            CatchUp::Run(3)
+        );
+        assert_eq!(
+            catch_up(Duration::from_secs(2), tick, 5),
+            CatchUp::Skip(100)
+        );
+        assert_eq!(
+            catch_up(Duration::from_millis(20), tick, 0),
+            CatchUp::Skip(1)
+        );
+    }
+}
@@ -0,0 +1,374 @@
+//! Spatial indexes over entity positions, so finding the entities in a box
+//! (static entities coming into a player's interest) doesn't go through
+//! every entity in the world. Two kinds, picked per world:
+//! `TELEBOXEL_SPATIAL_INDEX` for the main world and rooms, a template's
+//! `spatial` for rooms made from it.
+//!
+//! - `grid` (the default): hashed cells of `CELL` blocks, only the ones
+//!   with entities in them. Moves are two hash lookups, a query looks up
+//!   every cell of the box, or scans the occupied ones when there are fewer
+//!   of those. Best when entities spread over the area players look at.
+//! - `octree`: an octree over the whole coordinate range, split only where
+//!   entities are (leaves of up to `BUCKET`, down to `CELL` blocks). A
+//!   query skips empty space a node at a time, so it suits sparse clusters
+//!   over huge coordinates, where boxes cross many cells with nothing in
+//!   them. Entities are points, they never straddle nodes, so the nodes
+//!   need no loose bounds.
+//!
+//! `cargo bench --bench spatial` compares them with uniform and clustered
+//! entities.
+
+use crate::claims::BlockPos;
+use serde::Deserialize;
+use std::{collections::HashMap, mem, str::FromStr};
+
+/// Grid cell and smallest octree node side, in blocks.
+pub const CELL: i32 = 64;
+
+/// Entities an octree leaf holds before it splits.
+pub const BUCKET: usize = 8;
+
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum SpatialKind {
+    #[default]
+    Grid,
+    Octree,
+}
+
+impl FromStr for SpatialKind {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        match s {
+            "grid" => Ok(SpatialKind::Grid),
+            "octree" => Ok(SpatialKind::Octree),
+            _ => Err(()),
+        }
+    }
+}
+
+impl SpatialKind {
+    pub fn build(self) -> Box<dyn SpatialIndex> {
+        match self {
+            SpatialKind::Grid => Box::<Grid>::default(),
+            SpatialKind::Octree => Box::<Octree>::default(),
+        }
+    }
+}
+
+/// Positions by id.
+pub trait SpatialIndex: Send {
+    /// Adds `id` at `position`, or moves it there.
+    fn insert(&mut self, id: u32, position: BlockPos);
+
+    fn remove(&mut self, id: u32);
+
+    /// Adds the ids between `lo` and `hi`, both included, to `out`, in no
+    /// particular order.
+    fn query(&self, lo: BlockPos, hi: BlockPos, out: &mut Vec<u32>);
+
+    /// Roughly the heap it takes, in bytes.
+    fn footprint(&self) -> usize;
+}
+
+fn inside(p: BlockPos, lo: BlockPos, hi: BlockPos) -> bool {
+    (lo.0..=hi.0).contains(&p.0) && (lo.1..=hi.1).contains(&p.1) && (lo.2..=hi.2).contains(&p.2)
+}
+
+type Cell = (i32, i32, i32);
+
+fn cell(p: BlockPos) -> Cell {
+    (
+        p.0.div_euclid(CELL),
+        p.1.div_euclid(CELL),
+        p.2.div_euclid(CELL),
+    )
+}
+
+#[derive(Default)]
+pub struct Grid {
+    cells: HashMap<Cell, Vec<(u32, BlockPos)>>,
+    positions: HashMap<u32, BlockPos>,
+}
+
+impl Grid {
+    fn unlink(&mut self, id: u32, at: BlockPos) {
+        let key = cell(at);
+        if let Some(members) = self.cells.get_mut(&key) {
+            members.retain(|(other, _)| *other != id);
+            if members.is_empty() {
+                self.cells.remove(&key);
+            }
+        }
+    }
+}
+
+impl SpatialIndex for Grid {
+    fn insert(&mut self, id: u32, position: BlockPos) {
+        if let Some(old) = self.positions.insert(id, position) {
+            if cell(old) == cell(position) {
+                let members = self.cells.get_mut(&cell(old)).unwrap();
+                let entry = members.iter_mut().find(|(other, _)| *other == id);
+                entry.unwrap().1 = position;
+                return;
+            }
+            self.unlink(id, old);
+        }
+        self.cells
+            .entry(cell(position))
+            .or_default()
+            .push((id, position));
+    }
+
+    fn remove(&mut self, id: u32) {
+        if let Some(old) = self.positions.remove(&id) {
+            self.unlink(id, old);
+        }
+    }
+
+    fn query(&self, lo: BlockPos, hi: BlockPos, out: &mut Vec<u32>) {
+        let (a, b) = (cell(lo), cell(hi));
+        let span = |a: i32, b: i32| (b as i64 - a as i64 + 1).max(0) as u128;
+        let volume = span(a.0, b.0) * span(a.1, b.1) * span(a.2, b.2);
+        let mut take = |members: &Vec<(u32, BlockPos)>| {
+            let found = members.iter().filter(|(_, p)| inside(*p, lo, hi));
+            out.extend(found.map(|(id, _)| *id));
+        };
+        if volume > self.cells.len() as u128 {
+            let within = |c: &Cell| inside(*c, a, b);
+            self.cells
+                .iter()
+                .filter(|(c, _)| within(c))
+                .for_each(|(_, m)| take(m));
+            return;
+        }
+        for x in a.0..=b.0 {
+            for y in a.1..=b.1 {
+                for z in a.2..=b.2 {
+                    if let Some(members) = self.cells.get(&(x, y, z)) {
+                        take(members);
+                    }
+                }
+            }
+        }
+    }
+
+    fn footprint(&self) -> usize {
+        let members: usize = self.cells.values().map(|m| m.capacity()).sum();
+        self.cells.capacity() * (mem::size_of::<(Cell, Vec<(u32, BlockPos)>)>() + 1)
+            + members * mem::size_of::<(u32, BlockPos)>()
+            + self.positions.capacity() * (mem::size_of::<(u32, BlockPos)>() + 1)
+    }
+}
+
+// A cube from `min`, `side` blocks wide
+#[derive(Clone, Copy)]
+struct Bounds {
+    min: (i64, i64, i64),
+    side: i64,
+}
+
+impl Bounds {
+    // The whole i32 range
+    const ALL: Bounds = Bounds {
+        min: (i32::MIN as i64, i32::MIN as i64, i32::MIN as i64),
+        side: 1 << 32,
+    };
+
+    fn child(self, octant: usize) -> Bounds {
+        let half = self.side / 2;
+        let step = |bit: usize, min: i64| min + half * (octant >> bit & 1) as i64;
+        Bounds {
+            min: (
+                step(0, self.min.0),
+                step(1, self.min.1),
+                step(2, self.min.2),
+            ),
+            side: half,
+        }
+    }
+
+    fn octant(self, p: BlockPos) -> usize {
+        let half = self.side / 2;
+        let bit = |v: i32, min: i64| (v as i64 >= min + half) as usize;
+        bit(p.0, self.min.0) | bit(p.1, self.min.1) << 1 | bit(p.2, self.min.2) << 2
+    }
+
+    fn overlaps(self, lo: BlockPos, hi: BlockPos) -> bool {
+        let axis = |min: i64, lo: i32, hi: i32| min <= hi as i64 && (lo as i64) < min + self.side;
+        axis(self.min.0, lo.0, hi.0) && axis(self.min.1, lo.1, hi.1) && axis(self.min.2, lo.2, hi.2)
+    }
+}
+
+enum Node {
+    Leaf(Vec<(u32, BlockPos)>),
+    // With how many entities are under it
+    Branch(Box<[Node; 8]>, usize),
+}
+
+impl Default for Node {
+    fn default() -> Self {
+        Node::Leaf(Vec::new())
+    }
+}
+
+impl Node {
+    fn insert(&mut self, bounds: Bounds, id: u32, position: BlockPos) {
+        match self {
+            Node::Branch(children, count) => {
+                *count += 1;
+                let octant = bounds.octant(position);
+                children[octant].insert(bounds.child(octant), id, position);
+            }
+            Node::Leaf(members) => {
+                members.push((id, position));
+                if members.len() > BUCKET && bounds.side > CELL as i64 {
+                    let members = mem::take(members);
+                    *self = Node::Branch(Box::default(), 0);
+                    for (id, position) in members {
+                        self.insert(bounds, id, position);
+                    }
+                }
+            }
+        }
+    }
+
+    fn remove(&mut self, bounds: Bounds, id: u32, position: BlockPos) {
+        match self {
+            Node::Leaf(members) => members.retain(|(other, _)| *other != id),
+            Node::Branch(children, count) => {
+                *count -= 1;
+                let octant = bounds.octant(position);
+                children[octant].remove(bounds.child(octant), id, position);
+                // Back to a leaf once it fits in one
+                if *count <= BUCKET {
+                    let mut members = Vec::with_capacity(*count);
+                    self.collect(&mut members);
+                    *self = Node::Leaf(members);
+                }
+            }
+        }
+    }
+
+    fn collect(&self, out: &mut Vec<(u32, BlockPos)>) {
+        match self {
+            Node::Leaf(members) => out.extend_from_slice(members),
+            Node::Branch(children, _) => children.iter().for_each(|c| c.collect(out)),
+        }
+    }
+
+    fn query(&self, bounds: Bounds, lo: BlockPos, hi: BlockPos, out: &mut Vec<u32>) {
+        match self {
+            Node::Leaf(members) => {
+                let found = members.iter().filter(|(_, p)| inside(*p, lo, hi));
+                out.extend(found.map(|(id, _)| *id));
+            }
+            Node::Branch(children, _) => {
+                for (octant, child) in children.iter().enumerate() {
+                    let bounds = bounds.child(octant);
+                    if bounds.overlaps(lo, hi) {
+                        child.query(bounds, lo, hi, out);
+                    }
+                }
+            }
+        }
+    }
+
+    fn footprint(&self) -> usize {
+        match self {
+            Node::Leaf(members) => members.capacity() * mem::size_of::<(u32, BlockPos)>(),
+            Node::Branch(children, _) => {
+                let nodes = mem::size_of::<[Node; 8]>();
+                nodes + children.iter().map(Node::footprint).sum::<usize>()
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Octree {
+    root: Node,
+    positions: HashMap<u32, BlockPos>,
+}
+
+impl SpatialIndex for Octree {
+    fn insert(&mut self, id: u32, position: BlockPos) {
+        if let Some(old) = self.positions.insert(id, position) {
+            self.root.remove(Bounds::ALL, id, old);
+        }
+        self.root.insert(Bounds::ALL, id, position);
+    }
+
+    fn remove(&mut self, id: u32) {
+        if let Some(old) = self.positions.remove(&id) {
+            self.root.remove(Bounds::ALL, id, old);
+        }
+    }
+
+    fn query(&self, lo: BlockPos, hi: BlockPos, out: &mut Vec<u32>) {
+        self.root.query(Bounds::ALL, lo, hi, out);
+    }
+
+    fn footprint(&self) -> usize {
+        self.root.footprint() + self.positions.capacity() * (mem::size_of::<(u32, BlockPos)>() + 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Area = (BlockPos, BlockPos);
+
+    fn found(index: &dyn SpatialIndex, (lo, hi): Area) -> Vec<u32> {
+        let mut ids = Vec::new();
+        index.query(lo, hi, &mut ids);
+        ids.sort();
+        ids
+    }
+
+    #[test]
+    fn both_kinds_find_the_same_entities() {
+        // Two far clusters and a few strays, some on the i32 edges
+        let mut positions = Vec::new();
+        for i in 0..40 {
+            positions.push((i * 3, 40 + i % 5, -i * 2));
+            positions.push((1 << 30 | i, -7, (1 << 29) + i * 11));
+        }
+        let (min, max) = (i32::MIN, i32::MAX);
+        positions.extend([(min, 0, 0), (max, max, max), (-1, -1, -1)]);
+        let everywhere = ((min, min, min), (max, max, max));
+        let near = ((0, 0, -100), (60, 100, 0));
+        let far = (
+            (1 << 30, -10, 1 << 29),
+            ((1 << 30) + 20, 0, (1 << 29) + 200),
+        );
+        let point = |p| (p, p);
+        let inverted = ((5, 5, 5), (4, 4, 4));
+
+        for kind in [SpatialKind::Grid, SpatialKind::Octree] {
+            let mut index = kind.build();
+            for (id, p) in positions.iter().enumerate() {
+                index.insert(id as u32, *p);
+            }
+            for (lo, hi) in [near, far, everywhere, point((-1, -1, -1)), inverted] {
+                let expected = positions.iter().enumerate();
+                let expected = expected.filter(|(_, p)| inside(**p, lo, hi));
+                let expected: Vec<_> = expected.map(|(id, _)| id as u32).collect();
+                assert_eq!(found(&*index, (lo, hi)), expected, "{kind:?} {lo:?}");
+            }
+
+            // Moved out, then gone
+            index.insert(0, (500, 500, 500));
+            assert!(!found(&*index, near).contains(&0));
+            assert_eq!(found(&*index, point((500, 500, 500))), [0]);
+            for id in 0..positions.len() as u32 {
+                index.remove(id);
+            }
+            assert!(found(&*index, everywhere).is_empty());
+        }
+        assert_eq!("octree".parse(), Ok(SpatialKind::Octree));
+        assert!("quadtree".parse::<SpatialKind>().is_err());
+    }
+}
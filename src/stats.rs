@@ -0,0 +1,213 @@
+//! Player statistics per world (room name, `main` for the main world) and
+//! summed over every world, for leaderboards. Worlds count what named
+//! players do and add it to storage on the save interval and when players
+//! leave; other counters are any name passed to `PlayerStats::count`.
+//!
+//! Read-only, unauthenticated routes under `/stats` when there's storage:
+//!
+//! - `GET /stats/leaderboard/{stat}?world=<room>&page=1&per_page=20`: players
+//!   by `stat`, highest first; every world summed without `world`
+//! - `GET /stats/players/{name}`: a player's statistics by world, and summed
+
+use crate::storage::{Page, StatDelta, Storage};
+use axum::{
+    Json, Router,
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use serde::Serialize;
+use serde_json::json;
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+    time::Instant,
+};
+
+/// Seconds connected.
+pub const PLAYTIME: &str = "playtime";
+/// Blocks moved, straight line between positions.
+pub const DISTANCE: &str = "distance";
+pub const BLOCKS_PLACED: &str = "blocks_placed";
+pub const PORTALS_USED: &str = "portals_used";
+
+/// Largest leaderboard page.
+pub const MAX_PER_PAGE: u64 = 100;
+
+/// What one player did since the last `take`.
+pub struct PlayerStats {
+    counters: HashMap<String, i64>,
+    // Fractions carry over to the next take
+    distance: f64,
+    // The same since the last `take_traveled`
+    traveled: f64,
+    since: Instant,
+}
+
+impl PlayerStats {
+    pub fn new(now: Instant) -> Self {
+        Self {
+            counters: HashMap::new(),
+            distance: 0.0,
+            traveled: 0.0,
+            since: now,
+        }
+    }
+
+    pub fn count(&mut self, stat: &str, by: i64) {
+        *self.counters.entry(stat.to_string()).or_default() += by;
+    }
+
+    pub fn moved(&mut self, from: (i32, i32, i32), to: (i32, i32, i32)) {
+        let d = |a: i32, b: i32| (a as f64 - b as f64).powi(2);
+        let distance = (d(from.0, to.0) + d(from.1, to.1) + d(from.2, to.2)).sqrt();
+        self.distance += distance;
+        self.traveled += distance;
+    }
+
+    /// Whole blocks moved since the last call, for the journal.
+    pub fn take_traveled(&mut self) -> u32 {
+        let blocks = self.traveled.floor().min(u32::MAX as f64);
+        self.traveled -= blocks;
+        blocks as u32
+    }
+
+    /// Deltas for `name` in `world` since the last take, playtime up to
+    /// `now`.
+    pub fn take(&mut self, world: &str, name: &str, now: Instant) -> Vec<StatDelta> {
+        let playtime = now.duration_since(self.since).as_secs();
+        // Keeps the leftover fraction of a second
+        self.since += std::time::Duration::from_secs(playtime);
+        if playtime > 0 {
+            self.count(PLAYTIME, playtime as i64);
+        }
+        let distance = self.distance.floor();
+        self.distance -= distance;
+        if distance > 0.0 {
+            self.count(DISTANCE, distance as i64);
+        }
+
+        self.counters
+            .drain()
+            .filter(|&(_, delta)| delta != 0)
+            .map(|(stat, delta)| StatDelta {
+                world: world.to_string(),
+                name: name.to_string(),
+                stat,
+                delta,
+            })
+            .collect()
+    }
+}
+
+#[derive(Clone)]
+pub struct StatsState {
+    pub storage: Arc<dyn Storage>,
+}
+
+pub fn router(state: StatsState) -> Router {
+    Router::new()
+        .route("/leaderboard/{stat}", get(leaderboard))
+        .route("/players/{name}", get(player))
+        .with_state(state)
+}
+
+type Params = Query<HashMap<String, String>>;
+
+// GET /stats/leaderboard/{stat}?world=<room>&page=<n>&per_page=<n>
+async fn leaderboard(
+    State(state): State<StatsState>,
+    Path(stat): Path<String>,
+    Query(params): Params,
+) -> Response {
+    let number = |key: &str, default: u64| match params.get(key) {
+        Some(n) => n.parse::<u64>().ok().filter(|&n| n >= 1),
+        None => Some(default),
+    };
+    let (Some(page), Some(per_page)) = (number("page", 1), number("per_page", 20)) else {
+        return (StatusCode::BAD_REQUEST, "page and per_page start at 1").into_response();
+    };
+    let per_page = per_page.min(MAX_PER_PAGE);
+    let world = params.get("world").map(String::as_str);
+
+    let query = Page {
+        offset: (page - 1).saturating_mul(per_page),
+        limit: per_page,
+    };
+    match state.storage.leaderboard(world, &stat, query).await {
+        Ok(entries) => Json(json!({
+            "stat": stat,
+            "world": world,
+            "page": page,
+            "per_page": per_page,
+            "entries": entries,
+        }))
+        .into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Serialize)]
+struct PlayerStatsBody {
+    name: String,
+    worlds: BTreeMap<String, BTreeMap<String, i64>>,
+    total: BTreeMap<String, i64>,
+}
+
+// GET /stats/players/{name}
+async fn player(State(state): State<StatsState>, Path(name): Path<String>) -> Response {
+    let stats = match state.storage.player_stats(&name).await {
+        Ok(stats) => stats,
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    };
+    if stats.is_empty() {
+        return (StatusCode::NOT_FOUND, format!("No stats for {name}")).into_response();
+    }
+
+    let mut body = PlayerStatsBody {
+        name,
+        worlds: BTreeMap::new(),
+        total: BTreeMap::new(),
+    };
+    for s in stats {
+        *body.total.entry(s.stat.clone()).or_default() += s.value;
+        body.worlds
+            .entry(s.world)
+            .or_default()
+            .insert(s.stat, s.value);
+    }
+    Json(body).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn counts_between_takes() {
+        let start = Instant::now();
+        let mut stats = PlayerStats::new(start);
+        stats.moved((0, 0, 0), (3, 4, 0));
+        stats.moved((3, 4, 0), (3, 4, 1));
+        stats.moved((3, 4, 1), (3, 4, 1));
+        stats.count(BLOCKS_PLACED, 2);
+
+        let mut deltas = stats.take("main", "bob", start + Duration::from_millis(2500));
+        deltas.sort_by(|a, b| a.stat.cmp(&b.stat));
+        let deltas: Vec<_> = deltas.iter().map(|d| (d.stat.as_str(), d.delta)).collect();
+        assert_eq!(deltas, [(BLOCKS_PLACED, 2), (DISTANCE, 6), (PLAYTIME, 2)]);
+
+        // The half second and the fractions carry over
+        stats.moved((0, 0, 0), (0, 0, 0));
+        let deltas = stats.take("main", "bob", start + Duration::from_millis(3000));
+        assert_eq!(deltas.len(), 1);
+        assert_eq!((deltas[0].stat.as_str(), deltas[0].delta), (PLAYTIME, 1));
+        assert!(
+            stats
+                .take("main", "bob", start + Duration::from_millis(3000))
+                .is_empty()
+        );
+    }
+}
@@ -0,0 +1,366 @@
+// Without any backend compiled in, records are never read or written
+#![cfg_attr(
+    not(any(feature = "sqlite", feature = "postgres", feature = "redis")),
+    allow(dead_code)
+)]
+
+#[cfg(feature = "postgres")]
+mod postgres;
+#[cfg(feature = "redis")]
+mod redis;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+
+use crate::{chunk::ChunkPos, roles::Role};
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    future::Future,
+    path::Path,
+    pin::Pin,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::mpsc;
+
+pub type StorageError = Box<dyn std::error::Error + Send + Sync>;
+pub type StorageFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T, StorageError>> + Send + 'a>>;
+
+/// Persistence backend for player data, and for the world's chunks and claims
+/// when there's no world directory. Implementations must be cheap to share
+/// (they are held behind an `Arc` by both the world and connection tasks).
+pub trait Storage: Send + Sync {
+    /// Loads a player by name, including any ban. `None` if never seen.
+    fn load_player<'a>(&'a self, name: &'a str) -> StorageFuture<'a, Option<PlayerRecord>>;
+
+    /// Saves all records atomically where the backend allows it. Bans are
+    /// managed separately and are never written from here.
+    fn save_players<'a>(&'a self, records: &'a [PlayerRecord]) -> StorageFuture<'a, ()>;
+
+    /// Writes a consistent copy of the stored data to the file at `dest`.
+    /// Backends with their own tooling (pg_dump, RDB snapshots) may refuse.
+    fn backup<'a>(&'a self, dest: &'a Path) -> StorageFuture<'a, ()>;
+
+    /// Adds to player statistics (see `stats.rs`), which start at zero.
+    fn add_stats<'a>(&'a self, deltas: &'a [StatDelta]) -> StorageFuture<'a, ()>;
+
+    /// Players by `stat` in `world`, or summed over every world when
+    /// `None`, highest first.
+    fn leaderboard<'a>(
+        &'a self,
+        world: Option<&'a str>,
+        stat: &'a str,
+        page: Page,
+    ) -> StorageFuture<'a, Vec<Ranked>>;
+
+    /// Every statistic of a player, by world.
+    fn player_stats<'a>(&'a self, name: &'a str) -> StorageFuture<'a, Vec<StatValue>>;
+
+    /// A player's role (see `roles.rs`), `Player` unless one was assigned.
+    fn load_role<'a>(&'a self, name: &'a str) -> StorageFuture<'a, Role>;
+
+    /// Assigns a role, `Player` clears the assignment.
+    fn save_role<'a>(&'a self, name: &'a str, role: Role) -> StorageFuture<'a, ()>;
+
+    /// A chunk's save (see `save.rs`), `None` if it was never saved.
+    fn load_chunk<'a>(&'a self, pos: ChunkPos) -> StorageFuture<'a, Option<Vec<u8>>>;
+
+    /// Saves chunks, each replacing its earlier save, atomically where the
+    /// backend allows it.
+    fn save_chunks<'a>(&'a self, chunks: &'a [(ChunkPos, Vec<u8>)]) -> StorageFuture<'a, ()>;
+
+    /// The claims save (see `claims.rs`), `None` if never saved.
+    fn load_claims<'a>(&'a self) -> StorageFuture<'a, Option<Vec<u8>>>;
+
+    /// Replaces the claims save.
+    fn save_claims<'a>(&'a self, data: &'a [u8]) -> StorageFuture<'a, ()>;
+}
+
+/// Inventory slots a record holds.
+pub const INVENTORY_SLOTS: u16 = 36;
+
+/// Persistent player data, keyed by player name.
+#[derive(Clone, Debug)]
+pub struct PlayerRecord {
+    pub name: String,
+    pub position: (i32, i32, i32),
+    pub properties: HashMap<String, String>,
+    pub inventory: Vec<InventorySlot>,
+    /// Ban reason, if the player is banned.
+    pub ban: Option<String>,
+    /// Never saved before, the world picks a spawn position.
+    pub is_new: bool,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct InventorySlot {
+    pub slot: u16,
+    pub item: u16,
+    pub count: u32,
+}
+
+/// Increment to one player's statistic in one world.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StatDelta {
+    pub world: String,
+    pub name: String,
+    pub stat: String,
+    pub delta: i64,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct StatValue {
+    pub world: String,
+    pub stat: String,
+    pub value: i64,
+}
+
+/// Leaderboard entry, ranks start at 1.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Ranked {
+    pub rank: u64,
+    pub name: String,
+    pub value: i64,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Page {
+    pub offset: u64,
+    pub limit: u64,
+}
+
+impl PlayerRecord {
+    pub fn new(name: String) -> Self {
+        Self {
+            name,
+            position: (0, 0, 0),
+            properties: HashMap::new(),
+            inventory: Vec::new(),
+            ban: None,
+            is_new: true,
+        }
+    }
+
+    /// Stacks onto the item's slot, or takes the first free one. `false`
+    /// when the inventory is full.
+    pub fn give(&mut self, item: u16, count: u32) -> bool {
+        if let Some(slot) = self.inventory.iter_mut().find(|s| s.item == item) {
+            slot.count = slot.count.saturating_add(count);
+            return true;
+        }
+        let Some(slot) = (0..INVENTORY_SLOTS).find(|&n| self.inventory.iter().all(|s| s.slot != n))
+        else {
+            return false;
+        };
+        self.inventory.push(InventorySlot { slot, item, count });
+        self.inventory.sort_by_key(|s| s.slot);
+        true
+    }
+}
+
+/// Writes player records a batch at a time, in the order they were queued.
+/// Saves are started from the tick loop without waiting, and a slow one
+/// finishing after a newer one would put the older record back.
+#[derive(Clone)]
+pub struct SaveQueue {
+    tx: mpsc::UnboundedSender<Vec<PlayerRecord>>,
+}
+
+impl SaveQueue {
+    /// Spawns the writer, must be called within a Tokio runtime.
+    pub fn new(storage: Arc<dyn Storage>) -> Self {
+        let (tx, mut rx) = mpsc::unbounded_channel::<Vec<PlayerRecord>>();
+        tokio::spawn(async move {
+            while let Some(records) = rx.recv().await {
+                if let Err(e) = storage.save_players(&records).await {
+                    eprintln!("Error saving players: {e}");
+                }
+            }
+        });
+        Self { tx }
+    }
+
+    pub fn save(&self, records: Vec<PlayerRecord>) {
+        if !records.is_empty() {
+            self.tx.send(records).ok();
+        }
+    }
+}
+
+/// Connects to the backend selected by the URL scheme (`sqlite:`,
+/// `postgres:`, `redis:`). Backends are compiled in via cargo features.
+pub async fn connect(url: &str) -> Result<Arc<dyn Storage>, StorageError> {
+    let scheme = url.split(':').next().unwrap_or("");
+
+    match scheme {
+        #[cfg(feature = "sqlite")]
+        "sqlite" => Ok(Arc::new(sqlite::SqliteStorage::connect(url).await?)),
+        #[cfg(feature = "postgres")]
+        "postgres" | "postgresql" => Ok(Arc::new(postgres::PostgresStorage::connect(url).await?)),
+        #[cfg(feature = "redis")]
+        "redis" | "rediss" => Ok(Arc::new(redis::RedisStorage::connect(url).await?)),
+        _ => Err(format!("unsupported or disabled storage backend: {scheme}").into()),
+    }
+}
+
+pub(crate) fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{sync::Mutex, time::Duration};
+
+    // Keeps positions, slow to write the first save
+    #[derive(Default)]
+    struct Slow {
+        positions: Mutex<HashMap<String, (i32, i32, i32)>>,
+    }
+
+    impl Storage for Slow {
+        fn load_player<'a>(&'a self, name: &'a str) -> StorageFuture<'a, Option<PlayerRecord>> {
+            Box::pin(async move {
+                let positions = self.positions.lock().unwrap();
+                Ok(positions.get(name).map(|&position| PlayerRecord {
+                    position,
+                    is_new: false,
+                    ..PlayerRecord::new(name.to_string())
+                }))
+            })
+        }
+
+        fn save_players<'a>(&'a self, records: &'a [PlayerRecord]) -> StorageFuture<'a, ()> {
+            Box::pin(async move {
+                if records.iter().any(|r| r.position.0 == 1) {
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                }
+                let mut positions = self.positions.lock().unwrap();
+                for record in records {
+                    positions.insert(record.name.clone(), record.position);
+                }
+                Ok(())
+            })
+        }
+
+        fn backup<'a>(&'a self, _: &'a Path) -> StorageFuture<'a, ()> {
+            Box::pin(async { Err("no backups".into()) })
+        }
+
+        fn add_stats<'a>(&'a self, _: &'a [StatDelta]) -> StorageFuture<'a, ()> {
+            Box::pin(async { Ok(()) })
+        }
+
+        fn leaderboard<'a>(
+            &'a self,
+            _: Option<&'a str>,
+            _: &'a str,
+            _: Page,
+        ) -> StorageFuture<'a, Vec<Ranked>> {
+            Box::pin(async { Ok(Vec::new()) })
+        }
+
+        fn player_stats<'a>(&'a self, _: &'a str) -> StorageFuture<'a, Vec<StatValue>> {
+            Box::pin(async { Ok(Vec::new()) })
+        }
+
+        fn load_role<'a>(&'a self, _: &'a str) -> StorageFuture<'a, Role> {
+            Box::pin(async { Ok(Role::Player) })
+        }
+
+        fn save_role<'a>(&'a self, _: &'a str, _: Role) -> StorageFuture<'a, ()> {
+            Box::pin(async { Ok(()) })
+        }
+
+        fn load_chunk<'a>(&'a self, _: ChunkPos) -> StorageFuture<'a, Option<Vec<u8>>> {
+            Box::pin(async { Ok(None) })
+        }
+
+        fn save_chunks<'a>(&'a self, _: &'a [(ChunkPos, Vec<u8>)]) -> StorageFuture<'a, ()> {
+            Box::pin(async { Ok(()) })
+        }
+
+        fn load_claims<'a>(&'a self) -> StorageFuture<'a, Option<Vec<u8>>> {
+            Box::pin(async { Ok(None) })
+        }
+
+        fn save_claims<'a>(&'a self, _: &'a [u8]) -> StorageFuture<'a, ()> {
+            Box::pin(async { Ok(()) })
+        }
+    }
+
+    /// What every backend must do with player records. `name` should be
+    /// unused in `storage`.
+    pub(crate) async fn keeps_players(storage: &dyn Storage, name: &str) {
+        assert!(storage.load_player(name).await.unwrap().is_none());
+
+        let mut record = PlayerRecord::new(name.to_string());
+        record.position = (-3, 40, 7);
+        record.properties.insert("team".into(), "red".into());
+        record.properties.insert("title".into(), "builder".into());
+        record.give(5, 64);
+        record.give(9, 1);
+        storage.save_players(&[record.clone()]).await.unwrap();
+
+        let loaded = storage.load_player(name).await.unwrap().unwrap();
+        assert!(!loaded.is_new && loaded.ban.is_none());
+        assert_eq!(loaded.position, (-3, 40, 7));
+        assert_eq!(loaded.properties, record.properties);
+        let slots = |r: &PlayerRecord| -> Vec<_> {
+            r.inventory
+                .iter()
+                .map(|s| (s.slot, s.item, s.count))
+                .collect()
+        };
+        assert_eq!(slots(&loaded), [(0, 5, 64), (1, 9, 1)]);
+
+        // A later save replaces the whole record
+        record.position = (0, 1, 0);
+        record.properties.remove("title");
+        record.inventory.remove(0);
+        storage.save_players(&[record.clone()]).await.unwrap();
+        let loaded = storage.load_player(name).await.unwrap().unwrap();
+        assert_eq!(loaded.position, (0, 1, 0));
+        assert_eq!(loaded.properties, record.properties);
+        assert_eq!(slots(&loaded), [(1, 9, 1)]);
+    }
+
+    /// What every backend must do with the world's saves. `at` and the
+    /// chunk after it on x should never have been saved in `storage`, its
+    /// claims are replaced.
+    pub(crate) async fn keeps_world(storage: &dyn Storage, at: ChunkPos) {
+        assert!(storage.load_chunk(at).await.unwrap().is_none());
+        let next = (at.0 + 1, at.1, at.2);
+        let chunks = [(at, vec![1, 2]), (next, vec![3])];
+        storage.save_chunks(&chunks).await.unwrap();
+        storage.save_chunks(&[(at, vec![4])]).await.unwrap();
+        assert_eq!(storage.load_chunk(at).await.unwrap(), Some(vec![4]));
+        assert_eq!(storage.load_chunk(next).await.unwrap(), Some(vec![3]));
+
+        storage.save_claims(b"claims").await.unwrap();
+        storage.save_claims(b"more claims").await.unwrap();
+        let claims = storage.load_claims().await.unwrap();
+        assert_eq!(claims.as_deref(), Some(&b"more claims"[..]));
+    }
+
+    #[tokio::test]
+    async fn saves_in_queued_order() {
+        let storage = Arc::new(Slow::default());
+        let saves = SaveQueue::new(storage.clone());
+        let at = |x| PlayerRecord {
+            position: (x, 0, 0),
+            ..PlayerRecord::new("bob".to_string())
+        };
+        // The slow save goes first and must not land last
+        saves.save(vec![at(1)]);
+        saves.save(vec![at(2)]);
+        saves.save(Vec::new());
+
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let record = storage.load_player("bob").await.unwrap().unwrap();
+        assert_eq!(record.position, (2, 0, 0));
+    }
+}
@@ -0,0 +1,362 @@
+use super::{
+    InventorySlot, Page, PlayerRecord, Ranked, StatDelta, StatValue, Storage, StorageFuture,
+    unix_now,
+};
+use crate::{chunk::ChunkPos, roles::Role};
+use sqlx::{
+    Row,
+    postgres::{PgPool, PgPoolOptions},
+};
+use std::path::Path;
+
+/// Postgres-backed storage, for deployments that need a shared durable store.
+pub struct PostgresStorage {
+    pool: PgPool,
+}
+
+impl PostgresStorage {
+    pub async fn connect(url: &str) -> Result<Self, sqlx::Error> {
+        let pool = PgPoolOptions::new().max_connections(8).connect(url).await?;
+
+        sqlx::migrate!("./migrations/postgres").run(&pool).await?;
+
+        Ok(Self { pool })
+    }
+
+    async fn load(&self, name: &str) -> Result<Option<PlayerRecord>, sqlx::Error> {
+        let ban = sqlx::query("SELECT reason FROM bans WHERE name = $1")
+            .bind(name)
+            .fetch_optional(&self.pool)
+            .await?
+            .map(|row| row.get::<String, _>("reason"));
+
+        let Some(row) = sqlx::query("SELECT pos_x, pos_y, pos_z FROM players WHERE name = $1")
+            .bind(name)
+            .fetch_optional(&self.pool)
+            .await?
+        else {
+            return Ok(ban.map(|reason| PlayerRecord {
+                ban: Some(reason),
+                ..PlayerRecord::new(name.to_string())
+            }));
+        };
+
+        let properties = sqlx::query("SELECT key, value FROM player_properties WHERE name = $1")
+            .bind(name)
+            .fetch_all(&self.pool)
+            .await?
+            .into_iter()
+            .map(|row| (row.get("key"), row.get("value")))
+            .collect();
+
+        let inventory = sqlx::query(
+            "SELECT slot, item, count FROM player_inventory WHERE name = $1 ORDER BY slot",
+        )
+        .bind(name)
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(|row| InventorySlot {
+            slot: row.get::<i32, _>("slot") as u16,
+            item: row.get::<i32, _>("item") as u16,
+            count: row.get::<i64, _>("count") as u32,
+        })
+        .collect();
+
+        Ok(Some(PlayerRecord {
+            name: name.to_string(),
+            position: (row.get("pos_x"), row.get("pos_y"), row.get("pos_z")),
+            properties,
+            inventory,
+            ban,
+            is_new: false,
+        }))
+    }
+
+    async fn save(&self, records: &[PlayerRecord]) -> Result<(), sqlx::Error> {
+        let now = unix_now();
+        let mut tx = self.pool.begin().await?;
+
+        for record in records {
+            let (x, y, z) = record.position;
+            sqlx::query(
+                "INSERT INTO players (name, pos_x, pos_y, pos_z, updated_at) VALUES ($1, $2, $3, $4, $5)
+                 ON CONFLICT (name) DO UPDATE SET
+                    pos_x = excluded.pos_x,
+                    pos_y = excluded.pos_y,
+                    pos_z = excluded.pos_z,
+                    updated_at = excluded.updated_at",
+            )
+            .bind(&record.name)
+            .bind(x)
+            .bind(y)
+            .bind(z)
+            .bind(now)
+            .execute(&mut *tx)
+            .await?;
+
+            sqlx::query("DELETE FROM player_properties WHERE name = $1")
+                .bind(&record.name)
+                .execute(&mut *tx)
+                .await?;
+            for (key, value) in &record.properties {
+                sqlx::query("INSERT INTO player_properties (name, key, value) VALUES ($1, $2, $3)")
+                    .bind(&record.name)
+                    .bind(key)
+                    .bind(value)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+
+            sqlx::query("DELETE FROM player_inventory WHERE name = $1")
+                .bind(&record.name)
+                .execute(&mut *tx)
+                .await?;
+            for slot in &record.inventory {
+                sqlx::query(
+                    "INSERT INTO player_inventory (name, slot, item, count) VALUES ($1, $2, $3, $4)",
+                )
+                .bind(&record.name)
+                .bind(slot.slot as i32)
+                .bind(slot.item as i32)
+                .bind(slot.count as i64)
+                .execute(&mut *tx)
+                .await?;
+            }
+        }
+
+        tx.commit().await
+    }
+
+    async fn add(&self, deltas: &[StatDelta]) -> Result<(), sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        for delta in deltas {
+            sqlx::query(
+                "INSERT INTO player_stats (world, name, stat, value) VALUES ($1, $2, $3, $4)
+                 ON CONFLICT (world, name, stat) DO UPDATE SET value = player_stats.value + excluded.value",
+            )
+            .bind(&delta.world)
+            .bind(&delta.name)
+            .bind(&delta.stat)
+            .bind(delta.delta)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await
+    }
+
+    async fn top(
+        &self,
+        world: Option<&str>,
+        stat: &str,
+        page: Page,
+    ) -> Result<Vec<Ranked>, sqlx::Error> {
+        let rows = match world {
+            Some(world) => sqlx::query(
+                "SELECT name, value FROM player_stats WHERE stat = $1 AND world = $2
+                     ORDER BY value DESC, name LIMIT $3 OFFSET $4",
+            )
+            .bind(stat)
+            .bind(world),
+            None => sqlx::query(
+                "SELECT name, SUM(value)::BIGINT AS value FROM player_stats WHERE stat = $1
+                 GROUP BY name ORDER BY value DESC, name LIMIT $2 OFFSET $3",
+            )
+            .bind(stat),
+        }
+        .bind(page.limit as i64)
+        .bind(page.offset as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .zip(page.offset + 1..)
+            .map(|(row, rank)| Ranked {
+                rank,
+                name: row.get("name"),
+                value: row.get("value"),
+            })
+            .collect())
+    }
+
+    async fn stats(&self, name: &str) -> Result<Vec<StatValue>, sqlx::Error> {
+        let rows = sqlx::query(
+            "SELECT world, stat, value FROM player_stats WHERE name = $1 ORDER BY world, stat",
+        )
+        .bind(name)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows
+            .into_iter()
+            .map(|row| StatValue {
+                world: row.get("world"),
+                stat: row.get("stat"),
+                value: row.get("value"),
+            })
+            .collect())
+    }
+
+    async fn role(&self, name: &str) -> Result<Role, sqlx::Error> {
+        let role = sqlx::query("SELECT role FROM roles WHERE name = $1")
+            .bind(name)
+            .fetch_optional(&self.pool)
+            .await?;
+        // Unknown roles (from a newer version) grant nothing
+        Ok(role
+            .and_then(|row| row.get::<String, _>("role").parse().ok())
+            .unwrap_or_default())
+    }
+
+    async fn set_role(&self, name: &str, role: Role) -> Result<(), sqlx::Error> {
+        if role == Role::Player {
+            sqlx::query("DELETE FROM roles WHERE name = $1")
+                .bind(name)
+                .execute(&self.pool)
+                .await?;
+            return Ok(());
+        }
+        sqlx::query(
+            "INSERT INTO roles (name, role, updated_at) VALUES ($1, $2, $3)
+             ON CONFLICT (name) DO UPDATE SET role = excluded.role, updated_at = excluded.updated_at",
+        )
+        .bind(name)
+        .bind(role.to_string())
+        .bind(unix_now())
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn chunk(&self, pos: ChunkPos) -> Result<Option<Vec<u8>>, sqlx::Error> {
+        let (cx, cy, cz) = pos;
+        let row = sqlx::query("SELECT data FROM chunks WHERE cx = $1 AND cy = $2 AND cz = $3")
+            .bind(cx)
+            .bind(cy)
+            .bind(cz)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(row.map(|row| row.get("data")))
+    }
+
+    async fn set_chunks(&self, chunks: &[(ChunkPos, Vec<u8>)]) -> Result<(), sqlx::Error> {
+        let now = unix_now();
+        let mut tx = self.pool.begin().await?;
+        for (pos, data) in chunks {
+            let (cx, cy, cz) = *pos;
+            sqlx::query(
+                "INSERT INTO chunks (cx, cy, cz, data, updated_at) VALUES ($1, $2, $3, $4, $5)
+                 ON CONFLICT (cx, cy, cz) DO UPDATE SET data = excluded.data, updated_at = excluded.updated_at",
+            )
+            .bind(cx)
+            .bind(cy)
+            .bind(cz)
+            .bind(data.as_slice())
+            .bind(now)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await
+    }
+
+    async fn claims(&self) -> Result<Option<Vec<u8>>, sqlx::Error> {
+        let row = sqlx::query("SELECT data FROM claims WHERE id = 1")
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(row.map(|row| row.get("data")))
+    }
+
+    async fn set_claims(&self, data: &[u8]) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "INSERT INTO claims (id, data, updated_at) VALUES (1, $1, $2)
+             ON CONFLICT (id) DO UPDATE SET data = excluded.data, updated_at = excluded.updated_at",
+        )
+        .bind(data)
+        .bind(unix_now())
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+}
+
+impl Storage for PostgresStorage {
+    fn load_player<'a>(&'a self, name: &'a str) -> StorageFuture<'a, Option<PlayerRecord>> {
+        Box::pin(async move { Ok(self.load(name).await?) })
+    }
+
+    fn save_players<'a>(&'a self, records: &'a [PlayerRecord]) -> StorageFuture<'a, ()> {
+        Box::pin(async move { Ok(self.save(records).await?) })
+    }
+
+    fn backup<'a>(&'a self, _dest: &'a Path) -> StorageFuture<'a, ()> {
+        Box::pin(async { Err("Postgres backups are not supported, use pg_dump".into()) })
+    }
+
+    fn add_stats<'a>(&'a self, deltas: &'a [StatDelta]) -> StorageFuture<'a, ()> {
+        Box::pin(async move { Ok(self.add(deltas).await?) })
+    }
+
+    fn leaderboard<'a>(
+        &'a self,
+        world: Option<&'a str>,
+        stat: &'a str,
+        page: Page,
+    ) -> StorageFuture<'a, Vec<Ranked>> {
+        Box::pin(async move { Ok(self.top(world, stat, page).await?) })
+    }
+
+    fn player_stats<'a>(&'a self, name: &'a str) -> StorageFuture<'a, Vec<StatValue>> {
+        Box::pin(async move { Ok(self.stats(name).await?) })
+    }
+
+    fn load_role<'a>(&'a self, name: &'a str) -> StorageFuture<'a, Role> {
+        Box::pin(async move { Ok(self.role(name).await?) })
+    }
+
+    fn save_role<'a>(&'a self, name: &'a str, role: Role) -> StorageFuture<'a, ()> {
+        Box::pin(async move { Ok(self.set_role(name, role).await?) })
+    }
+    fn load_chunk<'a>(&'a self, pos: ChunkPos) -> StorageFuture<'a, Option<Vec<u8>>> {
+        Box::pin(async move { Ok(self.chunk(pos).await?) })
+    }
+
+    fn save_chunks<'a>(&'a self, chunks: &'a [(ChunkPos, Vec<u8>)]) -> StorageFuture<'a, ()> {
+        Box::pin(async move { Ok(self.set_chunks(chunks).await?) })
+    }
+
+    fn load_claims<'a>(&'a self) -> StorageFuture<'a, Option<Vec<u8>>> {
+        Box::pin(async move { Ok(self.claims().await?) })
+    }
+
+    fn save_claims<'a>(&'a self, data: &'a [u8]) -> StorageFuture<'a, ()> {
+        Box::pin(async move { Ok(self.set_claims(data).await?) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Needs a server, skipped unless TELEBOXEL_TEST_POSTGRES_URL is set
+    #[tokio::test]
+    async fn keeps_players() {
+        let Ok(url) = std::env::var("TELEBOXEL_TEST_POSTGRES_URL") else {
+            return;
+        };
+        let storage = PostgresStorage::connect(&url).await.unwrap();
+        let name = format!("test-{}-{}", std::process::id(), unix_now());
+        crate::storage::tests::keeps_players(&storage, &name).await;
+    }
+
+    // Needs a server, skipped unless TELEBOXEL_TEST_POSTGRES_URL is set
+    #[tokio::test]
+    async fn keeps_world() {
+        let Ok(url) = std::env::var("TELEBOXEL_TEST_POSTGRES_URL") else {
+            return;
+        };
+        let storage = PostgresStorage::connect(&url).await.unwrap();
+        // A chunk far out, new to a database other tests ran against
+        let at = (std::process::id() as i32, unix_now() as i32, i32::MIN);
+        crate::storage::tests::keeps_world(&storage, at).await;
+    }
+}
@@ -0,0 +1,313 @@
+use super::{
+    InventorySlot, Page, PlayerRecord, Ranked, StatDelta, StatValue, Storage, StorageFuture,
+    unix_now,
+};
+use crate::{chunk::ChunkPos, roles::Role};
+use redis::{AsyncCommands, RedisError, aio::MultiplexedConnection};
+use std::{collections::HashMap, path::Path};
+
+/// Redis-backed storage, for low-latency deployments that accept Redis
+/// durability settings.
+///
+/// Layout per player name:
+/// - `teleboxel:player:{name}` hash: `pos_x`, `pos_y`, `pos_z`, `updated_at`
+/// - `teleboxel:player:{name}:properties` hash: key -> value
+/// - `teleboxel:player:{name}:inventory` hash: slot -> `item:count`
+/// - `teleboxel:ban:{name}` string: ban reason
+/// - `teleboxel:role:{name}` string: role, when above `player`
+///
+/// Statistics (see `stats.rs`):
+/// - `teleboxel:stats:{stat}:world:{world}` sorted set: name -> value
+/// - `teleboxel:stats:{stat}:global` sorted set: name -> sum over worlds
+/// - `teleboxel:player:{name}:stats` hash: `{stat}@{world}` -> value
+///
+/// The world's saves (see `save.rs`), when there's no world directory:
+/// - `teleboxel:chunk:{cx}:{cy}:{cz}` string: the chunk's save
+/// - `teleboxel:claims` string: the claims save
+pub struct RedisStorage {
+    conn: MultiplexedConnection,
+}
+
+impl RedisStorage {
+    pub async fn connect(url: &str) -> Result<Self, RedisError> {
+        let client = redis::Client::open(url)?;
+        let conn = client.get_multiplexed_async_connection().await?;
+        Ok(Self { conn })
+    }
+
+    async fn load(&self, name: &str) -> Result<Option<PlayerRecord>, RedisError> {
+        let mut conn = self.conn.clone();
+        let key = format!("teleboxel:player:{name}");
+
+        let ban: Option<String> = conn.get(format!("teleboxel:ban:{name}")).await?;
+        let fields: HashMap<String, i64> = conn.hgetall(&key).await?;
+
+        if fields.is_empty() {
+            return Ok(ban.map(|reason| PlayerRecord {
+                ban: Some(reason),
+                ..PlayerRecord::new(name.to_string())
+            }));
+        }
+
+        let field = |f: &str| fields.get(f).copied().unwrap_or(0) as i32;
+        let position = (field("pos_x"), field("pos_y"), field("pos_z"));
+
+        let properties: HashMap<String, String> = conn.hgetall(format!("{key}:properties")).await?;
+
+        let slots: HashMap<u16, String> = conn.hgetall(format!("{key}:inventory")).await?;
+        let mut inventory: Vec<InventorySlot> = slots
+            .into_iter()
+            .filter_map(|(slot, value)| {
+                let (item, count) = value.split_once(':')?;
+                Some(InventorySlot {
+                    slot,
+                    item: item.parse().ok()?,
+                    count: count.parse().ok()?,
+                })
+            })
+            .collect();
+        inventory.sort_by_key(|s| s.slot);
+
+        Ok(Some(PlayerRecord {
+            name: name.to_string(),
+            position,
+            properties,
+            inventory,
+            ban,
+            is_new: false,
+        }))
+    }
+
+    async fn save(&self, records: &[PlayerRecord]) -> Result<(), RedisError> {
+        let mut conn = self.conn.clone();
+        let now = unix_now();
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+
+        for record in records {
+            let key = format!("teleboxel:player:{}", record.name);
+            let (x, y, z) = record.position;
+            pipe.hset_multiple(
+                &key,
+                &[
+                    ("pos_x", x as i64),
+                    ("pos_y", y as i64),
+                    ("pos_z", z as i64),
+                    ("updated_at", now),
+                ],
+            )
+            .ignore();
+
+            let properties_key = format!("{key}:properties");
+            pipe.del(&properties_key).ignore();
+            if !record.properties.is_empty() {
+                let items: Vec<(&String, &String)> = record.properties.iter().collect();
+                pipe.hset_multiple(&properties_key, &items).ignore();
+            }
+
+            let inventory_key = format!("{key}:inventory");
+            pipe.del(&inventory_key).ignore();
+            if !record.inventory.is_empty() {
+                let items: Vec<(u16, String)> = record
+                    .inventory
+                    .iter()
+                    .map(|s| (s.slot, format!("{}:{}", s.item, s.count)))
+                    .collect();
+                pipe.hset_multiple(&inventory_key, &items).ignore();
+            }
+        }
+
+        pipe.query_async(&mut conn).await
+    }
+
+    async fn add(&self, deltas: &[StatDelta]) -> Result<(), RedisError> {
+        let mut conn = self.conn.clone();
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        for d in deltas {
+            let world_key = format!("teleboxel:stats:{}:world:{}", d.stat, d.world);
+            let global_key = format!("teleboxel:stats:{}:global", d.stat);
+            let player_key = format!("teleboxel:player:{}:stats", d.name);
+            pipe.zincr(world_key, &d.name, d.delta).ignore();
+            pipe.zincr(global_key, &d.name, d.delta).ignore();
+            pipe.hincr(player_key, format!("{}@{}", d.stat, d.world), d.delta)
+                .ignore();
+        }
+        pipe.query_async(&mut conn).await
+    }
+
+    async fn top(
+        &self,
+        world: Option<&str>,
+        stat: &str,
+        page: Page,
+    ) -> Result<Vec<Ranked>, RedisError> {
+        let mut conn = self.conn.clone();
+        let key = match world {
+            Some(world) => format!("teleboxel:stats:{stat}:world:{world}"),
+            None => format!("teleboxel:stats:{stat}:global"),
+        };
+        if page.limit == 0 {
+            return Ok(Vec::new());
+        }
+        let (start, stop) = (page.offset, page.offset + page.limit - 1);
+        let rows: Vec<(String, i64)> = conn
+            .zrevrange_withscores(key, start as isize, stop as isize)
+            .await?;
+        Ok(rows
+            .into_iter()
+            .zip(page.offset + 1..)
+            .map(|((name, value), rank)| Ranked { rank, name, value })
+            .collect())
+    }
+
+    async fn stats(&self, name: &str) -> Result<Vec<StatValue>, RedisError> {
+        let mut conn = self.conn.clone();
+        let fields: HashMap<String, i64> = conn
+            .hgetall(format!("teleboxel:player:{name}:stats"))
+            .await?;
+        let mut stats: Vec<_> = fields
+            .into_iter()
+            .filter_map(|(field, value)| {
+                let (stat, world) = field.split_once('@')?;
+                Some(StatValue {
+                    world: world.to_string(),
+                    stat: stat.to_string(),
+                    value,
+                })
+            })
+            .collect();
+        stats.sort_by(|a, b| (&a.world, &a.stat).cmp(&(&b.world, &b.stat)));
+        Ok(stats)
+    }
+
+    async fn role(&self, name: &str) -> Result<Role, RedisError> {
+        let mut conn = self.conn.clone();
+        let role: Option<String> = conn.get(format!("teleboxel:role:{name}")).await?;
+        // Unknown roles (from a newer version) grant nothing
+        Ok(role.and_then(|r| r.parse().ok()).unwrap_or_default())
+    }
+
+    async fn set_role(&self, name: &str, role: Role) -> Result<(), RedisError> {
+        let mut conn = self.conn.clone();
+        let key = format!("teleboxel:role:{name}");
+        if role == Role::Player {
+            conn.del::<_, ()>(key).await
+        } else {
+            conn.set::<_, _, ()>(key, role.to_string()).await
+        }
+    }
+
+    async fn chunk(&self, pos: ChunkPos) -> Result<Option<Vec<u8>>, RedisError> {
+        let mut conn = self.conn.clone();
+        conn.get(chunk_key(pos)).await
+    }
+
+    async fn set_chunks(&self, chunks: &[(ChunkPos, Vec<u8>)]) -> Result<(), RedisError> {
+        let mut conn = self.conn.clone();
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        for (pos, data) in chunks {
+            pipe.set(chunk_key(*pos), data.as_slice()).ignore();
+        }
+        pipe.query_async(&mut conn).await
+    }
+
+    async fn claims(&self) -> Result<Option<Vec<u8>>, RedisError> {
+        let mut conn = self.conn.clone();
+        conn.get("teleboxel:claims").await
+    }
+
+    async fn set_claims(&self, data: &[u8]) -> Result<(), RedisError> {
+        let mut conn = self.conn.clone();
+        conn.set("teleboxel:claims", data).await
+    }
+}
+
+fn chunk_key((cx, cy, cz): ChunkPos) -> String {
+    format!("teleboxel:chunk:{cx}:{cy}:{cz}")
+}
+
+impl Storage for RedisStorage {
+    fn load_player<'a>(&'a self, name: &'a str) -> StorageFuture<'a, Option<PlayerRecord>> {
+        Box::pin(async move { Ok(self.load(name).await?) })
+    }
+
+    fn save_players<'a>(&'a self, records: &'a [PlayerRecord]) -> StorageFuture<'a, ()> {
+        Box::pin(async move { Ok(self.save(records).await?) })
+    }
+
+    fn backup<'a>(&'a self, _dest: &'a Path) -> StorageFuture<'a, ()> {
+        Box::pin(async {
+            Err("Redis backups are not supported, use BGSAVE / RDB snapshots".into())
+        })
+    }
+
+    fn add_stats<'a>(&'a self, deltas: &'a [StatDelta]) -> StorageFuture<'a, ()> {
+        Box::pin(async move { Ok(self.add(deltas).await?) })
+    }
+
+    fn leaderboard<'a>(
+        &'a self,
+        world: Option<&'a str>,
+        stat: &'a str,
+        page: Page,
+    ) -> StorageFuture<'a, Vec<Ranked>> {
+        Box::pin(async move { Ok(self.top(world, stat, page).await?) })
+    }
+
+    fn player_stats<'a>(&'a self, name: &'a str) -> StorageFuture<'a, Vec<StatValue>> {
+        Box::pin(async move { Ok(self.stats(name).await?) })
+    }
+
+    fn load_role<'a>(&'a self, name: &'a str) -> StorageFuture<'a, Role> {
+        Box::pin(async move { Ok(self.role(name).await?) })
+    }
+
+    fn save_role<'a>(&'a self, name: &'a str, role: Role) -> StorageFuture<'a, ()> {
+        Box::pin(async move { Ok(self.set_role(name, role).await?) })
+    }
+    fn load_chunk<'a>(&'a self, pos: ChunkPos) -> StorageFuture<'a, Option<Vec<u8>>> {
+        Box::pin(async move { Ok(self.chunk(pos).await?) })
+    }
+
+    fn save_chunks<'a>(&'a self, chunks: &'a [(ChunkPos, Vec<u8>)]) -> StorageFuture<'a, ()> {
+        Box::pin(async move { Ok(self.set_chunks(chunks).await?) })
+    }
+
+    fn load_claims<'a>(&'a self) -> StorageFuture<'a, Option<Vec<u8>>> {
+        Box::pin(async move { Ok(self.claims().await?) })
+    }
+
+    fn save_claims<'a>(&'a self, data: &'a [u8]) -> StorageFuture<'a, ()> {
+        Box::pin(async move { Ok(self.set_claims(data).await?) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Needs a server, skipped unless TELEBOXEL_TEST_REDIS_URL is set
+    #[tokio::test]
+    async fn keeps_players() {
+        let Ok(url) = std::env::var("TELEBOXEL_TEST_REDIS_URL") else {
+            return;
+        };
+        let storage = RedisStorage::connect(&url).await.unwrap();
+        let name = format!("test-{}-{}", std::process::id(), unix_now());
+        crate::storage::tests::keeps_players(&storage, &name).await;
+    }
+
+    // Needs a server, skipped unless TELEBOXEL_TEST_REDIS_URL is set
+    #[tokio::test]
+    async fn keeps_world() {
+        let Ok(url) = std::env::var("TELEBOXEL_TEST_REDIS_URL") else {
+            return;
+        };
+        let storage = RedisStorage::connect(&url).await.unwrap();
+        // A chunk far out, new to a database other tests ran against
+        let at = (std::process::id() as i32, unix_now() as i32, i32::MIN);
+        crate::storage::tests::keeps_world(&storage, at).await;
+    }
+}
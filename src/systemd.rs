@@ -0,0 +1,137 @@
+//! systemd integration, with the `systemd` feature (Unix only):
+//!
+//! - `Type=notify`: `READY=1` once serving, `STOPPING=1` on shutdown. A hot
+//!   restart's successor sends its own `READY=1` with `MAINPID`, so the unit
+//!   needs `NotifyAccess=all` to follow it.
+//! - `WatchdogSec=`: `WATCHDOG=1` at half the interval while the main world
+//!   answers within a second, so a wedged tick loop gets the server
+//!   restarted.
+//! - Socket activation: the sockets passed in (`LISTEN_FDS`) are the first
+//!   listeners (see `listeners.rs`), instead of binding them.
+//!
+//! Without the variables systemd sets, all of it does nothing.
+
+use std::{
+    env,
+    ffi::OsStr,
+    future::Future,
+    io,
+    os::{unix::ffi::OsStrExt, unix::net::UnixDatagram},
+    time::Duration,
+};
+
+// The first passed socket, see sd_listen_fds(3)
+const LISTEN_FDS_START: i32 = 3;
+
+/// Sends `state` (e.g. `READY=1`) to the service manager, if there's one.
+/// Errors are logged.
+pub fn notify(state: &str) {
+    if let Some(socket) = env::var_os("NOTIFY_SOCKET")
+        && let Err(e) = notify_to(&socket, state)
+    {
+        eprintln!("systemd notify: {e}");
+    }
+}
+
+fn notify_to(socket: &OsStr, state: &str) -> io::Result<()> {
+    let sender = UnixDatagram::unbound()?;
+    match socket.as_bytes().strip_prefix(b"@") {
+        #[cfg(target_os = "linux")]
+        Some(name) => {
+            use std::os::linux::net::SocketAddrExt;
+            let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+            sender.send_to_addr(state.as_bytes(), &addr)?;
+        }
+        #[cfg(not(target_os = "linux"))]
+        Some(_) => return Err(io::Error::other("abstract sockets need Linux")),
+        None => {
+            sender.send_to(state.as_bytes(), socket)?;
+        }
+    }
+    Ok(())
+}
+
+/// The socket-activated listeners' fds, if systemd passed any to us.
+pub fn listen_fds() -> Vec<i32> {
+    let (pid, fds) = (env::var("LISTEN_PID").ok(), env::var("LISTEN_FDS").ok());
+    listen_fds_from(pid.as_deref(), fds.as_deref(), std::process::id())
+}
+
+// Children (a hot restart's successor) inherit the variables, the pid says
+// whether they're ours
+fn listen_fds_from(pid: Option<&str>, fds: Option<&str>, ours: u32) -> Vec<i32> {
+    let pid: Option<u32> = pid.and_then(|pid| pid.parse().ok());
+    let fds: i32 = fds.and_then(|fds| fds.parse().ok()).unwrap_or(0);
+    if pid != Some(ours) {
+        return Vec::new();
+    }
+    (LISTEN_FDS_START..LISTEN_FDS_START + fds).collect()
+}
+
+/// How often to ping the watchdog, half its timeout. `None` without one.
+pub fn watchdog_interval() -> Option<Duration> {
+    let (usec, pid) = (
+        env::var("WATCHDOG_USEC").ok(),
+        env::var("WATCHDOG_PID").ok(),
+    );
+    watchdog_from(usec.as_deref(), pid.as_deref(), std::process::id())
+}
+
+fn watchdog_from(usec: Option<&str>, pid: Option<&str>, ours: u32) -> Option<Duration> {
+    if pid.is_some_and(|pid| pid.parse() != Ok(ours)) {
+        return None;
+    }
+    let usec: u64 = usec?.parse().ok().filter(|&usec| usec > 0)?;
+    Some(Duration::from_micros(usec / 2))
+}
+
+/// Pings the watchdog every `interval` while `alive`, until the process
+/// exits.
+pub async fn watchdog<A, F>(interval: Duration, alive: A)
+where
+    A: Fn() -> F,
+    F: Future<Output = bool>,
+{
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        match tokio::time::timeout(Duration::from_secs(1), alive()).await {
+            Ok(true) => notify("WATCHDOG=1"),
+            _ => eprintln!("The main world isn't answering, no watchdog ping"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_the_environment_and_notifies() {
+        assert_eq!(listen_fds_from(Some("42"), Some("2"), 42), [3, 4]);
+        assert_eq!(
+            listen_fds_from(Some("41"), Some("1"), 42),
+            Vec::<i32>::new()
+        );
+        assert_eq!(
+            listen_fds_from(Some("42"), Some("0"), 42),
+            Vec::<i32>::new()
+        );
+        assert_eq!(listen_fds_from(None, None, 42), Vec::<i32>::new());
+
+        let half = Some(Duration::from_secs(5));
+        assert_eq!(watchdog_from(Some("10000000"), None, 42), half);
+        assert_eq!(watchdog_from(Some("10000000"), Some("42"), 42), half);
+        assert_eq!(watchdog_from(Some("10000000"), Some("41"), 42), None);
+        assert_eq!(watchdog_from(Some("0"), None, 42), None);
+
+        let path = env::temp_dir().join(format!("teleboxel-notify-{}.sock", std::process::id()));
+        std::fs::remove_file(&path).ok();
+        let manager = UnixDatagram::bind(&path).unwrap();
+        notify_to(path.as_os_str(), "READY=1").unwrap();
+        let mut buf = [0; 64];
+        let n = manager.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"READY=1");
+        std::fs::remove_file(&path).ok();
+    }
+}
@@ -0,0 +1,397 @@
+//! Optional OTLP export of traces and metrics (OTLP/HTTP with JSON bodies),
+//! for collectors like the OpenTelemetry Collector, Grafana Tempo or Jaeger.
+//! Enabled by `TELEBOXEL_OTLP_ENDPOINT`, e.g. `http://localhost:4318`.
+//!
+//! Spans: a `connection` per websocket with a `command` child for each slow
+//! command, and `tick` / `world_message` for slow world ticks and messages
+//! ("slow" is `OtlpConfig::slow`). Metrics, every export: the traffic totals
+//! from `traffic.rs`, connected players, and tick count and time.
+
+use crate::{config::OtlpConfig, http, traffic::Traffic};
+use serde_json::{Value, json};
+use std::{
+    collections::hash_map::RandomState,
+    hash::BuildHasher,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use tokio::{sync::mpsc, time::MissedTickBehavior};
+
+// Spans queued for export, newer spans are dropped when full
+const QUEUE: usize = 4096;
+const BATCH: usize = 512;
+
+pub enum AttrValue {
+    Str(String),
+    Int(i64),
+}
+
+impl From<&str> for AttrValue {
+    fn from(v: &str) -> Self {
+        AttrValue::Str(v.to_string())
+    }
+}
+
+impl From<String> for AttrValue {
+    fn from(v: String) -> Self {
+        AttrValue::Str(v)
+    }
+}
+
+impl From<i64> for AttrValue {
+    fn from(v: i64) -> Self {
+        AttrValue::Int(v)
+    }
+}
+
+impl From<u32> for AttrValue {
+    fn from(v: u32) -> Self {
+        AttrValue::Int(v as i64)
+    }
+}
+
+struct SpanData {
+    trace_id: u128,
+    span_id: u64,
+    parent_id: Option<u64>,
+    name: &'static str,
+    start: SystemTime,
+    end: SystemTime,
+    attrs: Vec<(&'static str, AttrValue)>,
+    error: Option<String>,
+}
+
+/// A span, exported when dropped.
+pub struct Span {
+    tx: mpsc::Sender<SpanData>,
+    // Taken on drop
+    data: Option<SpanData>,
+}
+
+impl Span {
+    pub fn set(&mut self, key: &'static str, value: impl Into<AttrValue>) {
+        self.data_mut().attrs.push((key, value.into()));
+    }
+
+    /// Marks the span as failed.
+    pub fn error(&mut self, message: impl Into<String>) {
+        self.data_mut().error = Some(message.into());
+    }
+
+    fn data(&self) -> &SpanData {
+        self.data.as_ref().unwrap()
+    }
+
+    fn data_mut(&mut self) -> &mut SpanData {
+        self.data.as_mut().unwrap()
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        if let Some(mut data) = self.data.take() {
+            data.end = SystemTime::now();
+            self.tx.try_send(data).ok();
+        }
+    }
+}
+
+impl SpanData {
+    fn new(name: &'static str, parent: Option<&Span>) -> Self {
+        let now = SystemTime::now();
+        Self {
+            trace_id: match parent {
+                Some(p) => p.data().trace_id,
+                None => (random_u64() as u128) << 64 | random_u64() as u128,
+            },
+            span_id: random_u64(),
+            parent_id: parent.map(|p| p.data().span_id),
+            name,
+            start: now,
+            end: now,
+            attrs: Vec::new(),
+            error: None,
+        }
+    }
+}
+
+pub struct Telemetry {
+    tx: mpsc::Sender<SpanData>,
+    slow: Duration,
+    ticks: AtomicU64,
+    tick_nanos: AtomicU64,
+}
+
+impl Telemetry {
+    /// Starts the exporter task.
+    pub fn start(config: OtlpConfig, traffic: Arc<Traffic>) -> Arc<Self> {
+        let (tx, rx) = mpsc::channel(QUEUE);
+        let telemetry = Arc::new(Self {
+            tx,
+            slow: config.slow,
+            ticks: AtomicU64::new(0),
+            tick_nanos: AtomicU64::new(0),
+        });
+        tokio::spawn(export(config, rx, telemetry.clone(), traffic));
+        telemetry
+    }
+
+    pub fn span(&self, name: &'static str, parent: Option<&Span>) -> Span {
+        Span {
+            tx: self.tx.clone(),
+            data: Some(SpanData::new(name, parent)),
+        }
+    }
+
+    /// A span for work that began at `started`, only if it took at least the
+    /// slow threshold.
+    pub fn slow_span(
+        &self,
+        name: &'static str,
+        started: Instant,
+        parent: Option<&Span>,
+    ) -> Option<Span> {
+        let elapsed = started.elapsed();
+        if elapsed < self.slow {
+            return None;
+        }
+
+        let mut span = self.span(name, parent);
+        span.data_mut().start -= elapsed;
+        span.set("duration_ms", elapsed.as_millis() as i64);
+        Some(span)
+    }
+
+    /// Counts a world tick, for the tick metrics.
+    pub fn record_tick(&self, elapsed: Duration) {
+        self.ticks.fetch_add(1, Ordering::Relaxed);
+        self.tick_nanos
+            .fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed);
+    }
+}
+
+// Randomly keyed hashes of a counter, unique enough for trace and span ids
+pub(crate) fn random_u64() -> u64 {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    RandomState::new().hash_one(COUNTER.fetch_add(1, Ordering::Relaxed))
+}
+
+async fn export(
+    config: OtlpConfig,
+    mut rx: mpsc::Receiver<SpanData>,
+    telemetry: Arc<Telemetry>,
+    traffic: Arc<Traffic>,
+) {
+    let endpoint = config.endpoint.trim_end_matches('/');
+    let traces_url = format!("{endpoint}/v1/traces");
+    let metrics_url = format!("{endpoint}/v1/metrics");
+    let started = SystemTime::now();
+
+    let mut ticker = tokio::time::interval(config.interval);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut batch = Vec::new();
+
+    loop {
+        tokio::select! {
+            span = rx.recv() => {
+                let Some(span) = span else { break };
+                batch.push(span);
+                if batch.len() < BATCH {
+                    continue;
+                }
+            }
+            _ = ticker.tick() => {
+                let body = metrics_json(&telemetry, &traffic, started);
+                send(&metrics_url, body).await;
+            }
+        }
+
+        if !batch.is_empty() {
+            let body = traces_json(&std::mem::take(&mut batch));
+            send(&traces_url, body).await;
+        }
+    }
+}
+
+async fn send(url: &str, body: Value) {
+    let headers = [("content-type", "application/json")];
+    if let Err(e) = http::post(url, &headers, body.to_string()).await {
+        eprintln!("OTLP export to {url} failed: {e}");
+    }
+}
+
+fn resource() -> Value {
+    json!({
+        "attributes": [
+            { "key": "service.name", "value": { "stringValue": "teleboxel" } },
+            { "key": "service.version", "value": { "stringValue": env!("CARGO_PKG_VERSION") } },
+        ]
+    })
+}
+
+fn nanos(t: SystemTime) -> String {
+    // OTLP JSON carries 64-bit integers as strings
+    t.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+        .to_string()
+}
+
+fn attrs(attrs: &[(&str, AttrValue)]) -> Value {
+    let attrs: Vec<_> = attrs
+        .iter()
+        .map(|(key, value)| {
+            let value = match value {
+                AttrValue::Str(s) => json!({ "stringValue": s }),
+                AttrValue::Int(i) => json!({ "intValue": i.to_string() }),
+            };
+            json!({ "key": key, "value": value })
+        })
+        .collect();
+    Value::Array(attrs)
+}
+
+fn traces_json(spans: &[SpanData]) -> Value {
+    let spans: Vec<_> = spans
+        .iter()
+        .map(|s| {
+            let mut span = json!({
+                "traceId": format!("{:032x}", s.trace_id),
+                "spanId": format!("{:016x}", s.span_id),
+                "name": s.name,
+                // SPAN_KIND_INTERNAL, SPAN_KIND_SERVER for connections
+                "kind": if s.parent_id.is_none() && s.name == "connection" { 2 } else { 1 },
+                "startTimeUnixNano": nanos(s.start),
+                "endTimeUnixNano": nanos(s.end),
+                "attributes": attrs(&s.attrs),
+            });
+            if let Some(parent) = s.parent_id {
+                span["parentSpanId"] = format!("{parent:016x}").into();
+            }
+            if let Some(message) = &s.error {
+                span["status"] = json!({ "code": 2, "message": message });
+            }
+            span
+        })
+        .collect();
+
+    json!({
+        "resourceSpans": [{
+            "resource": resource(),
+            "scopeSpans": [{ "scope": { "name": "teleboxel" }, "spans": spans }],
+        }]
+    })
+}
+
+fn metrics_json(telemetry: &Telemetry, traffic: &Traffic, started: SystemTime) -> Value {
+    let (start, now) = (nanos(started), nanos(SystemTime::now()));
+    // Cumulative sums since startup
+    let sum = |name: &str, unit: &str, points: Vec<(Value, u64)>| {
+        let points: Vec<_> = points
+            .into_iter()
+            .map(|(attributes, value)| {
+                json!({
+                    "attributes": attributes,
+                    "startTimeUnixNano": start,
+                    "timeUnixNano": now,
+                    "asInt": value.to_string(),
+                })
+            })
+            .collect();
+        json!({
+            "name": name,
+            "unit": unit,
+            "sum": { "aggregationTemporality": 2, "isMonotonic": true, "dataPoints": points },
+        })
+    };
+
+    let totals = traffic.totals();
+    let by_kind = |value: fn(&crate::traffic::Counter) -> u64| {
+        totals
+            .iter()
+            .map(|((dir, kind), counter)| {
+                let labels = [("dir", dir.as_str().into()), ("kind", (*kind).into())];
+                (attrs(&labels), value(counter))
+            })
+            .collect()
+    };
+    let none = || json!([]);
+
+    json!({
+        "resourceMetrics": [{
+            "resource": resource(),
+            "scopeMetrics": [{
+                "scope": { "name": "teleboxel" },
+                "metrics": [
+                    sum("teleboxel.messages", "1", by_kind(|c| c.msgs)),
+                    sum("teleboxel.bytes", "By", by_kind(|c| c.bytes)),
+                    sum("teleboxel.ticks", "1", vec![(none(), telemetry.ticks.load(Ordering::Relaxed))]),
+                    sum("teleboxel.tick.time", "ns", vec![(none(), telemetry.tick_nanos.load(Ordering::Relaxed))]),
+                    {
+                        "name": "teleboxel.players",
+                        "unit": "1",
+                        "gauge": { "dataPoints": [{
+                            "timeUnixNano": now,
+                            "asInt": traffic.players().len().to_string(),
+                        }] },
+                    },
+                ],
+            }],
+        }]
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spans_export_as_otlp_json() {
+        let (tx, mut rx) = mpsc::channel(8);
+        let telemetry = Telemetry {
+            tx,
+            slow: Duration::from_millis(5),
+            ticks: AtomicU64::new(0),
+            tick_nanos: AtomicU64::new(0),
+        };
+
+        let mut connection = telemetry.span("connection", None);
+        connection.set("player.id", 7u32);
+        let started = Instant::now() - Duration::from_millis(20);
+        let mut command = telemetry
+            .slow_span("command", started, Some(&connection))
+            .unwrap();
+        command.error("world task dead");
+        assert!(telemetry.slow_span("tick", Instant::now(), None).is_none());
+        drop(command);
+        drop(connection);
+
+        let spans = [rx.try_recv().unwrap(), rx.try_recv().unwrap()];
+        let json = traces_json(&spans);
+        let spans = &json["resourceSpans"][0]["scopeSpans"][0]["spans"];
+        let (command, connection) = (&spans[0], &spans[1]);
+
+        assert_eq!(command["traceId"], connection["traceId"]);
+        assert_eq!(command["parentSpanId"], connection["spanId"]);
+        assert_eq!(command["status"]["code"], 2);
+        assert_eq!(connection["kind"], 2);
+        assert_eq!(
+            connection["attributes"][0],
+            json!({ "key": "player.id", "value": { "intValue": "7" } })
+        );
+        let start: u128 = command["startTimeUnixNano"]
+            .as_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        let end: u128 = command["endTimeUnixNano"]
+            .as_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!(end - start >= 20_000_000);
+    }
+}
@@ -0,0 +1,170 @@
+//! Room templates: rooms declared once in a TOML file
+//! (`TELEBOXEL_TEMPLATES`) and created by name, so a fleet of identical
+//! match rooms is one admin call each:
+//!
+//! ```toml
+//! [template.duel]
+//! map = "maps/duel"              # a world directory, see `import-vox`
+//! mode = "world"                 # or "lockstep", "rollback", "relay"
+//! input_delay = 3                # rollback rooms, see rollback.rs
+//! tick_hz = 30
+//! features = { build = false }   # see features.rs
+//! quota = { max_entities = 50, action = "close" }  # see quotas.rs
+//! pool = 2                       # idle rooms kept ready, see pool.rs
+//! pool_max = 10
+//! max_players = 2                # see reservations.rs
+//! spatial = "octree"             # entity index, see spatial.rs
+//! ```
+//!
+//! Everything is optional. Without a `map` the room forks the main world,
+//! with one it's that map alone, read-only like every room. Unset flags and
+//! limits come from `TELEBOXEL_FEATURES` and `TELEBOXEL_QUOTAS` as for any
+//! room, and `tick_hz` and `spatial` from the server's. There's no scripting, so
+//! templates have no scripts.
+//!
+//! `POST /admin/rooms?template=duel&room=match-1` creates one (named
+//! `duel-<n>` without `room`), `GET /admin/templates` lists them.
+
+use crate::{
+    command::RoomMode, features::Toggles, quotas::QuotaTable, rollback::MAX_DELAY,
+    spatial::SpatialKind,
+};
+use serde::Deserialize;
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// What a room is made with. The default is a plain forked room.
+#[derive(Deserialize, Default, Clone, PartialEq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Template {
+    pub map: Option<PathBuf>,
+    #[serde(default)]
+    pub mode: RoomMode,
+    /// Rollback rooms' input delay in frames, see `rollback.rs`.
+    pub input_delay: Option<u32>,
+    pub tick_hz: Option<u32>,
+    #[serde(default)]
+    pub features: Toggles,
+    #[serde(default)]
+    pub quota: QuotaTable,
+    #[serde(default)]
+    pub pool: usize,
+    pub pool_max: Option<usize>,
+    /// Joins past it need a reservation, see `reservations.rs`.
+    pub max_players: Option<usize>,
+    pub spatial: Option<SpatialKind>,
+}
+
+impl Template {
+    /// Idle rooms kept ready at most, see `pool.rs`.
+    pub fn pool_max(&self) -> usize {
+        self.pool_max.unwrap_or(self.pool)
+    }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct TemplatesFile {
+    #[serde(default)]
+    template: BTreeMap<String, Template>,
+}
+
+/// Templates by name.
+#[derive(Default)]
+pub struct Templates {
+    templates: BTreeMap<String, Template>,
+}
+
+impl Templates {
+    /// See the module docs for the file format. Map paths are relative to
+    /// the file.
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let mut templates = Self::parse(&fs::read_to_string(path)?)?;
+        let dir = path.parent().unwrap_or(Path::new(""));
+        for (name, template) in &mut templates.templates {
+            if let Some(map) = &mut template.map {
+                *map = dir.join(&*map);
+                if !map.is_dir() {
+                    return Err(format!("template {name}: no map at {}", map.display()).into());
+                }
+            }
+        }
+        Ok(templates)
+    }
+
+    fn parse(text: &str) -> Result<Self, Box<dyn Error>> {
+        let file: TemplatesFile = toml::from_str(text)?;
+        for (name, template) in &file.template {
+            if template.tick_hz == Some(0) {
+                return Err(format!("template {name}: tick_hz must be at least 1").into());
+            }
+            if template.pool_max() < template.pool {
+                return Err(format!("template {name}: pool_max is under pool").into());
+            }
+            if template.input_delay.is_some_and(|delay| delay > MAX_DELAY) {
+                return Err(format!("template {name}: input_delay is over {MAX_DELAY}").into());
+            }
+            if template.max_players == Some(0) {
+                return Err(format!("template {name}: max_players must be at least 1").into());
+            }
+        }
+        Ok(Self {
+            templates: file.template,
+        })
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Template> {
+        self.templates.get(name)
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.templates.keys().map(String::as_str)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Template)> {
+        self.templates.iter().map(|(name, t)| (name.as_str(), t))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_templates() {
+        let templates = Templates::parse(
+            r#"
+            [template.duel]
+            mode = "lockstep"
+            tick_hz = 30
+            features = { build = false }
+            quota = { max_entities = 50, action = "close" }
+            max_players = 2
+            spatial = "octree"
+
+            [template.plain]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(templates.names().collect::<Vec<_>>(), ["duel", "plain"]);
+        let duel = templates.get("duel").unwrap();
+        assert_eq!(duel.mode, RoomMode::Lockstep);
+        assert_eq!(duel.tick_hz, Some(30));
+        assert_eq!(duel.features.build, Some(false));
+        assert_eq!(duel.features.chat, None);
+        assert_eq!(duel.max_players, Some(2));
+        assert_eq!(duel.spatial, Some(SpatialKind::Octree));
+        assert_eq!(templates.get("plain"), Some(&Template::default()));
+
+        assert!(Templates::parse("[template.x]\nscripts = []").is_err());
+        assert!(Templates::parse("[template.x]\ntick_hz = 0").is_err());
+        assert!(Templates::parse("[template.x]\nmode = \"chess\"").is_err());
+        assert!(Templates::parse("[template.x]\npool = 3\npool_max = 2").is_err());
+        assert!(Templates::parse("[template.x]\nmax_players = 0").is_err());
+        assert!(Templates::parse("[template.x]\ninput_delay = 99").is_err());
+    }
+}
@@ -0,0 +1,226 @@
+//! Tenants: several games sharing one server, each with API keys that keep
+//! it to its own rooms. Keys are made with `POST /admin/tenants/<name>/keys`
+//! (the only time the key is shown), listed with `GET /admin/tenants` and
+//! revoked with `DELETE /admin/tenants/<name>/keys/<id>`. Only hashes are
+//! kept, in `TELEBOXEL_TENANTS` (JSON) when set, otherwise until restart.
+//!
+//! A tenant's connections come with `?api_key=`:
+//!
+//! - Rooms are namespaced: `?room=arena`, `RoomCreate arena` and
+//!   `JoinRoom arena` mean `<tenant>/arena`. Instead of the main world they
+//!   have `<tenant>/lobby`. Rooms are forked from the main world when first
+//!   joined. Other players can't name them, `/` isn't allowed in room
+//!   names.
+//! - Persistence is namespaced: records, stats, roles and presence go by
+//!   `<tenant>/<name>`, chat shows the plain name.
+//! - Metrics are labeled: per world ones get `tenant="<tenant>"`, traffic
+//!   lines show the room.
+//!
+//! A key also works as an `/admin` Bearer token for the moderation routes
+//! (`/world`, `/say`, kicks), with `?room=` read the same way.
+
+use crate::storage::unix_now;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Write},
+    fs, io,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+/// A tenant's room when it names none, its main world.
+pub const LOBBY: &str = "lobby";
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ApiKey {
+    pub id: String,
+    /// Unix seconds.
+    pub created: i64,
+    // SHA-256 of the whole key, hex
+    #[serde(skip_serializing_if = "String::is_empty", default)]
+    hash: String,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum TenantError {
+    InvalidName,
+    NoTenant,
+    NoKey,
+    Io(String),
+}
+
+impl fmt::Display for TenantError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TenantError::InvalidName => write!(f, "Invalid tenant name"),
+            TenantError::NoTenant => write!(f, "No such tenant"),
+            TenantError::NoKey => write!(f, "No such key"),
+            TenantError::Io(e) => write!(f, "Saving tenants: {e}"),
+        }
+    }
+}
+
+/// Up to 32 of `a-z`, `0-9` and `-`.
+pub fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 32
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-')
+}
+
+/// `name` within `tenant`'s namespace.
+pub fn scoped(tenant: &str, name: &str) -> String {
+    format!("{tenant}/{name}")
+}
+
+/// The tenant a world belongs to, from its name.
+pub fn of_world(world: &str) -> Option<&str> {
+    world.split_once('/').map(|(tenant, _)| tenant)
+}
+
+#[derive(Default)]
+pub struct Tenants {
+    path: Option<PathBuf>,
+    keys: Mutex<BTreeMap<String, Vec<ApiKey>>>,
+}
+
+impl Tenants {
+    /// From `path`, empty if it doesn't exist yet.
+    pub fn load(path: Option<&Path>) -> io::Result<Self> {
+        let keys = match path.map(fs::read) {
+            Some(Ok(data)) => serde_json::from_slice(&data).map_err(io::Error::other)?,
+            Some(Err(e)) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => BTreeMap::new(),
+        };
+        Ok(Self {
+            path: path.map(Path::to_path_buf),
+            keys: Mutex::new(keys),
+        })
+    }
+
+    /// The tenant of an API key.
+    pub fn check(&self, key: &str) -> Option<String> {
+        let id = key.strip_prefix("tbx_")?.split('_').next()?;
+        let hash = hash(key);
+        let keys = self.keys.lock().unwrap();
+        keys.iter()
+            .find(|(_, keys)| keys.iter().any(|k| k.id == id && k.hash == hash))
+            .map(|(tenant, _)| tenant.clone())
+    }
+
+    /// A new key for `tenant`, made on its first. Its id and the key.
+    pub fn create_key(&self, tenant: &str) -> Result<(String, String), TenantError> {
+        if !valid_name(tenant) {
+            return Err(TenantError::InvalidName);
+        }
+        let mut random = [0; 20];
+        getrandom::getrandom(&mut random).map_err(|e| TenantError::Io(e.to_string()))?;
+        let id = hex(&random[..4]);
+        let key = format!("tbx_{id}_{}", hex(&random[4..]));
+        let mut keys = self.keys.lock().unwrap();
+        keys.entry(tenant.to_string()).or_default().push(ApiKey {
+            id: id.clone(),
+            created: unix_now(),
+            hash: hash(&key),
+        });
+        self.save(&keys)?;
+        Ok((id, key))
+    }
+
+    pub fn revoke(&self, tenant: &str, id: &str) -> Result<(), TenantError> {
+        let mut keys = self.keys.lock().unwrap();
+        let tenant_keys = keys.get_mut(tenant).ok_or(TenantError::NoTenant)?;
+        let before = tenant_keys.len();
+        tenant_keys.retain(|k| k.id != id);
+        if tenant_keys.len() == before {
+            return Err(TenantError::NoKey);
+        }
+        self.save(&keys)
+    }
+
+    /// The tenant and all its keys. Its rooms stay until they empty.
+    pub fn remove(&self, tenant: &str) -> Result<(), TenantError> {
+        let mut keys = self.keys.lock().unwrap();
+        keys.remove(tenant).ok_or(TenantError::NoTenant)?;
+        self.save(&keys)
+    }
+
+    /// Keys by tenant, without their hashes.
+    pub fn list(&self) -> BTreeMap<String, Vec<ApiKey>> {
+        let mut keys = self.keys.lock().unwrap().clone();
+        for key in keys.values_mut().flatten() {
+            key.hash.clear();
+        }
+        keys
+    }
+
+    // Small and rarely changed, written in place under the lock
+    fn save(&self, keys: &BTreeMap<String, Vec<ApiKey>>) -> Result<(), TenantError> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let json = serde_json::to_vec_pretty(keys).unwrap();
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, json)
+            .and_then(|()| fs::rename(&tmp, path))
+            .map_err(|e| TenantError::Io(e.to_string()))
+    }
+}
+
+fn hash(key: &str) -> String {
+    hex(&Sha256::digest(key.as_bytes()))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len() * 2);
+    for b in bytes {
+        write!(out, "{b:02x}").unwrap();
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_map_to_tenants_and_persist_as_hashes() {
+        let dir = std::env::temp_dir().join(format!("teleboxel-tenants-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("tenants.json");
+        fs::remove_file(&path).ok();
+
+        let tenants = Tenants::load(Some(&path)).unwrap();
+        assert_eq!(tenants.create_key("Acme"), Err(TenantError::InvalidName));
+        let (id, key) = tenants.create_key("acme").unwrap();
+        let (other, _) = tenants.create_key("acme").unwrap();
+        assert!(key.starts_with(&format!("tbx_{id}_")));
+        assert_eq!(tenants.check(&key).as_deref(), Some("acme"));
+        assert_eq!(tenants.check(&format!("{key}0")), None);
+        assert_eq!(tenants.check("nope"), None);
+
+        // The file has no keys, only their hashes
+        let saved = fs::read_to_string(&path).unwrap();
+        assert!(!saved.contains(&key));
+        let tenants = Tenants::load(Some(&path)).unwrap();
+        assert_eq!(tenants.check(&key).as_deref(), Some("acme"));
+        let listed = tenants.list();
+        assert_eq!(listed["acme"].len(), 2);
+        assert!(listed["acme"].iter().all(|k| k.hash.is_empty()));
+
+        tenants.revoke("acme", &id).unwrap();
+        assert_eq!(tenants.check(&key), None);
+        assert_eq!(tenants.revoke("acme", &id), Err(TenantError::NoKey));
+        assert_eq!(tenants.revoke("other", &other), Err(TenantError::NoTenant));
+        tenants.remove("acme").unwrap();
+        assert!(tenants.list().is_empty());
+
+        assert_eq!(scoped("acme", "arena"), "acme/arena");
+        assert_eq!(of_world("acme/arena"), Some("acme"));
+        assert_eq!(of_world("main"), None);
+        fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,264 @@
+//! Procedural terrain. Generators run on the blocking thread pool (see
+//! `chunk_cache`), so generation never runs on the tick loop.
+
+use crate::chunk::{CHUNK_SIZE, Chunk, ChunkPos};
+
+pub const AIR: u16 = 0;
+pub const STONE: u16 = 1;
+pub const DIRT: u16 = 2;
+pub const GRASS: u16 = 3;
+
+/// Produces the initial contents of a chunk. Must be deterministic for a
+/// given position (chunks can be evicted and regenerated).
+pub trait ChunkGenerator: Send + Sync + 'static {
+    fn generate(&self, pos: ChunkPos) -> Chunk;
+
+    /// Terrain surface y at a world column, if cheaply known without
+    /// generating chunks (used to pick spawn points).
+    fn surface_height(&self, _x: i32, _z: i32) -> Option<i32> {
+        None
+    }
+}
+
+/// Flat world: stone below y = 0, grass at y = 0.
+pub struct FlatGenerator;
+
+impl ChunkGenerator for FlatGenerator {
+    fn generate(&self, (_, cy, _): ChunkPos) -> Chunk {
+        let mut chunk = Chunk::empty();
+        for y in 0..CHUNK_SIZE {
+            let wy = cy * CHUNK_SIZE as i32 + y as i32;
+            let block = match wy {
+                ..0 => STONE,
+                0 => GRASS,
+                _ => break,
+            };
+            fill_layer(&mut chunk, y, block);
+        }
+        chunk
+    }
+
+    fn surface_height(&self, _x: i32, _z: i32) -> Option<i32> {
+        Some(0)
+    }
+}
+
+/// Seeded fractal value-noise heightmap with 3D noise caves.
+pub struct NoiseGenerator {
+    seed: u64,
+}
+
+impl NoiseGenerator {
+    const BASE_HEIGHT: f32 = 32.0;
+    const AMPLITUDE: f32 = 24.0;
+    const CAVE_THRESHOLD: f32 = 0.55;
+
+    pub fn new(seed: u64) -> Self {
+        Self { seed }
+    }
+
+    fn height(&self, x: i32, z: i32) -> i32 {
+        let n = fbm2(self.seed, x as f32 / 96.0, z as f32 / 96.0, 4);
+        (Self::BASE_HEIGHT + Self::AMPLITUDE * n) as i32
+    }
+
+    fn is_cave(&self, x: i32, y: i32, z: i32) -> bool {
+        let n = value3(
+            self.seed ^ 0x5eed_cafe,
+            x as f32 / 24.0,
+            y as f32 / 16.0,
+            z as f32 / 24.0,
+        );
+        n > Self::CAVE_THRESHOLD
+    }
+}
+
+impl ChunkGenerator for NoiseGenerator {
+    fn generate(&self, (cx, cy, cz): ChunkPos) -> Chunk {
+        let size = CHUNK_SIZE as i32;
+        let mut chunk = Chunk::empty();
+
+        for z in 0..CHUNK_SIZE {
+            for x in 0..CHUNK_SIZE {
+                let (wx, wz) = (cx * size + x as i32, cz * size + z as i32);
+                let height = self.height(wx, wz);
+
+                for y in 0..CHUNK_SIZE {
+                    let wy = cy * size + y as i32;
+                    let above = wy > height;
+                    let cave = wy < height - 4 && self.is_cave(wx, wy, wz);
+                    let block = if above || cave {
+                        AIR
+                    } else if wy == height {
+                        GRASS
+                    } else if wy > height - 4 {
+                        DIRT
+                    } else {
+                        STONE
+                    };
+                    chunk.set(x, y, z, block);
+                }
+            }
+        }
+
+        chunk
+    }
+
+    fn surface_height(&self, x: i32, z: i32) -> Option<i32> {
+        Some(self.height(x, z))
+    }
+}
+
+fn fill_layer(chunk: &mut Chunk, y: usize, block: u16) {
+    for z in 0..CHUNK_SIZE {
+        for x in 0..CHUNK_SIZE {
+            chunk.set(x, y, z, block);
+        }
+    }
+}
+
+// Noise helpers: hashed lattice values in [-1, 1], smoothly interpolated
+
+fn hash(seed: u64, x: i32, y: i32, z: i32) -> f32 {
+    // splitmix64 over the packed lattice coordinates
+    let mut h = seed
+        ^ (x as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15)
+        ^ (y as u64).wrapping_mul(0xc2b2_ae3d_27d4_eb4f)
+        ^ (z as u64).wrapping_mul(0x1656_67b1_9e37_79f9);
+    h = (h ^ (h >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    h = (h ^ (h >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    h ^= h >> 31;
+    (h >> 40) as f32 / (1u64 << 23) as f32 - 1.0
+}
+
+fn smooth(t: f32) -> f32 {
+    t * t * (3.0 - 2.0 * t)
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+fn value3(seed: u64, x: f32, y: f32, z: f32) -> f32 {
+    let (x0, y0, z0) = (x.floor(), y.floor(), z.floor());
+    let (tx, ty, tz) = (smooth(x - x0), smooth(y - y0), smooth(z - z0));
+    let (x0, y0, z0) = (x0 as i32, y0 as i32, z0 as i32);
+
+    let corner = |dx, dy, dz| hash(seed, x0 + dx, y0 + dy, z0 + dz);
+    let face = |dz| {
+        lerp(
+            lerp(corner(0, 0, dz), corner(1, 0, dz), tx),
+            lerp(corner(0, 1, dz), corner(1, 1, dz), tx),
+            ty,
+        )
+    };
+
+    lerp(face(0), face(1), tz)
+}
+
+/// Fractal 2D value noise, roughly in [-1, 1].
+fn fbm2(seed: u64, x: f32, z: f32, octaves: u32) -> f32 {
+    let (mut sum, mut amplitude, mut frequency, mut norm) = (0.0, 1.0, 1.0, 0.0);
+    for octave in 0..octaves {
+        sum += amplitude
+            * value3(
+                seed.wrapping_add(octave as u64),
+                x * frequency,
+                0.0,
+                z * frequency,
+            );
+        norm += amplitude;
+        amplitude *= 0.5;
+        frequency *= 2.0;
+    }
+    sum / norm
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Topmost solid block of a world column from generated chunks, stacked
+    // over `cys`
+    fn column_top(generator: &dyn ChunkGenerator, x: i32, z: i32, cys: &[i32]) -> Option<i32> {
+        let size = CHUNK_SIZE as i32;
+        let (cx, lx) = (x.div_euclid(size), x.rem_euclid(size) as usize);
+        let (cz, lz) = (z.div_euclid(size), z.rem_euclid(size) as usize);
+        cys.iter().rev().find_map(|&cy| {
+            let chunk = generator.generate((cx, cy, cz));
+            (0..CHUNK_SIZE)
+                .rev()
+                .find(|&ly| chunk.get(lx, ly, lz) != AIR)
+                .map(|ly| cy * size + ly as i32)
+        })
+    }
+
+    #[test]
+    fn noise_is_deterministic_per_seed() {
+        let (a, b) = (NoiseGenerator::new(7), NoiseGenerator::new(7));
+        for pos in [(0, 1, 0), (-3, 0, 5), (12, 2, -40)] {
+            assert_eq!(a.generate(pos), b.generate(pos));
+        }
+        let other = NoiseGenerator::new(8);
+        assert!(
+            [(0, 1, 0), (-3, 1, 5), (12, 2, -40)]
+                .iter()
+                .any(|&pos| a.generate(pos) != other.generate(pos))
+        );
+    }
+
+    #[test]
+    fn noise_chunks_line_up_with_their_neighbours() {
+        let noise = NoiseGenerator::new(42);
+        let cys: Vec<_> = (0..4).collect();
+        // Across the chunk borders at x -16, 0, 16 and z 0
+        for z in [-1, 0] {
+            let mut last: Option<i32> = None;
+            for x in -17..17 {
+                let top = column_top(&noise, x, z, &cys).unwrap();
+                assert_eq!(Some(top), noise.surface_height(x, z), "column {x}, {z}");
+                if let Some(last) = last {
+                    assert!((top - last).abs() <= 2, "step at {x}, {z}");
+                }
+                last = Some(top);
+            }
+        }
+
+        // Stacked chunks agree on each column: grass at the surface, dirt
+        // below and air above, whichever chunks those fall in
+        let mut chunks = std::collections::HashMap::new();
+        let mut crossed = false;
+        for x in -16..16 {
+            let height = noise.surface_height(x, 0).unwrap();
+            crossed |= (height - 3).div_euclid(16) != (height + 1).div_euclid(16);
+            for y in height - 3..=height + 1 {
+                let pos = (x.div_euclid(16), y.div_euclid(16), 0);
+                let chunk = chunks.entry(pos).or_insert_with(|| noise.generate(pos));
+                let block = chunk.get(x.rem_euclid(16) as usize, y.rem_euclid(16) as usize, 0);
+                let expected = match y - height {
+                    1 => AIR,
+                    0 => GRASS,
+                    _ => DIRT,
+                };
+                assert_eq!(block, expected, "{x}, {y}");
+            }
+        }
+        assert!(crossed, "no surface near a vertical chunk border");
+    }
+
+    #[test]
+    fn flat_chunks_line_up() {
+        let flat = FlatGenerator;
+        assert_eq!(flat.generate((0, 0, 0)), flat.generate((-5, 0, 9)));
+        let cys = [-2, -1, 0, 1];
+        for (x, z) in [(0, 0), (-1, 15), (16, -17)] {
+            assert_eq!(column_top(&flat, x, z, &cys), Some(0));
+        }
+        let below = flat.generate((0, -1, 0));
+        let above = flat.generate((0, 0, 0));
+        assert_eq!(below.get(3, 15, 3), STONE);
+        assert_eq!(above.get(3, 0, 3), GRASS);
+        assert_eq!(above.get(3, 1, 3), AIR);
+        assert_eq!(flat.generate((0, 1, 0)), Chunk::empty());
+    }
+}
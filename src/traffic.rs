@@ -0,0 +1,302 @@
+//! Per-player traffic counters by message type, in and out, to find which
+//! feature is eating bandwidth. Outbound server frames count per submessage
+//! (plus `frame` headers) at their binary size, JSON clients included; text
+//! counts as `handshake`, `reply` and the command names.
+//!
+//! Totals outlive players and go out as Prometheus counters on
+//! `/admin/metrics`, with client messages dropped by message authentication
+//! (`secure.rs`); `/admin/traffic` lists the connected players. Usage
+//! records (`usage.rs`) are taken from the same counters.
+
+use crate::usage::Tally;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Write,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Dir {
+    In,
+    Out,
+}
+
+impl Dir {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Dir::In => "in",
+            Dir::Out => "out",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct Counter {
+    pub msgs: u64,
+    pub bytes: u64,
+}
+
+pub type Counters = BTreeMap<(Dir, &'static str), Counter>;
+
+/// One connection's counters. Cheap to share between the world task and the
+/// connection task.
+pub struct PlayerTraffic {
+    key: u64,
+    /// The world the player is in, e.g. `main` or `arena`.
+    pub world: String,
+    /// e.g. `7 (alice)` or `arena/3`
+    pub label: String,
+    counters: Mutex<Counters>,
+    totals: Arc<Mutex<Counters>>,
+    // Bytes at the last top talkers log, in and out
+    logged: Mutex<(u64, u64)>,
+    // When usage was last taken and the totals then, in and out
+    billed: Mutex<(Instant, Counter, Counter)>,
+}
+
+impl PlayerTraffic {
+    pub fn record(&self, dir: Dir, kind: &'static str, bytes: usize) {
+        for counters in [&self.counters, &*self.totals] {
+            let mut counters = counters.lock().unwrap();
+            let counter = counters.entry((dir, kind)).or_default();
+            counter.msgs += 1;
+            counter.bytes += bytes as u64;
+        }
+    }
+
+    pub fn counters(&self) -> Counters {
+        self.counters.lock().unwrap().clone()
+    }
+
+    /// Bytes so far, in and out.
+    pub fn bytes(&self) -> (u64, u64) {
+        let (sent_in, sent_out) = self.sums();
+        (sent_in.bytes, sent_out.bytes)
+    }
+
+    /// Messages and bytes so far, in and out.
+    pub fn sums(&self) -> (Counter, Counter) {
+        let counters = self.counters.lock().unwrap();
+        let sum = |d| {
+            counters.iter().filter(|((dir, _), _)| *dir == d).fold(
+                Counter::default(),
+                |sum, (_, c)| Counter {
+                    msgs: sum.msgs + c.msgs,
+                    bytes: sum.bytes + c.bytes,
+                },
+            )
+        };
+        (sum(Dir::In), sum(Dir::Out))
+    }
+
+    /// Connected time and counts since the last call, or since joining.
+    pub fn unbilled(&self, now: Instant) -> Tally {
+        let (sent_in, sent_out) = self.sums();
+        let mut billed = self.billed.lock().unwrap();
+        let (at, before_in, before_out) = std::mem::replace(&mut *billed, (now, sent_in, sent_out));
+        Tally {
+            seconds: now.saturating_duration_since(at).as_secs_f64(),
+            msgs_in: sent_in.msgs - before_in.msgs,
+            msgs_out: sent_out.msgs - before_out.msgs,
+            bytes_in: sent_in.bytes - before_in.bytes,
+            bytes_out: sent_out.bytes - before_out.bytes,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Traffic {
+    next_key: AtomicU64,
+    players: Mutex<HashMap<u64, Arc<PlayerTraffic>>>,
+    totals: Arc<Mutex<Counters>>,
+    // Dropped client messages by reason
+    rejected: Mutex<BTreeMap<&'static str, u64>>,
+}
+
+impl Traffic {
+    pub fn register(&self, world: String, label: String) -> Arc<PlayerTraffic> {
+        let key = self.next_key.fetch_add(1, Ordering::Relaxed);
+        let player = Arc::new(PlayerTraffic {
+            key,
+            world,
+            label,
+            counters: Mutex::default(),
+            totals: self.totals.clone(),
+            logged: Mutex::default(),
+            billed: Mutex::new((Instant::now(), Counter::default(), Counter::default())),
+        });
+        self.players.lock().unwrap().insert(key, player.clone());
+        player
+    }
+
+    /// The player's counts stay in the totals.
+    pub fn unregister(&self, player: &PlayerTraffic) {
+        self.players.lock().unwrap().remove(&player.key);
+    }
+
+    pub fn players(&self) -> Vec<Arc<PlayerTraffic>> {
+        let mut players: Vec<_> = self.players.lock().unwrap().values().cloned().collect();
+        players.sort_by_key(|p| p.key);
+        players
+    }
+
+    /// Counters of every player since startup.
+    pub fn totals(&self) -> Counters {
+        self.totals.lock().unwrap().clone()
+    }
+
+    /// Counts a client message dropped for a bad signature (`mac`) or a used
+    /// sequence number (`replay`).
+    pub fn reject(&self, reason: &'static str) {
+        *self.rejected.lock().unwrap().entry(reason).or_default() += 1;
+    }
+
+    /// Counters since startup, in Prometheus text format.
+    pub fn prometheus(&self) -> String {
+        let totals = self.totals.lock().unwrap();
+        let mut out = String::new();
+        for (metric, help) in [
+            (
+                "teleboxel_messages_total",
+                "Messages by direction and type.",
+            ),
+            (
+                "teleboxel_bytes_total",
+                "Bytes by direction and message type.",
+            ),
+        ] {
+            writeln!(out, "# HELP {metric} {help}\n# TYPE {metric} counter").unwrap();
+            for ((dir, kind), counter) in totals.iter() {
+                let value = match metric {
+                    "teleboxel_messages_total" => counter.msgs,
+                    _ => counter.bytes,
+                };
+                let dir = dir.as_str();
+                writeln!(out, "{metric}{{dir=\"{dir}\",kind=\"{kind}\"}} {value}").unwrap();
+            }
+        }
+        writeln!(
+            out,
+            "# HELP teleboxel_rejected_messages_total Client messages dropped by \
+             message authentication, by reason.\n\
+             # TYPE teleboxel_rejected_messages_total counter"
+        )
+        .unwrap();
+        for (reason, count) in self.rejected.lock().unwrap().iter() {
+            writeln!(
+                out,
+                "teleboxel_rejected_messages_total{{reason=\"{reason}\"}} {count}"
+            )
+            .unwrap();
+        }
+        writeln!(
+            out,
+            "# HELP teleboxel_players Connected players.\n\
+             # TYPE teleboxel_players gauge\n\
+             teleboxel_players {}",
+            self.players.lock().unwrap().len()
+        )
+        .unwrap();
+        out
+    }
+
+    /// The `n` players that moved the most bytes (in + out) since the last
+    /// call, with their (in, out) bytes over that time.
+    pub fn top_talkers(&self, n: usize) -> Vec<(String, u64, u64)> {
+        let mut talkers: Vec<_> = self
+            .players()
+            .iter()
+            .map(|p| {
+                let (bytes_in, bytes_out) = p.bytes();
+                let mut logged = p.logged.lock().unwrap();
+                let delta = (bytes_in - logged.0, bytes_out - logged.1);
+                *logged = (bytes_in, bytes_out);
+                (p.label.clone(), delta.0, delta.1)
+            })
+            .filter(|&(_, bytes_in, bytes_out)| bytes_in + bytes_out > 0)
+            .collect();
+        talkers.sort_by_key(|&(_, bytes_in, bytes_out)| std::cmp::Reverse(bytes_in + bytes_out));
+        talkers.truncate(n);
+        talkers
+    }
+
+    /// Logs the top talkers every `interval` until the process exits.
+    pub async fn log_top_talkers(self: Arc<Self>, interval: Duration) {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.tick().await;
+
+        loop {
+            ticker.tick().await;
+            let talkers = self.top_talkers(5);
+            if talkers.is_empty() {
+                continue;
+            }
+
+            let secs = interval.as_secs_f64();
+            let list: Vec<_> = talkers
+                .iter()
+                .map(|(label, bytes_in, bytes_out)| {
+                    format!(
+                        "{label} {:.0} B/s in {:.0} B/s out",
+                        *bytes_in as f64 / secs,
+                        *bytes_out as f64 / secs
+                    )
+                })
+                .collect();
+            println!("Top talkers: {}", list.join(", "));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_per_player_and_in_totals() {
+        let traffic = Traffic::default();
+        let alice = traffic.register("main".into(), "1 (alice)".into());
+        let bob = traffic.register("main".into(), "2".into());
+
+        alice.record(Dir::Out, "chunk_snapshot", 900);
+        alice.record(Dir::Out, "chunk_snapshot", 100);
+        alice.record(Dir::In, "SetBlock", 20);
+        bob.record(Dir::Out, "chunk_delta", 30);
+
+        assert_eq!(
+            alice.counters()[&(Dir::Out, "chunk_snapshot")],
+            Counter {
+                msgs: 2,
+                bytes: 1000
+            }
+        );
+        assert_eq!(
+            traffic.top_talkers(1),
+            [("1 (alice)".to_string(), 20, 1000)]
+        );
+        // Only what moved since the last call
+        bob.record(Dir::Out, "chunk_delta", 30);
+        assert_eq!(traffic.top_talkers(5), [("2".to_string(), 0, 30)]);
+
+        traffic.unregister(&alice);
+        assert_eq!(traffic.players().len(), 1);
+        let metrics = traffic.prometheus();
+        assert!(
+            metrics.contains("teleboxel_bytes_total{dir=\"out\",kind=\"chunk_snapshot\"} 1000")
+        );
+        assert!(metrics.contains("teleboxel_messages_total{dir=\"out\",kind=\"chunk_delta\"} 2"));
+        assert!(metrics.contains("teleboxel_players 1"));
+
+        traffic.reject("replay");
+        traffic.reject("replay");
+        assert!(
+            traffic
+                .prometheus()
+                .contains("teleboxel_rejected_messages_total{reason=\"replay\"} 2")
+        );
+    }
+}
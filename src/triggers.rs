@@ -0,0 +1,207 @@
+//! Triggers: named boxes or spheres of blocks in a world or room that notice
+//! players walking in and out (checkpoints, kill zones, scripted areas),
+//! configured in a TOML file (`TELEBOXEL_TRIGGERS`):
+//!
+//! ```toml
+//! [[trigger]]
+//! name = "start"
+//! room = "main"
+//! min = [-8, 40, -8]
+//! max = [8, 48, 8]
+//! spawn = true           # new players join in here
+//!
+//! [[trigger]]
+//! name = "lava"
+//! room = "arena"
+//! center = [0, 20, 0]
+//! radius = 6
+//! replicate = true       # the player gets TRIGGER messages
+//! ```
+//!
+//! A trigger is a box (`min`, `max`, inclusive) or a sphere (`center`,
+//! `radius` in blocks). There's no simulation trait or scripting to call
+//! back into yet, so entering and leaving go out as `trigger_entered` and
+//! `trigger_exited` webhooks, and to the player as `TRIGGER` when the
+//! trigger has `replicate` set. Like portals, only moves cross them: a
+//! player joining inside or leaving the world doesn't enter or exit.
+//!
+//! The first `spawn` trigger of a world is where its new players join: the
+//! top of the terrain at its center column, or the center if that's air.
+
+use crate::claims::BlockPos;
+use serde::Deserialize;
+use std::{error::Error, fs, path::Path};
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct TriggersFile {
+    #[serde(default)]
+    trigger: Vec<Trigger>,
+}
+
+#[derive(Default)]
+pub struct Triggers {
+    triggers: Vec<Trigger>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Trigger {
+    pub name: String,
+    pub room: String,
+    min: Option<BlockPos>,
+    max: Option<BlockPos>,
+    center: Option<BlockPos>,
+    radius: Option<u32>,
+    #[serde(default)]
+    pub spawn: bool,
+    #[serde(default)]
+    pub replicate: bool,
+}
+
+/// A trigger a move crossed, `entered` or left.
+#[derive(Debug)]
+pub struct Crossing<'a> {
+    pub trigger: &'a Trigger,
+    pub entered: bool,
+}
+
+impl Triggers {
+    /// See the module docs for the file format.
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    fn parse(text: &str) -> Result<Self, Box<dyn Error>> {
+        let file: TriggersFile = toml::from_str(text)?;
+        for (i, trigger) in file.trigger.iter().enumerate() {
+            let shape = (trigger.min, trigger.max, trigger.center, trigger.radius);
+            if !matches!(
+                shape,
+                (Some(_), Some(_), None, None) | (None, None, Some(_), Some(_))
+            ) {
+                let e = format!("trigger {i}: needs min and max, or center and radius");
+                return Err(e.into());
+            }
+            if file.trigger[..i].iter().any(|t| t.name == trigger.name) {
+                return Err(format!("trigger {i}: {} is taken", trigger.name).into());
+            }
+        }
+        Ok(Self {
+            triggers: file.trigger,
+        })
+    }
+
+    /// The triggers in `room` a player moving `from` -> `to` entered or
+    /// left, in file order.
+    pub fn crossed<'a>(
+        &'a self,
+        room: &'a str,
+        from: BlockPos,
+        to: BlockPos,
+    ) -> impl Iterator<Item = Crossing<'a>> + 'a {
+        self.triggers
+            .iter()
+            .filter(move |t| t.room == room)
+            .filter_map(move |trigger| {
+                let entered = trigger.contains(to);
+                (trigger.contains(from) != entered).then_some(Crossing { trigger, entered })
+            })
+    }
+
+    /// Center of the first `spawn` trigger in `room`.
+    pub fn spawn(&self, room: &str) -> Option<BlockPos> {
+        self.triggers
+            .iter()
+            .find(|t| t.room == room && t.spawn)
+            .map(Trigger::center)
+    }
+}
+
+impl Trigger {
+    pub fn contains(&self, (x, y, z): BlockPos) -> bool {
+        match (self.min, self.max, self.center, self.radius) {
+            (Some(min), Some(max), _, _) => {
+                (min.0..=max.0).contains(&x)
+                    && (min.1..=max.1).contains(&y)
+                    && (min.2..=max.2).contains(&z)
+            }
+            (_, _, Some((cx, cy, cz)), Some(radius)) => {
+                let d = [x - cx, y - cy, z - cz].map(|d| (d as i64).pow(2));
+                d.iter().sum::<i64>() <= (radius as i64).pow(2)
+            }
+            _ => false,
+        }
+    }
+
+    pub fn center(&self) -> BlockPos {
+        match (self.min, self.max, self.center) {
+            (Some(min), Some(max), _) => (
+                min.0 + (max.0 - min.0) / 2,
+                min.1 + (max.1 - min.1) / 2,
+                min.2 + (max.2 - min.2) / 2,
+            ),
+            (_, _, Some(center)) => center,
+            _ => (0, 0, 0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crosses_boxes_and_spheres() {
+        let triggers = Triggers::parse(
+            r#"
+            [[trigger]]
+            name = "start"
+            room = "main"
+            min = [0, 40, 0]
+            max = [4, 44, 4]
+            spawn = true
+
+            [[trigger]]
+            name = "lava"
+            room = "main"
+            center = [10, 40, 0]
+            radius = 2
+            replicate = true
+            "#,
+        )
+        .unwrap();
+
+        let names = |from, to| {
+            triggers
+                .crossed("main", from, to)
+                .map(|c| (c.trigger.name.as_str(), c.entered))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names((-1, 40, 0), (0, 40, 0)), [("start", true)]);
+        assert_eq!(names((1, 41, 1), (2, 42, 2)), []);
+        assert_eq!(
+            names((4, 40, 0), (8, 40, 0)),
+            [("start", false), ("lava", true)]
+        );
+        // Corners of the sphere's bounding box are outside it
+        assert_eq!(names((0, 30, 0), (12, 42, 0)), []);
+        assert!(
+            triggers
+                .crossed("arena", (-1, 40, 0), (0, 40, 0))
+                .next()
+                .is_none()
+        );
+
+        assert_eq!(triggers.spawn("main"), Some((2, 42, 2)));
+        assert_eq!(triggers.spawn("arena"), None);
+
+        let both = "[[trigger]]\nname = \"a\"\nroom = \"main\"\nmin = [0, 0, 0]\n\
+                    max = [0, 0, 0]\nradius = 3";
+        assert!(Triggers::parse(both).is_err());
+        let taken = "[[trigger]]\nname = \"a\"\nroom = \"main\"\ncenter = [0, 0, 0]\n\
+                     radius = 1\n[[trigger]]\nname = \"a\"\nroom = \"b\"\n\
+                     center = [0, 0, 0]\nradius = 1";
+        assert!(Triggers::parse(taken).is_err());
+    }
+}
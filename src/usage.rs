@@ -0,0 +1,219 @@
+//! Usage accounting, to attribute hosting costs when running teleboxel as a
+//! service. With `TELEBOXEL_USAGE_LOG` set, every `TELEBOXEL_USAGE_SECS`
+//! (300) one JSON line per tenant and room active in the period is
+//! appended to it:
+//!
+//! ```text
+//! {"start":1760000000,"end":1760000300,"tenant":"acme","room":"arena",
+//!  "connection_minutes":12.5,"messages_in":900,"messages_out":4500,
+//!  "bytes_in":30000,"bytes_out":2100000}
+//! ```
+//!
+//! `tenant` is `null` outside tenants (see `tenants.rs`), the main world is
+//! room `main`. Counts are the traffic counters (`traffic.rs`) of players
+//! in the room, taken when they leave it and at each export, so a
+//! connection moving between rooms is split between them. The last period
+//! is written on shutdown.
+
+use crate::{
+    config::UsageConfig,
+    storage::unix_now,
+    tenants,
+    traffic::{PlayerTraffic, Traffic},
+};
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+/// Connected time and traffic of one player, or summed over a room.
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+pub struct Tally {
+    pub seconds: f64,
+    pub msgs_in: u64,
+    pub msgs_out: u64,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+}
+
+impl Tally {
+    fn add(&mut self, other: Tally) {
+        self.seconds += other.seconds;
+        self.msgs_in += other.msgs_in;
+        self.msgs_out += other.msgs_out;
+        self.bytes_in += other.bytes_in;
+        self.bytes_out += other.bytes_out;
+    }
+}
+
+/// One line of the log.
+#[derive(Serialize, PartialEq, Debug)]
+pub struct Record {
+    /// Unix seconds.
+    pub start: i64,
+    pub end: i64,
+    pub tenant: Option<String>,
+    pub room: String,
+    pub connection_minutes: f64,
+    pub messages_in: u64,
+    pub messages_out: u64,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+}
+
+pub struct UsageLog {
+    config: UsageConfig,
+    period: Mutex<Period>,
+}
+
+// Since the last export, by world name
+struct Period {
+    start: i64,
+    worlds: BTreeMap<String, Tally>,
+}
+
+impl UsageLog {
+    pub fn open(config: UsageConfig) -> io::Result<Self> {
+        if let Some(dir) = config.path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        Ok(Self {
+            config,
+            period: Mutex::new(Period {
+                start: unix_now(),
+                worlds: BTreeMap::new(),
+            }),
+        })
+    }
+
+    /// What's left to count of a player leaving its world.
+    pub fn left(&self, player: &PlayerTraffic) {
+        let tally = player.unbilled(Instant::now());
+        let mut period = self.period.lock().unwrap();
+        period
+            .worlds
+            .entry(player.world.clone())
+            .or_default()
+            .add(tally);
+    }
+
+    /// Ends the period, with the players still connected.
+    pub fn records(&self, live: &[Arc<PlayerTraffic>]) -> Vec<Record> {
+        let now = Instant::now();
+        let mut period = self.period.lock().unwrap();
+        for player in live {
+            let tally = player.unbilled(now);
+            period
+                .worlds
+                .entry(player.world.clone())
+                .or_default()
+                .add(tally);
+        }
+        let end = unix_now();
+        let start = std::mem::replace(&mut period.start, end);
+        std::mem::take(&mut period.worlds)
+            .into_iter()
+            .map(|(world, tally)| {
+                let (tenant, room) = match tenants::of_world(&world) {
+                    Some(tenant) => (Some(tenant.to_string()), world[tenant.len() + 1..].into()),
+                    None => (None, world),
+                };
+                Record {
+                    start,
+                    end,
+                    tenant,
+                    room,
+                    connection_minutes: tally.seconds / 60.0,
+                    messages_in: tally.msgs_in,
+                    messages_out: tally.msgs_out,
+                    bytes_in: tally.bytes_in,
+                    bytes_out: tally.bytes_out,
+                }
+            })
+            .collect()
+    }
+
+    /// Appends the period's records. Write errors are logged, the period's
+    /// counts are lost then.
+    pub fn export(&self, live: &[Arc<PlayerTraffic>]) {
+        let mut lines = String::new();
+        for record in self.records(live) {
+            lines += &serde_json::to_string(&record).unwrap();
+            lines.push('\n');
+        }
+        if lines.is_empty() {
+            return;
+        }
+        let written = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.config.path)
+            .and_then(|mut file| file.write_all(lines.as_bytes()));
+        if let Err(e) = written {
+            eprintln!("Usage log {}: {e}", self.config.path.display());
+        }
+    }
+
+    /// Exports every interval until the process exits.
+    pub async fn run(self: Arc<Self>, traffic: Arc<Traffic>) {
+        let mut ticker = tokio::time::interval(self.config.interval);
+        ticker.tick().await;
+
+        loop {
+            ticker.tick().await;
+            self.export(&traffic.players());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::traffic::Dir;
+    use std::time::Duration;
+
+    #[test]
+    fn records_rooms_by_tenant() {
+        let path =
+            std::env::temp_dir().join(format!("teleboxel-usage-{}.jsonl", std::process::id()));
+        fs::remove_file(&path).ok();
+        let usage = UsageLog::open(UsageConfig {
+            path: path.clone(),
+            interval: Duration::from_secs(300),
+        })
+        .unwrap();
+        let traffic = Traffic::default();
+        let alice = traffic.register("acme/arena".into(), "acme/arena/1".into());
+        let bob = traffic.register("main".into(), "2".into());
+        alice.record(Dir::In, "SetBlock", 20);
+        alice.record(Dir::Out, "chunk_snapshot", 900);
+        bob.record(Dir::In, "Chat", 10);
+
+        // Bob's counts are taken on leaving, not again at the export
+        usage.left(&bob);
+        traffic.unregister(&bob);
+        let records = usage.records(&traffic.players());
+        assert_eq!(records.len(), 2);
+        let (arena, main) = (&records[0], &records[1]);
+        assert_eq!(arena.tenant.as_deref(), Some("acme"));
+        assert_eq!(arena.room, "arena");
+        assert_eq!((arena.messages_in, arena.bytes_in), (1, 20));
+        assert_eq!((arena.messages_out, arena.bytes_out), (1, 900));
+        assert_eq!((main.tenant.as_deref(), main.room.as_str()), (None, "main"));
+        assert_eq!(main.bytes_in, 10);
+
+        // Only what happened since
+        alice.record(Dir::Out, "chunk_delta", 30);
+        usage.export(&traffic.players());
+        let line = fs::read_to_string(&path).unwrap();
+        let record: serde_json::Value = serde_json::from_str(line.trim()).unwrap();
+        assert_eq!(record["room"], "arena");
+        assert_eq!(record["bytes_out"], 30);
+        assert_eq!(record["messages_in"], 0);
+        fs::remove_file(&path).ok();
+    }
+}
@@ -0,0 +1,179 @@
+//! Proximity voice chat. Players in a world send Opus frames as
+//! `VOICE_SEND` in binary client frames, and the server forwards each as
+//! `VOICE` to the players who can hear it: those whose interest holds the
+//! speaker's chunk and who are within `TELEBOXEL_VOICE_RANGE` blocks, minus
+//! the ones muting the speaker (`VoiceMute Id`). `VOICE` carries the
+//! distance so clients can fade voices out; the server doesn't mix or
+//! decode anything.
+//!
+//! With `TELEBOXEL_VOICE_OCCLUSION` it also carries how many solid blocks
+//! are on the straight line between their heads (`occlusion`), for clients
+//! to muffle voices through walls without having the map in between.
+//! Chunks that aren't loaded count as air, and it's 0 when off.
+//!
+//! Each connection may send `TELEBOXEL_VOICE_KBPS` kilobits a second, with
+//! a second's worth of burst. Frames over that are dropped without a reply,
+//! like packets lost on the way, so a misbehaving client can't flood a
+//! crowd. `0` turns voice off.
+
+use crate::chunk::{self, ChunkPos};
+use std::time::Instant;
+
+/// Largest Opus packet, in bytes.
+pub const MAX_FRAME_LEN: usize = 1275;
+/// Speakers each player can mute.
+pub const MAX_MUTED: usize = 256;
+/// Most solid blocks `occlusion` counts.
+pub const MAX_OCCLUSION: u8 = 32;
+
+/// Per-connection cap on voice bytes, a token bucket.
+pub struct Bitrate {
+    // Bytes a second, also the bucket size
+    rate: f64,
+    allowance: f64,
+    last: Option<Instant>,
+}
+
+impl Bitrate {
+    pub fn new(kbps: u32) -> Self {
+        let rate = kbps as f64 * 1000.0 / 8.0;
+        Self {
+            rate,
+            allowance: rate,
+            last: None,
+        }
+    }
+
+    pub fn is_off(&self) -> bool {
+        self.rate == 0.0
+    }
+
+    /// Whether a frame of `len` bytes fits under the cap at `now`, taking
+    /// it from the allowance if so.
+    pub fn allow(&mut self, len: usize, now: Instant) -> bool {
+        if let Some(last) = self.last {
+            let refill = now.saturating_duration_since(last).as_secs_f64() * self.rate;
+            self.allowance = (self.allowance + refill).min(self.rate);
+        }
+        self.last = Some(now);
+        if len as f64 > self.allowance {
+            return false;
+        }
+        self.allowance -= len as f64;
+        true
+    }
+}
+
+/// How far a listener at `listener` is from a speaker at `speaker`, in
+/// blocks rounded to the nearest, or `None` if they can't hear them: the
+/// speaker's chunk is outside their `interest` (center and radius in
+/// chunks) or they're more than `range` blocks away.
+pub fn distance(
+    speaker: (i32, i32, i32),
+    listener: (i32, i32, i32),
+    interest: Option<(ChunkPos, u16)>,
+    range: u32,
+) -> Option<u16> {
+    let (center, radius) = interest?;
+    let chunk = (
+        chunk::split(speaker.0).0,
+        chunk::split(speaker.1).0,
+        chunk::split(speaker.2).0,
+    );
+    let radius = radius as i32;
+    if (chunk.0 - center.0).abs() > radius
+        || (chunk.1 - center.1).abs() > radius
+        || (chunk.2 - center.2).abs() > radius
+    {
+        return None;
+    }
+
+    let d = |a: i32, b: i32| (a as f64 - b as f64).powi(2);
+    let distance = (d(speaker.0, listener.0) + d(speaker.1, listener.1) + d(speaker.2, listener.2))
+        .sqrt()
+        .round();
+    (distance <= range as f64).then_some(distance.min(u16::MAX as f64) as u16)
+}
+
+/// Solid blocks between a speaker and a listener at these (feet)
+/// positions, on the line between the centers of their head blocks and not
+/// counting those, up to `MAX_OCCLUSION`. Walks every block the line
+/// crosses, `solid` tells which are. Heads at the top of the world stay in
+/// the highest block.
+pub fn occlusion(
+    speaker: (i32, i32, i32),
+    listener: (i32, i32, i32),
+    solid: impl Fn((i32, i32, i32)) -> bool,
+) -> u8 {
+    let from = [speaker.0, speaker.1.saturating_add(1), speaker.2];
+    let to = [listener.0, listener.1.saturating_add(1), listener.2];
+    let delta = [0, 1, 2].map(|i| to[i] as f64 - from[i] as f64);
+    let step = delta.map(|d| d.signum() as i32);
+    // Line lengths (0 to 1) to the next block boundary on each axis, the
+    // first half a block away
+    let per_block = delta.map(|d| 1.0 / d.abs());
+    let mut next = per_block.map(|t| t / 2.0);
+    let steps = (0..3)
+        .map(|i| from[i].abs_diff(to[i]))
+        .fold(0, u32::saturating_add);
+
+    let mut cell = from;
+    let mut count = 0;
+    for _ in 1..steps {
+        let axis = (0..3).min_by(|&a, &b| next[a].total_cmp(&next[b])).unwrap();
+        cell[axis] += step[axis];
+        next[axis] += per_block[axis];
+        if solid((cell[0], cell[1], cell[2])) {
+            count += 1;
+            if count == MAX_OCCLUSION {
+                break;
+            }
+        }
+    }
+    count
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn hears_nearby_speakers_under_the_cap() {
+        let interest = Some(((0, 0, 0), 1));
+        assert_eq!(distance((3, 0, 4), (0, 0, 0), interest, 48), Some(5));
+        assert_eq!(distance((40, 0, 0), (0, 0, 0), interest, 32), None);
+        // In range, but the listener's interest doesn't reach that chunk
+        assert_eq!(
+            distance((-17, 0, 0), (-1, 0, 0), Some(((0, 0, 0), 0)), 48),
+            None
+        );
+        assert_eq!(distance((0, 0, 0), (0, 0, 0), None, 48), None);
+
+        // A wall at x = 5, 3 thick above y = 0
+        let wall = |(x, y, _): (i32, i32, i32)| (5..8).contains(&x) && y >= 0;
+        assert_eq!(occlusion((0, 0, 0), (10, 0, 0), wall), 3);
+        // Diagonally through more of it
+        assert_eq!(occlusion((10, 0, 3), (0, 0, -2), wall), 5);
+        assert_eq!(occlusion((0, 0, 0), (4, 0, 9), wall), 0);
+        // Over it, and standing in it
+        assert_eq!(occlusion((0, -5, 0), (10, -5, 0), wall), 0);
+        assert_eq!(occlusion((5, 0, 0), (7, 0, 0), wall), 1);
+        assert_eq!(occlusion((0, 0, 0), (0, 0, 0), wall), 0);
+        let rock = |_| true;
+        assert_eq!(occlusion((0, 0, 0), (100, 40, 0), rock), MAX_OCCLUSION);
+        // At the top of the world
+        let top = (0, i32::MAX, 0);
+        assert_eq!(occlusion(top, (10, i32::MAX, 0), wall), 3);
+        assert_eq!(occlusion(top, (0, i32::MIN, 0), rock), MAX_OCCLUSION);
+
+        // 8 kbps is 1000 bytes a second
+        let mut cap = Bitrate::new(8);
+        let start = Instant::now();
+        assert!(cap.allow(800, start));
+        assert!(!cap.allow(300, start));
+        assert!(cap.allow(300, start + Duration::from_millis(100)));
+        assert!(!Bitrate::new(0).allow(1, start));
+        assert!(Bitrate::new(0).is_off());
+    }
+}
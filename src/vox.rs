@@ -0,0 +1,484 @@
+//! MagicaVoxel `.vox` import/export.
+//!
+//! Block ids map 1:1 to palette indices (`1..=255`), so block ids above 255
+//! can't be exported and are skipped. Palettes (`RGBA`) are ignored on import
+//! and not written on export, MagicaVoxel then shows its default palette.
+//!
+//! MagicaVoxel is Z-up, teleboxel is Y-up: vox `(x, y, z)` maps to world
+//! `(x, z, -y - 1)`, which keeps handedness and round-trips exactly.
+//!
+//! Multi-model scenes are placed through the scene graph (`nTRN` translations,
+//! rotations are ignored); files without a scene graph place models at the
+//! origin. Graphs deeper than 64 levels, visiting more than 65536 nodes or
+//! placing more than 2^26 voxels are rejected. Export splits the world into
+//! models of up to 256^3 blocks.
+
+use crate::chunk::ChunkStore;
+use std::{collections::HashMap, fmt};
+
+const MAX_MODEL_SIZE: i32 = 256;
+// Guards against cyclic or absurdly deep scene graphs
+const MAX_GRAPH_DEPTH: usize = 64;
+// Nodes can be shared, so a shallow graph can still reach a node (and
+// place its models) exponentially many times
+const MAX_GRAPH_VISITS: usize = 1 << 16;
+const MAX_PLACED_VOXELS: usize = 1 << 26;
+
+#[derive(Debug)]
+pub enum VoxError {
+    BadMagic,
+    Truncated,
+    Invalid(&'static str),
+}
+
+impl fmt::Display for VoxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VoxError::BadMagic => write!(f, "not a MagicaVoxel file"),
+            VoxError::Truncated => write!(f, "file is truncated"),
+            VoxError::Invalid(why) => write!(f, "invalid file: {why}"),
+        }
+    }
+}
+
+impl std::error::Error for VoxError {}
+
+type Vec3 = (i32, i32, i32);
+
+struct Model {
+    size: Vec3,
+    // x, y, z, color index
+    voxels: Vec<[u8; 4]>,
+}
+
+enum Node {
+    Transform { child: i32, translation: Vec3 },
+    Group { children: Vec<i32> },
+    Shape { models: Vec<i32> },
+}
+
+fn to_world((x, y, z): Vec3) -> Vec3 {
+    (x, z, -y - 1)
+}
+
+fn to_vox((x, y, z): Vec3) -> Vec3 {
+    (x, -z - 1, y)
+}
+
+/// Reads a `.vox` file into a new chunk store.
+pub fn import(data: &[u8]) -> Result<ChunkStore, VoxError> {
+    let mut r = Reader { data, pos: 0 };
+    if r.bytes(4)? != b"VOX " {
+        return Err(VoxError::BadMagic);
+    }
+    r.i32()?; // version, 150 and 200 share the layout we read
+
+    let (id, content, children) = r.chunk()?;
+    if id != b"MAIN" || !content.is_empty() {
+        return Err(VoxError::Invalid("missing MAIN chunk"));
+    }
+
+    let mut models = Vec::new();
+    let mut nodes = HashMap::new();
+    let mut pending_size = None;
+    let mut r = Reader {
+        data: children,
+        pos: 0,
+    };
+
+    while !r.done() {
+        let (id, content, _) = r.chunk()?;
+        let mut c = Reader {
+            data: content,
+            pos: 0,
+        };
+
+        match id {
+            b"SIZE" => pending_size = Some((c.i32()?, c.i32()?, c.i32()?)),
+            b"XYZI" => {
+                let size = pending_size
+                    .take()
+                    .ok_or(VoxError::Invalid("XYZI without SIZE"))?;
+                let count = c.len()?;
+                let mut voxels = Vec::with_capacity(count.min(content.len() / 4));
+                for _ in 0..count {
+                    let v = c.bytes(4)?;
+                    voxels.push([v[0], v[1], v[2], v[3]]);
+                }
+                models.push(Model { size, voxels });
+            }
+            b"nTRN" => {
+                let id = c.i32()?;
+                c.dict()?;
+                let child = c.i32()?;
+                c.i32()?; // reserved
+                c.i32()?; // layer
+                let frames = c.len()?;
+                let mut translation = (0, 0, 0);
+                // Only the first animation frame is used
+                for frame in 0..frames {
+                    let attrs = c.dict()?;
+                    if let Some((_, t)) = attrs.iter().find(|(k, _)| k == "_t")
+                        && frame == 0
+                    {
+                        translation = parse_vec3(t)?;
+                    }
+                }
+                nodes.insert(id, Node::Transform { child, translation });
+            }
+            b"nGRP" => {
+                let id = c.i32()?;
+                c.dict()?;
+                let count = c.len()?;
+                let children = (0..count).map(|_| c.i32()).collect::<Result<_, _>>()?;
+                nodes.insert(id, Node::Group { children });
+            }
+            b"nSHP" => {
+                let id = c.i32()?;
+                c.dict()?;
+                let count = c.len()?;
+                let mut shape_models = Vec::new();
+                for _ in 0..count {
+                    shape_models.push(c.i32()?);
+                    c.dict()?;
+                }
+                nodes.insert(
+                    id,
+                    Node::Shape {
+                        models: shape_models,
+                    },
+                );
+            }
+            _ => {}
+        }
+    }
+
+    let mut store = ChunkStore::new();
+    let mut placed = 0;
+    let mut place = |model: &Model, offset: Vec3| {
+        placed += model.voxels.len();
+        if placed > MAX_PLACED_VOXELS {
+            return Err(VoxError::Invalid("scene places too many voxels"));
+        }
+        for &[x, y, z, color] in &model.voxels {
+            let v = (
+                offset.0 + x as i32,
+                offset.1 + y as i32,
+                offset.2 + z as i32,
+            );
+            let (wx, wy, wz) = to_world(v);
+            store.set_block(wx, wy, wz, color as u16);
+        }
+        Ok(())
+    };
+
+    if nodes.is_empty() {
+        for model in &models {
+            place(model, (0, 0, 0))?;
+        }
+    } else {
+        let mut stack = vec![(0, (0, 0, 0), 0)];
+        let mut visits = 0;
+        while let Some((id, t, depth)) = stack.pop() {
+            if depth > MAX_GRAPH_DEPTH {
+                return Err(VoxError::Invalid("scene graph too deep"));
+            }
+            visits += 1;
+            if visits > MAX_GRAPH_VISITS {
+                return Err(VoxError::Invalid("scene graph too large"));
+            }
+
+            match nodes.get(&id) {
+                Some(Node::Transform { child, translation }) => {
+                    let t = (
+                        t.0 + translation.0,
+                        t.1 + translation.1,
+                        t.2 + translation.2,
+                    );
+                    stack.push((*child, t, depth + 1));
+                }
+                Some(Node::Group { children }) => {
+                    stack.extend(children.iter().map(|&c| (c, t, depth + 1)));
+                }
+                Some(Node::Shape { models: ids }) => {
+                    for &m in ids {
+                        let model = usize::try_from(m)
+                            .ok()
+                            .and_then(|m| models.get(m))
+                            .ok_or(VoxError::Invalid("shape references a missing model"))?;
+                        // Translations point at the model's center
+                        let (sx, sy, sz) = model.size;
+                        place(model, (t.0 - sx / 2, t.1 - sy / 2, t.2 - sz / 2))?;
+                    }
+                }
+                None => return Err(VoxError::Invalid("scene graph references a missing node")),
+            }
+        }
+    }
+
+    Ok(store)
+}
+
+/// Writes the store as a `.vox` file. Returns the file and the number of
+/// blocks skipped because their id doesn't fit a palette index.
+pub fn export(store: &ChunkStore) -> (Vec<u8>, usize) {
+    let mut skipped = 0;
+    let mut regions: HashMap<Vec3, Vec<(Vec3, u8)>> = HashMap::new();
+
+    for (&(cx, cy, cz), chunk) in store.iter() {
+        for (i, &block) in chunk.blocks().iter().enumerate() {
+            if block == 0 {
+                continue;
+            }
+            let Ok(color) = u8::try_from(block) else {
+                skipped += 1;
+                continue;
+            };
+
+            let (lx, ly, lz) = ((i % 16) as i32, (i / 256) as i32, (i / 16 % 16) as i32);
+            let v = to_vox((cx * 16 + lx, cy * 16 + ly, cz * 16 + lz));
+            let region = (
+                v.0.div_euclid(MAX_MODEL_SIZE),
+                v.1.div_euclid(MAX_MODEL_SIZE),
+                v.2.div_euclid(MAX_MODEL_SIZE),
+            );
+            regions.entry(region).or_default().push((v, color));
+        }
+    }
+
+    // Stable output for identical worlds
+    let mut regions: Vec<_> = regions.into_values().collect();
+    regions.sort_by_key(|voxels| voxels[0].0);
+
+    let mut children = Vec::new();
+    let mut placements = Vec::new();
+
+    for voxels in &regions {
+        let min = voxels
+            .iter()
+            .fold((i32::MAX, i32::MAX, i32::MAX), |m, (v, _)| {
+                (m.0.min(v.0), m.1.min(v.1), m.2.min(v.2))
+            });
+        let max = voxels
+            .iter()
+            .fold((i32::MIN, i32::MIN, i32::MIN), |m, (v, _)| {
+                (m.0.max(v.0), m.1.max(v.1), m.2.max(v.2))
+            });
+        let size = (max.0 - min.0 + 1, max.1 - min.1 + 1, max.2 - min.2 + 1);
+
+        let mut content = Vec::new();
+        for n in [size.0, size.1, size.2] {
+            content.extend_from_slice(&n.to_le_bytes());
+        }
+        write_chunk(&mut children, b"SIZE", &content, &[]);
+
+        let mut content = (voxels.len() as i32).to_le_bytes().to_vec();
+        for &((x, y, z), color) in voxels {
+            content.extend_from_slice(&[
+                (x - min.0) as u8,
+                (y - min.1) as u8,
+                (z - min.2) as u8,
+                color,
+            ]);
+        }
+        write_chunk(&mut children, b"XYZI", &content, &[]);
+
+        // Translation of the model center, see import
+        placements.push((min.0 + size.0 / 2, min.1 + size.1 / 2, min.2 + size.2 / 2));
+    }
+
+    // Scene graph: root transform 0 -> group 1 -> (transform, shape) per model
+    let model_nodes: Vec<i32> = (0..placements.len() as i32).map(|i| 2 + i * 2).collect();
+    write_transform(&mut children, 0, 1, -1, (0, 0, 0));
+    let mut group = Vec::new();
+    write_i32(&mut group, 1);
+    write_i32(&mut group, 0);
+    write_i32(&mut group, model_nodes.len() as i32);
+    for &node in &model_nodes {
+        write_i32(&mut group, node);
+    }
+    write_chunk(&mut children, b"nGRP", &group, &[]);
+
+    for (i, (&node, &t)) in model_nodes.iter().zip(&placements).enumerate() {
+        write_transform(&mut children, node, node + 1, 0, t);
+        let mut shape = Vec::new();
+        write_i32(&mut shape, node + 1);
+        write_i32(&mut shape, 0);
+        write_i32(&mut shape, 1);
+        write_i32(&mut shape, i as i32);
+        write_i32(&mut shape, 0);
+        write_chunk(&mut children, b"nSHP", &shape, &[]);
+    }
+
+    let mut out = b"VOX ".to_vec();
+    write_i32(&mut out, 200);
+    write_chunk(&mut out, b"MAIN", &[], &children);
+    (out, skipped)
+}
+
+fn parse_vec3(s: &str) -> Result<Vec3, VoxError> {
+    let mut it = s.split(' ').map(|n| n.parse::<i32>());
+    match (it.next(), it.next(), it.next(), it.next()) {
+        (Some(Ok(x)), Some(Ok(y)), Some(Ok(z)), None) => Ok((x, y, z)),
+        _ => Err(VoxError::Invalid("bad translation")),
+    }
+}
+
+fn write_i32(out: &mut Vec<u8>, n: i32) {
+    out.extend_from_slice(&n.to_le_bytes());
+}
+
+fn write_string(out: &mut Vec<u8>, s: &str) {
+    write_i32(out, s.len() as i32);
+    out.extend_from_slice(s.as_bytes());
+}
+
+fn write_chunk(out: &mut Vec<u8>, id: &[u8; 4], content: &[u8], children: &[u8]) {
+    out.extend_from_slice(id);
+    write_i32(out, content.len() as i32);
+    write_i32(out, children.len() as i32);
+    out.extend_from_slice(content);
+    out.extend_from_slice(children);
+}
+
+fn write_transform(out: &mut Vec<u8>, id: i32, child: i32, layer: i32, t: Vec3) {
+    let mut content = Vec::new();
+    write_i32(&mut content, id);
+    write_i32(&mut content, 0); // no node attributes
+    write_i32(&mut content, child);
+    write_i32(&mut content, -1); // reserved
+    write_i32(&mut content, layer);
+    write_i32(&mut content, 1); // one frame
+    if t == (0, 0, 0) {
+        write_i32(&mut content, 0);
+    } else {
+        write_i32(&mut content, 1);
+        write_string(&mut content, "_t");
+        write_string(&mut content, &format!("{} {} {}", t.0, t.1, t.2));
+    }
+    write_chunk(out, b"nTRN", &content, &[]);
+}
+
+type RawChunk<'a> = (&'a [u8], &'a [u8], &'a [u8]);
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn done(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8], VoxError> {
+        let end = self.pos.checked_add(n).ok_or(VoxError::Truncated)?;
+        let bytes = self.data.get(self.pos..end).ok_or(VoxError::Truncated)?;
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn i32(&mut self) -> Result<i32, VoxError> {
+        let b = self.bytes(4)?;
+        Ok(i32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    /// A non-negative count or length.
+    fn len(&mut self) -> Result<usize, VoxError> {
+        usize::try_from(self.i32()?).map_err(|_| VoxError::Invalid("negative length"))
+    }
+
+    fn string(&mut self) -> Result<String, VoxError> {
+        let len = self.len()?;
+        Ok(String::from_utf8_lossy(self.bytes(len)?).into_owned())
+    }
+
+    fn dict(&mut self) -> Result<Vec<(String, String)>, VoxError> {
+        let count = self.len()?;
+        let mut pairs = Vec::new();
+        for _ in 0..count {
+            pairs.push((self.string()?, self.string()?));
+        }
+        Ok(pairs)
+    }
+
+    /// Returns (id, content, children).
+    fn chunk(&mut self) -> Result<RawChunk<'a>, VoxError> {
+        let id = self.bytes(4)?;
+        let content_len = self.len()?;
+        let children_len = self.len()?;
+        Ok((id, self.bytes(content_len)?, self.bytes(children_len)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Two models under translations: a 2x2x1 one at the origin, a 1x1x3
+    // one at (10, -5, 3), with a palette and node names to skip
+    const SCENE: &[u8] = include_bytes!("../tests/fixtures/scene.vox");
+
+    fn blocks(store: &ChunkStore) -> Vec<((i32, i32, i32), u16)> {
+        let mut blocks = Vec::new();
+        for (&(cx, cy, cz), chunk) in store.iter() {
+            for (i, &block) in chunk.blocks().iter().enumerate() {
+                let (lx, ly, lz) = ((i % 16) as i32, (i / 256) as i32, (i / 16 % 16) as i32);
+                if block != 0 {
+                    blocks.push(((cx * 16 + lx, cy * 16 + ly, cz * 16 + lz), block));
+                }
+            }
+        }
+        blocks.sort();
+        blocks
+    }
+
+    #[test]
+    fn imports_and_round_trips_a_scene() {
+        let store = import(SCENE).unwrap();
+        let expected = [
+            ((-1, 0, 0), 1),
+            ((0, 0, -1), 2),
+            ((10, 2, 4), 3),
+            ((10, 4, 4), 4),
+        ];
+        assert_eq!(blocks(&store), expected);
+
+        let (data, skipped) = export(&store);
+        assert_eq!(skipped, 0);
+        assert_eq!(blocks(&import(&data).unwrap()), expected);
+    }
+
+    #[test]
+    fn rejects_scene_graphs_that_multiply() {
+        // Each group holds the next one twice: 2^40 paths to the shape
+        let mut children = Vec::new();
+        write_chunk(
+            &mut children,
+            b"SIZE",
+            &[1, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0],
+            &[],
+        );
+        write_chunk(&mut children, b"XYZI", &[1, 0, 0, 0, 0, 0, 0, 1], &[]);
+        for id in 0..40 {
+            let mut group = Vec::new();
+            for n in [id, 0, 2, id + 1, id + 1] {
+                write_i32(&mut group, n);
+            }
+            write_chunk(&mut children, b"nGRP", &group, &[]);
+        }
+        let mut shape = Vec::new();
+        for n in [40, 0, 1, 0, 0] {
+            write_i32(&mut shape, n);
+        }
+        write_chunk(&mut children, b"nSHP", &shape, &[]);
+        let mut data = b"VOX ".to_vec();
+        write_i32(&mut data, 200);
+        write_chunk(&mut data, b"MAIN", &[], &children);
+
+        assert!(matches!(
+            import(&data),
+            Err(VoxError::Invalid("scene graph too large"))
+        ));
+    }
+}
@@ -0,0 +1,347 @@
+//! Outbound webhooks for world events (players joining and leaving, rooms
+//! created and destroyed, triggers entered and exited, server started and
+//! stopping), so bots and analytics can react to server activity. Enabled
+//! by `TELEBOXEL_WEBHOOK_URLS`. World events come from the journal
+//! (`journal.rs`), on a task of their own, see `follow`.
+//!
+//! Each URL gets a JSON POST per event, in order, retried with exponential
+//! backoff. With a secret, `X-Teleboxel-Signature` is `sha256=<hex>`, the
+//! HMAC-SHA256 of `<X-Teleboxel-Timestamp>.<body>`; receivers should check
+//! it and reject old timestamps.
+
+use crate::{
+    config::WebhookConfig,
+    http,
+    journal::{JournalEntry, JournalEvent},
+    telemetry::random_u64,
+};
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use sha2::Sha256;
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+    sync::{broadcast, mpsc},
+    task::JoinHandle,
+};
+
+// Deliveries queued per URL, newer events are dropped when full
+const QUEUE: usize = 1024;
+const BACKOFF: Duration = Duration::from_secs(1);
+
+#[derive(Serialize, Clone, Debug)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum WebhookEvent {
+    PlayerJoined {
+        room: String,
+        id: u32,
+        name: Option<String>,
+    },
+    PlayerLeft {
+        room: String,
+        id: u32,
+        name: Option<String>,
+    },
+    RoomCreated {
+        room: String,
+    },
+    RoomDestroyed {
+        room: String,
+    },
+    // See triggers.rs
+    TriggerEntered {
+        room: String,
+        id: u32,
+        trigger: String,
+    },
+    TriggerExited {
+        room: String,
+        id: u32,
+        trigger: String,
+    },
+    ServerStarted {
+        version: &'static str,
+    },
+    ServerStopping,
+}
+
+impl WebhookEvent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            WebhookEvent::PlayerJoined { .. } => "player_joined",
+            WebhookEvent::PlayerLeft { .. } => "player_left",
+            WebhookEvent::RoomCreated { .. } => "room_created",
+            WebhookEvent::RoomDestroyed { .. } => "room_destroyed",
+            WebhookEvent::TriggerEntered { .. } => "trigger_entered",
+            WebhookEvent::TriggerExited { .. } => "trigger_exited",
+            WebhookEvent::ServerStarted { .. } => "server_started",
+            WebhookEvent::ServerStopping => "server_stopping",
+        }
+    }
+
+    /// The event for a journal entry, `None` for the ones webhooks don't
+    /// get (blocks, entities, properties, simulation and custom events).
+    pub fn from_journal(entry: &JournalEntry) -> Option<Self> {
+        let room = entry.world.clone();
+        Some(match entry.event.clone() {
+            JournalEvent::PlayerJoined { id, name } => Self::PlayerJoined { room, id, name },
+            JournalEvent::PlayerLeft { id, name } => Self::PlayerLeft { room, id, name },
+            JournalEvent::TriggerEntered { id, trigger } => {
+                Self::TriggerEntered { room, id, trigger }
+            }
+            JournalEvent::TriggerExited { id, trigger } => {
+                Self::TriggerExited { room, id, trigger }
+            }
+            JournalEvent::RoomCreated => Self::RoomCreated { room },
+            JournalEvent::RoomDestroyed => Self::RoomDestroyed { room },
+            JournalEvent::BlockSet { .. }
+            | JournalEvent::EntitySpawned { .. }
+            | JournalEvent::EntityRemoved { .. }
+            | JournalEvent::PropertiesSet { .. }
+            | JournalEvent::SimulationChanged { .. }
+            | JournalEvent::TimeSkipped { .. }
+            | JournalEvent::Custom { .. }
+            | JournalEvent::Traveled { .. }
+            | JournalEvent::AchievementEarned { .. } => return None,
+        })
+    }
+}
+
+/// Follows the journal for `/admin/events` (`events`, no receivers unless
+/// someone is on it) and `webhooks`, until the journal is gone.
+pub async fn follow(
+    mut journal: broadcast::Receiver<Arc<JournalEntry>>,
+    events: broadcast::Sender<WebhookEvent>,
+    webhooks: Option<Arc<Webhooks>>,
+) {
+    loop {
+        let entry = match journal.recv().await {
+            Ok(entry) => entry,
+            Err(broadcast::error::RecvError::Lagged(n)) => {
+                eprintln!("Webhooks fell behind the journal, {n} entries skipped");
+                continue;
+            }
+            Err(broadcast::error::RecvError::Closed) => return,
+        };
+        let Some(event) = WebhookEvent::from_journal(&entry) else {
+            continue;
+        };
+        events.send(event.clone()).ok();
+        if let Some(webhooks) = &webhooks {
+            webhooks.notify(event);
+        }
+    }
+}
+
+struct Delivery {
+    id: String,
+    event: &'static str,
+    body: String,
+}
+
+pub struct Webhooks {
+    events: Option<Vec<String>>,
+    senders: Mutex<Vec<mpsc::Sender<Delivery>>>,
+    workers: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl Webhooks {
+    /// Starts a delivery task per URL.
+    pub fn start(config: WebhookConfig) -> Self {
+        Self::with_backoff(config, BACKOFF)
+    }
+
+    fn with_backoff(config: WebhookConfig, backoff: Duration) -> Self {
+        let mut senders = Vec::new();
+        let mut workers = Vec::new();
+        for url in config.urls {
+            let (tx, rx) = mpsc::channel(QUEUE);
+            let secret = config.secret.clone();
+            workers.push(tokio::spawn(deliver(
+                url,
+                secret,
+                config.retries,
+                backoff,
+                rx,
+            )));
+            senders.push(tx);
+        }
+
+        Self {
+            events: config.events,
+            senders: Mutex::new(senders),
+            workers: Mutex::new(workers),
+        }
+    }
+
+    /// Queues `event` for every URL, unless filtered out.
+    pub fn notify(&self, event: WebhookEvent) {
+        let name = event.name();
+        if self
+            .events
+            .as_ref()
+            .is_some_and(|events| !events.iter().any(|e| e == name))
+        {
+            return;
+        }
+
+        let id = format!("{:016x}", random_u64());
+        let mut body = serde_json::to_value(&event).unwrap();
+        body["delivery"] = id.clone().into();
+        body["time"] = unix_secs().into();
+        let body = body.to_string();
+
+        for tx in self.senders.lock().unwrap().iter() {
+            let delivery = Delivery {
+                id: id.clone(),
+                event: name,
+                body: body.clone(),
+            };
+            if tx.try_send(delivery).is_err() {
+                eprintln!("Webhook queue full, dropped {name}");
+            }
+        }
+    }
+
+    /// Stops taking events and waits up to `timeout` for queued deliveries.
+    pub async fn shutdown(&self, timeout: Duration) {
+        self.senders.lock().unwrap().clear();
+        let workers = std::mem::take(&mut *self.workers.lock().unwrap());
+        let all = async {
+            for worker in workers {
+                worker.await.ok();
+            }
+        };
+        if tokio::time::timeout(timeout, all).await.is_err() {
+            eprintln!("Webhooks still pending at shutdown, dropped");
+        }
+    }
+}
+
+fn unix_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// `sha256=<hex>` over `<timestamp>.<body>`.
+pub fn sign(secret: &str, timestamp: &str, body: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(timestamp.as_bytes());
+    mac.update(b".");
+    mac.update(body.as_bytes());
+    let hex: String = mac
+        .finalize()
+        .into_bytes()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect();
+    format!("sha256={hex}")
+}
+
+async fn deliver(
+    url: String,
+    secret: Option<String>,
+    retries: u32,
+    backoff: Duration,
+    mut rx: mpsc::Receiver<Delivery>,
+) {
+    while let Some(delivery) = rx.recv().await {
+        for attempt in 0..=retries {
+            if attempt > 0 {
+                tokio::time::sleep(backoff * 2u32.pow(attempt - 1)).await;
+            }
+
+            // Signed per attempt, so retries carry a fresh timestamp
+            let timestamp = unix_secs().to_string();
+            let signature = secret
+                .as_deref()
+                .map(|secret| sign(secret, &timestamp, &delivery.body));
+            let mut headers = vec![
+                ("content-type", "application/json"),
+                ("x-teleboxel-event", delivery.event),
+                ("x-teleboxel-delivery", delivery.id.as_str()),
+                ("x-teleboxel-timestamp", timestamp.as_str()),
+            ];
+            if let Some(signature) = &signature {
+                headers.push(("x-teleboxel-signature", signature.as_str()));
+            }
+
+            match http::post(&url, &headers, delivery.body.clone()).await {
+                Ok(_) => break,
+                Err(e) if attempt == retries => {
+                    eprintln!("Webhook {} to {url} failed, giving up: {e}", delivery.event);
+                }
+                Err(_) => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Router, http::HeaderMap, http::StatusCode, routing::post};
+    use serde_json::Value;
+    use std::sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    };
+
+    #[tokio::test]
+    async fn retries_and_signs_deliveries() {
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let (received_tx, mut received) = mpsc::unbounded_channel();
+        let app = Router::new().route(
+            "/hook",
+            post({
+                let attempts = attempts.clone();
+                move |headers: HeaderMap, body: String| async move {
+                    // The first attempt fails
+                    if attempts.fetch_add(1, Ordering::SeqCst) == 0 {
+                        return StatusCode::INTERNAL_SERVER_ERROR;
+                    }
+                    let header = |name| headers[name].to_str().unwrap().to_string();
+                    received_tx
+                        .send((
+                            header("x-teleboxel-timestamp"),
+                            header("x-teleboxel-signature"),
+                            body,
+                        ))
+                        .ok();
+                    StatusCode::NO_CONTENT
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let webhooks = Webhooks::with_backoff(
+            WebhookConfig {
+                urls: vec![format!("http://{addr}/hook")],
+                secret: Some("hunter2".into()),
+                events: Some(vec!["room_created".into()]),
+                retries: 3,
+            },
+            Duration::from_millis(10),
+        );
+        webhooks.notify(WebhookEvent::ServerStarted { version: "0.1.0" });
+        webhooks.notify(WebhookEvent::RoomCreated {
+            room: "arena".into(),
+        });
+        webhooks.shutdown(Duration::from_secs(5)).await;
+
+        let (timestamp, signature, body) = received.recv().await.unwrap();
+        assert_eq!(attempts.load(Ordering::SeqCst), 2);
+        assert_eq!(signature, sign("hunter2", &timestamp, &body));
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["event"], "room_created");
+        assert_eq!(body["room"], "arena");
+        // Filtered out
+        assert!(received.try_recv().is_err());
+    }
+}